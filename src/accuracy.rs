@@ -0,0 +1,122 @@
+// Accuracy vs speed trade-offs
+// One switch (AccuracyProfile) selects a set of individual settings, so users on weak
+// hardware can trade timing accuracy for speed without knowing every knob.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum AccuracyProfile {
+    /// Cheapest emulation; timing-sensitive games and test ROMs may misbehave. Serial
+    /// transfers on the internal clock never complete without a cable or link device.
+    Fast,
+    /// Everything games are known to rely on
+    #[default]
+    Balanced,
+    /// Every accuracy feature enabled, including hardware bugs games avoid (OAM corruption)
+    Accurate,
+}
+
+impl AccuracyProfile {
+    pub fn settings(&self) -> AccuracySettings {
+        match self {
+            AccuracyProfile::Fast => AccuracySettings {
+                sub_instruction_timing: false,
+                idle_skip: true,
+                pixel_fifo: false,
+                serial_timing: false,
+                oam_bug: false,
+            },
+            AccuracyProfile::Balanced => AccuracySettings {
+                sub_instruction_timing: true,
+                idle_skip: false,
+                pixel_fifo: true,
+                serial_timing: true,
+                oam_bug: false,
+            },
+            AccuracyProfile::Accurate => AccuracySettings {
+                oam_bug: true,
+                ..AccuracyProfile::Balanced.settings()
+            },
+        }
+    }
+}
+
+impl std::str::FromStr for AccuracyProfile {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "fast" => Ok(AccuracyProfile::Fast),
+            "balanced" => Ok(AccuracyProfile::Balanced),
+            "accurate" => Ok(AccuracyProfile::Accurate),
+            other => Err(format!(
+                "unknown accuracy profile '{}' (expected fast, balanced or accurate)",
+                other
+            )),
+        }
+    }
+}
+
+/// Individual accuracy toggles selected by an AccuracyProfile
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AccuracySettings {
+    /// Advance timer/PPU on every memory access inside an instruction. When off,
+    /// peripherals catch up once per instruction, so mid-instruction reads see stale state.
+    pub sub_instruction_timing: bool,
+    /// Fast-forward busy-wait loops polling LY (see idle.rs)
+    pub idle_skip: bool,
+    /// Run the PPU's pixel FIFO dot by dot through mode 3, so registers written mid-line
+    /// (raster effects) change the pixels after the write. When off, each line is drawn
+    /// in one go at the end of mode 3, with the registers as they are then.
+    pub pixel_fifo: bool,
+    /// Complete internal-clock serial transfers after 8 * 512 cycles and raise the serial
+    /// interrupt. When off, transfers never finish unless a link device or cable is
+    /// plugged in: SC bit 7 stays set and no interrupt is raised (the original behavior).
+    pub serial_timing: bool,
+    /// DMG OAM corruption: CPU accesses to FE00-FEFF, and INC/DEC of a register pointing
+    /// there, during the PPU's OAM scan garble the object attributes being read
+    pub oam_bug: bool,
+}
+
+impl Default for AccuracySettings {
+    fn default() -> Self {
+        AccuracyProfile::default().settings()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_profile_parsing() {
+        assert_eq!("Fast".parse(), Ok(AccuracyProfile::Fast));
+        assert_eq!("accurate".parse(), Ok(AccuracyProfile::Accurate));
+        assert!("turbo".parse::<AccuracyProfile>().is_err());
+    }
+
+    #[test]
+    fn test_only_fast_disables_sub_instruction_timing() {
+        assert!(!AccuracyProfile::Fast.settings().sub_instruction_timing);
+        assert!(AccuracyProfile::Balanced.settings().sub_instruction_timing);
+        assert!(AccuracyProfile::Accurate.settings().sub_instruction_timing);
+        assert!(AccuracyProfile::Fast.settings().idle_skip);
+        assert!(!AccuracyProfile::Balanced.settings().idle_skip);
+        assert_eq!(
+            AccuracySettings::default(),
+            AccuracyProfile::Balanced.settings()
+        );
+    }
+
+    #[test]
+    fn test_profiles_differ() {
+        let [fast, balanced, accurate] = [
+            AccuracyProfile::Fast,
+            AccuracyProfile::Balanced,
+            AccuracyProfile::Accurate,
+        ]
+        .map(|profile| profile.settings());
+        assert!(!fast.pixel_fifo && !fast.serial_timing && !fast.oam_bug);
+        assert!(balanced.pixel_fifo && balanced.serial_timing && !balanced.oam_bug);
+        assert!(accurate.oam_bug);
+        assert_ne!(balanced, accurate);
+    }
+}
@@ -0,0 +1,243 @@
+// Audio sample transport
+// The emulation thread pushes stereo samples into a lock-free single-producer,
+// single-consumer ring that the host audio callback drains on its own thread, so neither
+// side ever blocks the other. Emulation and the sound card run off different clocks;
+// DriftCorrector keeps the ring near a target fill level by nudging the emulated frame
+// duration by a fraction of a percent, which is inaudible, instead of dropping samples
+// (crackle) or stalling video (stutter).
+//
+// There is no APU yet, so nothing produces samples: the SDL frontend plays silence from
+// the ring and frame pacing stays on the video clock until samples arrive.
+
+use std::sync::atomic::{AtomicU32, AtomicU64, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::runner::Audio;
+
+struct Shared {
+    // One stereo frame per slot: left in the low half, right in the high half
+    slots: Box<[AtomicU32]>,
+    // Frames ever read and written; the difference is the fill level
+    read: AtomicUsize,
+    written: AtomicUsize,
+    underruns: AtomicU64,
+}
+
+impl Shared {
+    fn len(&self) -> usize {
+        self.written
+            .load(Ordering::Acquire)
+            .wrapping_sub(self.read.load(Ordering::Acquire))
+    }
+}
+
+/// Ring holding `capacity` stereo frames, split into its emulation and host halves
+pub fn sample_ring(capacity: usize) -> (SampleProducer, SampleConsumer) {
+    assert!(
+        capacity > 0,
+        "sample ring needs room for at least one frame"
+    );
+    let shared = Arc::new(Shared {
+        slots: (0..capacity).map(|_| AtomicU32::new(0)).collect(),
+        read: AtomicUsize::new(0),
+        written: AtomicUsize::new(0),
+        underruns: AtomicU64::new(0),
+    });
+    (
+        SampleProducer {
+            shared: shared.clone(),
+            dropped: 0,
+            drift: DriftCorrector::default(),
+        },
+        SampleConsumer { shared, last: 0 },
+    )
+}
+
+/// Emulation side of the ring
+pub struct SampleProducer {
+    shared: Arc<Shared>,
+    dropped: u64,
+    pub drift: DriftCorrector,
+}
+
+impl SampleProducer {
+    /// Queue one stereo frame; returns false (and drops it) when the ring is full
+    pub fn push(&mut self, left: i16, right: i16) -> bool {
+        let shared = &self.shared;
+        let written = shared.written.load(Ordering::Relaxed);
+        if written.wrapping_sub(shared.read.load(Ordering::Acquire)) >= shared.slots.len() {
+            self.dropped += 1;
+            return false;
+        }
+        let frame = left as u16 as u32 | (right as u16 as u32) << 16;
+        shared.slots[written % shared.slots.len()].store(frame, Ordering::Relaxed);
+        shared
+            .written
+            .store(written.wrapping_add(1), Ordering::Release);
+        true
+    }
+
+    /// Fraction of the ring in use (0.0-1.0)
+    pub fn fill(&self) -> f64 {
+        self.shared.len() as f64 / self.shared.slots.len() as f64
+    }
+
+    /// Frames dropped because the host stopped draining the ring
+    pub fn dropped(&self) -> u64 {
+        self.dropped
+    }
+
+    /// Frames the host asked for that had not been produced yet
+    pub fn underruns(&self) -> u64 {
+        self.shared.underruns.load(Ordering::Relaxed)
+    }
+}
+
+impl Audio for SampleProducer {
+    fn push_samples(&mut self, samples: &[i16]) {
+        for frame in samples.chunks_exact(2) {
+            self.push(frame[0], frame[1]);
+        }
+    }
+
+    /// Follows the sound card once samples flow; until then video keeps its own pace
+    fn pace(&self, frame_duration: Duration) -> Duration {
+        if self.shared.written.load(Ordering::Relaxed) == 0 {
+            return frame_duration;
+        }
+        self.drift.frame_duration(frame_duration, self.fill())
+    }
+}
+
+/// Host side of the ring, for the audio callback
+pub struct SampleConsumer {
+    shared: Arc<Shared>,
+    last: u32,
+}
+
+impl SampleConsumer {
+    /// Fill `out` with interleaved stereo samples. On underrun the last frame is held
+    /// (a step is quieter than a jump to silence); returns the number of frames missing.
+    pub fn fill(&mut self, out: &mut [i16]) -> usize {
+        let shared = &self.shared;
+        let mut read = shared.read.load(Ordering::Relaxed);
+        let written = shared.written.load(Ordering::Acquire);
+        let mut missing = 0;
+        for frame in out.chunks_exact_mut(2) {
+            if read != written {
+                self.last = shared.slots[read % shared.slots.len()].load(Ordering::Relaxed);
+                read = read.wrapping_add(1);
+            } else {
+                missing += 1;
+            }
+            frame[0] = self.last as u16 as i16;
+            frame[1] = (self.last >> 16) as u16 as i16;
+        }
+        shared.read.store(read, Ordering::Release);
+        if missing > 0 {
+            shared
+                .underruns
+                .fetch_add(missing as u64, Ordering::Relaxed);
+        }
+        missing
+    }
+}
+
+/// Adjusts emulation pacing to keep the sample ring at a target fill level
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DriftCorrector {
+    /// Fill level to hold the ring at (0.0-1.0)
+    pub target_fill: f64,
+    /// Largest change to the frame duration, as a fraction (0.005 = 0.5%)
+    pub max_adjustment: f64,
+}
+
+impl Default for DriftCorrector {
+    fn default() -> Self {
+        Self {
+            target_fill: 0.5,
+            max_adjustment: 0.005,
+        }
+    }
+}
+
+impl DriftCorrector {
+    /// Frame duration to use next: longer while the ring is fuller than the target
+    /// (the host is consuming slower than we produce), shorter while it is emptier
+    pub fn frame_duration(&self, base: Duration, fill: f64) -> Duration {
+        let error = ((fill - self.target_fill) / self.target_fill).clamp(-1.0, 1.0);
+        base.mul_f64(1.0 + error * self.max_adjustment)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ring_round_trip_and_underrun() {
+        let (mut producer, mut consumer) = sample_ring(4);
+        producer.push_samples(&[1, -1, 2, -2, 3, -3]);
+        assert_eq!(producer.fill(), 0.75);
+
+        let mut out = [0; 8];
+        assert_eq!(consumer.fill(&mut out), 1);
+        assert_eq!(out, [1, -1, 2, -2, 3, -3, 3, -3]);
+        assert_eq!(producer.underruns(), 1);
+
+        for i in 0..5 {
+            assert_eq!(producer.push(i, i), i < 4);
+        }
+        assert_eq!(producer.dropped(), 1);
+        assert_eq!(producer.fill(), 1.0);
+    }
+
+    #[test]
+    fn test_ring_across_threads() {
+        let (mut producer, mut consumer) = sample_ring(64);
+        let host = std::thread::spawn(move || {
+            let mut received = Vec::new();
+            let mut out = [0; 2];
+            while received.len() < 1000 {
+                if consumer.fill(&mut out) == 0 {
+                    received.push(out[0]);
+                }
+            }
+            received
+        });
+        let mut next = 0;
+        while next < 1000 {
+            if producer.push(next, -next) {
+                next += 1;
+            }
+        }
+        let received = host.join().unwrap();
+        assert!(received.iter().copied().eq(0..1000));
+    }
+
+    #[test]
+    fn test_pacing_waits_for_samples() {
+        let (mut producer, _consumer) = sample_ring(100);
+        let base = Duration::from_micros(16_000);
+        assert_eq!(producer.pace(base), base);
+        producer.push(0, 0);
+        assert!(producer.pace(base) < base);
+    }
+
+    #[test]
+    fn test_drift_correction_is_bounded() {
+        let corrector = DriftCorrector::default();
+        let base = Duration::from_micros(16_000);
+        assert_eq!(corrector.frame_duration(base, 0.5), base);
+        assert_eq!(
+            corrector.frame_duration(base, 1.0),
+            Duration::from_micros(16_080)
+        );
+        assert_eq!(
+            corrector.frame_duration(base, 0.0),
+            Duration::from_micros(15_920)
+        );
+        assert!(corrector.frame_duration(base, 0.6) > base);
+    }
+}
@@ -0,0 +1,192 @@
+// Battery saves (.sav files)
+// Cartridge RAM is written to a temporary file that is then renamed over the save, so a
+// process killed mid-write leaves the previous save intact. Games that write RAM every
+// frame would rewrite the file constantly, so writes are coalesced: the save is flushed
+// FLUSH_DELAY after the first unsaved write, and once more on exit.
+//
+// Cartridges with a clock (MBC3+TIMER) get the clock appended in the common 48-byte RTC
+// footer, stamped with the host time so it catches up on the time the emulator was closed.
+
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime};
+
+use crate::memory::{Memory, EXTERNAL_RAM_SIZE};
+use crate::rtc::FOOTER_SIZE;
+
+/// Longest time cartridge RAM writes stay unsaved while the emulator keeps running
+pub const FLUSH_DELAY: Duration = Duration::from_secs(3);
+
+/// Save file next to the ROM: `game.gb` -> `game.sav`
+pub fn save_path(rom_path: &Path) -> PathBuf {
+    rom_path.with_extension("sav")
+}
+
+/// Replace `path` with `data` so that readers see either the old or the new contents
+pub fn write_atomic(path: &Path, data: &[u8]) -> io::Result<()> {
+    let mut temp_name = path.file_name().unwrap_or_default().to_os_string();
+    temp_name.push(".tmp");
+    let temp_path = path.with_file_name(temp_name);
+
+    let mut file = fs::File::create(&temp_path)?;
+    file.write_all(data)?;
+    file.sync_all()?;
+    drop(file);
+    fs::rename(&temp_path, path)
+}
+
+pub struct BatterySave {
+    path: PathBuf,
+    // When the oldest unsaved cartridge RAM write was seen
+    dirty_since: Option<Instant>,
+}
+
+impl BatterySave {
+    pub fn new(path: PathBuf) -> Self {
+        Self {
+            path,
+            dirty_since: None,
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Load the save into cartridge RAM, and the clock if the save has one; false if
+    /// there is no save yet
+    pub fn load(&self, mem: &mut Memory) -> io::Result<bool> {
+        match fs::read(&self.path) {
+            Ok(mut data) => {
+                if let Some(rtc) = mem.rtc_mut() {
+                    if data.len() % EXTERNAL_RAM_SIZE == FOOTER_SIZE {
+                        let footer = data.split_off(data.len() - FOOTER_SIZE);
+                        rtc.load_footer(&footer, unix_time())
+                            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+                    }
+                }
+                mem.load_external_ram(&data);
+                Ok(true)
+            }
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(false),
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Call once per frame. Returns whether the save was written.
+    pub fn update(&mut self, mem: &mut Memory, now: Instant) -> io::Result<bool> {
+        if mem.take_external_ram_dirty() && self.dirty_since.is_none() {
+            self.dirty_since = Some(now);
+        }
+        match self.dirty_since {
+            Some(since) if now.duration_since(since) >= FLUSH_DELAY => {
+                self.flush(mem)?;
+                Ok(true)
+            }
+            _ => Ok(false),
+        }
+    }
+
+    /// Write any unsaved cartridge RAM now (on exit). Cartridges with a clock are always
+    /// written, so the save records when the clock was last running.
+    pub fn flush(&mut self, mem: &mut Memory) -> io::Result<()> {
+        let dirty = mem.take_external_ram_dirty() || self.dirty_since.is_some();
+        if dirty || mem.rtc().is_some() {
+            let mut data = mem.external_ram().to_vec();
+            if let Some(rtc) = mem.rtc() {
+                data.extend_from_slice(&rtc.footer(unix_time()));
+            }
+            write_atomic(&self.path, &data)?;
+            self.dirty_since = None;
+        }
+        Ok(())
+    }
+}
+
+// Host time in seconds since the Unix epoch, for RTC footers
+fn unix_time() -> u64 {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_secs())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_save(name: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("gbemu_battery_{}_{}", name, std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        dir.join("game.sav")
+    }
+
+    #[test]
+    fn test_writes_are_coalesced() {
+        let path = temp_save("coalesce");
+        let mut mem = Memory::new(vec![0; 0x8000]);
+        let mut save = BatterySave::new(path.clone());
+        let start = Instant::now();
+
+        // A game writing every frame: nothing hits the disk until FLUSH_DELAY has passed
+        for frame in 0..180u32 {
+            mem.write_8(0xA000, frame as u8);
+            let now = start + Duration::from_millis(frame as u64 * 16);
+            assert!(!save.update(&mut mem, now).unwrap());
+        }
+        assert!(!path.exists());
+        assert!(save.update(&mut mem, start + FLUSH_DELAY).unwrap());
+        assert_eq!(fs::read(&path).unwrap()[0], 179);
+
+        // Nothing new to save
+        assert!(!save.update(&mut mem, start + FLUSH_DELAY * 3).unwrap());
+
+        mem.write_8(0xA001, 0x42);
+        save.flush(&mut mem).unwrap();
+        let mut loaded = Memory::new(vec![0; 0x8000]);
+        assert!(save.load(&mut loaded).unwrap());
+        assert_eq!(loaded.external_ram(), mem.external_ram());
+        fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn test_clock_is_saved_with_ram() {
+        let path = temp_save("rtc");
+        let mut rom = vec![0; 0x8000];
+        rom[0x0147] = 0x10; // MBC3+TIMER+RAM+BATTERY
+        rom[0x0149] = 0x03;
+        let mut mem = Memory::new(rom.clone());
+        mem.write_8(0x0000, 0x0A);
+        mem.write_8(0xA000, 0x42);
+        mem.rtc_mut().unwrap().set("12:03:04:05".parse().unwrap());
+        mem.rtc_mut().unwrap().set_halted(true);
+        let mut save = BatterySave::new(path.clone());
+        save.flush(&mut mem).unwrap();
+        assert_eq!(
+            fs::read(&path).unwrap().len(),
+            4 * EXTERNAL_RAM_SIZE + FOOTER_SIZE
+        );
+
+        let mut loaded = Memory::new(rom);
+        assert!(save.load(&mut loaded).unwrap());
+        assert_eq!(loaded.external_ram(), mem.external_ram());
+        assert_eq!(loaded.rtc().unwrap().time().to_string(), "12:03:04:05");
+        fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn test_write_atomic_replaces_file() {
+        let path = temp_save("atomic");
+        fs::write(&path, b"old").unwrap();
+        write_atomic(&path, b"new save").unwrap();
+        assert_eq!(fs::read(&path).unwrap(), b"new save");
+        let leftovers = fs::read_dir(path.parent().unwrap()).unwrap().count();
+        assert_eq!(leftovers, 1);
+        fs::remove_dir_all(path.parent().unwrap()).unwrap();
+
+        let missing = BatterySave::new(temp_save("missing"));
+        assert!(!missing.load(&mut Memory::new(Vec::new())).unwrap());
+        fs::remove_dir_all(missing.path().parent().unwrap()).unwrap();
+    }
+}
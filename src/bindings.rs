@@ -0,0 +1,147 @@
+// Keyboard and controller bindings
+// Config entries `key.BUTTON = KEY,KEY...` choose the keyboard keys that press a Game Boy
+// button, and `pad.BUTTON = PAD,PAD...` the game controller buttons (named as in combo
+// hotkeys). Keys keep SDL's names ("Z", "Return", "Left Shift") and are resolved by the
+// frontend. Buttons without an entry keep their default bindings.
+
+use std::fmt;
+
+use crate::hotkeys::PadButton;
+use crate::joypad::JoypadButton;
+
+/// Prefix of the config keys that bind keyboard keys
+pub const KEY_PREFIX: &str = "key.";
+/// Prefix of the config keys that bind controller buttons
+pub const PAD_PREFIX: &str = "pad.";
+
+/// Host inputs bound to each Game Boy button
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Bindings {
+    // Indexed like `JoypadButton::ALL`
+    keys: [Vec<String>; 8],
+    pad: [Vec<PadButton>; 8],
+}
+
+impl Default for Bindings {
+    fn default() -> Self {
+        let keys = |names: &[&str]| names.iter().map(|name| name.to_string()).collect();
+        Self {
+            keys: [
+                keys(&["Right"]),
+                keys(&["Left"]),
+                keys(&["Up"]),
+                keys(&["Down"]),
+                keys(&["Z"]),
+                keys(&["X"]),
+                keys(&["Left Shift", "Right Shift"]),
+                keys(&["Return"]),
+            ],
+            pad: [
+                vec![PadButton::Right],
+                vec![PadButton::Left],
+                vec![PadButton::Up],
+                vec![PadButton::Down],
+                vec![PadButton::A],
+                vec![PadButton::B],
+                vec![PadButton::Select],
+                vec![PadButton::Start],
+            ],
+        }
+    }
+}
+
+impl Bindings {
+    /// Names of the keys that press `button`
+    pub fn keys(&self, button: JoypadButton) -> &[String] {
+        &self.keys[button as usize]
+    }
+
+    pub fn bind_keys(&mut self, button: JoypadButton, keys: Vec<String>) {
+        self.keys[button as usize] = keys;
+    }
+
+    /// Controller buttons that press `button`
+    pub fn pad(&self, button: JoypadButton) -> &[PadButton] {
+        &self.pad[button as usize]
+    }
+
+    pub fn bind_pad(&mut self, button: JoypadButton, pad: Vec<PadButton>) {
+        self.pad[button as usize] = pad;
+    }
+
+    /// Game Boy buttons pressed by the controller button `pad`
+    pub fn buttons_for_pad(&self, pad: PadButton) -> impl Iterator<Item = JoypadButton> + '_ {
+        JoypadButton::ALL
+            .into_iter()
+            .filter(move |&button| self.pad(button).contains(&pad))
+    }
+
+    /// Apply one `key.BUTTON` or `pad.BUTTON` config entry; false if `key` is neither
+    pub fn bind_entry(&mut self, key: &str, value: &str) -> Result<bool, String> {
+        let names = || {
+            value
+                .split(',')
+                .map(str::trim)
+                .filter(|name| !name.is_empty())
+        };
+        if let Some(button) = key.strip_prefix(KEY_PREFIX) {
+            let button = button.parse()?;
+            self.bind_keys(button, names().map(String::from).collect());
+        } else if let Some(button) = key.strip_prefix(PAD_PREFIX) {
+            let button = button.parse()?;
+            self.bind_pad(button, names().map(str::parse).collect::<Result<_, _>>()?);
+        } else {
+            return Ok(false);
+        }
+        Ok(true)
+    }
+}
+
+/// Every binding as config entries, one per line
+impl fmt::Display for Bindings {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for button in JoypadButton::ALL {
+            writeln!(
+                f,
+                "{}{} = {}",
+                KEY_PREFIX,
+                button,
+                self.keys(button).join(",")
+            )?;
+        }
+        for button in JoypadButton::ALL {
+            let pad: Vec<_> = self.pad(button).iter().map(|pad| pad.to_string()).collect();
+            writeln!(f, "{}{} = {}", PAD_PREFIX, button, pad.join(","))?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_config_entries() {
+        let mut bindings = Bindings::default();
+        assert_eq!(
+            bindings.keys(JoypadButton::Select),
+            ["Left Shift", "Right Shift"]
+        );
+        assert_eq!(bindings.bind_entry("key.a", "Space, K"), Ok(true));
+        assert_eq!(bindings.bind_entry("pad.b", "b,y"), Ok(true));
+        assert_eq!(bindings.bind_entry("pad.a", "a,y"), Ok(true));
+        assert_eq!(bindings.bind_entry("palette", "gray"), Ok(false));
+        assert!(bindings.bind_entry("key.turbo", "Tab").is_err());
+        assert!(bindings.bind_entry("pad.a", "z").is_err());
+
+        assert_eq!(bindings.keys(JoypadButton::A), ["Space", "K"]);
+        assert_eq!(bindings.keys(JoypadButton::B), ["X"]);
+        assert_eq!(
+            bindings.buttons_for_pad(PadButton::Y).collect::<Vec<_>>(),
+            [JoypadButton::A, JoypadButton::B]
+        );
+        assert!(bindings.to_string().contains("key.a = Space,K\n"));
+        assert!(bindings.to_string().contains("pad.select = select\n"));
+    }
+}
@@ -0,0 +1,215 @@
+// Cartridge header checks
+// Rejects ROMs whose mapper is not emulated, instead of running them with the
+// wrong banking in Memory and producing garbage.
+
+use std::fmt;
+
+// Cartridge header fields
+const TITLE_RANGE: std::ops::Range<usize> = 0x0134..0x0144;
+const CARTRIDGE_TYPE_ADDR: usize = 0x0147;
+const ROM_SIZE_ADDR: usize = 0x0148;
+const RAM_SIZE_ADDR: usize = 0x0149;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CartridgeError {
+    /// Cartridge type byte of a mapper that is not emulated
+    UnsupportedMapper(u8),
+}
+
+impl fmt::Display for CartridgeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CartridgeError::UnsupportedMapper(code) => write!(
+                f,
+                "unsupported cartridge mapper 0x{:02X} ({})",
+                code,
+                mapper_name(*code)
+            ),
+        }
+    }
+}
+
+impl std::error::Error for CartridgeError {}
+
+/// Name of a cartridge type byte, as listed in the Pan Docs
+pub fn mapper_name(code: u8) -> &'static str {
+    match code {
+        0x00 => "ROM ONLY",
+        0x01 => "MBC1",
+        0x02 => "MBC1+RAM",
+        0x03 => "MBC1+RAM+BATTERY",
+        0x05 => "MBC2",
+        0x06 => "MBC2+BATTERY",
+        0x08 => "ROM+RAM",
+        0x09 => "ROM+RAM+BATTERY",
+        0x0B => "MMM01",
+        0x0C => "MMM01+RAM",
+        0x0D => "MMM01+RAM+BATTERY",
+        0x0F => "MBC3+TIMER+BATTERY",
+        0x10 => "MBC3+TIMER+RAM+BATTERY",
+        0x11 => "MBC3",
+        0x12 => "MBC3+RAM",
+        0x13 => "MBC3+RAM+BATTERY",
+        0x19 => "MBC5",
+        0x1A => "MBC5+RAM",
+        0x1B => "MBC5+RAM+BATTERY",
+        0x1C => "MBC5+RUMBLE",
+        0x1D => "MBC5+RUMBLE+RAM",
+        0x1E => "MBC5+RUMBLE+RAM+BATTERY",
+        0x20 => "MBC6",
+        0x22 => "MBC7+SENSOR+RUMBLE+RAM+BATTERY",
+        0xFC => "POCKET CAMERA",
+        0xFD => "BANDAI TAMA5",
+        0xFE => "HuC3",
+        0xFF => "HuC1+RAM+BATTERY",
+        _ => "unknown",
+    }
+}
+
+/// Check that the mapper declared in the header of `rom` is emulated
+pub fn check_mapper(rom: &[u8]) -> Result<(), CartridgeError> {
+    // Headerless images (e.g. tiny test programs) are treated as ROM ONLY
+    let code = rom.get(CARTRIDGE_TYPE_ADDR).copied().unwrap_or(0x00);
+    if mapper_supported(code) {
+        Ok(())
+    } else {
+        Err(CartridgeError::UnsupportedMapper(code))
+    }
+}
+
+// ROM ONLY, MBC1, MBC3 and MBC5, with or without RAM, are what Memory maps
+fn mapper_supported(code: u8) -> bool {
+    matches!(code, 0x00..=0x03 | 0x08 | 0x09 | 0x0F..=0x13 | 0x19..=0x1E)
+}
+
+/// Hardware described by the cartridge header
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CartridgeHeader {
+    pub title: String,
+    /// Cartridge type byte (0x0147)
+    pub cartridge_type: u8,
+    /// ROM size in bytes, None for an unknown size code (0x0148)
+    pub rom_size: Option<usize>,
+    /// External RAM size in bytes, None for an unknown size code (0x0149)
+    pub ram_size: Option<usize>,
+}
+
+impl CartridgeHeader {
+    /// Parse the header of `rom`; missing bytes read as 0 (ROM ONLY, 32 KiB, no RAM)
+    pub fn parse(rom: &[u8]) -> Self {
+        let byte = |addr: usize| rom.get(addr).copied().unwrap_or(0);
+        let title = rom
+            .get(TITLE_RANGE)
+            .unwrap_or_default()
+            .iter()
+            .take_while(|&&c| c != 0)
+            .map(|&c| {
+                if c.is_ascii_graphic() || c == b' ' {
+                    c as char
+                } else {
+                    '?'
+                }
+            })
+            .collect::<String>();
+        let rom_size = match byte(ROM_SIZE_ADDR) {
+            code @ 0x00..=0x08 => Some(0x8000 << code),
+            _ => None,
+        };
+        let ram_size = match byte(RAM_SIZE_ADDR) {
+            0x00 => Some(0),
+            0x01 => Some(0x800),
+            0x02 => Some(0x2000),
+            0x03 => Some(0x8000),
+            0x04 => Some(0x20000),
+            0x05 => Some(0x10000),
+            _ => None,
+        };
+        Self {
+            title: title.trim_end().to_string(),
+            cartridge_type: byte(CARTRIDGE_TYPE_ADDR),
+            rom_size,
+            ram_size,
+        }
+    }
+
+    pub fn mapper_name(&self) -> &'static str {
+        mapper_name(self.cartridge_type)
+    }
+
+    /// Whether the mapper is emulated (see `check_mapper`)
+    pub fn mapper_supported(&self) -> bool {
+        mapper_supported(self.cartridge_type)
+    }
+
+    pub fn has_battery(&self) -> bool {
+        self.mapper_name().contains("BATTERY")
+    }
+
+    pub fn has_rtc(&self) -> bool {
+        self.mapper_name().contains("TIMER")
+    }
+}
+
+/// Title and mapper, e.g. "POKEMON RED — MBC3+RAM+BATTERY"
+impl fmt::Display for CartridgeHeader {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.title.is_empty() {
+            f.write_str(self.mapper_name())
+        } else {
+            write!(f, "{} \u{2014} {}", self.title, self.mapper_name())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rom_with_type(code: u8) -> Vec<u8> {
+        let mut rom = vec![0; 0x8000];
+        rom[CARTRIDGE_TYPE_ADDR] = code;
+        rom
+    }
+
+    #[test]
+    fn test_supported_mappers() {
+        for code in [
+            0x00, 0x01, 0x02, 0x03, 0x08, 0x09, 0x0F, 0x10, 0x13, 0x19, 0x1B, 0x1E,
+        ] {
+            assert_eq!(check_mapper(&rom_with_type(code)), Ok(()));
+        }
+        assert_eq!(check_mapper(&[0x18, 0xFE]), Ok(()));
+    }
+
+    #[test]
+    fn test_unsupported_mapper_error() {
+        let err = check_mapper(&rom_with_type(0x22)).unwrap_err();
+        assert_eq!(err, CartridgeError::UnsupportedMapper(0x22));
+        assert_eq!(
+            err.to_string(),
+            "unsupported cartridge mapper 0x22 (MBC7+SENSOR+RUMBLE+RAM+BATTERY)"
+        );
+        assert_eq!(mapper_name(0x42), "unknown");
+    }
+
+    #[test]
+    fn test_parse_header() {
+        let mut rom = rom_with_type(0x13);
+        rom[0x0134..0x0140].copy_from_slice(b"POKEMON RED\0");
+        rom[0x0148] = 0x05;
+        rom[0x0149] = 0x03;
+        let header = CartridgeHeader::parse(&rom);
+        assert_eq!(header.title, "POKEMON RED");
+        assert_eq!(header.rom_size, Some(0x100000));
+        assert_eq!(header.ram_size, Some(0x8000));
+        assert!(header.has_battery() && !header.has_rtc());
+        assert!(header.mapper_supported());
+        assert_eq!(header.to_string(), "POKEMON RED \u{2014} MBC3+RAM+BATTERY");
+
+        let header = CartridgeHeader::parse(&[0x18, 0xFE]);
+        assert_eq!(header.title, "");
+        assert_eq!(header.to_string(), "ROM ONLY");
+        assert_eq!(header.rom_size, Some(0x8000));
+        assert!(header.mapper_supported());
+    }
+}
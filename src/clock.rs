@@ -0,0 +1,242 @@
+// Emulated clock
+// Time derived only from executed cycles, never from the host clock, so anything in the
+// core that needs "now" (cartridge RTC, movie timestamps, on-screen display) stays
+// deterministic under fast-forward, pausing and replays. EmuInstant and EmuDuration are
+// the core's units of time; std::time only appears where the frontend paces frames
+// against the host.
+
+use std::fmt;
+use std::ops::{Add, AddAssign, Sub, SubAssign};
+use std::time::Duration;
+
+/// DMG CPU clock in T-cycles per second
+pub const CPU_FREQUENCY: u64 = 4_194_304;
+
+/// T-cycles per frame (154 scanlines * 456 dots)
+pub const FRAME_CYCLES: u64 = 70224;
+
+/// Span of emulated time, counted in T-cycles
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct EmuDuration(u64);
+
+impl EmuDuration {
+    pub const ZERO: EmuDuration = EmuDuration(0);
+    /// One video frame
+    pub const FRAME: EmuDuration = EmuDuration(FRAME_CYCLES);
+
+    pub const fn from_cycles(cycles: u64) -> Self {
+        Self(cycles)
+    }
+
+    pub const fn from_secs(secs: u64) -> Self {
+        Self(secs * CPU_FREQUENCY)
+    }
+
+    pub const fn from_millis(millis: u64) -> Self {
+        Self(millis * CPU_FREQUENCY / 1000)
+    }
+
+    pub const fn from_frames(frames: u64) -> Self {
+        Self(frames * FRAME_CYCLES)
+    }
+
+    pub const fn cycles(self) -> u64 {
+        self.0
+    }
+
+    /// Whole seconds
+    pub const fn as_secs(self) -> u64 {
+        self.0 / CPU_FREQUENCY
+    }
+
+    /// Cycles past the last whole second
+    pub const fn subsec_cycles(self) -> u64 {
+        self.0 % CPU_FREQUENCY
+    }
+
+    pub fn as_secs_f64(self) -> f64 {
+        self.0 as f64 / CPU_FREQUENCY as f64
+    }
+
+    /// Whole frames
+    pub const fn as_frames(self) -> u64 {
+        self.0 / FRAME_CYCLES
+    }
+
+    /// The same span of host time at the console's speed, rounded down to a nanosecond
+    pub fn as_duration(self) -> Duration {
+        let nanos = self.subsec_cycles() * 1_000_000_000 / CPU_FREQUENCY;
+        Duration::new(self.as_secs(), nanos as u32)
+    }
+
+    pub fn saturating_sub(self, other: EmuDuration) -> EmuDuration {
+        Self(self.0.saturating_sub(other.0))
+    }
+}
+
+impl Add for EmuDuration {
+    type Output = EmuDuration;
+
+    fn add(self, other: EmuDuration) -> EmuDuration {
+        Self(self.0 + other.0)
+    }
+}
+
+impl AddAssign for EmuDuration {
+    fn add_assign(&mut self, other: EmuDuration) {
+        self.0 += other.0;
+    }
+}
+
+impl Sub for EmuDuration {
+    type Output = EmuDuration;
+
+    fn sub(self, other: EmuDuration) -> EmuDuration {
+        Self(self.0 - other.0)
+    }
+}
+
+impl SubAssign for EmuDuration {
+    fn sub_assign(&mut self, other: EmuDuration) {
+        self.0 -= other.0;
+    }
+}
+
+/// Seconds with millisecond precision, e.g. `1.004s`
+impl fmt::Display for EmuDuration {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let millis = self.subsec_cycles() * 1000 / CPU_FREQUENCY;
+        write!(f, "{}.{:03}s", self.as_secs(), millis)
+    }
+}
+
+/// Point in emulated time, in T-cycles since power-on
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct EmuInstant(u64);
+
+impl EmuInstant {
+    pub const POWER_ON: EmuInstant = EmuInstant(0);
+
+    pub const fn from_cycles(cycles: u64) -> Self {
+        Self(cycles)
+    }
+
+    pub const fn cycles(self) -> u64 {
+        self.0
+    }
+
+    /// Time since `earlier`, zero if it is later (a savestate moved the clock back)
+    pub fn duration_since(self, earlier: EmuInstant) -> EmuDuration {
+        EmuDuration(self.0.saturating_sub(earlier.0))
+    }
+}
+
+impl Add<EmuDuration> for EmuInstant {
+    type Output = EmuInstant;
+
+    fn add(self, duration: EmuDuration) -> EmuInstant {
+        Self(self.0 + duration.0)
+    }
+}
+
+impl AddAssign<EmuDuration> for EmuInstant {
+    fn add_assign(&mut self, duration: EmuDuration) {
+        self.0 += duration.0;
+    }
+}
+
+impl Sub for EmuInstant {
+    type Output = EmuDuration;
+
+    fn sub(self, earlier: EmuInstant) -> EmuDuration {
+        EmuDuration(self.0 - earlier.0)
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct EmulatedClock {
+    cycles: u64,
+}
+
+impl EmulatedClock {
+    pub fn new() -> Self {
+        Self { cycles: 0 }
+    }
+
+    /// Clock that has already run `cycles` T-cycles (restoring a savestate)
+    pub fn from_cycles(cycles: u64) -> Self {
+        Self { cycles }
+    }
+
+    /// Advance by `t_cycles` executed T-cycles
+    pub fn tick(&mut self, t_cycles: u32) {
+        self.cycles += t_cycles as u64;
+    }
+
+    /// T-cycles executed since power-on
+    pub fn cycles(&self) -> u64 {
+        self.cycles
+    }
+
+    /// Whole frames' worth of cycles executed since power-on
+    pub fn frame_count(&self) -> u64 {
+        self.cycles / FRAME_CYCLES
+    }
+
+    pub fn now(&self) -> EmuInstant {
+        EmuInstant(self.cycles)
+    }
+
+    /// Emulated time since power-on
+    pub fn emulated_time(&self) -> EmuDuration {
+        EmuDuration(self.cycles)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_time_follows_cycles() {
+        let mut clock = EmulatedClock::new();
+        assert_eq!(clock.emulated_time(), EmuDuration::ZERO);
+        let start = clock.now();
+
+        for _ in 0..60 {
+            clock.tick(FRAME_CYCLES as u32);
+        }
+        assert_eq!(clock.frame_count(), 60);
+        // 60 frames are a little over a second (~59.73 Hz refresh)
+        assert_eq!(clock.emulated_time().as_duration().as_millis(), 1004);
+        assert_eq!(clock.emulated_time().to_string(), "1.004s");
+        assert_eq!(clock.now() - start, EmuDuration::from_frames(60));
+
+        clock.tick((CPU_FREQUENCY * 3 - clock.cycles()) as u32);
+        assert_eq!(clock.emulated_time().as_duration(), Duration::from_secs(3));
+        assert_eq!(
+            clock.now(),
+            EmuInstant::POWER_ON + EmuDuration::from_secs(3)
+        );
+    }
+
+    #[test]
+    fn test_instants_and_durations() {
+        let half = EmuDuration::from_millis(500);
+        assert_eq!(half.cycles(), CPU_FREQUENCY / 2);
+        assert_eq!((half + half).as_secs(), 1);
+        assert_eq!((half + half).subsec_cycles(), 0);
+        assert_eq!(EmuDuration::FRAME.as_frames(), 1);
+        assert!((EmuDuration::FRAME.as_secs_f64() * 1000.0 - 16.74).abs() < 0.01);
+
+        let later = EmuInstant::from_cycles(100) + half;
+        assert_eq!(later.duration_since(EmuInstant::from_cycles(100)), half);
+        // A restored savestate can put "now" before an instant taken earlier
+        assert_eq!(
+            EmuInstant::POWER_ON.duration_since(later),
+            EmuDuration::ZERO
+        );
+    }
+}
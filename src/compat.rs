@@ -0,0 +1,328 @@
+// Compatibility smoke testing
+// Boots ROMs headlessly for a number of frames and classifies whether they reached
+// a live picture, so maintainers can track compatibility of a ROM collection across releases.
+
+use std::collections::hash_map::DefaultHasher;
+use std::fmt::Write as _;
+use std::fs;
+use std::hash::{Hash, Hasher};
+use std::io;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::thread;
+
+use crate::cartridge::check_mapper;
+use crate::cpu::Cpu;
+use crate::memory::Memory;
+use crate::model::Model;
+use crate::romimage::RomImage;
+
+// T-cycles per frame (154 scanlines * 456 dots)
+const FRAME_CYCLES: u32 = 70224;
+
+// Emulator state is large and moved by value; give workers room beyond the 2 MB default
+const WORKER_STACK_SIZE: usize = 32 * 1024 * 1024;
+
+/// Number of worker threads to use when none is requested
+pub fn default_jobs() -> usize {
+    thread::available_parallelism().map_or(1, |n| n.get())
+}
+
+/// Apply `f` to every item on up to `jobs` threads (one emulator per thread),
+/// returning the results in input order
+pub fn run_parallel<T, R, F>(items: &[T], jobs: usize, f: F) -> Vec<R>
+where
+    T: Sync,
+    R: Send,
+    F: Fn(&T) -> R + Sync,
+{
+    let next = AtomicUsize::new(0);
+    let results: Mutex<Vec<Option<R>>> = Mutex::new(items.iter().map(|_| None).collect());
+
+    thread::scope(|scope| {
+        for _ in 0..jobs.clamp(1, items.len().max(1)) {
+            thread::Builder::new()
+                .stack_size(WORKER_STACK_SIZE)
+                .spawn_scoped(scope, || loop {
+                    let index = next.fetch_add(1, Ordering::Relaxed);
+                    let Some(item) = items.get(index) else {
+                        break;
+                    };
+                    let result = f(item);
+                    results.lock().unwrap()[index] = Some(result);
+                })
+                .expect("failed to spawn worker thread");
+        }
+    });
+
+    results
+        .into_inner()
+        .unwrap()
+        .into_iter()
+        .map(|r| r.expect("worker skipped an item"))
+        .collect()
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CompatStatus {
+    /// Non-blank picture that was still changing at the end of the run
+    Running,
+    /// Non-blank picture that stopped changing
+    Static,
+    /// The last frame was a single color
+    Blank,
+    /// The cartridge uses a mapper that is not emulated
+    Unsupported(String),
+    /// The emulator panicked
+    Crashed(String),
+}
+
+impl CompatStatus {
+    pub fn label(&self) -> &'static str {
+        match self {
+            CompatStatus::Running => "running",
+            CompatStatus::Static => "static",
+            CompatStatus::Blank => "blank",
+            CompatStatus::Unsupported(_) => "unsupported",
+            CompatStatus::Crashed(_) => "crashed",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CompatResult {
+    pub rom: PathBuf,
+    pub status: CompatStatus,
+}
+
+fn framebuffer_hash(framebuffer: &[u8]) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    framebuffer.hash(&mut hasher);
+    hasher.finish()
+}
+
+fn is_blank(framebuffer: &[u8]) -> bool {
+    framebuffer.chunks(3).all(|px| px == &framebuffer[..3])
+}
+
+fn run_frame(cpu: &mut Cpu, mem: &mut Memory) {
+    let mut cycles = 0u32;
+    while cycles < FRAME_CYCLES {
+        cycles += cpu.step(mem);
+    }
+}
+
+/// Boot `rom` without a boot ROM and run it for `frames` frames
+pub fn check_rom(rom: impl Into<RomImage>, frames: u32) -> CompatStatus {
+    let rom = rom.into();
+    if let Err(err) = check_mapper(&rom) {
+        return CompatStatus::Unsupported(err.to_string());
+    }
+
+    let result = panic::catch_unwind(AssertUnwindSafe(|| {
+        let mut mem = Memory::new(rom);
+        mem.skip_boot(Model::Dmg);
+        let mut cpu = Cpu::new();
+        cpu.skip_boot(Model::Dmg, mem.read_8(0x014D));
+        cpu.registers.ime = 1;
+
+        // Only changes in the second half count, so a picture drawn once during
+        // start-up and then frozen is reported as static
+        let mut previous_hash = None;
+        let mut changed = false;
+        for frame in 0..frames {
+            run_frame(&mut cpu, &mut mem);
+            let hash = framebuffer_hash(&mem.ppu.framebuffer);
+            if frame >= frames / 2 && previous_hash.is_some_and(|prev| prev != hash) {
+                changed = true;
+            }
+            previous_hash = Some(hash);
+        }
+
+        if is_blank(&mem.ppu.framebuffer) {
+            CompatStatus::Blank
+        } else if changed {
+            CompatStatus::Running
+        } else {
+            CompatStatus::Static
+        }
+    }));
+
+    result.unwrap_or_else(|payload| {
+        let message = payload
+            .downcast_ref::<String>()
+            .cloned()
+            .or_else(|| payload.downcast_ref::<&str>().map(|s| s.to_string()))
+            .unwrap_or_else(|| "unknown panic".to_string());
+        CompatStatus::Crashed(message)
+    })
+}
+
+/// Check every .gb/.gbc file directly inside `dir` on `jobs` threads, sorted by file name.
+/// The files are memory-mapped (see `RomImage::map`).
+///
+/// # Safety
+///
+/// No .gb/.gbc file in `dir` may be written or truncated until this returns, as with
+/// `RomImage::map`.
+pub unsafe fn verify_dir(dir: &Path, frames: u32, jobs: usize) -> io::Result<Vec<CompatResult>> {
+    let mut roms: Vec<PathBuf> = fs::read_dir(dir)?
+        .filter_map(|entry| entry.ok().map(|e| e.path()))
+        .filter(|path| {
+            path.extension()
+                .and_then(|ext| ext.to_str())
+                .map(|ext| ext.eq_ignore_ascii_case("gb") || ext.eq_ignore_ascii_case("gbc"))
+                .unwrap_or(false)
+        })
+        .collect();
+    roms.sort();
+
+    Ok(run_parallel(&roms, jobs, |rom| {
+        // Mapped rather than read, so checking a large set doesn't wait on reading
+        // every file in full.
+        // SAFETY: the caller keeps the files unchanged until `verify_dir` returns, and each
+        // image is dropped before then.
+        let status = match unsafe { RomImage::map(rom) } {
+            Ok(data) => check_rom(data, frames),
+            Err(err) => CompatStatus::Crashed(format!("failed to read ROM: {}", err)),
+        };
+        CompatResult {
+            rom: rom.clone(),
+            status,
+        }
+    }))
+}
+
+/// Plain-text report, one line per ROM followed by a summary
+pub fn format_report(results: &[CompatResult]) -> String {
+    let mut out = String::new();
+    for result in results {
+        let name = result
+            .rom
+            .file_name()
+            .map(|n| n.to_string_lossy().into_owned())
+            .unwrap_or_else(|| result.rom.display().to_string());
+        let _ = write!(out, "{:<11} {}", result.status.label(), name);
+        if let CompatStatus::Crashed(message) | CompatStatus::Unsupported(message) = &result.status
+        {
+            let _ = write!(out, " ({})", message.lines().next().unwrap_or(""));
+        }
+        out.push('\n');
+    }
+
+    let count = |label: &str| results.iter().filter(|r| r.status.label() == label).count();
+    let _ = writeln!(
+        out,
+        "{} ROMs: {} running, {} static, {} blank, {} unsupported, {} crashed",
+        results.len(),
+        count("running"),
+        count("static"),
+        count("blank"),
+        count("unsupported"),
+        count("crashed")
+    );
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rom_with_program(program: &[u8]) -> Vec<u8> {
+        let mut rom = vec![0; 0x8000];
+        rom[0x0100..0x0100 + program.len()].copy_from_slice(program);
+        rom
+    }
+
+    #[test]
+    fn test_idle_rom_is_blank() {
+        // JR -2
+        let rom = rom_with_program(&[0x18, 0xFE]);
+        assert_eq!(check_rom(rom, 5), CompatStatus::Blank);
+    }
+
+    #[test]
+    fn test_scrolling_rom_is_running() {
+        let rom = rom_with_program(&[
+            0x3E, 0xFF, // LD A,0xFF
+            0xEA, 0x00, 0x80, // LD (0x8000),A ; first row of tile 0
+            0xF0, 0x42, // LDH A,(SCY)
+            0x3C, // INC A
+            0xE0, 0x42, // LDH (SCY),A
+            0x3E, 0xFF, // LD A,0xFF
+            0x18, 0xF4, // JR -12
+        ]);
+        assert_eq!(check_rom(rom, 5), CompatStatus::Running);
+    }
+
+    #[test]
+    fn test_frozen_picture_is_static() {
+        let rom = rom_with_program(&[
+            0x3E, 0xFF, // LD A,0xFF
+            0xEA, 0x00, 0x80, // LD (0x8000),A
+            0xFA, 0x00, 0x80, // LD A,(0x8000)
+            0xFE, 0xFF, // CP 0xFF
+            0x20, 0xF4, // JR NZ,-12 ; retry if the write hit mode 3
+            0x18, 0xFE, // JR -2
+        ]);
+        assert_eq!(check_rom(rom, 5), CompatStatus::Static);
+    }
+
+    #[test]
+    fn test_unsupported_mapper_is_reported() {
+        let mut rom = rom_with_program(&[0x18, 0xFE]);
+        rom[0x0147] = 0x20; // MBC6
+        assert_eq!(
+            check_rom(rom, 5),
+            CompatStatus::Unsupported("unsupported cartridge mapper 0x20 (MBC6)".to_string())
+        );
+    }
+
+    #[test]
+    fn test_run_parallel_keeps_order() {
+        let items: Vec<u32> = (0..50).collect();
+        let squares = run_parallel(&items, 4, |n| n * n);
+        assert_eq!(squares, items.iter().map(|n| n * n).collect::<Vec<_>>());
+        assert!(run_parallel(&[] as &[u32], 4, |n| *n).is_empty());
+    }
+
+    #[test]
+    fn test_verify_dir_in_parallel() {
+        let dir = std::env::temp_dir().join(format!("gbemu_verify_dir_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        for name in ["a.gb", "b.gb", "c.gbc"] {
+            fs::write(dir.join(name), rom_with_program(&[0x18, 0xFE])).unwrap();
+        }
+        fs::write(dir.join("notes.txt"), "not a ROM").unwrap();
+
+        // SAFETY: nothing else touches the test's own directory
+        let results = unsafe { verify_dir(&dir, 2, 3) }.unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+
+        let names: Vec<_> = results.iter().map(|r| r.rom.file_name().unwrap()).collect();
+        assert_eq!(names, ["a.gb", "b.gb", "c.gbc"]);
+        assert!(results.iter().all(|r| r.status == CompatStatus::Blank));
+    }
+
+    #[test]
+    fn test_report_summary() {
+        let results = vec![
+            CompatResult {
+                rom: PathBuf::from("roms/a.gb"),
+                status: CompatStatus::Running,
+            },
+            CompatResult {
+                rom: PathBuf::from("roms/b.gb"),
+                status: CompatStatus::Crashed("boom\nbacktrace".to_string()),
+            },
+        ];
+        let report = format_report(&results);
+        assert!(report.contains("running     a.gb\n"));
+        assert!(report.contains("crashed     b.gb (boom)\n"));
+        assert!(
+            report.ends_with("2 ROMs: 1 running, 0 static, 0 blank, 0 unsupported, 1 crashed\n")
+        );
+    }
+}
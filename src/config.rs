@@ -0,0 +1,212 @@
+// Configuration file
+// Host preferences kept between runs, stored as `key = value` lines; `#` starts a
+// comment. Unknown keys are preserved so older builds don't drop newer settings.
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::bindings::Bindings;
+use crate::hotkeys::{self, ComboHotkeys};
+use crate::i18n::Language;
+use crate::palette::ColorScheme;
+
+const PALETTE_KEY: &str = "palette";
+const LANGUAGE_KEY: &str = "language";
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Config {
+    entries: BTreeMap<String, String>,
+}
+
+impl Config {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// `$GBEMU_CONFIG`, or `gbemu_rust/config` in the XDG config directory
+    pub fn default_path() -> Option<PathBuf> {
+        if let Some(path) = std::env::var_os("GBEMU_CONFIG") {
+            return Some(PathBuf::from(path));
+        }
+        let config_dir = std::env::var_os("XDG_CONFIG_HOME")
+            .map(PathBuf::from)
+            .or_else(|| std::env::var_os("HOME").map(|home| Path::new(&home).join(".config")))?;
+        Some(config_dir.join("gbemu_rust").join("config"))
+    }
+
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let mut config = Config::new();
+        for (number, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or_default().trim();
+            if line.is_empty() {
+                continue;
+            }
+            let (key, value) = line
+                .split_once('=')
+                .ok_or_else(|| format!("line {}: expected key = value", number + 1))?;
+            config.set(key.trim(), value.trim());
+        }
+        Ok(config)
+    }
+
+    /// Read the config file; a missing file is an empty config
+    pub fn load(path: &Path) -> Result<Self, String> {
+        match fs::read_to_string(path) {
+            Ok(text) => Self::parse(&text).map_err(|err| format!("{}: {}", path.display(), err)),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(Config::new()),
+            Err(err) => Err(format!("{}: {}", path.display(), err)),
+        }
+    }
+
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        fs::write(path, self.to_string())
+    }
+
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.entries.get(key).map(String::as_str)
+    }
+
+    pub fn set(&mut self, key: &str, value: &str) {
+        self.entries.insert(key.to_string(), value.to_string());
+    }
+
+    /// DMG shade colors, if set
+    pub fn color_scheme(&self) -> Result<Option<ColorScheme>, String> {
+        self.get(PALETTE_KEY)
+            .map(|value| value.parse())
+            .transpose()
+            .map_err(|err| format!("{}: {}", PALETTE_KEY, err))
+    }
+
+    pub fn set_color_scheme(&mut self, scheme: ColorScheme) {
+        self.set(PALETTE_KEY, &scheme.to_string());
+    }
+
+    /// Frontend message language, if set
+    pub fn language(&self) -> Result<Option<Language>, String> {
+        self.get(LANGUAGE_KEY)
+            .map(|value| value.parse())
+            .transpose()
+            .map_err(|err| format!("{}: {}", LANGUAGE_KEY, err))
+    }
+}
+
+impl Config {
+    /// Controller combos bound with `hotkey.BUTTON+BUTTON... = ACTION` entries
+    pub fn combo_hotkeys(&self) -> Result<ComboHotkeys, String> {
+        let mut combos = ComboHotkeys::new();
+        for (key, value) in &self.entries {
+            if let Some(combo) = key.strip_prefix(hotkeys::CONFIG_PREFIX) {
+                let binding = combo.parse().and_then(|combo| Ok((combo, value.parse()?)));
+                let (combo, action) = binding.map_err(|err| format!("{}: {}", key, err))?;
+                combos.bind(combo, action);
+            }
+        }
+        Ok(combos)
+    }
+
+    /// Default bindings with the config's `key.` and `pad.` entries applied
+    pub fn bindings(&self) -> Result<Bindings, String> {
+        let mut bindings = Bindings::default();
+        for (key, value) in &self.entries {
+            bindings
+                .bind_entry(key, value)
+                .map_err(|err| format!("{}: {}", key, err))?;
+        }
+        Ok(bindings)
+    }
+}
+
+impl fmt::Display for Config {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (key, value) in &self.entries {
+            writeln!(f, "{} = {}", key, value)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hotkeys::{HotkeyAction, PadButton};
+    use crate::joypad::JoypadButton;
+
+    #[test]
+    fn test_parse_round_trip() {
+        let config =
+            Config::parse("# settings\npalette = gray\n\nfuture_key = 1 # kept\n").unwrap();
+        assert_eq!(config.color_scheme(), Ok(Some(ColorScheme::GRAYSCALE)));
+        assert_eq!(config.get("future_key"), Some("1"));
+        assert_eq!(config.language(), Ok(None));
+        assert_eq!(Config::parse(&config.to_string()), Ok(config));
+
+        assert!(Config::parse("palette").is_err());
+        assert_eq!(
+            Config::parse("language = it").unwrap().language(),
+            Ok(Some(Language::Italian))
+        );
+        assert!(Config::parse("language = xx").unwrap().language().is_err());
+        assert!(Config::parse("palette = nope")
+            .unwrap()
+            .color_scheme()
+            .is_err());
+    }
+
+    #[test]
+    fn test_combo_hotkeys() {
+        let config =
+            Config::parse("hotkey.select+start+l = save-state\nhotkey.r+l = turbo\n").unwrap();
+        let mut combos = config.combo_hotkeys().unwrap();
+        combos.press(0, PadButton::RightShoulder);
+        assert_eq!(
+            combos.press(0, PadButton::LeftShoulder),
+            Some(HotkeyAction::ToggleTurbo)
+        );
+        assert!(Config::parse("hotkey.select = rewind")
+            .unwrap()
+            .combo_hotkeys()
+            .is_ok());
+        assert!(Config::parse("hotkey.select = fly")
+            .unwrap()
+            .combo_hotkeys()
+            .is_err());
+        assert!(Config::new().combo_hotkeys().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_bindings() {
+        let config = Config::parse("key.start = Space\npad.a = b\n").unwrap();
+        let bindings = config.bindings().unwrap();
+        assert_eq!(bindings.keys(JoypadButton::Start), ["Space"]);
+        assert_eq!(bindings.pad(JoypadButton::A), [PadButton::B]);
+        assert_eq!(bindings.keys(JoypadButton::A), ["Z"]);
+        assert_eq!(
+            Config::parse("key.turbo = Tab").unwrap().bindings(),
+            Err("key.turbo: unknown Game Boy button 'turbo'".to_string())
+        );
+    }
+
+    #[test]
+    fn test_save_and_load() {
+        let path = std::env::temp_dir()
+            .join(format!("gbemu_config_test_{}", std::process::id()))
+            .join("config");
+        assert_eq!(Config::load(&path), Ok(Config::new()));
+
+        let mut config = Config::new();
+        config.set_color_scheme(ColorScheme::POCKET);
+        config.save(&path).unwrap();
+        assert_eq!(
+            Config::load(&path).unwrap().color_scheme(),
+            Ok(Some(ColorScheme::POCKET))
+        );
+        fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+}
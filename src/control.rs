@@ -0,0 +1,647 @@
+// Control server (feature "control-server")
+// Lets external tools (test orchestration, bots) drive the emulator over a localhost TCP
+// socket. Each request is one flat JSON object per line, e.g.
+//     {"cmd":"peek","addr":49152,"len":4}
+// and is answered with one JSON object per line: {"ok":true,...} or {"ok":false,"error":"..."}.
+// Connections are served on background threads, but requests are only applied by
+// `ControlServer::poll`, called from the emulation loop, so the core stays single-threaded.
+
+use std::fmt::Write as _;
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::str::FromStr;
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::thread;
+
+use crate::cpu::{Cpu, Reg16};
+use crate::debugger::{self, Breakpoints, HitKind};
+use crate::joypad::ButtonMask;
+use crate::memory::{Memory, EXTERNAL_RAM_SIZE, MAX_EXTERNAL_RAM_BANKS};
+use crate::palette::ColorScheme;
+
+// Largest block returned by one peek
+const MAX_PEEK_LEN: u32 = 0x1000;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum JsonValue {
+    String(String),
+    Number(i64),
+    Bool(bool),
+    Null,
+}
+
+// Parse a flat JSON object (string, integer, bool and null values only)
+fn parse_object(line: &str) -> Result<Vec<(String, JsonValue)>, String> {
+    let mut chars = line.trim().chars().peekable();
+    let mut fields = Vec::new();
+
+    fn skip_ws(chars: &mut std::iter::Peekable<std::str::Chars>) {
+        while chars.peek().is_some_and(|c| c.is_whitespace()) {
+            chars.next();
+        }
+    }
+    fn expect(chars: &mut std::iter::Peekable<std::str::Chars>, c: char) -> Result<(), String> {
+        skip_ws(chars);
+        match chars.next() {
+            Some(found) if found == c => Ok(()),
+            Some(found) => Err(format!("expected '{}', found '{}'", c, found)),
+            None => Err(format!("expected '{}', found end of line", c)),
+        }
+    }
+    fn string(chars: &mut std::iter::Peekable<std::str::Chars>) -> Result<String, String> {
+        expect(chars, '"')?;
+        let mut s = String::new();
+        loop {
+            match chars.next() {
+                Some('"') => return Ok(s),
+                Some('\\') => match chars.next() {
+                    Some(c @ ('"' | '\\' | '/')) => s.push(c),
+                    Some('n') => s.push('\n'),
+                    Some(c) => return Err(format!("unsupported escape '\\{}'", c)),
+                    None => return Err("unterminated string".to_string()),
+                },
+                Some(c) => s.push(c),
+                None => return Err("unterminated string".to_string()),
+            }
+        }
+    }
+
+    expect(&mut chars, '{')?;
+    skip_ws(&mut chars);
+    if chars.peek() == Some(&'}') {
+        chars.next();
+        return Ok(fields);
+    }
+    loop {
+        skip_ws(&mut chars);
+        let key = string(&mut chars)?;
+        expect(&mut chars, ':')?;
+        skip_ws(&mut chars);
+        let value = match chars.peek() {
+            Some('"') => JsonValue::String(string(&mut chars)?),
+            Some(c) if c.is_ascii_alphanumeric() || *c == '-' => {
+                let mut word = String::new();
+                while chars
+                    .peek()
+                    .is_some_and(|c| c.is_ascii_alphanumeric() || *c == '-')
+                {
+                    word.push(chars.next().unwrap());
+                }
+                match word.as_str() {
+                    "true" => JsonValue::Bool(true),
+                    "false" => JsonValue::Bool(false),
+                    "null" => JsonValue::Null,
+                    number => JsonValue::Number(
+                        number
+                            .parse()
+                            .map_err(|_| format!("invalid value '{}'", number))?,
+                    ),
+                }
+            }
+            _ => return Err(format!("unsupported value for '{}'", key)),
+        };
+        fields.push((key, value));
+
+        skip_ws(&mut chars);
+        match chars.next() {
+            Some(',') => continue,
+            Some('}') => break,
+            _ => return Err("expected ',' or '}'".to_string()),
+        }
+    }
+    skip_ws(&mut chars);
+    match chars.next() {
+        None => Ok(fields),
+        Some(c) => Err(format!("unexpected '{}' after object", c)),
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ControlRequest {
+    /// Stop emulating frames (the window keeps running)
+    Pause,
+    Resume,
+    /// PC, frame count and pause state
+    Status,
+    /// Keep a copy of the machine state in the server
+    SaveState,
+    /// Restore the copy made by the last SaveState
+    LoadState,
+    /// Current frame as 160x144 RGB24, hex encoded
+    Screenshot,
+    Peek {
+        address: u16,
+        len: u32,
+    },
+    Poke {
+        address: u16,
+        value: u8,
+    },
+    /// Read cartridge RAM by bank and offset, with the RAM-enable state
+    CartRamPeek {
+        bank: usize,
+        offset: usize,
+        len: u32,
+    },
+    /// Edit cartridge RAM (e.g. to repair a save); the battery save is rewritten
+    CartRamPoke {
+        bank: usize,
+        offset: usize,
+        value: u8,
+    },
+    /// Replace the held buttons (same bit layout as ButtonMask)
+    Input {
+        buttons: ButtonMask,
+    },
+    /// Draw the four shades with other colors, e.g. `"shades":"FFFFFF,AAAAAA,555555,000000"`
+    Palette {
+        scheme: ColorScheme,
+    },
+    /// Add a PC breakpoint, reported by StepFrame
+    Break {
+        address: u16,
+    },
+    /// Watch an address for value changes, reported by StepFrame
+    Watch {
+        address: u16,
+    },
+    ClearBreakpoints,
+    /// Pause and run exactly one frame, returning each breakpoint and watchpoint hit
+    /// with the scanline and dot it happened at
+    StepFrame,
+}
+
+/// Parses one request line, e.g. `{"cmd":"poke","addr":49152,"value":3}`
+impl FromStr for ControlRequest {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let fields = parse_object(s)?;
+        let field = |name: &str| fields.iter().find(|(k, _)| k == name).map(|(_, v)| v);
+        let number = |name: &str, max: i64| match field(name) {
+            Some(JsonValue::Number(n)) if (0..=max).contains(n) => Ok(*n),
+            Some(_) => Err(format!("'{}' must be a number from 0 to {}", name, max)),
+            None => Err(format!("missing '{}'", name)),
+        };
+
+        let request = match field("cmd") {
+            Some(JsonValue::String(cmd)) => match cmd.as_str() {
+                "pause" => ControlRequest::Pause,
+                "resume" => ControlRequest::Resume,
+                "status" => ControlRequest::Status,
+                "savestate" => ControlRequest::SaveState,
+                "loadstate" => ControlRequest::LoadState,
+                "screenshot" => ControlRequest::Screenshot,
+                "peek" => ControlRequest::Peek {
+                    address: number("addr", 0xFFFF)? as u16,
+                    len: match field("len") {
+                        None => 1,
+                        Some(_) => number("len", MAX_PEEK_LEN as i64)? as u32,
+                    },
+                },
+                "poke" => ControlRequest::Poke {
+                    address: number("addr", 0xFFFF)? as u16,
+                    value: number("value", 0xFF)? as u8,
+                },
+                "cartram_peek" => ControlRequest::CartRamPeek {
+                    bank: number("bank", MAX_EXTERNAL_RAM_BANKS as i64 - 1)? as usize,
+                    offset: number("offset", EXTERNAL_RAM_SIZE as i64 - 1)? as usize,
+                    len: match field("len") {
+                        None => 1,
+                        Some(_) => number("len", MAX_PEEK_LEN as i64)? as u32,
+                    },
+                },
+                "cartram_poke" => ControlRequest::CartRamPoke {
+                    bank: number("bank", MAX_EXTERNAL_RAM_BANKS as i64 - 1)? as usize,
+                    offset: number("offset", EXTERNAL_RAM_SIZE as i64 - 1)? as usize,
+                    value: number("value", 0xFF)? as u8,
+                },
+                "input" => ControlRequest::Input {
+                    buttons: ButtonMask(number("buttons", 0xFF)? as u8),
+                },
+                "palette" => match field("shades") {
+                    Some(JsonValue::String(shades)) => ControlRequest::Palette {
+                        scheme: shades.parse()?,
+                    },
+                    Some(_) => return Err("'shades' must be a string".to_string()),
+                    None => return Err("missing 'shades'".to_string()),
+                },
+                "break" => ControlRequest::Break {
+                    address: number("addr", 0xFFFF)? as u16,
+                },
+                "watch" => ControlRequest::Watch {
+                    address: number("addr", 0xFFFF)? as u16,
+                },
+                "clear_breakpoints" => ControlRequest::ClearBreakpoints,
+                "step_frame" => ControlRequest::StepFrame,
+                other => return Err(format!("unknown command '{}'", other)),
+            },
+            _ => return Err("missing 'cmd'".to_string()),
+        };
+        Ok(request)
+    }
+}
+
+// Errors can echo what the client sent, so everything JSON doesn't allow raw in a string
+// is escaped, keeping the response on one line
+fn error_response(message: &str) -> String {
+    let mut escaped = String::with_capacity(message.len());
+    for c in message.chars() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            '"' => escaped.push_str("\\\""),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            '\t' => escaped.push_str("\\t"),
+            c if c.is_control() => escaped.push_str(&format!("\\u{:04x}", c as u32)),
+            c => escaped.push(c),
+        }
+    }
+    format!("{{\"ok\":false,\"error\":\"{}\"}}", escaped)
+}
+
+type PendingRequest = (ControlRequest, Sender<String>);
+
+pub struct ControlServer {
+    local_addr: SocketAddr,
+    requests: Receiver<PendingRequest>,
+    paused: bool,
+    saved_state: Option<(Cpu, Box<Memory>)>,
+    breakpoints: Breakpoints,
+}
+
+impl ControlServer {
+    /// Listen on 127.0.0.1:`port` (0 picks a free port, see `local_addr`)
+    pub fn bind(port: u16) -> io::Result<Self> {
+        let listener = TcpListener::bind(("127.0.0.1", port))?;
+        let local_addr = listener.local_addr()?;
+        let (sender, requests) = mpsc::channel();
+        thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                let sender = sender.clone();
+                thread::spawn(move || serve_connection(stream, sender));
+            }
+        });
+        Ok(Self {
+            local_addr,
+            requests,
+            paused: false,
+            saved_state: None,
+            breakpoints: Breakpoints::default(),
+        })
+    }
+
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+
+    pub fn paused(&self) -> bool {
+        self.paused
+    }
+
+    /// Apply all requests received since the last call. Call once per frame,
+    /// including while paused.
+    pub fn poll(&mut self, cpu: &mut Cpu, mem: &mut Memory) {
+        while let Ok((request, reply)) = self.requests.try_recv() {
+            let response = self.handle(request, cpu, mem);
+            // The client may have disconnected in the meantime
+            let _ = reply.send(response);
+        }
+    }
+
+    /// Apply one request and build its JSON response
+    pub fn handle(&mut self, request: ControlRequest, cpu: &mut Cpu, mem: &mut Memory) -> String {
+        match request {
+            ControlRequest::Pause => self.paused = true,
+            ControlRequest::Resume => self.paused = false,
+            ControlRequest::Status => {
+                return format!(
+                    "{{\"ok\":true,\"paused\":{},\"pc\":{},\"frame\":{},\"cycles\":{}}}",
+                    self.paused,
+                    cpu.registers.read_r16(Reg16::PC),
+                    mem.clock.frame_count(),
+                    mem.clock.cycles()
+                );
+            }
+            ControlRequest::SaveState => {
+                self.saved_state = Some((cpu.clone(), Box::new(mem.clone())));
+            }
+            ControlRequest::LoadState => match &self.saved_state {
+                Some((saved_cpu, saved_mem)) => {
+                    *cpu = saved_cpu.clone();
+                    *mem = (**saved_mem).clone();
+                    mem.ppu.mark_all_rows_dirty();
+                }
+                None => return error_response("no saved state"),
+            },
+            ControlRequest::Screenshot => {
+                let mut rgb = String::with_capacity(mem.ppu.framebuffer.len() * 2);
+                for byte in mem.ppu.framebuffer.iter() {
+                    let _ = write!(rgb, "{:02x}", byte);
+                }
+                return format!(
+                    "{{\"ok\":true,\"width\":160,\"height\":144,\"rgb\":\"{}\"}}",
+                    rgb
+                );
+            }
+            ControlRequest::Peek { address, len } => {
+                let data: Vec<String> = (0..len)
+                    .map(|i| mem.read_8(address.wrapping_add(i as u16)).to_string())
+                    .collect();
+                return format!("{{\"ok\":true,\"data\":[{}]}}", data.join(","));
+            }
+            ControlRequest::Poke { address, value } => mem.write_8(address, value),
+            ControlRequest::CartRamPeek { bank, offset, len } => {
+                let Some(ram) = mem.external_ram_bank(bank) else {
+                    return error_response(&format!("no cartridge RAM bank {}", bank));
+                };
+                let end = (offset + len as usize).min(ram.len());
+                let data: Vec<String> = ram[offset..end].iter().map(u8::to_string).collect();
+                return format!(
+                    "{{\"ok\":true,\"enabled\":{},\"data\":[{}]}}",
+                    mem.external_ram_enabled(),
+                    data.join(",")
+                );
+            }
+            ControlRequest::CartRamPoke {
+                bank,
+                offset,
+                value,
+            } => {
+                if let Err(err) = mem.write_external_ram(bank, offset, &[value]) {
+                    return error_response(&err);
+                }
+            }
+            ControlRequest::Input { buttons } => mem.joypad.set_buttons(buttons),
+            ControlRequest::Palette { scheme } => mem.ppu.set_color_scheme(scheme),
+            ControlRequest::Break { address } => {
+                self.breakpoints.pc.insert(address);
+            }
+            ControlRequest::Watch { address } => {
+                self.breakpoints.watch.insert(address);
+            }
+            ControlRequest::ClearBreakpoints => self.breakpoints = Breakpoints::default(),
+            ControlRequest::StepFrame => {
+                self.paused = true;
+                let step = debugger::step_frame(cpu, mem, &self.breakpoints);
+                let hits: Vec<String> = step
+                    .hits
+                    .iter()
+                    .map(|hit| {
+                        let kind = match hit.kind {
+                            HitKind::Breakpoint => "\"type\":\"break\"".to_string(),
+                            HitKind::Watchpoint { address, old, new } => format!(
+                                "\"type\":\"watch\",\"addr\":{},\"old\":{},\"new\":{}",
+                                address, old, new
+                            ),
+                            HitKind::Read { address, value } => format!(
+                                "\"type\":\"read\",\"addr\":{},\"value\":{}",
+                                address, value
+                            ),
+                            HitKind::Write { address, value } => format!(
+                                "\"type\":\"write\",\"addr\":{},\"value\":{}",
+                                address, value
+                            ),
+                            HitKind::Strict(violation) => {
+                                format!("\"type\":\"strict\",\"message\":\"{}\"", violation)
+                            }
+                        };
+                        format!(
+                            "{{{},\"pc\":{},\"cycle\":{},\"ly\":{},\"dot\":{}}}",
+                            kind, hit.pc, hit.cycle, hit.ly, hit.dot
+                        )
+                    })
+                    .collect();
+                return format!(
+                    "{{\"ok\":true,\"cycle\":{},\"cycles\":{},\"hits\":[{}]}}",
+                    step.start_cycle,
+                    step.cycles,
+                    hits.join(",")
+                );
+            }
+        }
+        "{\"ok\":true}".to_string()
+    }
+}
+
+fn serve_connection(stream: TcpStream, requests: Sender<PendingRequest>) {
+    let Ok(mut writer) = stream.try_clone() else {
+        return;
+    };
+    for line in BufReader::new(stream).lines() {
+        let Ok(line) = line else {
+            return;
+        };
+        if line.trim().is_empty() {
+            continue;
+        }
+        let response = match line.parse::<ControlRequest>() {
+            Ok(request) => {
+                let (reply, response) = mpsc::channel();
+                if requests.send((request, reply)).is_err() {
+                    return; // Server dropped
+                }
+                match response.recv() {
+                    Ok(response) => response,
+                    Err(_) => return,
+                }
+            }
+            Err(err) => error_response(&err),
+        };
+        if writeln!(writer, "{}", response).is_err() {
+            return;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn machine() -> (Cpu, Memory) {
+        (Cpu::new(), Memory::new(vec![0; 0x8000]))
+    }
+
+    #[test]
+    fn test_parse_requests() {
+        assert_eq!(
+            r#"{"cmd":"cartram_poke","bank":0,"offset":16,"value":1}"#.parse(),
+            Ok(ControlRequest::CartRamPoke {
+                bank: 0,
+                offset: 16,
+                value: 1
+            })
+        );
+        assert!(
+            r#"{"cmd":"cartram_peek","bank":16,"offset":0}"#.parse::<ControlRequest>().is_err()
+        );
+        assert_eq!(
+            r#"{"cmd":"poke","addr":49152,"value":3}"#.parse(),
+            Ok(ControlRequest::Poke {
+                address: 0xC000,
+                value: 3
+            })
+        );
+        assert_eq!(
+            r#" { "cmd" : "peek", "addr" : 65280 } "#.parse(),
+            Ok(ControlRequest::Peek {
+                address: 0xFF00,
+                len: 1
+            })
+        );
+        assert_eq!(
+            r#"{"cmd":"input","buttons":144}"#.parse(),
+            Ok(ControlRequest::Input {
+                buttons: ButtonMask(0x90)
+            })
+        );
+        assert_eq!(
+            r#"{"cmd":"palette","shades":"pocket"}"#.parse(),
+            Ok(ControlRequest::Palette {
+                scheme: ColorScheme::POCKET
+            })
+        );
+        assert!(r#"{"cmd":"palette","shades":"FFFFFF"}"#.parse::<ControlRequest>().is_err());
+        assert!(r#"{"cmd":"poke","addr":49152,"value":256}"#.parse::<ControlRequest>().is_err());
+        assert!(r#"{"cmd":"fly"}"#.parse::<ControlRequest>().is_err());
+        assert!("peek c000".parse::<ControlRequest>().is_err());
+    }
+
+    #[test]
+    fn test_error_response_stays_on_one_line() {
+        let err = r#"{"cmd":"a\nb\"c\""}"#.parse::<ControlRequest>().unwrap_err();
+        assert_eq!(
+            error_response(&err),
+            r#"{"ok":false,"error":"unknown command 'a\nb\"c\"'"}"#
+        );
+        assert_eq!(
+            error_response("\t\r\\\u{1}\u{7f}"),
+            r#"{"ok":false,"error":"\t\r\\\u0001\u007f"}"#
+        );
+    }
+
+    #[test]
+    fn test_handle_memory_and_state_requests() {
+        let (mut cpu, mut mem) = machine();
+        let mut server = ControlServer::bind(0).unwrap();
+
+        let mut handle = |request| server.handle(request, &mut cpu, &mut mem);
+        assert_eq!(
+            handle(ControlRequest::LoadState),
+            r#"{"ok":false,"error":"no saved state"}"#
+        );
+        handle(ControlRequest::Poke {
+            address: 0xC000,
+            value: 7,
+        });
+        handle(ControlRequest::SaveState);
+        handle(ControlRequest::Poke {
+            address: 0xC001,
+            value: 9,
+        });
+        assert_eq!(
+            handle(ControlRequest::Peek {
+                address: 0xC000,
+                len: 2
+            }),
+            r#"{"ok":true,"data":[7,9]}"#
+        );
+        handle(ControlRequest::LoadState);
+        assert_eq!(
+            handle(ControlRequest::Peek {
+                address: 0xC000,
+                len: 2
+            }),
+            r#"{"ok":true,"data":[7,0]}"#
+        );
+
+        handle(ControlRequest::CartRamPoke {
+            bank: 0,
+            offset: 0x1FFF,
+            value: 5,
+        });
+        assert_eq!(mem.read_8(0xBFFF), 5);
+        assert!(mem.take_external_ram_dirty());
+        let mut handle = |request| server.handle(request, &mut cpu, &mut mem);
+        assert_eq!(
+            handle(ControlRequest::CartRamPeek {
+                bank: 0,
+                offset: 0x1FFE,
+                len: 4
+            }),
+            r#"{"ok":true,"enabled":true,"data":[0,5]}"#
+        );
+    }
+
+    #[test]
+    fn test_step_frame_reports_hits() {
+        let mut rom = vec![0; 0x8000];
+        // LD HL,0xC000 ; INC (HL) ; JR -3
+        rom[0x0100..0x0106].copy_from_slice(&[0x21, 0x00, 0xC0, 0x34, 0x18, 0xFD]);
+        let mut mem = Memory::new(rom);
+        mem.skip_boot(crate::model::Model::Dmg);
+        let mut cpu = Cpu::new();
+        cpu.skip_boot(crate::model::Model::Dmg, 0);
+        let mut server = ControlServer::bind(0).unwrap();
+
+        assert_eq!(
+            "{\"cmd\":\"break\",\"addr\":259}".parse(),
+            Ok(ControlRequest::Break { address: 0x0103 })
+        );
+        server.handle(
+            ControlRequest::Break { address: 0x0100 },
+            &mut cpu,
+            &mut mem,
+        );
+        server.handle(
+            ControlRequest::Watch { address: 0xC000 },
+            &mut cpu,
+            &mut mem,
+        );
+        let response = server.handle(ControlRequest::StepFrame, &mut cpu, &mut mem);
+        assert!(server.paused());
+        assert!(response.starts_with(r#"{"ok":true,"cycle":"#));
+        assert!(response.contains(r#"{"type":"break","pc":256,"#));
+        assert!(response.contains(r#"{"type":"watch","addr":49152,"old":0,"new":1,"pc":259,"#));
+
+        server.handle(ControlRequest::ClearBreakpoints, &mut cpu, &mut mem);
+        let response = server.handle(ControlRequest::StepFrame, &mut cpu, &mut mem);
+        assert!(response.ends_with(r#""hits":[]}"#));
+    }
+
+    #[test]
+    fn test_requests_over_socket() {
+        let (mut cpu, mut mem) = machine();
+        let mut server = ControlServer::bind(0).unwrap();
+        let addr = server.local_addr();
+
+        let client = thread::spawn(move || {
+            let mut stream = TcpStream::connect(addr).unwrap();
+            let mut responses = BufReader::new(stream.try_clone().unwrap()).lines();
+            let lines = [
+                r#"{"cmd":"pause"}"#,
+                r#"{"cmd":"poke","addr":49152,"value":42}"#,
+                "not json",
+            ];
+            lines
+                .iter()
+                .map(|line| {
+                    writeln!(stream, "{}", line).unwrap();
+                    responses.next().unwrap().unwrap()
+                })
+                .collect::<Vec<_>>()
+        });
+
+        // Requests are only applied when the emulation loop polls
+        while !client.is_finished() {
+            server.poll(&mut cpu, &mut mem);
+            thread::yield_now();
+        }
+        let responses = client.join().unwrap();
+        assert_eq!(responses[0], r#"{"ok":true}"#);
+        assert_eq!(responses[1], r#"{"ok":true}"#);
+        assert!(responses[2].starts_with(r#"{"ok":false"#));
+        assert!(server.paused());
+        assert_eq!(mem.read_8(0xC000), 42);
+    }
+}
@@ -1,9 +1,12 @@
 use Reg16::*;
 use Reg8::*;
 
+use crate::gb_log;
+use crate::idle;
+use crate::logging::LogTarget;
 use crate::memory::Memory;
-
-pub static mut DEBUG_PC: u16 = 0;
+use crate::model::Model;
+use crate::savestate::{StateReader, StateWriter};
 
 // Enum for register operands - replaces string manipulation
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -50,6 +53,8 @@ impl Operand {
     }
 }
 
+#[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Cpu {
     pub registers: Registers,
     pub cycles: u64,
@@ -57,10 +62,12 @@ pub struct Cpu {
     pub halted: bool,     // CPU is halted waiting for interrupt
     pub halt_bug: bool, // HALT bug: PC doesn't increment after HALT when IME=0 and interrupt pending
     #[cfg(debug_assertions)]
+    #[cfg_attr(feature = "serde", serde(skip))]
     instruction_m_cycles_ticked: u32, // Debug: Track M-cycles ticked during current instruction
 }
 
 #[derive(Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Registers {
     af: u16,
     bc: u16,
@@ -135,60 +142,120 @@ impl Registers {
     }
 }
 
+// Placeholder entry for the 11 opcodes the SM83 leaves undefined (see INVALID_OPCODES).
+// Real hardware locks up when it fetches one, so they have neither a length nor a duration;
+// the emulator deliberately skips the byte instead and carries on.
+const INVALID: u8 = 0;
+
+/// Opcodes with no defined behaviour on the SM83.
+pub const INVALID_OPCODES: [u8; 11] = [
+    0xD3, 0xDB, 0xDD, 0xE3, 0xE4, 0xEB, 0xEC, 0xED, 0xF4, 0xFC, 0xFD,
+];
+
+// Instruction durations in T-cycles. Conditional jumps/calls/returns list the
+// taken (longest) duration.
 const OPCODE_DURATION: [u8; 256] = [
     // 0x00-0x0F
-    4, 12, 8, 8, 4, 4, 8, 4, 20, 8, 8, 8, 4, 4, 8, 4, // 0x10-0x1F
-    4, 12, 8, 8, 4, 4, 8, 4, 12, 8, 8, 8, 4, 4, 8, 4, // 0x20-0x2F
-    12, 12, 8, 8, 4, 4, 8, 4, 12, 8, 8, 8, 4, 4, 8, 4, // 0x30-0x3F
+    4, 12, 8, 8, 4, 4, 8, 4, 20, 8, 8, 8, 4, 4, 8, 4,
+    // 0x10-0x1F (0x10 STOP)
+    4, 12, 8, 8, 4, 4, 8, 4, 12, 8, 8, 8, 4, 4, 8, 4,
+    // 0x20-0x2F (JR cc,e = 12/8)
+    12, 12, 8, 8, 4, 4, 8, 4, 12, 8, 8, 8, 4, 4, 8, 4,
+    // 0x30-0x3F
     12, 12, 8, 8, 12, 12, 12, 4, 12, 8, 8, 8, 4, 4, 8, 4,
-    // 0x40-0x4F (LD r,r = 4 cycles, LD r,(HL) = 8)
-    4, 4, 4, 4, 4, 4, 8, 4, 4, 4, 4, 4, 4, 4, 8, 4, // 0x50-0x5F
-    4, 4, 4, 4, 4, 4, 8, 4, 4, 4, 4, 4, 4, 4, 8, 4, // 0x60-0x6F
+    // 0x40-0x4F (LD r,r = 4, LD r,(HL) = 8)
+    4, 4, 4, 4, 4, 4, 8, 4, 4, 4, 4, 4, 4, 4, 8, 4,
+    // 0x50-0x5F
+    4, 4, 4, 4, 4, 4, 8, 4, 4, 4, 4, 4, 4, 4, 8, 4,
+    // 0x60-0x6F
+    4, 4, 4, 4, 4, 4, 8, 4, 4, 4, 4, 4, 4, 4, 8, 4,
+    // 0x70-0x7F (LD (HL),r = 8, except 0x76 HALT = 4)
+    8, 8, 8, 8, 8, 8, 4, 8, 4, 4, 4, 4, 4, 4, 8, 4,
+    // 0x80-0x8F (ALU A,r = 4, ALU A,(HL) = 8)
+    4, 4, 4, 4, 4, 4, 8, 4, 4, 4, 4, 4, 4, 4, 8, 4,
+    // 0x90-0x9F
+    4, 4, 4, 4, 4, 4, 8, 4, 4, 4, 4, 4, 4, 4, 8, 4,
+    // 0xA0-0xAF
     4, 4, 4, 4, 4, 4, 8, 4, 4, 4, 4, 4, 4, 4, 8, 4,
-    // 0x70-0x77 (LD (HL),r = 8 cycles EXCEPT 0x76 HALT = 4)
-    8, 8, 8, 8, 8, 8, 4, 8, // 0x78-0x7F
-    4, 4, 4, 4, 4, 4, 8, 4, // 0x80-0x8F (ALU r = 4, ALU (HL) = 8)
-    4, 4, 4, 4, 4, 4, 8, 4, 4, 4, 4, 4, 4, 4, 8, 4, // 0x90-0x9F
-    4, 4, 4, 4, 4, 4, 8, 4, 4, 4, 4, 4, 4, 4, 8, 4, // 0xA0-0xAF
-    4, 4, 4, 4, 4, 4, 8, 4, 4, 4, 4, 4, 4, 4, 8, 4, // 0xB0-0xBF
+    // 0xB0-0xBF
     4, 4, 4, 4, 4, 4, 8, 4, 4, 4, 4, 4, 4, 4, 8, 4,
-    // 0xC0-0xCF (RET cc = 20/8, RET = 16, JP cc = 16/12, JP = 16, CALL cc = 24/12, CALL = 24)
-    20, 12, 16, 16, 24, 16, 8, 16, 20, 16, 16, 4, 24, 24, 8, 16, // 0xD0-0xDF
-    20, 12, 16, 0, 24, 16, 8, 16, 20, 16, 16, 0, 24, 0, 8, 16, // 0xE0-0xEF
-    12, 12, 8, 0, 0, 16, 8, 16, 16, 4, 16, 0, 0, 0, 8, 16, // 0xF0-0xFF
-    12, 12, 8, 4, 0, 16, 8, 16, 12, 8, 16, 4, 0, 0, 8, 16,
+    // 0xC0-0xCF (RET cc = 20/8, JP cc = 16/12, CALL cc = 24/12; 0xCB is timed by OPCODE_DURATION_CB)
+    20, 12, 16, 16, 24, 16, 8, 16, 20, 16, 16, 4, 24, 24, 8, 16,
+    // 0xD0-0xDF
+    20, 12, 16, INVALID, 24, 16, 8, 16, 20, 16, 16, INVALID, 24, INVALID, 8, 16,
+    // 0xE0-0xEF
+    12, 12, 8, INVALID, INVALID, 16, 8, 16, 16, 4, 16, INVALID, INVALID, INVALID, 8, 16,
+    // 0xF0-0xFF
+    12, 12, 8, 4, INVALID, 16, 8, 16, 12, 8, 16, 4, INVALID, INVALID, 8, 16,
 ];
 const OPCODE_DURATION_CB: [u8; 256] = [
-    8, 8, 8, 8, 8, 8, 16, 8, 8, 8, 8, 8, 8, 8, 16, 8, 8, 8, 8, 8, 8, 8, 16, 8, 8, 8, 8, 8, 8, 8,
-    16, 8, 8, 8, 8, 8, 8, 8, 16, 8, 8, 8, 8, 8, 8, 8, 16, 8, 8, 8, 8, 8, 8, 8, 16, 8, 8, 8, 8, 8,
-    8, 8, 16, 8, 8, 8, 8, 8, 8, 8, 16, 8, 8, 8, 8, 8, 8, 8, 16, 8, 8, 8, 8, 8, 8, 8, 16, 8, 8, 8,
-    8, 8, 8, 8, 16, 8, 8, 8, 8, 8, 8, 8, 16, 8, 8, 8, 8, 8, 8, 8, 16, 8, 8, 8, 8, 8, 8, 8, 16, 8,
-    8, 8, 8, 8, 8, 8, 16, 8, 8, 8, 8, 8, 8, 8, 16, 8, 8, 8, 8, 8, 8, 8, 16, 8, 8, 8, 8, 8, 8, 8,
-    16, 8, 8, 8, 8, 8, 8, 8, 16, 8, 8, 8, 8, 8, 8, 8, 16, 8, 8, 8, 8, 8, 8, 8, 16, 8, 8, 8, 8, 8,
-    8, 8, 16, 8, 8, 8, 8, 8, 8, 8, 16, 8, 8, 8, 8, 8, 8, 8, 16, 8, 8, 8, 8, 8, 8, 8, 16, 8, 8, 8,
-    8, 8, 8, 8, 16, 8, 8, 8, 8, 8, 8, 8, 16, 8, 8, 8, 8, 8, 8, 8, 16, 8, 8, 8, 8, 8, 8, 8, 16, 8,
+    // 0x00-0x0F (RLC/RRC)
+    8, 8, 8, 8, 8, 8, 16, 8, 8, 8, 8, 8, 8, 8, 16, 8,
+    // 0x10-0x1F (RL/RR)
+    8, 8, 8, 8, 8, 8, 16, 8, 8, 8, 8, 8, 8, 8, 16, 8,
+    // 0x20-0x2F (SLA/SRA)
+    8, 8, 8, 8, 8, 8, 16, 8, 8, 8, 8, 8, 8, 8, 16, 8,
+    // 0x30-0x3F (SWAP/SRL)
+    8, 8, 8, 8, 8, 8, 16, 8, 8, 8, 8, 8, 8, 8, 16, 8,
+    // 0x40-0x4F (BIT n,r = 8, BIT n,(HL) = 12)
+    8, 8, 8, 8, 8, 8, 12, 8, 8, 8, 8, 8, 8, 8, 12, 8,
+    // 0x50-0x5F
+    8, 8, 8, 8, 8, 8, 12, 8, 8, 8, 8, 8, 8, 8, 12, 8,
+    // 0x60-0x6F
+    8, 8, 8, 8, 8, 8, 12, 8, 8, 8, 8, 8, 8, 8, 12, 8,
+    // 0x70-0x7F
+    8, 8, 8, 8, 8, 8, 12, 8, 8, 8, 8, 8, 8, 8, 12, 8,
+    // 0x80-0x8F (RES n,r = 8, RES n,(HL) = 16)
+    8, 8, 8, 8, 8, 8, 16, 8, 8, 8, 8, 8, 8, 8, 16, 8,
+    // 0x90-0x9F
+    8, 8, 8, 8, 8, 8, 16, 8, 8, 8, 8, 8, 8, 8, 16, 8,
+    // 0xA0-0xAF
+    8, 8, 8, 8, 8, 8, 16, 8, 8, 8, 8, 8, 8, 8, 16, 8,
+    // 0xB0-0xBF
+    8, 8, 8, 8, 8, 8, 16, 8, 8, 8, 8, 8, 8, 8, 16, 8,
+    // 0xC0-0xCF (SET n,r = 8, SET n,(HL) = 16)
+    8, 8, 8, 8, 8, 8, 16, 8, 8, 8, 8, 8, 8, 8, 16, 8,
+    // 0xD0-0xDF
+    8, 8, 8, 8, 8, 8, 16, 8, 8, 8, 8, 8, 8, 8, 16, 8,
+    // 0xE0-0xEF
+    8, 8, 8, 8, 8, 8, 16, 8, 8, 8, 8, 8, 8, 8, 16, 8,
+    // 0xF0-0xFF
     8, 8, 8, 8, 8, 8, 16, 8, 8, 8, 8, 8, 8, 8, 16, 8,
 ];
+// Instruction lengths in bytes, including the opcode itself.
 const OPCODE_LENGTHS: [u8; 256] = [
     // 0x00-0x0F
-    1, 3, 1, 1, 1, 1, 2, 1, 3, 1, 1, 1, 1, 1, 2, 1, // 0x10-0x1F (0x10 STOP is 2 bytes)
-    2, 3, 1, 1, 1, 1, 2, 1, 2, 1, 1, 1, 1, 1, 2, 1, // 0x20-0x2F
-    2, 3, 1, 1, 1, 1, 2, 1, 2, 1, 1, 1, 1, 1, 2, 1, // 0x30-0x3F
+    1, 3, 1, 1, 1, 1, 2, 1, 3, 1, 1, 1, 1, 1, 2, 1,
+    // 0x10-0x1F (0x10 STOP is 2 bytes: the opcode is followed by a padding byte)
+    2, 3, 1, 1, 1, 1, 2, 1, 2, 1, 1, 1, 1, 1, 2, 1,
+    // 0x20-0x2F
     2, 3, 1, 1, 1, 1, 2, 1, 2, 1, 1, 1, 1, 1, 2, 1,
-    // 0x40-0x7F (LD r,r and HALT - all 1 byte)
-    1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1,
-    1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1,
-    // 0x80-0xBF (ALU operations - all 1 byte)
-    1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1,
-    1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1,
-    // 0xC0-0xCF
+    // 0x30-0x3F
+    2, 3, 1, 1, 1, 1, 2, 1, 2, 1, 1, 1, 1, 1, 2, 1,
+    // 0x40-0x4F
+    1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1,
+    // 0x50-0x5F
+    1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1,
+    // 0x60-0x6F
+    1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1,
+    // 0x70-0x7F
+    1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1,
+    // 0x80-0x8F
+    1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1,
+    // 0x90-0x9F
+    1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1,
+    // 0xA0-0xAF
+    1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1,
+    // 0xB0-0xBF
+    1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1,
+    // 0xC0-0xCF (0xCB prefix is 2 bytes including the CB opcode)
     1, 1, 3, 3, 3, 1, 2, 1, 1, 1, 3, 2, 3, 3, 2, 1,
-    // 0xD0-0xDF (0xD3, 0xDB, 0xDD are invalid = 0)
-    1, 1, 3, 0, 3, 1, 2, 1, 1, 1, 3, 0, 3, 0, 2, 1,
-    // 0xE0-0xEF (0xE3, 0xE4, 0xEB, 0xEC, 0xED are invalid = 0)
-    2, 1, 1, 0, 0, 1, 2, 1, 2, 1, 3, 0, 0, 0, 2, 1,
-    // 0xF0-0xFF (0xF4, 0xFC, 0xFD are invalid = 0)
-    2, 1, 1, 1, 0, 1, 2, 1, 2, 1, 3, 1, 0, 0, 2, 1,
+    // 0xD0-0xDF
+    1, 1, 3, INVALID, 3, 1, 2, 1, 1, 1, 3, INVALID, 3, INVALID, 2, 1,
+    // 0xE0-0xEF
+    2, 1, 1, INVALID, INVALID, 1, 2, 1, 2, 1, 3, INVALID, INVALID, INVALID, 2, 1,
+    // 0xF0-0xFF
+    2, 1, 1, 1, INVALID, 1, 2, 1, 2, 1, 3, 1, INVALID, INVALID, 2, 1,
 ];
 
 const ZERO_FLAG: u8 = 0b10000000;
@@ -223,7 +290,37 @@ impl Cpu {
         }
     }
 
-    /// Execute one instruction and return cycles taken
+    /// Load the register values `model`'s boot ROM leaves behind at 0x0100.
+    /// On DMG/MGB the H and C flags depend on the cartridge header checksum (0x014D).
+    pub fn skip_boot(&mut self, model: Model, header_checksum: u8) {
+        let checksum_flags = if header_checksum != 0 {
+            HALF_CARRY_FLAG | CARRY_FLAG
+        } else {
+            0
+        };
+        let (af, bc, de, hl) = match model {
+            Model::Dmg0 => (0x0100, 0xFF13, 0x00C1, 0x8403),
+            Model::Dmg => (0x0180 | checksum_flags as u16, 0x0013, 0x00D8, 0x014D),
+            Model::Mgb => (0xFF80 | checksum_flags as u16, 0x0013, 0x00D8, 0x014D),
+            Model::Cgb => (0x1180, 0x0000, 0xFF56, 0x000D),
+        };
+        self.registers.write_r16(AF, af);
+        self.registers.write_r16(BC, bc);
+        self.registers.write_r16(DE, de);
+        self.registers.write_r16(HL, hl);
+        self.registers.write_r16(SP, 0xFFFE);
+        self.registers.write_r16(PC, 0x0100);
+    }
+
+    /// Power-cycle the CPU: a pending EI, HALT and the HALT bug are cleared before
+    /// applying `model`'s post-boot registers. Pair with `Memory::reset`.
+    pub fn reset(&mut self, model: Model, header_checksum: u8) {
+        *self = Cpu::new();
+        self.skip_boot(model, header_checksum);
+    }
+
+    /// Execute one instruction (or one halted M-cycle), then dispatch a pending interrupt
+    /// if IME allows it; returns the T-cycles taken, including the 20 of a dispatch
     pub fn step(&mut self, mem: &mut Memory) -> u32 {
         // Reset instruction cycle counter for debug verification
         #[cfg(debug_assertions)]
@@ -231,31 +328,64 @@ impl Cpu {
             self.instruction_m_cycles_ticked = 0;
         }
 
+        let cycles = self.run_instruction(mem);
+        // Interrupts are checked between instructions
+        cycles + self.handle_interrupts(mem)
+    }
+
+    fn run_instruction(&mut self, mem: &mut Memory) -> u32 {
         // If CPU is halted, check if we should exit halt
         if self.halted {
             // Check if any interrupt is pending (regardless of IME)
             let ie = mem.read_8(0xFFFF);
             let if_reg = mem.read_8(0xFF0F);
             if (ie & if_reg & 0x1F) != 0 {
+                if self.registers.read_ime() != 0 {
+                    // handle_interrupts leaves HALT and dispatches straight away
+                    return 0;
+                }
                 // Exit halt state
                 self.halted = false;
             } else {
                 // Still halted, consume 4 cycles and return
                 mem.tick_components(1); // Tick 1 M-cycle even when halted
+                mem.flush_deferred_ticks();
                 return 4;
             }
         }
 
         let pc = self.registers.read_r16(PC);
 
-        // Fetch opcode - don't tick here, instructions handle their full timing including fetch
-        let opcode = mem.read_8(pc);
+        // Fast-forward LY busy-wait loops; the current iteration still runs below
+        let mut skipped_cycles = 0;
+        if mem.accuracy.idle_skip {
+            if let Some(spin) = idle::detect_ly_spin(mem, pc) {
+                skipped_cycles = idle::skip_ly_spin(mem, spin);
+            }
+        }
+
+        // Opcode fetch: the first M-cycle of every instruction. The instruction then ticks
+        // its remaining M-cycles in bus order, one per operand read, memory access or
+        // internal step, so each access sees the timer, PPU and serial where hardware does
+        mem.set_instruction_pc(pc);
+        let opcode = self.read_byte_tick(mem, pc);
+
+        // HALT bug: PC failed to move past the opcode, so its operands are read starting
+        // at the opcode itself, and the byte after the instruction is one earlier
+        if self.halt_bug {
+            self.halt_bug = false;
+            self.registers.write_r16(PC, pc.wrapping_sub(1));
+        }
+
+        // EI executed by the previous instruction; a DI in this one cancels it
+        let ei_was_pending = self.ei_pending;
 
         self.execute(opcode, mem);
-        let cycles = self.handle_post_instruction(mem, opcode, 0);
+        mem.flush_deferred_ticks(); // No-op unless sub-instruction timing is disabled
+        let cycles = self.handle_post_instruction(mem, opcode, 0) + skipped_cycles;
 
-        // Handle EI delay - if EI was executed, enable interrupts AFTER this instruction
-        if self.ei_pending {
+        // Handle EI delay - interrupts are enabled AFTER the instruction following EI
+        if ei_was_pending && self.ei_pending {
             self.registers.write_ime(1);
             self.ei_pending = false;
         }
@@ -267,7 +397,7 @@ impl Cpu {
     // This provides instruction-internal memory-access-level timing
 
     fn read_byte_tick(&mut self, mem: &mut Memory, addr: u16) -> u8 {
-        let value = mem.read_8(addr);
+        let value = mem.cpu_read_8(addr);
         mem.tick_components(1); // 1 M-cycle per memory read
         #[cfg(debug_assertions)]
         {
@@ -277,7 +407,7 @@ impl Cpu {
     }
 
     fn write_byte_tick(&mut self, mem: &mut Memory, addr: u16, value: u8) {
-        mem.write_8(addr, value);
+        mem.cpu_write_8(addr, value);
         mem.tick_components(1); // 1 M-cycle per memory write
         #[cfg(debug_assertions)]
         {
@@ -287,13 +417,33 @@ impl Cpu {
 
     fn read_word_tick(&mut self, mem: &mut Memory, addr: u16) -> u16 {
         let lo = self.read_byte_tick(mem, addr);
-        let hi = self.read_byte_tick(mem, addr + 1);
+        let hi = self.read_byte_tick(mem, addr.wrapping_add(1));
         u16::from_le_bytes([lo, hi])
     }
 
+    // Low byte first, as LD (nn),SP does; the address wraps past 0xFFFF
     fn write_word_tick(&mut self, mem: &mut Memory, addr: u16, value: u16) {
         self.write_byte_tick(mem, addr, (value & 0xFF) as u8);
-        self.write_byte_tick(mem, addr + 1, (value >> 8) as u8);
+        self.write_byte_tick(mem, addr.wrapping_add(1), (value >> 8) as u8);
+    }
+
+    // Stack pushes write the high byte first, at SP-1, then the low byte at SP-2.
+    // The order is visible when the stack overlaps IO registers or IE.
+    fn push_word_tick(&mut self, mem: &mut Memory, value: u16) {
+        let sp = self.registers.read_r16(SP).wrapping_sub(1);
+        self.write_byte_tick(mem, sp, (value >> 8) as u8);
+        let sp = sp.wrapping_sub(1);
+        self.write_byte_tick(mem, sp, (value & 0xFF) as u8);
+        self.registers.write_r16(SP, sp);
+    }
+
+    // Stack pops read the low byte at SP, then the high byte at SP+1
+    fn pop_word_tick(&mut self, mem: &mut Memory) -> u16 {
+        let sp = self.registers.read_r16(SP);
+        mem.check_stack_pop(sp);
+        let value = self.read_word_tick(mem, sp);
+        self.registers.write_r16(SP, sp.wrapping_add(2));
+        value
     }
 
     // Tick for internal CPU operations (ALU, etc.) that don't access memory
@@ -308,14 +458,12 @@ impl Cpu {
     fn ld_r16_nn(&mut self, mem: &mut Memory, reg: Reg16) {
         let pc = self.registers.read_r16(PC);
         let value = self.read_word_tick(mem, pc + 1); // Ticks 2 M-cycles (read immediate word)
-        self.tick_internal(mem, 1); // 1 additional M-cycle for 16-bit load
         self.registers.write_r16(reg, value);
     }
 
     fn ld_r8_n(&mut self, mem: &mut Memory, reg: Reg8) {
         let pc = self.registers.read_r16(PC);
         let value = self.read_byte_tick(mem, pc + 1); // Ticks 1 M-cycle
-        self.tick_internal(mem, 1); // Internal cycle
         self.registers.write_r8(reg, value);
     }
 
@@ -333,8 +481,7 @@ impl Cpu {
             };
             self.read_byte_tick(mem, addr) // Ticks 1 M-cycle (memory read)
         } else {
-            self.tick_internal(mem, 1); // Register-to-register: 1 M-cycle
-            self.read_operand(mem, source)
+            self.read_operand(mem, source) // Register-to-register: just the fetch
         };
 
         if needs_mem_write {
@@ -346,10 +493,6 @@ impl Cpu {
             };
             self.write_byte_tick(mem, addr, value); // Ticks 1 M-cycle (memory write)
         } else {
-            // Writing to register - for memory-to-register, need 1 additional M-cycle
-            if needs_mem_read {
-                self.tick_internal(mem, 1); // 1 M-cycle for memory-to-register (total 2)
-            }
             self.write_operand(mem, dest, value);
         }
     }
@@ -358,23 +501,21 @@ impl Cpu {
         let pc = self.registers.read_r16(PC);
         let addr = self.read_word_tick(mem, pc + 1); // Ticks 2 M-cycles (read nn)
         let value = self.registers.read_r8(A);
-        self.write_byte_tick(mem, addr, value); // Ticks 1 M-cycle (write to (nn))
-        self.tick_internal(mem, 1); // 1 additional internal M-cycle (total 4)
+        self.write_byte_tick(mem, addr, value); // Ticks 1 M-cycle (write to (nn)) - total 4
     }
 
     fn ld_m_n(&mut self, mem: &mut Memory) {
         let pc = self.registers.read_r16(PC);
         let value = self.read_byte_tick(mem, pc + 1); // Ticks 1 M-cycle (read immediate n)
         let addr = self.registers.read_r16(HL);
-        self.write_byte_tick(mem, addr, value); // Ticks 1 M-cycle (write to (HL))
-        self.tick_internal(mem, 1); // 1 additional internal M-cycle (total 3)
+        self.write_byte_tick(mem, addr, value); // Ticks 1 M-cycle (write to (HL)) - total 3
     }
 
     fn ld_sp_e(&mut self, mem: &mut Memory) {
         // Opcode 0xF8: LD HL, SP+e - Load SP + signed offset into HL
         let pc = self.registers.read_r16(PC);
         let offset = self.read_byte_tick(mem, pc + 1) as i8; // Ticks 1 M-cycle
-        self.tick_internal(mem, 2); // 2 internal cycles for calculation
+        self.tick_internal(mem, 1); // 1 internal cycle for calculation (total 3)
         let sp = self.registers.read_r16(SP);
         let result = sp.wrapping_add(offset as i16 as u16);
         self.registers.write_r16(HL, result);
@@ -398,7 +539,7 @@ impl Cpu {
 
     fn ld_sp_hl(&mut self, mem: &mut Memory) {
         // Opcode 0xF9: LD SP, HL - Copy HL to SP
-        self.tick_internal(mem, 2); // 2 M-cycles for 16-bit register transfer
+        self.tick_internal(mem, 1); // 1 internal M-cycle for 16-bit register transfer
         let value = self.registers.read_r16(HL);
         self.registers.write_r16(SP, value);
     }
@@ -408,56 +549,45 @@ impl Cpu {
         let pc = self.registers.read_r16(PC);
         let addr = self.read_word_tick(mem, pc + 1); // Ticks 2 M-cycles (read nn)
         let sp = self.registers.read_r16(SP);
-        self.write_word_tick(mem, addr, sp); // Ticks 2 M-cycles (write SP to (nn))
-        self.tick_internal(mem, 1); // 1 additional internal M-cycle (total 5)
+        self.write_word_tick(mem, addr, sp); // Ticks 2 M-cycles (write SP to (nn)) - total 5
     }
 
     fn ldh_n_a(&mut self, mem: &mut Memory) {
         let pc = self.registers.read_r16(PC);
         let offset = self.read_byte_tick(mem, pc + 1); // Ticks 1 M-cycle (read n)
         let value = self.registers.read_r8(A);
-        self.write_byte_tick(mem, 0xFF00 + offset as u16, value); // Ticks 1 M-cycle (write to FF00+n)
-        self.tick_internal(mem, 1); // 1 additional internal M-cycle (total 3)
+        self.write_byte_tick(mem, 0xFF00 + offset as u16, value); // Ticks 1 M-cycle (write to FF00+n) - total 3
     }
 
     fn ldh_a_n(&mut self, mem: &mut Memory) {
         let pc = self.registers.read_r16(PC);
         let offset = self.read_byte_tick(mem, pc + 1); // Ticks 1 M-cycle (read n)
-        let value = self.read_byte_tick(mem, 0xFF00 + offset as u16); // Ticks 1 M-cycle (read from FF00+n)
-        self.tick_internal(mem, 1); // 1 additional internal M-cycle (total 3)
+        let value = self.read_byte_tick(mem, 0xFF00 + offset as u16); // Ticks 1 M-cycle (read from FF00+n) - total 3
         self.registers.write_r8(A, value);
     }
 
     fn ldh_c_a(&mut self, mem: &mut Memory) {
-        self.tick_internal(mem, 1); // Internal cycle
         let value = self.registers.read_r8(A);
         self.write_byte_tick(mem, 0xFF00 + self.registers.read_r8(C) as u16, value); // Ticks 1 M-cycle
     }
 
     fn ldh_a_c(&mut self, mem: &mut Memory) {
-        self.tick_internal(mem, 1); // Internal cycle
         let value = self.read_byte_tick(mem, 0xFF00 + (self.registers.read_r8(C) as u16)); // Ticks 1 M-cycle
         self.registers.write_r8(A, value);
     }
 
     fn pop(&mut self, mem: &mut Memory, reg: Reg16) {
-        let sp = self.registers.read_r16(SP);
-        let value = self.read_word_tick(mem, sp); // Ticks 2 M-cycles for reading from stack
-        self.tick_internal(mem, 1); // 1 internal cycle (total 3)
+        let value = self.pop_word_tick(mem); // Ticks 2 M-cycles for reading from stack (total 3)
         self.registers.write_r16(reg, value);
-        self.registers.write_r16(SP, sp.wrapping_add(2));
     }
 
     fn push(&mut self, mem: &mut Memory, reg: Reg16) {
         let value = self.registers.read_r16(reg);
-        self.tick_internal(mem, 2); // 2 internal cycles for preparing push
-        let sp = self.registers.read_r16(SP);
-        self.registers.write_r16(SP, sp.wrapping_sub(2));
-        self.write_word_tick(mem, sp.wrapping_sub(2), value); // Ticks 2 M-cycles for writing to stack (total 4)
+        self.tick_internal(mem, 1); // 1 internal cycle to decrement SP
+        self.push_word_tick(mem, value); // Ticks 2 M-cycles for writing to stack (total 4)
     }
 
-    fn inc_r8(&mut self, mem: &mut Memory, reg: Reg8) {
-        self.tick_internal(mem, 1); // 1 M-cycle for 8-bit ALU operation
+    fn inc_r8(&mut self, reg: Reg8) {
         let value = self.registers.read_r8(reg);
         let result = value.wrapping_add(1);
         self.registers.write_r8(reg, result);
@@ -474,13 +604,13 @@ impl Cpu {
     }
 
     fn inc_r16(&mut self, mem: &mut Memory, reg: Reg16) {
-        self.tick_internal(mem, 2); // 2 M-cycles for 16-bit operation
         let value = self.registers.read_r16(reg);
+        mem.idu_access(value);
+        self.tick_internal(mem, 1); // 1 internal M-cycle for the 16-bit operation
         self.registers.write_r16(reg, value.wrapping_add(1));
     }
 
-    fn dec_r8(&mut self, mem: &mut Memory, reg: Reg8) {
-        self.tick_internal(mem, 1); // 1 M-cycle for 8-bit ALU operation
+    fn dec_r8(&mut self, reg: Reg8) {
         let value = self.registers.read_r8(reg);
         let result = value.wrapping_sub(1);
         self.registers.write_r8(reg, result);
@@ -498,8 +628,9 @@ impl Cpu {
     }
 
     fn dec_r16(&mut self, mem: &mut Memory, reg: Reg16) {
-        self.tick_internal(mem, 2); // 2 M-cycles for 16-bit operation
         let value = self.registers.read_r16(reg);
+        mem.idu_access(value);
+        self.tick_internal(mem, 1); // 1 internal M-cycle for the 16-bit operation
         self.registers.write_r16(reg, value.wrapping_sub(1));
     }
 
@@ -507,7 +638,6 @@ impl Cpu {
         let addr = self.registers.read_r16(reg);
         let value = self.read_byte_tick(mem, addr); // Ticks 1 M-cycle (read)
         let result = value.wrapping_add(1);
-        self.tick_internal(mem, 1); // 1 M-cycle for ALU operation
         self.write_byte_tick(mem, addr, result); // Ticks 1 M-cycle (write) - total 3
 
         let mut flags = self.registers.read_r8(F);
@@ -525,7 +655,6 @@ impl Cpu {
         let addr = self.registers.read_r16(reg);
         let value = self.read_byte_tick(mem, addr); // Ticks 1 M-cycle (read)
         let result = value.wrapping_sub(1);
-        self.tick_internal(mem, 1); // 1 M-cycle for ALU operation
         self.write_byte_tick(mem, addr, result); // Ticks 1 M-cycle (write) - total 3
 
         let mut flags = self.registers.read_r8(F);
@@ -540,8 +669,7 @@ impl Cpu {
         self.registers.write_r8(F, flags);
     }
 
-    fn rlca(&mut self, mem: &mut Memory) {
-        self.tick_internal(mem, 1); // 1 M-cycle for ALU operation
+    fn rlca(&mut self) {
         let value = self.registers.read_r8(A);
         let msb = value & 0x80;
         let new_value = (value << 1) | (msb >> 7);
@@ -556,8 +684,7 @@ impl Cpu {
         self.registers.write_r8(F, flags);
     }
 
-    fn rla(&mut self, mem: &mut Memory) {
-        self.tick_internal(mem, 1); // 1 M-cycle for ALU operation
+    fn rla(&mut self) {
         let value = self.registers.read_r8(A);
         let msb = value & 0x80;
         let new_value = (value << 1) | ((self.registers.read_r8(F) & CARRY_FLAG) >> 4);
@@ -570,8 +697,7 @@ impl Cpu {
         self.registers.write_r8(F, flags);
     }
 
-    fn rrca(&mut self, mem: &mut Memory) {
-        self.tick_internal(mem, 1); // 1 M-cycle for ALU operation
+    fn rrca(&mut self) {
         let value = self.registers.read_r8(A);
         let lsb = value & 0x01;
         let new_value = (value >> 1) | (lsb << 7);
@@ -587,8 +713,7 @@ impl Cpu {
         self.registers.write_r8(F, flags);
     }
 
-    fn rra(&mut self, mem: &mut Memory) {
-        self.tick_internal(mem, 1); // 1 M-cycle for ALU operation
+    fn rra(&mut self) {
         let value = self.registers.read_r8(A);
         let lsb = value & 0x01;
         let new_value = (value >> 1) | ((self.registers.read_r8(F) & CARRY_FLAG) << 3);
@@ -606,7 +731,7 @@ impl Cpu {
 
     //arithmetic and logic
     fn add_hl(&mut self, mem: &mut Memory, reg: Reg16) {
-        self.tick_internal(mem, 2); // 2 M-cycles for 16-bit ADD
+        self.tick_internal(mem, 1); // 1 internal M-cycle for the 16-bit ADD
         let value = self.registers.read_r16(reg);
         let hl = self.registers.read_r16(HL);
         let result: u32 = value as u32 + hl as u32;
@@ -628,11 +753,8 @@ impl Cpu {
     fn add_a_r(&mut self, mem: &mut Memory, op: Operand) {
         let value = if matches!(op, Operand::MemHL) {
             let addr = self.registers.read_r16(HL);
-            let v = self.read_byte_tick(mem, addr); // Ticks 1 M-cycle for memory access
-            self.tick_internal(mem, 1); // 1 M-cycle for ALU operation on memory (total 2)
-            v
+            self.read_byte_tick(mem, addr) // Ticks 1 M-cycle for memory access (total 2)
         } else {
-            self.tick_internal(mem, 1); // 1 M-cycle for register operation
             self.read_operand(mem, op)
         };
 
@@ -659,8 +781,7 @@ impl Cpu {
 
     fn add_a_n(&mut self, mem: &mut Memory) {
         let pc = self.registers.read_r16(PC);
-        let value = self.read_byte_tick(mem, pc + 1); // Ticks 1 M-cycle
-        self.tick_internal(mem, 1); // 1 M-cycle for ALU operation
+        let value = self.read_byte_tick(mem, pc + 1); // Ticks 1 M-cycle (total 2)
         let a = self.registers.read_r8(A);
         let result: u16 = value as u16 + a as u16;
         self.registers.write_r8(A, result as u8);
@@ -686,7 +807,7 @@ impl Cpu {
         // Opcode 0xE8: ADD SP, e - Add signed offset to SP
         let pc = self.registers.read_r16(PC);
         let offset = self.read_byte_tick(mem, pc + 1) as i8; // Ticks 1 M-cycle
-        self.tick_internal(mem, 3); // 3 internal M-cycles for SP arithmetic
+        self.tick_internal(mem, 2); // 2 internal M-cycles for SP arithmetic (total 4)
         let sp = self.registers.read_r16(SP);
         let result = sp.wrapping_add(offset as i16 as u16);
         self.registers.write_r16(SP, result);
@@ -711,11 +832,8 @@ impl Cpu {
     fn adc_a_r(&mut self, mem: &mut Memory, op: Operand) {
         let value = if matches!(op, Operand::MemHL) {
             let addr = self.registers.read_r16(HL);
-            let v = self.read_byte_tick(mem, addr); // Ticks 1 M-cycle for memory access
-            self.tick_internal(mem, 1); // 1 M-cycle for ALU operation on memory (total 2)
-            v
+            self.read_byte_tick(mem, addr) // Ticks 1 M-cycle for memory access (total 2)
         } else {
-            self.tick_internal(mem, 1); // 1 M-cycle for register operation
             self.read_operand(mem, op)
         };
 
@@ -744,8 +862,7 @@ impl Cpu {
 
     fn adc_a_n(&mut self, mem: &mut Memory) {
         let pc = self.registers.read_r16(PC);
-        let value = self.read_byte_tick(mem, pc + 1); // Ticks 1 M-cycle
-        self.tick_internal(mem, 1); // 1 M-cycle for ALU operation
+        let value = self.read_byte_tick(mem, pc + 1); // Ticks 1 M-cycle (total 2)
         let a = self.registers.read_r8(A);
         let carry = (self.registers.read_r8(F) & CARRY_FLAG) >> 4;
 
@@ -772,11 +889,8 @@ impl Cpu {
     fn sub_a_r(&mut self, mem: &mut Memory, op: Operand) {
         let value = if matches!(op, Operand::MemHL) {
             let addr = self.registers.read_r16(HL);
-            let v = self.read_byte_tick(mem, addr); // Ticks 1 M-cycle for memory access
-            self.tick_internal(mem, 1); // 1 M-cycle for ALU operation on memory (total 2)
-            v
+            self.read_byte_tick(mem, addr) // Ticks 1 M-cycle for memory access (total 2)
         } else {
-            self.tick_internal(mem, 1); // 1 M-cycle for register operation
             self.read_operand(mem, op)
         };
 
@@ -810,8 +924,7 @@ impl Cpu {
 
     fn sub_a_n(&mut self, mem: &mut Memory) {
         let pc = self.registers.read_r16(PC);
-        let value = self.read_byte_tick(mem, pc + 1); // Ticks 1 M-cycle
-        self.tick_internal(mem, 1); // 1 M-cycle for ALU operation
+        let value = self.read_byte_tick(mem, pc + 1); // Ticks 1 M-cycle (total 2)
         let a = self.registers.read_r8(A);
         let result = a.wrapping_sub(value);
         self.registers.write_r8(A, result);
@@ -843,11 +956,8 @@ impl Cpu {
     fn sbc_a_r(&mut self, mem: &mut Memory, op: Operand) {
         let value = if matches!(op, Operand::MemHL) {
             let addr = self.registers.read_r16(HL);
-            let v = self.read_byte_tick(mem, addr); // Ticks 1 M-cycle for memory access
-            self.tick_internal(mem, 1); // 1 M-cycle for ALU operation on memory (total 2)
-            v
+            self.read_byte_tick(mem, addr) // Ticks 1 M-cycle for memory access (total 2)
         } else {
-            self.tick_internal(mem, 1); // 1 M-cycle for register operation
             self.read_operand(mem, op)
         };
 
@@ -891,8 +1001,7 @@ impl Cpu {
 
     fn sbc_a_n(&mut self, mem: &mut Memory) {
         let pc = self.registers.read_r16(PC);
-        let value = self.read_byte_tick(mem, pc + 1); // Ticks 1 M-cycle
-        self.tick_internal(mem, 1); // 1 M-cycle for ALU operation
+        let value = self.read_byte_tick(mem, pc + 1); // Ticks 1 M-cycle (total 2)
         let a = self.registers.read_r8(A);
         let carry_in = if (self.registers.read_r8(F) & CARRY_FLAG) != 0 {
             1u8
@@ -931,8 +1040,7 @@ impl Cpu {
         self.registers.write_r8(F, flags);
     }
 
-    fn daa(&mut self, mem: &mut Memory) {
-        self.tick_internal(mem, 1); // 1 M-cycle for DAA operation
+    fn daa(&mut self) {
         let mut value = self.registers.read_r8(A);
         let flags = self.registers.read_r8(F);
         let mut new_flags = flags;
@@ -977,8 +1085,7 @@ impl Cpu {
         self.registers.write_r8(A, value);
     }
 
-    fn cpl(&mut self, mem: &mut Memory) {
-        self.tick_internal(mem, 1); // 1 M-cycle for CPL operation
+    fn cpl(&mut self) {
         let value = self.registers.read_r8(A);
         self.registers.write_r8(A, !value);
         let mut flags = self.registers.read_r8(F);
@@ -989,11 +1096,8 @@ impl Cpu {
     fn and_a_r(&mut self, mem: &mut Memory, op: Operand) {
         let value = if matches!(op, Operand::MemHL) {
             let addr = self.registers.read_r16(HL);
-            let v = self.read_byte_tick(mem, addr); // Ticks 1 M-cycle for memory access
-            self.tick_internal(mem, 1); // 1 M-cycle for ALU operation on memory (total 2)
-            v
+            self.read_byte_tick(mem, addr) // Ticks 1 M-cycle for memory access (total 2)
         } else {
-            self.tick_internal(mem, 1); // 1 M-cycle for register operation
             self.read_operand(mem, op)
         };
 
@@ -1017,8 +1121,7 @@ impl Cpu {
 
     fn and_a_n(&mut self, mem: &mut Memory) {
         let pc = self.registers.read_r16(PC);
-        let value = self.read_byte_tick(mem, pc + 1); // Ticks 1 M-cycle
-        self.tick_internal(mem, 1); // 1 M-cycle for ALU operation
+        let value = self.read_byte_tick(mem, pc + 1); // Ticks 1 M-cycle (total 2)
         let a = self.registers.read_r8(A);
         let result = a & value;
         self.registers.write_r8(A, result);
@@ -1040,11 +1143,8 @@ impl Cpu {
     fn xor_a_r(&mut self, mem: &mut Memory, op: Operand) {
         let value = if matches!(op, Operand::MemHL) {
             let addr = self.registers.read_r16(HL);
-            let v = self.read_byte_tick(mem, addr); // Ticks 1 M-cycle for memory access
-            self.tick_internal(mem, 1); // 1 M-cycle for ALU operation on memory (total 2)
-            v
+            self.read_byte_tick(mem, addr) // Ticks 1 M-cycle for memory access (total 2)
         } else {
-            self.tick_internal(mem, 1); // 1 M-cycle for register operation
             self.read_operand(mem, op)
         };
 
@@ -1068,8 +1168,7 @@ impl Cpu {
 
     fn xor_a_n(&mut self, mem: &mut Memory) {
         let pc = self.registers.read_r16(PC);
-        let value = self.read_byte_tick(mem, pc + 1); // Ticks 1 M-cycle
-        self.tick_internal(mem, 1); // 1 M-cycle for ALU operation
+        let value = self.read_byte_tick(mem, pc + 1); // Ticks 1 M-cycle (total 2)
         let a = self.registers.read_r8(A);
         let result = a ^ value;
         self.registers.write_r8(A, result);
@@ -1091,11 +1190,8 @@ impl Cpu {
     fn or_a_r(&mut self, mem: &mut Memory, op: Operand) {
         let value = if matches!(op, Operand::MemHL) {
             let addr = self.registers.read_r16(HL);
-            let v = self.read_byte_tick(mem, addr); // Ticks 1 M-cycle for memory access
-            self.tick_internal(mem, 1); // 1 M-cycle for ALU operation on memory (total 2)
-            v
+            self.read_byte_tick(mem, addr) // Ticks 1 M-cycle for memory access (total 2)
         } else {
-            self.tick_internal(mem, 1); // 1 M-cycle for register operation
             self.read_operand(mem, op)
         };
 
@@ -1119,8 +1215,7 @@ impl Cpu {
 
     fn or_a_n(&mut self, mem: &mut Memory) {
         let pc = self.registers.read_r16(PC);
-        let value = self.read_byte_tick(mem, pc + 1); // Ticks 1 M-cycle
-        self.tick_internal(mem, 1); // 1 M-cycle for ALU operation
+        let value = self.read_byte_tick(mem, pc + 1); // Ticks 1 M-cycle (total 2)
         let a = self.registers.read_r8(A);
         let result = a | value;
         self.registers.write_r8(A, result);
@@ -1141,11 +1236,8 @@ impl Cpu {
     fn cp_a_r(&mut self, mem: &mut Memory, op: Operand) {
         let value = if matches!(op, Operand::MemHL) {
             let addr = self.registers.read_r16(HL);
-            let v = self.read_byte_tick(mem, addr); // Ticks 1 M-cycle for memory access
-            self.tick_internal(mem, 1); // 1 M-cycle for ALU operation on memory (total 2)
-            v
+            self.read_byte_tick(mem, addr) // Ticks 1 M-cycle for memory access (total 2)
         } else {
-            self.tick_internal(mem, 1); // 1 M-cycle for register operation
             self.read_operand(mem, op)
         };
 
@@ -1177,8 +1269,7 @@ impl Cpu {
 
     pub fn cp_a_n(&mut self, mem: &mut Memory) {
         let pc = self.registers.read_r16(PC);
-        let value = self.read_byte_tick(mem, pc + 1); // Ticks 1 M-cycle
-        self.tick_internal(mem, 1); // 1 M-cycle for ALU operation
+        let value = self.read_byte_tick(mem, pc + 1); // Ticks 1 M-cycle (total 2)
         let a = self.registers.read_r8(A);
 
         // Clear all flags first, then set as needed
@@ -1230,18 +1321,12 @@ impl Cpu {
 
 
     //misc
-    fn nop(&mut self, mem: &mut Memory) {
-        self.tick_internal(mem, 1); // 1 M-cycle for NOP
-    }
-
-    fn stop(&mut self, mem: &mut Memory) {
-        self.tick_internal(mem, 1); // 1 M-cycle for STOP
+    fn stop(&mut self) {
         //stop Cpu until button pressed
     }
 
     fn halt(&mut self, mem: &mut Memory) {
-        // HALT: Stop CPU until interrupt occurs
-        self.tick_internal(mem, 1); // HALT consumes 1 M-cycle (4 T-cycles)
+        // HALT: Stop CPU until interrupt occurs. Only the opcode fetch is ticked
         // HALT bug: If IME=0 and an interrupt is pending, don't halt
         // but set halt_bug flag to prevent PC increment after next instruction
         //not sure if this is completetly correct I should check the pandocs again
@@ -1259,8 +1344,7 @@ impl Cpu {
         // Note: HALT timing is handled specially - it just stops the CPU
     }
 
-    fn scf(&mut self, mem: &mut Memory) {
-        self.tick_internal(mem, 1); // 1 M-cycle for flag operation
+    fn scf(&mut self) {
         let mut flags = self.registers.read_r8(F);
         flags |= CARRY_FLAG;
         flags &= !HALF_CARRY_FLAG;
@@ -1268,8 +1352,7 @@ impl Cpu {
         self.registers.write_r8(F, flags);
     }
 
-    fn ccf(&mut self, mem: &mut Memory) {
-        self.tick_internal(mem, 1); // 1 M-cycle for flag operation
+    fn ccf(&mut self) {
         let mut flags = self.registers.read_r8(F);
         flags ^= CARRY_FLAG;
         flags &= !HALF_CARRY_FLAG;
@@ -1391,10 +1474,8 @@ impl Cpu {
     fn sla_r(&mut self, mem: &mut Memory, op: Operand) {
         let value = if matches!(op, Operand::MemHL) {
             let addr = self.registers.read_r16(HL);
-            self.tick_internal(mem, 1);
             self.read_byte_tick(mem, addr) as i8 // Ticks 1 M-cycle
         } else {
-            self.tick_internal(mem, 1); // 1 M-cycle for register operation
             self.read_operand(mem, op) as i8
         };
 
@@ -1418,10 +1499,8 @@ impl Cpu {
 
         if matches!(op, Operand::MemHL) {
             let addr = self.registers.read_r16(HL);
-            self.tick_internal(mem, 1);
             self.write_byte_tick(mem, addr, result as u8); // Ticks 1 M-cycle
         } else {
-            self.tick_internal(mem, 1); // 1 M-cycle for result write
             self.write_operand(mem, op, result as u8);
         }
     }
@@ -1429,10 +1508,8 @@ impl Cpu {
     fn sra_r(&mut self, mem: &mut Memory, op: Operand) {
         let value = if matches!(op, Operand::MemHL) {
             let addr = self.registers.read_r16(HL);
-            self.tick_internal(mem, 1);
             self.read_byte_tick(mem, addr) as i8 // Ticks 1 M-cycle
         } else {
-            self.tick_internal(mem, 1); // 1 M-cycle for register operation
             self.read_operand(mem, op) as i8
         };
 
@@ -1456,10 +1533,8 @@ impl Cpu {
 
         if matches!(op, Operand::MemHL) {
             let addr = self.registers.read_r16(HL);
-            self.tick_internal(mem, 1);
             self.write_byte_tick(mem, addr, result as u8); // Ticks 1 M-cycle
         } else {
-            self.tick_internal(mem, 1); // 1 M-cycle for result write
             self.write_operand(mem, op, result as u8);
         }
     }
@@ -1484,8 +1559,6 @@ impl Cpu {
     fn bit_n_r(&mut self, mem: &mut Memory, op: Operand, n: u8) {
         let value = self.read_op_cb(mem, op);
 
-        self.tick_internal(mem, 1); // 1 additional M-cycle for BIT operation
-
         let mut flags = self.registers.read_r8(F);
 
         flags |= HALF_CARRY_FLAG;
@@ -1544,21 +1617,16 @@ impl Cpu {
     fn write_op_cb(&mut self, mem: &mut Memory, op: Operand, result: u8) {
         if matches!(op, Operand::MemHL) {
             let addr = self.registers.read_r16(HL);
-            self.tick_internal(mem, 1);
             self.write_byte_tick(mem, addr, result); // Ticks 1 M-cycle
         } else {
-            self.tick_internal(mem, 1); // 1 M-cycle for result write
             self.write_operand(mem, op, result);
         }
     }
     fn read_op_cb(&mut self, mem: &mut Memory, op: Operand) -> u8 {
         if matches!(op, Operand::MemHL) {
             let addr = self.registers.read_r16(HL);
-            self.tick_internal(mem, 1); //TODO this is probably wrong
             self.read_byte_tick(mem, addr) // Ticks 1 M-cycle
-
         } else {
-            self.tick_internal(mem, 1); // TODO this is really the fetch cycle, why did I thought it was for something else I'm too tired same for write_op_cb and for much of the other operations
             self.read_operand(mem, op)
         }
 
@@ -1566,7 +1634,7 @@ impl Cpu {
 
     fn call_cb(&mut self, mem: &mut Memory) {
         let pc = self.registers.read_r16(PC);
-        let cb_opcode = mem.read_8(pc + 1); // Don't tick - reading instruction byte
+        let cb_opcode = self.read_byte_tick(mem, pc + 1); // Ticks 1 M-cycle (second opcode byte)
         let op = Operand::from_index(cb_opcode & 0x07);
 
         match cb_opcode {
@@ -1593,14 +1661,12 @@ impl Cpu {
         }
     }
 
-    fn di(&mut self, mem: &mut Memory) {
-        self.tick_internal(mem, 1); // 1 M-cycle for DI
+    fn di(&mut self) {
         self.registers.write_ime(0);
         self.ei_pending = false; // Cancel any pending EI
     }
 
-    fn ei(&mut self, mem: &mut Memory) {
-        self.tick_internal(mem, 1); // 1 M-cycle for EI
+    fn ei(&mut self) {
         // EI enables interrupts after the NEXT instruction executes
         self.ei_pending = true;
     }
@@ -1609,7 +1675,7 @@ impl Cpu {
     fn jr_e(&mut self, mem: &mut Memory) {
         let pc = self.registers.read_r16(PC);
         let offset = self.read_byte_tick(mem, pc + 1) as i8; // Ticks 1 M-cycle
-        self.tick_internal(mem, 2); // 2 internal M-cycles for jump
+        self.tick_internal(mem, 1); // 1 internal M-cycle for jump (total 3)
         // Jump relative to PC+2 (after the JR instruction which is 2 bytes)
         let target = (pc as i32 + 2 + offset as i32) as u16;
         self.registers.write_r16(PC, target);
@@ -1634,12 +1700,11 @@ impl Cpu {
 
         if (self.registers.read_r8(F) & flag) >> shift == cond {
             // Condition met - take the jump
-            self.tick_internal(mem, 2); // 2 internal M-cycles for jump
+            self.tick_internal(mem, 1); // 1 internal M-cycle for jump (total 3)
             let target = (pc as i32 + 2 + offset as i32) as u16;
             self.registers.write_r16(PC, target);
         } else {
             // Condition not met - skip to next instruction (PC+2)
-            self.tick_internal(mem, 1); // 1 internal M-cycle
             self.registers.write_r16(PC, pc + 2);
         }
     }
@@ -1647,7 +1712,7 @@ impl Cpu {
     fn jp_nn(&mut self, mem: &mut Memory) {
         let pc = self.registers.read_r16(PC);
         let target_address = self.read_word_tick(mem, pc + 1); // Ticks 2 M-cycles
-        self.tick_internal(mem, 2); // 2 internal M-cycles for jump
+        self.tick_internal(mem, 1); // 1 internal M-cycle for jump (total 4)
         self.registers.write_r16(PC, target_address);
     }
 
@@ -1670,16 +1735,14 @@ impl Cpu {
         let target_address = self.read_word_tick(mem, pc + 1); // Ticks 2 M-cycles
 
         if (self.registers.read_r8(F) & flag) >> shift == cond {
-            self.tick_internal(mem, 2); // 2 internal M-cycles for jump
+            self.tick_internal(mem, 1); // 1 internal M-cycle for jump (total 4)
             self.registers.write_r16(PC, target_address);
         } else {
-            self.tick_internal(mem, 1); // 1 internal M-cycle
             self.registers.write_r16(PC, pc + 3);
         }
     }
 
-    fn jp_hl(&mut self, mem: &mut Memory) {
-        self.tick_internal(mem, 1); // 1 M-cycle for jump
+    fn jp_hl(&mut self) {
         self.registers.write_r16(PC, self.registers.read_r16(HL));
     }
 
@@ -1688,15 +1751,12 @@ impl Cpu {
         let target_address = self.read_word_tick(mem, pc + 1); // Ticks 2 M-cycles for reading address
         let return_address = pc.wrapping_add(3); // Return to instruction after CALL
 
-        self.tick_internal(mem, 1); // Internal cycle for preparing stack operation
+        self.tick_internal(mem, 1); // Internal cycle to decrement SP
 
         // Push return address onto stack
-        let sp = self.registers.read_r16(SP);
-        self.registers.write_r16(SP, sp.wrapping_sub(2));
-        self.write_word_tick(mem, sp.wrapping_sub(2), return_address); // Ticks 2 M-cycles for writing to stack
+        self.push_word_tick(mem, return_address); // Ticks 2 M-cycles for writing to stack (total 6)
 
         // Jump to target address
-        self.tick_internal(mem, 1); // Internal cycle for jump
         self.registers.write_r16(PC, target_address);
     }
 
@@ -1715,46 +1775,40 @@ impl Cpu {
 
         let cond = if z { 1 } else { 0 };
 
+        // The address is read whether or not the call is taken
+        let pc = self.registers.read_r16(PC);
+        let target_address = self.read_word_tick(mem, pc.wrapping_add(1)); // Ticks 2 M-cycles
+
         if (self.registers.read_r8(F) & flag) >> shift == cond {
-            let target_address = mem.read_16(self.registers.read_r16(PC) + 1);
-            let return_address = self.registers.read_r16(PC) + 3; // Return to instruction after CALL
+            let return_address = pc.wrapping_add(3); // Return to instruction after CALL
 
             // Push return address onto stack
-            self.registers
-                .write_r16(SP, self.registers.read_r16(SP) - 2);
-            mem.write_16(self.registers.read_r16(SP), return_address);
+            self.tick_internal(mem, 1); // Internal cycle to decrement SP
+            self.push_word_tick(mem, return_address); // Ticks 2 M-cycles for writing to stack (total 6)
 
             // Jump to target address
             self.registers.write_r16(PC, target_address);
-            self.tick_internal(mem, 6);
         } else {
             // Condition not met - skip to next instruction (PC+3)
-            let pc = self.registers.read_r16(PC);
             self.registers.write_r16(PC, pc + 3);
-            self.tick_internal(mem, 3);
         }
     }
 
     fn rst(&mut self, mem: &mut Memory, value: u16) {
         let return_address = self.registers.read_r16(PC).wrapping_add(1); // RST is 1 byte
 
-        self.tick_internal(mem, 1); // Internal cycle for preparing stack operation
+        self.tick_internal(mem, 1); // Internal cycle to decrement SP
 
         // Push return address onto stack
-        let sp = self.registers.read_r16(SP);
-        self.registers.write_r16(SP, sp.wrapping_sub(2));
-        self.write_word_tick(mem, sp.wrapping_sub(2), return_address); // Ticks 2 M-cycles
+        self.push_word_tick(mem, return_address); // Ticks 2 M-cycles (total 4)
 
         // Jump to RST vector
-        self.tick_internal(mem, 1); // Internal cycle for jump
         self.registers.write_r16(PC, value);
     }
 
     fn ret(&mut self, mem: &mut Memory) {
-        let sp = self.registers.read_r16(SP);
-        let value = self.read_word_tick(mem, sp); // Ticks 2 M-cycles for reading from stack
-        self.registers.write_r16(SP, sp.wrapping_add(2));
-        self.tick_internal(mem, 2); // 2 internal cycles for jump (total 4)
+        let value = self.pop_word_tick(mem); // Ticks 2 M-cycles for reading from stack
+        self.tick_internal(mem, 1); // 1 internal cycle for jump (total 4)
         self.registers.write_r16(PC, value);
     }
 
@@ -1777,125 +1831,114 @@ impl Cpu {
 
         if (self.registers.read_r8(F) & flag) >> shift == cond {
             // Condition met - perform return
-            let sp = self.registers.read_r16(SP);
-            let value = self.read_word_tick(mem, sp); // Ticks 2 M-cycles
-            self.registers.write_r16(SP, sp.wrapping_add(2));
-            self.tick_internal(mem, 1); // Internal cycle for jump
+            let value = self.pop_word_tick(mem); // Ticks 2 M-cycles
+            self.tick_internal(mem, 1); // Internal cycle for jump (total 5)
             self.registers.write_r16(PC, value);
-            self.tick_internal(mem, 1);
         } else {
             // Condition not met - skip to next instruction (PC+1)
             let pc = self.registers.read_r16(PC);
             self.registers.write_r16(PC, pc + 1);
-            self.tick_internal(mem, 1);
         }
     }
 
     fn reti(&mut self, mem: &mut Memory) {
-        let sp = self.registers.read_r16(SP);
-        let value = self.read_word_tick(mem, sp); // Ticks 2 M-cycles
-        self.registers.write_r16(SP, sp.wrapping_add(2));
-        self.tick_internal(mem, 1); // Internal cycle for jump
+        let value = self.pop_word_tick(mem); // Ticks 2 M-cycles
+        self.tick_internal(mem, 1); // Internal cycle for jump (total 4)
         self.registers.write_r16(PC, value);
         self.registers.write_ime(1); // Re-enable interrupts
-        self.tick_internal(mem, 1);
     }
 
     //end of Cpu
     pub fn execute(&mut self, opcode: u8, mem: &mut Memory) {
         match opcode {
-            0x00 => self.nop(mem),
+            0x00 => {} // NOP: only the opcode fetch
             0x01 => self.ld_r16_nn(mem, BC),
             0x02 => self.ld_operand(mem, Operand::MemBC, Operand::Reg8(A)),
             0x03 => self.inc_r16(mem, BC),
-            0x04 => self.inc_r8(mem, B),
-            0x05 => self.dec_r8(mem, B),
+            0x04 => self.inc_r8(B),
+            0x05 => self.dec_r8(B),
             0x06 => self.ld_r8_n(mem, B),
-            0x07 => self.rlca(mem),
+            0x07 => self.rlca(),
             0x08 => self.ld_nn_sp(mem),
             0x09 => self.add_hl(mem, BC),
             0x0A => self.ld_operand(mem, Operand::Reg8(A), Operand::MemBC),
             0x0B => self.dec_r16(mem, BC),
-            0x0C => self.inc_r8(mem, C),
-            0x0D => self.dec_r8(mem, C),
+            0x0C => self.inc_r8(C),
+            0x0D => self.dec_r8(C),
             0x0E => self.ld_r8_n(mem, C),
-            0x0F => self.rrca(mem),
-            0x10 => self.stop(mem),
+            0x0F => self.rrca(),
+            0x10 => self.stop(),
             0x11 => self.ld_r16_nn(mem, DE),
             0x12 => self.ld_operand(mem, Operand::MemDE, Operand::Reg8(A)),
             0x13 => self.inc_r16(mem, DE),
-            0x14 => self.inc_r8(mem, D),
-            0x15 => self.dec_r8(mem, D),
+            0x14 => self.inc_r8(D),
+            0x15 => self.dec_r8(D),
             0x16 => self.ld_r8_n(mem, D),
-            0x17 => self.rla(mem),
+            0x17 => self.rla(),
             0x18 => self.jr_e(mem),
             0x19 => self.add_hl(mem, DE),
             0x1A => self.ld_operand(mem, Operand::Reg8(A), Operand::MemDE),
             0x1B => self.dec_r16(mem, DE),
-            0x1C => self.inc_r8(mem, E),
-            0x1D => self.dec_r8(mem, E),
+            0x1C => self.inc_r8(E),
+            0x1D => self.dec_r8(E),
             0x1E => self.ld_r8_n(mem, E),
-            0x1F => self.rra(mem),
+            0x1F => self.rra(),
             0x20 => self.jr_f_e(mem, 'z', false),
             0x21 => self.ld_r16_nn(mem, HL),
             0x22 => {
                 // LD (HL+),A - 8 T-cycles (2 M-cycles total)
                 let addr = self.registers.read_r16(HL);
                 let value = self.registers.read_r8(A);
-                self.write_byte_tick(mem, addr, value); // 1 M-cycle for memory write
-                self.tick_internal(mem, 1); // 1 M-cycle for increment (total 2)
+                self.write_byte_tick(mem, addr, value); // 1 M-cycle for memory write (total 2)
                 self.registers.write_r16(HL, addr.wrapping_add(1));
             }
             0x23 => self.inc_r16(mem, HL),
-            0x24 => self.inc_r8(mem, H),
-            0x25 => self.dec_r8(mem, H),
+            0x24 => self.inc_r8(H),
+            0x25 => self.dec_r8(H),
             0x26 => self.ld_r8_n(mem, H),
-            0x27 => self.daa(mem),
+            0x27 => self.daa(),
             0x28 => self.jr_f_e(mem, 'z', true),
             0x29 => self.add_hl(mem, HL),
             0x2A => {
                 // LD A,(HL+) - 8 T-cycles (2 M-cycles total)
                 let addr = self.registers.read_r16(HL);
-                let value = self.read_byte_tick(mem, addr); // 1 M-cycle for memory read
-                self.tick_internal(mem, 1); // 1 M-cycle for increment (total 2)
+                let value = self.read_byte_tick(mem, addr); // 1 M-cycle for memory read (total 2)
                 self.registers.write_r8(A, value);
                 self.registers.write_r16(HL, addr.wrapping_add(1));
             }
             0x2B => self.dec_r16(mem, HL),
-            0x2C => self.inc_r8(mem, L),
-            0x2D => self.dec_r8(mem, L),
+            0x2C => self.inc_r8(L),
+            0x2D => self.dec_r8(L),
             0x2E => self.ld_r8_n(mem, L),
-            0x2F => self.cpl(mem),
+            0x2F => self.cpl(),
             0x30 => self.jr_f_e(mem, 'c', false),
             0x31 => self.ld_r16_nn(mem, SP),
             0x32 => {
                 // LD (HL-),A - 8 T-cycles (2 M-cycles total)
                 let addr = self.registers.read_r16(HL);
                 let value = self.registers.read_r8(A);
-                self.write_byte_tick(mem, addr, value); // 1 M-cycle for memory write
-                self.tick_internal(mem, 1); // 1 M-cycle for decrement (total 2)
+                self.write_byte_tick(mem, addr, value); // 1 M-cycle for memory write (total 2)
                 self.registers.write_r16(HL, addr.wrapping_sub(1));
             }
             0x33 => self.inc_r16(mem, SP),
             0x34 => self.inc_mem(mem, HL),
             0x35 => self.dec_mem(mem, HL),
             0x36 => self.ld_m_n(mem),
-            0x37 => self.scf(mem),
+            0x37 => self.scf(),
             0x38 => self.jr_f_e(mem, 'c', true),
             0x39 => self.add_hl(mem, SP),
             0x3A => {
                 // LD A,(HL-) - 8 T-cycles (2 M-cycles total)
                 let addr = self.registers.read_r16(HL);
-                let value = self.read_byte_tick(mem, addr); // 1 M-cycle for memory read
-                self.tick_internal(mem, 1); // 1 M-cycle for decrement (total 2)
+                let value = self.read_byte_tick(mem, addr); // 1 M-cycle for memory read (total 2)
                 self.registers.write_r8(A, value);
                 self.registers.write_r16(HL, addr.wrapping_sub(1));
             }
             0x3B => self.dec_r16(mem, SP),
-            0x3C => self.inc_r8(mem, A),
-            0x3D => self.dec_r8(mem, A),
+            0x3C => self.inc_r8(A),
+            0x3D => self.dec_r8(A),
             0x3E => self.ld_r8_n(mem, A),
-            0x3F => self.ccf(mem),
+            0x3F => self.ccf(),
             0x76 => self.halt(mem), // HALT instruction (not LD (HL),(HL))
             0x40..=0x75 | 0x77..=0x7F => {
                 // LD r1, r2 instructions (excluding 0x76 which is HALT)
@@ -1947,14 +1990,14 @@ impl Cpu {
             0xE6 => self.and_a_n(mem),
             0xE7 => self.rst(mem, 0x20),
             0xE8 => self.add_sp_e(mem),
-            0xE9 => self.jp_hl(mem),
+            0xE9 => self.jp_hl(),
             0xEA => self.ld_nn_a(mem),
             0xEE => self.xor_a_n(mem),
             0xEF => self.rst(mem, 0x28),
             0xF0 => self.ldh_a_n(mem),
             0xF1 => self.pop(mem, AF),
             0xF2 => self.ldh_a_c(mem),
-            0xF3 => self.di(mem),
+            0xF3 => self.di(),
             0xF5 => self.push(mem, AF),
             0xF6 => self.or_a_n(mem),
             0xF7 => self.rst(mem, 0x30),
@@ -1964,20 +2007,21 @@ impl Cpu {
                 // LD A,(nn) - 16 T-cycles (4 M-cycles)
                 let pc = self.registers.read_r16(PC);
                 let addr = self.read_word_tick(mem, pc + 1); // Ticks 2 M-cycles (read nn)
-                let value = self.read_byte_tick(mem, addr); // Ticks 1 M-cycle (read from (nn))
-                self.tick_internal(mem, 1); // 1 additional internal M-cycle (total 4)
+                let value = self.read_byte_tick(mem, addr); // Ticks 1 M-cycle (read from (nn)) - total 4
                 self.registers.write_r8(A, value);
             }
-            0xFB => self.ei(mem),
+            0xFB => self.ei(),
             0xFE => self.cp_a_n(mem),
             0xFF => self.rst(mem, 0x38),
-            _ => {
-                println!(
-                    "CPU: Unknown/unimplemented opcode 0x{:02X} at PC 0x{:04X}!",
+            0xD3 | 0xDB | 0xDD | 0xE3 | 0xE4 | 0xEB | 0xEC | 0xED | 0xF4 | 0xFC | 0xFD => {
+                // INVALID_OPCODES: hardware locks up here. The emulator deliberately skips the
+                // byte instead, so a stray jump shows up in the log rather than as a frozen game.
+                gb_log!(
+                    LogTarget::Cpu,
+                    "Invalid opcode 0x{:02X} at PC 0x{:04X}",
                     opcode,
                     self.registers.read_r16(PC)
                 );
-                // Just NOP and continue instead of panicking
                 let pc = self.registers.read_r16(PC);
                 self.registers.write_r16(PC, pc + 1);
             }
@@ -1998,27 +2042,21 @@ impl Cpu {
             0xC7, 0xCF, 0xD7, 0xDF, 0xE7, 0xEF, 0xF7, 0xFF, // RST
         ];
 
-        // Only increment PC if this is not a PC-modifying instruction
-        if !pc_modifying_opcodes.contains(&opcode) {
-            let pc = self.registers.read_r16(PC);
-            let length = OPCODE_LENGTHS[opcode as usize] as u16;
-
-            // Handle HALT bug: when halt_bug is set, the next instruction after HALT
-            // doesn't increment PC, causing it to execute twice
-            if self.halt_bug {
-                self.halt_bug = false;
-                // PC stays at current position - next fetch will read same byte again
-            } else {
-                self.registers.write_r16(PC, pc.wrapping_add(length));
-            }
-        }
-
-        // Track cycles
+        // Track cycles. Read the CB opcode before PC moves past the instruction
         let mut cycles = OPCODE_DURATION[opcode as usize];
 
         if opcode == 0xCB {
-            let cb_opcode = mem.read_8(self.registers.read_r16(PC) + 1);
+            let cb_opcode = mem.read_8(self.registers.read_r16(PC).wrapping_add(1));
             cycles = OPCODE_DURATION_CB[cb_opcode as usize];
+        } else if INVALID_OPCODES.contains(&opcode) {
+            cycles = 4; // Only the opcode fetch
+        }
+
+        // Only increment PC if this is not a PC-modifying instruction
+        if !pc_modifying_opcodes.contains(&opcode) {
+            let pc = self.registers.read_r16(PC);
+            let length = OPCODE_LENGTHS[opcode as usize] as u16;
+            self.registers.write_r16(PC, pc.wrapping_add(length));
         }
 
         // NOTE: All instructions now handle their own timing via ticking helpers
@@ -2037,14 +2075,12 @@ impl Cpu {
                 0xC0, 0xC8, 0xD0, 0xD8, // RET cc
                 0xC2, 0xCA, 0xD2, 0xDA, // JP cc,nn
                 0xC4, 0xCC, 0xD4, 0xDC, // CALL cc,nn
-                0xCB
             ];
 
             let is_variable_cycle = variable_cycle_opcodes.contains(&opcode);
-            if !is_variable_cycle && actual_m_cycles != expected_m_cycles && opcode != 0xCB{
+            if !is_variable_cycle && actual_m_cycles != expected_m_cycles {
                 let cb_info = if opcode == 0xCB {
-                    let cb_opcode = mem.read_8(self.registers.read_r16(PC) + 1);
-                    format!(" (CB prefix, CB opcode: 0x{:02X})", cb_opcode)
+                    format!(" (CB prefix, {} T-cycles in the CB table)", cycles)
                 } else {
                     String::new()
                 };
@@ -2054,7 +2090,7 @@ impl Cpu {
                      This means the instruction implementation doesn't match OPCODE_DURATION table!",
                     opcode, cb_info, expected_m_cycles, cycles, actual_m_cycles
                 );
-            } else if is_variable_cycle && actual_m_cycles > expected_m_cycles && opcode != 0xCB {
+            } else if is_variable_cycle && actual_m_cycles > expected_m_cycles {
                 // For variable-cycle instructions, actual can be <= expected
                 // But it should never be MORE than expected
                 panic!(
@@ -2069,8 +2105,9 @@ impl Cpu {
         cycles as u32
     }
 
-    // Handle interrupts - should be called after each instruction
-    pub fn handle_interrupts(&mut self, mem: &mut Memory) {
+    // Latch the components' interrupt requests into IF, leave HALT if one is enabled and
+    // dispatch it if IME is set. Returns the T-cycles of the dispatch, 0 if none.
+    fn handle_interrupts(&mut self, mem: &mut Memory) -> u32 {
         // --- 1. SYNC HARDWARE FLAGS TO IF REGISTER (0xFF0F) ---
 
         let mut request_flags = 0;
@@ -2113,41 +2150,33 @@ impl Cpu {
 
         // --- 2. SERVICE INTERRUPTS ---
 
-        if self.registers.read_ime() == 0 && !self.halted {
-            return;
-        }
-
         // Read IE (Enabled) and IF (Request)
         let ie = mem.read_8(0xFFFF);
         let if_reg = mem.read_8(0xFF0F);
-        let pending = ie & if_reg;
-
-        // HALT BUG: If CPU is Halted, IME=0, and interrupt is pending,
-        // the CPU wakes up but often encounters the "HALT bug" (PC fails to increment).
-        if self.halted && pending != 0 {
-            self.halted = false;
+        if ie & if_reg & 0x1F == 0 {
+            return 0;
         }
 
-        // If IME is disabled, we don't actually jump to the handler
+        // An enabled request ends HALT whether or not IME is set; with IME off the CPU
+        // just carries on after the HALT
+        let woke = self.halted;
+        self.halted = false;
         if self.registers.read_ime() == 0 {
-            return;
-        }
-
-        if pending == 0 {
-            return;
+            return 0;
         }
 
-        // Service highest priority interrupt
-        // Priority: VBlank(0) > Stat(1) > Timer(2) > Serial(3) > Joypad(4)
-        for i in 0..5 {
-            if pending & (1 << i) != 0 {
-                self.service_interrupt(mem, i);
-                return; // Only service one interrupt per step
-            }
+        let mut cycles = 0;
+        if woke {
+            // Leaving HALT takes one more M-cycle before the dispatch starts
+            mem.tick_components(1);
+            self.cycles += 4;
+            cycles += 4;
         }
+        cycles + self.service_interrupt(mem) // Only service one interrupt per step
     }
-    // Service an interrupt
-    fn service_interrupt(&mut self, mem: &mut Memory, interrupt: u8) {
+
+    // Service an interrupt; returns the 20 T-cycles it takes
+    fn service_interrupt(&mut self, mem: &mut Memory) -> u32 {
         // Cancel halted state if CPU was halted
         self.halted = false;
 
@@ -2155,20 +2184,597 @@ impl Cpu {
         self.registers.write_ime(0);
         self.ei_pending = false; // Cancel any pending EI
 
-        let if_reg = mem.read_8(0xFF0F);
-        mem.write_8(0xFF0F, if_reg & !(1 << interrupt));
+        // Dispatch takes 5 M-cycles on the bus: two internal ones, the two pushes and
+        // one to load the vector into PC
+        mem.tick_components(2);
 
-        // Push PC onto stack
-        let pc = self.registers.read_r16(PC);
-        let sp = self.registers.read_r16(SP);
-        self.registers.write_r16(SP, sp.wrapping_sub(2));
-        mem.write_16(self.registers.read_r16(SP), pc);
+        // Push PC onto stack, high byte first. After EI; HALT with a request pending, the
+        // HALT bug hits the dispatch instead: the handler returns to the HALT
+        let mut pc = self.registers.read_r16(PC);
+        if self.halt_bug {
+            self.halt_bug = false;
+            pc = pc.wrapping_sub(1);
+        }
+        let sp = self.registers.read_r16(SP).wrapping_sub(1);
+        mem.write_8(sp, (pc >> 8) as u8);
+        mem.tick_components(1);
+
+        // The interrupt is picked after the high byte push: if that write landed on IE,
+        // a different interrupt may be taken, or none at all (dispatch jumps to 0x0000)
+        let pending = mem.read_8(0xFFFF) & mem.read_8(0xFF0F) & 0x1F;
+
+        let sp = sp.wrapping_sub(1);
+        mem.write_8(sp, (pc & 0xFF) as u8);
+        mem.tick_components(1);
+        self.registers.write_r16(SP, sp);
+
+        // Service highest priority interrupt
+        // Priority: VBlank(0) > Stat(1) > Timer(2) > Serial(3) > Joypad(4)
+        let vector = if pending == 0 {
+            0x0000
+        } else {
+            let interrupt = pending.trailing_zeros() as u16;
+            let if_reg = mem.read_8(0xFF0F);
+            mem.write_8(0xFF0F, if_reg & !(1 << interrupt));
+            0x0040 + interrupt * 0x08
+        };
 
         // Jump to interrupt vector
-        let vector = 0x0040 + (interrupt as u16 * 0x08);
         self.registers.write_r16(PC, vector);
+        mem.tick_components(1);
+        mem.flush_deferred_ticks();
+        if pending != 0 {
+            mem.interrupt_dispatched(pending.trailing_zeros() as usize);
+        }
 
         // Add interrupt handling cycles (20 cycles)
         self.cycles += 20;
+        20
+    }
+}
+
+impl Cpu {
+    pub(crate) fn save_state(&self, w: &mut StateWriter) {
+        for register in [AF, BC, DE, HL, SP, PC] {
+            w.u16(self.registers.read_r16(register));
+        }
+        w.u8(self.registers.ime);
+        w.u64(self.cycles);
+        w.bool(self.ei_pending);
+        w.bool(self.halted);
+        w.bool(self.halt_bug);
+    }
+
+    pub(crate) fn load_state(&mut self, r: &mut StateReader) -> Result<(), String> {
+        for register in [AF, BC, DE, HL, SP, PC] {
+            self.registers.write_r16(register, r.u16()?);
+        }
+        self.registers.ime = r.u8()?;
+        self.cycles = r.u64()?;
+        self.ei_pending = r.bool()?;
+        self.halted = r.bool()?;
+        self.halt_bug = r.bool()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Canonical SM83 encoding, decoded from the opcode's x/y/z/p/q fields
+    // (x = bits 7-6, y = bits 5-3, z = bits 2-0, p = y >> 1, q = y & 1).
+    // Returns (length in bytes, duration in T-cycles), or None for invalid opcodes.
+    fn canonical(opcode: u8) -> Option<(u8, u8)> {
+        let x = opcode >> 6;
+        let y = (opcode >> 3) & 0x07;
+        let z = opcode & 0x07;
+        let p = y >> 1;
+        let q = y & 1;
+        let hl = |y_or_z: u8| y_or_z == 6;
+
+        let entry = match (x, z) {
+            (0, 0) => match y {
+                0 => (1, 4),  // NOP
+                1 => (3, 20), // LD (nn),SP
+                2 => (2, 4),  // STOP
+                _ => (2, 12), // JR e / JR cc,e
+            },
+            (0, 1) if q == 0 => (3, 12), // LD rp,nn
+            (0, 1) => (1, 8),            // ADD HL,rp
+            (0, 2) | (0, 3) => (1, 8),   // LD (rp),A / LD A,(rp) / INC rp / DEC rp
+            (0, 4) | (0, 5) => (1, if hl(y) { 12 } else { 4 }), // INC/DEC r
+            (0, 6) => (2, if hl(y) { 12 } else { 8 }), // LD r,n
+            (0, 7) => (1, 4),                          // RLCA/RRCA/RLA/RRA/DAA/CPL/SCF/CCF
+            (1, _) if hl(y) && hl(z) => (1, 4),        // HALT
+            (1, _) => (1, if hl(y) || hl(z) { 8 } else { 4 }), // LD r,r
+            (2, _) => (1, if hl(z) { 8 } else { 4 }),  // ALU A,r
+            (3, 0) => match y {
+                0..=3 => (1, 20), // RET cc
+                5 => (2, 16),     // ADD SP,e
+                _ => (2, 12),     // LDH (n),A / LDH A,(n) / LD HL,SP+e
+            },
+            (3, 1) if q == 0 => (1, 12), // POP rp2
+            (3, 1) => match p {
+                0 | 1 => (1, 16), // RET / RETI
+                2 => (1, 4),      // JP HL
+                _ => (1, 8),      // LD SP,HL
+            },
+            (3, 2) => match y {
+                0..=3 => (3, 16), // JP cc,nn
+                4 | 6 => (1, 8),  // LDH (C),A / LDH A,(C)
+                _ => (3, 16),     // LD (nn),A / LD A,(nn)
+            },
+            (3, 3) => match y {
+                0 => (3, 16),         // JP nn
+                1 => (2, 4),          // CB prefix
+                6 | 7 => (1, 4),      // DI / EI
+                _ => return None,
+            },
+            (3, 4) if y <= 3 => (3, 24), // CALL cc,nn
+            (3, 5) if q == 0 => (1, 16), // PUSH rp2
+            (3, 5) if p == 0 => (3, 24), // CALL nn
+            (3, 6) => (2, 8),            // ALU A,n
+            (3, 7) => (1, 16),           // RST
+            _ => return None,
+        };
+        Some(entry)
+    }
+
+    fn canonical_cb_duration(cb_opcode: u8) -> u8 {
+        let x = cb_opcode >> 6;
+        let z = cb_opcode & 0x07;
+        match (x, z) {
+            (1, 6) => 12, // BIT n,(HL) only reads memory
+            (_, 6) => 16, // read-modify-write on (HL)
+            _ => 8,
+        }
+    }
+
+    #[test]
+    fn test_opcode_tables_match_canonical_encoding() {
+        for opcode in 0..=0xFFu8 {
+            let expected = canonical(opcode).unwrap_or((INVALID, INVALID));
+            let actual = (
+                OPCODE_LENGTHS[opcode as usize],
+                OPCODE_DURATION[opcode as usize],
+            );
+            assert_eq!(actual, expected, "opcode 0x{:02X}", opcode);
+        }
+    }
+
+    #[test]
+    fn test_cb_duration_table_matches_canonical_encoding() {
+        for cb_opcode in 0..=0xFFu8 {
+            assert_eq!(
+                OPCODE_DURATION_CB[cb_opcode as usize],
+                canonical_cb_duration(cb_opcode),
+                "CB opcode 0x{:02X}",
+                cb_opcode
+            );
+        }
+    }
+
+    #[test]
+    fn test_disassembler_lengths_match_table() {
+        for opcode in 0..=0xFFu8 {
+            let instruction = crate::disasm::decode(&[opcode, 0x00, 0x00], 0);
+            if INVALID_OPCODES.contains(&opcode) {
+                assert_eq!(instruction.text, format!("db ${:02X}", opcode));
+            } else {
+                assert_eq!(
+                    instruction.len(),
+                    OPCODE_LENGTHS[opcode as usize] as usize,
+                    "opcode 0x{:02X}",
+                    opcode
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_invalid_opcodes_have_no_metadata() {
+        for opcode in 0..=0xFFu8 {
+            let invalid = INVALID_OPCODES.contains(&opcode);
+            assert_eq!(canonical(opcode).is_none(), invalid, "opcode 0x{:02X}", opcode);
+            assert_eq!(OPCODE_LENGTHS[opcode as usize] == INVALID, invalid);
+            assert_eq!(OPCODE_DURATION[opcode as usize] == INVALID, invalid);
+        }
+    }
+
+    #[test]
+    fn test_di_and_stop_entries() {
+        assert_eq!(OPCODE_LENGTHS[0xF3], 1);
+        assert_eq!(OPCODE_DURATION[0xF3], 4);
+        assert_eq!(OPCODE_LENGTHS[0x10], 2);
+        assert_eq!(OPCODE_DURATION[0x10], 4);
+    }
+
+    #[test]
+    fn test_skip_boot_registers() {
+        let mut cpu = Cpu::new();
+        cpu.skip_boot(Model::Dmg, 0x4D);
+        assert_eq!(cpu.registers.read_r16(AF), 0x01B0);
+        assert_eq!(cpu.registers.read_r16(HL), 0x014D);
+        assert_eq!(cpu.registers.read_r16(PC), 0x0100);
+
+        cpu.skip_boot(Model::Dmg, 0x00);
+        assert_eq!(cpu.registers.read_r16(AF), 0x0180);
+
+        cpu.skip_boot(Model::Cgb, 0x4D);
+        assert_eq!(cpu.registers.read_r8(A), 0x11);
+    }
+
+    // Run the assembled program for long enough to reach its final `done: jr done` loop
+    fn run_program(source: &str) -> Memory {
+        let rom = crate::testrom::assemble(source);
+        let mut mem = Memory::new(rom);
+        mem.skip_boot(Model::Dmg);
+        let mut cpu = Cpu::new();
+        cpu.skip_boot(Model::Dmg, 0);
+        for _ in 0..10_000 {
+            cpu.step(&mut mem);
+        }
+        mem
+    }
+
+    #[test]
+    fn test_ei_takes_effect_after_next_instruction() {
+        let mem = run_program(
+            "
+            org $0050
+                ld a, b         ; INC Bs executed before the handler ran
+                ld ($C000), a
+                reti
+            org $0100
+                ld a, $04
+                ldh ($FF), a    ; IE: timer
+                ldh ($0F), a    ; IF: timer already requested
+                ei
+                inc b
+                inc b
+                inc b
+            done:
+                jr done
+            ",
+        );
+        assert_eq!(mem.read_8(0xC000), 1);
+    }
+
+    // Step the assembled program until PC reaches `pc`
+    fn run_to(source: &str, pc: u16) -> (Cpu, Memory) {
+        let rom = crate::testrom::assemble(source);
+        let mut mem = Memory::new(rom);
+        mem.skip_boot(Model::Dmg);
+        let mut cpu = Cpu::new();
+        cpu.skip_boot(Model::Dmg, 0);
+        while cpu.registers.read_r16(PC) != pc {
+            cpu.step(&mut mem);
+        }
+        (cpu, mem)
+    }
+
+    #[test]
+    fn test_step_includes_dispatch_cycles() {
+        let (mut cpu, mut mem) = run_to(
+            "
+            org $0100
+                ld a, $04
+                ldh ($FF), a    ; IE: timer
+                ldh ($0F), a    ; IF: timer
+                ei
+                nop
+            ",
+            0x0106,
+        );
+        assert_eq!(cpu.step(&mut mem), 4); // EI: IME is still off after it
+        assert_eq!(cpu.step(&mut mem), 4 + 20); // NOP, then the dispatch
+        assert_eq!(cpu.registers.read_r16(PC), 0x0050);
+        assert_eq!(cpu.registers.read_r16(SP), 0xFFFC);
+        assert_eq!(mem.read_16(0xFFFC), 0x0108);
+    }
+
+    #[test]
+    fn test_di_right_after_ei_cancels_it() {
+        let mem = run_program(
+            "
+            org $0050
+                ld a, $FF
+                ld ($C000), a
+                reti
+            org $0100
+                ld a, $04
+                ldh ($FF), a
+                ldh ($0F), a
+                ei
+                di
+                nop
+            done:
+                jr done
+            ",
+        );
+        assert_eq!(mem.read_8(0xC000), 0x00);
+    }
+
+    #[test]
+    fn test_halt_wake_dispatches_with_extra_cycle() {
+        let (mut cpu, mut mem) = run_to(
+            "
+            org $0100
+                ld a, $04
+                ldh ($FF), a
+                ei
+                halt
+                nop
+            ",
+            0x0105,
+        );
+        cpu.step(&mut mem); // HALT
+        assert!(cpu.halted);
+        assert_eq!(cpu.step(&mut mem), 4);
+
+        mem.write_8(0xFF0F, 0x04);
+        // One M-cycle to leave HALT, then the dispatch; the NOP does not run first
+        assert_eq!(cpu.step(&mut mem), 4 + 20);
+        assert!(!cpu.halted);
+        assert_eq!(cpu.registers.read_r16(PC), 0x0050);
+        assert_eq!(mem.read_16(0xFFFC), 0x0106);
+    }
+
+    #[test]
+    fn test_halt_bug_reads_next_byte_twice() {
+        // LD A,$14 runs as LD A,$3E, then the $14 operand runs as INC D
+        let mem = run_program(
+            "
+            org $0100
+                di
+                ld d, $00
+                ld a, $04
+                ldh ($FF), a
+                ldh ($0F), a
+                halt
+                ld a, $14
+                ld ($C000), a
+                ld a, d
+                ld ($C001), a
+            done:
+                jr done
+            ",
+        );
+        assert_eq!(mem.read_8(0xC000), 0x3E);
+        assert_eq!(mem.read_8(0xC001), 0x01);
+    }
+
+    #[test]
+    fn test_ei_halt_with_request_returns_to_halt() {
+        let (mut cpu, mut mem) = run_to(
+            "
+            org $0050
+                ld hl, $C000
+                inc (hl)
+                reti
+            org $0100
+                ld a, $04
+                ldh ($FF), a
+                ldh ($0F), a
+                ei
+                halt
+            ",
+            0x0106,
+        );
+        cpu.step(&mut mem); // EI
+        cpu.step(&mut mem); // HALT hits the bug and the interrupt is taken
+        assert_eq!(mem.read_16(0xFFFC), 0x0107);
+        for _ in 0..10 {
+            cpu.step(&mut mem);
+        }
+        // Back on the HALT, which now halts: the handler ran once
+        assert_eq!(mem.read_8(0xC000), 1);
+        assert!(cpu.halted);
+        assert_eq!(cpu.registers.read_r16(PC), 0x0108);
+    }
+
+    #[test]
+    fn test_halt_wakes_without_ime() {
+        let mem = run_program(
+            "
+            org $0050
+                ld a, $FF       ; Must not be dispatched with IME off
+                ld ($C000), a
+                reti
+            org $0100
+                di
+                ld a, $04
+                ldh ($FF), a
+                ld a, $05
+                ldh ($07), a    ; Timer on, 262144 Hz
+                halt
+                nop
+                ld a, $01
+                ld ($C001), a
+            done:
+                jr done
+            ",
+        );
+        assert_eq!(mem.read_8(0xC000), 0x00);
+        assert_eq!(mem.read_8(0xC001), 0x01);
+    }
+
+    #[test]
+    fn test_stack_writes_wrap_into_io() {
+        let mem = run_program(
+            "
+            org $0100
+                ld sp, $FF10
+                ld bc, $1BAA
+                push bc         ; High byte lands on IF (FF0F)
+                ld sp, $0001
+                ld de, $12AB
+                push de         ; Low byte lands on IE, SP wraps to FFFF
+                ld ($C000), sp
+                ld sp, $C104
+                ld ($FFFF), sp  ; Low byte on IE, high byte wraps to 0000
+                ld a, $01
+                ld ($C002), a
+            done:
+                jr done
+            ",
+        );
+        assert_eq!(mem.read_8(0xFF0F) & 0x1F, 0x1B);
+        assert_eq!(mem.read_8(0xC000), 0xFF);
+        assert_eq!(mem.read_8(0xC001), 0xFF);
+        assert_eq!(mem.read_8(0xFFFF), 0x04);
+        assert_eq!(mem.read_8(0xC002), 0x01);
+    }
+
+    // Dispatch with SP = 0000 pushes the PC high byte (01) onto IE before picking
+    // the interrupt, leaving only VBlank enabled
+    fn dispatch_with_stack_on_ie(requested: u8) -> Memory {
+        run_program(&format!(
+            "
+            org $0000
+                ld a, $01
+                ld ($C000), a
+            stop0:
+                jr stop0
+            org $0040
+                ld a, $40
+                ld ($C000), a
+            stop40:
+                jr stop40
+            org $0100
+                ld sp, $0000
+                ld a, $04
+                ldh ($FF), a    ; IE: timer
+                ld a, ${:02X}
+                ldh ($0F), a
+                ei
+                nop
+            done:
+                jr done
+            ",
+            requested
+        ))
+    }
+
+    #[test]
+    fn test_interrupt_push_onto_ie_redirects_dispatch() {
+        let mem = dispatch_with_stack_on_ie(0x05);
+        assert_eq!(mem.read_8(0xC000), 0x40);
+        assert_eq!(mem.read_8(0xFF0F) & 0x04, 0x04); // Timer still requested
+
+        let mem = dispatch_with_stack_on_ie(0x04);
+        assert_eq!(mem.read_8(0xC000), 0x01);
+        assert_eq!(mem.read_8(0xFF0F) & 0x04, 0x04);
+    }
+
+    #[test]
+    fn test_push_into_rom_writes_mapper_register() {
+        // High byte first: the bank register ends up with the low byte (bank 3)
+        let mem = run_program(
+            "
+            org $0147
+                db $01          ; MBC1
+            bank 15
+            org $4000
+                db 0
+            org $0100
+                ld sp, $3001
+                ld bc, $0203
+                push bc
+            done:
+                jr done
+            ",
+        );
+        assert_eq!(mem.rom_bank(), 3);
+
+        // Interrupt dispatch pushes the return address (010B) the same way
+        let mem = run_program(
+            "
+            org $0147
+                db $01
+            bank 15
+            org $4000
+                db 0
+            org $0050
+            handler:
+                jr handler
+            org $0100
+                ld sp, $2002
+                ld a, $04
+                ldh ($FF), a
+                ldh ($0F), a
+                ei
+                nop
+            done:               ; 010B
+                jr done
+            ",
+        );
+        assert_eq!(mem.rom_bank(), 0x0B);
+    }
+
+    #[test]
+    fn test_push_into_echo_ram() {
+        let mem = run_program(
+            "
+            org $0100
+                ld sp, $E102
+                ld bc, $1234
+                push bc         ; Lands in C100-C101 through echo RAM
+                call sub
+            done:
+                jr done
+            sub:
+                ld sp, $FEA2
+                push bc         ; Unusable area: writes are dropped
+                ld a, ($E0FE)   ; Low byte of CALL's return address, via echo
+                ld ($C000), a
+            stop:
+                jr stop
+            ",
+        );
+        assert_eq!(mem.read_8(0xC100), 0x34);
+        assert_eq!(mem.read_8(0xC101), 0x12);
+        assert_eq!(mem.read_8(0xC0FF), 0x01);
+        assert_eq!(mem.read_8(0xC000), mem.read_8(0xC0FE));
+        assert_eq!(mem.read_8(0xFEA0), 0x00);
+        assert_eq!(mem.read_8(0xFEA1), 0x00);
+    }
+
+    #[test]
+    fn test_invalid_opcode_skips_one_byte() {
+        let mut mem = Memory::new(vec![0xD3, 0x00]);
+        let mut cpu = Cpu::new();
+        let cycles = cpu.step(&mut mem);
+        assert_eq!(cycles, 4); // The opcode fetch still takes its M-cycle
+        assert_eq!(cpu.registers.read_r16(PC), 0x0001);
+    }
+
+    // Number of NOPs between resetting DIV and `read` (which loads TIMA into A) from
+    // which the read sees TIMA incremented (TAC=06: every 16 M-cycles). Each M-cycle
+    // later in its instruction that the read happens lowers the count by one.
+    fn tima_read_threshold(read: &str) -> usize {
+        (0..24)
+            .find(|&delay| {
+                let source = format!(
+                    "org $0100\n ld a, $06\n ldh ($07), a\n ld hl, $FF05\n ld c, $05\n xor a\n \
+                     ldh ($04), a\n ldh ($05), a\n{} {}\n ldh ($80), a\n halt\n",
+                    " nop\n".repeat(delay),
+                    read
+                );
+                run_program(&source).read_8(0xFF80) != 0
+            })
+            .expect("TIMA never incremented")
+    }
+
+    #[test]
+    fn test_reads_land_on_their_bus_cycle() {
+        // Read on M-cycle 2, right after the opcode fetch
+        let second = tima_read_threshold("ld a, (hl)");
+        assert_eq!(tima_read_threshold("ldh a, (c)"), second);
+        assert_eq!(tima_read_threshold("add a, (hl)"), second);
+        // After the operand fetches
+        assert_eq!(tima_read_threshold("ldh a, ($05)"), second - 1);
+        assert_eq!(tima_read_threshold("ld a, ($FF05)"), second - 2);
     }
 }
@@ -0,0 +1,1857 @@
+// Debugger support
+// Views that decode machine state into human-readable form for interactive debugging.
+
+use std::collections::BTreeSet;
+use std::fmt;
+use std::str::FromStr;
+
+use crate::cartridge::CartridgeHeader;
+use crate::clock::FRAME_CYCLES;
+use crate::cpu::{Cpu, Reg16};
+use crate::disasm::{self, Instruction};
+use crate::history::ExecutionHistory;
+use crate::i18n::Catalog;
+use crate::mbc::{Mbc, RamMapping};
+use crate::memory::{MbcWrite, Memory, WatchedAccess, BANK_MASK, EXTERNAL_RAM_SIZE};
+use crate::ramsearch::{CandidateList, RamSearch, SearchCondition};
+use crate::rtc::{self, RtcTime};
+use crate::strict::Violation;
+
+// Timer input clock (T-cycles per second)
+const CLOCK_HZ: u32 = 4_194_304;
+
+// Interrupt sources in priority order (bit index in IE/IF)
+const INTERRUPT_NAMES: [&str; 5] = ["VBlank", "LCD STAT", "Timer", "Serial", "Joypad"];
+
+/// State of one interrupt source
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InterruptLine {
+    pub name: &'static str,
+    /// Bit set in IE (0xFFFF)
+    pub enabled: bool,
+    /// Bit set in IF (0xFF0F)
+    pub requested: bool,
+    /// The peripheral has raised the interrupt but it has not been latched into IF yet
+    pub source_pending: bool,
+}
+
+impl InterruptLine {
+    /// Would be dispatched as soon as IME allows it
+    pub fn serviceable(&self) -> bool {
+        self.enabled && (self.requested || self.source_pending)
+    }
+}
+
+/// Decoded IE/IF/IME state, most "my game freezes" reports come down to this
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InterruptReport {
+    pub ime: bool,
+    pub ei_pending: bool,
+    pub halted: bool,
+    pub ie: u8,
+    pub if_reg: u8,
+    pub lines: [InterruptLine; 5],
+}
+
+impl InterruptReport {
+    pub fn capture(cpu: &Cpu, mem: &Memory) -> Self {
+        let ie = mem.read_8(0xFFFF);
+        let if_reg = mem.read_8(0xFF0F);
+        let source_flags = [
+            mem.ppu.vblank_interrupt,
+            mem.ppu.stat_interrupt,
+            mem.timer.interrupt_pending,
+            mem.serial.interrupt_pending,
+            mem.joypad.interrupt_requested,
+        ];
+
+        let lines = std::array::from_fn(|i| InterruptLine {
+            name: INTERRUPT_NAMES[i],
+            enabled: ie & (1 << i) != 0,
+            requested: if_reg & (1 << i) != 0,
+            source_pending: source_flags[i],
+        });
+
+        Self {
+            ime: cpu.registers.read_ime() != 0,
+            ei_pending: cpu.ei_pending,
+            halted: cpu.halted,
+            ie,
+            if_reg,
+            lines,
+        }
+    }
+}
+
+impl fmt::Display for InterruptReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let yes_no = |b: bool| if b { "yes" } else { "no" };
+        writeln!(
+            f,
+            "IME: {} (EI pending: {}, halted: {})",
+            if self.ime { "enabled" } else { "disabled" },
+            yes_no(self.ei_pending),
+            yes_no(self.halted)
+        )?;
+        writeln!(f, "IE: 0x{:02X}  IF: 0x{:02X}", self.ie, self.if_reg)?;
+        for line in &self.lines {
+            let pending = match (line.requested, line.source_pending) {
+                (true, _) => "pending",
+                (false, true) => "pending (not yet in IF)",
+                (false, false) => "not pending",
+            };
+            writeln!(
+                f,
+                "{:<9} {}, {}",
+                format!("{}:", line.name),
+                if line.enabled { "enabled" } else { "disabled" },
+                pending
+            )?;
+        }
+        if !self.ime && self.lines.iter().any(|l| l.serviceable()) {
+            writeln!(f, "note: interrupts are pending but IME is off")?;
+        }
+        Ok(())
+    }
+}
+
+fn format_size(bytes: usize) -> String {
+    if bytes >= 1024 * 1024 {
+        format!("{} MiB", bytes / (1024 * 1024))
+    } else {
+        format!("{} KiB", bytes.div_ceil(1024))
+    }
+}
+
+/// Memory map of the loaded cartridge as emulated: what the header asks for next to
+/// what Memory actually maps, so unsupported hardware shows up here first
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MemoryMap {
+    pub header: CartridgeHeader,
+    /// ROM banks the emulator can map, including bank 0
+    pub mappable_rom_banks: usize,
+    /// Bank currently mapped at 0x4000-0x7FFF
+    pub rom_bank: u16,
+    /// Mapper state, None for cartridges without an emulated mapper
+    pub mbc: Option<Mbc>,
+    /// Cartridge RAM banks the emulator keeps
+    pub ram_banks: usize,
+}
+
+impl MemoryMap {
+    pub fn capture(mem: &Memory) -> Self {
+        Self {
+            header: CartridgeHeader::parse(&mem.rom.buffer[..]),
+            mappable_rom_banks: mem.mappable_rom_banks(),
+            rom_bank: mem.rom_bank(),
+            mbc: mem.mbc().cloned(),
+            ram_banks: mem.external_ram_banks(),
+        }
+    }
+}
+
+impl fmt::Display for MemoryMap {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let header = &self.header;
+        let yes_no = |b: bool| if b { "yes" } else { "no" };
+        writeln!(f, "Title:   {}", header.title)?;
+        writeln!(
+            f,
+            "Mapper:  {} (0x{:02X}){}",
+            header.mapper_name(),
+            header.cartridge_type,
+            if header.mapper_supported() {
+                ""
+            } else {
+                ", NOT emulated"
+            }
+        )?;
+        match header.rom_size {
+            Some(size) => writeln!(f, "ROM:     {}, {} banks", format_size(size), size / 0x4000)?,
+            None => writeln!(f, "ROM:     unknown size")?,
+        }
+        match header.ram_size {
+            Some(0) => writeln!(f, "RAM:     none")?,
+            Some(size) => writeln!(
+                f,
+                "RAM:     {}, {} bank(s){}",
+                format_size(size),
+                size.div_ceil(EXTERNAL_RAM_SIZE),
+                if header.has_battery() {
+                    ", battery-backed"
+                } else {
+                    ""
+                }
+            )?,
+            None => writeln!(f, "RAM:     unknown size")?,
+        }
+        match self.mbc.as_ref().and_then(Mbc::rtc) {
+            Some(rtc) => writeln!(
+                f,
+                "RTC:     yes, {}{}",
+                rtc.time(),
+                if rtc.halted() { ", halted" } else { "" }
+            )?,
+            None => writeln!(f, "RTC:     {}", yes_no(header.has_rtc()))?,
+        }
+        writeln!(f)?;
+
+        let last_bank = self.mappable_rom_banks - 1;
+        let header_banks = header.rom_size.map_or(0, |size| size / 0x4000);
+        match &self.mbc {
+            Some(mbc) => {
+                write!(f, "0000-3FFF  ROM bank {}", mbc.low_rom_bank())?;
+                if let Mbc::Mbc1(mbc1) = mbc {
+                    write!(f, " (banking mode {})", mbc1.mode())?;
+                }
+                writeln!(f)?;
+                let last_bank = last_bank.min(header_banks.saturating_sub(1)).max(1);
+                // MBC1 takes bank bits 5-6 from its second register, MBC5 bit 8 from
+                // 3000-3FFF; only MBC5 can map bank 0 here
+                let (first_bank, registers) = match mbc {
+                    Mbc::Mbc1(_) if last_bank >= 0x20 => (1, "2000-3FFF, 4000-5FFF"),
+                    Mbc::Mbc5(_) if last_bank >= 0x100 => (0, "2000-2FFF, 3000-3FFF"),
+                    Mbc::Mbc5(_) => (0, "2000-2FFF"),
+                    _ => (1, "2000-3FFF"),
+                };
+                write!(
+                    f,
+                    "4000-7FFF  ROM bank {} (switchable {}-{} via {})",
+                    self.rom_bank, first_bank, last_bank, registers
+                )?;
+            }
+            None => {
+                writeln!(f, "0000-3FFF  ROM bank 0")?;
+                write!(f, "4000-7FFF  ROM bank 1 (no mapper)")?;
+            }
+        }
+        if header_banks > self.mappable_rom_banks {
+            write!(f, ", banks {}+ read 0xFF", self.mappable_rom_banks)?;
+        }
+        writeln!(f)?;
+        writeln!(f, "8000-9FFF  VRAM")?;
+        match &self.mbc {
+            Some(mbc) => {
+                match mbc.ram_mapping() {
+                    RamMapping::Bank(bank) => write!(
+                        f,
+                        "A000-BFFF  Cartridge RAM bank {} of {}",
+                        bank, self.ram_banks
+                    )?,
+                    RamMapping::Rtc(select) => write!(
+                        f,
+                        "A000-BFFF  RTC register {:02X} ({})",
+                        select,
+                        rtc::register_name(select)
+                    )?,
+                }
+                if mbc.ram_enabled() {
+                    write!(f, ", enabled")?;
+                } else {
+                    write!(f, ", disabled (enable via 0000-1FFF)")?;
+                }
+            }
+            None => write!(f, "A000-BFFF  Cartridge RAM, 1 bank, always enabled")?,
+        }
+        let ram_size = header.ram_size.unwrap_or(0);
+        let mapped = self.ram_banks * EXTERNAL_RAM_SIZE;
+        if ram_size > mapped {
+            write!(
+                f,
+                " ({} of {} mapped)",
+                format_size(mapped),
+                format_size(ram_size)
+            )?;
+        }
+        writeln!(f)?;
+        writeln!(f, "C000-DFFF  WRAM")?;
+        writeln!(f, "E000-FDFF  Echo RAM (mirror of C000-DDFF)")?;
+        writeln!(f, "FE00-FE9F  OAM")?;
+        writeln!(f, "FEA0-FEFF  Unusable (reads 00, writes ignored)")?;
+        writeln!(f, "FF00-FF7F  I/O registers")?;
+        writeln!(f, "FF80-FFFE  HRAM")?;
+        writeln!(f, "FFFF      IE")
+    }
+}
+
+/// Recent mapper register writes, oldest first, for tracking down bank-switch bugs
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MbcLog {
+    /// None when logging is off (`Memory::set_mbc_log_enabled`)
+    pub writes: Option<Vec<MbcWrite>>,
+    pub rom_bank: u16,
+    /// Decodes the writes; without an emulated mapper every write is ignored
+    pub mbc: Option<Mbc>,
+}
+
+impl MbcLog {
+    pub fn capture(mem: &Memory) -> Self {
+        Self {
+            writes: mem
+                .mbc_log_enabled()
+                .then(|| mem.mbc_writes().copied().collect()),
+            rom_bank: mem.rom_bank(),
+            mbc: mem.mbc().cloned(),
+        }
+    }
+}
+
+impl fmt::Display for MbcLog {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let Some(writes) = &self.writes else {
+            return writeln!(f, "MBC log is off");
+        };
+        writeln!(
+            f,
+            "MBC writes: {}, ROM bank now {}",
+            writes.len(),
+            self.rom_bank
+        )?;
+        for write in writes {
+            write!(
+                f,
+                "  cycle {:>10}  {:04X} <- {:02X}  ",
+                write.cycle, write.address, write.value
+            )?;
+            let Some(mbc) = &self.mbc else {
+                writeln!(f, "ignored (no mapper)")?;
+                continue;
+            };
+            let mbc1 = matches!(mbc, Mbc::Mbc1(_));
+            let mbc5 = matches!(mbc, Mbc::Mbc5(_));
+            let used_bits = match write.address {
+                0x0000..=0x1FFF => {
+                    let state = if write.ram_enabled {
+                        "enabled"
+                    } else {
+                        "disabled"
+                    };
+                    writeln!(f, "RAM {}", state)?;
+                    continue;
+                }
+                // MBC5 can select bank 0, and takes bit 8 from 3000-3FFF
+                0x2000..=0x2FFF if mbc5 => {
+                    write!(f, "ROM bank {}", write.rom_bank)?;
+                    0xFF
+                }
+                0x3000..=0x3FFF if mbc5 => {
+                    write!(f, "ROM bank {} (bit 8)", write.rom_bank)?;
+                    0x01
+                }
+                0x4000..=0x5FFF if mbc5 => {
+                    write!(f, "RAM bank {}", write.ram_bank)?;
+                    0x0F
+                }
+                0x2000..=0x3FFF => {
+                    let bank_mask = if mbc1 { BANK_MASK } else { 0x7F };
+                    write!(f, "ROM bank {}", write.rom_bank)?;
+                    if write.value & bank_mask == 0 {
+                        write!(f, " (bank 0 selects 1)")?;
+                    }
+                    bank_mask
+                }
+                0x4000..=0x5FFF if mbc1 => {
+                    write!(
+                        f,
+                        "ROM bank {}, RAM bank {}",
+                        write.rom_bank, write.ram_bank
+                    )?;
+                    0x03
+                }
+                0x4000..=0x5FFF => match write.value {
+                    select @ 0x08..=0x0C => {
+                        write!(f, "RTC register ({})", rtc::register_name(select))?;
+                        0xFF
+                    }
+                    _ => {
+                        write!(f, "RAM bank {}", write.ram_bank)?;
+                        0x03
+                    }
+                },
+                _ if mbc5 => {
+                    writeln!(f, "ignored (no register)")?;
+                    continue;
+                }
+                _ if !mbc1 => {
+                    writeln!(f, "RTC latch")?;
+                    continue;
+                }
+                _ => {
+                    write!(
+                        f,
+                        "banking mode {}, RAM bank {}",
+                        write.value & 0x01,
+                        write.ram_bank
+                    )?;
+                    0x01
+                }
+            };
+            if write.value & !used_bits != 0 {
+                write!(f, " (bits {:02X} masked off)", write.value & !used_bits)?;
+            }
+            writeln!(f)?;
+        }
+        Ok(())
+    }
+}
+
+/// One cartridge RAM bank as a hex dump, for inspecting save data
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CartRamView {
+    pub bank: usize,
+    pub banks: usize,
+    pub enabled: bool,
+    pub data: Vec<u8>,
+}
+
+impl CartRamView {
+    pub fn capture(mem: &Memory, bank: usize) -> Result<Self, String> {
+        let data = mem.external_ram_bank(bank).ok_or_else(|| {
+            format!(
+                "no cartridge RAM bank {} ({} bank(s))",
+                bank,
+                mem.external_ram_banks()
+            )
+        })?;
+        Ok(Self {
+            bank,
+            banks: mem.external_ram_banks(),
+            enabled: mem.external_ram_enabled(),
+            data: data.to_vec(),
+        })
+    }
+}
+
+impl fmt::Display for CartRamView {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "Cartridge RAM bank {} of {}, {}",
+            self.bank,
+            self.banks,
+            if self.enabled { "enabled" } else { "disabled" }
+        )?;
+        // Like hexdump, runs of repeated rows are shown as a single '*'
+        let mut previous: Option<&[u8]> = None;
+        let mut skipping = false;
+        for (i, row) in self.data.chunks(16).enumerate() {
+            if previous == Some(row) {
+                if !skipping {
+                    writeln!(f, "*")?;
+                    skipping = true;
+                }
+                continue;
+            }
+            previous = Some(row);
+            skipping = false;
+            write!(f, "{:04X} ", 0xA000 + i * 16)?;
+            for byte in row {
+                write!(f, " {:02X}", byte)?;
+            }
+            let text: String = row
+                .iter()
+                .map(|&b| if b.is_ascii_graphic() { b as char } else { '.' })
+                .collect();
+            writeln!(f, "  {}", text)?;
+        }
+        Ok(())
+    }
+}
+
+/// Cycle-exact timer state, for games whose music or game speed runs too fast or slow
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TimerReport {
+    /// Internal 16-bit divider; DIV is its upper byte
+    pub divider: u16,
+    pub tima: u8,
+    pub tma: u8,
+    /// Low three bits of TAC (the rest read as 1)
+    pub tac: u8,
+    /// Divider bit whose falling edge increments TIMA
+    pub selected_bit: u16,
+    /// T-cycles until TIMA next increments, None while the timer is stopped
+    pub cycles_until_increment: Option<u32>,
+    /// T-cycles until TMA is reloaded into TIMA after an overflow
+    pub reload_in: Option<u8>,
+    /// Overflow raised but not yet latched into IF
+    pub interrupt_pending: bool,
+}
+
+impl TimerReport {
+    pub fn capture(mem: &Memory) -> Self {
+        let timer = &mem.timer;
+        Self {
+            divider: timer.internal_counter(),
+            tima: timer.read(0xFF05),
+            tma: timer.read(0xFF06),
+            tac: timer.read(0xFF07) & 0x07,
+            selected_bit: timer.selected_bit(),
+            cycles_until_increment: timer.cycles_until_increment(),
+            reload_in: timer.reload_pending(),
+            interrupt_pending: timer.interrupt_pending,
+        }
+    }
+
+    /// TIMA increments per second with the current TAC
+    pub fn frequency(&self) -> u32 {
+        CLOCK_HZ >> (self.selected_bit + 1)
+    }
+}
+
+impl fmt::Display for TimerReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "Divider: 0x{:04X} (DIV 0x{:02X})",
+            self.divider,
+            self.divider >> 8
+        )?;
+        writeln!(
+            f,
+            "TIMA: 0x{:02X}  TMA: 0x{:02X}  TAC: 0x{:02X} ({}, {} Hz, divider bit {})",
+            self.tima,
+            self.tma,
+            self.tac,
+            if self.tac & 0x04 != 0 {
+                "running"
+            } else {
+                "stopped"
+            },
+            self.frequency(),
+            self.selected_bit
+        )?;
+        match self.cycles_until_increment {
+            Some(cycles) => writeln!(f, "Next TIMA increment in {} cycles", cycles)?,
+            None => writeln!(f, "Next TIMA increment: never (timer stopped)")?,
+        }
+        match self.reload_in {
+            Some(cycles) => writeln!(f, "Overflow: TMA reload and interrupt in {} cycles", cycles)?,
+            None => writeln!(f, "Overflow: no reload pending")?,
+        }
+        if self.interrupt_pending {
+            writeln!(f, "Timer interrupt raised, not yet in IF")?;
+        }
+        // Interrupt rate, which is what sound drivers usually run off
+        let steps = 0x100 - self.tma as u32;
+        writeln!(
+            f,
+            "Overflow period: {} increments = {:.2} Hz",
+            steps,
+            self.frequency() as f64 / steps as f64
+        )
+    }
+}
+
+/// PC breakpoints and memory watchpoints checked while stepping
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Breakpoints {
+    /// Stop before executing the instruction at these addresses
+    pub pc: BTreeSet<u16>,
+    /// Report when the value at these addresses changes
+    pub watch: BTreeSet<u16>,
+    /// Report every CPU read of these addresses
+    pub read: BTreeSet<u16>,
+    /// Report every CPU write to these addresses, even of the value already there
+    pub write: BTreeSet<u16>,
+}
+
+impl Breakpoints {
+    pub fn is_empty(&self) -> bool {
+        self.pc.is_empty() && self.watch.is_empty() && self.read.is_empty() && self.write.is_empty()
+    }
+
+    /// One line per kind that has any, in `catalog`'s language
+    pub fn describe(&self, catalog: &Catalog) -> String {
+        if self.is_empty() {
+            return catalog.get("debug-no-breakpoints").to_string() + "\n";
+        }
+        let lists = [
+            ("debug-list-breakpoints", &self.pc),
+            ("debug-list-watch", &self.watch),
+            ("debug-list-read-watch", &self.read),
+            ("debug-list-write-watch", &self.write),
+        ];
+        let mut text = String::new();
+        for (id, addresses) in lists {
+            if !addresses.is_empty() {
+                let addresses: Vec<String> = addresses
+                    .iter()
+                    .map(|address| format!("{:04X}", address))
+                    .collect();
+                text += &catalog.format(id, &[&addresses.join(" ")]);
+                text.push('\n');
+            }
+        }
+        text
+    }
+}
+
+impl fmt::Display for Breakpoints {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.describe(&Catalog::default()))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HitKind {
+    Breakpoint,
+    /// The value changed during the instruction at `pc`
+    Watchpoint {
+        address: u16,
+        old: u8,
+        new: u8,
+    },
+    /// The CPU read `value` from a read watchpoint
+    Read {
+        address: u16,
+        value: u8,
+    },
+    /// The CPU wrote `value` to a write watchpoint
+    Write {
+        address: u16,
+        value: u8,
+    },
+    /// A strict-mode check failed (see `Memory::set_strict_enabled`)
+    Strict(Violation),
+}
+
+impl From<WatchedAccess> for HitKind {
+    fn from(access: WatchedAccess) -> Self {
+        let (address, value) = (access.address, access.value);
+        match access.write {
+            false => HitKind::Read { address, value },
+            true => HitKind::Write { address, value },
+        }
+    }
+}
+
+/// A breakpoint or watchpoint hit, stamped with where the PPU was at the time
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FrameHit {
+    pub kind: HitKind,
+    pub pc: u16,
+    /// T-cycle timestamp (`EmulatedClock::cycles`)
+    pub cycle: u64,
+    pub ly: u8,
+    pub dot: u32,
+}
+
+/// Everything that hit during one stepped frame
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FrameStep {
+    /// Timestamp of the first cycle of the frame
+    pub start_cycle: u64,
+    pub cycles: u32,
+    pub hits: Vec<FrameHit>,
+}
+
+/// Run one frame, recording every breakpoint, watchpoint and strict-mode hit instead of
+/// stopping. Breakpoints are stamped before their instruction runs; watchpoints and strict
+/// violations after the instruction that caused them, so their position is where that
+/// instruction ended.
+pub fn step_frame(cpu: &mut Cpu, mem: &mut Memory, breakpoints: &Breakpoints) -> FrameStep {
+    let start_cycle = mem.clock.cycles();
+    mem.watch_accesses(&breakpoints.read, &breakpoints.write);
+    let mut watched: Vec<(u16, u8)> = breakpoints
+        .watch
+        .iter()
+        .map(|&address| (address, mem.read_8(address)))
+        .collect();
+    let mut hits = Vec::new();
+    let mut cycles = 0;
+    while (cycles as u64) < FRAME_CYCLES {
+        let pc = cpu.registers.read_r16(Reg16::PC);
+        let stamp = |kind, mem: &Memory| FrameHit {
+            kind,
+            pc,
+            cycle: mem.clock.cycles(),
+            ly: mem.ppu.ly(),
+            dot: mem.ppu.dot(),
+        };
+        if breakpoints.pc.contains(&pc) {
+            hits.push(stamp(HitKind::Breakpoint, mem));
+        }
+
+        cycles += cpu.step(mem);
+
+        for (address, old) in watched.iter_mut() {
+            let new = mem.read_8(*address);
+            if new != *old {
+                let kind = HitKind::Watchpoint {
+                    address: *address,
+                    old: *old,
+                    new,
+                };
+                hits.push(stamp(kind, mem));
+                *old = new;
+            }
+        }
+        for access in mem.take_watched_accesses() {
+            hits.push(stamp(HitKind::from(access), mem));
+        }
+        for event in mem.take_strict_events() {
+            hits.push(FrameHit {
+                pc: event.pc,
+                cycle: event.cycle,
+                ..stamp(HitKind::Strict(event.violation), mem)
+            });
+        }
+    }
+    mem.unwatch_accesses();
+    FrameStep {
+        start_cycle,
+        cycles,
+        hits,
+    }
+}
+
+impl fmt::Display for FrameStep {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "Frame at cycle {}: {} cycles, {} hit(s)",
+            self.start_cycle,
+            self.cycles,
+            self.hits.len()
+        )?;
+        for hit in &self.hits {
+            write!(
+                f,
+                "  LY {:>3} dot {:>3}  +{:<5}  PC {:04X}  ",
+                hit.ly,
+                hit.dot,
+                hit.cycle - self.start_cycle,
+                hit.pc
+            )?;
+            match hit.kind {
+                HitKind::Breakpoint => writeln!(f, "breakpoint")?,
+                HitKind::Watchpoint { address, old, new } => {
+                    writeln!(f, "watch {:04X}: {:02X} -> {:02X}", address, old, new)?
+                }
+                HitKind::Read { address, value } => {
+                    writeln!(f, "read {:04X}: {:02X}", address, value)?
+                }
+                HitKind::Write { address, value } => {
+                    writeln!(f, "write {:04X}: {:02X}", address, value)?
+                }
+                HitKind::Strict(violation) => writeln!(f, "strict: {}", violation)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Why `step_instruction`, `step_over` or `run_until_break` returned
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StopReason {
+    /// The requested instruction(s) finished
+    Stepped,
+    /// A breakpoint, watchpoint or strict-mode check hit (see `Stop::hits`)
+    Hit,
+    /// The cycle budget ran out first
+    CycleLimit,
+}
+
+/// Where an instruction-level run stopped
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Stop {
+    pub reason: StopReason,
+    /// Everything that hit during the last instruction, or the breakpoint at `pc`
+    pub hits: Vec<HitKind>,
+    /// Address of the next instruction
+    pub pc: u16,
+    /// T-cycles run
+    pub cycles: u64,
+}
+
+impl Stop {
+    /// Where and why the run stopped, then one line per hit, in `catalog`'s language
+    pub fn describe(&self, catalog: &Catalog) -> String {
+        let id = match self.reason {
+            StopReason::Stepped => "debug-stopped",
+            StopReason::Hit => "debug-hit",
+            StopReason::CycleLimit => "debug-cycle-limit",
+        };
+        let pc = format!("{:04X}", self.pc);
+        let mut text = catalog.format(id, &[&pc, &self.cycles]) + "\n";
+        for hit in &self.hits {
+            text += &match *hit {
+                HitKind::Breakpoint => "  breakpoint\n".to_string(),
+                HitKind::Watchpoint { address, old, new } => {
+                    format!("  watch {:04X}: {:02X} -> {:02X}\n", address, old, new)
+                }
+                HitKind::Read { address, value } => {
+                    format!("  read {:04X}: {:02X}\n", address, value)
+                }
+                HitKind::Write { address, value } => {
+                    format!("  write {:04X}: {:02X}\n", address, value)
+                }
+                HitKind::Strict(violation) => format!("  strict: {}\n", violation),
+            };
+        }
+        text
+    }
+}
+
+impl fmt::Display for Stop {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.describe(&Catalog::default()))
+    }
+}
+
+// Hits caused by the instruction that just ran: watched values that changed, watched
+// accesses and strict-mode violations
+fn instruction_hits(mem: &mut Memory, watched: &mut [(u16, u8)]) -> Vec<HitKind> {
+    let mut hits = Vec::new();
+    for (address, old) in watched.iter_mut() {
+        let new = mem.read_8(*address);
+        if new != *old {
+            hits.push(HitKind::Watchpoint {
+                address: *address,
+                old: *old,
+                new,
+            });
+            *old = new;
+        }
+    }
+    hits.extend(mem.take_watched_accesses().into_iter().map(HitKind::from));
+    hits.extend(
+        mem.take_strict_events()
+            .into_iter()
+            .map(|event| HitKind::Strict(event.violation)),
+    );
+    hits
+}
+
+// Run instructions until `done` accepts the CPU state after one, something in
+// `breakpoints` hits or `max_cycles` have run. The breakpoint at the starting PC is not
+// checked, so that a run can leave the breakpoint it stopped at. With a `history`, the
+// instructions are recorded there for stepping back.
+fn run_instructions(
+    cpu: &mut Cpu,
+    mem: &mut Memory,
+    breakpoints: &Breakpoints,
+    mut history: Option<&mut ExecutionHistory>,
+    max_cycles: u64,
+    done: impl Fn(&Cpu) -> bool,
+) -> Stop {
+    mem.watch_accesses(&breakpoints.read, &breakpoints.write);
+    let mut watched: Vec<(u16, u8)> = breakpoints
+        .watch
+        .iter()
+        .map(|&address| (address, mem.read_8(address)))
+        .collect();
+    // Measured on the clock: `Cpu::step` reports a conditional branch's longest duration
+    let start_cycle = mem.clock.cycles();
+    let (reason, hits) = loop {
+        if mem.clock.cycles() - start_cycle >= max_cycles {
+            break (StopReason::CycleLimit, Vec::new());
+        }
+        match history.as_deref_mut() {
+            Some(history) => history.step(cpu, mem),
+            None => cpu.step(mem),
+        };
+        let hits = instruction_hits(mem, &mut watched);
+        if !hits.is_empty() {
+            break (StopReason::Hit, hits);
+        }
+        if done(cpu) {
+            break (StopReason::Stepped, hits);
+        }
+        if breakpoints.pc.contains(&cpu.registers.read_r16(Reg16::PC)) {
+            break (StopReason::Hit, vec![HitKind::Breakpoint]);
+        }
+    };
+    mem.unwatch_accesses();
+    Stop {
+        reason,
+        hits,
+        pc: cpu.registers.read_r16(Reg16::PC),
+        cycles: mem.clock.cycles() - start_cycle,
+    }
+}
+
+// The instruction at `address`, read without side effects
+fn instruction_at(mem: &Memory, address: u16) -> Instruction {
+    let bytes: Vec<u8> = (0..3)
+        .map(|offset| mem.read_8(address.wrapping_add(offset)))
+        .collect();
+    disasm::decode(&bytes, address)
+}
+
+/// Execute one instruction (or one halted M-cycle), reporting watchpoint and strict-mode
+/// hits it caused. With a `history`, it is recorded there so that it can be undone.
+pub fn step_instruction(
+    cpu: &mut Cpu,
+    mem: &mut Memory,
+    breakpoints: &Breakpoints,
+    history: Option<&mut ExecutionHistory>,
+) -> Stop {
+    run_instructions(cpu, mem, breakpoints, history, u64::MAX, |_| true)
+}
+
+/// Like `step_instruction`, but a CALL or RST runs until it returns: until PC is back
+/// after the call with the stack popped to where it was. Stops early on a hit inside the
+/// subroutine or after `max_cycles`.
+pub fn step_over(
+    cpu: &mut Cpu,
+    mem: &mut Memory,
+    breakpoints: &Breakpoints,
+    history: Option<&mut ExecutionHistory>,
+    max_cycles: u64,
+) -> Stop {
+    let pc = cpu.registers.read_r16(Reg16::PC);
+    let instruction = instruction_at(mem, pc);
+    let opcode = instruction.bytes[0];
+    let is_call = matches!(opcode, 0xCD | 0xC4 | 0xCC | 0xD4 | 0xDC) || opcode & 0xC7 == 0xC7;
+    if !is_call || cpu.halted {
+        return step_instruction(cpu, mem, breakpoints, history);
+    }
+    let return_pc = pc.wrapping_add(instruction.len() as u16);
+    let sp = cpu.registers.read_r16(Reg16::SP);
+    run_instructions(cpu, mem, breakpoints, history, max_cycles, |cpu| {
+        cpu.registers.read_r16(Reg16::PC) == return_pc && cpu.registers.read_r16(Reg16::SP) >= sp
+    })
+}
+
+/// Run until a breakpoint or watchpoint hits, or `max_cycles` have run
+pub fn run_until_break(
+    cpu: &mut Cpu,
+    mem: &mut Memory,
+    breakpoints: &Breakpoints,
+    history: Option<&mut ExecutionHistory>,
+    max_cycles: u64,
+) -> Stop {
+    run_instructions(cpu, mem, breakpoints, history, max_cycles, |_| false)
+}
+
+/// CPU registers and flags, and the instruction about to run
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RegisterDump {
+    pub af: u16,
+    pub bc: u16,
+    pub de: u16,
+    pub hl: u16,
+    pub sp: u16,
+    pub pc: u16,
+    pub ime: bool,
+    pub halted: bool,
+    pub next: Instruction,
+}
+
+impl RegisterDump {
+    pub fn capture(cpu: &Cpu, mem: &Memory) -> Self {
+        let pc = cpu.registers.read_r16(Reg16::PC);
+        Self {
+            af: cpu.registers.read_r16(Reg16::AF),
+            bc: cpu.registers.read_r16(Reg16::BC),
+            de: cpu.registers.read_r16(Reg16::DE),
+            hl: cpu.registers.read_r16(Reg16::HL),
+            sp: cpu.registers.read_r16(Reg16::SP),
+            pc,
+            ime: cpu.registers.read_ime() != 0,
+            halted: cpu.halted,
+            next: instruction_at(mem, pc),
+        }
+    }
+}
+
+impl fmt::Display for RegisterDump {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "AF {:04X}  BC {:04X}  DE {:04X}  HL {:04X}  SP {:04X}  PC {:04X}",
+            self.af, self.bc, self.de, self.hl, self.sp, self.pc
+        )?;
+        let flags: String = "ZNHC"
+            .chars()
+            .enumerate()
+            .map(|(bit, name)| match self.af & (0x80 >> bit) {
+                0 => '-',
+                _ => name,
+            })
+            .collect();
+        writeln!(
+            f,
+            "Flags {}  IME {}{}",
+            flags,
+            if self.ime { "on" } else { "off" },
+            if self.halted { "  halted" } else { "" }
+        )?;
+        let bytes: Vec<String> = self
+            .next
+            .bytes
+            .iter()
+            .map(|byte| format!("{:02X}", byte))
+            .collect();
+        writeln!(f, "{:04X}: {:<8}  {}", self.pc, bytes.join(" "), self.next)
+    }
+}
+
+// Frames `continue` runs without a hit before giving control back to the prompt
+const CONTINUE_FRAMES: u64 = 600;
+// RAM search candidates `candidates` lists without a count
+const CANDIDATES_LISTED: usize = 20;
+
+// Instructions between history keyframes, and keyframes kept: about 100,000 instructions
+// (around 25 frames) can be stepped back
+const HISTORY_INTERVAL: u64 = 1000;
+const HISTORY_KEYFRAMES: usize = 100;
+
+/// What the debugger prompt keeps between commands
+pub struct DebugSession {
+    pub breakpoints: Breakpoints,
+    /// Instructions run from the prompt, for `back` and `lw`. Clear it when the game has
+    /// run without it, as its keyframes no longer lead to the current state.
+    pub history: ExecutionHistory,
+    /// RAM search started with `search`
+    pub search: Option<RamSearch>,
+}
+
+impl Default for DebugSession {
+    fn default() -> Self {
+        Self {
+            breakpoints: Breakpoints::default(),
+            history: ExecutionHistory::new(HISTORY_INTERVAL, HISTORY_KEYFRAMES),
+            search: None,
+        }
+    }
+}
+
+/// A command typed at the debugger prompt
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DebugCommand {
+    Step,
+    Next,
+    Continue {
+        frames: u64,
+    },
+    Break(u16),
+    Delete(u16),
+    Watch(u16),
+    ReadWatch(u16),
+    WriteWatch(u16),
+    Clear,
+    List,
+    Registers,
+    Examine {
+        address: u16,
+        len: u16,
+    },
+    StepBack,
+    LastWrite(u16),
+    Search,
+    Filter(SearchCondition),
+    Candidates {
+        limit: usize,
+    },
+    Interrupts,
+    Map,
+    MbcLog,
+    CartRam {
+        bank: usize,
+    },
+    /// Write `value` to byte `offset` of cartridge RAM bank `bank`
+    CartPoke {
+        bank: usize,
+        offset: usize,
+        value: u8,
+    },
+    Timer,
+    Rtc,
+    RtcSet(RtcTime),
+    /// Move the cartridge clock by this many seconds
+    RtcAdjust(i64),
+    RtcHalt(bool),
+    Resume,
+    Help,
+}
+
+fn parse_address(text: Option<&str>, catalog: &Catalog) -> Result<u16, String> {
+    let text = text.ok_or_else(|| catalog.get("debug-missing-address").to_string())?;
+    let digits = text
+        .trim_start_matches('$')
+        .trim_start_matches("0x")
+        .trim_start_matches("0X");
+    u16::from_str_radix(digits, 16).map_err(|_| catalog.format("invalid-address", &[&text]))
+}
+
+fn parse_bank(text: &str, catalog: &Catalog) -> Result<usize, String> {
+    text.parse()
+        .map_err(|_| catalog.format("debug-invalid-bank", &[&text]))
+}
+
+impl FromStr for DebugCommand {
+    type Err = String;
+
+    /// Parses with English errors; see `DebugCommand::parse`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::parse(s, &Catalog::default())
+    }
+}
+
+impl DebugCommand {
+    /// Parse a prompt line, with errors in `catalog`'s language
+    pub fn parse(s: &str, catalog: &Catalog) -> Result<Self, String> {
+        let missing = |id| catalog.get(id).to_string();
+        let mut words = s.split_whitespace();
+        let command = match words.next().unwrap_or("") {
+            "s" | "step" => DebugCommand::Step,
+            "n" | "next" => DebugCommand::Next,
+            "c" | "continue" => DebugCommand::Continue {
+                frames: match words.next() {
+                    Some(frames) => frames
+                        .parse()
+                        .map_err(|_| catalog.format("debug-invalid-frames", &[&frames]))?,
+                    None => CONTINUE_FRAMES,
+                },
+            },
+            "b" | "break" => DebugCommand::Break(parse_address(words.next(), catalog)?),
+            "d" | "delete" => DebugCommand::Delete(parse_address(words.next(), catalog)?),
+            "w" | "watch" => DebugCommand::Watch(parse_address(words.next(), catalog)?),
+            "rw" => DebugCommand::ReadWatch(parse_address(words.next(), catalog)?),
+            "ww" => DebugCommand::WriteWatch(parse_address(words.next(), catalog)?),
+            "clear" => DebugCommand::Clear,
+            "l" | "list" => DebugCommand::List,
+            "r" | "regs" => DebugCommand::Registers,
+            "x" => DebugCommand::Examine {
+                address: parse_address(words.next(), catalog)?,
+                len: match words.next() {
+                    Some(len) => parse_address(Some(len), catalog)?,
+                    None => 0x10,
+                },
+            },
+            "bs" | "back" => DebugCommand::StepBack,
+            "lw" => DebugCommand::LastWrite(parse_address(words.next(), catalog)?),
+            "search" => DebugCommand::Search,
+            "filter" => {
+                // The condition takes the rest of the line
+                let condition = words.by_ref().collect::<Vec<_>>().join(" ");
+                DebugCommand::Filter(
+                    condition
+                        .parse()
+                        .map_err(|_| catalog.format("debug-invalid-condition", &[&condition]))?,
+                )
+            }
+            "candidates" => DebugCommand::Candidates {
+                limit: match words.next() {
+                    Some(limit) => limit
+                        .parse()
+                        .map_err(|_| catalog.format("debug-invalid-count", &[&limit]))?,
+                    None => CANDIDATES_LISTED,
+                },
+            },
+            "irq" => DebugCommand::Interrupts,
+            "map" => DebugCommand::Map,
+            "mbc" => DebugCommand::MbcLog,
+            "cartram" => DebugCommand::CartRam {
+                bank: match words.next() {
+                    Some(bank) => parse_bank(bank, catalog)?,
+                    None => 0,
+                },
+            },
+            "cartpoke" => {
+                let bank = words.next().ok_or_else(|| missing("debug-missing-bank"))?;
+                let bank = parse_bank(bank, catalog)?;
+                let address = parse_address(words.next(), catalog)?;
+                if !(0xA000..=0xBFFF).contains(&address) {
+                    let address = format!("{:04X}", address);
+                    return Err(catalog.format("debug-not-cartram", &[&address]));
+                }
+                let value = words.next().ok_or_else(|| missing("debug-missing-value"))?;
+                DebugCommand::CartPoke {
+                    bank,
+                    offset: (address - 0xA000) as usize,
+                    value: u8::from_str_radix(value.trim_start_matches('$'), 16)
+                        .map_err(|_| catalog.format("debug-invalid-byte", &[&value]))?,
+                }
+            }
+            "timer" => DebugCommand::Timer,
+            "rtc" => match words.next() {
+                None => DebugCommand::Rtc,
+                Some("set") => {
+                    let time = words.next().ok_or_else(|| missing("debug-missing-time"))?;
+                    DebugCommand::RtcSet(
+                        time.parse()
+                            .map_err(|_| catalog.format("debug-invalid-time", &[&time]))?,
+                    )
+                }
+                Some("adjust") => {
+                    let seconds = words
+                        .next()
+                        .ok_or_else(|| missing("debug-missing-seconds"))?;
+                    DebugCommand::RtcAdjust(
+                        seconds
+                            .trim_start_matches('+')
+                            .parse()
+                            .map_err(|_| catalog.format("debug-invalid-seconds", &[&seconds]))?,
+                    )
+                }
+                Some("halt") => DebugCommand::RtcHalt(true),
+                Some("run") => DebugCommand::RtcHalt(false),
+                Some(other) => return Err(catalog.format("debug-unknown-rtc-command", &[&other])),
+            },
+            "g" | "go" | "q" | "quit" => DebugCommand::Resume,
+            "h" | "help" | "?" => DebugCommand::Help,
+            other => return Err(catalog.format("debug-unknown-command", &[&other])),
+        };
+        match words.next() {
+            Some(extra) => Err(catalog.format("debug-unexpected", &[&extra])),
+            None => Ok(command),
+        }
+    }
+
+    /// Run the command; returns what to print, in `catalog`'s language.
+    /// `Resume` does nothing here: the caller leaves the prompt.
+    pub fn execute(
+        &self,
+        cpu: &mut Cpu,
+        mem: &mut Memory,
+        session: &mut DebugSession,
+        catalog: &Catalog,
+    ) -> String {
+        let stopped = |stop: Stop, cpu: &Cpu, mem: &Memory| {
+            format!(
+                "{}{}",
+                stop.describe(catalog),
+                RegisterDump::capture(cpu, mem)
+            )
+        };
+        // One line of text, with addresses and bytes in hex
+        let line = |id, args: &[&dyn fmt::Display]| catalog.format(id, args) + "\n";
+        let hex = |address: u16| format!("{:04X}", address);
+        let DebugSession {
+            breakpoints,
+            history,
+            search,
+        } = session;
+        match *self {
+            DebugCommand::Step => {
+                let stop = step_instruction(cpu, mem, breakpoints, Some(history));
+                stopped(stop, cpu, mem)
+            }
+            DebugCommand::Next => {
+                let max_cycles = CONTINUE_FRAMES * FRAME_CYCLES;
+                let stop = step_over(cpu, mem, breakpoints, Some(history), max_cycles);
+                stopped(stop, cpu, mem)
+            }
+            DebugCommand::Continue { frames } => {
+                let max_cycles = frames.saturating_mul(FRAME_CYCLES);
+                let stop = run_until_break(cpu, mem, breakpoints, Some(history), max_cycles);
+                stopped(stop, cpu, mem)
+            }
+            DebugCommand::Break(address) => {
+                breakpoints.pc.insert(address);
+                line("debug-breakpoint-set", &[&hex(address)])
+            }
+            DebugCommand::Delete(address) => match breakpoints.pc.remove(&address) {
+                true => line("debug-breakpoint-deleted", &[&hex(address)]),
+                false => line("debug-no-breakpoint", &[&hex(address)]),
+            },
+            DebugCommand::Watch(address) => {
+                breakpoints.watch.insert(address);
+                line("debug-watching", &[&hex(address)])
+            }
+            DebugCommand::ReadWatch(address) => {
+                breakpoints.read.insert(address);
+                line("debug-watching-reads", &[&hex(address)])
+            }
+            DebugCommand::WriteWatch(address) => {
+                breakpoints.write.insert(address);
+                line("debug-watching-writes", &[&hex(address)])
+            }
+            DebugCommand::Clear => {
+                *breakpoints = Breakpoints::default();
+                line("debug-breakpoints-cleared", &[])
+            }
+            DebugCommand::List => breakpoints.describe(catalog),
+            DebugCommand::Registers => RegisterDump::capture(cpu, mem).to_string(),
+            DebugCommand::Examine { address, len } => {
+                let mut text = String::new();
+                for row in (0..len as u32).step_by(16) {
+                    let start = address.wrapping_add(row as u16);
+                    let bytes: Vec<String> = (0..(len as u32 - row).min(16) as u16)
+                        .map(|offset| format!("{:02X}", mem.read_8(start.wrapping_add(offset))))
+                        .collect();
+                    text += &format!("{:04X}: {}\n", start, bytes.join(" "));
+                }
+                text
+            }
+            DebugCommand::StepBack => match history.step_back(cpu, mem) {
+                true => {
+                    let pc = cpu.registers.read_r16(Reg16::PC);
+                    line("debug-back", &[&hex(pc)]) + &RegisterDump::capture(cpu, mem).to_string()
+                }
+                false => line("debug-no-history", &[]),
+            },
+            DebugCommand::LastWrite(address) => match history.last_write(address) {
+                Some(write) => line(
+                    "debug-last-write",
+                    &[
+                        &hex(address),
+                        &format!("{:02X}", write.old_value),
+                        &format!("{:02X}", write.new_value),
+                        &hex(write.pc),
+                        &(history.position() - write.position),
+                    ],
+                ),
+                None => line("debug-no-write", &[&hex(address)]),
+            },
+            DebugCommand::Search => {
+                let started = search.insert(RamSearch::new(mem));
+                line("debug-searching", &[&started.len()])
+            }
+            DebugCommand::Filter(condition) => match search.as_mut() {
+                Some(search) => line("debug-candidates-left", &[&search.filter(mem, condition)]),
+                None => line("debug-no-search", &[]),
+            },
+            DebugCommand::Candidates { limit } => match search.as_ref() {
+                Some(search) => CandidateList { search, limit }.describe(catalog),
+                None => line("debug-no-search", &[]),
+            },
+            DebugCommand::Interrupts => InterruptReport::capture(cpu, mem).to_string(),
+            DebugCommand::Map => MemoryMap::capture(mem).to_string(),
+            DebugCommand::MbcLog => MbcLog::capture(mem).to_string(),
+            DebugCommand::CartRam { bank } | DebugCommand::CartPoke { bank, .. }
+                if bank >= mem.external_ram_banks() =>
+            {
+                line("debug-no-cartram-bank", &[&bank, &mem.external_ram_banks()])
+            }
+            DebugCommand::CartRam { bank } => match CartRamView::capture(mem, bank) {
+                Ok(view) => view.to_string(),
+                Err(err) => format!("{}\n", err),
+            },
+            DebugCommand::CartPoke {
+                bank,
+                offset,
+                value,
+            } => match mem.write_external_ram(bank, offset, &[value]) {
+                Ok(()) => line(
+                    "debug-cartpoke",
+                    &[
+                        &format!("{:02X}", value),
+                        &hex(0xA000 + offset as u16),
+                        &bank,
+                    ],
+                ),
+                Err(err) => format!("{}\n", err),
+            },
+            DebugCommand::Timer => TimerReport::capture(mem).to_string(),
+            DebugCommand::Rtc
+            | DebugCommand::RtcSet(_)
+            | DebugCommand::RtcAdjust(_)
+            | DebugCommand::RtcHalt(_) => match mem.rtc_mut() {
+                Some(rtc) => {
+                    match *self {
+                        DebugCommand::RtcSet(time) => rtc.set(time),
+                        DebugCommand::RtcAdjust(seconds) => rtc.adjust(seconds),
+                        DebugCommand::RtcHalt(halted) => rtc.set_halted(halted),
+                        _ => {}
+                    }
+                    match rtc.halted() {
+                        true => line("debug-rtc-halted", &[&rtc.time()]),
+                        false => line("debug-rtc", &[&rtc.time()]),
+                    }
+                }
+                None => line("debug-no-rtc", &[]),
+            },
+            DebugCommand::Resume => String::new(),
+            DebugCommand::Help => catalog.get("debugger-help").to_string(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::i18n::Language;
+    use crate::memory::MBC_LOG_CAPACITY;
+
+    #[test]
+    fn test_interrupt_report_decodes_ie_and_if() {
+        let mut mem = Memory::new(vec![0; 0x8000]);
+        let cpu = Cpu::new();
+        mem.write_8(0xFFFF, 0x05); // VBlank + Timer enabled
+        mem.write_8(0xFF0F, 0x04); // Timer requested
+        mem.ppu.vblank_interrupt = true;
+
+        let report = InterruptReport::capture(&cpu, &mem);
+        assert!(!report.ime);
+        assert!(report.lines[0].enabled && report.lines[0].source_pending);
+        assert!(report.lines[2].enabled && report.lines[2].requested);
+        assert!(!report.lines[1].enabled && !report.lines[1].requested);
+
+        let text = report.to_string();
+        assert!(text.contains("VBlank:   enabled, pending (not yet in IF)"));
+        assert!(text.contains("Timer:    enabled, pending"));
+        assert!(text.contains("LCD STAT: disabled, not pending"));
+        assert!(text.contains("IME is off"));
+    }
+
+    fn rom_with_header(cartridge_type: u8, rom_size: u8, ram_size: u8) -> Vec<u8> {
+        let mut rom = vec![0; 0x8000];
+        rom[0x0134..0x0139].copy_from_slice(b"TETRA");
+        rom[0x0147] = cartridge_type;
+        rom[0x0148] = rom_size;
+        rom[0x0149] = ram_size;
+        rom
+    }
+
+    #[test]
+    fn test_memory_map_describes_cartridge() {
+        let mut rom = rom_with_header(0x03, 0x02, 0x02);
+        rom.resize(0x20000, 0);
+        let mut mem = Memory::new(rom);
+        mem.write_8(0x2000, 0x05);
+        let map = MemoryMap::capture(&mem);
+        assert_eq!(map.header.title, "TETRA");
+        assert_eq!(map.rom_bank, 5);
+
+        let text = map.to_string();
+        assert!(text.contains("Mapper:  MBC1+RAM+BATTERY (0x03)\n"));
+        assert!(text.contains("ROM:     128 KiB, 8 banks"));
+        assert!(text.contains("RAM:     8 KiB, 1 bank(s), battery-backed"));
+        assert!(text.contains("0000-3FFF  ROM bank 0 (banking mode 0)\n"));
+        assert!(text.contains("4000-7FFF  ROM bank 5 (switchable 1-7 via 2000-3FFF)\n"));
+        assert!(text
+            .contains("A000-BFFF  Cartridge RAM bank 0 of 1, disabled (enable via 0000-1FFF)\n"));
+
+        mem.write_8(0x0000, 0x0A);
+        let text = MemoryMap::capture(&mem).to_string();
+        assert!(text.contains("A000-BFFF  Cartridge RAM bank 0 of 1, enabled\n"));
+
+        let text = MemoryMap::capture(&Memory::new(vec![0; 0x8000])).to_string();
+        assert!(text.contains("4000-7FFF  ROM bank 1 (no mapper)\n"));
+        assert!(text.contains("A000-BFFF  Cartridge RAM, 1 bank, always enabled\n"));
+    }
+
+    #[test]
+    fn test_mbc_log_shows_resulting_banks() {
+        let mut rom = rom_with_header(0x01, 0x05, 0x03);
+        rom.resize(0x100000, 0);
+        let mut mem = Memory::new(rom);
+        assert_eq!(MbcLog::capture(&mem).to_string(), "MBC log is off\n");
+
+        mem.set_mbc_log_enabled(true);
+        mem.write_8(0x2000, 0x23);
+        mem.write_8(0x3FFF, 0x00);
+        mem.write_8(0x0000, 0x0A);
+        mem.write_8(0x4000, 0x05);
+        mem.write_8(0x6000, 0x01);
+        let log = MbcLog::capture(&mem);
+        let writes = log.writes.as_ref().unwrap();
+        assert_eq!(writes.len(), 5);
+        assert_eq!((writes[0].address, writes[0].value), (0x2000, 0x23));
+        assert_eq!(writes[0].rom_bank, 3);
+        assert_eq!(writes[1].rom_bank, 1);
+
+        let text = log.to_string();
+        assert!(text.starts_with("MBC writes: 5, ROM bank now 33\n"));
+        assert!(text.contains("2000 <- 23  ROM bank 3 (bits 20 masked off)\n"));
+        assert!(text.contains("3FFF <- 00  ROM bank 1 (bank 0 selects 1)\n"));
+        assert!(text.contains("0000 <- 0A  RAM enabled\n"));
+        assert!(text.contains("4000 <- 05  ROM bank 33, RAM bank 0 (bits 04 masked off)\n"));
+        assert!(text.contains("6000 <- 01  banking mode 1, RAM bank 1\n"));
+
+        let mut mem = Memory::new(vec![0; 0x8000]);
+        mem.set_mbc_log_enabled(true);
+        mem.write_8(0x2000, 0x02);
+        let text = MbcLog::capture(&mem).to_string();
+        assert!(text.contains("2000 <- 02  ignored (no mapper)\n"));
+    }
+
+    #[test]
+    fn test_mbc5_map_and_log() {
+        // 8 MiB ROM, 32 KiB RAM
+        let mut rom = rom_with_header(0x1B, 0x08, 0x03);
+        rom.resize(0x800000, 0);
+        let mut mem = Memory::new(rom);
+        mem.set_mbc_log_enabled(true);
+        mem.write_8(0x2000, 0x00);
+        mem.write_8(0x3000, 0x01);
+        mem.write_8(0x4000, 0x12);
+        mem.write_8(0x6000, 0x01);
+
+        let text = MemoryMap::capture(&mem).to_string();
+        assert!(text.contains("0000-3FFF  ROM bank 0\n"));
+        assert!(
+            text.contains("4000-7FFF  ROM bank 256 (switchable 0-511 via 2000-2FFF, 3000-3FFF)\n")
+        );
+
+        let text = MbcLog::capture(&mem).to_string();
+        assert!(text.contains("2000 <- 00  ROM bank 0\n"));
+        assert!(text.contains("3000 <- 01  ROM bank 256 (bit 8)\n"));
+        assert!(text.contains("4000 <- 12  RAM bank 2 (bits 10 masked off)\n"));
+        assert!(text.contains("6000 <- 01  ignored (no register)\n"));
+    }
+
+    #[test]
+    fn test_mbc_log_is_bounded() {
+        let mut mem = Memory::new(vec![0; 0x8000]);
+        mem.set_mbc_log_enabled(true);
+        for i in 0..MBC_LOG_CAPACITY + 10 {
+            mem.write_8(0x2000, i as u8);
+        }
+        let writes: Vec<_> = mem.mbc_writes().collect();
+        assert_eq!(writes.len(), MBC_LOG_CAPACITY);
+        assert_eq!(writes[0].value, 10);
+    }
+
+    #[test]
+    fn test_cart_ram_view_and_edit() {
+        let mut mem = Memory::new(vec![0; 0x8000]);
+        mem.write_external_ram(0, 0x10, b"SAVE").unwrap();
+        assert!(mem.take_external_ram_dirty());
+        assert_eq!(mem.read_8(0xA011), b'A');
+        assert!(mem
+            .write_external_ram(0, EXTERNAL_RAM_SIZE - 1, &[1, 2])
+            .is_err());
+        assert!(mem.write_external_ram(1, 0, &[1]).is_err());
+        assert!(CartRamView::capture(&mem, 1).is_err());
+
+        let text = CartRamView::capture(&mem, 0).unwrap().to_string();
+        let lines: Vec<_> = text.lines().collect();
+        assert_eq!(lines[0], "Cartridge RAM bank 0 of 1, enabled");
+        assert!(lines[1].starts_with("A000  00 00"));
+        assert_eq!(
+            lines[2],
+            "A010  53 41 56 45 00 00 00 00 00 00 00 00 00 00 00 00  SAVE............"
+        );
+        assert!(lines[3].starts_with("A020 "));
+        assert_eq!(lines[4], "*");
+        assert_eq!(lines.len(), 5);
+    }
+
+    #[test]
+    fn test_memory_map_flags_emulation_limits() {
+        // MBC7 with a 1 MiB ROM and 128 KiB RAM
+        let mem = Memory::new(rom_with_header(0x22, 0x05, 0x04));
+        let text = MemoryMap::capture(&mem).to_string();
+        assert!(text.contains("MBC7+SENSOR+RUMBLE+RAM+BATTERY (0x22), NOT emulated"));
+        let last = mem.mappable_rom_banks();
+        assert!(text.contains(&format!("banks {}+ read 0xFF", last)));
+        assert!(text.contains("(8 KiB of 128 KiB mapped)"));
+    }
+
+    #[test]
+    fn test_memory_map_shows_mbc3_clock() {
+        let mut mem = Memory::new(rom_with_header(0x10, 0x00, 0x03));
+        mem.write_8(0x0000, 0x0A);
+        mem.write_8(0x4000, 0x0A);
+        mem.write_8(0xA000, 5); // Hours
+        let text = MemoryMap::capture(&mem).to_string();
+        assert!(text.contains("RTC:     yes, 0:05:00:00\n"));
+        assert!(text.contains("0000-3FFF  ROM bank 0\n"));
+        assert!(text.contains("A000-BFFF  RTC register 0A (hours), enabled\n"));
+
+        mem.set_mbc_log_enabled(true);
+        for (address, value) in [
+            (0x2000, 0x80),
+            (0x4000, 0x0C),
+            (0x4000, 0x03),
+            (0x6000, 0x01),
+        ] {
+            mem.write_8(address, value);
+        }
+        let text = MbcLog::capture(&mem).to_string();
+        assert!(text.contains("2000 <- 80  ROM bank 1 (bank 0 selects 1) (bits 80 masked off)\n"));
+        assert!(text.contains("4000 <- 0C  RTC register (day high/flags)\n"));
+        assert!(text.contains("4000 <- 03  RAM bank 3\n"));
+        assert!(text.contains("6000 <- 01  RTC latch\n"));
+    }
+
+    #[test]
+    fn test_timer_report_counts_down_to_increment() {
+        let mut mem = Memory::new(vec![0; 0x8000]);
+        mem.timer.set_internal_counter(0x1234);
+        mem.write_8(0xFF06, 0xC0);
+        mem.write_8(0xFF07, 0x05); // running, divider bit 3 (262144 Hz)
+
+        let report = TimerReport::capture(&mem);
+        assert_eq!(report.selected_bit, 3);
+        assert_eq!(report.frequency(), 262144);
+        assert_eq!(report.cycles_until_increment, Some(12));
+        assert_eq!(report.reload_in, None);
+
+        // Stepping exactly that many cycles increments TIMA
+        mem.timer.tick(3);
+        assert_eq!(mem.read_8(0xFF05), 1);
+        assert_eq!(TimerReport::capture(&mem).cycles_until_increment, Some(16));
+
+        mem.write_8(0xFF05, 0xFF);
+        mem.timer.tick(4);
+        let report = TimerReport::capture(&mem);
+        assert_eq!(report.reload_in, Some(4));
+        let text = report.to_string();
+        assert!(text.contains("Divider: 0x1250 (DIV 0x12)\n"));
+        assert!(text.contains("TAC: 0x05 (running, 262144 Hz, divider bit 3)"));
+        assert!(text.contains("TMA reload and interrupt in 4 cycles"));
+        assert!(text.contains("Overflow period: 64 increments = 4096.00 Hz"));
+
+        mem.write_8(0xFF07, 0x00);
+        assert!(TimerReport::capture(&mem)
+            .to_string()
+            .contains("never (timer stopped)"));
+    }
+    #[test]
+    fn test_step_frame_stamps_hits_with_ppu_position() {
+        let rom = crate::testrom::assemble(
+            "
+            org $0100
+            wait:
+                ldh a, ($44)
+                cp $40
+                jr nz, wait
+                ld a, $55       ; $0106
+                ld ($C000), a
+            done:
+                jr done
+            ",
+        );
+        let mut mem = Memory::new(rom);
+        mem.skip_boot(crate::model::Model::Dmg);
+        let mut cpu = Cpu::new();
+        cpu.skip_boot(crate::model::Model::Dmg, 0);
+        let mut breakpoints = Breakpoints::default();
+        breakpoints.pc.insert(0x0106);
+        breakpoints.watch.insert(0xC000);
+
+        let step = step_frame(&mut cpu, &mut mem, &breakpoints);
+        assert!(step.cycles as u64 >= FRAME_CYCLES);
+        assert_eq!(step.hits.len(), 2);
+        let (hit, write) = (step.hits[0], step.hits[1]);
+        assert_eq!(
+            (hit.kind, hit.pc, hit.ly),
+            (HitKind::Breakpoint, 0x0106, 0x40)
+        );
+        assert_eq!(
+            write.kind,
+            HitKind::Watchpoint {
+                address: 0xC000,
+                old: 0x00,
+                new: 0x55
+            }
+        );
+        assert_eq!(write.ly, 0x40);
+        // LD A,n then LD (nn),A: 6 M-cycles later on the same line
+        assert_eq!(write.cycle - hit.cycle, 24);
+        assert_eq!(write.dot, hit.dot + 24);
+
+        let text = step.to_string();
+        assert!(text.contains(&format!("LY  64 dot {:>3}", hit.dot)));
+        assert!(text.contains("PC 0108  watch C000: 00 -> 55\n"));
+        assert!(step_frame(&mut cpu, &mut mem, &breakpoints).hits.is_empty());
+    }
+
+    fn boot(source: &str) -> (Cpu, Memory) {
+        let mut mem = Memory::new(crate::testrom::assemble(source));
+        mem.skip_boot(crate::model::Model::Dmg);
+        let mut cpu = Cpu::new();
+        cpu.skip_boot(crate::model::Model::Dmg, 0);
+        (cpu, mem)
+    }
+
+    #[test]
+    fn test_prompt_commands() {
+        let (mut cpu, mut mem) = boot(
+            "
+            org $0100
+                call nz, sub    ; Z is set after boot, so not taken
+                rst $38
+                ldh a, ($80)    ; $0104
+            done:
+                jr done
+            org $0038
+            sub:
+                ret
+            ",
+        );
+        mem.write_8(0xFF80, 0x42);
+        let mut session = DebugSession::default();
+        let mut run = |line: &str, cpu: &mut Cpu, mem: &mut Memory| {
+            line.parse::<DebugCommand>().unwrap().execute(
+                cpu,
+                mem,
+                &mut session,
+                &Catalog::default(),
+            )
+        };
+
+        let text = run("r", &mut cpu, &mut mem);
+        assert!(text.contains("AF 0180  BC 0013  DE 00D8  HL 014D  SP FFFE  PC 0100\n"));
+        assert!(text.contains("Flags Z---  IME off\n"));
+        assert!(text.contains("0100: C4 38 00  call nz, $0038\n"));
+
+        assert!(run("n", &mut cpu, &mut mem).contains("Stopped at 0103 after 12 cycles"));
+        // RST, then RET
+        assert!(run("next", &mut cpu, &mut mem).contains("Stopped at 0104 after 32 cycles"));
+        assert_eq!(cpu.registers.read_r16(Reg16::SP), 0xFFFE);
+
+        run("rw $FF80", &mut cpu, &mut mem);
+        run("b 0106", &mut cpu, &mut mem);
+        assert_eq!(
+            run("l", &mut cpu, &mut mem),
+            "Breakpoints: 0106\nRead watch: FF80\n"
+        );
+        assert!(
+            run("s", &mut cpu, &mut mem).contains("Hit at 0106 after 12 cycles\n  read FF80: 42\n")
+        );
+        // The loop at 0106 comes straight back to its breakpoint
+        assert!(
+            run("c", &mut cpu, &mut mem).contains("Hit at 0106 after 12 cycles\n  breakpoint\n")
+        );
+        assert!(run("d 106", &mut cpu, &mut mem).contains("Deleted"));
+        assert!(run("c 1", &mut cpu, &mut mem).contains("Cycle limit reached at 0106"));
+        run("clear", &mut cpu, &mut mem);
+        assert_eq!(run("x c000 18", &mut cpu, &mut mem).lines().count(), 2);
+
+        assert_eq!("g".parse(), Ok(DebugCommand::Resume));
+        assert!("b".parse::<DebugCommand>().is_err());
+        assert!("b 10000".parse::<DebugCommand>().is_err());
+        assert!("s 1".parse::<DebugCommand>().is_err());
+        assert!("jump".parse::<DebugCommand>().is_err());
+    }
+
+    #[test]
+    fn test_step_back_and_last_write_commands() {
+        let (mut cpu, mut mem) = boot(
+            "
+            org $0100
+                ld a, $05
+                ld ($C000), a   ; $0102
+                inc a
+                ld ($C000), a   ; $0106
+            done:
+                jr done
+            ",
+        );
+        let mut session = DebugSession::default();
+        let mut run = |line: &str, cpu: &mut Cpu, mem: &mut Memory| {
+            line.parse::<DebugCommand>().unwrap().execute(
+                cpu,
+                mem,
+                &mut session,
+                &Catalog::default(),
+            )
+        };
+
+        assert!(run("back", &mut cpu, &mut mem).contains("No earlier instruction"));
+        for _ in 0..4 {
+            run("s", &mut cpu, &mut mem);
+        }
+        assert_eq!(mem.read_8(0xC000), 0x06);
+        assert_eq!(
+            run("lw c000", &mut cpu, &mut mem),
+            "C000 changed from 05 to 06 by the instruction at 0106, 1 instructions ago\n"
+        );
+
+        assert!(run("bs", &mut cpu, &mut mem).starts_with("Back at 0106\n"));
+        assert_eq!(mem.read_8(0xC000), 0x05);
+        assert!(run("lw c000", &mut cpu, &mut mem).contains("by the instruction at 0102"));
+        assert!(run("lw c001", &mut cpu, &mut mem).contains("No change to C001"));
+    }
+
+    #[test]
+    fn test_ram_search_commands() {
+        let (mut cpu, mut mem) = boot(
+            "
+            org $0100
+                ld a, $03
+                ld ($C123), a
+                dec a
+                ld ($C123), a
+            done:
+                jr done
+            ",
+        );
+        let mut session = DebugSession::default();
+        let mut run = |line: &str, cpu: &mut Cpu, mem: &mut Memory| {
+            line.parse::<DebugCommand>().unwrap().execute(
+                cpu,
+                mem,
+                &mut session,
+                &Catalog::default(),
+            )
+        };
+
+        assert!(run("filter eq 3", &mut cpu, &mut mem).contains("No RAM search started"));
+        assert_eq!(run("search", &mut cpu, &mut mem), "Searching 16511 bytes\n");
+        run("s", &mut cpu, &mut mem);
+        run("s", &mut cpu, &mut mem);
+        // Another byte holds 3 after boot, but doesn't go down
+        assert_eq!(
+            run("filter eq 3", &mut cpu, &mut mem),
+            "2 candidates left\n"
+        );
+        run("s", &mut cpu, &mut mem);
+        run("s", &mut cpu, &mut mem);
+        assert_eq!(run("filter dec", &mut cpu, &mut mem), "1 candidates left\n");
+        assert_eq!(
+            run("candidates", &mut cpu, &mut mem),
+            "1 candidates after 2 searches\n  C123: 02 (2)\n"
+        );
+
+        assert_eq!(
+            "filter lt $10".parse(),
+            Ok(DebugCommand::Filter(SearchCondition::Less(0x10)))
+        );
+        assert!("filter".parse::<DebugCommand>().is_err());
+        assert!("candidates x".parse::<DebugCommand>().is_err());
+    }
+
+    #[test]
+    fn test_view_commands() {
+        let mut rom = vec![0; 0x8000];
+        rom[0x0147] = 0x10; // MBC3 + timer + RAM + battery
+        rom[0x0149] = 0x03; // 32 KiB of RAM
+        let mut mem = Memory::new(rom);
+        let mut cpu = Cpu::new();
+        let mut session = DebugSession::default();
+        let mut run = |line: &str| {
+            line.parse::<DebugCommand>().unwrap().execute(
+                &mut cpu,
+                &mut mem,
+                &mut session,
+                &Catalog::new(Language::Italian),
+            )
+        };
+
+        assert!(run("help").contains("riprende il gioco"));
+        for view in ["irq", "map", "mbc", "cartram", "timer"] {
+            assert!(!run(view).is_empty(), "{}", view);
+        }
+        assert_eq!(run("rtc"), "RTC 0:00:00:00\n");
+        assert_eq!(run("rtc set 3:04:05:06"), "RTC 3:04:05:06\n");
+        assert_eq!(run("rtc adjust -3600"), "RTC 3:03:05:06\n");
+        assert_eq!(run("rtc adjust +86400"), "RTC 4:03:05:06\n");
+        assert_eq!(run("rtc halt"), "RTC 4:03:05:06, fermo\n");
+        assert_eq!(run("rtc run"), "RTC 4:03:05:06\n");
+        assert!("rtc set 1:24:00:00".parse::<DebugCommand>().is_err());
+        assert!("rtc adjust".parse::<DebugCommand>().is_err());
+        assert!("rtc stop".parse::<DebugCommand>().is_err());
+
+        assert_eq!(
+            run("cartpoke 3 a010 5a"),
+            "Scritto 5A in A010 nel banco 3 della RAM della cartuccia\n"
+        );
+        assert!(run("cartram 3").contains("A010  5A 00"));
+        assert_eq!(
+            run("cartram 4"),
+            "nessun banco 4 nella RAM della cartuccia (4 banchi)\n"
+        );
+        assert!(run("cartpoke 4 a000 0").contains("nessun banco 4"));
+        assert!("cartpoke 0 c000 0".parse::<DebugCommand>().is_err());
+        assert!("cartpoke 0 a000 100".parse::<DebugCommand>().is_err());
+        assert!("cartram x".parse::<DebugCommand>().is_err());
+
+        let italian = Catalog::new(Language::Italian);
+        assert_eq!(
+            DebugCommand::parse("frob", &italian),
+            Err("comando sconosciuto 'frob' (prova help)".to_string())
+        );
+        assert_eq!(
+            DebugCommand::parse("cartpoke 0 c000 0", &italian),
+            Err("C000 non fa parte della RAM della cartuccia (A000-BFFF)".to_string())
+        );
+    }
+}
@@ -0,0 +1,116 @@
+// State deltas
+// Successive save states are nearly identical: a frame changes some work RAM, the
+// registers and part of the framebuffer, and leaves the rest alone. A delta stores the
+// XOR of two states, where every unchanged byte is zero, run-length encoded: the target
+// length, then (zero run, literal count) pairs each followed by its literal XOR bytes, all
+// counts as LEB128. Bytes past the end of the base count as zero, so states of different
+// sizes work too. Rewind keeps its history this way, and a state sync between two
+// machines only needs to send the delta from a state both sides already have.
+
+/// Longest target `decode` accepts, far above any save state, so that a corrupt or hostile
+/// delta can't make it allocate without bound
+pub const MAX_TARGET_LEN: usize = 64 << 20;
+
+/// Delta that turns `base` into `target`
+pub fn encode(base: &[u8], target: &[u8]) -> Vec<u8> {
+    let xor = |i: usize| target[i] ^ base.get(i).copied().unwrap_or(0);
+    let mut delta = Vec::new();
+    write_count(&mut delta, target.len());
+    let mut i = 0;
+    while i < target.len() {
+        let zeros_start = i;
+        while i < target.len() && xor(i) == 0 {
+            i += 1;
+        }
+        let literal_start = i;
+        // A lone zero between changed bytes costs less as a literal than as a new pair
+        while i < target.len() && (xor(i) != 0 || (i + 1 < target.len() && xor(i + 1) != 0)) {
+            i += 1;
+        }
+        write_count(&mut delta, literal_start - zeros_start);
+        write_count(&mut delta, i - literal_start);
+        delta.extend((literal_start..i).map(xor));
+    }
+    delta
+}
+
+/// Rebuild the `target` a delta from `encode` was made for, from the same `base`
+pub fn decode(base: &[u8], delta: &[u8]) -> Result<Vec<u8>, String> {
+    const CORRUPT: &str = "corrupt state delta";
+    let mut rest = delta;
+    let len = read_count(&mut rest)
+        .filter(|&len| len <= MAX_TARGET_LEN)
+        .ok_or(CORRUPT)?;
+    // Only what the delta can back up front: zero runs past the base cost it next to nothing
+    let mut target = Vec::with_capacity(len.min(base.len() + rest.len()));
+    let base_byte = |i: usize| base.get(i).copied().unwrap_or(0);
+    while target.len() < len {
+        let zeros = read_count(&mut rest).ok_or(CORRUPT)?;
+        let literals = read_count(&mut rest).ok_or(CORRUPT)?;
+        let end = zeros
+            .checked_add(literals)
+            .and_then(|run| run.checked_add(target.len()));
+        if (zeros, literals) == (0, 0) || end.is_none_or(|end| end > len) || literals > rest.len() {
+            return Err(CORRUPT.to_string());
+        }
+        for _ in 0..zeros {
+            target.push(base_byte(target.len()));
+        }
+        let (bytes, tail) = rest.split_at(literals);
+        for &byte in bytes {
+            target.push(byte ^ base_byte(target.len()));
+        }
+        rest = tail;
+    }
+    Ok(target)
+}
+
+fn write_count(out: &mut Vec<u8>, mut count: usize) {
+    while count >= 0x80 {
+        out.push(count as u8 | 0x80);
+        count >>= 7;
+    }
+    out.push(count as u8);
+}
+
+fn read_count(data: &mut &[u8]) -> Option<usize> {
+    let mut count = 0usize;
+    for shift in (0..usize::BITS).step_by(7) {
+        let (&byte, rest) = data.split_first()?;
+        *data = rest;
+        count |= ((byte & 0x7F) as usize) << shift;
+        if byte & 0x80 == 0 {
+            return Some(count);
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_delta_round_trip() {
+        let base: Vec<u8> = (0..1000).map(|i| (i * 7) as u8).collect();
+        let mut target = base.clone();
+        target[3] ^= 0xFF;
+        target[5] = 0;
+        target[900..].fill(0x55);
+        target.extend_from_slice(&[1, 2, 3]);
+
+        let delta = encode(&base, &target);
+        assert!(delta.len() < 140);
+        assert_eq!(decode(&base, &delta), Ok(target.clone()));
+        assert_eq!(decode(&target, &encode(&target, &base)), Ok(base));
+        assert!(decode(&target, &delta[..delta.len() - 1]).is_err());
+
+        // Lengths and runs too long to be real
+        let huge = [0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0x7F];
+        assert!(decode(&[], &huge).is_err());
+        let mut overflowing = vec![0x10];
+        overflowing.extend_from_slice(&huge);
+        overflowing.extend_from_slice(&huge);
+        assert!(decode(&target, &overflowing).is_err());
+    }
+}
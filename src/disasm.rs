@@ -0,0 +1,465 @@
+// SM83 disassembler
+// Decodes instructions in the syntax the test ROM assembler reads (`ld a, (hl+)`,
+// `ldh ($44), a`, `jr nz, $4010`), so a listing of plain code assembles back to the same
+// bytes. Listings name addresses after an RGBDS-style .sym file when one is given, and
+// make up labels for jump and call targets inside the listed range.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::{self, Write};
+use std::str::FromStr;
+
+use crate::mbc::ROM_BANK_SIZE;
+
+const REGISTERS: [&str; 8] = ["b", "c", "d", "e", "h", "l", "(hl)", "a"];
+const PAIRS: [&str; 4] = ["bc", "de", "hl", "sp"];
+const STACK_PAIRS: [&str; 4] = ["bc", "de", "hl", "af"];
+const CONDITIONS: [&str; 4] = ["nz", "z", "nc", "c"];
+const ALU: [&str; 8] = [
+    "add a,", "adc a,", "sub", "sbc a,", "and", "xor", "or", "cp",
+];
+const CB_SHIFTS: [&str; 8] = ["rlc", "rrc", "rl", "rr", "sla", "sra", "swap", "srl"];
+const ACCUMULATOR_OPS: [&str; 8] = ["rlca", "rrca", "rla", "rra", "daa", "cpl", "scf", "ccf"];
+
+/// One decoded instruction
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Instruction {
+    pub address: u16,
+    pub bytes: Vec<u8>,
+    /// Assembly text, with addresses written as `$XXXX`
+    pub text: String,
+    /// 16-bit address operand: a jump or call target, or a memory operand
+    pub operand_address: Option<u16>,
+    /// Whether execution may continue at `operand_address` (jp, jr, call)
+    pub branch: bool,
+}
+
+impl Instruction {
+    pub fn len(&self) -> usize {
+        self.bytes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.bytes.is_empty()
+    }
+}
+
+/// The assembly text, e.g. `ld a, ($C0A0)`
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.text)
+    }
+}
+
+/// Decode the instruction at the start of `bytes`, located at `address`. Unused opcodes,
+/// and instructions cut short by the end of `bytes`, decode as a one-byte `db`.
+pub fn decode(bytes: &[u8], address: u16) -> Instruction {
+    let opcode = bytes.first().copied().unwrap_or(0xFF);
+    let (len, text, operand_address, branch) =
+        decode_operands(bytes, address).unwrap_or((1, format!("db ${:02X}", opcode), None, false));
+    Instruction {
+        address,
+        bytes: bytes[..len.min(bytes.len())].to_vec(),
+        text,
+        operand_address,
+        branch,
+    }
+}
+
+// (length, text, address operand, branch); None for undefined or truncated instructions
+fn decode_operands(bytes: &[u8], address: u16) -> Option<(usize, String, Option<u16>, bool)> {
+    let opcode = *bytes.first()?;
+    let n8 = || bytes.get(1).copied();
+    let n16 = || Some(u16::from_le_bytes([*bytes.get(1)?, *bytes.get(2)?]));
+    let relative = || {
+        let offset = n8()? as i8;
+        Some(address.wrapping_add(2).wrapping_add(offset as u16))
+    };
+    let simple = |text: &str| Some((1, text.to_string(), None, false));
+
+    let x = opcode >> 6;
+    let y = (opcode >> 3) & 0x07;
+    let z = opcode & 0x07;
+    let r = |index: u8| REGISTERS[index as usize];
+    let rr = PAIRS[(y >> 1) as usize];
+    let cc = CONDITIONS[(y & 0x03) as usize];
+
+    match (x, z) {
+        _ if opcode == 0xCB => {
+            let op = n8()?;
+            let (y, z) = ((op >> 3) & 0x07, r(op & 0x07));
+            let text = match op >> 6 {
+                0 => format!("{} {}", CB_SHIFTS[y as usize], z),
+                1 => format!("bit {}, {}", y, z),
+                2 => format!("res {}, {}", y, z),
+                _ => format!("set {}, {}", y, z),
+            };
+            Some((2, text, None, false))
+        }
+        (0, 0) => match y {
+            0 => simple("nop"),
+            1 => {
+                let target = n16()?;
+                Some((3, format!("ld (${:04X}), sp", target), Some(target), false))
+            }
+            2 => Some((2, "stop".to_string(), None, false)),
+            3 => {
+                let target = relative()?;
+                Some((2, format!("jr ${:04X}", target), Some(target), true))
+            }
+            _ => {
+                let target = relative()?;
+                let cc = CONDITIONS[(y - 4) as usize];
+                Some((2, format!("jr {}, ${:04X}", cc, target), Some(target), true))
+            }
+        },
+        (0, 1) if y & 1 == 0 => Some((3, format!("ld {}, ${:04X}", rr, n16()?), None, false)),
+        (0, 1) => simple(&format!("add hl, {}", rr)),
+        (0, 2) => {
+            let pointer = ["(bc)", "(de)", "(hl+)", "(hl-)"][(y >> 1) as usize];
+            if y & 1 == 0 {
+                simple(&format!("ld {}, a", pointer))
+            } else {
+                simple(&format!("ld a, {}", pointer))
+            }
+        }
+        (0, 3) if y & 1 == 0 => simple(&format!("inc {}", rr)),
+        (0, 3) => simple(&format!("dec {}", rr)),
+        (0, 4) => simple(&format!("inc {}", r(y))),
+        (0, 5) => simple(&format!("dec {}", r(y))),
+        (0, 6) => Some((2, format!("ld {}, ${:02X}", r(y), n8()?), None, false)),
+        (0, _) => simple(ACCUMULATOR_OPS[y as usize]),
+        (1, _) if opcode == 0x76 => simple("halt"),
+        (1, _) => simple(&format!("ld {}, {}", r(y), r(z))),
+        (2, _) => simple(&format!("{} {}", ALU[y as usize], r(z))),
+        (_, 0) => match y {
+            0..=3 => simple(&format!("ret {}", cc)),
+            4 => Some((2, format!("ldh (${:02X}), a", n8()?), None, false)),
+            5 => Some((2, format!("add sp, {}", n8()? as i8), None, false)),
+            6 => Some((2, format!("ldh a, (${:02X})", n8()?), None, false)),
+            _ => Some((2, format!("ld hl, sp{:+}", n8()? as i8), None, false)),
+        },
+        (_, 1) => match y {
+            1 => simple("ret"),
+            3 => simple("reti"),
+            5 => simple("jp hl"),
+            7 => simple("ld sp, hl"),
+            _ => simple(&format!("pop {}", STACK_PAIRS[(y >> 1) as usize])),
+        },
+        (_, 2) => match y {
+            0..=3 => {
+                let target = n16()?;
+                Some((3, format!("jp {}, ${:04X}", cc, target), Some(target), true))
+            }
+            4 => simple("ld (c), a"),
+            6 => simple("ld a, (c)"),
+            5 => {
+                let target = n16()?;
+                Some((3, format!("ld (${:04X}), a", target), Some(target), false))
+            }
+            _ => {
+                let target = n16()?;
+                Some((3, format!("ld a, (${:04X})", target), Some(target), false))
+            }
+        },
+        (_, 3) => match y {
+            0 => {
+                let target = n16()?;
+                Some((3, format!("jp ${:04X}", target), Some(target), true))
+            }
+            6 => simple("di"),
+            7 => simple("ei"),
+            _ => None,
+        },
+        (_, 4) if y < 4 => {
+            let target = n16()?;
+            Some((
+                3,
+                format!("call {}, ${:04X}", cc, target),
+                Some(target),
+                true,
+            ))
+        }
+        (_, 5) if y & 1 == 0 => simple(&format!("push {}", STACK_PAIRS[(y >> 1) as usize])),
+        (_, 5) if y == 1 => {
+            let target = n16()?;
+            Some((3, format!("call ${:04X}", target), Some(target), true))
+        }
+        (_, 6) => Some((
+            2,
+            format!("{} ${:02X}", ALU[y as usize], n8()?),
+            None,
+            false,
+        )),
+        (_, 7) => simple(&format!("rst ${:02X}", y * 8)),
+        _ => None,
+    }
+}
+
+/// Symbols from an RGBDS-style .sym file: `BB:AAAA Name` lines, with `;` comments
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Symbols {
+    // (bank, address) -> name; the first name given for an address wins
+    names: BTreeMap<(u16, u16), String>,
+}
+
+impl Symbols {
+    /// Name of `address` as seen with ROM bank `bank` mapped at 0x4000-0x7FFF. Outside
+    /// the ROM, symbols from any bank match.
+    pub fn get(&self, bank: u16, address: u16) -> Option<&str> {
+        let name = match address {
+            0x0000..=0x3FFF => self.names.get(&(0, address)),
+            0x4000..=0x7FFF => self.names.get(&(bank, address)),
+            _ => self
+                .names
+                .iter()
+                .find(|((_, a), _)| *a == address)
+                .map(|(_, name)| name),
+        };
+        name.map(String::as_str)
+    }
+
+    pub fn len(&self) -> usize {
+        self.names.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.names.is_empty()
+    }
+}
+
+impl FromStr for Symbols {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut symbols = Symbols::default();
+        for (number, line) in s.lines().enumerate() {
+            let line = line.split(';').next().unwrap_or_default().trim();
+            if line.is_empty() {
+                continue;
+            }
+            let invalid = || format!("line {}: expected 'BB:AAAA Name': {}", number + 1, line);
+            let (location, name) = line.split_once(char::is_whitespace).ok_or_else(invalid)?;
+            let (bank, address) = location.split_once(':').ok_or_else(invalid)?;
+            let bank = u16::from_str_radix(bank, 16).map_err(|_| invalid())?;
+            let address = u16::from_str_radix(address, 16).map_err(|_| invalid())?;
+            symbols
+                .names
+                .entry((bank, address))
+                .or_insert_with(|| name.trim().to_string());
+        }
+        Ok(symbols)
+    }
+}
+
+/// Listing of `len` bytes of ROM from `start`, with `bank` mapped at 0x4000-0x7FFF.
+/// The range must stay within one of 0x0000-0x3FFF (bank 0) and 0x4000-0x7FFF.
+pub fn disassemble(
+    rom: &[u8],
+    bank: u16,
+    start: u16,
+    len: u16,
+    symbols: &Symbols,
+) -> Result<String, String> {
+    let end = start as usize + len as usize;
+    let area_end = if start < 0x4000 { 0x4000 } else { 0x8000 };
+    if len == 0 || end > area_end {
+        return Err(format!(
+            "range ${:04X}-${:04X} must be non-empty and within ${:04X}-${:04X}",
+            start,
+            end.saturating_sub(1),
+            area_end - ROM_BANK_SIZE,
+            area_end - 1
+        ));
+    }
+    let bank = if start < 0x4000 {
+        if bank != 0 {
+            return Err(format!("${:04X} is in bank 0, not bank {}", start, bank));
+        }
+        0
+    } else {
+        bank
+    };
+    let banks = rom.len().div_ceil(ROM_BANK_SIZE);
+    if bank as usize >= banks {
+        return Err(format!("no ROM bank {} ({} bank(s))", bank, banks));
+    }
+
+    // The whole bank is decoded from, so the last instruction can run past the range
+    let area_start = area_end - ROM_BANK_SIZE;
+    let bank_offset = bank as usize * ROM_BANK_SIZE;
+    let code = &rom[bank_offset..(bank_offset + ROM_BANK_SIZE).min(rom.len())];
+    let mut instructions = Vec::new();
+    let mut address = start as usize;
+    while address < end {
+        let offset = address - area_start;
+        let instruction = decode(code.get(offset..).unwrap_or_default(), address as u16);
+        address += instruction.len().max(1);
+        instructions.push(instruction);
+    }
+
+    // Branch targets inside the range get a label unless the .sym file names them
+    let targets: BTreeSet<u16> = instructions
+        .iter()
+        .filter(|instruction| instruction.branch)
+        .filter_map(|instruction| instruction.operand_address)
+        .filter(|&target| (start as usize..end).contains(&(target as usize)))
+        .collect();
+    let label = |address: u16| -> Option<String> {
+        match symbols.get(bank, address) {
+            Some(name) => Some(name.to_string()),
+            None => targets
+                .contains(&address)
+                .then(|| format!("L{:02X}_{:04X}", bank, address)),
+        }
+    };
+
+    let mut out = String::new();
+    let _ = writeln!(out, "; ROM bank {}, ${:04X}-${:04X}", bank, start, end - 1);
+    if start >= 0x4000 {
+        let _ = writeln!(out, "bank {}", bank);
+    }
+    let _ = writeln!(out, "org ${:04X}", start);
+    for instruction in &instructions {
+        if let Some(name) = label(instruction.address) {
+            let _ = writeln!(out, "{}:", name);
+        }
+        let mut text = instruction.text.clone();
+        if let Some(name) = instruction.operand_address.and_then(label) {
+            let operand = format!("${:04X}", instruction.operand_address.unwrap_or_default());
+            text = text.replacen(&operand, &name, 1);
+        }
+        let bytes: Vec<String> = instruction
+            .bytes
+            .iter()
+            .map(|byte| format!("{:02X}", byte))
+            .collect();
+        let _ = writeln!(
+            out,
+            "    {:<24}; {:04X}  {}",
+            text,
+            instruction.address,
+            bytes.join(" ")
+        );
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testrom::assemble;
+
+    fn text(bytes: &[u8], address: u16) -> String {
+        decode(bytes, address).text
+    }
+
+    #[test]
+    fn test_decode() {
+        assert_eq!(text(&[0x3E, 0x05], 0), "ld a, $05");
+        assert_eq!(text(&[0x2A], 0), "ld a, (hl+)");
+        assert_eq!(text(&[0xE0, 0x44], 0), "ldh ($44), a");
+        assert_eq!(text(&[0xFA, 0xA0, 0xC0], 0), "ld a, ($C0A0)");
+        assert_eq!(text(&[0x20, 0xFE], 0x4010), "jr nz, $4010");
+        assert_eq!(text(&[0xCD, 0x00, 0x40], 0), "call $4000");
+        assert_eq!(text(&[0xCB, 0x7C], 0), "bit 7, h");
+        assert_eq!(text(&[0xCB, 0x37], 0), "swap a");
+        assert_eq!(text(&[0x96], 0), "sub (hl)");
+        assert_eq!(text(&[0xCE, 0x01], 0), "adc a, $01");
+        assert_eq!(text(&[0xF8, 0xFD], 0), "ld hl, sp-3");
+        assert_eq!(text(&[0xFF], 0), "rst $38");
+        assert_eq!(text(&[0xD3], 0), "db $D3");
+        // Cut short by the end of the bytes
+        let truncated = decode(&[0xC3, 0x50], 0);
+        assert_eq!((truncated.text.as_str(), truncated.len()), ("db $C3", 1));
+
+        assert_eq!(decode(&[0x18, 0x00], 0x0150).to_string(), "jr $0152");
+
+        let call = decode(&[0xC4, 0x34, 0x12], 0);
+        assert_eq!(
+            (call.operand_address, call.branch, call.len()),
+            (Some(0x1234), true, 3)
+        );
+    }
+
+    #[test]
+    fn test_every_cb_opcode_decodes() {
+        for op in 0..=0xFFu8 {
+            let instruction = decode(&[0xCB, op], 0);
+            assert_eq!(instruction.len(), 2, "CB {:02X}", op);
+            assert!(!instruction.text.starts_with("db"), "CB {:02X}", op);
+        }
+        assert_eq!(text(&[0xCB, 0x00], 0), "rlc b");
+        assert_eq!(text(&[0xCB, 0x86], 0), "res 0, (hl)");
+        assert_eq!(text(&[0xCB, 0xFF], 0), "set 7, a");
+    }
+
+    #[test]
+    fn test_listing_reassembles() {
+        let source = "
+            bank 2
+            org $4000
+                ld hl, $C000
+                ld b, 16
+            loop:
+                ld a, (hl+)
+                cp $20
+                jr z, done
+                call $0150
+                dec b
+                jr nz, loop
+            done:
+                ldh ($80), a
+                ret
+        ";
+        let rom = assemble(source);
+        let listing = disassemble(&rom, 2, 0x4000, 0x14, &Symbols::default()).unwrap();
+        assert!(listing.starts_with("; ROM bank 2, $4000-$4013\nbank 2\norg $4000\n"));
+        assert!(listing.contains("L02_4005:\n    ld a, (hl+)             ; 4005  2A\n"));
+        assert!(listing.contains("    jr nz, L02_4005"));
+        assert!(listing.contains("    call $0150"));
+        assert_eq!(assemble(&listing), rom);
+    }
+
+    #[test]
+    fn test_symbols_name_addresses() {
+        let symbols: Symbols = "; File generated by rgblink
+            00:0150 Init
+            02:4000 Bank2Entry
+            03:4000 Bank3Entry
+            00:c0a0 wLives
+            01:d000 wBuffer"
+            .parse()
+            .unwrap();
+        assert_eq!(symbols.len(), 5);
+        assert_eq!(symbols.get(7, 0x0150), Some("Init"));
+        assert_eq!(symbols.get(3, 0x4000), Some("Bank3Entry"));
+        assert_eq!(symbols.get(1, 0x4000), None);
+        assert_eq!(symbols.get(1, 0xD000), Some("wBuffer"));
+
+        let rom = assemble(
+            "
+            bank 3
+            org $4000
+                call $0150
+                ld ($C0A0), a
+                jp $4000
+            ",
+        );
+        let listing = disassemble(&rom, 3, 0x4000, 9, &symbols).unwrap();
+        assert!(listing.contains("Bank3Entry:\n    call Init "));
+        assert!(listing.contains("    ld (wLives), a "));
+        assert!(listing.contains("    jp Bank3Entry "));
+
+        assert!("0150 Init".parse::<Symbols>().is_err());
+    }
+
+    #[test]
+    fn test_range_errors() {
+        let rom = vec![0; 0x10000];
+        let symbols = Symbols::default();
+        assert!(disassemble(&rom, 0, 0x3F00, 0x200, &symbols).is_err());
+        assert!(disassemble(&rom, 1, 0x0100, 0x10, &symbols).is_err());
+        assert!(disassemble(&rom, 4, 0x4000, 0x10, &symbols).is_err());
+        assert!(disassemble(&rom, 3, 0x7FF0, 0x10, &symbols).is_ok());
+    }
+}
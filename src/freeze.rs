@@ -0,0 +1,227 @@
+// Frozen RAM addresses (trainer-style RAM lock)
+// Rewrites fixed values into memory after every instruction or once per frame, so a game
+// variable found with the RAM search (lives, health, timer) stays at the chosen value.
+// Only RAM can be frozen: work RAM, high RAM and cartridge RAM. Values go straight into
+// the storage rather than through the bus, which for a mapper register or IO port would
+// switch banks, reset DIV or start a DMA every time. Cartridge RAM addresses refer to the
+// bank mapped at the time, and are left alone while the MBC3 clock is mapped instead.
+
+use std::ops::RangeInclusive;
+use std::str::FromStr;
+
+use crate::mbc::{Mbc, RamMapping};
+use crate::memory::Memory;
+
+/// Addresses that can be frozen
+pub const FREEZABLE: [RangeInclusive<u16>; 3] = [0xA000..=0xBFFF, 0xC000..=0xDFFF, 0xFF80..=0xFFFE];
+
+/// When frozen values are written back
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum FreezeTiming {
+    /// After every instruction: the game never observes another value
+    Instruction,
+    /// Once per frame: cheaper, but the game may briefly see its own writes
+    #[default]
+    Frame,
+}
+
+impl FromStr for FreezeTiming {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "instruction" => Ok(FreezeTiming::Instruction),
+            "frame" => Ok(FreezeTiming::Frame),
+            other => Err(format!(
+                "unknown freeze timing '{}' (expected instruction or frame)",
+                other
+            )),
+        }
+    }
+}
+
+/// One frozen address
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Freeze {
+    pub address: u16,
+    pub value: u8,
+}
+
+fn strip_hex_prefix(value: &str) -> &str {
+    value.trim_start_matches("0x").trim_start_matches("0X")
+}
+
+/// Parses `ADDR=VALUE`, both in hex (e.g. `C0A0=63`)
+impl FromStr for Freeze {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (address, value) = s
+            .split_once('=')
+            .ok_or_else(|| format!("expected ADDR=VALUE, got '{}'", s))?;
+        let freeze = Freeze {
+            address: u16::from_str_radix(strip_hex_prefix(address), 16)
+                .map_err(|_| format!("invalid address '{}'", address))?,
+            value: u8::from_str_radix(strip_hex_prefix(value), 16)
+                .map_err(|_| format!("invalid value '{}'", value))?,
+        };
+        check_freezable(freeze.address)?;
+        Ok(freeze)
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct RamFreezer {
+    freezes: Vec<Freeze>,
+    pub timing: FreezeTiming,
+}
+
+impl RamFreezer {
+    pub fn new(timing: FreezeTiming) -> Self {
+        Self {
+            freezes: Vec::new(),
+            timing,
+        }
+    }
+
+    /// Freeze `address` at `value`, replacing any earlier freeze of the same address.
+    /// Fails for addresses outside FREEZABLE.
+    pub fn freeze(&mut self, address: u16, value: u8) -> Result<(), String> {
+        check_freezable(address)?;
+        self.unfreeze(address);
+        self.freezes.push(Freeze { address, value });
+        Ok(())
+    }
+
+    pub fn unfreeze(&mut self, address: u16) {
+        self.freezes.retain(|f| f.address != address);
+    }
+
+    pub fn freezes(&self) -> &[Freeze] {
+        &self.freezes
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.freezes.is_empty()
+    }
+
+    /// Call after each executed instruction
+    pub fn after_instruction(&self, mem: &mut Memory) {
+        if self.timing == FreezeTiming::Instruction {
+            self.apply(mem);
+        }
+    }
+
+    /// Call after each emulated frame
+    pub fn after_frame(&self, mem: &mut Memory) {
+        if self.timing == FreezeTiming::Frame {
+            self.apply(mem);
+        }
+    }
+
+    fn apply(&self, mem: &mut Memory) {
+        for &Freeze { address, value } in &self.freezes {
+            let address = address as usize;
+            match address {
+                0xC000..=0xDFFF => mem.work_ram_mut()[address - 0xC000] = value,
+                0xFF80..=0xFFFE => mem.high_ram_mut()[address - 0xFF80] = value,
+                _ => {
+                    let offset = address - 0xA000;
+                    let bank = match mem.mbc().map(Mbc::ram_mapping) {
+                        Some(RamMapping::Bank(bank)) => bank,
+                        Some(RamMapping::Rtc(_)) => continue,
+                        None => 0,
+                    };
+                    // Only on a change, as a write marks the battery save for flushing
+                    if mem.external_ram_bank(bank).map(|ram| ram[offset]) != Some(value) {
+                        let _ = mem.write_external_ram(bank, offset, &[value]);
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn check_freezable(address: u16) -> Result<(), String> {
+    if FREEZABLE.iter().any(|range| range.contains(&address)) {
+        Ok(())
+    } else {
+        Err(format!(
+            "{:04X} is not RAM (work RAM C000-DFFF, high RAM FF80-FFFE or cartridge RAM A000-BFFF can be frozen)",
+            address
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_timing_selects_application_point() {
+        let mut mem = Memory::new(vec![0; 0x8000]);
+        let mut freezer = RamFreezer::new(FreezeTiming::Frame);
+        freezer.freeze(0xC0A0, 0x63).unwrap();
+
+        freezer.after_instruction(&mut mem);
+        assert_eq!(mem.read_8(0xC0A0), 0x00);
+        freezer.after_frame(&mut mem);
+        assert_eq!(mem.read_8(0xC0A0), 0x63);
+
+        freezer.timing = FreezeTiming::Instruction;
+        mem.write_8(0xC0A0, 0x01);
+        freezer.after_instruction(&mut mem);
+        assert_eq!(mem.read_8(0xC0A0), 0x63);
+    }
+
+    #[test]
+    fn test_freeze_writes_storage_directly() {
+        let mut rom = vec![0; 0x8000];
+        rom[0x0147] = 0x03; // MBC1+RAM+BATTERY
+        rom[0x0149] = 0x03;
+        let mut mem = Memory::new(rom);
+        let mut freezer = RamFreezer::default();
+        freezer.freeze(0xFF90, 0x12).unwrap();
+        freezer.freeze(0xA001, 0x34).unwrap();
+        assert!(freezer.freeze(0x0000, 0x0A).is_err());
+
+        // Cartridge RAM is disabled, but the freeze still lands in the mapped bank
+        freezer.after_frame(&mut mem);
+        assert_eq!(mem.read_8(0xFF90), 0x12);
+        assert_eq!(mem.external_ram_bank(0).unwrap()[1], 0x34);
+        assert!(!mem.external_ram_enabled());
+    }
+
+    #[test]
+    fn test_refreeze_and_unfreeze() {
+        let mut freezer = RamFreezer::default();
+        freezer.freeze(0xC000, 1).unwrap();
+        freezer.freeze(0xC000, 2).unwrap();
+        assert_eq!(
+            freezer.freezes(),
+            &[Freeze {
+                address: 0xC000,
+                value: 2
+            }]
+        );
+        freezer.unfreeze(0xC000);
+        assert!(freezer.is_empty());
+    }
+
+    #[test]
+    fn test_parse_freeze() {
+        assert_eq!(
+            "C0A0=63".parse(),
+            Ok(Freeze {
+                address: 0xC0A0,
+                value: 0x63
+            })
+        );
+        assert!("C0A0".parse::<Freeze>().is_err());
+        assert!("C0A0=100".parse::<Freeze>().is_err());
+        // Mapper registers and IO ports would have side effects
+        assert!("2000=01".parse::<Freeze>().is_err());
+        assert!("FF04=00".parse::<Freeze>().is_err());
+        assert_eq!("frame".parse(), Ok(FreezeTiming::Frame));
+    }
+}
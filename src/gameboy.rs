@@ -0,0 +1,410 @@
+// Whole-console facade for embedders
+// Bundles the CPU and the memory bus behind a small API (load, press buttons, run a
+// frame, read the picture), so code that only wants to run games does not depend on how
+// the core is split into components. Tools that need more (debuggers, the SDL frontend)
+// still reach the components through `cpu` and `memory`. Breakpoints set here are only
+// checked by the instruction-level calls (`step_instruction`, `step_over`,
+// `run_until_break`), so `run_frame` stays at full speed. An execution history, when
+// installed, records everything the GameBoy runs so that `step_back` can undo it.
+
+use crate::cartridge::{CartridgeError, CartridgeHeader};
+use crate::clock::{EmuDuration, EmuInstant};
+use crate::cpu::Cpu;
+use crate::debugger::{self, Breakpoints, RegisterDump, Stop};
+use crate::history::{ExecutionHistory, WriteRecord};
+use crate::joypad::{ButtonMask, JoypadButton};
+use crate::link::LinkCable;
+use crate::memory::Memory;
+use crate::model::Model;
+use crate::runner::CYCLES_PER_FRAME;
+use crate::savestate;
+use crate::trace::Tracer;
+
+pub struct GameBoy {
+    cpu: Cpu,
+    mem: Memory,
+    breakpoints: Breakpoints,
+    tracer: Option<Tracer>,
+    history: Option<ExecutionHistory>,
+}
+
+impl GameBoy {
+    /// Insert `rom` and start it where `model`'s boot ROM hands over (0x0100). Fails for
+    /// cartridges whose mapper is not emulated.
+    pub fn new(rom: Vec<u8>, model: Model) -> Result<Self, CartridgeError> {
+        let mut mem = Memory::from_rom(rom)?;
+        mem.skip_boot(model);
+        let mut cpu = Cpu::new();
+        cpu.skip_boot(model, mem.read_8(0x014D));
+        Ok(Self {
+            cpu,
+            mem,
+            breakpoints: Breakpoints::default(),
+            tracer: None,
+            history: None,
+        })
+    }
+
+    /// Execute one instruction (or one halted M-cycle) and service interrupts; returns
+    /// the T-cycles it took
+    pub fn step(&mut self) -> u32 {
+        if let Some(tracer) = self.tracer.as_mut() {
+            tracer.trace(&self.cpu, &self.mem);
+        }
+        match self.history.as_mut() {
+            Some(history) => history.step(&mut self.cpu, &mut self.mem),
+            None => self.cpu.step(&mut self.mem),
+        }
+    }
+
+    /// Log every instruction `step` and `run_frame` execute from now on, or stop with
+    /// None; returns the tracer that was installed, e.g. to flush it
+    pub fn set_tracer(&mut self, tracer: Option<Tracer>) -> Option<Tracer> {
+        std::mem::replace(&mut self.tracer, tracer)
+    }
+
+    /// Record every instruction from now on, so that `step_back` can undo it, or stop with
+    /// None. Recording keeps a copy of the machine every `interval` instructions, so it is
+    /// meant for debugging rather than playing.
+    pub fn set_history(&mut self, history: Option<ExecutionHistory>) {
+        self.history = history;
+    }
+
+    /// Undo the last recorded instruction; false without a history or when the
+    /// instruction is older than it reaches
+    pub fn step_back(&mut self) -> bool {
+        match self.history.as_mut() {
+            Some(history) => history.step_back(&mut self.cpu, &mut self.mem),
+            None => false,
+        }
+    }
+
+    /// The recorded instruction that last changed the byte at `address`
+    pub fn last_write(&self, address: u16) -> Option<WriteRecord> {
+        self.history.as_ref()?.last_write(address)
+    }
+
+    /// Run one video frame's worth of cycles; returns the T-cycles executed, which can
+    /// overshoot by part of an instruction
+    pub fn run_frame(&mut self) -> u32 {
+        let mut cycles = 0;
+        while cycles < CYCLES_PER_FRAME {
+            cycles += self.step();
+        }
+        cycles
+    }
+
+    /// PC breakpoints and watchpoints for the instruction-level calls below
+    pub fn breakpoints(&self) -> &Breakpoints {
+        &self.breakpoints
+    }
+
+    pub fn breakpoints_mut(&mut self) -> &mut Breakpoints {
+        &mut self.breakpoints
+    }
+
+    /// Execute one instruction, reporting the watchpoints it hit
+    pub fn step_instruction(&mut self) -> Stop {
+        let history = self.history.as_mut();
+        debugger::step_instruction(&mut self.cpu, &mut self.mem, &self.breakpoints, history)
+    }
+
+    /// Execute one instruction, running a CALL or RST through to its return; gives up
+    /// after `max_cycles` T-cycles
+    pub fn step_over(&mut self, max_cycles: u64) -> Stop {
+        let history = self.history.as_mut();
+        debugger::step_over(
+            &mut self.cpu,
+            &mut self.mem,
+            &self.breakpoints,
+            history,
+            max_cycles,
+        )
+    }
+
+    /// Run until a breakpoint or watchpoint hits, for at most `max_cycles` T-cycles
+    pub fn run_until_break(&mut self, max_cycles: u64) -> Stop {
+        let (cpu, mem) = (&mut self.cpu, &mut self.mem);
+        debugger::run_until_break(
+            cpu,
+            mem,
+            &self.breakpoints,
+            self.history.as_mut(),
+            max_cycles,
+        )
+    }
+
+    /// Registers, flags and the next instruction
+    pub fn registers(&self) -> RegisterDump {
+        RegisterDump::capture(&self.cpu, &self.mem)
+    }
+
+    /// Hold `buttons` (and release the rest) from the game's next joypad poll
+    pub fn set_buttons(&mut self, buttons: ButtonMask) {
+        self.mem.joypad.set_buttons(buttons);
+    }
+
+    /// Hold `button` down, keeping the others as they are
+    pub fn press_button(&mut self, button: JoypadButton) {
+        self.mem.joypad.press_button(button);
+    }
+
+    pub fn release_button(&mut self, button: JoypadButton) {
+        self.mem.joypad.release_button(button);
+    }
+
+    /// Emulated time: T-cycles run since power-on, independent of the host clock
+    pub fn now(&self) -> EmuInstant {
+        self.mem.clock.now()
+    }
+
+    /// Whole frames' worth of emulated time since power-on
+    pub fn frame_count(&self) -> u64 {
+        self.mem.clock.frame_count()
+    }
+
+    /// Emulated time since power-on
+    pub fn emulated_time(&self) -> EmuDuration {
+        self.mem.clock.emulated_time()
+    }
+
+    /// Bytes the game has sent over the link port (test ROMs print their results here)
+    pub fn serial_output(&self) -> &[u8] {
+        &self.mem.serial.output_buffer
+    }
+
+    /// Connect a link cable between this Game Boy and `other`, replacing any cable either
+    /// had. Step both in turns (a frame each, say) for them to talk.
+    pub fn connect_link(&mut self, other: &mut GameBoy) {
+        let (here, there) = LinkCable::pair();
+        self.mem.serial.set_link(Some(here));
+        other.mem.serial.set_link(Some(there));
+    }
+
+    /// The last frame: 160x144 pixels, RGB24
+    pub fn framebuffer(&self) -> &[u8] {
+        self.mem.ppu.get_framebuffer()
+    }
+
+    /// Title and mapper of the inserted cartridge, from its header
+    pub fn rom_info(&self) -> CartridgeHeader {
+        self.mem.cartridge_header()
+    }
+
+    pub fn cpu(&self) -> &Cpu {
+        &self.cpu
+    }
+
+    pub fn cpu_mut(&mut self) -> &mut Cpu {
+        &mut self.cpu
+    }
+
+    pub fn memory(&self) -> &Memory {
+        &self.mem
+    }
+
+    pub fn memory_mut(&mut self) -> &mut Memory {
+        &mut self.mem
+    }
+
+    /// Snapshot the whole machine (see `savestate::save_state`)
+    pub fn save_state(&self) -> Vec<u8> {
+        savestate::save_state(&self.cpu, &self.mem)
+    }
+
+    /// Restore a snapshot taken with `save_state` from the same game; on error the
+    /// machine keeps running as before. The execution history starts over.
+    pub fn load_state(&mut self, data: &[u8]) -> Result<(), String> {
+        savestate::load_state(&mut self.cpu, &mut self.mem, data)?;
+        if let Some(history) = self.history.as_mut() {
+            history.clear();
+        }
+        Ok(())
+    }
+
+    /// The components, for driving them directly (e.g. with `runner::Runner`)
+    pub fn into_parts(self) -> (Cpu, Memory) {
+        (self.cpu, self.mem)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::debugger::{HitKind, StopReason};
+    use crate::testrom::assemble;
+
+    #[test]
+    fn test_run_frame_with_buttons() {
+        // Poll the buttons and copy the low nibble of P1 to C000, forever
+        let rom = assemble(
+            "
+            org 0x0100
+            loop:
+            ld a, 0x10
+            ldh (0x00), a
+            ldh a, (0x00)
+            ld (0xC000), a
+            jr loop
+            ",
+        );
+        let mut gb = GameBoy::new(rom, Model::Dmg).unwrap();
+        gb.set_buttons(ButtonMask::NONE.with(JoypadButton::Start));
+        let start = gb.now();
+        let cycles = gb.run_frame();
+
+        assert!(cycles >= CYCLES_PER_FRAME);
+        assert_eq!(gb.now() - start, EmuDuration::from_cycles(cycles as u64));
+        assert_eq!(gb.emulated_time(), EmuDuration::from_cycles(cycles as u64));
+        assert_eq!(gb.frame_count(), 1);
+        assert_eq!(gb.memory().work_ram()[0] & 0x0F, 0x07);
+        assert_eq!(gb.framebuffer().len(), 160 * 144 * 3);
+        assert_eq!(gb.rom_info().mapper_name(), "ROM ONLY");
+    }
+
+    #[test]
+    fn test_step_buttons_and_serial_output() {
+        // Print "OK" over the link port, then copy P1 to C000 forever
+        let rom = assemble(
+            "
+            org 0x0100
+            ld a, 0x4f
+            ldh (0x01), a
+            ld a, 0x81
+            ldh (0x02), a
+            ld a, 0x4b
+            ldh (0x01), a
+            ld a, 0x81
+            ldh (0x02), a
+            loop:
+            ld a, 0x20
+            ldh (0x00), a
+            ldh a, (0x00)
+            ld (0xC000), a
+            jr loop
+            ",
+        );
+        let mut gb = GameBoy::new(rom, Model::Dmg).unwrap();
+        assert_eq!(gb.step(), 8);
+        assert_eq!(gb.cpu().registers.read_r16(crate::cpu::Reg16::PC), 0x0102);
+
+        gb.press_button(JoypadButton::Down);
+        gb.run_frame();
+        assert_eq!(gb.serial_output(), b"OK");
+        assert_eq!(gb.memory().work_ram()[0] & 0x0F, 0x07);
+        gb.release_button(JoypadButton::Down);
+        gb.run_frame();
+        assert_eq!(gb.memory().work_ram()[0] & 0x0F, 0x0F);
+    }
+
+    #[test]
+    fn test_link_cable_between_two_game_boys() {
+        // Send SB with SC = `sc`, wait for the transfer and store what came back in C000
+        let program = |sb: u8, sc: u8| {
+            assemble(&format!(
+                "
+                org 0x0100
+                ld a, {}
+                ldh (0x01), a
+                ld a, {}
+                ldh (0x02), a
+                wait:
+                ldh a, (0x02)
+                and 0x80
+                jr nz, wait
+                ldh a, (0x01)
+                ld (0xC000), a
+                done:
+                jr done
+                ",
+                sb, sc
+            ))
+        };
+        let mut master = GameBoy::new(program(0x42, 0x81), Model::Dmg).unwrap();
+        let mut slave = GameBoy::new(program(0x99, 0x80), Model::Dmg).unwrap();
+        master.connect_link(&mut slave);
+
+        // The slave waits however long the master takes to clock the byte
+        slave.run_frame();
+        slave.run_frame();
+        assert_eq!(slave.memory().work_ram()[0], 0x00);
+        master.run_frame();
+        slave.run_frame();
+        assert_eq!(master.memory().work_ram()[0], 0x99);
+        assert_eq!(slave.memory().work_ram()[0], 0x42);
+
+        // Without a partner waiting, the master reads 0xFF
+        let mut alone = GameBoy::new(program(0x42, 0x81), Model::Dmg).unwrap();
+        alone.connect_link(&mut GameBoy::new(program(0, 0), Model::Dmg).unwrap());
+        alone.run_frame();
+        assert_eq!(alone.memory().work_ram()[0], 0xFF);
+    }
+
+    #[test]
+    fn test_breakpoints_and_stepping() {
+        let rom = assemble(
+            "
+            org 0x0100
+            loop:
+            call sub        ; $0100
+            jr loop         ; $0103
+            sub:
+            ld a, 0x12      ; $0105
+            ld (0xC000), a
+            ret
+            ",
+        );
+        let mut gb = GameBoy::new(rom, Model::Dmg).unwrap();
+        gb.breakpoints_mut().write.insert(0xC000);
+        let stop = gb.step_over(1000);
+        assert_eq!(stop.reason, StopReason::Hit);
+        assert_eq!(
+            stop.hits,
+            [HitKind::Write {
+                address: 0xC000,
+                value: 0x12
+            }]
+        );
+        assert_eq!(gb.registers().pc, 0x010A);
+
+        gb.breakpoints_mut().write.clear();
+        gb.breakpoints_mut().pc.insert(0x0105);
+        assert_eq!(gb.step_instruction().pc, 0x0103);
+        assert_eq!(gb.step_instruction().pc, 0x0100);
+        let stop = gb.run_until_break(1000);
+        assert_eq!((stop.reason, stop.pc), (StopReason::Hit, 0x0105));
+        assert_eq!(gb.registers().next.text, "ld a, $12");
+    }
+
+    #[test]
+    fn test_step_back() {
+        let rom = assemble(
+            "
+            org 0x0100
+            ld a, 0x12      ; $0100
+            ld (0xC000), a  ; $0102
+            inc a
+            ld (0xC000), a
+            done:
+            jr done
+            ",
+        );
+        let mut gb = GameBoy::new(rom, Model::Dmg).unwrap();
+        gb.step();
+        assert!(!gb.step_back());
+
+        gb.set_history(Some(ExecutionHistory::new(2, 4)));
+        gb.step();
+        gb.step_instruction();
+        gb.step();
+        gb.step();
+        assert_eq!(gb.memory().read_8(0xC000), 0x13);
+        assert_eq!(gb.last_write(0xC000).unwrap().pc, 0x0106);
+
+        assert!(gb.step_back());
+        assert!(gb.step_back());
+        assert_eq!(gb.registers().pc, 0x0106);
+        assert_eq!(gb.memory().read_8(0xC000), 0x12);
+        assert_eq!(gb.last_write(0xC000).unwrap().pc, 0x0102);
+    }
+}
@@ -0,0 +1,303 @@
+// Headless runs
+// Runs a game with no window, sound or input, for CI and scripts: a fixed number of
+// frames, or until the serial output contains a pattern (test ROMs print "Passed" there).
+// The outcome maps to a process exit status. Frame hashes identify a picture across
+// builds, so `git bisect run` can find the commit that changed one, and screenshots taken
+// at chosen frames make galleries and golden frames for regression tests.
+
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+use crate::gameboy::GameBoy;
+use crate::png;
+use crate::runner::Runner;
+
+const FNV_OFFSET_BASIS: u64 = 0xCBF2_9CE4_8422_2325;
+const FNV_PRIME: u64 = 0x0100_0000_01B3;
+
+/// Hash of a picture (64-bit FNV-1a). Unlike std's hasher it is the same on every
+/// platform and Rust release, so hashes can be compared between builds.
+pub fn frame_hash(framebuffer: &[u8]) -> u64 {
+    framebuffer.iter().fold(FNV_OFFSET_BASIS, |hash, &byte| {
+        (hash ^ byte as u64).wrapping_mul(FNV_PRIME)
+    })
+}
+
+/// Run `frames` frames of `gb` and hash the picture it ends on
+pub fn hash_after_frames(gb: &mut GameBoy, frames: u64) -> u64 {
+    for _ in 0..frames {
+        gb.run_frame();
+    }
+    frame_hash(gb.framebuffer())
+}
+
+/// When a headless run stops
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HeadlessLimits {
+    /// Most frames to run
+    pub frames: u64,
+    /// Stop as soon as the serial output contains these bytes
+    pub until_serial: Option<Vec<u8>>,
+}
+
+/// PNG screenshots to save during a headless run
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Screenshots {
+    /// Frames to capture, counted from 1 like `HeadlessOutcome::Matched`
+    pub frames: Vec<u64>,
+    /// Directory the files go to, named `PREFIX-FRAME.png`
+    pub dir: PathBuf,
+    pub prefix: String,
+    /// Files written so far
+    pub saved: Vec<PathBuf>,
+}
+
+impl Screenshots {
+    pub fn path(&self, frame: u64) -> PathBuf {
+        self.dir.join(format!("{}-{}.png", self.prefix, frame))
+    }
+
+    /// Save `framebuffer` if `frame` is one of the listed frames
+    pub fn capture(&mut self, frame: u64, framebuffer: &[u8]) -> io::Result<()> {
+        if self.frames.contains(&frame) {
+            let path = self.path(frame);
+            save_png(&path, framebuffer)?;
+            self.saved.push(path);
+        }
+        Ok(())
+    }
+
+    /// Last frame captured, where a run without its own frame limit can stop
+    pub fn last_frame(&self) -> Option<u64> {
+        self.frames.iter().copied().max()
+    }
+}
+
+/// Write a 160x144 RGB24 framebuffer as a PNG file
+pub fn save_png(path: &Path, framebuffer: &[u8]) -> io::Result<()> {
+    std::fs::write(path, png::encode_rgb(160, 144, framebuffer))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HeadlessOutcome {
+    /// The serial pattern appeared during frame `frame` (counted from 1)
+    Matched { frame: u64 },
+    /// Every frame ran and no pattern was asked for
+    Finished,
+    /// Every frame ran without the serial pattern appearing
+    TimedOut,
+}
+
+impl HeadlessOutcome {
+    /// Process exit status: 0 on success, 1 when the pattern never appeared
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            HeadlessOutcome::Matched { .. } | HeadlessOutcome::Finished => 0,
+            HeadlessOutcome::TimedOut => 1,
+        }
+    }
+}
+
+// Finds a byte pattern in a stream, keeping only as many bytes as the pattern is long
+struct SerialMatcher<'a> {
+    pattern: &'a [u8],
+    tail: Vec<u8>,
+}
+
+impl<'a> SerialMatcher<'a> {
+    fn new(pattern: &'a [u8]) -> Self {
+        Self {
+            pattern,
+            tail: Vec::with_capacity(pattern.len()),
+        }
+    }
+
+    // True once the stream so far ends with the pattern
+    fn push(&mut self, byte: u8) -> bool {
+        if self.tail.len() == self.pattern.len() {
+            if self.tail.is_empty() {
+                return true;
+            }
+            self.tail.remove(0);
+        }
+        self.tail.push(byte);
+        self.tail == self.pattern
+    }
+}
+
+/// Run frames until `limits` says to stop, copying serial output to `out` as it arrives
+/// and saving the frames `screenshots` lists
+pub fn run(
+    runner: &mut Runner,
+    limits: &HeadlessLimits,
+    mut screenshots: Option<&mut Screenshots>,
+    out: &mut dyn Write,
+) -> io::Result<HeadlessOutcome> {
+    let mut matcher = limits.until_serial.as_deref().map(SerialMatcher::new);
+    for _ in 0..limits.frames {
+        runner.run_frame()?;
+        if let Some(screenshots) = screenshots.as_deref_mut() {
+            screenshots.capture(runner.frame_number(), &runner.mem.ppu.framebuffer)?;
+        }
+
+        let mut result = Ok(());
+        let mut matched = false;
+        runner.mem.serial.drain_output(&mut |byte| {
+            if result.is_ok() {
+                result = out.write_all(&[byte]);
+            }
+            if let Some(matcher) = matcher.as_mut() {
+                matched |= matcher.push(byte);
+            }
+        });
+        result?;
+        out.flush()?;
+        if matched {
+            return Ok(HeadlessOutcome::Matched {
+                frame: runner.frame_number(),
+            });
+        }
+    }
+    Ok(match matcher {
+        Some(_) => HeadlessOutcome::TimedOut,
+        None => HeadlessOutcome::Finished,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cpu::Cpu;
+    use crate::memory::Memory;
+    use crate::model::Model;
+    use crate::testrom::assemble;
+
+    #[test]
+    fn test_frame_hash_is_fnv1a() {
+        assert_eq!(frame_hash(&[]), 0xCBF2_9CE4_8422_2325);
+        assert_eq!(frame_hash(b"a"), 0xAF63_DC4C_8601_EC8C);
+    }
+
+    #[test]
+    fn test_hash_after_frames_is_repeatable() {
+        // Scrolls the background one pixel per frame
+        let rom = assemble(
+            "
+            org $0100
+            loop:
+                ldh a, ($44)
+                cp $90
+                jr nz, loop
+                ldh a, ($43)
+                inc a
+                ldh ($43), a
+            wait:
+                ldh a, ($44)
+                cp $90
+                jr z, wait
+                jr loop
+            ",
+        );
+        let hash = |frames| {
+            let mut gb = GameBoy::new(rom.clone(), Model::Dmg).unwrap();
+            gb.memory_mut().ppu.vram[0] = 0xF0;
+            hash_after_frames(&mut gb, frames)
+        };
+        assert_eq!(hash(5), hash(5));
+        assert_ne!(hash(5), hash(6));
+    }
+
+    // Sends "OK" over the link port during the first frame, then spins
+    fn runner() -> Runner {
+        let mut mem = Memory::new(assemble(
+            "
+            org $0100
+                ld a, $4F       ; 'O'
+                ldh ($01), a
+                ld a, $80
+                ldh ($02), a
+                ld a, $4B       ; 'K'
+                ldh ($01), a
+                ld a, $80
+                ldh ($02), a
+            done:
+                jr done
+            ",
+        ));
+        mem.skip_boot(Model::Dmg);
+        let mut cpu = Cpu::new();
+        cpu.skip_boot(Model::Dmg, 0);
+        Runner::new(cpu, mem)
+    }
+
+    #[test]
+    fn test_stops_when_serial_output_matches() {
+        let mut runner = runner();
+        let mut out = Vec::new();
+        let limits = HeadlessLimits {
+            frames: 10,
+            until_serial: Some(b"OK".to_vec()),
+        };
+        let outcome = run(&mut runner, &limits, None, &mut out).unwrap();
+        assert_eq!(outcome, HeadlessOutcome::Matched { frame: 1 });
+        assert_eq!(outcome.exit_code(), 0);
+        assert_eq!(out, b"OK");
+    }
+
+    #[test]
+    fn test_frame_limit() {
+        let mut out = Vec::new();
+        let limits = HeadlessLimits {
+            frames: 3,
+            until_serial: Some(b"Passed".to_vec()),
+        };
+        let outcome = run(&mut runner(), &limits, None, &mut out).unwrap();
+        assert_eq!(outcome, HeadlessOutcome::TimedOut);
+        assert_eq!(outcome.exit_code(), 1);
+
+        let mut runner = runner();
+        let limits = HeadlessLimits {
+            frames: 3,
+            until_serial: None,
+        };
+        assert_eq!(
+            run(&mut runner, &limits, None, &mut out).unwrap(),
+            HeadlessOutcome::Finished
+        );
+        assert_eq!(runner.frame_number(), 3);
+    }
+
+    #[test]
+    fn test_screenshots_at_frames() {
+        let dir = std::env::temp_dir().join(format!("gbemu_screenshots_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let mut screenshots = Screenshots {
+            frames: vec![3, 1, 9],
+            dir: dir.clone(),
+            prefix: "ok".to_string(),
+            saved: Vec::new(),
+        };
+        let limits = HeadlessLimits {
+            frames: 4,
+            until_serial: None,
+        };
+        let mut runner = runner();
+        run(
+            &mut runner,
+            &limits,
+            Some(&mut screenshots),
+            &mut Vec::new(),
+        )
+        .unwrap();
+
+        assert_eq!(screenshots.last_frame(), Some(9));
+        assert_eq!(
+            screenshots.saved,
+            [dir.join("ok-1.png"), dir.join("ok-3.png")]
+        );
+        let png = std::fs::read(dir.join("ok-3.png")).unwrap();
+        assert_eq!(png[..8], png::SIGNATURE);
+        assert!(!dir.join("ok-2.png").exists());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,106 @@
+// Audio output high-pass filter
+// The Game Boy couples each audio output through a capacitor, which removes the DC offset
+// of the DACs. Without it, a channel DAC turning on or off shifts the whole output level and
+// is heard as a pop. One filter is applied per output (left/right) to the mixed samples,
+// with the capacitor charge factor of the model (Pan Docs, "Audio details").
+
+use crate::model::Model;
+
+/// Fraction of the capacitor charge kept per T-cycle
+fn charge_per_cycle(model: Model) -> f64 {
+    if model.is_cgb() {
+        0.998943
+    } else {
+        0.999958
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct HighPassFilter {
+    capacitor: f32,
+    charge: f32,
+}
+
+impl HighPassFilter {
+    /// Filter for output samples taken every `cycles_per_sample` T-cycles
+    /// (4194304 / sample rate)
+    pub fn new(model: Model, cycles_per_sample: f64) -> Self {
+        Self {
+            capacitor: 0.0,
+            charge: charge_per_cycle(model).powf(cycles_per_sample) as f32,
+        }
+    }
+
+    /// Filter one sample. When every channel DAC is off the output is silent and the
+    /// capacitor keeps its charge.
+    pub fn process(&mut self, input: f32, any_dac_enabled: bool) -> f32 {
+        if !any_dac_enabled {
+            return 0.0;
+        }
+        let output = input - self.capacitor;
+        self.capacitor = input - output * self.charge;
+        output
+    }
+}
+
+/// Filters for the left and right outputs
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct StereoHighPass {
+    pub left: HighPassFilter,
+    pub right: HighPassFilter,
+}
+
+impl StereoHighPass {
+    pub fn new(model: Model, cycles_per_sample: f64) -> Self {
+        let filter = HighPassFilter::new(model, cycles_per_sample);
+        Self {
+            left: filter,
+            right: filter,
+        }
+    }
+
+    pub fn process(&mut self, (left, right): (f32, f32), any_dac_enabled: bool) -> (f32, f32) {
+        (
+            self.left.process(left, any_dac_enabled),
+            self.right.process(right, any_dac_enabled),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 48 kHz output
+    const CYCLES_PER_SAMPLE: f64 = 4_194_304.0 / 48_000.0;
+
+    #[test]
+    fn test_dc_offset_decays() {
+        let mut filter = HighPassFilter::new(Model::Dmg, CYCLES_PER_SAMPLE);
+        // A DAC switching on steps the input to a constant level
+        assert_eq!(filter.process(1.0, true), 1.0);
+
+        let mut output = 1.0;
+        for _ in 0..48_000 {
+            output = filter.process(1.0, true);
+        }
+        // DMG time constant is ~5.7 ms, so a second later the offset is gone
+        assert!(output.abs() < 1e-3, "output {}", output);
+    }
+
+    #[test]
+    fn test_cgb_filter_decays_faster() {
+        let mut dmg = HighPassFilter::new(Model::Dmg, CYCLES_PER_SAMPLE);
+        let mut cgb = HighPassFilter::new(Model::Cgb, CYCLES_PER_SAMPLE);
+        dmg.process(1.0, true);
+        cgb.process(1.0, true);
+        assert!(cgb.process(1.0, true) < dmg.process(1.0, true));
+    }
+
+    #[test]
+    fn test_silent_without_dacs() {
+        let mut filters = StereoHighPass::new(Model::Dmg, CYCLES_PER_SAMPLE);
+        assert_eq!(filters.process((0.5, -0.5), false), (0.0, 0.0));
+        assert_eq!(filters.process((0.5, -0.5), true), (0.5, -0.5));
+    }
+}
@@ -0,0 +1,213 @@
+// Execution history for reverse stepping
+// Keeps in-memory keyframes (full Cpu + Memory copies) every few instructions. Going back
+// one instruction restores the nearest earlier keyframe and re-executes up to the target,
+// which is exact because emulation is deterministic without host input.
+
+use std::collections::VecDeque;
+
+use crate::cpu::{Cpu, Reg16};
+use crate::memory::Memory;
+
+struct Keyframe {
+    position: u64,
+    cpu: Cpu,
+    mem: Box<Memory>,
+}
+
+/// Instruction that last changed a memory location
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WriteRecord {
+    /// History position of the instruction (see `ExecutionHistory::position`)
+    pub position: u64,
+    /// Address of the instruction
+    pub pc: u16,
+    pub old_value: u8,
+    pub new_value: u8,
+}
+
+pub struct ExecutionHistory {
+    keyframes: VecDeque<Keyframe>,
+    interval: u64,
+    capacity: usize,
+    position: u64,
+}
+
+impl ExecutionHistory {
+    /// Take a keyframe every `interval` instructions, keeping at most `capacity` of them.
+    /// The reachable history is roughly `interval * capacity` instructions.
+    pub fn new(interval: u64, capacity: usize) -> Self {
+        Self {
+            keyframes: VecDeque::with_capacity(capacity),
+            interval: interval.max(1),
+            capacity: capacity.max(1),
+            position: 0,
+        }
+    }
+
+    /// Instructions executed through this history
+    pub fn position(&self) -> u64 {
+        self.position
+    }
+
+    /// Earliest position that can still be restored
+    pub fn oldest_position(&self) -> Option<u64> {
+        self.keyframes.front().map(|k| k.position)
+    }
+
+    /// Forget the recorded history, e.g. after the machine ran or was changed without it
+    pub fn clear(&mut self) {
+        self.keyframes.clear();
+        self.position = 0;
+    }
+
+    /// Execute one instruction (and any interrupt dispatch), recording a keyframe when due
+    pub fn step(&mut self, cpu: &mut Cpu, mem: &mut Memory) -> u32 {
+        let due = self.position.is_multiple_of(self.interval);
+        if due && self.keyframes.back().map(|k| k.position) != Some(self.position) {
+            if self.keyframes.len() == self.capacity {
+                self.keyframes.pop_front();
+            }
+            self.keyframes.push_back(Keyframe {
+                position: self.position,
+                cpu: cpu.clone(),
+                mem: Box::new(mem.clone()),
+            });
+        }
+        self.position += 1;
+        Self::execute(cpu, mem)
+    }
+
+    /// Undo the last instruction. Returns false when it is older than the kept history.
+    pub fn step_back(&mut self, cpu: &mut Cpu, mem: &mut Memory) -> bool {
+        match self.position.checked_sub(1) {
+            Some(target) => self.seek(target, cpu, mem),
+            None => false,
+        }
+    }
+
+    /// Restore the machine to how it was at `target` (at most the current position)
+    pub fn seek(&mut self, target: u64, cpu: &mut Cpu, mem: &mut Memory) -> bool {
+        if target > self.position {
+            return false;
+        }
+        let Some(keyframe) = self.keyframes.iter().rev().find(|k| k.position <= target) else {
+            return false;
+        };
+
+        *cpu = keyframe.cpu.clone();
+        *mem = (*keyframe.mem).clone();
+        for _ in keyframe.position..target {
+            Self::execute(cpu, mem);
+        }
+
+        // The discarded future may not happen again the same way
+        while self.keyframes.back().is_some_and(|k| k.position > target) {
+            self.keyframes.pop_back();
+        }
+        self.position = target;
+        true
+    }
+
+    /// Find the most recent instruction that changed the byte at `addr`, by replaying the
+    /// kept history. The current machine state is not modified.
+    pub fn last_write(&self, addr: u16) -> Option<WriteRecord> {
+        let keyframe = self.keyframes.front()?;
+        let mut cpu = keyframe.cpu.clone();
+        let mut mem = (*keyframe.mem).clone();
+
+        let mut last = None;
+        let mut value = mem.read_8(addr);
+        for position in keyframe.position..self.position {
+            let pc = cpu.registers.read_r16(Reg16::PC);
+            Self::execute(&mut cpu, &mut mem);
+            let new_value = mem.read_8(addr);
+            if new_value != value {
+                last = Some(WriteRecord {
+                    position,
+                    pc,
+                    old_value: value,
+                    new_value,
+                });
+                value = new_value;
+            }
+        }
+        last
+    }
+
+    fn execute(cpu: &mut Cpu, mem: &mut Memory) -> u32 {
+        cpu.step(mem)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // LD HL,0xC000 ; loop: INC A ; LD (HL),A ; JR loop
+    fn counter_machine() -> (Cpu, Memory) {
+        let mut rom = vec![0; 0x8000];
+        rom[0x0100..0x0107].copy_from_slice(&[0x21, 0x00, 0xC0, 0x3C, 0x77, 0x18, 0xFC]);
+        let mut cpu = Cpu::new();
+        cpu.registers.write_r16(Reg16::PC, 0x0100);
+        (cpu, Memory::new(rom))
+    }
+
+    fn snapshot(cpu: &Cpu, mem: &Memory) -> (u16, u16, u8) {
+        (
+            cpu.registers.read_r16(Reg16::PC),
+            cpu.registers.read_r16(Reg16::AF),
+            mem.read_8(0xC000),
+        )
+    }
+
+    #[test]
+    fn test_step_back_restores_previous_instruction_state() {
+        let (mut cpu, mut mem) = counter_machine();
+        let mut history = ExecutionHistory::new(4, 8);
+
+        let mut states = vec![snapshot(&cpu, &mem)];
+        for _ in 0..25 {
+            history.step(&mut cpu, &mut mem);
+            states.push(snapshot(&cpu, &mem));
+        }
+
+        for expected in (20..25).rev() {
+            assert!(history.step_back(&mut cpu, &mut mem));
+            assert_eq!(history.position(), expected as u64);
+            assert_eq!(snapshot(&cpu, &mem), states[expected]);
+        }
+
+        // Stepping forward again follows the same path
+        history.step(&mut cpu, &mut mem);
+        assert_eq!(snapshot(&cpu, &mem), states[21]);
+    }
+
+    #[test]
+    fn test_history_is_bounded() {
+        let (mut cpu, mut mem) = counter_machine();
+        let mut history = ExecutionHistory::new(4, 2);
+        for _ in 0..20 {
+            history.step(&mut cpu, &mut mem);
+        }
+        assert_eq!(history.oldest_position(), Some(12));
+        assert!(!history.seek(11, &mut cpu, &mut mem));
+        assert!(history.seek(12, &mut cpu, &mut mem));
+    }
+
+    #[test]
+    fn test_last_write_finds_storing_instruction() {
+        let (mut cpu, mut mem) = counter_machine();
+        let mut history = ExecutionHistory::new(4, 8);
+        for _ in 0..10 {
+            history.step(&mut cpu, &mut mem);
+        }
+
+        // Instructions: LD HL (0), then INC A / LD (HL),A / JR repeating; the last store is #8
+        let write = history.last_write(0xC000).unwrap();
+        assert_eq!(write.position, 8);
+        assert_eq!(write.pc, 0x0104);
+        assert_eq!((write.old_value, write.new_value), (2, 3));
+        assert_eq!(history.position(), 10);
+        assert_eq!(history.last_write(0xC001), None);
+    }
+}
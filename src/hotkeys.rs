@@ -0,0 +1,330 @@
+// Controller button combos
+// Config entries like `hotkey.select+start+l = save-state` bind a combo of game
+// controller buttons to a frontend action, so the emulator can be driven from a
+// controller alone (handhelds, couch setups). A combo fires when its last button goes
+// down while the others are held on the same controller; the buttons still reach the game.
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::str::FromStr;
+
+/// Prefix of the config keys that bind combos
+pub const CONFIG_PREFIX: &str = "hotkey.";
+
+/// Game controller button, named like the SDL game controller mapping
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PadButton {
+    A,
+    B,
+    X,
+    Y,
+    Select,
+    Guide,
+    Start,
+    LeftStick,
+    RightStick,
+    LeftShoulder,
+    RightShoulder,
+    Up,
+    Down,
+    Left,
+    Right,
+}
+
+impl PadButton {
+    pub const ALL: [PadButton; 15] = [
+        PadButton::A,
+        PadButton::B,
+        PadButton::X,
+        PadButton::Y,
+        PadButton::Select,
+        PadButton::Guide,
+        PadButton::Start,
+        PadButton::LeftStick,
+        PadButton::RightStick,
+        PadButton::LeftShoulder,
+        PadButton::RightShoulder,
+        PadButton::Up,
+        PadButton::Down,
+        PadButton::Left,
+        PadButton::Right,
+    ];
+
+    fn name(&self) -> &'static str {
+        match self {
+            PadButton::A => "a",
+            PadButton::B => "b",
+            PadButton::X => "x",
+            PadButton::Y => "y",
+            PadButton::Select => "select",
+            PadButton::Guide => "guide",
+            PadButton::Start => "start",
+            PadButton::LeftStick => "ls",
+            PadButton::RightStick => "rs",
+            PadButton::LeftShoulder => "l",
+            PadButton::RightShoulder => "r",
+            PadButton::Up => "up",
+            PadButton::Down => "down",
+            PadButton::Left => "left",
+            PadButton::Right => "right",
+        }
+    }
+
+    fn bit(&self) -> u16 {
+        1 << *self as u16
+    }
+}
+
+impl FromStr for PadButton {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        PadButton::ALL
+            .into_iter()
+            .find(|button| button.name() == s)
+            .ok_or_else(|| format!("unknown controller button '{}'", s))
+    }
+}
+
+impl fmt::Display for PadButton {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// Set of controller buttons, written as names joined by `+`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct ButtonCombo(u16);
+
+impl ButtonCombo {
+    pub fn contains(&self, button: PadButton) -> bool {
+        self.0 & button.bit() != 0
+    }
+
+    pub fn with(self, button: PadButton) -> Self {
+        Self(self.0 | button.bit())
+    }
+
+    pub fn without(self, button: PadButton) -> Self {
+        Self(self.0 & !button.bit())
+    }
+
+    /// Every button of `other` is in this set
+    pub fn includes(&self, other: ButtonCombo) -> bool {
+        self.0 & other.0 == other.0
+    }
+}
+
+impl FromStr for ButtonCombo {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut combo = ButtonCombo::default();
+        for name in s.split('+') {
+            combo = combo.with(name.trim().parse()?);
+        }
+        Ok(combo)
+    }
+}
+
+impl fmt::Display for ButtonCombo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let names: Vec<_> = PadButton::ALL
+            .into_iter()
+            .filter(|&button| self.contains(button))
+            .map(|button| button.name())
+            .collect();
+        f.write_str(&names.join("+"))
+    }
+}
+
+/// Frontend action a combo triggers
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HotkeyAction {
+    SaveState,
+    LoadState,
+    /// Run as fast as possible until triggered again
+    ToggleTurbo,
+    NextPalette,
+    ToggleLayerView,
+    /// Open or close the pause menu
+    Menu,
+    /// Run backwards for as long as the combo is held
+    Rewind,
+    Quit,
+}
+
+impl HotkeyAction {
+    pub const ALL: [HotkeyAction; 8] = [
+        HotkeyAction::SaveState,
+        HotkeyAction::LoadState,
+        HotkeyAction::ToggleTurbo,
+        HotkeyAction::NextPalette,
+        HotkeyAction::ToggleLayerView,
+        HotkeyAction::Menu,
+        HotkeyAction::Rewind,
+        HotkeyAction::Quit,
+    ];
+
+    fn name(&self) -> &'static str {
+        match self {
+            HotkeyAction::SaveState => "save-state",
+            HotkeyAction::LoadState => "load-state",
+            HotkeyAction::ToggleTurbo => "turbo",
+            HotkeyAction::NextPalette => "next-palette",
+            HotkeyAction::ToggleLayerView => "layer-view",
+            HotkeyAction::Menu => "menu",
+            HotkeyAction::Rewind => "rewind",
+            HotkeyAction::Quit => "quit",
+        }
+    }
+}
+
+impl FromStr for HotkeyAction {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        HotkeyAction::ALL
+            .into_iter()
+            .find(|action| action.name() == s)
+            .ok_or_else(|| {
+                let names: Vec<_> = HotkeyAction::ALL.iter().map(HotkeyAction::name).collect();
+                format!(
+                    "unknown hotkey action '{}' (expected {})",
+                    s,
+                    names.join(", ")
+                )
+            })
+    }
+}
+
+impl fmt::Display for HotkeyAction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// Combo bindings and the buttons each controller holds
+#[derive(Debug, Clone, Default)]
+pub struct ComboHotkeys {
+    bindings: Vec<(ButtonCombo, HotkeyAction)>,
+    held: BTreeMap<u32, ButtonCombo>,
+}
+
+impl ComboHotkeys {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn bind(&mut self, combo: ButtonCombo, action: HotkeyAction) {
+        self.bindings.retain(|&(bound, _)| bound != combo);
+        self.bindings.push((combo, action));
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.bindings.is_empty()
+    }
+
+    /// `button` went down on `controller`; returns the action whose combo it completes.
+    /// When several combos match, the one with the most buttons wins.
+    pub fn press(&mut self, controller: u32, button: PadButton) -> Option<HotkeyAction> {
+        let held = self.held.entry(controller).or_default();
+        *held = held.with(button);
+        let held = *held;
+        self.bindings
+            .iter()
+            .filter(|(combo, _)| combo.contains(button) && held.includes(*combo))
+            .max_by_key(|(combo, _)| combo.0.count_ones())
+            .map(|&(_, action)| action)
+    }
+
+    pub fn release(&mut self, controller: u32, button: PadButton) {
+        if let Some(held) = self.held.get_mut(&controller) {
+            *held = held.without(button);
+        }
+    }
+
+    /// Whether a controller holds a combo for `action`, for actions that last while held.
+    /// As with `press`, a longer combo held at the same time takes precedence.
+    pub fn is_held(&self, action: HotkeyAction) -> bool {
+        self.held.values().any(|&held| {
+            self.bindings
+                .iter()
+                .filter(|(combo, _)| held.includes(*combo))
+                .max_by_key(|(combo, _)| combo.0.count_ones())
+                .is_some_and(|&(_, bound)| bound == action)
+        })
+    }
+
+    /// Forget what a disconnected controller was holding
+    pub fn remove_controller(&mut self, controller: u32) {
+        self.held.remove(&controller);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_names() {
+        let combo: ButtonCombo = "select + start+l".parse().unwrap();
+        assert_eq!(combo.to_string(), "select+start+l");
+        assert_eq!(combo.to_string().parse(), Ok(combo));
+        assert!("select+home".parse::<ButtonCombo>().is_err());
+        assert_eq!("turbo".parse(), Ok(HotkeyAction::ToggleTurbo));
+        assert_eq!("rewind".parse(), Ok(HotkeyAction::Rewind));
+        assert!("rewind-hold".parse::<HotkeyAction>().is_err());
+    }
+
+    #[test]
+    fn test_held_combo() {
+        let mut hotkeys = ComboHotkeys::new();
+        hotkeys.bind("select+l".parse().unwrap(), HotkeyAction::Rewind);
+        hotkeys.bind("select+l+start".parse().unwrap(), HotkeyAction::SaveState);
+
+        hotkeys.press(0, PadButton::Select);
+        assert!(!hotkeys.is_held(HotkeyAction::Rewind));
+        assert_eq!(
+            hotkeys.press(0, PadButton::LeftShoulder),
+            Some(HotkeyAction::Rewind)
+        );
+        assert!(hotkeys.is_held(HotkeyAction::Rewind));
+        // Other buttons can be held too, unless they make up a longer combo
+        hotkeys.press(0, PadButton::A);
+        assert!(hotkeys.is_held(HotkeyAction::Rewind));
+        hotkeys.press(0, PadButton::Start);
+        assert!(!hotkeys.is_held(HotkeyAction::Rewind));
+        hotkeys.release(0, PadButton::Start);
+        assert!(hotkeys.is_held(HotkeyAction::Rewind));
+
+        hotkeys.release(0, PadButton::Select);
+        assert!(!hotkeys.is_held(HotkeyAction::Rewind));
+    }
+
+    #[test]
+    fn test_combo_fires_when_completed_on_one_controller() {
+        let mut hotkeys = ComboHotkeys::new();
+        hotkeys.bind("select+start".parse().unwrap(), HotkeyAction::Quit);
+        hotkeys.bind("select+start+l".parse().unwrap(), HotkeyAction::SaveState);
+
+        assert_eq!(hotkeys.press(0, PadButton::Select), None);
+        assert_eq!(hotkeys.press(1, PadButton::Start), None); // Other controller
+        assert_eq!(hotkeys.press(0, PadButton::LeftShoulder), None);
+        assert_eq!(
+            hotkeys.press(0, PadButton::Start),
+            Some(HotkeyAction::SaveState)
+        );
+
+        // Holding the combo does not repeat it; pressing its last button again does
+        assert_eq!(hotkeys.press(0, PadButton::A), None);
+        hotkeys.release(0, PadButton::LeftShoulder);
+        assert_eq!(
+            hotkeys.press(0, PadButton::LeftShoulder),
+            Some(HotkeyAction::SaveState)
+        );
+        hotkeys.release(0, PadButton::LeftShoulder);
+        hotkeys.release(0, PadButton::Start);
+        assert_eq!(hotkeys.press(0, PadButton::Start), Some(HotkeyAction::Quit));
+    }
+}
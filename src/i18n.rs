@@ -0,0 +1,485 @@
+// Message catalog
+// User-facing frontend strings are looked up by id, so translations live in one table
+// per language instead of literals spread across modules. Messages take arguments as
+// `{0}`, `{1}`, ...; a message missing from a translation falls back to English.
+
+use std::fmt;
+use std::str::FromStr;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Language {
+    #[default]
+    English,
+    Italian,
+}
+
+impl Language {
+    pub const ALL: [Language; 2] = [Language::English, Language::Italian];
+
+    /// ISO 639-1 code, as written in the config file
+    pub fn code(&self) -> &'static str {
+        match self {
+            Language::English => "en",
+            Language::Italian => "it",
+        }
+    }
+
+    fn messages(&self) -> &'static [(&'static str, &'static str)] {
+        match self {
+            Language::English => ENGLISH,
+            Language::Italian => ITALIAN,
+        }
+    }
+}
+
+impl FromStr for Language {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::ALL
+            .into_iter()
+            .find(|language| language.code() == s)
+            .ok_or_else(|| format!("unknown language '{}' (expected en or it)", s))
+    }
+}
+
+impl fmt::Display for Language {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.code())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Catalog {
+    pub language: Language,
+}
+
+impl Catalog {
+    pub fn new(language: Language) -> Self {
+        Self { language }
+    }
+
+    /// Message text; unknown ids are returned as-is so a typo shows up instead of vanishing
+    pub fn get<'a>(&self, id: &'a str) -> &'a str {
+        let lookup = |language: Language| {
+            language
+                .messages()
+                .iter()
+                .find(|(message_id, _)| *message_id == id)
+                .map(|&(_, text)| text)
+        };
+        lookup(self.language)
+            .or_else(|| lookup(Language::English))
+            .unwrap_or(id)
+    }
+
+    /// Message text with `{0}`, `{1}`, ... replaced by `args`
+    pub fn format(&self, id: &str, args: &[&dyn fmt::Display]) -> String {
+        let mut text = self.get(id).to_string();
+        for (i, arg) in args.iter().enumerate() {
+            text = text.replace(&format!("{{{}}}", i), &arg.to_string());
+        }
+        text
+    }
+}
+
+const ENGLISH: &[(&str, &str)] = &[
+    ("error", "error: {0}"),
+    ("warning", "warning: {0}"),
+    ("usage", "usage:"),
+    ("invalid-address", "invalid address '{0}'"),
+    ("invalid-port", "invalid port '{0}'"),
+    ("unknown-key", "unknown key '{0}' in the key bindings"),
+    ("invalid-hash", "invalid hash '{0}'"),
+    ("unknown-option", "unknown option {0}"),
+    ("option-requires-value", "{0} requires a value"),
+    ("option-requires-number", "{0} requires a number"),
+    ("option-out-of-range", "{0} must be between {1} and {2}"),
+    (
+        "option-requires-headless",
+        "{0} only applies with --headless",
+    ),
+    (
+        "option-requires-screenshots",
+        "{0} only applies with --screenshot-at",
+    ),
+    ("option-requires-trace", "{0} only applies with --trace"),
+    ("options-conflict", "{0} and {1} can't be used together"),
+    ("rtc-missing", "the cartridge has no clock; ignoring {0}"),
+    ("cannot-read", "cannot read {0}: {1}"),
+    ("cannot-write", "cannot write {0}: {1}"),
+    ("config-ignored", "ignoring config file: {0}"),
+    ("config-missing", "no usable config file (set GBEMU_CONFIG)"),
+    ("palette-saved", "Saved palette {0} to {1}"),
+    ("palette-selected", "Palette: {0}"),
+    ("loading-rom", "Loading ROM: {0}"),
+    ("battery-save-loaded", "Loaded battery save {0}"),
+    ("state-saved", "Saved state to {0}"),
+    ("state-loaded", "Loaded state from {0}"),
+    ("turbo-on", "Turbo on"),
+    (
+        "emulation-paused",
+        "Paused (P resumes, N advances one frame)",
+    ),
+    ("emulation-resumed", "Resumed"),
+    ("machine-reset", "Reset"),
+    ("turbo-off", "Turbo off"),
+    ("rom-loaded", "Loaded {0}"),
+    (
+        "stats-summary",
+        "Played {0} over {1} sessions, last on {2} UTC",
+    ),
+    (
+        "stats-missing",
+        "no data directory for play statistics (set GBEMU_STATS)",
+    ),
+    (
+        "legacy-logs-found",
+        "{0} MB of debug logs left by an older version in {1}; delete them with `gbemu_rust clean-logs`",
+    ),
+    ("legacy-log-deleted", "Deleted {0} ({1} MB)"),
+    ("legacy-logs-none", "No old debug logs in {0}"),
+    ("link-waiting", "Waiting for a link partner on {0}"),
+    ("link-connected", "Link cable connected"),
+    ("unsupported-cartridge", "Unsupported cartridge"),
+    (
+        "control-server-listening",
+        "Control server listening on {0}",
+    ),
+    ("controller-connected", "Controller {0} connected: {1}"),
+    ("audio-unavailable", "no audio output: {0}"),
+    ("frame-hash-mismatch", "frame {0}: hash {1}, expected {2}"),
+    ("headless-matched", "Serial output matched after {0} frames"),
+    ("screenshot-saved", "Saved {0}"),
+    (
+        "headless-timed-out",
+        "Serial output did not match within {0} frames",
+    ),
+    (
+        "debugger-paused",
+        "Paused in the debugger; type help for commands, g to resume",
+    ),
+    (
+        "debugger-help",
+        "\
+s, step          execute one instruction
+n, next          step over CALL and RST
+c [FRAMES]       run until a breakpoint hits (at most 600 frames by default)
+b ADDR, d ADDR   set or delete a PC breakpoint
+w ADDR           report when the value at ADDR changes
+rw ADDR, ww ADDR report CPU reads or writes of ADDR
+clear            delete every breakpoint and watchpoint
+l                list breakpoints and watchpoints
+r                dump registers and flags
+x ADDR [LEN]     hex dump memory
+bs, back         undo the last instruction run from the prompt
+lw ADDR          find the instruction that last changed ADDR
+search           start a RAM search with every RAM byte as a candidate
+filter COND      keep the candidates that match COND: eq, ne, gt or lt N, inc, dec,
+                 changed or unchanged (against the previous search)
+candidates [N]   list the first N candidates left (20 by default)
+irq              show interrupt enables, requests and handlers
+map              show the memory map and the selected banks
+mbc              list recent mapper register writes
+cartram [BANK]   hex dump a cartridge RAM bank (0 by default)
+cartpoke BANK ADDR BYTE
+                 write BYTE to ADDR (A000-BFFF) in cartridge RAM bank BANK
+timer            show the divider and timer state
+rtc              show the cartridge clock
+rtc set DAYS:HH:MM:SS, rtc adjust [-]SECS
+                 set the cartridge clock, or move it forward or back
+rtc halt, rtc run
+                 stop or restart the cartridge clock
+g                resume the game
+",
+    ),
+    ("debug-missing-address", "missing address"),
+    ("debug-invalid-bank", "invalid bank '{0}'"),
+    ("debug-invalid-frames", "invalid frame count '{0}'"),
+    ("debug-invalid-count", "invalid count '{0}'"),
+    ("debug-invalid-condition", "invalid search condition '{0}' (try help)"),
+    ("debug-not-cartram", "{0} is not in cartridge RAM (A000-BFFF)"),
+    ("debug-missing-bank", "missing bank"),
+    ("debug-missing-value", "missing value"),
+    ("debug-invalid-byte", "invalid byte '{0}'"),
+    ("debug-missing-time", "missing time"),
+    ("debug-invalid-time", "invalid clock time '{0}' (expected DAYS:HH:MM:SS)"),
+    ("debug-missing-seconds", "missing seconds"),
+    ("debug-invalid-seconds", "invalid seconds '{0}'"),
+    ("debug-unknown-rtc-command", "unknown rtc command '{0}'"),
+    ("debug-unknown-command", "unknown command '{0}' (try help)"),
+    ("debug-unexpected", "unexpected '{0}'"),
+    ("debug-stopped", "Stopped at {0} after {1} cycles"),
+    ("debug-hit", "Hit at {0} after {1} cycles"),
+    ("debug-cycle-limit", "Cycle limit reached at {0} after {1} cycles"),
+    ("debug-breakpoint-set", "Breakpoint at {0}"),
+    ("debug-breakpoint-deleted", "Deleted breakpoint at {0}"),
+    ("debug-no-breakpoint", "No breakpoint at {0}"),
+    ("debug-watching", "Watching {0}"),
+    ("debug-watching-reads", "Watching reads of {0}"),
+    ("debug-watching-writes", "Watching writes to {0}"),
+    ("debug-breakpoints-cleared", "Deleted every breakpoint and watchpoint"),
+    ("debug-no-breakpoints", "No breakpoints or watchpoints"),
+    ("debug-list-breakpoints", "Breakpoints: {0}"),
+    ("debug-list-watch", "Watch: {0}"),
+    ("debug-list-read-watch", "Read watch: {0}"),
+    ("debug-list-write-watch", "Write watch: {0}"),
+    ("debug-back", "Back at {0}"),
+    ("debug-no-history", "No earlier instruction in the history"),
+    ("debug-last-write", "{0} changed from {1} to {2} by the instruction at {3}, {4} instructions ago"),
+    ("debug-no-write", "No change to {0} in the history"),
+    ("debug-searching", "Searching {0} bytes"),
+    ("debug-candidates-left", "{0} candidates left"),
+    ("debug-no-search", "No RAM search started (try search)"),
+    ("debug-candidates", "{0} candidates after {1} searches"),
+    ("debug-more-candidates", "  ... {0} more"),
+    ("debug-no-cartram-bank", "no cartridge RAM bank {0} ({1} bank(s))"),
+    ("debug-cartpoke", "Wrote {0} to {1} in cartridge RAM bank {2}"),
+    ("debug-rtc", "RTC {0}"),
+    ("debug-rtc-halted", "RTC {0}, halted"),
+    ("debug-no-rtc", "The cartridge has no clock"),
+    ("menu-title", "Paused"),
+    ("menu-resume", "Resume"),
+    ("menu-reset", "Reset"),
+    ("menu-slot", "State slot: < {0} >"),
+    ("menu-save-state", "Save state"),
+    ("menu-load-state", "Load state"),
+    ("menu-palette", "Palette"),
+    ("menu-quit", "Quit"),
+    ("window-title", "Game Boy Emulator"),
+    (
+        "window-title-status",
+        "Game Boy Emulator - {0} FPS, {1} emulated ({2})",
+    ),
+];
+
+const ITALIAN: &[(&str, &str)] = &[
+    ("error", "errore: {0}"),
+    ("warning", "attenzione: {0}"),
+    ("usage", "uso:"),
+    ("invalid-address", "indirizzo non valido '{0}'"),
+    ("invalid-port", "porta non valida '{0}'"),
+    (
+        "unknown-key",
+        "tasto sconosciuto '{0}' nelle associazioni dei tasti",
+    ),
+    ("invalid-hash", "hash non valido '{0}'"),
+    ("unknown-option", "opzione sconosciuta {0}"),
+    ("option-requires-value", "{0} richiede un valore"),
+    ("option-requires-number", "{0} richiede un numero"),
+    ("option-out-of-range", "{0} deve essere compreso tra {1} e {2}"),
+    ("option-requires-headless", "{0} vale solo con --headless"),
+    (
+        "option-requires-screenshots",
+        "{0} vale solo con --screenshot-at",
+    ),
+    ("option-requires-trace", "{0} vale solo con --trace"),
+    ("options-conflict", "{0} e {1} non si possono usare insieme"),
+    ("rtc-missing", "la cartuccia non ha un orologio; {0} ignorato"),
+    ("cannot-read", "impossibile leggere {0}: {1}"),
+    ("cannot-write", "impossibile scrivere {0}: {1}"),
+    ("config-ignored", "file di configurazione ignorato: {0}"),
+    (
+        "config-missing",
+        "nessun file di configurazione utilizzabile (impostare GBEMU_CONFIG)",
+    ),
+    ("palette-saved", "Tavolozza {0} salvata in {1}"),
+    ("palette-selected", "Tavolozza: {0}"),
+    ("loading-rom", "Caricamento ROM: {0}"),
+    ("battery-save-loaded", "Salvataggio caricato da {0}"),
+    ("state-saved", "Stato salvato in {0}"),
+    ("state-loaded", "Stato caricato da {0}"),
+    ("turbo-on", "Turbo attivo"),
+    (
+        "emulation-paused",
+        "In pausa (P riprende, N avanza di un fotogramma)",
+    ),
+    ("emulation-resumed", "Ripreso"),
+    ("machine-reset", "Riavviato"),
+    ("turbo-off", "Turbo disattivato"),
+    ("rom-loaded", "Caricato {0}"),
+    (
+        "stats-summary",
+        "Giocato {0} in {1} sessioni, l'ultima il {2} UTC",
+    ),
+    (
+        "stats-missing",
+        "nessuna cartella dati per le statistiche di gioco (impostare GBEMU_STATS)",
+    ),
+    (
+        "legacy-logs-found",
+        "{0} MB di log di debug lasciati da una versione precedente in {1}; eliminali con `gbemu_rust clean-logs`",
+    ),
+    ("legacy-log-deleted", "Eliminato {0} ({1} MB)"),
+    ("legacy-logs-none", "Nessun vecchio log di debug in {0}"),
+    ("link-waiting", "In attesa di un partner per il cavo link su {0}"),
+    ("link-connected", "Cavo link collegato"),
+    ("unsupported-cartridge", "Cartuccia non supportata"),
+    (
+        "control-server-listening",
+        "Server di controllo in ascolto su {0}",
+    ),
+    ("controller-connected", "Controller {0} collegato: {1}"),
+    ("audio-unavailable", "nessuna uscita audio: {0}"),
+    (
+        "frame-hash-mismatch",
+        "fotogramma {0}: hash {1}, atteso {2}",
+    ),
+    (
+        "headless-matched",
+        "Uscita seriale riconosciuta dopo {0} fotogrammi",
+    ),
+    ("screenshot-saved", "Salvato {0}"),
+    (
+        "headless-timed-out",
+        "Uscita seriale non riconosciuta entro {0} fotogrammi",
+    ),
+    (
+        "debugger-paused",
+        "In pausa nel debugger; digita help per i comandi, g per riprendere",
+    ),
+    (
+        "debugger-help",
+        "\
+s, step          esegue un'istruzione
+n, next          esegue CALL e RST fino al ritorno
+c [FRAMES]       esegue fino a un breakpoint (massimo 600 fotogrammi di default)
+b ADDR, d ADDR   imposta o elimina un breakpoint sul PC
+w ADDR           segnala quando cambia il valore in ADDR
+rw ADDR, ww ADDR segnala le letture o le scritture della CPU in ADDR
+clear            elimina tutti i breakpoint e i watchpoint
+l                elenca breakpoint e watchpoint
+r                mostra registri e flag
+x ADDR [LEN]     dump esadecimale della memoria
+bs, back         annulla l'ultima istruzione eseguita dal prompt
+lw ADDR          trova l'ultima istruzione che ha modificato ADDR
+search           avvia una ricerca in RAM con ogni byte della RAM come candidato
+filter COND      tiene i candidati che soddisfano COND: eq, ne, gt o lt N, inc, dec,
+                 changed o unchanged (rispetto alla ricerca precedente)
+candidates [N]   elenca i primi N candidati rimasti (20 se non indicato)
+irq              mostra abilitazioni, richieste e gestori degli interrupt
+map              mostra la mappa della memoria e i banchi selezionati
+mbc              elenca le ultime scritture nei registri del mapper
+cartram [BANK]   dump esadecimale di un banco della RAM della cartuccia (0 se non indicato)
+cartpoke BANK ADDR BYTE
+                 scrive BYTE in ADDR (A000-BFFF) nel banco BANK della RAM della cartuccia
+timer            mostra lo stato del divisore e del timer
+rtc              mostra l'orologio della cartuccia
+rtc set DAYS:HH:MM:SS, rtc adjust [-]SECS
+                 imposta l'orologio della cartuccia, o lo sposta avanti o indietro
+rtc halt, rtc run
+                 ferma o riavvia l'orologio della cartuccia
+g                riprende il gioco
+",
+    ),
+    ("debug-missing-address", "indirizzo mancante"),
+    ("debug-invalid-bank", "banco non valido '{0}'"),
+    ("debug-invalid-frames", "numero di fotogrammi non valido '{0}'"),
+    ("debug-invalid-count", "numero non valido '{0}'"),
+    ("debug-invalid-condition", "condizione di ricerca non valida '{0}' (prova help)"),
+    ("debug-not-cartram", "{0} non fa parte della RAM della cartuccia (A000-BFFF)"),
+    ("debug-missing-bank", "banco mancante"),
+    ("debug-missing-value", "valore mancante"),
+    ("debug-invalid-byte", "byte non valido '{0}'"),
+    ("debug-missing-time", "ora mancante"),
+    ("debug-invalid-time", "ora non valida '{0}' (formato DAYS:HH:MM:SS)"),
+    ("debug-missing-seconds", "secondi mancanti"),
+    ("debug-invalid-seconds", "secondi non validi '{0}'"),
+    ("debug-unknown-rtc-command", "comando rtc sconosciuto '{0}'"),
+    ("debug-unknown-command", "comando sconosciuto '{0}' (prova help)"),
+    ("debug-unexpected", "argomento inatteso '{0}'"),
+    ("debug-stopped", "Fermo a {0} dopo {1} cicli"),
+    ("debug-hit", "Interrotto a {0} dopo {1} cicli"),
+    ("debug-cycle-limit", "Limite di cicli raggiunto a {0} dopo {1} cicli"),
+    ("debug-breakpoint-set", "Breakpoint in {0}"),
+    ("debug-breakpoint-deleted", "Breakpoint in {0} eliminato"),
+    ("debug-no-breakpoint", "Nessun breakpoint in {0}"),
+    ("debug-watching", "Osservo {0}"),
+    ("debug-watching-reads", "Osservo le letture di {0}"),
+    ("debug-watching-writes", "Osservo le scritture in {0}"),
+    ("debug-breakpoints-cleared", "Eliminati tutti i breakpoint e i watchpoint"),
+    ("debug-no-breakpoints", "Nessun breakpoint o watchpoint"),
+    ("debug-list-breakpoints", "Breakpoint: {0}"),
+    ("debug-list-watch", "Watch: {0}"),
+    ("debug-list-read-watch", "Watch in lettura: {0}"),
+    ("debug-list-write-watch", "Watch in scrittura: {0}"),
+    ("debug-back", "Tornato a {0}"),
+    ("debug-no-history", "Nessuna istruzione precedente nella cronologia"),
+    ("debug-last-write", "{0} cambiato da {1} a {2} dall'istruzione in {3}, {4} istruzioni fa"),
+    ("debug-no-write", "Nessuna modifica a {0} nella cronologia"),
+    ("debug-searching", "Ricerca su {0} byte"),
+    ("debug-candidates-left", "{0} candidati rimasti"),
+    ("debug-no-search", "Nessuna ricerca in RAM avviata (prova search)"),
+    ("debug-candidates", "{0} candidati dopo {1} ricerche"),
+    ("debug-more-candidates", "  ... altri {0}"),
+    ("debug-no-cartram-bank", "nessun banco {0} nella RAM della cartuccia ({1} banchi)"),
+    ("debug-cartpoke", "Scritto {0} in {1} nel banco {2} della RAM della cartuccia"),
+    ("debug-rtc", "RTC {0}"),
+    ("debug-rtc-halted", "RTC {0}, fermo"),
+    ("debug-no-rtc", "La cartuccia non ha un orologio"),
+    // The OSD font has no accented letters or apostrophes
+    ("menu-title", "In pausa"),
+    ("menu-resume", "Riprendi"),
+    ("menu-reset", "Riavvia"),
+    ("menu-slot", "Slot stato: < {0} >"),
+    ("menu-save-state", "Salva stato"),
+    ("menu-load-state", "Carica stato"),
+    ("menu-palette", "Tavolozza"),
+    ("menu-quit", "Esci"),
+    ("window-title", "Emulatore Game Boy"),
+    (
+        "window-title-status",
+        "Emulatore Game Boy - {0} FPS, {1} emulati ({2})",
+    ),
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn placeholders(text: &str) -> Vec<&str> {
+        let mut found: Vec<_> = text
+            .match_indices('{')
+            .filter_map(|(start, _)| {
+                text[start..]
+                    .find('}')
+                    .map(|end| &text[start..=start + end])
+            })
+            .collect();
+        found.sort();
+        found
+    }
+
+    #[test]
+    fn test_translations_match_english() {
+        for language in Language::ALL {
+            for &(id, text) in language.messages() {
+                let english = Catalog::default().get(id);
+                assert_ne!(english, id, "{}: '{}' has no English text", language, id);
+                assert_eq!(
+                    placeholders(text),
+                    placeholders(english),
+                    "{}: '{}' arguments differ from English",
+                    language,
+                    id
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_format_and_fallback() {
+        let italian = Catalog::new(Language::Italian);
+        assert_eq!(
+            italian.format("cannot-read", &[&"rom.gb", &"no"]),
+            "impossibile leggere rom.gb: no"
+        );
+        assert_eq!(
+            Catalog::default().format("controller-connected", &[&2, &"Pad"]),
+            "Controller 2 connected: Pad"
+        );
+        assert_eq!(italian.get("no-such-message"), "no-such-message");
+        assert_eq!("it".parse(), Ok(Language::Italian));
+        assert!("xx".parse::<Language>().is_err());
+    }
+}
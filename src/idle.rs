@@ -0,0 +1,122 @@
+// Idle skipping
+// Many games wait for a scanline with a busy loop instead of HALT:
+//     loop: LDH A,(FF44) ; CP n ; JR NZ,loop
+// Emulating every iteration of such a loop is wasted work. Once the loop is recognised,
+// whole iterations are fast-forwarded by advancing the peripherals directly until LY reaches
+// n or an interrupt is pending, then the CPU resumes the loop normally. Where LY is sampled
+// inside an iteration is not modelled, so this is only enabled in the Fast profile.
+
+use crate::memory::Memory;
+
+// Upper bound for one skip, so a loop that can never exit (LCD off, nothing pending)
+// still returns to the caller once per frame
+const MAX_SKIP_CYCLES: u32 = 70224;
+
+/// Recognised `LD A,(LY) ; CP n ; JR NZ` loop
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LySpin {
+    /// LY value the loop waits for
+    pub target: u8,
+    /// M-cycles taken by one iteration that does not exit
+    pub iteration_m_cycles: u32,
+}
+
+/// Check whether the code at `pc` is a busy-wait on LY
+pub fn detect_ly_spin(mem: &Memory, pc: u16) -> Option<LySpin> {
+    let byte = |offset: u16| mem.read_8(pc.wrapping_add(offset));
+
+    // LDH A,(44) is 2 bytes / 3 M-cycles, LD A,(FF44) is 3 bytes / 4 M-cycles
+    let (load_len, load_m_cycles) = match (byte(0), byte(1), byte(2)) {
+        (0xF0, 0x44, _) => (2, 3),
+        (0xFA, 0x44, 0xFF) => (3, 4),
+        _ => return None,
+    };
+    // CP n (2 M-cycles), then a taken JR NZ (3 M-cycles) back to the load
+    let loop_len = load_len + 4;
+    if byte(load_len) != 0xFE
+        || byte(load_len + 2) != 0x20
+        || byte(load_len + 3) as i8 != -(loop_len as i8)
+    {
+        return None;
+    }
+
+    Some(LySpin {
+        target: byte(load_len + 1),
+        iteration_m_cycles: load_m_cycles + 2 + 3,
+    })
+}
+
+fn interrupt_pending(mem: &Memory) -> bool {
+    mem.read_8(0xFFFF) & mem.read_8(0xFF0F) & 0x1F != 0
+}
+
+/// Fast-forward whole iterations of `spin` that would not exit the loop.
+/// Returns the number of T-cycles skipped.
+pub fn skip_ly_spin(mem: &mut Memory, spin: LySpin) -> u32 {
+    let mut skipped = 0;
+    while skipped < MAX_SKIP_CYCLES && mem.ppu.ly() != spin.target && !interrupt_pending(mem) {
+        mem.tick_components(spin.iteration_m_cycles);
+        mem.flush_deferred_ticks();
+        skipped += spin.iteration_m_cycles * 4;
+    }
+    skipped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cpu::{Cpu, Reg16};
+    use crate::model::Model;
+
+    // DI ; loop: LDH A,(44) ; CP 0x90 ; JR NZ,loop ; JR $
+    const WAIT_FOR_VBLANK: [u8; 9] = [0xF3, 0xF0, 0x44, 0xFE, 0x90, 0x20, 0xFA, 0x18, 0xFE];
+
+    fn machine(idle_skip: bool) -> (Cpu, Memory) {
+        let mut rom = vec![0; 0x8000];
+        rom[0x0100..0x0109].copy_from_slice(&WAIT_FOR_VBLANK);
+        let mut mem = Memory::new(rom);
+        mem.skip_boot(Model::Dmg);
+        mem.accuracy.idle_skip = idle_skip;
+        let mut cpu = Cpu::new();
+        cpu.skip_boot(Model::Dmg, 0);
+        (cpu, mem)
+    }
+
+    #[test]
+    fn test_detects_ly_loops() {
+        let (_, mem) = machine(false);
+        assert_eq!(
+            detect_ly_spin(&mem, 0x0101),
+            Some(LySpin {
+                target: 0x90,
+                iteration_m_cycles: 8
+            })
+        );
+        assert_eq!(detect_ly_spin(&mem, 0x0100), None);
+
+        let mut rom = vec![0; 0x8000];
+        rom[0x0200..0x0207].copy_from_slice(&[0xFA, 0x44, 0xFF, 0xFE, 0x00, 0x20, 0xF9]);
+        let mem = Memory::new(rom);
+        assert_eq!(detect_ly_spin(&mem, 0x0200).unwrap().iteration_m_cycles, 9);
+    }
+
+    #[test]
+    fn test_skipping_reaches_same_line_with_fewer_steps() {
+        let run = |idle_skip| {
+            let (mut cpu, mut mem) = machine(idle_skip);
+            let mut steps = 0;
+            while cpu.registers.read_r16(Reg16::PC) != 0x0107 {
+                cpu.step(&mut mem);
+                steps += 1;
+            }
+            (steps, mem.ppu.ly())
+        };
+
+        let (slow_steps, slow_ly) = run(false);
+        let (fast_steps, fast_ly) = run(true);
+        assert_eq!(slow_ly, 0x90);
+        assert_eq!(fast_ly, 0x90);
+        assert!(fast_steps < 10, "{} steps with idle skipping", fast_steps);
+        assert!(slow_steps > 1000);
+    }
+}
@@ -1,6 +1,16 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::str::FromStr;
+use std::sync::Arc;
+
+use crate::sgb::{SgbCommand, SgbPacketReceiver};
+
+/// SGB sound commands kept until the frontend drains them; older ones are dropped
+pub const SGB_SOUND_QUEUE: usize = 16;
 
 /// Game Boy joypad state
+#[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Joypad {
     /// Current button states (true = pressed)
     buttons: HashMap<JoypadButton, bool>,
@@ -16,9 +26,86 @@ pub struct Joypad {
 
     /// Interrupt flag - set when button pressed
     pub interrupt_requested: bool,
+
+    /// Button state injected by `set_buttons`, applied at the next P1 write
+    pending_mask: Option<ButtonMask>,
+
+    /// SGB command receiver, present only when running as a Super Game Boy
+    sgb: Option<SgbPacketReceiver>,
+
+    /// SOUND and SOU_TRN commands received, for the frontend to play or report
+    #[cfg_attr(feature = "serde", serde(skip))]
+    sgb_sounds: VecDeque<SgbCommand>,
+
+    /// Joypads multiplexed through P1 (1, 2 or 4), set by the SGB MLT_REQ command
+    player_count: u8,
+
+    /// Joypad currently visible through P1 (0 = player 1)
+    current_player: u8,
+
+    /// Held buttons of players 2-4
+    extra_players: [ButtonMask; 3],
+
+    /// Host input sampled whenever the game reads P1 (low-latency input)
+    #[cfg_attr(feature = "serde", serde(skip))]
+    input_provider: Option<Arc<dyn InputProvider>>,
+
+    /// Buttons reported by the last `poll_input`, held in addition to pressed ones
+    polled: ButtonMask,
+
+    /// What the game sees while both directions of an axis are held
+    #[cfg_attr(feature = "serde", serde(skip))]
+    opposing: OpposingDirections,
+
+    /// Direction of each axis pressed most recently, which wins under `Forbid`
+    newest_horizontal: JoypadButton,
+    newest_vertical: JoypadButton,
+}
+
+/// Policy for Left+Right and Up+Down held together, which a real D-pad can't produce
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum OpposingDirections {
+    /// Like hardware: the direction pressed last hides the older one until released
+    #[default]
+    Forbid,
+    /// Both reach the game (tool-assisted runs, glitch hunting)
+    Allow,
+}
+
+impl FromStr for OpposingDirections {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "forbid" => Ok(OpposingDirections::Forbid),
+            "allow" => Ok(OpposingDirections::Allow),
+            other => Err(format!(
+                "unknown opposing directions policy '{}' (expected forbid or allow)",
+                other
+            )),
+        }
+    }
+}
+
+impl fmt::Display for OpposingDirections {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            OpposingDirections::Forbid => "forbid",
+            OpposingDirections::Allow => "allow",
+        })
+    }
+}
+
+/// Source of player 1's host input, sampled at the moment the game reads P1 instead of
+/// once per host frame
+pub trait InputProvider: Send + Sync {
+    /// Buttons currently held on the host
+    fn poll(&self) -> ButtonMask;
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum JoypadButton {
     Right,
     Left,
@@ -30,6 +117,87 @@ pub enum JoypadButton {
     Start,
 }
 
+impl JoypadButton {
+    pub const ALL: [JoypadButton; 8] = [
+        JoypadButton::Right,
+        JoypadButton::Left,
+        JoypadButton::Up,
+        JoypadButton::Down,
+        JoypadButton::A,
+        JoypadButton::B,
+        JoypadButton::Select,
+        JoypadButton::Start,
+    ];
+
+    fn name(&self) -> &'static str {
+        match self {
+            JoypadButton::Right => "right",
+            JoypadButton::Left => "left",
+            JoypadButton::Up => "up",
+            JoypadButton::Down => "down",
+            JoypadButton::A => "a",
+            JoypadButton::B => "b",
+            JoypadButton::Select => "select",
+            JoypadButton::Start => "start",
+        }
+    }
+}
+
+impl FromStr for JoypadButton {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        JoypadButton::ALL
+            .into_iter()
+            .find(|button| button.name() == s)
+            .ok_or_else(|| format!("unknown Game Boy button '{}'", s))
+    }
+}
+
+impl fmt::Display for JoypadButton {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// Set of held buttons, one bit per button, for scripted input
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ButtonMask(pub u8);
+
+impl ButtonMask {
+    pub const NONE: ButtonMask = ButtonMask(0);
+
+    pub fn bit(button: JoypadButton) -> u8 {
+        match button {
+            JoypadButton::Right => 0x01,
+            JoypadButton::Left => 0x02,
+            JoypadButton::Up => 0x04,
+            JoypadButton::Down => 0x08,
+            JoypadButton::A => 0x10,
+            JoypadButton::B => 0x20,
+            JoypadButton::Select => 0x40,
+            JoypadButton::Start => 0x80,
+        }
+    }
+
+    pub fn with(self, button: JoypadButton) -> ButtonMask {
+        ButtonMask(self.0 | Self::bit(button))
+    }
+
+    pub fn contains(&self, button: JoypadButton) -> bool {
+        (self.0 & Self::bit(button)) != 0
+    }
+}
+
+impl From<&[JoypadButton]> for ButtonMask {
+    fn from(buttons: &[JoypadButton]) -> Self {
+        buttons
+            .iter()
+            .fold(ButtonMask::NONE, |mask, &button| mask.with(button))
+    }
+}
+
 impl Default for Joypad {
     fn default() -> Self {
         Self::new()
@@ -52,7 +220,148 @@ impl Joypad {
             buttons,
             register: 0xCF, // All buttons released, both groups selected
             interrupt_requested: false,
+            pending_mask: None,
+            sgb: None,
+            sgb_sounds: VecDeque::new(),
+            player_count: 1,
+            current_player: 0,
+            extra_players: [ButtonMask::NONE; 3],
+            input_provider: None,
+            polled: ButtonMask::NONE,
+            opposing: OpposingDirections::default(),
+            newest_horizontal: JoypadButton::Right,
+            newest_vertical: JoypadButton::Down,
+        }
+    }
+
+    pub fn opposing_directions(&self) -> OpposingDirections {
+        self.opposing
+    }
+
+    pub fn set_opposing_directions(&mut self, policy: OpposingDirections) {
+        self.opposing = policy;
+        self.update_register();
+    }
+
+    // Remember `button` as its axis' newest direction
+    fn direction_pressed(&mut self, button: JoypadButton) {
+        match button {
+            JoypadButton::Left | JoypadButton::Right => self.newest_horizontal = button,
+            JoypadButton::Up | JoypadButton::Down => self.newest_vertical = button,
+            _ => {}
+        }
+    }
+
+    /// Sample player 1's input from `provider` on every P1 read, on top of the buttons
+    /// pressed through `press`. `None` goes back to event-driven input only.
+    pub fn set_input_provider(&mut self, provider: Option<Arc<dyn InputProvider>>) {
+        self.input_provider = provider;
+        self.polled = ButtonMask::NONE;
+        self.update_register();
+    }
+
+    /// Refresh the polled input from the input provider, if any. Called right before
+    /// the CPU reads P1.
+    pub fn poll_input(&mut self) {
+        let Some(provider) = &self.input_provider else {
+            return;
+        };
+        let mask = provider.poll();
+        if mask == self.polled {
+            return;
+        }
+
+        // Newly held buttons in a selected group raise the interrupt, as with `press`
+        let previous = self.buttons();
+        self.polled = mask;
+        let pressed = self.buttons().0 & !previous.0;
+        for button in JoypadButton::ALL {
+            if pressed & ButtonMask::bit(button) != 0 {
+                self.direction_pressed(button);
+            }
+        }
+        let mut selected = 0;
+        if self.register & 0x10 == 0 {
+            selected |= 0x0F;
+        }
+        if self.register & 0x20 == 0 {
+            selected |= 0xF0;
         }
+        if pressed & selected != 0 {
+            self.interrupt_requested = true;
+        }
+        self.update_register();
+    }
+
+    /// Listen for SGB command packets on P1 writes
+    pub fn enable_sgb(&mut self) {
+        self.sgb = Some(SgbPacketReceiver::new());
+    }
+
+    /// Whether SGB commands are being received
+    pub fn sgb_enabled(&self) -> bool {
+        self.sgb.is_some()
+    }
+
+    /// SGB sound commands (`Sound` and `SouTrn`) received since the last call, oldest first.
+    /// The Game Boy side is not affected by them; playing the effects is up to the frontend.
+    pub fn drain_sgb_sounds(&mut self) -> impl Iterator<Item = SgbCommand> + '_ {
+        self.sgb_sounds.drain(..)
+    }
+
+    /// Number of joypads multiplexed through P1
+    pub fn player_count(&self) -> u8 {
+        self.player_count
+    }
+
+    /// Joypad currently visible through P1 (0 = player 1)
+    pub fn current_player(&self) -> u8 {
+        self.current_player
+    }
+
+    /// Hold exactly the buttons in `mask` on player 2-4's joypad (`player` 1..=3).
+    /// Player 1 uses press/release like a single joypad.
+    pub fn set_player_buttons(&mut self, player: usize, mask: ButtonMask) {
+        if let Some(state) = player
+            .checked_sub(1)
+            .and_then(|i| self.extra_players.get_mut(i))
+        {
+            *state = mask;
+            self.update_register();
+        }
+    }
+
+    fn set_player_count(&mut self, players: u8) {
+        self.player_count = players;
+        self.current_player = 0;
+    }
+
+    // Buttons the joypad currently visible through P1 presents to the game
+    fn current_mask(&self) -> ButtonMask {
+        let mask = match self.current_player {
+            0 => self.buttons(),
+            player => self.extra_players[player as usize - 1],
+        };
+        match self.opposing {
+            OpposingDirections::Allow => mask,
+            OpposingDirections::Forbid => self.without_older_directions(mask),
+        }
+    }
+
+    // Drop the older direction of each axis held both ways. Players 2-4 have no press
+    // order, so player 1's (Right and Down at first) decides for them too.
+    fn without_older_directions(&self, mut mask: ButtonMask) -> ButtonMask {
+        let axes = [
+            (self.newest_horizontal, JoypadButton::Left, JoypadButton::Right),
+            (self.newest_vertical, JoypadButton::Up, JoypadButton::Down),
+        ];
+        for (newest, first, second) in axes {
+            if mask.contains(first) && mask.contains(second) {
+                let older = if newest == first { second } else { first };
+                mask = ButtonMask(mask.0 & !ButtonMask::bit(older));
+            }
+        }
+        mask
     }
 
     /// Press a button
@@ -60,6 +369,7 @@ impl Joypad {
         if let Some(state) = self.buttons.get_mut(&button) {
             if !*state {
                 *state = true;
+                self.direction_pressed(button);
                 // Only trigger interrupt if the button's group is currently selected
                 let is_direction = matches!(
                     button,
@@ -101,6 +411,33 @@ impl Joypad {
         self.press(button);
     }
 
+    /// Hold exactly the buttons in `mask` (releasing all others) from the next
+    /// input latch point, i.e. the next time the game writes P1 to select a group.
+    /// Lets scripted tests hold input across frames without host key events.
+    pub fn set_buttons(&mut self, mask: ButtonMask) {
+        self.pending_mask = Some(mask);
+    }
+
+    /// Current button state as a mask, including polled host input
+    pub fn buttons(&self) -> ButtonMask {
+        JoypadButton::ALL
+            .iter()
+            .filter(|&&button| self.is_pressed(button))
+            .fold(self.polled, |mask, &button| mask.with(button))
+    }
+
+    fn apply_pending_mask(&mut self) {
+        if let Some(mask) = self.pending_mask.take() {
+            for button in JoypadButton::ALL {
+                if mask.contains(button) {
+                    self.press(button);
+                } else {
+                    self.release(button);
+                }
+            }
+        }
+    }
+
     /// Check if a button is pressed
     pub fn is_pressed(&self, button: JoypadButton) -> bool {
         *self.buttons.get(&button).unwrap_or(&false)
@@ -114,43 +451,27 @@ impl Joypad {
         // Start with upper bits always set (bits 6-7 are always 1 on DMG)
         let mut value = (self.register & 0xF0) | 0xC0;
 
-        // If neither group is selected, all bits are 1
+        // If neither group is selected, all bits are 1, except in SGB multiplayer
+        // mode where the low nibble reads 0xF minus the current joypad ID
         if !select_buttons && !select_directions {
-            value |= 0x0F;
+            value |= if self.player_count > 1 {
+                0x0F - self.current_player
+            } else {
+                0x0F
+            };
         } else {
-            // Start with all bits set (buttons not pressed)
+            // ButtonMask keeps directions in the low nibble and Start/Select/B/A in the high one.
+            // With both groups selected the lines are wired together, so a bit reads 0 when
+            // its button is held in either group.
+            let mask = self.current_mask().0;
             let mut lower = 0x0F;
 
             if select_buttons {
-                // Button keys: Start, Select, B, A
-                if self.is_pressed(JoypadButton::Start) {
-                    lower &= !0x08;
-                }
-                if self.is_pressed(JoypadButton::Select) {
-                    lower &= !0x04;
-                }
-                if self.is_pressed(JoypadButton::B) {
-                    lower &= !0x02;
-                }
-                if self.is_pressed(JoypadButton::A) {
-                    lower &= !0x01;
-                }
+                lower &= !(mask >> 4);
             }
 
             if select_directions {
-                // Direction keys: Down, Up, Left, Right
-                if self.is_pressed(JoypadButton::Down) {
-                    lower &= !0x08;
-                }
-                if self.is_pressed(JoypadButton::Up) {
-                    lower &= !0x04;
-                }
-                if self.is_pressed(JoypadButton::Left) {
-                    lower &= !0x02;
-                }
-                if self.is_pressed(JoypadButton::Right) {
-                    lower &= !0x01;
-                }
+                lower &= !(mask & 0x0F);
             }
 
             value |= lower;
@@ -169,7 +490,26 @@ impl Joypad {
     pub fn write(&mut self, value: u8) {
         // Only bits 4 and 5 are writable from the value
         // Preserve bits 6-7 (typically 1 on DMG), bits 0-3 will be computed by update_register
+        let previous = self.register;
         self.register = (self.register & 0xC0) | (value & 0x30);
+
+        match self.sgb.as_mut().and_then(|sgb| sgb.write(value)) {
+            Some(SgbCommand::MltReq { players }) => self.set_player_count(players),
+            command => {
+                if let Some(sound @ (SgbCommand::Sound(_) | SgbCommand::SouTrn)) = command {
+                    if self.sgb_sounds.len() == SGB_SOUND_QUEUE {
+                        self.sgb_sounds.pop_front();
+                    }
+                    self.sgb_sounds.push_back(sound);
+                }
+                if self.player_count > 1 && previous & 0x20 == 0 && value & 0x20 != 0 {
+                    // The next joypad is selected when P15 goes from low to high
+                    self.current_player = (self.current_player + 1) % self.player_count;
+                }
+            }
+        }
+
+        self.apply_pending_mask();
         self.update_register();
     }
 
@@ -185,18 +525,18 @@ impl Joypad {
     }
 
     /// Get all currently pressed buttons
-    pub fn get_pressed_buttons(&self) -> Vec<JoypadButton> {
+    pub fn get_pressed_buttons(&self) -> impl Iterator<Item = JoypadButton> + '_ {
         self.buttons
             .iter()
             .filter(|(_, &pressed)| pressed)
             .map(|(&button, _)| button)
-            .collect()
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::sync::atomic::{AtomicU8, Ordering};
 
     #[test]
     fn test_joypad_initial_state() {
@@ -221,22 +561,50 @@ mod tests {
     #[test]
     fn test_direction_keys() {
         let mut joypad = Joypad::new();
+        joypad.set_opposing_directions(OpposingDirections::Allow);
 
         // Select direction keys
-        joypad.write(0x10);
-        assert_eq!(joypad.read(), 0x1F); // All released
+        joypad.write(0x20); // P14 low
+        assert_eq!(joypad.read(), 0xEF); // All released, bits 6-7 read 1
 
         joypad.press(JoypadButton::Right);
-        assert_eq!(joypad.read(), 0x1E); // Right pressed (bit 0 = 0)
+        assert_eq!(joypad.read(), 0xEE); // Right pressed (bit 0 = 0)
 
         joypad.press(JoypadButton::Left);
-        assert_eq!(joypad.read(), 0x1C); // Right + Left pressed
+        assert_eq!(joypad.read(), 0xEC); // Right + Left pressed
 
         joypad.press(JoypadButton::Up);
-        assert_eq!(joypad.read(), 0x18); // Right + Left + Up pressed
+        assert_eq!(joypad.read(), 0xE8); // Right + Left + Up pressed
 
         joypad.press(JoypadButton::Down);
-        assert_eq!(joypad.read(), 0x10); // All directions pressed
+        assert_eq!(joypad.read(), 0xE0); // All directions pressed
+    }
+
+    #[test]
+    fn test_forbid_opposing_directions() {
+        let mut joypad = Joypad::new();
+        joypad.write(0x20);
+        joypad.press(JoypadButton::Left);
+        joypad.press(JoypadButton::Up);
+        assert_eq!(joypad.read(), 0xE9);
+
+        // The newer direction of each axis hides the older one while both are held
+        joypad.press(JoypadButton::Right);
+        assert_eq!(joypad.read(), 0xEA);
+        joypad.press(JoypadButton::Down);
+        assert_eq!(joypad.read(), 0xE6);
+        joypad.release(JoypadButton::Right);
+        assert_eq!(joypad.read(), 0xE5);
+
+        // Pressing the older direction again makes it the newer one
+        joypad.release(JoypadButton::Up);
+        joypad.press(JoypadButton::Up);
+        assert_eq!(joypad.read(), 0xE9);
+
+        joypad.set_opposing_directions(OpposingDirections::Allow);
+        assert_eq!(joypad.read(), 0xE1);
+        assert_eq!("Allow".parse(), Ok(OpposingDirections::Allow));
+        assert_eq!(OpposingDirections::Forbid.to_string(), "forbid");
     }
 
     #[test]
@@ -244,32 +612,186 @@ mod tests {
         let mut joypad = Joypad::new();
 
         // Select button keys
-        joypad.write(0x20);
-        assert_eq!(joypad.read(), 0x2F); // All released
+        joypad.write(0x10); // P15 low
+        assert_eq!(joypad.read(), 0xDF); // All released, bits 6-7 read 1
 
         joypad.press(JoypadButton::A);
-        assert_eq!(joypad.read(), 0x2E); // A pressed (bit 0 = 0)
+        assert_eq!(joypad.read(), 0xDE); // A pressed (bit 0 = 0)
 
         joypad.press(JoypadButton::B);
-        assert_eq!(joypad.read(), 0x2C); // A + B pressed
+        assert_eq!(joypad.read(), 0xDC); // A + B pressed
 
         joypad.press(JoypadButton::Select);
-        assert_eq!(joypad.read(), 0x28); // A + B + Select pressed
+        assert_eq!(joypad.read(), 0xD8); // A + B + Select pressed
+
+        joypad.press(JoypadButton::Start);
+        assert_eq!(joypad.read(), 0xD0); // All buttons pressed
+    }
+
+    #[test]
+    fn test_both_groups_selected() {
+        let mut joypad = Joypad::new();
+        joypad.write(0x00);
+        assert_eq!(joypad.read(), 0xCF);
+
+        // Each group pulls down its own lines
+        joypad.press(JoypadButton::A);
+        assert_eq!(joypad.read(), 0xCE);
+        joypad.press(JoypadButton::Up);
+        assert_eq!(joypad.read(), 0xCA);
+
+        // A line held in both groups stays low until both are released
+        joypad.press(JoypadButton::Right);
+        joypad.release(JoypadButton::A);
+        assert_eq!(joypad.read(), 0xCA);
+        joypad.release(JoypadButton::Right);
+        assert_eq!(joypad.read(), 0xCB);
 
+        // Deselecting a group removes its buttons from the read
         joypad.press(JoypadButton::Start);
-        assert_eq!(joypad.read(), 0x20); // All buttons pressed
+        assert_eq!(joypad.read(), 0xC3);
+        joypad.write(0x20);
+        assert_eq!(joypad.read(), 0xEB);
+    }
+
+    #[test]
+    fn test_set_buttons_applies_at_next_latch() {
+        let mut joypad = Joypad::new();
+        let mask = ButtonMask::NONE
+            .with(JoypadButton::Right)
+            .with(JoypadButton::A);
+
+        joypad.set_buttons(mask);
+        assert!(!joypad.is_pressed(JoypadButton::Right));
+
+        // Selecting the direction group latches the injected state
+        joypad.write(0x20);
+        assert!(joypad.is_pressed(JoypadButton::Right));
+        assert!(joypad.is_pressed(JoypadButton::A));
+        assert_eq!(joypad.read() & 0x0F, 0x0E);
+        assert_eq!(joypad.buttons(), mask);
+
+        // The state is held across later latches until replaced
+        joypad.write(0x10);
+        assert_eq!(joypad.buttons(), mask);
+
+        joypad.set_buttons(ButtonMask::NONE);
+        joypad.write(0x20);
+        assert_eq!(joypad.get_pressed_buttons().count(), 0);
+    }
+
+    struct HostKeys(AtomicU8);
+
+    impl InputProvider for HostKeys {
+        fn poll(&self) -> ButtonMask {
+            ButtonMask(self.0.load(Ordering::Relaxed))
+        }
+    }
+
+    #[test]
+    fn test_input_provider_sampled_on_poll() {
+        let keys = Arc::new(HostKeys(Default::default()));
+        let mut joypad = Joypad::new();
+        joypad.set_input_provider(Some(keys.clone()));
+        joypad.write(0x10); // Buttons group
+
+        // Host state changes are invisible until the next poll
+        keys.0
+            .store(ButtonMask::bit(JoypadButton::A), Ordering::Relaxed);
+        assert_eq!(joypad.read() & 0x0F, 0x0F);
+        joypad.poll_input();
+        assert_eq!(joypad.read() & 0x0F, 0x0E);
+        assert!(joypad.interrupt_requested);
+
+        // Polled and pressed buttons combine
+        joypad.press(JoypadButton::Start);
+        assert_eq!(joypad.read() & 0x0F, 0x06);
+        keys.0.store(0, Ordering::Relaxed);
+        joypad.poll_input();
+        assert_eq!(joypad.read() & 0x0F, 0x07);
+
+        joypad.set_input_provider(None);
+        assert_eq!(joypad.buttons(), ButtonMask::NONE.with(JoypadButton::Start));
+    }
+
+    #[test]
+    fn test_button_mask_from_slice() {
+        let mask = ButtonMask::from(&[JoypadButton::Start, JoypadButton::Down][..]);
+        assert!(mask.contains(JoypadButton::Start));
+        assert!(mask.contains(JoypadButton::Down));
+        assert!(!mask.contains(JoypadButton::A));
+    }
+
+    #[test]
+    fn test_sgb_multiplayer() {
+        let mut joypad = Joypad::new();
+        joypad.enable_sgb();
+        joypad.press(JoypadButton::A);
+        joypad.set_player_buttons(1, ButtonMask::NONE.with(JoypadButton::B));
+        joypad.set_player_buttons(3, ButtonMask::NONE.with(JoypadButton::Start));
+
+        // MLT_REQ for 4 players
+        let mut packet = [0; crate::sgb::PACKET_SIZE];
+        packet[0] = (0x11 << 3) | 1;
+        packet[1] = 0x03;
+        for value in crate::sgb::packet_writes(&packet) {
+            joypad.write(value);
+        }
+        assert_eq!(joypad.player_count(), 4);
+
+        // Typical read sequence: ID, then buttons; P15 rising selects the next joypad
+        let mut reads = Vec::new();
+        for _ in 0..4 {
+            joypad.write(0x30);
+            let id = joypad.read() & 0x0F;
+            joypad.write(0x10);
+            reads.push((id, joypad.read() & 0x0F));
+        }
+        assert_eq!(
+            reads,
+            vec![(0x0F, 0x0E), (0x0E, 0x0D), (0x0D, 0x0F), (0x0C, 0x07)]
+        );
+    }
+
+    #[test]
+    fn test_sgb_sound_commands_are_queued() {
+        let mut joypad = Joypad::new();
+        joypad.enable_sgb();
+        let mut packet = [0; crate::sgb::PACKET_SIZE];
+        packet[0] = (0x08 << 3) | 1;
+        packet[1] = 0x01;
+        for _ in 0..SGB_SOUND_QUEUE + 1 {
+            for value in crate::sgb::packet_writes(&packet) {
+                joypad.write(value);
+            }
+        }
+
+        let sounds: Vec<_> = joypad.drain_sgb_sounds().collect();
+        assert_eq!(sounds.len(), SGB_SOUND_QUEUE);
+        assert!(matches!(sounds[0], SgbCommand::Sound(sound) if sound.effect_a == 0x01));
+        assert_eq!(joypad.drain_sgb_sounds().count(), 0);
+        assert_eq!(joypad.player_count(), 1);
+    }
+
+    #[test]
+    fn test_single_player_id_reads_f() {
+        let mut joypad = Joypad::new();
+        joypad.write(0x10);
+        joypad.write(0x30);
+        assert_eq!(joypad.read() & 0x0F, 0x0F);
+        assert_eq!(joypad.current_player(), 0);
     }
 
     #[test]
     fn test_get_pressed_buttons() {
         let mut joypad = Joypad::new();
 
-        assert_eq!(joypad.get_pressed_buttons().len(), 0);
+        assert_eq!(joypad.get_pressed_buttons().count(), 0);
 
         joypad.press(JoypadButton::A);
         joypad.press(JoypadButton::Start);
 
-        let pressed = joypad.get_pressed_buttons();
+        let pressed: Vec<_> = joypad.get_pressed_buttons().collect();
         assert_eq!(pressed.len(), 2);
         assert!(pressed.contains(&JoypadButton::A));
         assert!(pressed.contains(&JoypadButton::Start));
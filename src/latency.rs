@@ -0,0 +1,141 @@
+// Interrupt latency measurement
+// Counts the T-cycles from an interrupt reaching IF to the CPU jumping to its vector,
+// per interrupt type. Long waits usually come from sections that run with IME off (or a
+// handler that runs too long), and show up as audio or video jitter in the game.
+// Off by default; see `Memory::set_interrupt_latency_enabled`.
+
+use std::fmt;
+
+/// Interrupt types in IF bit order, as named in reports and metrics columns
+pub const INTERRUPT_KINDS: [&str; 5] = ["vblank", "stat", "timer", "serial", "joypad"];
+
+/// Latencies of one interrupt type
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct LatencyStats {
+    pub count: u64,
+    /// Sum of every latency, in T-cycles
+    pub total: u64,
+    /// Longest latency, in T-cycles
+    pub max: u32,
+}
+
+impl LatencyStats {
+    pub fn record(&mut self, cycles: u32) {
+        self.count += 1;
+        self.total += cycles as u64;
+        self.max = self.max.max(cycles);
+    }
+
+    pub fn average(&self) -> Option<f64> {
+        (self.count != 0).then(|| self.total as f64 / self.count as f64)
+    }
+}
+
+/// Pending request times and the latencies seen so far, kept by `Memory` while enabled
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct InterruptLatency {
+    // Clock cycle each interrupt was requested at, while it waits in IF
+    requested_at: [Option<u64>; 5],
+    totals: [LatencyStats; 5],
+    // Since the last `take_frame`
+    frame: [LatencyStats; 5],
+}
+
+impl InterruptLatency {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The IF bits in `mask` were set at `cycle`
+    pub fn requested(&mut self, mask: u8, cycle: u64) {
+        for (bit, requested_at) in self.requested_at.iter_mut().enumerate() {
+            if mask & (1 << bit) != 0 {
+                *requested_at = Some(cycle);
+            }
+        }
+    }
+
+    /// The game cleared the IF bits in `mask` itself, so they will not be dispatched
+    pub fn cancelled(&mut self, mask: u8) {
+        for (bit, requested_at) in self.requested_at.iter_mut().enumerate() {
+            if mask & (1 << bit) != 0 {
+                *requested_at = None;
+            }
+        }
+    }
+
+    /// The handler for `interrupt` (IF bit index) starts at `cycle`
+    pub fn dispatched(&mut self, interrupt: usize, cycle: u64) {
+        if let Some(requested_at) = self.requested_at[interrupt].take() {
+            let latency = cycle.saturating_sub(requested_at).min(u32::MAX as u64) as u32;
+            self.totals[interrupt].record(latency);
+            self.frame[interrupt].record(latency);
+        }
+    }
+
+    /// Latencies since enabled, per type in IF bit order
+    pub fn totals(&self) -> &[LatencyStats; 5] {
+        &self.totals
+    }
+
+    /// Latencies since the last call, per type in IF bit order
+    pub fn take_frame(&mut self) -> [LatencyStats; 5] {
+        std::mem::take(&mut self.frame)
+    }
+}
+
+impl fmt::Display for InterruptLatency {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Interrupt latency (T-cycles from IF to handler)")?;
+        writeln!(f, "{:<8} {:>8} {:>8} {:>8}", "type", "count", "avg", "max")?;
+        for (name, stats) in INTERRUPT_KINDS.iter().zip(&self.totals) {
+            match stats.average() {
+                Some(average) => writeln!(
+                    f,
+                    "{:<8} {:>8} {:>8.1} {:>8}",
+                    name, stats.count, average, stats.max
+                )?,
+                None => writeln!(f, "{:<8} {:>8} {:>8} {:>8}", name, 0, "-", "-")?,
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_latency_per_type() {
+        let mut latency = InterruptLatency::new();
+        latency.requested(0x05, 100);
+        latency.dispatched(0, 120);
+        latency.dispatched(2, 400);
+        // Not requested again, so not counted again
+        latency.dispatched(2, 500);
+        latency.requested(0x01, 1000);
+        latency.dispatched(0, 1060);
+        latency.requested(0x08, 2000);
+        latency.cancelled(0x08);
+        latency.dispatched(3, 2100);
+
+        let totals = latency.totals();
+        assert_eq!(
+            totals[0],
+            LatencyStats {
+                count: 2,
+                total: 80,
+                max: 60
+            }
+        );
+        assert_eq!(totals[2].max, 300);
+        assert_eq!(totals[3].count, 0);
+        assert_eq!(latency.take_frame()[0].count, 2);
+        assert_eq!(latency.take_frame()[0].count, 0);
+
+        let text = latency.to_string();
+        assert!(text.contains("vblank          2     40.0       60\n"));
+        assert!(text.contains("serial          0        -        -\n"));
+    }
+}
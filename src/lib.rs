@@ -1,9 +1,103 @@
 // Library interface for gbemu_rust
 // Exposes CPU and Memory modules for testing
 
+//! Game Boy emulator core.
+//!
+//! [`GameBoy`] runs a cartridge one instruction or one frame at a time, and the
+//! [`prelude`] brings in everything needed to drive it:
+//!
+//! ```
+//! use gbemu_rust::prelude::*;
+//!
+//! # fn main() -> Result<(), gbemu_rust::cartridge::CartridgeError> {
+//! // Load a ROM from bytes. This one polls the joypad forever:
+//! // LD A,0x10 ; LDH (P1),A ; JR -6
+//! let mut rom = vec![0; 0x8000];
+//! rom[0x0100..0x0106].copy_from_slice(&[0x3E, 0x10, 0xE0, 0x00, 0x18, 0xFA]);
+//! let mut gb = GameBoy::new(rom, Model::Dmg)?;
+//! gb.memory_mut().accuracy = AccuracyProfile::Balanced.settings();
+//!
+//! // Inject input: hold Start from the next time the game polls the joypad
+//! let start = ButtonMask::NONE.with(Button::Start);
+//! gb.set_buttons(start);
+//!
+//! // Run one frame (70224 T-cycles)
+//! gb.run_frame();
+//! assert_eq!(gb.memory().joypad.buttons(), start);
+//!
+//! // Read the framebuffer: 160x144 pixels, RGB24
+//! assert_eq!(gb.framebuffer().len(), 160 * 144 * 3);
+//!
+//! // Bytes sent over the link port, where test ROMs report their results
+//! assert!(gb.serial_output().is_empty());
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! The component modules ([`cpu`], [`memory`], [`ppu`], ...) stay public for debuggers
+//! and frontends. Their storage is reached through methods rather than fields, so it can
+//! be reorganized without breaking callers.
+
+pub mod accuracy;
+pub mod audio;
+pub mod battery;
+pub mod bindings;
+pub mod cartridge;
+pub mod clock;
+pub mod compat;
+pub mod config;
+#[cfg(feature = "control-server")]
+pub mod control;
 pub mod cpu;
+pub mod debugger;
+pub mod delta;
+pub mod disasm;
+pub mod entropy;
+pub mod freeze;
+pub mod gameboy;
+pub mod headless;
+pub mod highpass;
+pub mod history;
+pub mod hotkeys;
+pub mod i18n;
+pub mod idle;
 pub mod joypad;
+pub mod latency;
+pub mod link;
+pub mod logging;
+pub mod mbc;
 pub mod memory;
+pub mod menu;
+pub mod metrics;
+pub mod model;
+pub mod netlink;
+pub mod osd;
+pub mod palette;
+pub mod png;
 pub mod ppu;
+pub mod profiler;
+pub mod ramsearch;
+pub mod rewind;
+pub mod romimage;
+pub mod rtc;
+pub mod runner;
+pub mod savestate;
 pub mod serial;
+pub mod sgb;
+pub mod stats;
+pub mod strict;
+#[cfg(test)]
+mod testrom;
 pub mod timer;
+pub mod trace;
+
+pub use gameboy::GameBoy;
+
+/// The types most embedders need: `use gbemu_rust::prelude::*;`
+pub mod prelude {
+    pub use crate::accuracy::AccuracyProfile;
+    pub use crate::clock::{EmuDuration, EmuInstant};
+    pub use crate::gameboy::GameBoy;
+    pub use crate::joypad::{ButtonMask, JoypadButton as Button};
+    pub use crate::model::Model;
+}
@@ -0,0 +1,105 @@
+// Link cable
+// A SerialLink is one end of a cable between two Game Boys. The side using the internal
+// clock drives the transfer: once it has shifted its 8 bits out (8 * 512 T-cycles) it
+// swaps bytes with the partner, provided the partner is waiting with SC = 0x80 (external
+// clock). A partner that isn't waiting leaves the line high, so 0xFF comes back, as with
+// no cable. The waiting side's transfer completes when it next looks at the cable.
+//
+// Bytes are swapped whole rather than bit by bit. LinkCable connects two machines in the
+// same process; they can be stepped in turns of a frame or so, since the waiting side
+// keeps waiting however long the clocked side takes to get there.
+
+use std::sync::{Arc, Mutex};
+
+/// One end of a link cable, shared by every copy of the machine plugged into it
+pub trait SerialLink: Send + Sync {
+    /// This side clocked `byte` out. Returns the byte clocked in from the partner, or
+    /// None when the partner wasn't waiting for a transfer.
+    fn exchange(&self, byte: u8) -> Option<u8>;
+
+    /// This side waits on the external clock with `byte` in SB. Returns the byte the
+    /// partner clocked in, once it has.
+    fn poll(&self, byte: u8) -> Option<u8>;
+
+    /// This side stopped waiting (SC bit 7 was cleared or the clock switched)
+    fn cancel(&self);
+}
+
+// State of the wire, indexed by side
+#[derive(Debug, Default)]
+struct Wire {
+    // SB of a side waiting on the external clock
+    waiting: [Option<u8>; 2],
+    // Byte clocked into a waiting side, until it polls
+    delivered: [Option<u8>; 2],
+}
+
+/// Cable between two machines in the same process
+pub struct LinkCable;
+
+impl LinkCable {
+    /// Both ends of a new cable
+    pub fn pair() -> (Arc<LinkPort>, Arc<LinkPort>) {
+        let wire = Arc::new(Mutex::new(Wire::default()));
+        let port = |side| {
+            Arc::new(LinkPort {
+                wire: wire.clone(),
+                side,
+            })
+        };
+        (port(0), port(1))
+    }
+}
+
+/// One end of a `LinkCable`
+#[derive(Debug)]
+pub struct LinkPort {
+    wire: Arc<Mutex<Wire>>,
+    side: usize,
+}
+
+impl SerialLink for LinkPort {
+    fn exchange(&self, byte: u8) -> Option<u8> {
+        let mut wire = self.wire.lock().unwrap();
+        let partner = 1 - self.side;
+        let reply = wire.waiting[partner].take()?;
+        wire.delivered[partner] = Some(byte);
+        Some(reply)
+    }
+
+    fn poll(&self, byte: u8) -> Option<u8> {
+        let mut wire = self.wire.lock().unwrap();
+        let received = wire.delivered[self.side].take();
+        if received.is_none() {
+            wire.waiting[self.side] = Some(byte);
+        }
+        received
+    }
+
+    fn cancel(&self) {
+        let mut wire = self.wire.lock().unwrap();
+        wire.waiting[self.side] = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cable_swaps_bytes_with_a_waiting_partner() {
+        let (master, slave) = LinkCable::pair();
+        // Nobody is waiting yet
+        assert_eq!(master.exchange(0x12), None);
+
+        assert_eq!(slave.poll(0x34), None);
+        assert_eq!(master.exchange(0x56), Some(0x34));
+        assert_eq!(slave.poll(0x34), Some(0x56));
+
+        // A partner that stopped waiting isn't clocked
+        assert_eq!(slave.poll(0x78), None);
+        slave.cancel();
+        assert_eq!(master.exchange(0x9A), None);
+        assert_eq!(slave.poll(0x78), None);
+    }
+}
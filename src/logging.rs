@@ -0,0 +1,211 @@
+// Debug logging
+// Subsystems write diagnostic lines with `gb_log!` under a target (cpu, timer, ppu, mbc).
+// Every target is off until enabled at runtime (`--log`, or GBEMU_LOG in the environment).
+// The enabled set is one atomic, so a disabled target costs a relaxed load in the hot loops.
+// Lines go to stderr unless another sink is installed.
+//
+// Older versions wrote unbounded debug files into the working directory on every run;
+// the frontend looks for leftovers at startup and `clean-logs` deletes them.
+
+use std::fmt;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::sync::Mutex;
+
+/// Subsystem a log line belongs to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LogTarget {
+    /// Invalid opcodes
+    Cpu,
+    /// DIV/TIMA edges, overflows and TMA reloads, per T-cycle
+    Timer,
+    /// LCD switched on or off
+    Ppu,
+    /// Mapper register writes and the banks they map
+    Mbc,
+}
+
+impl LogTarget {
+    pub const ALL: [LogTarget; 4] = [
+        LogTarget::Cpu,
+        LogTarget::Timer,
+        LogTarget::Ppu,
+        LogTarget::Mbc,
+    ];
+
+    fn bit(self) -> u8 {
+        1 << self as u8
+    }
+}
+
+impl fmt::Display for LogTarget {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            LogTarget::Cpu => "cpu",
+            LogTarget::Timer => "timer",
+            LogTarget::Ppu => "ppu",
+            LogTarget::Mbc => "mbc",
+        })
+    }
+}
+
+impl FromStr for LogTarget {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        LogTarget::ALL
+            .into_iter()
+            .find(|target| target.to_string() == s.trim().to_ascii_lowercase())
+            .ok_or_else(|| {
+                format!(
+                    "unknown log target '{}' (expected cpu, timer, ppu, mbc or all)",
+                    s
+                )
+            })
+    }
+}
+
+static ENABLED: AtomicU8 = AtomicU8::new(0);
+static SINK: Mutex<Option<Box<dyn Write + Send>>> = Mutex::new(None);
+
+/// Whether lines for `target` are written
+pub fn enabled(target: LogTarget) -> bool {
+    ENABLED.load(Ordering::Relaxed) & target.bit() != 0
+}
+
+pub fn set_enabled(target: LogTarget, enabled: bool) {
+    if enabled {
+        ENABLED.fetch_or(target.bit(), Ordering::Relaxed);
+    } else {
+        ENABLED.fetch_and(!target.bit(), Ordering::Relaxed);
+    }
+}
+
+/// Parse a comma-separated list of targets such as "timer,mbc"; "all" means every target
+pub fn parse_list(list: &str) -> Result<Vec<LogTarget>, String> {
+    let mut targets = Vec::new();
+    for name in list.split(',').filter(|name| !name.trim().is_empty()) {
+        if name.trim().eq_ignore_ascii_case("all") {
+            targets.extend(LogTarget::ALL);
+        } else {
+            targets.push(name.parse()?);
+        }
+    }
+    Ok(targets)
+}
+
+/// Enable the targets in a list `parse_list` accepts; nothing is enabled if one is unknown
+pub fn enable_list(list: &str) -> Result<(), String> {
+    for target in parse_list(list)? {
+        set_enabled(target, true);
+    }
+    Ok(())
+}
+
+/// Send log lines to `sink` instead of stderr; None goes back to stderr
+pub fn set_sink(sink: Option<Box<dyn Write + Send>>) {
+    *SINK.lock().unwrap_or_else(|err| err.into_inner()) = sink;
+}
+
+/// Write one line for `target`; use `gb_log!`, which skips formatting when it is disabled
+pub fn write(target: LogTarget, args: fmt::Arguments) {
+    let mut sink = SINK.lock().unwrap_or_else(|err| err.into_inner());
+    // Logging must never stop emulation, so write errors are dropped
+    let _ = match sink.as_mut() {
+        Some(sink) => writeln!(sink, "[{}] {}", target, args),
+        None => writeln!(io::stderr(), "[{}] {}", target, args),
+    };
+}
+
+/// Debug files older versions wrote into the working directory, which grew without bound
+pub const LEGACY_LOG_FILES: [&str; 3] = ["ppu_debug.txt", "lcd_debug.txt", "vram_access.txt"];
+
+/// Legacy logs adding up to this many bytes are worth a warning at startup
+pub const LEGACY_LOG_WARN_BYTES: u64 = 64 << 20;
+
+/// Legacy debug files present in `dir`, with their sizes in bytes
+pub fn find_legacy_logs(dir: &Path) -> Vec<(PathBuf, u64)> {
+    LEGACY_LOG_FILES
+        .iter()
+        .map(|name| dir.join(name))
+        .filter_map(|path| {
+            let metadata = std::fs::metadata(&path).ok()?;
+            metadata.is_file().then_some((path, metadata.len()))
+        })
+        .collect()
+}
+
+/// Log a formatted line under a `LogTarget` if that target is enabled
+#[macro_export]
+macro_rules! gb_log {
+    ($target:expr, $($arg:tt)*) => {
+        if $crate::logging::enabled($target) {
+            $crate::logging::write($target, format_args!($($arg)*));
+        }
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    #[derive(Clone, Default)]
+    struct SharedBuffer(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for SharedBuffer {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_parse_targets() {
+        assert_eq!("Timer".parse(), Ok(LogTarget::Timer));
+        assert!("apu".parse::<LogTarget>().is_err());
+        assert!(enable_list("mbc,apu").is_err());
+        assert_eq!(parse_list("all").unwrap(), LogTarget::ALL);
+        assert_eq!(
+            parse_list("mbc, cpu").unwrap(),
+            [LogTarget::Mbc, LogTarget::Cpu]
+        );
+        for target in LogTarget::ALL {
+            assert_eq!(target.to_string().parse(), Ok(target));
+        }
+    }
+
+    // The only test that touches the global state, so parallel tests cannot race it
+    #[test]
+    fn test_enabled_targets_reach_the_sink() {
+        let buffer = SharedBuffer::default();
+        set_sink(Some(Box::new(buffer.clone())));
+        gb_log!(LogTarget::Ppu, "hidden {}", 1);
+        enable_list("ppu").unwrap();
+        gb_log!(LogTarget::Ppu, "shown {}", 2);
+        set_enabled(LogTarget::Ppu, false);
+        set_sink(None);
+
+        let text = String::from_utf8(buffer.0.lock().unwrap().clone()).unwrap();
+        assert!(!text.contains("hidden"));
+        assert!(text.contains("[ppu] shown 2\n"));
+    }
+
+    #[test]
+    fn test_find_legacy_logs() {
+        let dir = std::env::temp_dir().join(format!("gbemu_legacy_logs_{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("vram_access.txt")).unwrap();
+        std::fs::write(dir.join("lcd_debug.txt"), "LCD on\n").unwrap();
+        std::fs::write(dir.join("notes.txt"), "keep").unwrap();
+
+        // Directories that happen to have a legacy name are left alone
+        assert_eq!(find_legacy_logs(&dir), [(dir.join("lcd_debug.txt"), 7)]);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,16 +1,64 @@
-﻿extern crate sdl2;
-use gbemu_rust::cpu::{Cpu, Reg16};
-use gbemu_rust::joypad::JoypadButton;
-use gbemu_rust::memory::Memory;
+extern crate sdl2;
+use gbemu_rust::accuracy::AccuracyProfile;
+use gbemu_rust::audio::{self, SampleConsumer};
+use gbemu_rust::battery::{self, BatterySave};
+use gbemu_rust::bindings::Bindings;
+use gbemu_rust::clock::EmuDuration;
+use gbemu_rust::compat;
+use gbemu_rust::config::Config;
+#[cfg(feature = "control-server")]
+use gbemu_rust::control::ControlServer;
+use gbemu_rust::cpu::Cpu;
+use gbemu_rust::debugger::{
+    CartRamView, DebugCommand, DebugSession, InterruptReport, MbcLog, MemoryMap, RegisterDump,
+    TimerReport,
+};
+use gbemu_rust::disasm::{self, Symbols};
+use gbemu_rust::entropy::{self, SeededRng};
+use gbemu_rust::freeze::{Freeze, RamFreezer};
+use gbemu_rust::gameboy::GameBoy;
+use gbemu_rust::headless::{self, HeadlessLimits, HeadlessOutcome, Screenshots};
+use gbemu_rust::hotkeys::{ComboHotkeys, HotkeyAction, PadButton};
+use gbemu_rust::i18n::Catalog;
+use gbemu_rust::joypad::{ButtonMask, InputProvider, Joypad, JoypadButton, OpposingDirections};
+use gbemu_rust::link::LinkCable;
+use gbemu_rust::logging::{self, LogTarget};
+use gbemu_rust::memory::{Memory, RamInit};
+use gbemu_rust::menu::{Menu, MenuAction, MenuKey};
+use gbemu_rust::metrics::{MetricsFormat, MetricsWriter};
+use gbemu_rust::model::Model;
+use gbemu_rust::netlink::{self, NetLink};
+use gbemu_rust::osd;
+use gbemu_rust::palette::ColorScheme;
+use gbemu_rust::ppu::{ObjectPriority, Ppu};
+use gbemu_rust::rewind::{self, RewindBuffer};
+use gbemu_rust::romimage::RomImage;
+use gbemu_rust::rtc::RtcTime;
+use gbemu_rust::runner::{self, FrameInfo, Input, Runner, SystemClock, Video};
+use gbemu_rust::savestate;
+use gbemu_rust::serial::SerialDevice;
+use gbemu_rust::stats::{self, PlayStats, Session};
+use gbemu_rust::trace::{PcRange, TraceFilter, Tracer};
+use std::cell::RefCell;
 use std::env;
 use std::fs::File;
-use std::io::Read;
-use std::time::{Duration, Instant};
+use std::io::{self, BufRead, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::rc::Rc;
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
+use sdl2::audio::{AudioCallback, AudioSpecDesired};
+use sdl2::controller::{Button, GameController};
 use sdl2::event::Event;
-use sdl2::keyboard::Keycode;
+use sdl2::keyboard::{Keycode, Mod, Scancode};
+use sdl2::messagebox::{show_simple_message_box, MessageBoxFlag};
 use sdl2::pixels::PixelFormatEnum;
 use sdl2::rect::Rect;
+use sdl2::render::{Canvas, Texture};
+use sdl2::video::Window;
+use sdl2::{EventPump, GameControllerSubsystem};
 
 const SCREEN_WIDTH: u32 = 160;
 const SCREEN_HEIGHT: u32 = 144;
@@ -18,195 +66,2162 @@ const SCALE: u32 = 4;
 const WINDOW_WIDTH: u32 = SCREEN_WIDTH * SCALE;
 const WINDOW_HEIGHT: u32 = SCREEN_HEIGHT * SCALE;
 
-fn map_keycode_to_button(keycode: Keycode) -> Option<JoypadButton> {
-    match keycode {
-        Keycode::Right => Some(JoypadButton::Right),
-        Keycode::Left => Some(JoypadButton::Left),
-        Keycode::Up => Some(JoypadButton::Up),
-        Keycode::Down => Some(JoypadButton::Down),
-        Keycode::Z => Some(JoypadButton::A),
-        Keycode::X => Some(JoypadButton::B),
-        Keycode::Return => Some(JoypadButton::Start),
-        Keycode::RShift | Keycode::LShift => Some(JoypadButton::Select),
+// Output rate, and host audio buffer in stereo frames (~85 ms at 48 kHz)
+const AUDIO_SAMPLE_RATE: i32 = 48_000;
+const AUDIO_BUFFER_FRAMES: usize = 4096;
+// Frames a headless run gives up after without --frames (one emulated minute)
+const HEADLESS_FRAMES: u64 = 3600;
+
+const VERIFY_DIR_USAGE: &str = "gbemu_rust verify-dir DIR [--frames N] [--jobs N]";
+const PALETTE_USAGE: &str = "gbemu_rust palette [green|gray|pocket|RRGGBB,RRGGBB,RRGGBB,RRGGBB]";
+const MAP_USAGE: &str = "gbemu_rust map ROM";
+const BISECT_FRAME_USAGE: &str = "gbemu_rust bisect-frame ROM --frame N [--expect HASH]";
+const STATS_USAGE: &str = "gbemu_rust stats";
+const CLEAN_LOGS_USAGE: &str = "gbemu_rust clean-logs [DIR]";
+const REWIND_BENCH_USAGE: &str =
+    "gbemu_rust rewind-bench ROM [--frames N] [--rewind-interval N] [--rewind-memory MB]";
+const DISASM_USAGE: &str =
+    "gbemu_rust disasm ROM [--bank N] [--start ADDR] [--len LEN] [--sym FILE]";
+
+// Keyboard bindings resolved to SDL keycodes
+#[derive(Clone)]
+struct KeyMap(Vec<(Keycode, JoypadButton)>);
+
+impl KeyMap {
+    // Keys SDL has no name for are left out and returned
+    fn new(bindings: &Bindings) -> (Self, Vec<String>) {
+        let mut keys = Vec::new();
+        let mut unknown = Vec::new();
+        for button in JoypadButton::ALL {
+            for name in bindings.keys(button) {
+                match Keycode::from_name(name) {
+                    Some(key) => keys.push((key, button)),
+                    None => unknown.push(name.clone()),
+                }
+            }
+        }
+        (KeyMap(keys), unknown)
+    }
+
+    fn buttons(&self, key: Keycode) -> impl Iterator<Item = JoypadButton> + '_ {
+        self.0
+            .iter()
+            .filter(move |&&(bound, _)| bound == key)
+            .map(|&(_, button)| button)
+    }
+}
+
+// Keyboard state read from SDL whenever the game reads P1 (`--low-latency-input`),
+// instead of waiting for the key events handled once per host frame
+struct SdlKeyboardInput {
+    keys: KeyMap,
+}
+
+impl InputProvider for SdlKeyboardInput {
+    fn poll(&self) -> ButtonMask {
+        // SAFETY: SDL stays initialised while the emulator runs, and the emulator runs on
+        // the thread that initialised it. The state array is owned by SDL for its lifetime.
+        let state = unsafe {
+            sdl2::sys::SDL_PumpEvents();
+            let mut len = 0;
+            let keys = sdl2::sys::SDL_GetKeyboardState(&mut len);
+            std::slice::from_raw_parts(keys, len as usize)
+        };
+        self.keys
+            .0
+            .iter()
+            .filter(|&&(key, _)| {
+                Scancode::from_keycode(key)
+                    .and_then(|scancode| state.get(scancode as usize))
+                    .is_some_and(|&held| held != 0)
+            })
+            .fold(ButtonMask::NONE, |mask, &(_, button)| mask.with(button))
+    }
+}
+
+// Controller buttons as named in combo hotkeys
+fn map_pad_button(button: Button) -> Option<PadButton> {
+    match button {
+        Button::A => Some(PadButton::A),
+        Button::B => Some(PadButton::B),
+        Button::X => Some(PadButton::X),
+        Button::Y => Some(PadButton::Y),
+        Button::Back => Some(PadButton::Select),
+        Button::Guide => Some(PadButton::Guide),
+        Button::Start => Some(PadButton::Start),
+        Button::LeftStick => Some(PadButton::LeftStick),
+        Button::RightStick => Some(PadButton::RightStick),
+        Button::LeftShoulder => Some(PadButton::LeftShoulder),
+        Button::RightShoulder => Some(PadButton::RightShoulder),
+        Button::DPadUp => Some(PadButton::Up),
+        Button::DPadDown => Some(PadButton::Down),
+        Button::DPadLeft => Some(PadButton::Left),
+        Button::DPadRight => Some(PadButton::Right),
         _ => None,
     }
 }
 
-fn main() {
-    std::env::set_var("RUST_BACKTRACE", "1");
+// Player a controller drives, from its position in connection order and the position of
+// the one chosen with --controller: that one is player 1 (shared with the keyboard) and
+// the others follow it in order. Players 2-4 are only visible to the game in SGB
+// multiplayer mode.
+fn controller_player(position: usize, first: usize) -> usize {
+    match position.cmp(&first) {
+        std::cmp::Ordering::Equal => 0,
+        std::cmp::Ordering::Less => position + 1,
+        std::cmp::Ordering::Greater => position,
+    }
+}
 
-    let args: Vec<String> = env::args().collect();
-    let rom_path = if args.len() > 1 {
-        args[1].clone()
-    } else {
-        String::from("roms/test_roms/instr_timing.gb")
-    };
+fn controller_input(
+    joypad: &mut Joypad,
+    player_masks: &mut [ButtonMask; 4],
+    player: usize,
+    button: JoypadButton,
+    pressed: bool,
+) {
+    if player == 0 {
+        if pressed {
+            joypad.press_button(button);
+        } else {
+            joypad.release_button(button);
+        }
+    } else if let Some(mask) = player_masks.get_mut(player) {
+        *mask = if pressed {
+            mask.with(button)
+        } else {
+            ButtonMask(mask.0 & !ButtonMask::bit(button))
+        };
+        joypad.set_player_buttons(player, *mask);
+    }
+}
 
-    println!("Loading ROM: {}", rom_path);
+// Frames the cartridge banner stays up after loading (3 seconds)
+const BANNER_FRAMES: u32 = 180;
 
-    // Initialize SDL2
-    let sdl_context = sdl2::init().expect("Failed to initialize SDL2");
-    let video_subsystem = sdl_context
-        .video()
-        .expect("Failed to initialize video subsystem");
+// Window showing the LCD, scaled up
+struct SdlVideo<'a> {
+    canvas: Canvas<Window>,
+    texture: Texture<'a>,
+    // --link-local: the second machine's picture, shown to the right of the first
+    partner_texture: Option<Texture<'a>>,
+    lcd_was_on: bool,
+    // The window already shows this frame
+    unchanged: bool,
+    // Pause menu drawn over the game while open, shared with SdlInput
+    menu: Rc<RefCell<Option<Menu>>>,
+    // Language of the menu
+    catalog: Catalog,
+    // Lines shown along the bottom of the picture, and the frames left to show them
+    banner: Option<(Vec<String>, u32)>,
+}
 
-    // Create a window
-    let window = video_subsystem
-        .window("Game Boy Emulator", WINDOW_WIDTH, WINDOW_HEIGHT)
-        .position_centered()
-        .build()
-        .expect("Failed to create window");
+impl SdlVideo<'_> {
+    // Show a whole frame with an overlay drawn into it
+    fn draw_overlaid(&mut self, frame: &[u8]) {
+        let pitch = (SCREEN_WIDTH * 3) as usize;
+        self.texture
+            .update(None, frame, pitch)
+            .expect("Failed to update texture");
+        self.unchanged = false;
+        self.canvas.clear();
+        let dst_rect = Rect::new(0, 0, WINDOW_WIDTH, WINDOW_HEIGHT);
+        self.canvas
+            .copy(&self.texture, None, Some(dst_rect))
+            .expect("Failed to copy texture");
+    }
+}
 
-    // Create a canvas
-    let mut canvas = window
-        .into_canvas()
-        .build()
-        .expect("Failed to create canvas");
-    let texture_creator = canvas.texture_creator();
-    let mut texture = texture_creator
-        .create_texture_streaming(PixelFormatEnum::RGB24, SCREEN_WIDTH, SCREEN_HEIGHT)
-        .expect("Failed to create texture");
+impl Video for SdlVideo<'_> {
+    fn draw(&mut self, ppu: &mut Ppu, info: &FrameInfo) {
+        let pitch = (SCREEN_WIDTH * 3) as usize;
+        if let Some(menu) = self.menu.clone().borrow().as_ref() {
+            // Redrawn in full each frame; closing the menu marks every row dirty
+            let mut frame = ppu.framebuffer.to_vec();
+            menu.render(&mut frame, &self.catalog);
+            self.draw_overlaid(&frame);
+            return;
+        }
+        if let Some((lines, frames_left)) = self.banner.as_mut() {
+            *frames_left -= 1;
+            if *frames_left == 0 {
+                // Bring back the rows the banner covered
+                self.banner = None;
+                ppu.mark_all_rows_dirty();
+            } else {
+                let mut frame = ppu.framebuffer.to_vec();
+                let lines: Vec<_> = lines.iter().map(String::as_str).collect();
+                osd::draw_banner(&mut frame, &lines);
+                self.draw_overlaid(&frame);
+                return;
+            }
+        }
+        // Once the LCD has been off for a whole frame the picture stays put
+        self.unchanged = !info.lcd_on && !self.lcd_was_on && ppu.pixel_info.is_none();
+        self.lcd_was_on = info.lcd_on;
+        if self.unchanged {
+            return;
+        }
+        match ppu.layer_view() {
+            Some(layer_view) => self
+                .texture
+                .update(None, &layer_view, pitch)
+                .expect("Failed to update texture"),
+            // Only upload the rows that changed since the last frame
+            None => {
+                if let Some(rows) = ppu.take_dirty_rows() {
+                    let rect = Rect::new(0, rows.start as i32, SCREEN_WIDTH, rows.len() as u32);
+                    self.texture
+                        .update(
+                            rect,
+                            &ppu.framebuffer[rows.start * pitch..rows.end * pitch],
+                            pitch,
+                        )
+                        .expect("Failed to update texture");
+                }
+            }
+        }
 
-    // Load ROM
-    let mut rom_file = File::open(&rom_path).expect("Failed to open ROM file");
-    let mut rom_buffer = Vec::new();
-    rom_file
-        .read_to_end(&mut rom_buffer)
-        .expect("Failed to read ROM file");
+        self.canvas.clear();
+        let dst_rect = Rect::new(0, 0, WINDOW_WIDTH, WINDOW_HEIGHT);
+        self.canvas
+            .copy(&self.texture, None, Some(dst_rect))
+            .expect("Failed to copy texture");
+    }
 
-    // Initialize emulator components
-    let mut mem = Memory::new(rom_buffer.clone());
-    mem.init_rom_bank();
-    mem.init_post_boot_state();
+    fn draw_partner(&mut self, ppu: &mut Ppu, _info: &FrameInfo) {
+        let pitch = (SCREEN_WIDTH * 3) as usize;
+        let Some(texture) = self.partner_texture.as_mut() else {
+            return;
+        };
+        if let Some(rows) = ppu.take_dirty_rows() {
+            let rect = Rect::new(0, rows.start as i32, SCREEN_WIDTH, rows.len() as u32);
+            texture
+                .update(
+                    rect,
+                    &ppu.framebuffer[rows.start * pitch..rows.end * pitch],
+                    pitch,
+                )
+                .expect("Failed to update texture");
+        }
+        // Both halves again, since draw leaves the canvas alone when its picture stays put
+        self.unchanged = false;
+        let left = Rect::new(0, 0, WINDOW_WIDTH, WINDOW_HEIGHT);
+        let right = Rect::new(WINDOW_WIDTH as i32, 0, WINDOW_WIDTH, WINDOW_HEIGHT);
+        self.canvas
+            .copy(&self.texture, None, Some(left))
+            .expect("Failed to copy texture");
+        self.canvas
+            .copy(texture, None, Some(right))
+            .expect("Failed to copy texture");
+    }
 
-    let mut cpu = Cpu::new();
-    cpu.registers.write_r16(Reg16::AF, 0x01B0);
-    cpu.registers.write_r16(Reg16::BC, 0x0013);
-    cpu.registers.write_r16(Reg16::DE, 0x00D8);
-    cpu.registers.write_r16(Reg16::HL, 0x014D);
-    cpu.registers.write_r16(Reg16::SP, 0xFFFE);
-    cpu.registers.write_r16(Reg16::PC, 0x0100);
-    cpu.registers.ime = 1; // Interrupts enabled after boot ROM
-
-    // Main emulation loop
-    let mut event_pump = sdl_context
-        .event_pump()
-        .expect("Failed to get SDL event pump");
-    let frame_duration = Duration::from_secs_f64(1.0 / 60.0);
-    let mut last_frame = Instant::now();
-
-    // FPS counter
-    let mut fps_counter = 0u32;
-    let mut fps_timer = Instant::now();
-    let mut current_fps;
-
-    // Dynamic estimate for presentation time (exponential moving average)
-    let mut estimated_present_time = Duration::from_micros(0);
-    const PRESENT_TIME_ALPHA: f64 = 0.2; // Smoothing factor for EMA
-
-    // Serial forwarding state (mirror final_test harness)
-    let mut last_serial_len: usize = 0;
-
-    'running: loop {
-        // Handle SDL events
-        for event in event_pump.poll_iter() {
+    fn present(&mut self) {
+        if !self.unchanged {
+            self.canvas.present();
+        }
+    }
+
+    fn set_status(&mut self, status: &str) {
+        self.canvas
+            .window_mut()
+            .set_title(status)
+            .expect("Failed to set window title");
+    }
+}
+
+// Drains the sample ring on SDL's audio thread
+struct SdlAudio {
+    samples: SampleConsumer,
+}
+
+impl AudioCallback for SdlAudio {
+    type Channel = i16;
+
+    fn callback(&mut self, out: &mut [i16]) {
+        self.samples.fill(out);
+    }
+}
+
+// Keyboard, game controllers and debugger hotkeys
+struct SdlInput {
+    event_pump: EventPump,
+    controller_subsystem: GameControllerSubsystem,
+    // Game controllers, in the order they were connected
+    controllers: Vec<GameController>,
+    // Position of the controller that plays player 1; None ignores controllers
+    first_controller: Option<usize>,
+    keys: KeyMap,
+    bindings: Bindings,
+    player_masks: [ButtonMask; 4],
+    // --link-local: controller buttons go to the second machine, queued until poll_partner
+    partner_presses: Option<Vec<(JoypadButton, bool)>>,
+    // Keyboard is sampled by SdlKeyboardInput instead of handled here
+    low_latency: bool,
+    config: Option<(PathBuf, Config)>,
+    catalog: Catalog,
+    // Shift+F5 saves the machine to the selected slot, Shift+F8 restores it. Slot 0 is
+    // this path, the others add their number to the extension.
+    state_path: PathBuf,
+    state_slot: u8,
+    // Savestate use this session, for the play statistics
+    states_saved: u64,
+    states_loaded: u64,
+    // Controller button combos from the config file
+    combos: ComboHotkeys,
+    // Held with P; N lets exactly one frame through
+    paused: bool,
+    advance_frame: bool,
+    // Run unthrottled: turned on and off by the turbo hotkey, or while Tab is held
+    turbo: bool,
+    fast_forward_held: bool,
+    // Backspace is down, stepping back through the rewind history
+    rewind_held: bool,
+    // Shared with SdlVideo: the open pause menu, which holds emulation
+    menu: Rc<RefCell<Option<Menu>>>,
+    // Model the menu resets to
+    model: Model,
+    // Events of the current poll, kept to reuse the allocation
+    events: Vec<Event>,
+    // Breakpoints and history of the F9 debugger prompt, kept between visits
+    debug_session: DebugSession,
+}
+
+impl SdlInput {
+    fn slot_path(&self) -> PathBuf {
+        match self.state_slot {
+            0 => self.state_path.clone(),
+            slot => self.state_path.with_extension(format!("state{}", slot)),
+        }
+    }
+
+    // Save the whole machine next to the ROM
+    fn save_state(&mut self, cpu: &Cpu, mem: &Memory) {
+        let catalog = self.catalog;
+        let path = self.slot_path();
+        match std::fs::write(&path, savestate::save_state(cpu, mem)) {
+            Ok(()) => {
+                self.states_saved += 1;
+                println!("{}", catalog.format("state-saved", &[&path.display()]))
+            }
+            Err(err) => {
+                let err = catalog.format("cannot-write", &[&path.display(), &err]);
+                eprintln!("{}", catalog.format("warning", &[&err]));
+            }
+        }
+    }
+
+    // Restore the state saved with `save_state`
+    fn load_state(&mut self, cpu: &mut Cpu, mem: &mut Memory) {
+        let catalog = self.catalog;
+        let path = self.slot_path();
+        let loaded = std::fs::read(&path)
+            .map_err(|err| err.to_string())
+            .and_then(|data| savestate::load_state(cpu, mem, &data));
+        match loaded {
+            Ok(()) => {
+                self.states_loaded += 1;
+                println!("{}", catalog.format("state-loaded", &[&path.display()]))
+            }
+            Err(err) => {
+                let err = catalog.format("cannot-read", &[&path.display(), &err]);
+                eprintln!("{}", catalog.format("warning", &[&err]));
+            }
+        }
+    }
+
+    // Switch to the next preset palette and keep it for later runs
+    fn next_palette(&mut self, mem: &mut Memory) {
+        let catalog = self.catalog;
+        let (name, scheme) = mem.ppu.color_scheme().next_preset();
+        mem.ppu.set_color_scheme(scheme);
+        println!("{}", catalog.format("palette-selected", &[&name]));
+        if let Some((path, config)) = self.config.as_mut() {
+            config.set_color_scheme(scheme);
+            if let Err(err) = config.save(path) {
+                let err = catalog.format("cannot-write", &[&path.display(), &err]);
+                eprintln!("{}", catalog.format("warning", &[&err]));
+            }
+        }
+    }
+
+    // Action bound to a controller combo; false to quit
+    fn run_action(&mut self, action: HotkeyAction, cpu: &mut Cpu, mem: &mut Memory) -> bool {
+        match action {
+            HotkeyAction::SaveState => self.save_state(cpu, mem),
+            HotkeyAction::LoadState => self.load_state(cpu, mem),
+            HotkeyAction::ToggleTurbo => {
+                self.turbo = !self.turbo;
+                let key = if self.turbo { "turbo-on" } else { "turbo-off" };
+                println!("{}", self.catalog.get(key));
+            }
+            HotkeyAction::NextPalette => self.next_palette(mem),
+            HotkeyAction::ToggleLayerView => toggle_layer_view(mem),
+            HotkeyAction::Menu => self.toggle_menu(mem),
+            // Lasts while the combo is held, see `rewinding`
+            HotkeyAction::Rewind => {}
+            HotkeyAction::Quit => return false,
+        }
+        true
+    }
+
+    // Power-cycle the machine with the same cartridge
+    fn reset(&mut self, cpu: &mut Cpu, mem: &mut Memory) {
+        mem.reset(self.model);
+        *cpu = Cpu::new();
+        // With a boot ROM, the CPU starts over at 0x0000 to run it again
+        if !mem.boot_rom_mapped() {
+            cpu.skip_boot(self.model, mem.read_8(0x014D));
+        }
+    }
+
+    fn menu_open(&self) -> bool {
+        self.menu.borrow().is_some()
+    }
+
+    fn toggle_menu(&mut self, mem: &mut Memory) {
+        let mut menu = self.menu.borrow_mut();
+        if menu.take().is_some() {
+            // Bring back the game picture the menu was drawn over
+            mem.ppu.mark_all_rows_dirty();
+        } else {
+            *menu = Some(Menu::new(self.state_slot));
+        }
+    }
+
+    // F9: hold the game and read debugger commands from stdin until one resumes it. The
+    // window is not redrawn while the prompt is open.
+    fn debug_prompt(&mut self, cpu: &mut Cpu, mem: &mut Memory) {
+        // The game ran unrecorded since the prompt was last open
+        self.debug_session.history.clear();
+        println!("{}", self.catalog.get("debugger-paused"));
+        print!("{}", RegisterDump::capture(cpu, mem));
+        let mut line = String::new();
+        loop {
+            print!("(gb) ");
+            let _ = io::stdout().flush();
+            line.clear();
+            if matches!(io::stdin().lock().read_line(&mut line), Ok(0) | Err(_)) {
+                break;
+            }
+            if line.trim().is_empty() {
+                continue;
+            }
+            match DebugCommand::parse(&line, &self.catalog) {
+                Ok(DebugCommand::Resume) => break,
+                Ok(command) => print!(
+                    "{}",
+                    command.execute(cpu, mem, &mut self.debug_session, &self.catalog)
+                ),
+                Err(err) => println!("{}", err),
+            }
+        }
+        mem.ppu.mark_all_rows_dirty();
+    }
+
+    // Navigate the open menu and carry out what it selects; false to quit
+    fn menu_input(&mut self, key: MenuKey, cpu: &mut Cpu, mem: &mut Memory) -> bool {
+        let action = match self.menu.borrow_mut().as_mut() {
+            Some(menu) => {
+                let action = menu.input(key);
+                self.state_slot = menu.slot();
+                action
+            }
+            None => return true,
+        };
+        match action {
+            None => return true,
+            Some(MenuAction::NextPalette) => {
+                // Stays open: the new colors show once the game runs again
+                self.next_palette(mem);
+                return true;
+            }
+            Some(MenuAction::Resume) => {}
+            Some(MenuAction::Reset) => self.reset(cpu, mem),
+            Some(MenuAction::SaveState(_)) => self.save_state(cpu, mem),
+            Some(MenuAction::LoadState(_)) => self.load_state(cpu, mem),
+            Some(MenuAction::Quit) => return false,
+        }
+        self.toggle_menu(mem);
+        true
+    }
+}
+
+fn map_menu_key(key: Keycode) -> Option<MenuKey> {
+    match key {
+        Keycode::Up => Some(MenuKey::Up),
+        Keycode::Down => Some(MenuKey::Down),
+        Keycode::Left => Some(MenuKey::Left),
+        Keycode::Right => Some(MenuKey::Right),
+        Keycode::Return | Keycode::Z => Some(MenuKey::Confirm),
+        Keycode::Escape | Keycode::X | Keycode::Backspace => Some(MenuKey::Back),
+        _ => None,
+    }
+}
+
+fn map_menu_button(button: Button) -> Option<MenuKey> {
+    match button {
+        Button::DPadUp => Some(MenuKey::Up),
+        Button::DPadDown => Some(MenuKey::Down),
+        Button::DPadLeft => Some(MenuKey::Left),
+        Button::DPadRight => Some(MenuKey::Right),
+        Button::A => Some(MenuKey::Confirm),
+        Button::B | Button::Start | Button::Guide => Some(MenuKey::Back),
+        _ => None,
+    }
+}
+
+// Debug view: color pixels by the layer they came from
+fn toggle_layer_view(mem: &mut Memory) {
+    let show_layers = mem.ppu.pixel_info.is_none();
+    mem.ppu.set_pixel_info_enabled(show_layers);
+    mem.ppu.mark_all_rows_dirty();
+}
+
+impl Input for SdlInput {
+    fn poll(&mut self, cpu: &mut Cpu, mem: &mut Memory) -> bool {
+        let catalog = self.catalog;
+        // Collected first so that handlers can borrow the whole input
+        self.advance_frame = false;
+        let mut events = std::mem::take(&mut self.events);
+        events.extend(self.event_pump.poll_iter());
+        for event in events.drain(..) {
             match event {
-                Event::Quit { .. } => break 'running,
+                Event::Quit { .. } => return false,
+                // The open menu takes every key press; releases still reach the game
+                Event::KeyDown {
+                    keycode: Some(key), ..
+                } if self.menu_open() => {
+                    if let Some(key) = map_menu_key(key) {
+                        if !self.menu_input(key, cpu, mem) {
+                            return false;
+                        }
+                    }
+                }
+                Event::ControllerButtonDown { which, button, .. } if self.menu_open() => {
+                    if let Some(pad_button) = map_pad_button(button) {
+                        self.combos.press(which, pad_button);
+                    }
+                    if let Some(key) = map_menu_button(button) {
+                        if !self.menu_input(key, cpu, mem) {
+                            return false;
+                        }
+                    }
+                }
+                Event::KeyDown {
+                    keycode: Some(Keycode::Escape),
+                    ..
+                } => self.toggle_menu(mem),
+                Event::KeyDown {
+                    keycode: Some(Keycode::F2),
+                    ..
+                } => {
+                    // Debugger: dump interrupt configuration
+                    print!("{}", InterruptReport::capture(cpu, mem));
+                }
+                Event::KeyDown {
+                    keycode: Some(Keycode::F5),
+                    keymod,
+                    ..
+                } if keymod.intersects(Mod::LSHIFTMOD | Mod::RSHIFTMOD) => {
+                    self.save_state(cpu, mem)
+                }
+                Event::KeyDown {
+                    keycode: Some(Keycode::F8),
+                    keymod,
+                    ..
+                } if keymod.intersects(Mod::LSHIFTMOD | Mod::RSHIFTMOD) => {
+                    self.load_state(cpu, mem)
+                }
+                Event::KeyDown {
+                    keycode: Some(Keycode::F5),
+                    ..
+                } => {
+                    // Debugger: dump the memory map, including the current ROM bank
+                    print!("{}", MemoryMap::capture(mem));
+                }
+                Event::KeyDown {
+                    keycode: Some(Keycode::F6),
+                    ..
+                } => {
+                    // Debugger: recent mapper register writes and the banks they selected
+                    print!("{}", MbcLog::capture(mem));
+                }
+                Event::KeyDown {
+                    keycode: Some(Keycode::F7),
+                    ..
+                } => {
+                    // Debugger: hex dump of cartridge RAM (save data); the prompt's cartram
+                    // command shows the other banks
+                    match CartRamView::capture(mem, 0) {
+                        Ok(view) => print!("{}", view),
+                        Err(err) => println!("{}", err),
+                    }
+                }
+                Event::KeyDown {
+                    keycode: Some(Keycode::F8),
+                    ..
+                } => {
+                    // Debugger: divider, TIMA countdown and overflow state
+                    print!("{}", TimerReport::capture(mem));
+                }
+                Event::KeyDown {
+                    keycode: Some(Keycode::F9),
+                    ..
+                } => self.debug_prompt(cpu, mem),
+                Event::KeyDown {
+                    keycode: Some(Keycode::F3),
+                    ..
+                } => toggle_layer_view(mem),
+                Event::KeyDown {
+                    keycode: Some(Keycode::F4),
+                    ..
+                } => self.next_palette(mem),
+                Event::ControllerDeviceAdded { .. } | Event::ControllerDeviceRemoved { .. }
+                    if self.first_controller.is_none() => {}
+                // Also sent at startup for controllers that are already plugged in
+                Event::ControllerDeviceAdded { which, .. } if self.controllers.len() < 4 => {
+                    if let Ok(controller) = self.controller_subsystem.open(which) {
+                        println!(
+                            "{}",
+                            catalog.format(
+                                "controller-connected",
+                                &[&(self.controllers.len() + 1), &controller.name()]
+                            )
+                        );
+                        self.controllers.push(controller);
+                    }
+                }
+                Event::ControllerDeviceRemoved { which, .. } => {
+                    self.controllers.retain(|c| c.instance_id() != which);
+                    self.combos.remove_controller(which);
+                }
+                Event::ControllerButtonDown { which, button, .. }
+                | Event::ControllerButtonUp { which, button, .. } => {
+                    let pressed = matches!(event, Event::ControllerButtonDown { .. });
+                    if pressed && button == Button::Guide {
+                        self.toggle_menu(mem);
+                    }
+                    if let Some(pad_button) = map_pad_button(button) {
+                        if !pressed {
+                            self.combos.release(which, pad_button);
+                        } else if let Some(action) = self.combos.press(which, pad_button) {
+                            if !self.run_action(action, cpu, mem) {
+                                return false;
+                            }
+                        }
+                    }
+                    let player = self
+                        .controllers
+                        .iter()
+                        .position(|c| c.instance_id() == which)
+                        .zip(self.first_controller)
+                        .map(|(position, first)| controller_player(position, first));
+                    if let (Some(presses), Some(pad_button)) =
+                        (self.partner_presses.as_mut(), map_pad_button(button))
+                    {
+                        let buttons = self.bindings.buttons_for_pad(pad_button);
+                        presses.extend(buttons.map(|button| (button, pressed)));
+                    } else if let (Some(player), Some(pad_button)) =
+                        (player, map_pad_button(button))
+                    {
+                        for button in self.bindings.buttons_for_pad(pad_button) {
+                            controller_input(
+                                &mut mem.joypad,
+                                &mut self.player_masks,
+                                player,
+                                button,
+                                pressed,
+                            );
+                        }
+                    }
+                }
+                Event::KeyDown {
+                    keycode: Some(Keycode::P),
+                    repeat: false,
+                    ..
+                } => {
+                    self.paused = !self.paused;
+                    let key = if self.paused {
+                        "emulation-paused"
+                    } else {
+                        "emulation-resumed"
+                    };
+                    println!("{}", catalog.get(key));
+                }
+                Event::KeyDown {
+                    keycode: Some(Keycode::N),
+                    ..
+                } if self.paused => self.advance_frame = true,
+                Event::KeyDown {
+                    keycode: Some(Keycode::R),
+                    keymod,
+                    repeat: false,
+                    ..
+                } if keymod.intersects(Mod::LCTRLMOD | Mod::RCTRLMOD) => {
+                    self.reset(cpu, mem);
+                    println!("{}", catalog.get("machine-reset"));
+                }
+                Event::KeyDown {
+                    keycode: Some(Keycode::Tab),
+                    ..
+                } => self.fast_forward_held = true,
+                Event::KeyUp {
+                    keycode: Some(Keycode::Tab),
+                    ..
+                } => self.fast_forward_held = false,
+                Event::KeyDown {
+                    keycode: Some(Keycode::Backspace),
+                    ..
+                } => self.rewind_held = true,
+                Event::KeyUp {
+                    keycode: Some(Keycode::Backspace),
+                    ..
+                } => self.rewind_held = false,
+                // With low-latency input the keyboard is sampled by SdlKeyboardInput
+                Event::KeyDown { .. } | Event::KeyUp { .. } if self.low_latency => {}
                 Event::KeyDown {
                     keycode: Some(key), ..
                 } => {
-                    if let Some(button) = map_keycode_to_button(key) {
+                    for button in self.keys.buttons(key) {
                         mem.joypad.press_button(button);
                     }
                 }
                 Event::KeyUp {
                     keycode: Some(key), ..
                 } => {
-                    if let Some(button) = map_keycode_to_button(key) {
+                    for button in self.keys.buttons(key) {
                         mem.joypad.release_button(button);
                     }
                 }
                 _ => {}
             }
         }
+        self.events = events;
+        true
+    }
+
+    fn poll_partner(&mut self, _cpu: &mut Cpu, mem: &mut Memory) {
+        let presses = self
+            .partner_presses
+            .iter_mut()
+            .flat_map(|presses| presses.drain(..));
+        for (button, pressed) in presses {
+            if pressed {
+                mem.joypad.press_button(button);
+            } else {
+                mem.joypad.release_button(button);
+            }
+        }
+    }
+
+    fn paused(&self) -> bool {
+        self.menu_open() || (self.paused && !self.advance_frame)
+    }
+
+    fn fast_forward(&self) -> bool {
+        self.turbo || self.fast_forward_held
+    }
+
+    fn rewinding(&self) -> bool {
+        self.rewind_held || self.combos.is_held(HotkeyAction::Rewind)
+    }
+}
 
-        // Run CPU cycles for one frame
-        let mut cycles = 0u32;
-        while cycles < 70224 {
-            let delta_cycles = cpu.step(&mut mem);
-            cycles += delta_cycles;
+struct Options {
+    rom_path: String,
+    // Per-frame metrics output file (CSV, or JSON Lines for .json/.jsonl)
+    metrics_path: Option<String>,
+    // Addresses whose values are included in each metrics record
+    watch_addresses: Vec<u16>,
+    accuracy: AccuracyProfile,
+    // Listen for Super Game Boy command packets (multiplayer)
+    sgb: bool,
+    // Addresses held at fixed values
+    freezer: RamFreezer,
+    // Sample the keyboard when the game reads the joypad rather than once per frame
+    low_latency_input: bool,
+    // Shade colors for this run, instead of the configured ones
+    palette: Option<ColorScheme>,
+    // Frame rate as a multiple of the Game Boy's
+    speed: f64,
+    // Start in turbo, and draw one frame in this many while in it
+    fast_forward: bool,
+    frame_skip: u32,
+    // Frames between rewind snapshots (0 turns rewind off) and the history's size in MiB
+    rewind_interval: u32,
+    rewind_memory: usize,
+    // Sprite overlap order to use instead of the model's
+    obj_priority: Option<ObjectPriority>,
+    // Whether Left+Right and Up+Down reach the game
+    opposing_directions: OpposingDirections,
+    // What is plugged into the link port
+    serial: SerialDevice,
+    // Cartridge clock time to start from, over the one in the battery save
+    rtc_set: Option<RtcTime>,
+    // Second cartridge, played side by side over a link cable
+    link_local: Option<String>,
+    // Link cable over TCP: wait for a partner on this address, or connect to one
+    link_listen: Option<String>,
+    link_connect: Option<String>,
+    // DMG boot ROM to run before the cartridge; without one the post-boot state is applied
+    boot_rom_path: Option<String>,
+    // Print strict-mode violations (homebrew development)
+    strict: bool,
+    // Power-on RAM contents, and the seed of every random number the machine uses
+    ram_init: RamInit,
+    seed: u64,
+    // Measure interrupt latency, for the exit report and the metrics export
+    irq_latency: bool,
+    // Per-instruction trace file ("-" for stdout), and the PC ranges it is limited to
+    trace_path: Option<String>,
+    trace_ranges: Vec<PcRange>,
+    // Fix LY at 0x90, as in Gameboy Doctor's reference traces
+    trace_doctor: bool,
+    // Debug log targets to enable, on top of GBEMU_LOG
+    log_targets: Vec<LogTarget>,
+    // Run without a window (CI, scripts) and exit with the outcome's status
+    headless: bool,
+    // Headless: most frames to run
+    frames: Option<u64>,
+    // Headless: stop once the serial output contains this text
+    until_serial: Option<String>,
+    // Headless: frames to save as PNGs, and the directory they go to
+    screenshot_at: Vec<u64>,
+    out_dir: Option<String>,
+    // Position of the game controller that plays player 1; None ignores controllers
+    controller: Option<usize>,
+    // Localhost port for the JSON control server
+    #[cfg(feature = "control-server")]
+    control_port: Option<u16>,
+}
+
+fn parse_hex_u16(value: &str, catalog: &Catalog) -> Result<u16, String> {
+    let digits = value.trim_start_matches("0x").trim_start_matches("0X");
+    u16::from_str_radix(digits, 16).map_err(|_| catalog.format("invalid-address", &[&value]))
+}
 
-            /*if mem.dma_active {
-                let m_cycles = (delta_cycles) as u16;
-                if mem.dma_cycles_remaining > m_cycles {
-                    mem.dma_cycles_remaining -= m_cycles;
-                } else {
-                    mem.dma_cycles_remaining = 0;
-                    mem.dma_active = false;
+fn parse_args(args: &[String], catalog: &Catalog) -> Result<Options, String> {
+    let mut options = Options {
+        rom_path: String::from("roms/test_roms/instr_timing.gb"),
+        metrics_path: None,
+        watch_addresses: Vec::new(),
+        accuracy: AccuracyProfile::default(),
+        sgb: false,
+        freezer: RamFreezer::default(),
+        low_latency_input: false,
+        palette: None,
+        speed: 1.0,
+        fast_forward: false,
+        frame_skip: 1,
+        rewind_interval: rewind::DEFAULT_INTERVAL,
+        rewind_memory: rewind::DEFAULT_BUDGET >> 20,
+        obj_priority: None,
+        opposing_directions: OpposingDirections::default(),
+        serial: SerialDevice::default(),
+        rtc_set: None,
+        link_local: None,
+        link_listen: None,
+        link_connect: None,
+        boot_rom_path: None,
+        strict: false,
+        ram_init: RamInit::default(),
+        seed: entropy::DEFAULT_SEED,
+        irq_latency: false,
+        trace_path: None,
+        trace_ranges: Vec::new(),
+        trace_doctor: false,
+        log_targets: Vec::new(),
+        headless: false,
+        frames: None,
+        until_serial: None,
+        screenshot_at: Vec::new(),
+        out_dir: None,
+        controller: Some(0),
+        #[cfg(feature = "control-server")]
+        control_port: None,
+    };
+
+    let mut iter = args.iter().skip(1);
+    while let Some(arg) = iter.next() {
+        let mut value = |flag: &str| {
+            iter.next()
+                .cloned()
+                .ok_or_else(|| catalog.format("option-requires-value", &[&flag]))
+        };
+        match arg.as_str() {
+            "--metrics" => options.metrics_path = Some(value("--metrics")?),
+            "--watch" => {
+                for addr in value("--watch")?.split(',') {
+                    options.watch_addresses.push(parse_hex_u16(addr, catalog)?);
+                }
+            }
+            "--accuracy" => options.accuracy = value("--accuracy")?.parse()?,
+            "--sgb" => options.sgb = true,
+            "--freeze" => {
+                for entry in value("--freeze")?.split(',') {
+                    let freeze: Freeze = entry.parse()?;
+                    options.freezer.freeze(freeze.address, freeze.value)?;
                 }
-            }*/
-            // NOTE: Timer/PPU ticking now happens INSIDE instructions via tick_components()
-            // We no longer tick here to avoid double-ticking
-            // DMA still needs to be progressed based on cycles
+            }
+            "--freeze-timing" => options.freezer.timing = value("--freeze-timing")?.parse()?,
+            "--low-latency-input" => options.low_latency_input = true,
+            "--palette" => options.palette = Some(value("--palette")?.parse()?),
+            "--speed" => {
+                options.speed = match value("--speed")?.parse::<f64>() {
+                    Ok(speed) if (runner::MIN_SPEED..=runner::MAX_SPEED).contains(&speed) => speed,
+                    Ok(_) => {
+                        return Err(catalog.format(
+                            "option-out-of-range",
+                            &[&"--speed", &runner::MIN_SPEED, &runner::MAX_SPEED],
+                        ))
+                    }
+                    _ => return Err(catalog.format("option-requires-number", &[&"--speed"])),
+                };
+            }
+            "--fast-forward" => options.fast_forward = true,
+            "--frame-skip" => {
+                options.frame_skip = match value("--frame-skip")?.parse::<u32>() {
+                    Ok(frames) if frames > 0 => frames,
+                    _ => return Err(catalog.format("option-requires-number", &[&"--frame-skip"])),
+                };
+            }
+            "--rewind-interval" => {
+                options.rewind_interval = match value("--rewind-interval")?.parse() {
+                    Ok(frames) => frames,
+                    _ => {
+                        return Err(
+                            catalog.format("option-requires-number", &[&"--rewind-interval"])
+                        )
+                    }
+                };
+            }
+            "--rewind-memory" => {
+                options.rewind_memory = match value("--rewind-memory")?.parse() {
+                    Ok(mib) => mib,
+                    _ => {
+                        return Err(catalog.format("option-requires-number", &[&"--rewind-memory"]))
+                    }
+                };
+            }
+            "--obj-priority" => options.obj_priority = Some(value("--obj-priority")?.parse()?),
+            "--rtc-set" => options.rtc_set = Some(value("--rtc-set")?.parse()?),
+            "--opposing-directions" => {
+                options.opposing_directions = value("--opposing-directions")?.parse()?
+            }
+            "--serial" => options.serial = value("--serial")?.parse()?,
+            "--link-local" => options.link_local = Some(value("--link-local")?),
+            "--link-listen" => options.link_listen = Some(value("--link-listen")?),
+            "--link-connect" => options.link_connect = Some(value("--link-connect")?),
+            "--boot-rom" => options.boot_rom_path = Some(value("--boot-rom")?),
+            "--strict" => options.strict = true,
+            "--ram-init" => options.ram_init = value("--ram-init")?.parse()?,
+            "--seed" => {
+                let seed = value("--seed")?;
+                let parsed = match seed.strip_prefix("0x") {
+                    Some(hex) => u64::from_str_radix(hex, 16),
+                    None => seed.parse(),
+                };
+                options.seed =
+                    parsed.map_err(|_| catalog.format("option-requires-number", &[&"--seed"]))?;
+            }
+            "--irq-latency" => options.irq_latency = true,
+            "--trace" => options.trace_path = Some(value("--trace")?),
+            "--trace-range" => options.trace_ranges.push(value("--trace-range")?.parse()?),
+            "--trace-doctor" => options.trace_doctor = true,
+            "--log" => options
+                .log_targets
+                .extend(logging::parse_list(&value("--log")?)?),
+            "--headless" => options.headless = true,
+            "--frames" => {
+                let frames = value("--frames")?;
+                options.frames = Some(
+                    frames
+                        .parse()
+                        .map_err(|_| catalog.format("option-requires-number", &[&"--frames"]))?,
+                );
+            }
+            "--until-serial" => options.until_serial = Some(value("--until-serial")?),
+            "--screenshot-at" => {
+                options.headless = true;
+                for frame in value("--screenshot-at")?.split(',') {
+                    options
+                        .screenshot_at
+                        .push(frame.trim().parse().map_err(|_| {
+                            catalog.format("option-requires-number", &[&"--screenshot-at"])
+                        })?);
+                }
+            }
+            "--out" => options.out_dir = Some(value("--out")?),
+            "--controller" => {
+                let controller = value("--controller")?;
+                options.controller = match controller.as_str() {
+                    "off" => None,
+                    number => match number.parse::<usize>() {
+                        Ok(number) if number > 0 => Some(number - 1),
+                        _ => {
+                            return Err(catalog.format("option-requires-number", &[&"--controller"]))
+                        }
+                    },
+                };
+            }
+            #[cfg(feature = "control-server")]
+            "--control-port" => {
+                let port = value("--control-port")?;
+                options.control_port = Some(
+                    port.parse()
+                        .map_err(|_| catalog.format("invalid-port", &[&port]))?,
+                );
+            }
+            flag if flag.starts_with("--") => {
+                return Err(catalog.format("unknown-option", &[&flag]))
+            }
+            path => options.rom_path = path.to_string(),
+        }
+    }
+    if !options.headless {
+        if options.frames.is_some() {
+            return Err(catalog.format("option-requires-headless", &[&"--frames"]));
+        }
+        if options.until_serial.is_some() {
+            return Err(catalog.format("option-requires-headless", &[&"--until-serial"]));
+        }
+    }
+    if options.link_listen.is_some() && options.link_connect.is_some() {
+        return Err(catalog.format("options-conflict", &[&"--link-listen", &"--link-connect"]));
+    }
+    if options.link_local.is_some() {
+        // The link port is taken by the cable, and these only have one machine to watch
+        #[cfg(feature = "control-server")]
+        let control_port = options.control_port.is_some();
+        #[cfg(not(feature = "control-server"))]
+        let control_port = false;
+        let unsupported = [
+            (options.link_listen.is_some(), "--link-listen"),
+            (options.link_connect.is_some(), "--link-connect"),
+            (options.headless, "--headless"),
+            (options.serial != SerialDevice::default(), "--serial"),
+            (options.low_latency_input, "--low-latency-input"),
+            (options.metrics_path.is_some(), "--metrics"),
+            (options.trace_path.is_some(), "--trace"),
+            (!options.freezer.is_empty(), "--freeze"),
+            (control_port, "--control-port"),
+        ];
+        if let Some((_, other)) = unsupported.iter().find(|(set, _)| *set) {
+            return Err(catalog.format("options-conflict", &[&"--link-local", other]));
+        }
+    }
+    if options.out_dir.is_some() && options.screenshot_at.is_empty() {
+        return Err(catalog.format("option-requires-screenshots", &[&"--out"]));
+    }
+    if options.trace_path.is_none() {
+        if !options.trace_ranges.is_empty() {
+            return Err(catalog.format("option-requires-trace", &[&"--trace-range"]));
+        }
+        if options.trace_doctor {
+            return Err(catalog.format("option-requires-trace", &[&"--trace-doctor"]));
+        }
+    }
+    Ok(options)
+}
+
+// `--link-listen` / `--link-connect`: plug a network link cable into the serial port,
+// waiting for the partner first. Exits if the connection can't be made. Messages go to
+// stderr, since headless runs print the serial output on stdout.
+fn connect_link_cable(mem: &mut Memory, options: &Options, catalog: &Catalog) {
+    let link = if let Some(addr) = &options.link_listen {
+        eprintln!(
+            "{}",
+            catalog.format("link-waiting", &[&netlink::listen_address(addr)])
+        );
+        NetLink::listen(addr, netlink::DEFAULT_TIMEOUT)
+    } else if let Some(addr) = &options.link_connect {
+        NetLink::connect(addr, netlink::DEFAULT_TIMEOUT)
+    } else {
+        return;
+    };
+    match link {
+        Ok(link) => {
+            eprintln!("{}", catalog.get("link-connected"));
+            mem.serial.set_link(Some(Arc::new(link)));
+        }
+        Err(err) => {
+            eprintln!("{}", catalog.format("error", &[&err]));
+            process::exit(1);
+        }
+    }
+}
+
+// --rtc-set: start the cartridge clock at the given time. After the battery save is loaded,
+// which brings back the clock it was saved with.
+fn set_clock(mem: &mut Memory, options: &Options, catalog: &Catalog) {
+    let Some(time) = options.rtc_set else {
+        return;
+    };
+    match mem.rtc_mut() {
+        Some(rtc) => rtc.set(time),
+        None => {
+            let err = catalog.format("rtc-missing", &[&"--rtc-set"]);
+            eprintln!("{}", catalog.format("warning", &[&err]));
+        }
+    }
+}
+
+// Apply the machine options to a freshly loaded cartridge and power it on: through the
+// boot ROM if one was given, otherwise where it would hand over. Exits if the boot ROM
+// can't be used.
+fn power_on(mem: &mut Memory, options: &Options, model: Model, catalog: &Catalog) -> Cpu {
+    mem.accuracy = options.accuracy.settings();
+    if options.sgb {
+        mem.joypad.enable_sgb();
+    }
+    mem.serial.set_device(options.serial);
+    connect_link_cable(mem, options, catalog);
+    mem.ppu.force_object_priority(options.obj_priority);
+    mem.joypad
+        .set_opposing_directions(options.opposing_directions);
+    mem.set_entropy(Box::new(SeededRng::new(options.seed)));
+    let boot_rom = options.boot_rom_path.as_ref().map(|path| {
+        std::fs::read(path)
+            .map_err(|err| catalog.format("cannot-read", &[path, &err]))
+            .and_then(|boot_rom| {
+                mem.start_boot_rom(boot_rom)
+                    .map_err(|err| format!("{}: {}", path, err))
+            })
+            .unwrap_or_else(|err| {
+                eprintln!("{}", catalog.format("error", &[&err]));
+                process::exit(1);
+            })
+    });
+    if boot_rom.is_none() {
+        mem.skip_boot(model);
+    }
+    mem.set_ram_init(options.ram_init);
+    // Before the game runs, so every work RAM write it makes is seen
+    mem.set_strict_enabled(options.strict);
+    mem.set_interrupt_latency_enabled(options.irq_latency);
+    mem.set_ly_override(options.trace_doctor.then_some(0x90));
+
+    let mut cpu = Cpu::new();
+    if !mem.boot_rom_mapped() {
+        cpu.skip_boot(model, mem.read_8(0x014D));
+        cpu.registers.ime = 1; // Interrupts enabled after boot ROM
+    }
+    cpu
+}
+
+// Per-instruction trace, if a file was given
+fn open_trace(options: &Options) -> Option<Tracer> {
+    options.trace_path.as_ref().map(|path| {
+        let out: Box<dyn Write> = match path.as_str() {
+            "-" => Box::new(io::stdout()),
+            path => Box::new(BufWriter::new(
+                File::create(path).expect("Failed to create trace file"),
+            )),
+        };
+        let filter = options
+            .trace_ranges
+            .iter()
+            .cloned()
+            .fold(TraceFilter::new(), TraceFilter::with_range);
+        Tracer::to_writer(out).with_filter(filter)
+    })
+}
+
+// Battery-backed cartridge RAM kept at `path`, if the cartridge has any, loaded with what
+// was saved there
+fn open_battery_save(mem: &mut Memory, path: PathBuf, catalog: &Catalog) -> Option<BatterySave> {
+    let save = mem
+        .cartridge_header()
+        .has_battery()
+        .then(|| BatterySave::new(path))?;
+    match save.load(mem) {
+        Ok(true) => println!(
+            "{}",
+            catalog.format("battery-save-loaded", &[&save.path().display()])
+        ),
+        Ok(false) => {}
+        Err(err) => {
+            let err = catalog.format("cannot-read", &[&save.path().display(), &err]);
+            eprintln!("{}", catalog.format("warning", &[&err]));
+        }
+    }
+    Some(save)
+}
 
-            cpu.handle_interrupts(&mut mem);
+// Per-frame metrics export, if a file was given
+fn open_metrics(options: &Options) -> Option<MetricsWriter<Box<dyn Write>>> {
+    options.metrics_path.as_ref().map(|path| {
+        let file = File::create(path).expect("Failed to create metrics file");
+        MetricsWriter::new(
+            Box::new(BufWriter::new(file)) as Box<dyn Write>,
+            MetricsFormat::from_path(path),
+            options.watch_addresses.clone(),
+        )
+    })
+}
+
+// `--headless`: run without SDL for a number of frames, or until the serial output
+// matches, forwarding serial output to stdout. Battery saves and play statistics are
+// left alone so runs are repeatable. Returns the exit status.
+fn run_headless(options: Options, catalog: Catalog) -> i32 {
+    let rom_path = &options.rom_path;
+    let mem = std::fs::read(rom_path)
+        .map_err(|err| catalog.format("cannot-read", &[rom_path, &err]))
+        .and_then(|rom| Memory::from_rom(rom).map_err(|err| format!("{}: {}", rom_path, err)));
+    let mut mem = match mem {
+        Ok(mem) => mem,
+        Err(err) => {
+            eprintln!("{}", catalog.format("error", &[&err]));
+            return 1;
+        }
+    };
+    let cpu = power_on(&mut mem, &options, Model::Dmg, &catalog);
+    set_clock(&mut mem, &options, &catalog);
 
-            // Forward serial output as it arrives
-            let serial_output = mem.serial.get_output_string();
-            if serial_output.len() > last_serial_len {
-                let new_output = &serial_output[last_serial_len..];
-                print!("{}", new_output);
-                last_serial_len = serial_output.len();
+    let mut runner = Runner::new(cpu, mem);
+    runner.metrics = open_metrics(&options);
+    runner.trace = open_trace(&options);
+    runner.freezer = options.freezer;
+    let mut screenshots = (!options.screenshot_at.is_empty()).then(|| Screenshots {
+        frames: options.screenshot_at,
+        dir: PathBuf::from(options.out_dir.as_deref().unwrap_or(".")),
+        prefix: Path::new(rom_path)
+            .file_stem()
+            .map_or("screenshot".into(), |stem| {
+                stem.to_string_lossy().into_owned()
+            }),
+        saved: Vec::new(),
+    });
+    // Without --frames, a run taking screenshots stops after the last one
+    let limits = HeadlessLimits {
+        frames: options
+            .frames
+            .or(screenshots.as_ref().and_then(Screenshots::last_frame))
+            .unwrap_or(HEADLESS_FRAMES),
+        until_serial: options.until_serial.map(String::into_bytes),
+    };
+    let outcome = match &screenshots {
+        Some(screenshots) => std::fs::create_dir_all(&screenshots.dir),
+        None => Ok(()),
+    }
+    .and_then(|()| {
+        headless::run(
+            &mut runner,
+            &limits,
+            screenshots.as_mut(),
+            &mut io::stdout(),
+        )
+    })
+    .and_then(|outcome| runner.finish().map(|()| outcome));
+    for path in screenshots
+        .iter()
+        .flat_map(|screenshots| &screenshots.saved)
+    {
+        eprintln!("{}", catalog.format("screenshot-saved", &[&path.display()]));
+    }
+    match outcome {
+        Ok(outcome) => {
+            match outcome {
+                HeadlessOutcome::Matched { frame } => {
+                    eprintln!("{}", catalog.format("headless-matched", &[&frame]))
+                }
+                HeadlessOutcome::TimedOut => {
+                    eprintln!(
+                        "{}",
+                        catalog.format("headless-timed-out", &[&limits.frames])
+                    )
+                }
+                HeadlessOutcome::Finished => {}
             }
+            // stdout carries the serial output
+            if let Some(latency) = runner.mem.interrupt_latency() {
+                eprint!("{}", latency);
+            }
+            outcome.exit_code()
+        }
+        Err(err) => {
+            eprintln!("{}", catalog.format("error", &[&err]));
+            1
         }
+    }
+}
 
-        // Update texture with framebuffer
-        let framebuffer = &mem.ppu.framebuffer;
-        texture
-            .update(None, framebuffer, (SCREEN_WIDTH * 3) as usize)
-            .expect("Failed to update texture");
+// `verify-dir DIR [--frames N] [--jobs N]`: boot every ROM in DIR headlessly and print a report
+fn run_verify_dir(args: &[String], catalog: &Catalog) -> i32 {
+    let mut dir = None;
+    let mut frames = 300;
+    let mut jobs = compat::default_jobs();
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "--frames" => match iter.next().and_then(|v| v.parse().ok()) {
+                Some(n) => frames = n,
+                None => {
+                    let err = catalog.format("option-requires-number", &[&"--frames"]);
+                    eprintln!("{}", catalog.format("error", &[&err]));
+                    return 2;
+                }
+            },
+            "--jobs" => match iter.next().and_then(|v| v.parse().ok()) {
+                Some(n) => jobs = n,
+                None => {
+                    let err = catalog.format("option-requires-number", &[&"--jobs"]);
+                    eprintln!("{}", catalog.format("error", &[&err]));
+                    return 2;
+                }
+            },
+            path => dir = Some(path.to_string()),
+        }
+    }
+    let Some(dir) = dir else {
+        print_usage(catalog, &[VERIFY_DIR_USAGE]);
+        return 2;
+    };
 
-        // Prepare rendering
-        canvas.clear();
-        let dst_rect = Rect::new(0, 0, WINDOW_WIDTH, WINDOW_HEIGHT);
-        canvas
-            .copy(&texture, None, Some(dst_rect))
-            .expect("Failed to copy texture");
+    // Crashing ROMs are reported in the summary; don't print every panic
+    let default_hook = std::panic::take_hook();
+    std::panic::set_hook(Box::new(|_| {}));
+    // SAFETY: the emulator never writes ROM files, and the README tells users not to
+    // rebuild or replace them while verify-dir runs
+    let results = unsafe { compat::verify_dir(Path::new(&dir), frames, jobs) };
+    std::panic::set_hook(default_hook);
+
+    match results {
+        Ok(results) => {
+            print!("{}", compat::format_report(&results));
+            0
+        }
+        Err(err) => {
+            let err = catalog.format("cannot-read", &[&dir, &err]);
+            eprintln!("{}", catalog.format("error", &[&err]));
+            1
+        }
+    }
+}
+
+// The config file and its location; None if it can't be located or read, in which
+// case nothing is saved so a file with errors is never overwritten
+fn load_config(catalog: &Catalog) -> Option<(PathBuf, Config)> {
+    let path = Config::default_path()?;
+    match Config::load(&path) {
+        Ok(config) => Some((path, config)),
+        Err(err) => {
+            let err = catalog.format("config-ignored", &[&err]);
+            eprintln!("{}", catalog.format("warning", &[&err]));
+            None
+        }
+    }
+}
+
+// Messages in the configured language; English if none is set
+fn load_catalog(config: Option<&Config>) -> Catalog {
+    let language = config.map_or(Ok(None), Config::language);
+    Catalog::new(
+        language
+            .unwrap_or_else(|err| {
+                eprintln!("{}", Catalog::default().format("warning", &[&err]));
+                None
+            })
+            .unwrap_or_default(),
+    )
+}
+
+// Print command synopses after the "usage:" label, continuation lines aligned under
+// the first option
+fn print_usage(catalog: &Catalog, synopses: &[&str]) {
+    let label = catalog.get("usage");
+    let indent = label.chars().count() + 1;
+    for (i, synopsis) in synopses.iter().enumerate() {
+        for (j, line) in synopsis.lines().enumerate() {
+            let prefix = if i == 0 && j == 0 { label } else { "" };
+            let continuation = if j == 0 { 0 } else { "gbemu_rust ".len() };
+            eprintln!("{:indent$}{:continuation$}{}", prefix, "", line);
+        }
+    }
+}
+
+// `map ROM`: print the memory map the emulator sets up for a cartridge
+fn run_map_command(args: &[String], catalog: &Catalog) -> i32 {
+    let [rom_path] = args else {
+        print_usage(catalog, &[MAP_USAGE]);
+        return 2;
+    };
+    match std::fs::read(rom_path) {
+        Ok(rom) => {
+            print!("{}", MemoryMap::capture(&Memory::new(rom)));
+            0
+        }
+        Err(err) => {
+            let err = catalog.format("cannot-read", &[rom_path, &err]);
+            eprintln!("{}", catalog.format("error", &[&err]));
+            1
+        }
+    }
+}
+
+// `bisect-frame ROM --frame N [--expect HASH]`: run N frames headlessly and print the
+// picture's hash. With --expect, exit 0 if it matches and 1 if not, for `git bisect run`;
+// a cartridge this build can't load exits 125 so bisect skips the commit.
+fn run_bisect_frame(args: &[String], catalog: &Catalog) -> i32 {
+    let mut rom_path = None;
+    let mut frame = None;
+    let mut expect = None;
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "--frame" => match iter.next().and_then(|v| v.parse().ok()) {
+                Some(n) => frame = Some(n),
+                None => {
+                    let err = catalog.format("option-requires-number", &[&"--frame"]);
+                    eprintln!("{}", catalog.format("error", &[&err]));
+                    return 2;
+                }
+            },
+            "--expect" => {
+                let value = iter.next().map(|v| {
+                    let digits = v.trim_start_matches("0x").trim_start_matches("0X");
+                    u64::from_str_radix(digits, 16)
+                        .map_err(|_| catalog.format("invalid-hash", &[&v]))
+                });
+                match value {
+                    Some(Ok(hash)) => expect = Some(hash),
+                    Some(Err(err)) => {
+                        eprintln!("{}", catalog.format("error", &[&err]));
+                        return 2;
+                    }
+                    None => {
+                        let err = catalog.format("option-requires-value", &[&"--expect"]);
+                        eprintln!("{}", catalog.format("error", &[&err]));
+                        return 2;
+                    }
+                }
+            }
+            path => rom_path = Some(path.to_string()),
+        }
+    }
+    let (Some(rom_path), Some(frame)) = (rom_path, frame) else {
+        print_usage(catalog, &[BISECT_FRAME_USAGE]);
+        return 2;
+    };
+
+    let rom = match std::fs::read(&rom_path) {
+        Ok(rom) => rom,
+        Err(err) => {
+            let err = catalog.format("cannot-read", &[&rom_path, &err]);
+            eprintln!("{}", catalog.format("error", &[&err]));
+            return 2;
+        }
+    };
+    let mut gb = match GameBoy::new(rom, Model::Dmg) {
+        Ok(gb) => gb,
+        Err(err) => {
+            let err = format!("{}: {}", rom_path, err);
+            eprintln!("{}", catalog.format("error", &[&err]));
+            return 125;
+        }
+    };
+    let hash = headless::hash_after_frames(&mut gb, frame);
+    println!("{:016x}", hash);
+    match expect {
+        Some(expected) if expected != hash => {
+            let expected = format!("{:016x}", expected);
+            let hash = format!("{:016x}", hash);
+            eprintln!(
+                "{}",
+                catalog.format("frame-hash-mismatch", &[&frame, &hash, &expected])
+            );
+            1
+        }
+        _ => 0,
+    }
+}
+
+// `disasm ROM [--bank N] [--start ADDR] [--len LEN] [--sym FILE]`: print a labeled
+// listing of ROM code. ADDR and LEN are hex; the range defaults to the rest of the 16 KiB
+// area it starts in, and symbols to ROM.sym next to the ROM, if there is one.
+fn run_disasm_command(args: &[String], catalog: &Catalog) -> i32 {
+    let mut rom_path = None;
+    let mut bank = None;
+    let mut start = None;
+    let mut len = None;
+    let mut sym_path = None;
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "--bank" => match iter.next().and_then(|v| v.parse().ok()) {
+                Some(n) => bank = Some(n),
+                None => {
+                    let err = catalog.format("option-requires-number", &[&"--bank"]);
+                    eprintln!("{}", catalog.format("error", &[&err]));
+                    return 2;
+                }
+            },
+            flag @ ("--start" | "--len") => {
+                let value = iter.next().map(|v| parse_hex_u16(v, catalog));
+                match value {
+                    Some(Ok(n)) if flag == "--start" => start = Some(n),
+                    Some(Ok(n)) => len = Some(n),
+                    Some(Err(err)) => {
+                        eprintln!("{}", catalog.format("error", &[&err]));
+                        return 2;
+                    }
+                    None => {
+                        let err = catalog.format("option-requires-value", &[&flag]);
+                        eprintln!("{}", catalog.format("error", &[&err]));
+                        return 2;
+                    }
+                }
+            }
+            "--sym" => match iter.next() {
+                Some(path) => sym_path = Some(PathBuf::from(path)),
+                None => {
+                    let err = catalog.format("option-requires-value", &[&"--sym"]);
+                    eprintln!("{}", catalog.format("error", &[&err]));
+                    return 2;
+                }
+            },
+            path => rom_path = Some(path.to_string()),
+        }
+    }
+    let Some(rom_path) = rom_path else {
+        print_usage(catalog, &[DISASM_USAGE]);
+        return 2;
+    };
+
+    // `--bank 3` alone lists that bank from 4000
+    let start = start.unwrap_or(if bank.unwrap_or(0) > 0 { 0x4000 } else { 0 });
+    let bank = bank.unwrap_or(if start >= 0x4000 { 1 } else { 0 });
+    let area_end: u16 = if start < 0x4000 { 0x4000 } else { 0x8000 };
+    let len = len.unwrap_or(area_end.saturating_sub(start));
+
+    let rom = match std::fs::read(&rom_path) {
+        Ok(rom) => rom,
+        Err(err) => {
+            let err = catalog.format("cannot-read", &[&rom_path, &err]);
+            eprintln!("{}", catalog.format("error", &[&err]));
+            return 1;
+        }
+    };
+    let explicit_sym = sym_path.is_some();
+    let sym_path = sym_path.unwrap_or_else(|| Path::new(&rom_path).with_extension("sym"));
+    let symbols = match std::fs::read_to_string(&sym_path) {
+        Ok(text) => match text.parse::<Symbols>() {
+            Ok(symbols) => symbols,
+            Err(err) => {
+                let err = format!("{}: {}", sym_path.display(), err);
+                eprintln!("{}", catalog.format("error", &[&err]));
+                return 1;
+            }
+        },
+        Err(err) if explicit_sym => {
+            let err = catalog.format("cannot-read", &[&sym_path.display(), &err]);
+            eprintln!("{}", catalog.format("error", &[&err]));
+            return 1;
+        }
+        Err(_) => Symbols::default(),
+    };
+
+    match disasm::disassemble(&rom, bank, start, len, &symbols) {
+        Ok(listing) => {
+            print!("{}", listing);
+            0
+        }
+        Err(err) => {
+            eprintln!("{}", catalog.format("error", &[&err]));
+            1
+        }
+    }
+}
+
+// `stats`: play time and savestate use of every game played, most recent first
+// Older versions wrote debug files into the working directory that could reach gigabytes
+fn warn_about_legacy_logs(catalog: &Catalog) {
+    let dir = Path::new(".");
+    let total: u64 = logging::find_legacy_logs(dir)
+        .iter()
+        .map(|(_, size)| size)
+        .sum();
+    if total >= logging::LEGACY_LOG_WARN_BYTES {
+        let found = catalog.format("legacy-logs-found", &[&(total >> 20), &dir.display()]);
+        eprintln!("{}", catalog.format("warning", &[&found]));
+    }
+}
+
+// `clean-logs [DIR]`: delete the debug files older versions left in DIR (default: here)
+fn run_clean_logs_command(args: &[String], catalog: &Catalog) -> i32 {
+    let dir = match args {
+        [] => Path::new("."),
+        [dir] => Path::new(dir),
+        _ => {
+            print_usage(catalog, &[CLEAN_LOGS_USAGE]);
+            return 2;
+        }
+    };
+    let logs = logging::find_legacy_logs(dir);
+    if logs.is_empty() {
+        println!("{}", catalog.format("legacy-logs-none", &[&dir.display()]));
+    }
+    let mut status = 0;
+    for (path, size) in logs {
+        match std::fs::remove_file(&path) {
+            Ok(()) => println!(
+                "{}",
+                catalog.format("legacy-log-deleted", &[&path.display(), &(size >> 20)])
+            ),
+            Err(err) => {
+                let err = catalog.format("cannot-write", &[&path.display(), &err]);
+                eprintln!("{}", catalog.format("error", &[&err]));
+                status = 1;
+            }
+        }
+    }
+    status
+}
+
+// `rewind-bench ROM`: run a ROM headless with rewind recording and report what a second
+// of history costs, and how much of it the budget holds
+fn run_rewind_bench(args: &[String], catalog: &Catalog) -> i32 {
+    let mut rom_path = None;
+    let mut frames = 3600;
+    let mut interval = rewind::DEFAULT_INTERVAL;
+    let mut budget_mib = rewind::DEFAULT_BUDGET >> 20;
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            option @ ("--frames" | "--rewind-interval" | "--rewind-memory") => {
+                let Some(n) = iter.next().and_then(|v| v.parse::<u32>().ok()) else {
+                    let err = catalog.format("option-requires-number", &[&option]);
+                    eprintln!("{}", catalog.format("error", &[&err]));
+                    return 2;
+                };
+                match option {
+                    "--frames" => frames = n,
+                    "--rewind-interval" => interval = n.max(1),
+                    _ => budget_mib = n as usize,
+                }
+            }
+            path => rom_path = Some(path.to_string()),
+        }
+    }
+    let Some(rom_path) = rom_path else {
+        print_usage(catalog, &[REWIND_BENCH_USAGE]);
+        return 2;
+    };
+
+    let rom = match std::fs::read(&rom_path) {
+        Ok(rom) => rom,
+        Err(err) => {
+            let err = catalog.format("cannot-read", &[&rom_path, &err]);
+            eprintln!("{}", catalog.format("error", &[&err]));
+            return 2;
+        }
+    };
+    let mut gb = match GameBoy::new(rom, Model::Dmg) {
+        Ok(gb) => gb,
+        Err(err) => {
+            let err = format!("{}: {}", rom_path, err);
+            eprintln!("{}", catalog.format("error", &[&err]));
+            return 125;
+        }
+    };
+    let mut history = RewindBuffer::new(interval, budget_mib << 20);
+    let mut recording = Duration::ZERO;
+    for _ in 0..frames {
+        gb.run_frame();
+        let start = Instant::now();
+        history.record_frame(gb.cpu(), gb.memory());
+        recording += start.elapsed();
+    }
+
+    // ~59.73 frames per second
+    let seconds = |frames: u64| frames as f64 * 70224.0 / 4_194_304.0;
+    let state = savestate::save_state(gb.cpu(), gb.memory()).len();
+    let snapshots = (frames / interval).max(1);
+    let covered = seconds(history.history_frames());
+    let per_second = if covered > 0.0 {
+        history.memory_used().saturating_sub(state) as f64 / covered
+    } else {
+        0.0
+    };
+    println!("state      {} bytes", state);
+    println!(
+        "snapshots  {} every {} frames, {:.1} us each",
+        frames / interval,
+        interval,
+        recording.as_secs_f64() * 1e6 / snapshots as f64
+    );
+    println!(
+        "history    {} states over {:.1} s, {} bytes",
+        history.len(),
+        covered,
+        history.memory_used()
+    );
+    println!("rate       {:.0} bytes per second of rewind", per_second);
+    if per_second > 0.0 {
+        println!(
+            "budget     {} MB holds {:.0} s",
+            budget_mib,
+            (budget_mib << 20) as f64 / per_second
+        );
+    }
+    0
+}
+
+fn run_stats_command(args: &[String], catalog: &Catalog) -> i32 {
+    if !args.is_empty() {
+        print_usage(catalog, &[STATS_USAGE]);
+        return 2;
+    }
+    let Some(path) = PlayStats::default_path() else {
+        let err = catalog.get("stats-missing");
+        eprintln!("{}", catalog.format("error", &[&err]));
+        return 1;
+    };
+    let stats = match PlayStats::load(&path) {
+        Ok(stats) => stats,
+        Err(err) => {
+            eprintln!("{}", catalog.format("error", &[&err]));
+            return 1;
+        }
+    };
+    println!("TITLE                  TIME     FRAMES SESSIONS  SAVED LOADED  LAST PLAYED");
+    for game in stats.games() {
+        println!(
+            "{:<16} {:>10} {:>10} {:>8} {:>6} {:>6}  {}",
+            game.title,
+            stats::format_play_time(game.play_time),
+            game.frames,
+            game.sessions,
+            game.states_saved,
+            game.states_loaded,
+            stats::format_timestamp(game.last_played)
+        );
+    }
+    0
+}
+
+// `palette [SCHEME]`: print the configured shade colors, or save new ones
+fn run_palette_command(
+    args: &[String],
+    config: Option<(PathBuf, Config)>,
+    catalog: &Catalog,
+) -> i32 {
+    let Some((path, mut config)) = config else {
+        let err = catalog.get("config-missing");
+        eprintln!("{}", catalog.format("error", &[&err]));
+        return 1;
+    };
+    let Some(value) = args.first() else {
+        match config.color_scheme() {
+            Ok(scheme) => println!("{}", scheme.unwrap_or_default()),
+            Err(err) => {
+                eprintln!("{}", catalog.format("error", &[&err]));
+                return 1;
+            }
+        }
+        return 0;
+    };
+    let scheme = match value.parse::<ColorScheme>() {
+        Ok(scheme) => scheme,
+        Err(err) => {
+            eprintln!("{}", catalog.format("error", &[&err]));
+            print_usage(catalog, &[PALETTE_USAGE]);
+            return 2;
+        }
+    };
+    config.set_color_scheme(scheme);
+    match config.save(&path) {
+        Ok(()) => {
+            println!(
+                "{}",
+                catalog.format("palette-saved", &[&scheme, &path.display()])
+            );
+            0
+        }
+        Err(err) => {
+            let err = catalog.format("cannot-write", &[&path.display(), &err]);
+            eprintln!("{}", catalog.format("error", &[&err]));
+            1
+        }
+    }
+}
+
+fn main() {
+    std::env::set_var("RUST_BACKTRACE", "1");
+
+    let args: Vec<String> = env::args().collect();
+    let config = load_config(&Catalog::default());
+    let catalog = load_catalog(config.as_ref().map(|(_, config)| config));
+    // Debug logs for every command, e.g. GBEMU_LOG=timer,mbc
+    if let Ok(list) = env::var("GBEMU_LOG") {
+        if let Err(err) = logging::enable_list(&list) {
+            eprintln!(
+                "{}",
+                catalog.format("warning", &[&format!("GBEMU_LOG: {}", err)])
+            );
+        }
+    }
+    match args.get(1).map(String::as_str) {
+        Some("verify-dir") => process::exit(run_verify_dir(&args[2..], &catalog)),
+        Some("palette") => process::exit(run_palette_command(&args[2..], config, &catalog)),
+        Some("map") => process::exit(run_map_command(&args[2..], &catalog)),
+        Some("bisect-frame") => process::exit(run_bisect_frame(&args[2..], &catalog)),
+        Some("disasm") => process::exit(run_disasm_command(&args[2..], &catalog)),
+        Some("stats") => process::exit(run_stats_command(&args[2..], &catalog)),
+        Some("clean-logs") => process::exit(run_clean_logs_command(&args[2..], &catalog)),
+        Some("rewind-bench") => process::exit(run_rewind_bench(&args[2..], &catalog)),
+        _ => {}
+    }
+    let options = parse_args(&args, &catalog).unwrap_or_else(|err| {
+        eprintln!("{}", catalog.format("error", &[&err]));
+        let control_port = if cfg!(feature = "control-server") {
+            "\n[--control-port PORT]"
+        } else {
+            ""
+        };
+        let synopsis = format!(
+            "gbemu_rust [ROM] [--metrics FILE] [--watch ADDR[,ADDR...]] [--accuracy fast|balanced|accurate] [--sgb]
+[--freeze ADDR=VAL[,...]] [--freeze-timing instruction|frame]
+[--speed X] [--fast-forward] [--frame-skip N] [--rewind-interval N] [--rewind-memory MB]
+[--low-latency-input] [--palette green|gray|pocket|RRGGBB,...] [--obj-priority dmg|cgb]
+[--opposing-directions forbid|allow] [--rtc-set DAYS:HH:MM:SS] [--serial none|loopback] [--link-listen [ADDR:]PORT | --link-connect HOST[:PORT] | --link-local ROM]
+[--boot-rom FILE] [--strict] [--ram-init fixed|random] [--seed N] [--irq-latency] [--log cpu|timer|ppu|mbc|all,...]
+[--trace FILE [--trace-range START-END[@BANK]] [--trace-doctor]]
+[--controller N|off] [--headless [--frames N] [--until-serial TEXT]]
+[--screenshot-at FRAME[,FRAME...] [--out DIR]]{}",
+            control_port
+        );
+        print_usage(
+            &catalog,
+            &[
+                &synopsis,
+                VERIFY_DIR_USAGE,
+                PALETTE_USAGE,
+                MAP_USAGE,
+                BISECT_FRAME_USAGE,
+                DISASM_USAGE,
+                STATS_USAGE,
+                CLEAN_LOGS_USAGE,
+                REWIND_BENCH_USAGE,
+            ],
+        );
+        process::exit(2);
+    });
+    for &target in &options.log_targets {
+        logging::set_enabled(target, true);
+    }
+    warn_about_legacy_logs(&catalog);
+    if options.headless {
+        process::exit(run_headless(options, catalog));
+    }
+    let rom_path = options.rom_path.clone();
+
+    println!("{}", catalog.format("loading-rom", &[&rom_path]));
+
+    // Initialize SDL2
+    let sdl_context = sdl2::init().expect("Failed to initialize SDL2");
+    let video_subsystem = sdl_context
+        .video()
+        .expect("Failed to initialize video subsystem");
+
+    // Create a window, twice as wide with --link-local's second machine on the right
+    let columns = if options.link_local.is_some() { 2 } else { 1 };
+    let window = video_subsystem
+        .window(
+            catalog.get("window-title"),
+            columns * WINDOW_WIDTH,
+            WINDOW_HEIGHT,
+        )
+        .position_centered()
+        .build()
+        .expect("Failed to create window");
+
+    // Create a canvas
+    let canvas = window
+        .into_canvas()
+        .build()
+        .expect("Failed to create canvas");
+    let texture_creator = canvas.texture_creator();
+    let texture = texture_creator
+        .create_texture_streaming(PixelFormatEnum::RGB24, SCREEN_WIDTH, SCREEN_HEIGHT)
+        .expect("Failed to create texture");
+    let partner_texture = options.link_local.is_some().then(|| {
+        texture_creator
+            .create_texture_streaming(PixelFormatEnum::RGB24, SCREEN_WIDTH, SCREEN_HEIGHT)
+            .expect("Failed to create texture")
+    });
+
+    // Load ROM
+    let rom_buffer = RomImage::open(Path::new(&rom_path)).expect("Failed to open ROM file");
 
-        // Update FPS counter
-        fps_counter += 1;
-        if fps_timer.elapsed() >= Duration::from_secs(1) {
-            current_fps = fps_counter;
-            fps_counter = 0;
-            fps_timer = Instant::now();
+    // Initialize emulator components
+    let model = Model::Dmg;
+    let mut mem = match Memory::from_rom(rom_buffer.clone()) {
+        Ok(mem) => mem,
+        Err(err) => {
+            // Tell the user instead of mis-emulating the cartridge
+            let err = format!("{}: {}", rom_path, err);
+            eprintln!("{}", catalog.format("error", &[&err]));
+            let _ = show_simple_message_box(
+                MessageBoxFlag::ERROR,
+                catalog.get("unsupported-cartridge"),
+                &format!("{}\n\n{}", rom_path, err),
+                canvas.window(),
+            );
+            process::exit(1);
+        }
+    };
+    let bindings = config
+        .as_ref()
+        .map_or(Ok(Bindings::default()), |(_, config)| config.bindings())
+        .unwrap_or_else(|err| {
+            eprintln!("{}", catalog.format("warning", &[&err]));
+            Bindings::default()
+        });
+    let (keys, unknown_keys) = KeyMap::new(&bindings);
+    for key in unknown_keys {
+        let err = catalog.format("unknown-key", &[&key]);
+        eprintln!("{}", catalog.format("warning", &[&err]));
+    }
+    if options.low_latency_input {
+        mem.joypad
+            .set_input_provider(Some(Arc::new(SdlKeyboardInput { keys: keys.clone() })));
+    }
+    let cpu = power_on(&mut mem, &options, model, &catalog);
+    // Bank switches are rare enough to always keep the last few for F6
+    mem.set_mbc_log_enabled(true);
 
-            // Update window title with FPS
-            canvas
-                .window_mut()
-                .set_title(&format!("Game Boy Emulator - {} FPS", current_fps))
-                .expect("Failed to set window title");
+    // Play time and savestate use are added to the game's totals on exit
+    let play_stats = PlayStats::default_path().and_then(|path| match PlayStats::load(&path) {
+        Ok(stats) => Some((path, stats)),
+        Err(err) => {
+            eprintln!("{}", catalog.format("warning", &[&err]));
+            None
         }
+    });
+    let rom_info = mem.cartridge_header();
+    println!("{}", catalog.format("rom-loaded", &[&rom_info]));
+    if let Some(game) = play_stats
+        .as_ref()
+        .and_then(|(_, stats)| stats.get(&rom_buffer))
+    {
+        println!(
+            "{}",
+            catalog.format(
+                "stats-summary",
+                &[
+                    &stats::format_play_time(game.play_time),
+                    &game.sessions,
+                    &stats::format_timestamp(game.last_played)
+                ]
+            )
+        );
+    }
 
-        // Frame timing with dynamic presentation time estimate
-        // Calculate sleep time accounting for estimated present() duration
-        let frame_time = last_frame.elapsed();
-        let target_sleep = frame_duration
-            .saturating_sub(frame_time)
-            .saturating_sub(estimated_present_time);
+    // Battery-backed cartridge RAM is kept in a .sav file next to the ROM
+    let battery_save =
+        open_battery_save(&mut mem, battery::save_path(Path::new(&rom_path)), &catalog);
+    set_clock(&mut mem, &options, &catalog);
 
-        if target_sleep > Duration::from_micros(100) {
-            std::thread::sleep(target_sleep);
+    let configured_palette = config.as_ref().and_then(|(_, config)| {
+        config.color_scheme().unwrap_or_else(|err| {
+            eprintln!("{}", catalog.format("warning", &[&err]));
+            None
+        })
+    });
+    let palette = options.palette.or(configured_palette);
+    if let Some(scheme) = palette {
+        mem.ppu.set_color_scheme(scheme);
+    }
+
+    // --link-local: a second machine on the other end of a link cable, for two-player
+    // games and trades on one computer. Game controllers play it, while the keyboard, the
+    // menu, savestates and the debugger stay with the first.
+    let partner = options.link_local.as_ref().map(|partner_path| {
+        println!("{}", catalog.format("loading-rom", &[partner_path]));
+        let mut mem = RomImage::open(Path::new(partner_path))
+            .map_err(|err| catalog.format("cannot-read", &[partner_path, &err]))
+            .and_then(|rom| {
+                Memory::from_rom(rom).map_err(|err| format!("{}: {}", partner_path, err))
+            })
+            .unwrap_or_else(|err| {
+                eprintln!("{}", catalog.format("error", &[&err]));
+                process::exit(1);
+            });
+        let cpu = power_on(&mut mem, &options, model, &catalog);
+        println!(
+            "{}",
+            catalog.format("rom-loaded", &[&mem.cartridge_header()])
+        );
+        // Two copies of one game keep separate saves, however the path was written
+        let same_rom = matches!(
+            (std::fs::canonicalize(&rom_path), std::fs::canonicalize(partner_path)),
+            (Ok(left), Ok(right)) if left == right
+        );
+        let save_path = match same_rom {
+            true => battery::save_path(Path::new(&rom_path)).with_extension("p2.sav"),
+            false => battery::save_path(Path::new(partner_path)),
+        };
+        let battery_save = open_battery_save(&mut mem, save_path, &catalog);
+        set_clock(&mut mem, &options, &catalog);
+        if let Some(scheme) = palette {
+            mem.ppu.set_color_scheme(scheme);
         }
+        let mut partner = Runner::new(cpu, mem);
+        partner.catalog = catalog;
+        partner.set_speed(options.speed);
+        partner.frame_skip = options.frame_skip;
+        partner.battery_save = battery_save;
+        partner
+    });
 
-        // Measure actual present time and update estimate
-        let present_start = Instant::now();
-        canvas.present();
-        let actual_present_time = present_start.elapsed();
+    let mut runner = Runner::new(cpu, mem);
+    runner.catalog = catalog;
+    runner.set_speed(options.speed);
+    runner.frame_skip = options.frame_skip;
+    // Rewinding one side of a link would leave the other behind
+    if options.rewind_interval > 0 && partner.is_none() {
+        runner.rewind = Some(RewindBuffer::new(
+            options.rewind_interval,
+            options.rewind_memory << 20,
+        ));
+    }
+    runner.metrics = open_metrics(&options);
+    runner.trace = open_trace(&options);
+    runner.freezer = options.freezer;
+    runner.battery_save = battery_save;
+    runner.serial_out = Some(Box::new(io::stdout()));
+    if let Some(mut partner) = partner {
+        let (here, there) = LinkCable::pair();
+        runner.mem.serial.set_link(Some(here));
+        partner.mem.serial.set_link(Some(there));
+        runner.link_partner = Some(Box::new(partner));
+    }
 
-        let new_estimate_micros = (PRESENT_TIME_ALPHA * actual_present_time.as_micros() as f64)
-            + ((1.0 - PRESENT_TIME_ALPHA) * estimated_present_time.as_micros() as f64);
-        estimated_present_time = Duration::from_micros(new_estimate_micros as u64);
+    #[cfg(feature = "control-server")]
+    {
+        runner.control = options.control_port.map(|port| {
+            let server = ControlServer::bind(port).expect("Failed to start control server");
+            println!(
+                "{}",
+                catalog.format("control-server-listening", &[&server.local_addr()])
+            );
+            server
+        });
+    }
 
-        last_frame = Instant::now();
+    // Audio plays from a ring the emulation thread fills, and paces frames to the sound
+    // card once samples flow. Without an APU it plays silence.
+    let (producer, consumer) = audio::sample_ring(AUDIO_BUFFER_FRAMES);
+    let desired = AudioSpecDesired {
+        freq: Some(AUDIO_SAMPLE_RATE),
+        channels: Some(2),
+        samples: Some(1024),
+    };
+    let audio_device = sdl_context.audio().and_then(|audio| {
+        audio.open_playback(None, &desired, |_spec| SdlAudio { samples: consumer })
+    });
+    let _audio_device = match audio_device {
+        Ok(device) => {
+            device.resume();
+            runner.audio = Some(Box::new(producer));
+            Some(device)
+        }
+        Err(err) => {
+            let err = catalog.format("audio-unavailable", &[&err]);
+            eprintln!("{}", catalog.format("warning", &[&err]));
+            None
+        }
+    };
+
+    let menu = Rc::new(RefCell::new(None));
+    let mut video = SdlVideo {
+        canvas,
+        texture,
+        partner_texture,
+        lcd_was_on: true,
+        unchanged: false,
+        menu: menu.clone(),
+        catalog,
+        // Confirms which game and mapper were actually loaded
+        banner: Some((
+            [rom_info.title.as_str(), rom_info.mapper_name()]
+                .into_iter()
+                .filter(|line| !line.is_empty())
+                .map(String::from)
+                .collect(),
+            BANNER_FRAMES,
+        )),
+    };
+    let combos = config
+        .as_ref()
+        .map_or(Ok(ComboHotkeys::new()), |(_, config)| {
+            config.combo_hotkeys()
+        })
+        .unwrap_or_else(|err| {
+            eprintln!("{}", catalog.format("warning", &[&err]));
+            ComboHotkeys::new()
+        });
+    let mut input = SdlInput {
+        event_pump: sdl_context
+            .event_pump()
+            .expect("Failed to get SDL event pump"),
+        controller_subsystem: sdl_context
+            .game_controller()
+            .expect("Failed to initialize game controller subsystem"),
+        controllers: Vec::new(),
+        first_controller: options.controller,
+        keys,
+        bindings,
+        player_masks: [ButtonMask::NONE; 4],
+        partner_presses: options.link_local.is_some().then(Vec::new),
+        low_latency: options.low_latency_input,
+        config,
+        catalog,
+        state_path: Path::new(&rom_path).with_extension("state"),
+        state_slot: 0,
+        states_saved: 0,
+        states_loaded: 0,
+        combos,
+        turbo: options.fast_forward,
+        fast_forward_held: false,
+        rewind_held: false,
+        paused: false,
+        advance_frame: false,
+        menu,
+        model,
+        events: Vec::new(),
+        debug_session: DebugSession::default(),
+    };
+    runner
+        .run(&mut video, &mut input, &mut SystemClock)
+        .expect("Failed to write metrics");
+
+    if let Some((path, mut stats)) = play_stats {
+        let session = Session {
+            play_time: EmuDuration::from_frames(runner.frame_number()),
+            frames: runner.frame_number(),
+            states_saved: input.states_saved,
+            states_loaded: input.states_loaded,
+            ended: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |since| since.as_secs()),
+        };
+        stats.record(&rom_buffer, &session);
+        if let Err(err) = stats.save(&path) {
+            let err = catalog.format("cannot-write", &[&path.display(), &err]);
+            eprintln!("{}", catalog.format("warning", &[&err]));
+        }
+    }
+
+    // Print frame timing diagnostics so stutter reports can include them
+    if !runner.profiler.is_empty() {
+        println!();
+        print!("{}", runner.profiler.report());
+    }
+    if let Some(latency) = runner.mem.interrupt_latency() {
+        println!();
+        print!("{}", latency);
     }
 }
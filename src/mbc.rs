@@ -0,0 +1,498 @@
+// Memory bank controllers
+// Write-only registers in the ROM area select what the CPU sees at 0000-7FFF and
+// A000-BFFF. Bank numbers wrap at the size of the ROM and RAM, as the chips ignore address
+// lines they don't have.
+//
+// MBC1: BANK1 (2000-3FFF) holds the low 5 bits of the ROM bank, BANK2 (4000-5FFF) two more
+// bits that go either to the ROM bank or, in mode 1, to the RAM bank and the 0000-3FFF
+// area too.
+// MBC3: a 7-bit ROM bank (2000-3FFF) and a select (4000-5FFF) mapping either a RAM bank or
+// one of the clock registers at A000-BFFF. Writing 00 then 01 to 6000-7FFF latches the
+// clock, which counts emulated time.
+// MBC5: a 9-bit ROM bank split over 2000-2FFF (low 8 bits) and 3000-3FFF (bit 8), where
+// bank 0 can be mapped at 4000-7FFF too, and a 4-bit RAM bank (4000-5FFF). Rumble carts
+// use bit 3 of the RAM bank for the motor.
+
+use crate::clock::EmuDuration;
+use crate::rtc::Rtc;
+use crate::savestate::{StateReader, StateWriter};
+
+/// Cartridge RAM bank size
+pub const RAM_BANK_SIZE: usize = 0x2000;
+/// ROM bank size
+pub const ROM_BANK_SIZE: usize = 0x4000;
+
+/// Emulated mapper chip
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Mbc {
+    Mbc1(Mbc1),
+    Mbc3(Mbc3),
+    Mbc5(Mbc5),
+}
+
+/// What 0xA000-0xBFFF maps while enabled
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RamMapping {
+    Bank(usize),
+    /// MBC3 clock register 0x08-0x0C
+    Rtc(u8),
+}
+
+impl Mbc {
+    /// Power-on mapper for a cartridge type byte (0x0147); None for cartridges without a
+    /// mapper, or whose mapper is not emulated
+    pub fn new(cartridge_type: u8, rom_banks: usize, ram_banks: usize) -> Option<Self> {
+        match cartridge_type {
+            0x01..=0x03 => Some(Mbc::Mbc1(Mbc1::new(rom_banks, ram_banks))),
+            0x0F..=0x13 => Some(Mbc::Mbc3(Mbc3::new(
+                rom_banks,
+                ram_banks,
+                matches!(cartridge_type, 0x0F | 0x10),
+            ))),
+            0x19..=0x1E => Some(Mbc::Mbc5(Mbc5::new(
+                rom_banks,
+                ram_banks,
+                matches!(cartridge_type, 0x1C..=0x1E),
+            ))),
+            _ => None,
+        }
+    }
+
+    /// Write to a register (0x0000-0x7FFF)
+    pub fn write(&mut self, address: u16, value: u8) {
+        match self {
+            Mbc::Mbc1(mbc) => mbc.write(address, value),
+            Mbc::Mbc3(mbc) => mbc.write(address, value),
+            Mbc::Mbc5(mbc) => mbc.write(address, value),
+        }
+    }
+
+    /// Run the cartridge's clock, if it has one, for `t_cycles` of emulated time
+    pub fn tick(&mut self, t_cycles: u32) {
+        if let Mbc::Mbc3(mbc) = self {
+            mbc.tick(t_cycles);
+        }
+    }
+
+    /// ROM bank mapped at 0x0000-0x3FFF
+    pub fn low_rom_bank(&self) -> usize {
+        match self {
+            Mbc::Mbc1(mbc) => mbc.low_rom_bank(),
+            Mbc::Mbc3(_) | Mbc::Mbc5(_) => 0,
+        }
+    }
+
+    /// ROM bank mapped at 0x4000-0x7FFF
+    pub fn high_rom_bank(&self) -> usize {
+        match self {
+            Mbc::Mbc1(mbc) => mbc.high_rom_bank(),
+            Mbc::Mbc3(mbc) => mbc.rom_bank(),
+            Mbc::Mbc5(mbc) => mbc.rom_bank(),
+        }
+    }
+
+    /// ROM banks the registers can address, including bank 0: the cartridge's, up to
+    /// 128 (MBC1, MBC3) or 512 (MBC5)
+    pub fn rom_banks(&self) -> usize {
+        match self {
+            Mbc::Mbc1(mbc) => mbc.rom_banks.min(128),
+            Mbc::Mbc3(mbc) => mbc.rom_banks.min(128),
+            Mbc::Mbc5(mbc) => mbc.rom_banks.min(512),
+        }
+    }
+
+    pub fn ram_enabled(&self) -> bool {
+        match self {
+            Mbc::Mbc1(mbc) => mbc.ram_enabled(),
+            Mbc::Mbc3(mbc) => mbc.ram_enabled,
+            Mbc::Mbc5(mbc) => mbc.ram_enabled,
+        }
+    }
+
+    pub fn ram_mapping(&self) -> RamMapping {
+        match self {
+            Mbc::Mbc1(mbc) => RamMapping::Bank(mbc.ram_bank()),
+            Mbc::Mbc3(mbc) => mbc.ram_mapping(),
+            Mbc::Mbc5(mbc) => RamMapping::Bank(mbc.ram_bank()),
+        }
+    }
+
+    /// Write to the clock register `select` (see `RamMapping::Rtc`)
+    pub fn write_rtc(&mut self, select: u8, value: u8) {
+        if let Mbc::Mbc3(mbc) = self {
+            mbc.write_rtc(select, value);
+        }
+    }
+
+    /// The cartridge's real-time clock (MBC3+TIMER)
+    pub fn rtc(&self) -> Option<&Rtc> {
+        match self {
+            Mbc::Mbc3(mbc) => mbc.rtc.as_ref(),
+            _ => None,
+        }
+    }
+
+    pub fn rtc_mut(&mut self) -> Option<&mut Rtc> {
+        match self {
+            Mbc::Mbc3(mbc) => mbc.rtc.as_mut(),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Mbc1 {
+    ram_enabled: bool,
+    bank1: u8,
+    bank2: u8,
+    mode: bool,
+    // Power-of-two bank counts, used as address masks
+    rom_banks: usize,
+    ram_banks: usize,
+}
+
+impl Mbc1 {
+    /// Power-on state for a cartridge with `rom_banks` ROM and `ram_banks` RAM banks;
+    /// both are rounded up to a power of two
+    pub fn new(rom_banks: usize, ram_banks: usize) -> Self {
+        Self {
+            ram_enabled: false,
+            bank1: 1,
+            bank2: 0,
+            mode: false,
+            rom_banks: rom_banks.max(2).next_power_of_two(),
+            ram_banks: ram_banks.max(1).next_power_of_two(),
+        }
+    }
+
+    /// Write to a register (0x0000-0x7FFF)
+    pub fn write(&mut self, address: u16, value: u8) {
+        match address {
+            0x0000..=0x1FFF => self.ram_enabled = value & 0x0F == 0x0A,
+            0x2000..=0x3FFF => self.bank1 = (value & 0x1F).max(1),
+            0x4000..=0x5FFF => self.bank2 = value & 0x03,
+            0x6000..=0x7FFF => self.mode = value & 0x01 != 0,
+            _ => {}
+        }
+    }
+
+    /// ROM bank mapped at 0x0000-0x3FFF: 0, or BANK2 << 5 in mode 1
+    pub fn low_rom_bank(&self) -> usize {
+        if self.mode {
+            ((self.bank2 as usize) << 5) & (self.rom_banks - 1)
+        } else {
+            0
+        }
+    }
+
+    /// ROM bank mapped at 0x4000-0x7FFF
+    pub fn high_rom_bank(&self) -> usize {
+        ((self.bank2 as usize) << 5 | self.bank1 as usize) & (self.rom_banks - 1)
+    }
+
+    /// RAM bank mapped at 0xA000-0xBFFF: 0, or BANK2 in mode 1
+    pub fn ram_bank(&self) -> usize {
+        if self.mode {
+            self.bank2 as usize & (self.ram_banks - 1)
+        } else {
+            0
+        }
+    }
+
+    /// RAM enabled with 0x0A in 0x0000-0x1FFF; reads return 0xFF and writes are lost
+    /// otherwise
+    pub fn ram_enabled(&self) -> bool {
+        self.ram_enabled
+    }
+
+    /// Banking mode (0x6000-0x7FFF): 0 or 1
+    pub fn mode(&self) -> u8 {
+        self.mode as u8
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Mbc3 {
+    // Enables both RAM and the clock registers
+    ram_enabled: bool,
+    rom_bank: u8,
+    // 0x00-0x03 selects a RAM bank, 0x08-0x0C a clock register
+    ram_select: u8,
+    rom_banks: usize,
+    ram_banks: usize,
+    rtc: Option<Rtc>,
+}
+
+impl Mbc3 {
+    /// Power-on state; `has_rtc` for the TIMER variants
+    pub fn new(rom_banks: usize, ram_banks: usize, has_rtc: bool) -> Self {
+        Self {
+            ram_enabled: false,
+            rom_bank: 1,
+            ram_select: 0,
+            rom_banks: rom_banks.max(2).next_power_of_two(),
+            ram_banks: ram_banks.max(1).next_power_of_two(),
+            rtc: has_rtc.then(Rtc::new),
+        }
+    }
+
+    /// Write to a register (0x0000-0x7FFF)
+    pub fn write(&mut self, address: u16, value: u8) {
+        match address {
+            0x0000..=0x1FFF => self.ram_enabled = value & 0x0F == 0x0A,
+            0x2000..=0x3FFF => self.rom_bank = (value & 0x7F).max(1),
+            0x4000..=0x5FFF => self.ram_select = value,
+            0x6000..=0x7FFF => {
+                if let Some(rtc) = self.rtc.as_mut() {
+                    rtc.write_latch(value);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Write to the clock register selected at 0x4000-0x5FFF. Writing the seconds also
+    /// restarts the current second.
+    pub fn write_rtc(&mut self, select: u8, value: u8) {
+        if let Some(rtc) = self.rtc.as_mut() {
+            rtc.write_register(select, value);
+        }
+    }
+
+    fn tick(&mut self, t_cycles: u32) {
+        if let Some(rtc) = self.rtc.as_mut() {
+            rtc.advance(EmuDuration::from_cycles(t_cycles as u64));
+        }
+    }
+
+    /// ROM bank mapped at 0x4000-0x7FFF
+    pub fn rom_bank(&self) -> usize {
+        self.rom_bank as usize & (self.rom_banks - 1)
+    }
+
+    /// What 0xA000-0xBFFF maps while enabled. On a cartridge without a clock, clock
+    /// selects wrap to a RAM bank.
+    pub fn ram_mapping(&self) -> RamMapping {
+        match self.ram_select {
+            0x08..=0x0C if self.rtc.is_some() => RamMapping::Rtc(self.ram_select),
+            select => RamMapping::Bank(select as usize & (self.ram_banks - 1)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Mbc5 {
+    ram_enabled: bool,
+    rom_bank: u16,
+    ram_bank: u8,
+    rom_banks: usize,
+    ram_banks: usize,
+    // Bit 3 of the RAM bank drives the motor instead of selecting RAM
+    rumble: bool,
+}
+
+impl Mbc5 {
+    /// Power-on state; `rumble` for the RUMBLE variants
+    pub fn new(rom_banks: usize, ram_banks: usize, rumble: bool) -> Self {
+        Self {
+            ram_enabled: false,
+            rom_bank: 1,
+            ram_bank: 0,
+            rom_banks: rom_banks.max(2).next_power_of_two(),
+            ram_banks: ram_banks.max(1).next_power_of_two(),
+            rumble,
+        }
+    }
+
+    /// Write to a register (0x0000-0x7FFF)
+    pub fn write(&mut self, address: u16, value: u8) {
+        match address {
+            // Unlike MBC1 and MBC3, all 8 bits are compared
+            0x0000..=0x1FFF => self.ram_enabled = value == 0x0A,
+            0x2000..=0x2FFF => self.rom_bank = (self.rom_bank & 0x100) | value as u16,
+            0x3000..=0x3FFF => {
+                self.rom_bank = (self.rom_bank & 0xFF) | ((value as u16 & 0x01) << 8)
+            }
+            0x4000..=0x5FFF => self.ram_bank = value & 0x0F,
+            _ => {}
+        }
+    }
+
+    /// ROM bank mapped at 0x4000-0x7FFF; 0 is not remapped to 1
+    pub fn rom_bank(&self) -> usize {
+        self.rom_bank as usize & (self.rom_banks - 1)
+    }
+
+    /// RAM bank mapped at 0xA000-0xBFFF
+    pub fn ram_bank(&self) -> usize {
+        let bank = if self.rumble {
+            self.ram_bank & 0x07
+        } else {
+            self.ram_bank
+        };
+        bank as usize & (self.ram_banks - 1)
+    }
+}
+
+// Bank counts come from the cartridge header, so only the registers are saved; the
+// leading tag keeps a state from being loaded into a different mapper
+impl Mbc {
+    pub(crate) fn save_state(&self, w: &mut StateWriter) {
+        match self {
+            Mbc::Mbc1(mbc) => {
+                w.u8(1);
+                w.bool(mbc.ram_enabled);
+                w.u8(mbc.bank1);
+                w.u8(mbc.bank2);
+                w.bool(mbc.mode);
+            }
+            Mbc::Mbc3(mbc) => {
+                w.u8(3);
+                w.bool(mbc.ram_enabled);
+                w.u8(mbc.rom_bank);
+                w.u8(mbc.ram_select);
+                if let Some(rtc) = &mbc.rtc {
+                    rtc.save_state(w);
+                }
+            }
+            Mbc::Mbc5(mbc) => {
+                w.u8(5);
+                w.bool(mbc.ram_enabled);
+                w.u16(mbc.rom_bank);
+                w.u8(mbc.ram_bank);
+            }
+        }
+    }
+
+    pub(crate) fn load_state(&mut self, r: &mut StateReader) -> Result<(), String> {
+        let tag = r.u8()?;
+        match (self, tag) {
+            (Mbc::Mbc1(mbc), 1) => {
+                mbc.ram_enabled = r.bool()?;
+                mbc.bank1 = r.u8()?;
+                mbc.bank2 = r.u8()?;
+                mbc.mode = r.bool()?;
+            }
+            (Mbc::Mbc3(mbc), 3) => {
+                mbc.ram_enabled = r.bool()?;
+                mbc.rom_bank = r.u8()?;
+                mbc.ram_select = r.u8()?;
+                if let Some(rtc) = &mut mbc.rtc {
+                    rtc.load_state(r)?;
+                }
+            }
+            (Mbc::Mbc5(mbc), 5) => {
+                mbc.ram_enabled = r.bool()?;
+                mbc.rom_bank = r.u16()?;
+                mbc.ram_bank = r.u8()?;
+            }
+            _ => {
+                return Err(format!(
+                    "save state has mapper tag {}, which does not match the cartridge",
+                    tag
+                ))
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::CPU_FREQUENCY;
+
+    #[test]
+    fn test_bank_registers() {
+        let mut mbc = Mbc1::new(128, 4);
+        assert_eq!((mbc.low_rom_bank(), mbc.high_rom_bank()), (0, 1));
+
+        mbc.write(0x2000, 0xE0); // Only 5 bits kept, and 0 selects 1
+        mbc.write(0x4000, 0x02);
+        assert_eq!(mbc.high_rom_bank(), 0x41);
+        assert_eq!((mbc.low_rom_bank(), mbc.ram_bank()), (0, 0));
+
+        mbc.write(0x6000, 0x01);
+        assert_eq!((mbc.low_rom_bank(), mbc.ram_bank()), (0x40, 2));
+
+        mbc.write(0x0000, 0x1A);
+        assert!(mbc.ram_enabled());
+        mbc.write(0x1FFF, 0x0B);
+        assert!(!mbc.ram_enabled());
+    }
+
+    #[test]
+    fn test_banks_wrap_at_cartridge_size() {
+        // 256 KiB ROM, 8 KiB RAM: BANK2 is ignored except by the 0 -> 1 check on BANK1
+        let mut mbc = Mbc1::new(16, 1);
+        mbc.write(0x2000, 0x13);
+        mbc.write(0x4000, 0x03);
+        mbc.write(0x6000, 0x01);
+        assert_eq!(mbc.high_rom_bank(), 0x03);
+        assert_eq!((mbc.low_rom_bank(), mbc.ram_bank()), (0, 0));
+
+        mbc.write(0x2000, 0x10);
+        assert_eq!(mbc.high_rom_bank(), 0x00);
+    }
+
+    #[test]
+    fn test_mbc3_selects_and_clock() {
+        let mut mbc = Mbc::new(0x10, 128, 4).unwrap();
+        mbc.write(0x2000, 0x00);
+        assert_eq!(mbc.high_rom_bank(), 1);
+        mbc.write(0x2000, 0xFF);
+        assert_eq!(mbc.high_rom_bank(), 0x7F);
+        mbc.write(0x4000, 0x03);
+        assert_eq!(mbc.ram_mapping(), RamMapping::Bank(3));
+        mbc.write(0x4000, 0x0A);
+        assert_eq!(mbc.ram_mapping(), RamMapping::Rtc(0x0A));
+
+        // Two and a half emulated seconds, latched
+        for _ in 0..5 {
+            mbc.tick(CPU_FREQUENCY as u32 / 2);
+        }
+        mbc.write(0x6000, 0x00);
+        mbc.write(0x6000, 0x01);
+        assert_eq!(mbc.rtc().unwrap().read_register(0x08), 2);
+
+        // Without a clock the selects fall back to RAM
+        let mbc = Mbc::new(0x13, 64, 4).unwrap();
+        assert!(mbc.rtc().is_none());
+        assert!(Mbc::new(0x20, 64, 4).is_none());
+    }
+
+    #[test]
+    fn test_mbc5_nine_bit_rom_bank() {
+        // 8 MiB ROM, 128 KiB RAM
+        let mut mbc = Mbc::new(0x1B, 512, 16).unwrap();
+        assert_eq!((mbc.low_rom_bank(), mbc.high_rom_bank()), (0, 1));
+
+        mbc.write(0x2000, 0x00);
+        assert_eq!(mbc.high_rom_bank(), 0);
+        mbc.write(0x2000, 0x34);
+        mbc.write(0x3000, 0x03); // Only bit 0 is kept
+        assert_eq!(mbc.high_rom_bank(), 0x134);
+        mbc.write(0x2FFF, 0xFF);
+        assert_eq!(mbc.high_rom_bank(), 0x1FF);
+        assert_eq!(mbc.rom_banks(), 512);
+
+        mbc.write(0x4000, 0x1F);
+        assert_eq!(mbc.ram_mapping(), RamMapping::Bank(0x0F));
+        mbc.write(0x0000, 0x1A);
+        assert!(!mbc.ram_enabled());
+        mbc.write(0x0000, 0x0A);
+        assert!(mbc.ram_enabled());
+
+        // Smaller ROMs ignore the upper bits; rumble carts don't use bit 3 for RAM
+        let mut mbc = Mbc::new(0x1E, 64, 4).unwrap();
+        mbc.write(0x2000, 0x45);
+        mbc.write(0x3000, 0x01);
+        assert_eq!(mbc.high_rom_bank(), 0x05);
+        mbc.write(0x4000, 0x0A);
+        assert_eq!(mbc.ram_mapping(), RamMapping::Bank(2));
+    }
+}
@@ -1,19 +1,156 @@
+use std::collections::{BTreeSet, VecDeque};
+use std::ops::Range;
+
+use crate::accuracy::AccuracySettings;
+use crate::cartridge::{check_mapper, CartridgeError, CartridgeHeader};
+use crate::clock::EmulatedClock;
+use crate::entropy::{EntropySource, SeededRng};
+use crate::gb_log;
 use crate::joypad::Joypad;
-use crate::ppu::Ppu;
-use crate::serial::Serial;
+use crate::latency::InterruptLatency;
+use crate::logging::LogTarget;
+use crate::mbc::{Mbc, RamMapping, RAM_BANK_SIZE, ROM_BANK_SIZE};
+use crate::model::Model;
+use crate::ppu::{ObjectPriority, Ppu};
+use crate::romimage::RomImage;
+use crate::rtc::Rtc;
+use crate::savestate::{StateReader, StateWriter};
+use crate::serial::{Serial, SerialDevice};
+use crate::strict::{self, StrictChecker, StrictEvent, Violation};
 use crate::timer::Timer;
 
 type MainMemory = [u8; 0x10000];
 
-type RawBankNumber = u8;
+// Internal RAM windows of `main_memory`
+const WORK_RAM: Range<usize> = 0xC000..0xE000;
+const HIGH_RAM: Range<usize> = 0xFF80..0xFFFF;
+
+pub(crate) const BANK_MASK: u8 = 0b0001_1111;
+
+/// Size of one cartridge RAM bank, mapped at 0xA000-0xBFFF
+pub const EXTERNAL_RAM_SIZE: usize = RAM_BANK_SIZE;
+/// Most cartridge RAM banks an emulated mapper can switch between (MBC5: 128 KiB)
+pub const MAX_EXTERNAL_RAM_BANKS: usize = 16;
+
+/// Mapper register writes kept by the MBC log; older ones are dropped
+pub const MBC_LOG_CAPACITY: usize = 256;
 
-const BANK_MASK: u8 = 0b0001_1111;
+/// Write to a mapper register (0x0000-0x7FFF)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MbcWrite {
+    /// T-cycle of the write (see `EmulatedClock::cycles`)
+    pub cycle: u64,
+    pub address: u16,
+    pub value: u8,
+    /// ROM bank mapped at 0x4000-0x7FFF after the write
+    pub rom_bank: u16,
+    /// Cartridge RAM bank, or MBC3 clock register (0x08-0x0C), mapped at 0xA000-0xBFFF
+    /// after the write
+    pub ram_bank: u8,
+    pub ram_enabled: bool,
+}
+
+/// What work RAM and high RAM hold at power-on
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum RamInit {
+    /// The same bytes every run: zeros in work RAM, the post-boot values in high RAM
+    #[default]
+    Fixed,
+    /// Bytes from the machine's entropy source, like the leftover charge of real RAM.
+    /// Shakes out games (and homebrew) that read RAM before writing it.
+    Random,
+}
 
+impl std::str::FromStr for RamInit {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "fixed" => Ok(RamInit::Fixed),
+            "random" => Ok(RamInit::Random),
+            other => Err(format!(
+                "unknown RAM init '{}' (expected fixed or random)",
+                other
+            )),
+        }
+    }
+}
+
+impl std::fmt::Display for RamInit {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            RamInit::Fixed => "fixed",
+            RamInit::Random => "random",
+        })
+    }
+}
+
+/// CPU access to an address with a read or write watchpoint
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WatchedAccess {
+    pub address: u16,
+    /// Value read, or written
+    pub value: u8,
+    pub write: bool,
+}
+
+// Addresses whose CPU accesses are recorded, and the accesses since they were last taken
+#[derive(Debug, Clone, Default)]
+struct AccessWatch {
+    reads: BTreeSet<u16>,
+    writes: BTreeSet<u16>,
+    hits: Vec<WatchedAccess>,
+}
+
+/// Size of the DMG boot ROM, mapped over 0x0000-0x00FF until FF50 is written
+pub const BOOT_ROM_SIZE: usize = 0x100;
+
+/// Bytes (and M-cycles) an OAM DMA transfer copies
+pub const DMA_LENGTH: u16 = 160;
+// M-cycles between the write to 0xFF46 and the first byte copied
+const DMA_STARTUP_M_CYCLES: u16 = 1;
+
+/// Source page of an OAM DMA transfer, the value written to 0xFF46
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct DmaSource(pub u8);
+
+impl DmaSource {
+    /// Address of the first byte copied. The DMG DMA unit decodes E000-FFFF as work RAM,
+    /// like echo RAM but including FE00-FFFF, so pages E0-FF copy from C000-DFFF.
+    pub fn base(&self) -> u16 {
+        let page = if self.0 >= 0xE0 {
+            self.0 - 0x20
+        } else {
+            self.0
+        };
+        (page as u16) << 8
+    }
+
+    /// Address of the `index`th byte copied (0-159)
+    pub fn address(&self, index: u8) -> u16 {
+        self.base() + index as u16
+    }
+}
+
+#[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Memory {
-    pub main_memory: MainMemory,
+    #[cfg_attr(
+        feature = "serde",
+        serde(with = "crate::savestate::serde_array::boxed")
+    )]
+    pub(crate) main_memory: Box<MainMemory>,
+    // Not serialized: put the cartridge back with `rom.buffer` after deserializing
+    #[cfg_attr(feature = "serde", serde(skip))]
     pub rom: Rom,
-    rom_loaded: bool,
-    pub(crate) current_rom_bank: u8,
+    // Boot ROM to run at power-on, see `start_boot_rom`
+    #[cfg_attr(feature = "serde", serde(skip))]
+    boot_rom: Option<Box<[u8]>>,
+    // None for cartridges without an emulated mapper
+    mbc: Option<Mbc>,
+    // Cartridge RAM, all banks; battery saves store it as is
+    external_ram: Vec<u8>,
     pub timer: Timer,
     pub serial: Serial,
     pub ppu: Ppu,
@@ -21,94 +158,417 @@ pub struct Memory {
     // OAM DMA state
     pub dma_active: bool,
     pub dma_cycles_remaining: u16,
-    pub dma_source: u16,
+    pub dma_source: DmaSource,
     // When true, `write_8` will not trigger side-effects (used during init/reset)
     pub suppress_io_side_effects: bool,
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub accuracy: AccuracySettings,
+    /// Cycles executed so far; the core's only notion of time
+    pub clock: EmulatedClock,
+    // M-cycles not yet applied to the peripherals when sub-instruction timing is off
+    deferred_m_cycles: u32,
+    // Cartridge RAM written since the last `take_external_ram_dirty` (battery saves)
+    external_ram_dirty: bool,
+    // Recent mapper register writes, only kept when enabled with `set_mbc_log_enabled`
+    #[cfg_attr(feature = "serde", serde(skip))]
+    mbc_writes: Option<VecDeque<MbcWrite>>,
+    // Strict-mode checks, only run when enabled with `set_strict_enabled`
+    #[cfg_attr(feature = "serde", serde(skip))]
+    strict: Option<Box<StrictChecker>>,
+    // Debugger read/write watchpoints, see `watch_accesses`
+    #[cfg_attr(feature = "serde", serde(skip))]
+    access_watch: Option<Box<AccessWatch>>,
+    // Interrupt latency measurement, see `set_interrupt_latency_enabled`
+    #[cfg_attr(feature = "serde", serde(skip))]
+    interrupt_latency: Option<Box<InterruptLatency>>,
+    // Value LY reads as instead of the current line, see `set_ly_override`
+    #[cfg_attr(feature = "serde", serde(skip))]
+    ly_override: Option<u8>,
+    // Every random number the machine uses comes from here, see `set_entropy`
+    #[cfg_attr(feature = "serde", serde(skip, default = "default_entropy"))]
+    entropy: Box<dyn EntropySource>,
+    #[cfg_attr(feature = "serde", serde(skip))]
+    ram_init: RamInit,
+    // CGB-only registers (KEY0, OPRI) are mapped
+    cgb: bool,
+    // KEY0 (FF4C): bit 2 selects DMG-compatibility mode
+    key0: u8,
 }
 
 impl Memory {
-    pub fn new(rom_buffer: Vec<u8>) -> Memory {
+    /// Memory with `rom` inserted; a `Vec<u8>` is taken as the image, a `RomImage` shared
+    pub fn new(rom: impl Into<RomImage>) -> Memory {
         let mut memory = Memory {
-            main_memory: [0; 0x10000],
+            main_memory: Box::new([0; 0x10000]),
             rom: Rom {
-                buffer: [0; 0x2FFFF],
+                buffer: RomImage::default(),
             },
-            rom_loaded: false,
-            current_rom_bank: 1,
+            boot_rom: None,
+            mbc: None,
+            external_ram: Vec::new(),
             timer: Timer::new(),
             serial: Serial::new(),
             ppu: Ppu::new(),
             joypad: Joypad::new(),
             dma_active: false,
             dma_cycles_remaining: 0,
-            dma_source: 0,
+            dma_source: DmaSource::default(),
             suppress_io_side_effects: false,
+            accuracy: AccuracySettings::default(),
+            clock: EmulatedClock::new(),
+            deferred_m_cycles: 0,
+            external_ram_dirty: false,
+            mbc_writes: None,
+            strict: None,
+            access_watch: None,
+            interrupt_latency: None,
+            ly_override: None,
+            entropy: Box::new(SeededRng::default()),
+            ram_init: RamInit::Fixed,
+            cgb: false,
+            key0: 0,
         };
 
-        // Copy the ROM buffer into the memory's ROM
-        let len = rom_buffer.len().min(memory.rom.buffer.len());
-        memory.rom.buffer[..len].copy_from_slice(&rom_buffer[..len]);
-        memory.rom_loaded = true;
-
+        memory.set_rom(rom);
         memory
     }
+
+    /// Like `new`, but refuses cartridges whose mapper is not emulated
+    pub fn from_rom(rom: impl Into<RomImage>) -> Result<Memory, CartridgeError> {
+        let rom = rom.into();
+        check_mapper(&rom)?;
+        Ok(Memory::new(rom))
+    }
+}
+
+#[cfg(feature = "serde")]
+fn default_entropy() -> Box<dyn EntropySource> {
+    Box::new(SeededRng::default())
 }
 
-#[derive(Clone, Copy)]
+// Shared, so that cloning Memory into keyframes or other instances doesn't copy the ROM
+#[derive(Clone, Default)]
 pub struct Rom {
-    pub buffer: [u8; 0x2FFFF],
+    /// The ROM image, padded to at least 32 KiB
+    pub buffer: RomImage,
 }
 
 impl Memory {
-    pub fn read_8(&self, address: u16) -> u8 {
+    /// Read on behalf of the CPU. Host input is sampled right before a P1 read, so a
+    /// button pressed mid-frame is seen by the very next joypad poll.
+    pub fn cpu_read_8(&mut self, address: u16) -> u8 {
+        if self.dma_conflict(address) {
+            return 0xFF;
+        }
         if address == 0xFF00 {
-            self.joypad.read()
-        } else if (0xFF04..=0xFF07).contains(&address) {
-            self.timer.read(address)
-        } else if (0xFF01..=0xFF02).contains(&address) {
-            self.serial.read(address)
-        } else if (0xFF40..=0xFF4B).contains(&address) {
-            self.ppu.read(address)
+            self.joypad.poll_input();
+        }
+        if self.strict.is_some() {
+            self.check_strict_access(address, None);
+        }
+        if (0xFE00..=0xFEFF).contains(&address) {
+            self.oam_bug(false);
+        }
+        let value = self.read_8(address);
+        if let Some(watch) = self.access_watch.as_mut() {
+            if watch.reads.contains(&address) {
+                watch.hits.push(WatchedAccess {
+                    address,
+                    value,
+                    write: false,
+                });
+            }
+        }
+        value
+    }
+
+    /// Write from the CPU: `write_8`, plus the strict-mode checks and write watchpoints
+    /// when they are enabled
+    pub fn cpu_write_8(&mut self, address: u16, value: u8) {
+        if self.strict.is_some() {
+            self.check_strict_access(address, Some(value));
+        }
+        if (0xFE00..=0xFEFF).contains(&address) {
+            self.oam_bug(true);
+        }
+        if address == 0xFF0F {
+            if let Some(latency) = self.interrupt_latency.as_mut() {
+                latency.cancelled(self.main_memory[0xFF0F] & !value & 0x1F);
+            }
+        }
+        if let Some(watch) = self.access_watch.as_mut() {
+            if watch.writes.contains(&address) {
+                watch.hits.push(WatchedAccess {
+                    address,
+                    value,
+                    write: true,
+                });
+            }
+        }
+        self.write_8(address, value);
+    }
+
+    /// Record CPU reads of `reads` and writes to `writes`, replacing any addresses watched
+    /// before, until `unwatch_accesses`. With both sets empty nothing is recorded.
+    pub fn watch_accesses(&mut self, reads: &BTreeSet<u16>, writes: &BTreeSet<u16>) {
+        self.access_watch = (!reads.is_empty() || !writes.is_empty()).then(|| {
+            Box::new(AccessWatch {
+                reads: reads.clone(),
+                writes: writes.clone(),
+                hits: Vec::new(),
+            })
+        });
+    }
+
+    pub fn unwatch_accesses(&mut self) {
+        self.access_watch = None;
+    }
+
+    /// Watched accesses since the last call, oldest first
+    pub fn take_watched_accesses(&mut self) -> Vec<WatchedAccess> {
+        self.access_watch
+            .as_mut()
+            .map_or_else(Vec::new, |watch| std::mem::take(&mut watch.hits))
+    }
+
+    pub fn read_8(&self, address: u16) -> u8 {
+        if (0xFF00..=0xFF7F).contains(&address) {
+            self.read_io(address)
         } else if (0x8000..=0x9FFF).contains(&address) {
             self.ppu.vram[(address - 0x8000) as usize]
         } else if (0xFE00..=0xFE9F).contains(&address) {
             self.ppu.oam[(address - 0xFE00) as usize]
-        } else if self.rom_loaded && address < 0x4000 {
-            self.rom.buffer[address as usize]
-        } else if self.rom_loaded && (0x4000..0x8000).contains(&address) {
-            let bank = if self.current_rom_bank == 0 {
-                1
-            } else {
-                self.current_rom_bank
-            };
-            let offset = (bank as usize) * 0x4000 + (address as usize - 0x4000);
-
-            if offset < self.rom.buffer.len() {
-                self.rom.buffer[offset]
-            } else {
-                0xFF // Return 0xFF for out-of-bounds ROM access
-            }
+        } else if (0xE000..=0xFDFF).contains(&address) {
+            // Echo RAM mirrors 0xC000-0xDDFF
+            self.main_memory[(address - 0x2000) as usize]
+        } else if (0xFEA0..=0xFEFF).contains(&address) {
+            0x00 // Unusable on DMG
+        } else if address < 0x8000 {
+            self.read_rom(address)
+        } else if (0xA000..=0xBFFF).contains(&address) {
+            self.read_external_ram(address)
         } else {
             self.main_memory[address as usize]
         }
     }
 
-    // Tick timer and PPU - called explicitly by CPU during instruction execution
+    // IO registers (0xFF00-0xFF7F). Addresses without a register, write-only registers,
+    // and CGB registers on DMG models read 0xFF.
+    fn read_io(&self, address: u16) -> u8 {
+        if let (0xFF44, Some(ly)) = (address, self.ly_override) {
+            return ly;
+        }
+        match address {
+            0xFF00 => self.joypad.read(),
+            0xFF01..=0xFF02 => self.serial.read(address),
+            0xFF04..=0xFF07 => self.timer.read(address),
+            // IF: only the five interrupt bits exist
+            0xFF0F => self.main_memory[0xFF0F] | 0xE0,
+            // Sound registers and wave RAM, kept as written until there is an APU
+            0xFF10..=0xFF14 | 0xFF16..=0xFF1E | 0xFF20..=0xFF26 | 0xFF30..=0xFF3F => {
+                self.main_memory[address as usize]
+            }
+            // DMA reads back the last source page
+            0xFF46 => self.main_memory[0xFF46],
+            0xFF40..=0xFF4B => self.ppu.read(address),
+            // KEY0 and OPRI: stored by write_compatibility_register, 0xFF on DMG
+            0xFF4C | 0xFF6C => self.main_memory[address as usize],
+            // KEY1, VBK, HDMA5, RP, palette access, SVBK and the undocumented FF72-FF77
+            0xFF4D | 0xFF4F | 0xFF55 | 0xFF56 | 0xFF68..=0xFF6B | 0xFF70 | 0xFF72..=0xFF77
+                if self.cgb =>
+            {
+                self.main_memory[address as usize]
+            }
+            _ => 0xFF,
+        }
+    }
+
+    fn read_rom(&self, address: u16) -> u8 {
+        if address < BOOT_ROM_SIZE as u16 && self.boot_rom_mapped() {
+            if let Some(boot_rom) = &self.boot_rom {
+                return boot_rom[address as usize];
+            }
+        }
+        let bank = match &self.mbc {
+            Some(mbc) if address < 0x4000 => mbc.low_rom_bank(),
+            Some(mbc) => mbc.high_rom_bank(),
+            None => address as usize / ROM_BANK_SIZE,
+        };
+        let offset = bank * ROM_BANK_SIZE + (address as usize % ROM_BANK_SIZE);
+        // Banks past the end of the image read as open bus
+        self.rom.buffer.get(offset).copied().unwrap_or(0xFF)
+    }
+
+    // What the CPU sees at 0xA000-0xBFFF, None while disabled
+    fn external_ram_mapping(&self) -> Option<RamMapping> {
+        match &self.mbc {
+            Some(mbc) if !mbc.ram_enabled() => None,
+            Some(mbc) => Some(mbc.ram_mapping()),
+            None => Some(RamMapping::Bank(0)),
+        }
+    }
+
+    fn read_external_ram(&self, address: u16) -> u8 {
+        match self.external_ram_mapping() {
+            Some(RamMapping::Bank(bank)) => {
+                self.external_ram[bank * EXTERNAL_RAM_SIZE + (address as usize - 0xA000)]
+            }
+            Some(RamMapping::Rtc(select)) => {
+                self.rtc().map_or(0xFF, |rtc| rtc.read_register(select))
+            }
+            None => 0xFF, // Disabled
+        }
+    }
+
+    fn write_cartridge_ram(&mut self, address: u16, value: u8) {
+        match self.external_ram_mapping() {
+            Some(RamMapping::Bank(bank)) => {
+                self.external_ram[bank * EXTERNAL_RAM_SIZE + (address as usize - 0xA000)] = value;
+            }
+            Some(RamMapping::Rtc(select)) => {
+                if let Some(mbc) = self.mbc.as_mut() {
+                    mbc.write_rtc(select, value);
+                }
+            }
+            None => return,
+        }
+        // The clock is saved with the RAM, so setting it also needs a save
+        self.external_ram_dirty = true;
+    }
+
+    /// The cartridge's mapper, if it has an emulated one
+    pub fn mbc(&self) -> Option<&Mbc> {
+        self.mbc.as_ref()
+    }
+
+    /// Work RAM (C000-DFFF)
+    pub fn work_ram(&self) -> &[u8] {
+        &self.main_memory[WORK_RAM]
+    }
+
+    pub fn work_ram_mut(&mut self) -> &mut [u8] {
+        &mut self.main_memory[WORK_RAM]
+    }
+
+    /// High RAM (FF80-FFFE)
+    pub fn high_ram(&self) -> &[u8] {
+        &self.main_memory[HIGH_RAM]
+    }
+
+    pub fn high_ram_mut(&mut self) -> &mut [u8] {
+        &mut self.main_memory[HIGH_RAM]
+    }
+
+    /// The cartridge's real-time clock (MBC3+TIMER)
+    pub fn rtc(&self) -> Option<&Rtc> {
+        self.mbc.as_ref()?.rtc()
+    }
+
+    pub fn rtc_mut(&mut self) -> Option<&mut Rtc> {
+        self.mbc.as_mut()?.rtc_mut()
+    }
+
+    /// ROM bank currently mapped at 0x4000-0x7FFF
+    pub fn rom_bank(&self) -> u16 {
+        self.mbc
+            .as_ref()
+            .map_or(1, |mbc| mbc.high_rom_bank() as u16)
+    }
+
+    /// ROM banks (including bank 0) the mapper can select; without a mapper only
+    /// banks 0 and 1 are mapped
+    pub fn mappable_rom_banks(&self) -> usize {
+        self.mbc.as_ref().map_or(2, Mbc::rom_banks)
+    }
+
+    /// The CPU put `address` on the 16-bit increment unit (INC rr, DEC rr), which disturbs
+    /// OAM like a write when it points there (see `AccuracySettings::oam_bug`)
+    pub fn idu_access(&mut self, address: u16) {
+        if (0xFE00..=0xFEFF).contains(&address) {
+            self.oam_bug(true);
+        }
+    }
+
+    // The OAM corruption bug, on models that have it
+    fn oam_bug(&mut self, write: bool) {
+        if self.accuracy.oam_bug && !self.cgb {
+            self.ppu.corrupt_oam(write);
+        }
+    }
+
+    /// The bus tick: advance the clock, timer, serial port, PPU, mapper and OAM DMA by
+    /// `m_cycles`. The CPU calls it once per M-cycle of each instruction, right after
+    /// that M-cycle's memory access, so accesses see the hardware where it really is.
     pub fn tick_components(&mut self, m_cycles: u32) {
+        if !self.accuracy.sub_instruction_timing {
+            // Catch up in one go at the end of the instruction (flush_deferred_ticks)
+            self.deferred_m_cycles += m_cycles;
+            return;
+        }
+        self.advance_components(m_cycles);
+    }
+
+    // Apply cycles deferred during the current instruction, if any
+    pub fn flush_deferred_ticks(&mut self) {
+        if self.deferred_m_cycles > 0 {
+            let m_cycles = std::mem::take(&mut self.deferred_m_cycles);
+            self.advance_components(m_cycles);
+        }
+    }
+
+    fn advance_components(&mut self, m_cycles: u32) {
+        self.clock.tick(m_cycles * 4);
         self.timer.tick(m_cycles as u16);
+        // A link device or cable needs transfers to progress even with serial timing off
+        if self.accuracy.serial_timing
+            || self.serial.device() != SerialDevice::Disconnected
+            || self.serial.link().is_some()
+        {
+            self.serial.tick(m_cycles * 4);
+        }
+        // Host setting, applied as the PPU runs so changing `accuracy` takes effect at once
+        self.ppu.pixel_fifo = self.accuracy.pixel_fifo;
         self.ppu.step(m_cycles*4);
+        if let Some(mbc) = self.mbc.as_mut() {
+            mbc.tick(m_cycles * 4);
+        }
 
-        // Handle DMA cycles
-        if self.dma_active {
-            if self.dma_cycles_remaining > (m_cycles) as u16 {
-                self.dma_cycles_remaining -= (m_cycles) as u16;
-            } else {
-                self.dma_cycles_remaining = 0;
+        // OAM DMA copies one byte per M-cycle
+        for _ in 0..m_cycles {
+            if !self.dma_active {
+                break;
+            }
+            self.dma_cycles_remaining -= 1;
+            if self.dma_cycles_remaining < DMA_LENGTH {
+                let index = (DMA_LENGTH - 1 - self.dma_cycles_remaining) as u8;
+                self.ppu.oam[index as usize] = self.read_8(self.dma_source.address(index));
+            }
+            if self.dma_cycles_remaining == 0 {
                 self.dma_active = false;
             }
         }
     }
 
+    /// OAM DMA is copying bytes (past its startup cycle)
+    pub fn dma_transferring(&self) -> bool {
+        self.dma_active && self.dma_cycles_remaining <= DMA_LENGTH
+    }
+
+    // The CPU cannot use the bus an OAM DMA transfer is reading from (the external bus
+    // for ROM, cartridge RAM and WRAM, or the VRAM bus) nor OAM itself; IO and HRAM stay
+    // accessible, which is why games run their DMA wait loop from HRAM
+    fn dma_conflict(&self, address: u16) -> bool {
+        if !self.dma_transferring() {
+            return false;
+        }
+        let vram_bus = |address: u16| (0x8000..=0x9FFF).contains(&address);
+        match address {
+            0xFE00..=0xFEFF => true,
+            0xFF00..=0xFFFF => false,
+            _ => vram_bus(address) == vram_bus(self.dma_source.base()),
+        }
+    }
+
     pub fn read_16(&self, address: u16) -> u16 {
         let x = self.read_8(address);
         let y = self.read_8(address + 1);
@@ -116,13 +576,168 @@ impl Memory {
     }
 
     fn write_to_rom_register(&mut self, address: u16, value: u8) {
-        // Simple MBC1-style lower 5 bits bank select in 0x2000-3FFF
-        if (0x2000..=0x3FFF).contains(&address) {
-            let mut bank_number: RawBankNumber = value & BANK_MASK;
-            if bank_number == 0 {
-                bank_number = 1; // Bank 0 is remapped to 1
+        if let Some(mbc) = self.mbc.as_mut() {
+            mbc.write(address, value);
+        }
+
+        let rom_bank = self.rom_bank();
+        let ram_bank = match self.mbc.as_ref().map(Mbc::ram_mapping) {
+            Some(RamMapping::Bank(bank)) => bank as u8,
+            Some(RamMapping::Rtc(select)) => select,
+            None => 0,
+        };
+        let ram_enabled = self.external_ram_enabled();
+        gb_log!(
+            LogTarget::Mbc,
+            "{:02X} -> {:04X}: ROM bank {}, RAM bank {}, RAM {}",
+            value,
+            address,
+            rom_bank,
+            ram_bank,
+            if ram_enabled { "enabled" } else { "disabled" }
+        );
+        if let Some(writes) = self.mbc_writes.as_mut() {
+            if writes.len() == MBC_LOG_CAPACITY {
+                writes.pop_front();
+            }
+            writes.push_back(MbcWrite {
+                cycle: self.clock.cycles(),
+                address,
+                value,
+                rom_bank,
+                ram_bank,
+                ram_enabled,
+            });
+        }
+    }
+
+    /// Start or stop logging mapper register writes (see `mbc_writes`)
+    pub fn set_mbc_log_enabled(&mut self, enabled: bool) {
+        self.mbc_writes = enabled.then(|| VecDeque::with_capacity(MBC_LOG_CAPACITY));
+    }
+
+    pub fn mbc_log_enabled(&self) -> bool {
+        self.mbc_writes.is_some()
+    }
+
+    /// The last `MBC_LOG_CAPACITY` mapper register writes, oldest first
+    pub fn mbc_writes(&self) -> impl Iterator<Item = &MbcWrite> {
+        self.mbc_writes.iter().flatten()
+    }
+
+    /// Start or stop strict mode (see `crate::strict`). Enabling it forgets which work RAM
+    /// bytes have been written, so it belongs before the game starts.
+    pub fn set_strict_enabled(&mut self, enabled: bool) {
+        self.strict = enabled.then(|| Box::new(StrictChecker::new()));
+    }
+
+    pub fn strict_enabled(&self) -> bool {
+        self.strict.is_some()
+    }
+
+    /// Strict-mode violations since the last call, oldest first
+    pub fn take_strict_events(&mut self) -> Vec<StrictEvent> {
+        self.strict
+            .as_mut()
+            .map_or_else(Vec::new, |strict| strict.take_events())
+    }
+
+    /// Make LY (0xFF44) read as `ly` whatever line the PPU is on, or undo that with None.
+    /// Gameboy Doctor's reference traces are made with LY fixed at 0x90, so a game that
+    /// polls LY only matches them with the same override. The PPU itself is unaffected.
+    pub fn set_ly_override(&mut self, ly: Option<u8>) {
+        self.ly_override = ly;
+    }
+
+    /// Draw randomness from `source` from now on. Features that need random numbers take
+    /// them from here rather than the OS, so a run is repeatable from the source's seed.
+    pub fn set_entropy(&mut self, source: Box<dyn EntropySource>) {
+        self.entropy = source;
+    }
+
+    pub fn entropy_mut(&mut self) -> &mut dyn EntropySource {
+        self.entropy.as_mut()
+    }
+
+    /// Fill work RAM and high RAM as `init` says, now and on every reset. Call after
+    /// powering on and before the game runs; `Fixed` keeps what power-on put there.
+    pub fn set_ram_init(&mut self, init: RamInit) {
+        self.ram_init = init;
+        if init == RamInit::Random {
+            self.entropy.fill_bytes(&mut self.main_memory[WORK_RAM]);
+            self.entropy.fill_bytes(&mut self.main_memory[HIGH_RAM]);
+        }
+    }
+
+    pub fn ram_init(&self) -> RamInit {
+        self.ram_init
+    }
+
+    /// Start or stop measuring interrupt latency (see `crate::latency`); enabling it
+    /// starts from empty statistics
+    pub fn set_interrupt_latency_enabled(&mut self, enabled: bool) {
+        self.interrupt_latency = enabled.then(|| Box::new(InterruptLatency::new()));
+    }
+
+    /// Latencies measured so far, if enabled
+    pub fn interrupt_latency(&self) -> Option<&InterruptLatency> {
+        self.interrupt_latency.as_deref()
+    }
+
+    pub fn interrupt_latency_mut(&mut self) -> Option<&mut InterruptLatency> {
+        self.interrupt_latency.as_deref_mut()
+    }
+
+    // The handler for `interrupt` (IF bit index) starts now
+    pub(crate) fn interrupt_dispatched(&mut self, interrupt: usize) {
+        let cycle = self.clock.cycles();
+        if let Some(latency) = self.interrupt_latency.as_mut() {
+            latency.dispatched(interrupt, cycle);
+        }
+    }
+
+    // The CPU is starting the instruction at `pc`; strict-mode events are stamped with it
+    pub(crate) fn set_instruction_pc(&mut self, pc: u16) {
+        if let Some(strict) = self.strict.as_mut() {
+            strict.set_pc(pc);
+        }
+    }
+
+    // The CPU is popping two bytes at `sp`; from 0xFFFE up that wraps past the top
+    pub(crate) fn check_stack_pop(&mut self, sp: u16) {
+        if sp >= 0xFFFE {
+            let cycle = self.clock.cycles();
+            if let Some(strict) = self.strict.as_mut() {
+                strict.report(cycle, Violation::StackUnderflow { sp });
+            }
+        }
+    }
+
+    // Strict-mode checks for a CPU read (`value` None) or write
+    fn check_strict_access(&mut self, address: u16, value: Option<u8>) {
+        let cycle = self.clock.cycles();
+        let mode = self.ppu.stat().mode();
+        let oam_locked = self.ppu.lcdc().lcd_enabled() && (mode == 2 || mode == 3);
+        let has_mapper = self.mbc.is_some();
+        let Some(checker) = self.strict.as_mut() else {
+            return;
+        };
+        let violation = match (address, value) {
+            (0xFE00..=0xFE9F, _) if oam_locked => Some(Violation::OamAccess {
+                address,
+                mode,
+                write: value.is_some(),
+            }),
+            (0x0000..=0x7FFF, Some(value)) if !has_mapper => {
+                Some(Violation::RomWrite { address, value })
             }
-            self.current_rom_bank = bank_number;
+            (_, None) => strict::wram_address(address)
+                .filter(|&wram| !checker.is_written(wram))
+                .map(|_| Violation::UninitializedRead { address }),
+            _ => None,
+        };
+        if let Some(violation) = violation {
+            checker.report(cycle, violation);
         }
     }
 
@@ -135,19 +750,28 @@ impl Memory {
             return;
         }
 
-        // OAM DMA trigger (write to 0xFF46)
-        if address == 0xFF46 {
-            let source = (value as u16) << 8;
-            self.dma_active = true;
-            // DMA takes 160  machine cycles on DMG (approx 160 cycles)
-            self.dma_cycles_remaining = 160;
-            self.dma_source = source;
+        if self.dma_conflict(address) {
+            return;
+        }
+
+        if let Some(strict) = self.strict.as_mut() {
+            if let Some(wram) = strict::wram_address(address) {
+                strict.mark_written(wram);
+            }
+        }
 
-            // Immediate copy of 160 bytes into OAM (FE00..FE9F)
-            for i in 0..160u16 {
-                let v = self.read_8(source + i);
-                self.ppu.oam[i as usize] = v;
+        if address == 0xFF0F {
+            if let Some(latency) = self.interrupt_latency.as_mut() {
+                let raised = value & !self.main_memory[0xFF0F] & 0x1F;
+                latency.requested(raised, self.clock.cycles());
             }
+        }
+
+        // OAM DMA trigger (write to 0xFF46); restarts a transfer in progress
+        if address == 0xFF46 {
+            self.dma_active = true;
+            self.dma_cycles_remaining = DMA_LENGTH + DMA_STARTUP_M_CYCLES;
+            self.dma_source = DmaSource(value);
 
             // Also write the value to IO register if code expects to read it
             self.main_memory[address as usize] = value;
@@ -170,72 +794,317 @@ impl Memory {
         } else if (0x8000..=0x9FFF).contains(&address) {
             // VRAM can only be written when LCD is off OR PPU is not in mode 3 (drawing)
             // Mode is stored in lower 2 bits of STAT register
-            let ppu_mode = self.ppu.stat & 0x03;
-            if self.dma_active || ppu_mode == 3 {
-                // DMA or mode 3 active, maybe we should add the LCD off check later but fir now it works
+            let ppu_mode = self.ppu.stat().mode();
+            if ppu_mode == 3 {
+                // Mode 3 active, maybe we should add the LCD off check later but fir now it works
                 return;
             }
 
             self.ppu.vram[(address - 0x8000) as usize] = value;
             return;
         } else if (0xFE00..=0xFE9F).contains(&address) {
-            if self.dma_active {
-                return;
-            }
             self.ppu.oam[(address - 0xFE00) as usize] = value;
             return;
+        } else if (0xE000..=0xFDFF).contains(&address) {
+            self.main_memory[(address - 0x2000) as usize] = value;
+            return;
+        } else if (0xFEA0..=0xFEFF).contains(&address) {
+            return; // Unusable, writes are ignored
+        } else if address == 0xFF50 {
+            // Boot ROM disable: any non-zero write unmaps it until the next reset
+            if value != 0 {
+                self.main_memory[0xFF50] = 0x01;
+            }
+            return;
+        } else if address == 0xFF4C || address == 0xFF6C {
+            self.write_compatibility_register(address, value);
+            return;
         } else if address < 0x8000 {
             // ROM writes (for ROM banking control)
             self.write_to_rom_register(address, value);
             return;
+        } else if (0xA000..=0xBFFF).contains(&address) {
+            self.write_cartridge_ram(address, value);
+            return;
         }
 
         // Default: write to main memory
         self.main_memory[address as usize] = value;
     }
 
+    /// Cartridge RAM, every bank in order, as stored in a battery save
+    pub fn external_ram(&self) -> &[u8] {
+        &self.external_ram
+    }
+
+    /// Fill cartridge RAM from a battery save; bytes past the last bank are ignored
+    pub fn load_external_ram(&mut self, data: &[u8]) {
+        let len = data.len().min(self.external_ram.len());
+        self.external_ram[..len].copy_from_slice(&data[..len]);
+    }
+
+    /// Whether the game has cartridge RAM enabled (0x0A written to 0x0000-0x1FFF).
+    /// Cartridges without a mapper have no enable register and always are.
+    pub fn external_ram_enabled(&self) -> bool {
+        self.mbc.as_ref().is_none_or(Mbc::ram_enabled)
+    }
+
+    /// Cartridge RAM banks; at least one, even when the header declares no RAM
+    pub fn external_ram_banks(&self) -> usize {
+        self.external_ram.len() / EXTERNAL_RAM_SIZE
+    }
+
+    /// Contents of cartridge RAM bank `bank`, if it exists
+    pub fn external_ram_bank(&self, bank: usize) -> Option<&[u8]> {
+        self.external_ram.chunks(EXTERNAL_RAM_SIZE).nth(bank)
+    }
+
+    /// Overwrite bytes of cartridge RAM bank `bank` from the debugger. Marks the RAM as
+    /// written, so an edited battery save is flushed like one the game wrote.
+    pub fn write_external_ram(
+        &mut self,
+        bank: usize,
+        offset: usize,
+        data: &[u8],
+    ) -> Result<(), String> {
+        if bank >= self.external_ram_banks() {
+            return Err(format!(
+                "no cartridge RAM bank {} ({} bank(s))",
+                bank,
+                self.external_ram_banks()
+            ));
+        }
+        if offset + data.len() > EXTERNAL_RAM_SIZE {
+            return Err(format!(
+                "offset {:#06X} is past the end of the bank",
+                offset + data.len()
+            ));
+        }
+        let start = bank * EXTERNAL_RAM_SIZE + offset;
+        self.external_ram[start..start + data.len()].copy_from_slice(data);
+        self.external_ram_dirty = true;
+        Ok(())
+    }
+
+    /// Whether cartridge RAM was written since the last call
+    pub fn take_external_ram_dirty(&mut self) -> bool {
+        std::mem::take(&mut self.external_ram_dirty)
+    }
+
+    // KEY0 and OPRI exist only on CGB, and only the boot ROM can write them: both lock
+    // once FF50 unmaps it
+    fn write_compatibility_register(&mut self, address: u16, value: u8) {
+        if !self.cgb || self.main_memory[0xFF50] != 0 {
+            return;
+        }
+        if address == 0xFF4C {
+            self.key0 = value;
+        } else {
+            self.set_object_priority(value);
+        }
+    }
+
+    fn set_object_priority(&mut self, opri: u8) {
+        self.ppu.set_object_priority(if opri & 0x01 != 0 {
+            ObjectPriority::XCoordinate
+        } else {
+            ObjectPriority::OamIndex
+        });
+        self.main_memory[0xFF6C] = opri | 0xFE;
+    }
+
+    /// A CGB running a DMG cartridge (KEY0 bit 2), with DMG sprite priority
+    pub fn dmg_compatibility_mode(&self) -> bool {
+        self.cgb && self.key0 & 0x04 != 0
+    }
+
+    /// Little-endian write, low byte first; the address wraps past 0xFFFF.
+    /// Stack pushes write high byte first, so the CPU orders those itself.
     pub fn write_16(&mut self, address: u16, value: u16) {
         self.write_8(address, (value & 0xFF) as u8);
         self.write_8(address.wrapping_add(1), (value >> 8) as u8);
     }
 
-    pub fn set_rom(&mut self, rom: [u8; 0x2FFFF]) {
-        self.rom.buffer = rom;
-        self.rom_loaded = true;
-        self.current_rom_bank = 1;
+    /// Header of the inserted cartridge
+    pub fn cartridge_header(&self) -> CartridgeHeader {
+        CartridgeHeader::parse(&self.rom.buffer)
     }
 
-    //initialize rom bank pointer after loading ROM
-    pub fn init_rom_bank(&mut self) {
-        // Kept for compatibility; just marks ROM loaded and resets bank
-        self.rom_loaded = true;
-        self.current_rom_bank = 1;
+    /// Insert a cartridge: the mapper starts in its power-on state and cartridge RAM is
+    /// cleared, sized from the header
+    pub fn set_rom(&mut self, rom: impl Into<RomImage>) {
+        let rom = rom.into();
+        let header = CartridgeHeader::parse(&rom);
+        let ram_banks = header
+            .ram_size
+            .unwrap_or(0)
+            .div_ceil(EXTERNAL_RAM_SIZE)
+            .clamp(1, MAX_EXTERNAL_RAM_BANKS);
+        self.mbc = Mbc::new(
+            header.cartridge_type,
+            rom.len().div_ceil(ROM_BANK_SIZE),
+            ram_banks,
+        );
+        // Without an emulated mapper, 32 KiB of ROM and 8 KiB of RAM are mapped
+        let ram_banks = if self.mbc.is_some() { ram_banks } else { 1 };
+        self.external_ram = vec![0; ram_banks * EXTERNAL_RAM_SIZE];
+        self.rom.buffer = rom;
     }
 
     pub fn init_post_boot_state(&mut self) {
-        // Initialize IO registers from the canonical post-boot table
-        // IO_RESET maps to 0xFF00..0xFFFF
+        self.skip_boot(Model::Dmg);
+    }
+
+    /// Put the IO space and peripherals in the state `model`'s boot ROM leaves
+    /// them in when it hands control to the cartridge at 0x0100.
+    pub fn skip_boot(&mut self, model: Model) {
+        let io_reset = match model {
+            Model::Dmg0 | Model::Dmg | Model::Mgb => &IO_RESET_DMG,
+            Model::Cgb => &IO_RESET_CGB,
+        };
+
         // Suppress IO side-effects while copying the canonical IO reset table
         // (this mirrors the behavior of a memcpy in the original C code)
         self.suppress_io_side_effects = true;
         for i in 0..0x100u16 {
             let addr = 0xFF00u16.wrapping_add(i);
-            let value = IO_RESET[i as usize];
+            let value = io_reset[i as usize];
             // Directly copy into main memory while side-effects are suppressed
             self.write_8(addr, value);
         }
         self.suppress_io_side_effects = false;
 
-        // Ensure the Joypad internal register reflects the copied IO_RESET value at 0xFF00
-        let joypad_init = self.main_memory[0xFF00];
-        self.joypad.set_register_raw(joypad_init);
- // Ensure boot-disable (FF50) is set to 1 to indicate boot ROM finished
+        // P1 lives in the joypad, not in main memory
+        self.joypad.set_register_raw(io_reset[0]);
+
+        // The boot ROM runs for a model-specific number of cycles, which fixes the DIV phase
+        self.timer.set_internal_counter(model.post_boot_div());
+
+        // The CGB boot ROM copies the header's CGB flag into KEY0, or puts cartridges
+        // without CGB support into DMG-compatibility mode with DMG sprite priority
+        self.cgb = model.is_cgb();
+        if self.cgb {
+            let cgb_flag = self.read_8(0x0143);
+            let (key0, opri) = if cgb_flag & 0x80 != 0 {
+                (cgb_flag, 0x00)
+            } else {
+                (0x04, 0x01)
+            };
+            self.key0 = key0;
+            self.set_object_priority(opri);
+        }
+
+        // Ensure boot-disable (FF50) is set to 1 to indicate boot ROM finished
         self.main_memory[0xFF50] = 0x01;
     }
+
+    /// Start from power-on with `boot_rom` (the 256-byte DMG boot ROM) mapped over
+    /// 0x0000-0x00FF instead of applying a post-boot state. The boot ROM scrolls the logo,
+    /// sets up the IO registers and unmaps itself by writing FF50 before the cartridge
+    /// starts at 0x0100. Pair with a fresh `Cpu::new()`, which starts at 0x0000.
+    pub fn start_boot_rom(&mut self, boot_rom: Vec<u8>) -> Result<(), String> {
+        if boot_rom.len() != BOOT_ROM_SIZE {
+            return Err(format!(
+                "boot ROM is {} bytes, expected {}",
+                boot_rom.len(),
+                BOOT_ROM_SIZE
+            ));
+        }
+        self.boot_rom = Some(boot_rom.into_boxed_slice());
+        self.main_memory[0xFF50] = 0x00;
+        // The LCD is off at power-on; the boot ROM turns it on
+        self.ppu.write(0xFF40, 0x00);
+        Ok(())
+    }
+
+    /// Whether the CPU sees the boot ROM at 0x0000-0x00FF
+    pub fn boot_rom_mapped(&self) -> bool {
+        self.boot_rom.is_some() && self.main_memory[0xFF50] == 0
+    }
+
+    /// Power-cycle the console and apply `model`'s post-boot state, as `skip_boot` does for
+    /// a freshly created Memory. With a boot ROM (see `start_boot_rom`) the console starts
+    /// from power-on and runs it again instead, so the CPU must restart at 0x0000. Every
+    /// peripheral goes back to power-on, including its edge state (DIV phase, PPU dot
+    /// position, serial shift progress, pending DMA, MBC bank), so a reset machine runs
+    /// exactly like a fresh one. The ROM, battery-backed cartridge RAM and clock and host
+    /// settings (accuracy, SGB, pixel info, colors, forced sprite priority) are kept.
+    pub fn reset(&mut self, model: Model) {
+        let mut fresh = Memory::new(Vec::new());
+        fresh.set_rom(self.rom.buffer.clone());
+        fresh.external_ram = std::mem::take(&mut self.external_ram);
+        fresh.external_ram_dirty = self.external_ram_dirty;
+        // The MBC3 clock runs off the cartridge battery, not the console
+        if let (Some(old), Some(rtc)) = (self.rtc_mut(), fresh.rtc_mut()) {
+            *rtc = std::mem::take(old);
+        }
+        fresh.accuracy = self.accuracy;
+        fresh.ly_override = self.ly_override;
+        fresh.serial.set_device(self.serial.device());
+        fresh.serial.set_link(self.serial.link().cloned());
+        fresh.entropy = std::mem::replace(&mut self.entropy, Box::new(SeededRng::default()));
+        if self.mbc_writes.is_some() {
+            fresh.set_mbc_log_enabled(true);
+        }
+        if self.strict.is_some() {
+            fresh.set_strict_enabled(true);
+        }
+        if self.interrupt_latency.is_some() {
+            fresh.set_interrupt_latency_enabled(true);
+        }
+        fresh.access_watch = self.access_watch.take().map(|mut watch| {
+            watch.hits.clear();
+            watch
+        });
+        if self.joypad.sgb_enabled() {
+            fresh.joypad.enable_sgb();
+        }
+        fresh
+            .joypad
+            .set_opposing_directions(self.joypad.opposing_directions());
+        fresh
+            .ppu
+            .set_pixel_info_enabled(self.ppu.pixel_info.is_some());
+        fresh.ppu.set_color_scheme(self.ppu.color_scheme());
+        fresh
+            .ppu
+            .force_object_priority(self.ppu.forced_object_priority());
+        match self.boot_rom.take() {
+            Some(boot_rom) => {
+                fresh
+                    .start_boot_rom(boot_rom.into_vec())
+                    .expect("boot ROM size was checked");
+            }
+            None => fresh.skip_boot(model),
+        }
+        fresh.set_ram_init(self.ram_init);
+        *self = fresh;
+    }
 }
 
-// IO register post-boot defaults (maps to 0xFF00..0xFFFF)
-static IO_RESET: [u8; 0x100] = [
+// IO register post-boot defaults (maps to 0xFF00..0xFFFF) for DMG0/DMG/MGB.
+// CGB-only registers are unmapped and read back as 0xFF.
+static IO_RESET_DMG: [u8; 0x100] = [
+    0xCF, 0x00, 0x7C, 0xFF, 0x00, 0x00, 0x00, 0xF8, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0x01,
+    0x80, 0xBF, 0xF3, 0xFF, 0xBF, 0xFF, 0x3F, 0x00, 0xFF, 0xBF, 0x7F, 0xFF, 0x9F, 0xFF, 0xBF, 0xFF,
+    0xFF, 0x00, 0x00, 0xBF, 0x77, 0xF3, 0xF1, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF,
+    0x00, 0xFF, 0x00, 0xFF, 0x00, 0xFF, 0x00, 0xFF, 0x00, 0xFF, 0x00, 0xFF, 0x00, 0xFF, 0x00, 0xFF,
+    0x91, 0x80, 0x00, 0x00, 0x00, 0x00, 0x00, 0xFC, 0x00, 0x00, 0x00, 0x00, 0xFF, 0xFF, 0xFF, 0xFF,
+    0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF,
+    0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF,
+    0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF,
+    0xCE, 0xED, 0x66, 0x66, 0xCC, 0x0D, 0x00, 0x0B, 0x03, 0x73, 0x00, 0x83, 0x00, 0x0C, 0x00, 0x0D,
+    0x00, 0x08, 0x11, 0x1F, 0x88, 0x89, 0x00, 0x0E, 0xDC, 0xCC, 0x6E, 0xE6, 0xDD, 0xDD, 0xD9, 0x99,
+    0xBB, 0xBB, 0x67, 0x63, 0x6E, 0x0E, 0xEC, 0xCC, 0xDD, 0xDC, 0x99, 0x9F, 0xBB, 0xB9, 0x33, 0x3E,
+    0x45, 0xEC, 0x52, 0xFA, 0x08, 0xB7, 0x07, 0x5D, 0x01, 0xFD, 0xC0, 0xFF, 0x08, 0xFC, 0x00, 0xE5,
+    0x0B, 0xF8, 0xC2, 0xCE, 0xF4, 0xF9, 0x0F, 0x7F, 0x45, 0x6D, 0x3D, 0xFE, 0x46, 0x97, 0x33, 0x5E,
+    0x08, 0xEF, 0xF1, 0xFF, 0x86, 0x83, 0x24, 0x74, 0x12, 0xFC, 0x00, 0x9F, 0xB4, 0xB7, 0x06, 0xD5,
+    0xD0, 0x7A, 0x00, 0x9E, 0x04, 0x5F, 0x41, 0x2F, 0x1D, 0x77, 0x36, 0x75, 0x81, 0xAA, 0x70, 0x3A,
+    0x98, 0xD1, 0x71, 0x02, 0x4D, 0x01, 0xC1, 0xFF, 0x0D, 0x00, 0xD3, 0x05, 0xF9, 0x00, 0x0B, 0x00,
+];
+
+// IO register post-boot defaults (maps to 0xFF00..0xFFFF) for CGB.
+static IO_RESET_CGB: [u8; 0x100] = [
     0xCF, 0x00, 0x7C, 0xFF, 0x00, 0x00, 0x00, 0xF8, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0x01,
     0x80, 0xBF, 0xF3, 0xFF, 0xBF, 0xFF, 0x3F, 0x00, 0xFF, 0xBF, 0x7F, 0xFF, 0x9F, 0xFF, 0xBF, 0xFF,
     0xFF, 0x00, 0x00, 0xBF, 0x77, 0xF3, 0xF1, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF,
@@ -253,3 +1122,716 @@ static IO_RESET: [u8; 0x100] = [
     0xD0, 0x7A, 0x00, 0x9E, 0x04, 0x5F, 0x41, 0x2F, 0x1D, 0x77, 0x36, 0x75, 0x81, 0xAA, 0x70, 0x3A,
     0x98, 0xD1, 0x71, 0x02, 0x4D, 0x01, 0xC1, 0xFF, 0x0D, 0x00, 0xD3, 0x05, 0xF9, 0x00, 0x0B, 0x00,
 ];
+
+impl Memory {
+    // Work RAM through IE; ROM comes from the cartridge and VRAM/OAM from the PPU
+    const STATE_MEMORY: Range<usize> = 0xC000..0x10000;
+
+    pub(crate) fn save_state(&self, w: &mut StateWriter) {
+        w.bytes(&self.main_memory[Self::STATE_MEMORY]);
+        w.u32(self.external_ram.len() as u32);
+        w.bytes(&self.external_ram);
+        if let Some(mbc) = &self.mbc {
+            mbc.save_state(w);
+        }
+        self.timer.save_state(w);
+        self.serial.save_state(w);
+        self.ppu.save_state(w);
+        w.u8(self.joypad.register);
+        w.bool(self.joypad.interrupt_requested);
+        w.bool(self.dma_active);
+        w.u16(self.dma_cycles_remaining);
+        w.u8(self.dma_source.0);
+        w.u64(self.clock.cycles());
+        w.u32(self.deferred_m_cycles);
+        w.bool(self.cgb);
+        w.u8(self.key0);
+    }
+
+    pub(crate) fn load_state(&mut self, r: &mut StateReader) -> Result<(), String> {
+        r.fill(&mut self.main_memory[Self::STATE_MEMORY])?;
+        let ram_len = r.u32()? as usize;
+        if ram_len != self.external_ram.len() {
+            return Err(format!(
+                "save state has {} bytes of cartridge RAM, the cartridge has {}",
+                ram_len,
+                self.external_ram.len()
+            ));
+        }
+        r.fill(&mut self.external_ram)?;
+        if let Some(mbc) = &mut self.mbc {
+            mbc.load_state(r)?;
+        }
+        self.timer.load_state(r)?;
+        self.serial.load_state(r)?;
+        self.ppu.load_state(r)?;
+        self.joypad.register = r.u8()?;
+        self.joypad.interrupt_requested = r.bool()?;
+        self.dma_active = r.bool()?;
+        self.dma_cycles_remaining = r.u16()?;
+        self.dma_source = DmaSource(r.u8()?);
+        self.clock = EmulatedClock::from_cycles(r.u64()?);
+        self.deferred_m_cycles = r.u32()?;
+        self.cgb = r.bool()?;
+        self.key0 = r.u8()?;
+        self.external_ram_dirty = true;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::accuracy::AccuracyProfile;
+    use crate::testrom::assemble;
+
+    #[test]
+    fn test_rom_area_is_read_only() {
+        let mut rom = vec![0; 0x8000];
+        rom[0x0100] = 0x42;
+        rom[0x4000] = 0x24;
+        let mut mem = Memory::new(rom);
+
+        // Writes below 0x8000 only reach the banking registers
+        mem.write_8(0x0100, 0x99);
+        mem.write_8(0x4000, 0x99);
+        assert_eq!(mem.read_8(0x0100), 0x42);
+        assert_eq!(mem.read_8(0x4000), 0x24);
+        assert_eq!(mem.main_memory[0x0100], 0x00);
+    }
+
+    #[test]
+    fn test_dma_from_high_pages_reads_work_ram() {
+        let mut mem = Memory::new(vec![0; 0x8000]);
+        for address in 0xC000..0xE000u16 {
+            mem.write_8(address, (address >> 8) as u8 ^ address as u8);
+        }
+
+        // Echo pages, and FE/FF which the CPU sees as OAM and IO, all copy from WRAM
+        for (page, base) in [
+            (0xC1, 0xC100),
+            (0xE1, 0xC100),
+            (0xFE, 0xDE00),
+            (0xFF, 0xDF00),
+        ] {
+            mem.write_8(0xFF46, page);
+            mem.tick_components(DMA_LENGTH as u32 + 1);
+            assert_eq!(mem.dma_source.base(), base);
+            let expected: Vec<u8> = (0..160).map(|i| mem.read_8(base + i)).collect();
+            assert_eq!(&mem.ppu.oam[..], &expected[..], "page {:02X}", page);
+        }
+        assert_eq!(DmaSource(0x80).address(159), 0x809F);
+    }
+
+    #[test]
+    fn test_dma_copies_a_byte_per_cycle_and_blocks_its_bus() {
+        let mut mem = Memory::new(vec![0; 0x8000]);
+        for i in 0..160u16 {
+            mem.write_8(0xC100 + i, i as u8 + 1);
+        }
+        mem.write_8(0xFF80, 0x11);
+        mem.write_8(0xFF46, 0xC1);
+
+        // One startup cycle before the transfer takes the bus
+        assert_eq!(mem.cpu_read_8(0xFE00), 0x00);
+        mem.tick_components(1);
+        assert!(mem.dma_transferring());
+        assert_eq!(mem.cpu_read_8(0xFE00), 0xFF);
+        assert_eq!(mem.cpu_read_8(0xC100), 0xFF);
+        assert_eq!(mem.cpu_read_8(0x0100), 0xFF);
+        assert_eq!(mem.cpu_read_8(0x8000), 0x00); // VRAM bus is free
+        assert_eq!(mem.cpu_read_8(0xFF80), 0x11);
+        mem.write_8(0xC000, 0x99);
+        assert_eq!(mem.read_8(0xC000), 0x00);
+
+        mem.tick_components(1);
+        assert_eq!(mem.ppu.oam[..2], [1, 0]);
+        mem.tick_components(DMA_LENGTH as u32 - 1);
+        assert!(!mem.dma_transferring());
+        assert_eq!(mem.cpu_read_8(0xFE9F), 160);
+        assert_eq!(mem.cpu_read_8(0xC100), 1);
+
+        // From VRAM only the VRAM bus is taken
+        mem.write_8(0xFF46, 0x80);
+        mem.tick_components(1);
+        assert_eq!(mem.cpu_read_8(0x8000), 0xFF);
+        assert_eq!(mem.cpu_read_8(0xC100), 1);
+    }
+
+    #[test]
+    fn test_deferred_ticks_without_sub_instruction_timing() {
+        let mut mem = Memory::new(vec![0; 0x8000]);
+        mem.accuracy = AccuracyProfile::Fast.settings();
+
+        mem.tick_components(64);
+        assert_eq!(mem.read_8(0xFF04), 0x00); // Not applied yet
+        mem.flush_deferred_ticks();
+        assert_eq!(mem.read_8(0xFF04), 0x01); // 256 T-cycles
+    }
+
+    struct HeldRight;
+
+    impl crate::joypad::InputProvider for HeldRight {
+        fn poll(&self) -> crate::joypad::ButtonMask {
+            crate::joypad::ButtonMask::NONE.with(crate::joypad::JoypadButton::Right)
+        }
+    }
+
+    #[test]
+    fn test_cpu_read_of_p1_polls_input() {
+        let mut mem = Memory::new(vec![0; 0x8000]);
+        mem.write_8(0xFF00, 0x20); // Directions group
+        mem.joypad
+            .set_input_provider(Some(std::sync::Arc::new(HeldRight)));
+
+        // Debug reads leave the joypad untouched
+        assert_eq!(mem.read_8(0xFF00) & 0x0F, 0x0F);
+        assert_eq!(mem.cpu_read_8(0xFF00) & 0x0F, 0x0E);
+    }
+
+    fn boot(rom: Vec<u8>) -> (crate::cpu::Cpu, Memory) {
+        let mut mem = Memory::new(rom);
+        mem.skip_boot(Model::Dmg);
+        let mut cpu = crate::cpu::Cpu::new();
+        cpu.skip_boot(Model::Dmg, 0);
+        (cpu, mem)
+    }
+
+    // Timer and serial interrupts enabled, then a loop sampling LY
+    fn trace_machine() -> (crate::cpu::Cpu, Memory) {
+        boot(assemble(
+            "
+            org $0050
+                reti
+            org $0058
+                reti
+            org $0100
+                ld a, $05
+                ldh ($07), a    ; Timer on, 262144 Hz
+                ld a, $81
+                ldh ($02), a    ; Serial transfer, internal clock
+                ld a, $0C
+                ldh ($FF), a
+                ei
+            loop:
+                inc bc
+                ldh a, ($44)
+                jr loop
+            ",
+        ))
+    }
+
+    #[test]
+    fn test_rom_bank_switching() {
+        let (mut cpu, mut mem) = boot(assemble(
+            "
+            org $0147
+                db $01          ; MBC1
+            org $0100
+                ld a, 2
+                ld ($2000), a
+                ld a, ($4000)
+                ld ($C000), a
+                ld a, 3
+                ld ($2000), a
+                ld a, ($4000)
+                ld ($C001), a
+                xor a
+                ld ($2000), a   ; Bank 0 maps to bank 1
+                ld a, ($4000)
+                ld ($C002), a
+            done:
+                jr done
+            bank 1
+            org $4000
+                db $11
+            bank 2
+            org $4000
+                db $22
+            bank 3
+            org $4000
+                db $33
+            ",
+        ));
+        for _ in 0..16 {
+            cpu.step(&mut mem);
+        }
+        assert_eq!(
+            [mem.read_8(0xC000), mem.read_8(0xC001), mem.read_8(0xC002)],
+            [0x22, 0x33, 0x11]
+        );
+    }
+
+    #[test]
+    fn test_mbc1_upper_bits_and_ram_banks() {
+        // MBC1+RAM+BATTERY, 1 MiB ROM with each bank's number at its start, 32 KiB RAM
+        let mut rom = vec![0; 0x100000];
+        for bank in 0..64 {
+            rom[bank * 0x4000] = bank as u8;
+        }
+        rom[0x0147] = 0x03;
+        rom[0x0149] = 0x03;
+        let mut mem = Memory::new(rom);
+        assert_eq!(mem.external_ram_banks(), 4);
+
+        mem.write_8(0x2000, 0x02);
+        mem.write_8(0x4000, 0x01);
+        assert_eq!((mem.read_8(0x0000), mem.read_8(0x4000)), (0x00, 0x22));
+        mem.write_8(0x6000, 0x01);
+        assert_eq!((mem.read_8(0x0000), mem.read_8(0x4000)), (0x20, 0x22));
+
+        // RAM reads open bus and ignores writes until enabled
+        mem.write_8(0xA000, 0x11);
+        assert_eq!(mem.read_8(0xA000), 0xFF);
+        assert!(!mem.take_external_ram_dirty());
+        mem.write_8(0x0000, 0x0A);
+        mem.write_8(0xA000, 0x11);
+        mem.write_8(0x4000, 0x02);
+        mem.write_8(0xA000, 0x22);
+        assert_eq!(mem.read_8(0xA000), 0x22);
+        mem.write_8(0x6000, 0x00); // Mode 0 maps RAM bank 0
+        assert_eq!(mem.read_8(0xA000), 0x00);
+        assert_eq!(mem.external_ram_bank(1).unwrap()[0], 0x11);
+        assert_eq!(mem.external_ram()[2 * EXTERNAL_RAM_SIZE], 0x22);
+
+        // A reset puts the mapper back to power-on but keeps the RAM
+        mem.reset(Model::Dmg);
+        assert_eq!(mem.rom_bank(), 1);
+        assert!(!mem.external_ram_enabled());
+        assert_eq!(mem.external_ram_bank(2).unwrap()[0], 0x22);
+    }
+
+    #[test]
+    fn test_mbc5_maps_large_roms_and_sixteen_ram_banks() {
+        // 8 MiB ROM with each bank's number at its start, 128 KiB RAM
+        let mut rom = vec![0; 512 * ROM_BANK_SIZE];
+        for (bank, chunk) in rom.chunks_mut(ROM_BANK_SIZE).enumerate() {
+            chunk[..2].copy_from_slice(&(bank as u16).to_le_bytes());
+        }
+        rom[0x0147] = 0x1B; // MBC5+RAM+BATTERY
+        rom[0x0148] = 0x08;
+        rom[0x0149] = 0x04;
+        let mut mem = Memory::new(rom);
+        assert_eq!(mem.external_ram_banks(), 16);
+        assert_eq!(mem.mappable_rom_banks(), 512);
+
+        mem.write_8(0x2000, 0xC3);
+        mem.write_8(0x3000, 0x01);
+        assert_eq!(mem.read_16(0x4000), 0x1C3);
+        assert_eq!(mem.rom_bank(), 0x1C3);
+        mem.write_8(0x3000, 0x00);
+        mem.write_8(0x2000, 0x00);
+        assert_eq!(mem.read_16(0x4000), 0);
+
+        mem.write_8(0x0000, 0x0A);
+        mem.write_8(0x4000, 0x0F);
+        mem.write_8(0xA000, 0x5A);
+        assert_eq!(mem.external_ram_bank(15).unwrap()[0], 0x5A);
+    }
+
+    #[test]
+    fn test_mbc3_clock_counts_emulated_time() {
+        let mut rom = vec![0; 0x8000];
+        rom[0x0147] = 0x10; // MBC3+TIMER+RAM+BATTERY
+        rom[0x0149] = 0x03;
+        let mut mem = Memory::new(rom);
+        mem.write_8(0x0000, 0x0A);
+        mem.write_8(0x4000, 0x08);
+        mem.write_8(0xA000, 58);
+        assert!(mem.take_external_ram_dirty());
+
+        // Three emulated seconds, in M-cycles
+        for _ in 0..3 {
+            mem.tick_components((crate::clock::CPU_FREQUENCY / 4) as u32);
+            mem.flush_deferred_ticks();
+        }
+        // Reads see the latched registers
+        assert_eq!(mem.read_8(0xA000), 0x00);
+        mem.write_8(0x6000, 0x00);
+        mem.write_8(0x6000, 0x01);
+        assert_eq!(mem.read_8(0xA000), 1);
+        mem.write_8(0x4000, 0x09);
+        assert_eq!(mem.read_8(0xA000), 1);
+
+        // RAM banks share the window with the clock
+        mem.write_8(0x4000, 0x02);
+        mem.write_8(0xA000, 0x22);
+        assert_eq!(mem.external_ram_bank(2).unwrap()[0], 0x22);
+        mem.write_8(0x0000, 0x00);
+        assert_eq!(mem.read_8(0xA000), 0xFF);
+    }
+
+    fn trace(cpu: &mut crate::cpu::Cpu, mem: &mut Memory, steps: usize) -> Vec<[u64; 6]> {
+        use crate::cpu::Reg16::{AF, BC, PC};
+        (0..steps)
+            .map(|_| {
+                cpu.step(mem);
+                [
+                    cpu.registers.read_r16(PC) as u64,
+                    cpu.registers.read_r16(AF) as u64,
+                    cpu.registers.read_r16(BC) as u64,
+                    mem.clock.cycles(),
+                    mem.read_8(0xFF04) as u64,
+                    mem.read_8(0xFF05) as u64 | (mem.read_8(0xFF02) as u64) << 8,
+                ]
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_reset_replays_like_fresh_boot() {
+        let (mut cpu, mut mem) = trace_machine();
+        let expected = trace(&mut cpu, &mut mem, 3000);
+
+        // Stop mid-scanline, mid-transfer and with DIV at an arbitrary phase
+        let (mut cpu, mut mem) = trace_machine();
+        trace(&mut cpu, &mut mem, 1234);
+        cpu.reset(Model::Dmg, 0);
+        mem.reset(Model::Dmg);
+        assert_eq!(trace(&mut cpu, &mut mem, 3000), expected);
+    }
+
+    #[test]
+    fn test_boot_rom_mapped_until_ff50_write() {
+        use crate::cpu::Reg16::PC;
+        let mut rom = vec![0; 0x8000];
+        rom[0x0000] = 0xAA;
+        // Boot ROM: NOPs, then LD A,1 ; LDH (0x50),A, falling through to 0x0100
+        let mut boot_rom = vec![0; BOOT_ROM_SIZE];
+        boot_rom[0xFC..].copy_from_slice(&[0x3E, 0x01, 0xE0, 0x50]);
+
+        let mut mem = Memory::new(rom);
+        assert!(mem.start_boot_rom(vec![0; 0x900]).is_err());
+        mem.start_boot_rom(boot_rom).unwrap();
+        assert!(mem.boot_rom_mapped());
+        assert_eq!(mem.read_8(0x0000), 0x00);
+        assert!(!mem.ppu.lcdc().lcd_enabled());
+
+        let mut cpu = crate::cpu::Cpu::new();
+        while cpu.registers.read_r16(PC) != 0x0100 {
+            cpu.step(&mut mem);
+            assert!(cpu.registers.read_r16(PC) <= 0x0100);
+        }
+        assert!(!mem.boot_rom_mapped());
+        assert_eq!(mem.read_8(0x0000), 0xAA);
+        // Writing FF50 again cannot map it back
+        mem.write_8(0xFF50, 0x00);
+        assert_eq!(mem.read_8(0x0000), 0xAA);
+
+        mem.reset(Model::Dmg);
+        assert!(mem.boot_rom_mapped());
+        assert_eq!(mem.read_8(0x0000), 0x00);
+    }
+
+    #[test]
+    fn test_reset_keeps_cartridge_ram_and_settings() {
+        let (_, mut mem) = trace_machine();
+        mem.accuracy = AccuracyProfile::Fast.settings();
+        mem.joypad.enable_sgb();
+        mem.write_8(0xA000, 0x12);
+        mem.write_8(0xC000, 0x34);
+
+        mem.reset(Model::Dmg);
+        assert_eq!(mem.read_8(0xA000), 0x12);
+        assert_eq!(mem.read_8(0xC000), 0x00);
+        assert_eq!(mem.read_8(0x0100), 0x3E);
+        assert_eq!(mem.accuracy, AccuracyProfile::Fast.settings());
+        assert!(mem.joypad.sgb_enabled());
+    }
+
+    #[test]
+    fn test_reset_keeps_cartridge_clock() {
+        let mut rom = vec![0; 0x8000];
+        rom[0x0147] = 0x10; // MBC3+TIMER+RAM+BATTERY
+        rom[0x0149] = 0x03;
+        let mut mem = Memory::new(rom);
+        let time = "3:04:05:06".parse().unwrap();
+        mem.rtc_mut().unwrap().set(time);
+
+        mem.reset(Model::Dmg);
+        assert_eq!(mem.rtc().unwrap().time(), time);
+    }
+
+    // Program using the serial interrupt as a timer with no link partner:
+    // IE = serial ; SC = 0x81 (start, internal clock) ; EI ; loop: INC BC ; JR loop
+    // with JR $ as the serial interrupt handler
+    fn run_serial_timer_program(profile: AccuracyProfile) -> Option<u64> {
+        let mut rom = vec![0; 0x8000];
+        rom[0x0058..0x005A].copy_from_slice(&[0x18, 0xFE]);
+        rom[0x0100..0x010C].copy_from_slice(&[
+            0x3E, 0x08, 0xE0, 0xFF, 0x3E, 0x81, 0xE0, 0x02, 0xFB, 0x03, 0x18, 0xFD,
+        ]);
+        let mut mem = Memory::new(rom);
+        mem.skip_boot(Model::Dmg);
+        mem.accuracy = profile.settings();
+        let mut cpu = crate::cpu::Cpu::new();
+        cpu.skip_boot(Model::Dmg, 0);
+
+        let pc = |cpu: &crate::cpu::Cpu| cpu.registers.read_r16(crate::cpu::Reg16::PC);
+        while pc(&cpu) != 0x0106 {
+            cpu.step(&mut mem);
+        }
+        // LDH (02),A writes SC on its third M-cycle
+        let start = mem.clock.cycles() + 8;
+        while mem.clock.cycles() < start + 10_000 {
+            cpu.step(&mut mem);
+            if pc(&cpu) == 0x0058 {
+                return Some(mem.clock.cycles() - start);
+            }
+        }
+        None
+    }
+
+    #[test]
+    fn test_serial_interrupt_fires_after_transfer_length() {
+        let elapsed = run_serial_timer_program(AccuracyProfile::Balanced).unwrap();
+        // 8 bits * 512 cycles, plus at most one loop iteration and the interrupt dispatch
+        assert!(
+            (4096..4096 + 20 + 20).contains(&elapsed),
+            "{} cycles",
+            elapsed
+        );
+        assert_eq!(run_serial_timer_program(AccuracyProfile::Fast), None);
+    }
+
+    #[test]
+    fn test_skip_boot_div_phase_per_model() {
+        let mut mem = Memory::new(vec![0; 0x8000]);
+        mem.skip_boot(Model::Dmg);
+        assert_eq!(mem.read_8(0xFF04), 0xAB);
+
+        mem.skip_boot(Model::Dmg0);
+        assert_eq!(mem.read_8(0xFF04), 0x18);
+    }
+
+    #[test]
+    fn test_skip_boot_cgb_registers_per_model() {
+        let mut mem = Memory::new(vec![0; 0x8000]);
+        mem.skip_boot(Model::Dmg);
+        assert_eq!(mem.read_8(0xFF4D), 0xFF);
+        assert_eq!(mem.read_8(0xFF70), 0xFF);
+
+        mem.skip_boot(Model::Cgb);
+        assert_eq!(mem.read_8(0xFF4D), 0x7E);
+        assert_eq!(mem.read_8(0xFF70), 0xF8);
+    }
+
+    // Expected read of an IO address on `model`, for addresses no register backs there
+    // (None where a register does): unused addresses, write-only HDMA sources and
+    // destinations, and on DMG models the CGB registers
+    fn unmapped_io_read(model: Model, address: u16) -> Option<u8> {
+        let unused = matches!(
+            address,
+            0xFF03
+                | 0xFF08..=0xFF0E
+                | 0xFF15
+                | 0xFF1F
+                | 0xFF27..=0xFF2F
+                | 0xFF4E
+                | 0xFF50..=0xFF54
+                | 0xFF57..=0xFF67
+                | 0xFF6D..=0xFF6F
+                | 0xFF71
+                | 0xFF78..=0xFF7F
+        );
+        let cgb_only = matches!(
+            address,
+            0xFF4C..=0xFF4D
+                | 0xFF4F
+                | 0xFF55..=0xFF56
+                | 0xFF68..=0xFF6C
+                | 0xFF70
+                | 0xFF72..=0xFF77
+        );
+        (unused || (cgb_only && !model.is_cgb())).then_some(0xFF)
+    }
+
+    #[test]
+    fn test_unmapped_io_reads_ff_per_model() {
+        for model in [Model::Dmg0, Model::Dmg, Model::Mgb, Model::Cgb] {
+            let mut mem = Memory::new(vec![0; 0x8000]);
+            mem.skip_boot(model);
+            for address in 0xFF00..=0xFF7F {
+                let Some(expected) = unmapped_io_read(model, address) else {
+                    continue;
+                };
+                mem.write_8(address, 0x00);
+                assert_eq!(
+                    mem.read_8(address),
+                    expected,
+                    "{:04X} on {:?}",
+                    address,
+                    model
+                );
+            }
+            assert_eq!(mem.read_8(0xFF0F) & 0xE0, 0xE0);
+        }
+
+        // CGB registers keep what is written on CGB
+        let mut mem = Memory::new(vec![0; 0x8000]);
+        mem.skip_boot(Model::Cgb);
+        mem.write_8(0xFF4D, 0x01);
+        assert_eq!(mem.read_8(0xFF4D), 0x01);
+    }
+
+    #[test]
+    fn test_cgb_compatibility_registers() {
+        let mut rom = vec![0; 0x8000];
+        let mut mem = Memory::new(rom.clone());
+        mem.skip_boot(Model::Dmg);
+        mem.write_8(0xFF6C, 0x00);
+        assert_eq!(mem.read_8(0xFF6C), 0xFF);
+        assert!(!mem.dmg_compatibility_mode());
+        assert_eq!(mem.ppu.object_priority(), ObjectPriority::XCoordinate);
+
+        // DMG cartridge on CGB: compatibility mode, DMG sprite priority
+        mem.reset(Model::Cgb);
+        assert!(mem.dmg_compatibility_mode());
+        assert_eq!(mem.read_8(0xFF6C), 0xFF);
+        assert_eq!(mem.ppu.object_priority(), ObjectPriority::XCoordinate);
+
+        // CGB cartridge: OAM-order priority, and the registers are locked after boot
+        rom[0x0143] = 0x80;
+        let mut mem = Memory::new(rom);
+        mem.skip_boot(Model::Cgb);
+        assert!(!mem.dmg_compatibility_mode());
+        assert_eq!(mem.read_8(0xFF6C), 0xFE);
+        assert_eq!(mem.ppu.object_priority(), ObjectPriority::OamIndex);
+        mem.write_8(0xFF6C, 0x01);
+        mem.write_8(0xFF4C, 0x04);
+        assert_eq!(mem.ppu.object_priority(), ObjectPriority::OamIndex);
+        assert!(!mem.dmg_compatibility_mode());
+
+        // A priority forced by the user outlasts a reset
+        mem.ppu
+            .force_object_priority(Some(ObjectPriority::XCoordinate));
+        mem.reset(Model::Cgb);
+        assert_eq!(mem.ppu.object_priority(), ObjectPriority::XCoordinate);
+    }
+
+    #[test]
+    fn test_interrupt_latency_counts_ime_off_time() {
+        // VBlank enabled, but IME stays off until LY reaches 0x94
+        let (mut cpu, mut mem) = boot(assemble(
+            "
+            org $0040
+                reti
+            org $0100
+                ld a, $00
+                ldh ($0F), a    ; Drop the VBlank request left by the boot ROM
+                ld a, $01
+                ldh ($FF), a
+            wait:
+                ldh a, ($44)
+                cp $94
+                jr nz, wait
+                ei
+            done:
+                jr done
+            ",
+        ));
+        mem.set_interrupt_latency_enabled(true);
+        while mem.interrupt_latency().unwrap().totals()[0].count < 3 {
+            cpu.step(&mut mem);
+        }
+
+        let vblank = mem.interrupt_latency().unwrap().totals()[0];
+        // Four lines waiting for EI, then the usual 5 M-cycle dispatch
+        assert!((4 * 456..5 * 456).contains(&vblank.max));
+        assert_eq!(vblank.total - vblank.max as u64, 2 * 20);
+        mem.reset(Model::Dmg);
+        assert_eq!(mem.interrupt_latency().unwrap().totals()[0].count, 0);
+    }
+
+    #[test]
+    fn test_strict_mode_reports_suspicious_accesses() {
+        let (mut cpu, mut mem) = boot(assemble(
+            "
+            org $0100
+                ld a, $12
+                ld ($2000), a   ; No mapper to receive it
+                ld a, ($C000)   ; Never written
+                ld ($C001), a
+                ld a, ($E001)   ; Echo of a written byte
+                ld sp, $FFFE
+                pop bc          ; Wraps SP to 0000
+            done:
+                jr done
+            ",
+        ));
+        mem.set_strict_enabled(true);
+        for _ in 0..8 {
+            cpu.step(&mut mem);
+        }
+
+        let events = mem.take_strict_events();
+        let violations: Vec<_> = events.iter().map(|event| event.violation).collect();
+        assert_eq!(
+            violations,
+            [
+                Violation::RomWrite {
+                    address: 0x2000,
+                    value: 0x12
+                },
+                Violation::UninitializedRead { address: 0xC000 },
+                Violation::StackUnderflow { sp: 0xFFFE },
+            ]
+        );
+        assert_eq!(
+            events.iter().map(|event| event.pc).collect::<Vec<_>>(),
+            [0x0102, 0x0105, 0x0111]
+        );
+
+        while mem.ppu.stat().mode() != 3 {
+            cpu.step(&mut mem);
+        }
+        mem.take_strict_events();
+        mem.cpu_read_8(0xFE00);
+        assert_eq!(
+            mem.take_strict_events()[0].violation,
+            Violation::OamAccess {
+                address: 0xFE00,
+                mode: 3,
+                write: false
+            }
+        );
+
+        // Off by default: nothing is checked or kept
+        mem.set_strict_enabled(false);
+        mem.cpu_read_8(0xFE00);
+        assert!(mem.take_strict_events().is_empty());
+    }
+
+    #[test]
+    fn test_random_ram_init_follows_the_seed() {
+        let power_on = |seed| {
+            let mut mem = Memory::new(vec![0; 0x8000]);
+            mem.skip_boot(Model::Dmg);
+            mem.set_entropy(Box::new(SeededRng::new(seed)));
+            mem.set_ram_init(RamInit::Random);
+            mem
+        };
+        let mut a = power_on(1);
+        let mut b = power_on(1);
+        assert_eq!(a.work_ram(), b.work_ram());
+        assert_eq!(a.high_ram(), b.high_ram());
+        assert_ne!(a.work_ram(), power_on(2).work_ram());
+        assert!(a.work_ram().iter().any(|&byte| byte != 0));
+
+        // A reset draws new contents, the same ones for the same seed
+        let before = a.work_ram().to_vec();
+        a.reset(Model::Dmg);
+        b.reset(Model::Dmg);
+        assert_eq!(a.work_ram(), b.work_ram());
+        assert_ne!(a.work_ram(), &before[..]);
+
+        let mut fixed = Memory::new(vec![0; 0x8000]);
+        fixed.skip_boot(Model::Dmg);
+        let high_ram = fixed.high_ram().to_vec();
+        fixed.set_ram_init(RamInit::Fixed);
+        assert!(fixed.work_ram().iter().all(|&byte| byte == 0));
+        assert_eq!(fixed.high_ram(), &high_ram[..]);
+        assert_eq!("Random".parse(), Ok(RamInit::Random));
+        assert_eq!(RamInit::Fixed.to_string(), "fixed");
+    }
+}
@@ -0,0 +1,215 @@
+// Pause menu
+// A list of frontend actions (resume, reset, savestate slots, palette, quit) drawn over the
+// paused game with the OSD. The menu only tracks the selection and the slot; frontends
+// map their keys and buttons to MenuKey and carry out the MenuAction it returns. Labels
+// come from the message catalog, in the letters the OSD font has.
+
+use crate::i18n::Catalog;
+use crate::osd::{self, CHAR_HEIGHT, CHAR_WIDTH};
+
+/// Savestate slots selectable in the menu (0-9)
+pub const STATE_SLOTS: u8 = 10;
+
+const BACKGROUND: [u8; 3] = [0x10, 0x18, 0x28];
+const TEXT: [u8; 3] = [0xC0, 0xC8, 0xD0];
+const SELECTED: [u8; 3] = [0xFF, 0xE0, 0x60];
+const LINE_HEIGHT: usize = CHAR_HEIGHT + 5;
+
+/// Menu navigation input
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MenuKey {
+    Up,
+    Down,
+    Left,
+    Right,
+    Confirm,
+    Back,
+}
+
+/// What the frontend should do after a key
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MenuAction {
+    /// Close the menu and continue
+    Resume,
+    Reset,
+    SaveState(u8),
+    LoadState(u8),
+    NextPalette,
+    Quit,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum MenuItem {
+    Resume,
+    Reset,
+    Slot,
+    SaveState,
+    LoadState,
+    Palette,
+    Quit,
+}
+
+const ITEMS: [MenuItem; 7] = [
+    MenuItem::Resume,
+    MenuItem::Reset,
+    MenuItem::Slot,
+    MenuItem::SaveState,
+    MenuItem::LoadState,
+    MenuItem::Palette,
+    MenuItem::Quit,
+];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Menu {
+    selected: usize,
+    slot: u8,
+}
+
+impl Menu {
+    /// Open on the first item with savestate slot `slot` selected
+    pub fn new(slot: u8) -> Self {
+        Self {
+            selected: 0,
+            slot: slot % STATE_SLOTS,
+        }
+    }
+
+    /// Savestate slot currently selected
+    pub fn slot(&self) -> u8 {
+        self.slot
+    }
+
+    fn step_slot(&mut self, forward: bool) {
+        let step = if forward { 1 } else { STATE_SLOTS - 1 };
+        self.slot = (self.slot + step) % STATE_SLOTS;
+    }
+
+    pub fn input(&mut self, key: MenuKey) -> Option<MenuAction> {
+        let item = ITEMS[self.selected];
+        match key {
+            MenuKey::Up => self.selected = (self.selected + ITEMS.len() - 1) % ITEMS.len(),
+            MenuKey::Down => self.selected = (self.selected + 1) % ITEMS.len(),
+            MenuKey::Left | MenuKey::Right if item == MenuItem::Slot => {
+                self.step_slot(key == MenuKey::Right)
+            }
+            MenuKey::Left | MenuKey::Right if item == MenuItem::Palette => {
+                return Some(MenuAction::NextPalette)
+            }
+            MenuKey::Left | MenuKey::Right => {}
+            MenuKey::Back => return Some(MenuAction::Resume),
+            MenuKey::Confirm => {
+                return match item {
+                    MenuItem::Resume => Some(MenuAction::Resume),
+                    MenuItem::Reset => Some(MenuAction::Reset),
+                    MenuItem::Slot => {
+                        self.step_slot(true);
+                        None
+                    }
+                    MenuItem::SaveState => Some(MenuAction::SaveState(self.slot)),
+                    MenuItem::LoadState => Some(MenuAction::LoadState(self.slot)),
+                    MenuItem::Palette => Some(MenuAction::NextPalette),
+                    MenuItem::Quit => Some(MenuAction::Quit),
+                }
+            }
+        }
+        None
+    }
+
+    fn label(&self, item: MenuItem, catalog: &Catalog) -> String {
+        match item {
+            MenuItem::Resume => catalog.get("menu-resume").to_string(),
+            MenuItem::Reset => catalog.get("menu-reset").to_string(),
+            MenuItem::Slot => catalog.format("menu-slot", &[&self.slot]),
+            MenuItem::SaveState => catalog.get("menu-save-state").to_string(),
+            MenuItem::LoadState => catalog.get("menu-load-state").to_string(),
+            MenuItem::Palette => catalog.get("menu-palette").to_string(),
+            MenuItem::Quit => catalog.get("menu-quit").to_string(),
+        }
+    }
+
+    /// Draw the menu over `frame`, a 160x144 RGB24 picture of the paused game, in
+    /// `catalog`'s language
+    pub fn render(&self, frame: &mut [u8], catalog: &Catalog) {
+        osd::dim(frame);
+        let widest = ITEMS
+            .iter()
+            .map(|&item| osd::text_width(&self.label(item, catalog)))
+            .max()
+            .unwrap_or(0);
+        let width = 2 * CHAR_WIDTH + widest + 16;
+        let height = (ITEMS.len() + 2) * LINE_HEIGHT + 8;
+        let left = (osd::WIDTH - width) / 2;
+        let top = (osd::HEIGHT - height) / 2;
+        osd::fill_rect(frame, left, top, width, height, BACKGROUND);
+
+        let title = catalog.get("menu-title");
+        let title_left = (osd::WIDTH - osd::text_width(title)) / 2;
+        osd::draw_text(frame, title_left, top + 6, title, SELECTED);
+        for (i, &item) in ITEMS.iter().enumerate() {
+            let y = top + 6 + (i + 2) * LINE_HEIGHT;
+            let (marker, color) = if i == self.selected {
+                ("> ", SELECTED)
+            } else {
+                ("  ", TEXT)
+            };
+            let text = format!("{}{}", marker, self.label(item, catalog));
+            osd::draw_text(frame, left + 8, y, &text, color);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::i18n::Language;
+
+    #[test]
+    fn test_navigation() {
+        let mut menu = Menu::new(3);
+        assert_eq!(menu.input(MenuKey::Confirm), Some(MenuAction::Resume));
+        assert_eq!(menu.input(MenuKey::Up), None);
+        assert_eq!(menu.input(MenuKey::Confirm), Some(MenuAction::Quit));
+
+        menu.input(MenuKey::Down); // Wraps to Resume
+        menu.input(MenuKey::Down);
+        menu.input(MenuKey::Down); // State slot
+        menu.input(MenuKey::Left);
+        menu.input(MenuKey::Left);
+        menu.input(MenuKey::Left);
+        menu.input(MenuKey::Left);
+        assert_eq!(menu.slot(), 9);
+        menu.input(MenuKey::Down);
+        assert_eq!(menu.input(MenuKey::Confirm), Some(MenuAction::SaveState(9)));
+        menu.input(MenuKey::Down);
+        assert_eq!(menu.input(MenuKey::Confirm), Some(MenuAction::LoadState(9)));
+        menu.input(MenuKey::Down);
+        assert_eq!(menu.input(MenuKey::Right), Some(MenuAction::NextPalette));
+        assert_eq!(menu.input(MenuKey::Back), Some(MenuAction::Resume));
+    }
+
+    #[test]
+    fn test_render_fits_and_draws() {
+        for language in Language::ALL {
+            let mut frame = vec![0xFF; osd::WIDTH * osd::HEIGHT * 3];
+            Menu::new(0).render(&mut frame, &Catalog::new(language));
+            // Dimmed corners, panel in the middle, some text on it
+            assert_eq!(frame[0], 0x3F);
+            let center = (osd::HEIGHT / 2 * osd::WIDTH + osd::WIDTH / 2) * 3;
+            let pixel = &frame[center..center + 3];
+            assert!(pixel == BACKGROUND || pixel == TEXT);
+            assert!(frame.chunks(3).any(|pixel| pixel == SELECTED));
+        }
+    }
+
+    #[test]
+    fn test_labels_are_translated() {
+        let menu = Menu::new(4);
+        let english = Catalog::default();
+        let italian = Catalog::new(Language::Italian);
+        assert_eq!(menu.label(MenuItem::Slot, &english), "State slot: < 4 >");
+        assert_eq!(menu.label(MenuItem::Slot, &italian), "Slot stato: < 4 >");
+        for item in ITEMS {
+            assert_ne!(menu.label(item, &english), menu.label(item, &italian));
+        }
+    }
+}
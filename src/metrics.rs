@@ -0,0 +1,243 @@
+// Per-frame metrics export
+// One record per emulated frame, written as CSV or JSON Lines for offline analysis
+// of gameplay or emulator behavior (e.g. plotting a game variable over time).
+
+use std::io::{self, Write};
+
+use crate::joypad::ButtonMask;
+use crate::latency::{LatencyStats, INTERRUPT_KINDS};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MetricsFormat {
+    Csv,
+    /// One JSON object per line
+    Json,
+}
+
+impl MetricsFormat {
+    /// Pick the format from an output file name (".json"/".jsonl" => JSON, otherwise CSV)
+    pub fn from_path(path: &str) -> Self {
+        let lower = path.to_ascii_lowercase();
+        if lower.ends_with(".json") || lower.ends_with(".jsonl") {
+            MetricsFormat::Json
+        } else {
+            MetricsFormat::Csv
+        }
+    }
+}
+
+/// Metrics for one frame
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FrameRecord {
+    pub frame: u64,
+    /// CPU T-cycles executed during the frame
+    pub cycles: u32,
+    /// PC of the instruction running when VBlank was raised (None if the LCD was off)
+    pub pc_at_vblank: Option<u16>,
+    /// Cycles into the frame at which VBlank was raised
+    pub vblank_cycle: Option<u32>,
+    /// ROM bank mapped at 0x4000-0x7FFF at the end of the frame
+    pub rom_bank: u16,
+    pub buttons: ButtonMask,
+    /// Values of the watched addresses, in the order given to MetricsWriter::new
+    pub watches: Vec<u8>,
+    /// Interrupt latencies during the frame, per type in IF bit order, when measured
+    /// (see `Memory::set_interrupt_latency_enabled`). Exported as the longest of each.
+    pub interrupt_latency: Option<[LatencyStats; 5]>,
+}
+
+pub struct MetricsWriter<W: Write> {
+    out: W,
+    format: MetricsFormat,
+    watch_addresses: Vec<u16>,
+    header_written: bool,
+}
+
+impl<W: Write> MetricsWriter<W> {
+    pub fn new(out: W, format: MetricsFormat, watch_addresses: Vec<u16>) -> Self {
+        Self {
+            out,
+            format,
+            watch_addresses,
+            header_written: false,
+        }
+    }
+
+    pub fn watch_addresses(&self) -> &[u16] {
+        &self.watch_addresses
+    }
+
+    pub fn write_record(&mut self, record: &FrameRecord) -> io::Result<()> {
+        match self.format {
+            MetricsFormat::Csv => self.write_csv(record),
+            MetricsFormat::Json => self.write_json(record),
+        }
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.out.flush()
+    }
+
+    fn write_csv(&mut self, record: &FrameRecord) -> io::Result<()> {
+        if !self.header_written {
+            write!(
+                self.out,
+                "frame,cycles,pc_at_vblank,vblank_cycle,rom_bank,buttons"
+            )?;
+            for addr in &self.watch_addresses {
+                write!(self.out, ",watch_{:04X}", addr)?;
+            }
+            if record.interrupt_latency.is_some() {
+                for kind in INTERRUPT_KINDS {
+                    write!(self.out, ",latency_{}", kind)?;
+                }
+            }
+            writeln!(self.out)?;
+            self.header_written = true;
+        }
+
+        write!(self.out, "{},{},", record.frame, record.cycles)?;
+        match record.pc_at_vblank {
+            Some(pc) => write!(self.out, "{:04X},", pc)?,
+            None => write!(self.out, ",")?,
+        }
+        match record.vblank_cycle {
+            Some(cycle) => write!(self.out, "{},", cycle)?,
+            None => write!(self.out, ",")?,
+        }
+        write!(self.out, "{},{:02X}", record.rom_bank, record.buttons.0)?;
+        for value in &record.watches {
+            write!(self.out, ",{:02X}", value)?;
+        }
+        for stats in record.interrupt_latency.iter().flatten() {
+            match stats.count {
+                0 => write!(self.out, ",")?,
+                _ => write!(self.out, ",{}", stats.max)?,
+            }
+        }
+        writeln!(self.out)
+    }
+
+    fn write_json(&mut self, record: &FrameRecord) -> io::Result<()> {
+        let opt = |v: Option<String>| v.unwrap_or_else(|| "null".to_string());
+        write!(
+            self.out,
+            "{{\"frame\":{},\"cycles\":{},\"pc_at_vblank\":{},\"vblank_cycle\":{},\"rom_bank\":{},\"buttons\":{},\"watches\":{{",
+            record.frame,
+            record.cycles,
+            opt(record.pc_at_vblank.map(|pc| pc.to_string())),
+            opt(record.vblank_cycle.map(|c| c.to_string())),
+            record.rom_bank,
+            record.buttons.0
+        )?;
+        for (i, (addr, value)) in self.watch_addresses.iter().zip(&record.watches).enumerate() {
+            if i > 0 {
+                write!(self.out, ",")?;
+            }
+            write!(self.out, "\"{:04X}\":{}", addr, value)?;
+        }
+        write!(self.out, "}}")?;
+        if let Some(latency) = &record.interrupt_latency {
+            write!(self.out, ",\"interrupt_latency\":{{")?;
+            for (i, (kind, stats)) in INTERRUPT_KINDS.iter().zip(latency).enumerate() {
+                let max = match stats.count {
+                    0 => "null".to_string(),
+                    _ => stats.max.to_string(),
+                };
+                write!(
+                    self.out,
+                    "{}\"{}\":{}",
+                    if i > 0 { "," } else { "" },
+                    kind,
+                    max
+                )?;
+            }
+            write!(self.out, "}}")?;
+        }
+        writeln!(self.out, "}}")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record() -> FrameRecord {
+        FrameRecord {
+            frame: 7,
+            cycles: 70224,
+            pc_at_vblank: Some(0x0150),
+            vblank_cycle: Some(65664),
+            rom_bank: 1,
+            buttons: ButtonMask(0x11),
+            watches: vec![0x2A, 0xFF],
+            interrupt_latency: None,
+        }
+    }
+
+    #[test]
+    fn test_csv_output() {
+        let mut writer = MetricsWriter::new(Vec::new(), MetricsFormat::Csv, vec![0xC0A0, 0xFF80]);
+        writer.write_record(&record()).unwrap();
+        let mut lcd_off = record();
+        lcd_off.pc_at_vblank = None;
+        lcd_off.vblank_cycle = None;
+        writer.write_record(&lcd_off).unwrap();
+
+        let text = String::from_utf8(writer.out).unwrap();
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(
+            lines[0],
+            "frame,cycles,pc_at_vblank,vblank_cycle,rom_bank,buttons,watch_C0A0,watch_FF80"
+        );
+        assert_eq!(lines[1], "7,70224,0150,65664,1,11,2A,FF");
+        assert_eq!(lines[2], "7,70224,,,1,11,2A,FF");
+    }
+
+    #[test]
+    fn test_json_output() {
+        let mut writer = MetricsWriter::new(Vec::new(), MetricsFormat::Json, vec![0xC0A0, 0xFF80]);
+        writer.write_record(&record()).unwrap();
+        let text = String::from_utf8(writer.out).unwrap();
+        assert_eq!(
+            text,
+            "{\"frame\":7,\"cycles\":70224,\"pc_at_vblank\":336,\"vblank_cycle\":65664,\"rom_bank\":1,\"buttons\":17,\"watches\":{\"C0A0\":42,\"FF80\":255}}\n"
+        );
+    }
+
+    #[test]
+    fn test_interrupt_latency_columns() {
+        let mut latency = [LatencyStats::default(); 5];
+        latency[0].record(20);
+        latency[0].record(1888);
+        latency[2].record(44);
+        let record = FrameRecord {
+            watches: vec![],
+            interrupt_latency: Some(latency),
+            ..record()
+        };
+
+        let mut writer = MetricsWriter::new(Vec::new(), MetricsFormat::Csv, vec![]);
+        writer.write_record(&record).unwrap();
+        let text = String::from_utf8(writer.out).unwrap();
+        assert_eq!(
+            text,
+            "frame,cycles,pc_at_vblank,vblank_cycle,rom_bank,buttons,latency_vblank,latency_stat,latency_timer,latency_serial,latency_joypad\n\
+             7,70224,0150,65664,1,11,1888,,44,,\n"
+        );
+
+        let mut writer = MetricsWriter::new(Vec::new(), MetricsFormat::Json, vec![]);
+        writer.write_record(&record).unwrap();
+        let text = String::from_utf8(writer.out).unwrap();
+        assert!(text.ends_with(
+            "\"watches\":{},\"interrupt_latency\":{\"vblank\":1888,\"stat\":null,\"timer\":44,\"serial\":null,\"joypad\":null}}\n"
+        ));
+    }
+
+    #[test]
+    fn test_format_from_path() {
+        assert_eq!(MetricsFormat::from_path("out.JSON"), MetricsFormat::Json);
+        assert_eq!(MetricsFormat::from_path("out.jsonl"), MetricsFormat::Json);
+        assert_eq!(MetricsFormat::from_path("out.csv"), MetricsFormat::Csv);
+    }
+}
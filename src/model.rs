@@ -0,0 +1,34 @@
+// Game Boy hardware models
+// The models differ in the state their boot ROM leaves behind (CPU registers,
+// DIV phase and a handful of IO registers), which is what `skip_boot` reproduces.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum Model {
+    /// Early DMG revision with the original boot ROM
+    Dmg0,
+    /// Original Game Boy (DMG-01)
+    #[default]
+    Dmg,
+    /// Game Boy Pocket
+    Mgb,
+    /// Game Boy Color
+    Cgb,
+}
+
+impl Model {
+    pub fn is_cgb(&self) -> bool {
+        matches!(self, Model::Cgb)
+    }
+
+    /// Value of the 16-bit timer divider when the boot ROM hands over at 0x0100.
+    /// DIV (the upper byte) matches Pan Docs; the lower byte is the phase that
+    /// Mooneye's boot_div tests expect on DMG/MGB. The CGB boot ROM takes a
+    /// header-dependent amount of time, so its value is only representative.
+    pub fn post_boot_div(&self) -> u16 {
+        match self {
+            Model::Dmg0 => 0x1830,
+            Model::Dmg | Model::Mgb => 0xABCC,
+            Model::Cgb => 0x1EA0,
+        }
+    }
+}
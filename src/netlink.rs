@@ -0,0 +1,297 @@
+// Network link cable
+// NetLink carries the link cable over TCP, so two emulators on different machines can
+// trade and battle. Rather than a round trip per byte, each side tells the other when it
+// starts or stops waiting on the external clock and keeps a copy of what the partner last
+// said. The side on the internal clock swaps bytes with that copy, as LinkPort does with
+// its shared wire, and sends its own byte across for the partner to pick up on its next
+// poll. A transfer clocked before the partner's "waiting" message has arrived finds
+// nobody there and reads 0xFF, as with an unplugged cable; link protocols retry those.
+//
+// The connection opens with a hello each way (magic and protocol version) that has to
+// arrive within the timeout. After it every message is two bytes, a kind and a value.
+// Writes time out too, and a connection that drops or stalls leaves the cable unplugged.
+
+use std::io::{Read, Write};
+use std::net::{Shutdown, TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use crate::link::SerialLink;
+
+/// Port used when an address doesn't give one
+pub const DEFAULT_PORT: u16 = 5370;
+/// How long the partner gets to say hello, and a write to go through
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(10);
+
+const MAGIC: &[u8; 4] = b"GBLK";
+const VERSION: u8 = 1;
+
+// Message kinds
+const WAITING: u8 = b'W'; // waiting on the external clock with this byte in SB
+const CANCEL: u8 = b'C'; // stopped waiting
+const CLOCKED: u8 = b'X'; // clocked this byte into the partner, who was waiting
+
+// What the partner last told us
+#[derive(Debug, Default)]
+struct Remote {
+    connected: bool,
+    // SB of the partner while it waits on the external clock
+    waiting: Option<u8>,
+    // Byte the partner clocked into this side, until it polls
+    delivered: Option<u8>,
+}
+
+/// Link cable to another emulator over TCP
+#[derive(Debug)]
+pub struct NetLink {
+    stream: Mutex<TcpStream>,
+    remote: Arc<Mutex<Remote>>,
+    // The byte last announced as waiting, so polling doesn't resend it every tick
+    announced: Mutex<Option<u8>>,
+}
+
+impl NetLink {
+    /// Wait for a partner to connect to `addr` ("PORT" listens on every interface)
+    pub fn listen(addr: &str, timeout: Duration) -> Result<Self, String> {
+        let addr = listen_address(addr);
+        let listener = TcpListener::bind(&addr).map_err(|err| format!("{}: {}", addr, err))?;
+        let (stream, _) = listener
+            .accept()
+            .map_err(|err| format!("{}: {}", addr, err))?;
+        Self::handshake(stream, timeout)
+    }
+
+    /// Connect to a partner listening on `addr` ("HOST" uses DEFAULT_PORT)
+    pub fn connect(addr: &str, timeout: Duration) -> Result<Self, String> {
+        let addr = connect_address(addr);
+        let targets = addr
+            .to_socket_addrs()
+            .map_err(|err| format!("{}: {}", addr, err))?;
+        let mut last_error = format!("{}: no address", addr);
+        for target in targets {
+            match TcpStream::connect_timeout(&target, timeout) {
+                Ok(stream) => return Self::handshake(stream, timeout),
+                Err(err) => last_error = format!("{}: {}", addr, err),
+            }
+        }
+        Err(last_error)
+    }
+
+    // Trade hellos, then start reading the partner's messages
+    fn handshake(mut stream: TcpStream, timeout: Duration) -> Result<Self, String> {
+        let peer = stream
+            .peer_addr()
+            .map_or_else(|_| "link partner".to_string(), |addr| addr.to_string());
+        let failed = |err: std::io::Error| format!("{}: {}", peer, err);
+        // Bytes are few and latency is everything
+        stream.set_nodelay(true).map_err(failed)?;
+        stream.set_read_timeout(Some(timeout)).map_err(failed)?;
+        stream.set_write_timeout(Some(timeout)).map_err(failed)?;
+
+        let mut hello = MAGIC.to_vec();
+        hello.push(VERSION);
+        stream.write_all(&hello).map_err(failed)?;
+        let mut reply = [0; 5];
+        stream.read_exact(&mut reply).map_err(failed)?;
+        if reply[..4] != MAGIC[..] {
+            return Err(format!("{}: not a gbemu_rust link cable", peer));
+        }
+        if reply[4] != VERSION {
+            return Err(format!(
+                "{}: link protocol version {} (expected {})",
+                peer, reply[4], VERSION
+            ));
+        }
+
+        // The partner may sit in a menu for as long as it likes between messages
+        stream.set_read_timeout(None).map_err(failed)?;
+        let reader = stream.try_clone().map_err(failed)?;
+        let remote = Arc::new(Mutex::new(Remote {
+            connected: true,
+            ..Remote::default()
+        }));
+        let shared = remote.clone();
+        thread::spawn(move || read_messages(reader, shared));
+        Ok(Self {
+            stream: Mutex::new(stream),
+            remote,
+            announced: Mutex::new(None),
+        })
+    }
+
+    /// False once the partner hung up or stopped answering
+    pub fn is_connected(&self) -> bool {
+        self.remote.lock().unwrap().connected
+    }
+
+    fn send(&self, kind: u8, value: u8) {
+        let mut stream = self.stream.lock().unwrap();
+        if stream.write_all(&[kind, value]).is_err() {
+            // Also ends the reader, which marks the cable unplugged
+            let _ = stream.shutdown(Shutdown::Both);
+            self.remote.lock().unwrap().connected = false;
+        }
+    }
+}
+
+impl SerialLink for NetLink {
+    fn exchange(&self, byte: u8) -> Option<u8> {
+        let reply = self.remote.lock().unwrap().waiting.take()?;
+        self.send(CLOCKED, byte);
+        Some(reply)
+    }
+
+    fn poll(&self, byte: u8) -> Option<u8> {
+        let received = self.remote.lock().unwrap().delivered.take();
+        let mut announced = self.announced.lock().unwrap();
+        if received.is_some() {
+            // The partner dropped our waiting byte when it clocked us
+            *announced = None;
+        } else if *announced != Some(byte) {
+            *announced = Some(byte);
+            self.send(WAITING, byte);
+        }
+        received
+    }
+
+    fn cancel(&self) {
+        if self.announced.lock().unwrap().take().is_some() {
+            self.send(CANCEL, 0);
+        }
+    }
+}
+
+impl Drop for NetLink {
+    fn drop(&mut self) {
+        let _ = self.stream.lock().unwrap().shutdown(Shutdown::Both);
+    }
+}
+
+// Mirror the partner's messages into `remote` until the connection ends
+fn read_messages(mut stream: TcpStream, remote: Arc<Mutex<Remote>>) {
+    let mut message = [0; 2];
+    while stream.read_exact(&mut message).is_ok() {
+        let mut remote = remote.lock().unwrap();
+        match message {
+            [WAITING, byte] => remote.waiting = Some(byte),
+            [CANCEL, _] => remote.waiting = None,
+            [CLOCKED, byte] => remote.delivered = Some(byte),
+            _ => break,
+        }
+    }
+    let mut remote = remote.lock().unwrap();
+    remote.connected = false;
+    remote.waiting = None;
+}
+
+/// "PORT" becomes every interface on that port; anything else is used as given
+pub fn listen_address(addr: &str) -> String {
+    match addr.parse::<u16>() {
+        Ok(port) => format!("0.0.0.0:{}", port),
+        Err(_) => addr.to_string(),
+    }
+}
+
+/// A host without a port gets DEFAULT_PORT
+pub fn connect_address(addr: &str) -> String {
+    let has_port = match addr.rsplit_once(':') {
+        // A bare IPv6 address has colons but no port
+        Some((host, port)) => {
+            port.parse::<u16>().is_ok() && (!host.contains(':') || host.ends_with(']'))
+        }
+        None => false,
+    };
+    if has_port {
+        addr.to_string()
+    } else if addr.contains(':') && !addr.starts_with('[') {
+        format!("[{}]:{}", addr, DEFAULT_PORT)
+    } else {
+        format!("{}:{}", addr, DEFAULT_PORT)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Instant;
+
+    // Wait for the reader thread to take in what the partner sent
+    fn eventually(mut condition: impl FnMut() -> bool) -> bool {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while Instant::now() < deadline {
+            if condition() {
+                return true;
+            }
+            thread::sleep(Duration::from_millis(1));
+        }
+        false
+    }
+
+    #[test]
+    fn test_addresses() {
+        assert_eq!(listen_address("5370"), "0.0.0.0:5370");
+        assert_eq!(listen_address("127.0.0.1:80"), "127.0.0.1:80");
+        assert_eq!(connect_address("example.com"), "example.com:5370");
+        assert_eq!(connect_address("10.0.0.2:99"), "10.0.0.2:99");
+        assert_eq!(connect_address("::1"), "[::1]:5370");
+        assert_eq!(connect_address("[::1]:99"), "[::1]:99");
+    }
+
+    #[test]
+    fn test_bytes_cross_the_network() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        let server = thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            NetLink::handshake(stream, DEFAULT_TIMEOUT).unwrap()
+        });
+        let master = NetLink::connect(&addr, DEFAULT_TIMEOUT).unwrap();
+        let slave = server.join().unwrap();
+
+        // Nobody is waiting yet
+        assert_eq!(master.exchange(0x12), None);
+
+        assert_eq!(slave.poll(0x34), None);
+        assert!(eventually(|| master
+            .remote
+            .lock()
+            .unwrap()
+            .waiting
+            .is_some()));
+        assert_eq!(master.exchange(0x56), Some(0x34));
+        assert!(eventually(|| slave.poll(0x34) == Some(0x56)));
+
+        // A partner that stopped waiting isn't clocked
+        assert_eq!(slave.poll(0x78), None);
+        assert!(eventually(
+            || master.remote.lock().unwrap().waiting == Some(0x78)
+        ));
+        slave.cancel();
+        assert!(eventually(|| {
+            let remote = master.remote.lock().unwrap();
+            remote.waiting.is_none()
+        }));
+        assert_eq!(master.exchange(0x9A), None);
+
+        // Hanging up unplugs the cable
+        drop(slave);
+        assert!(eventually(|| !master.is_connected()));
+        assert_eq!(master.exchange(0xBC), None);
+    }
+
+    #[test]
+    fn test_rejects_other_protocols() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        let server = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut hello = [0; 5];
+            stream.read_exact(&mut hello).unwrap();
+            stream.write_all(b"HTTP/").unwrap();
+        });
+        let err = NetLink::connect(&addr, DEFAULT_TIMEOUT).unwrap_err();
+        assert!(err.contains("not a gbemu_rust link cable"), "{}", err);
+        server.join().unwrap();
+    }
+}
@@ -0,0 +1,178 @@
+// On-screen display
+// Draws text and boxes straight into an RGB24 frame the size of the LCD (160x144), for
+// overlays such as the pause menu. Text uses a built-in 5x7 font covering upper-case
+// letters, digits and a little punctuation; lower case is drawn as upper case.
+
+/// Frame size the OSD draws into
+pub const WIDTH: usize = 160;
+pub const HEIGHT: usize = 144;
+/// Horizontal advance of one character (5 pixels and a space)
+pub const CHAR_WIDTH: usize = 6;
+/// Height of one character
+pub const CHAR_HEIGHT: usize = 7;
+
+// Rows of a glyph, top first; bit 4 is the leftmost pixel
+fn glyph(c: char) -> [u8; 7] {
+    match c.to_ascii_uppercase() {
+        'A' => [0x0E, 0x11, 0x11, 0x1F, 0x11, 0x11, 0x11],
+        'B' => [0x1E, 0x11, 0x11, 0x1E, 0x11, 0x11, 0x1E],
+        'C' => [0x0E, 0x11, 0x10, 0x10, 0x10, 0x11, 0x0E],
+        'D' => [0x1E, 0x11, 0x11, 0x11, 0x11, 0x11, 0x1E],
+        'E' => [0x1F, 0x10, 0x10, 0x1E, 0x10, 0x10, 0x1F],
+        'F' => [0x1F, 0x10, 0x10, 0x1E, 0x10, 0x10, 0x10],
+        'G' => [0x0E, 0x11, 0x10, 0x17, 0x11, 0x11, 0x0F],
+        'H' => [0x11, 0x11, 0x11, 0x1F, 0x11, 0x11, 0x11],
+        'I' => [0x0E, 0x04, 0x04, 0x04, 0x04, 0x04, 0x0E],
+        'J' => [0x07, 0x02, 0x02, 0x02, 0x02, 0x12, 0x0C],
+        'K' => [0x11, 0x12, 0x14, 0x18, 0x14, 0x12, 0x11],
+        'L' => [0x10, 0x10, 0x10, 0x10, 0x10, 0x10, 0x1F],
+        'M' => [0x11, 0x1B, 0x15, 0x15, 0x11, 0x11, 0x11],
+        'N' => [0x11, 0x11, 0x19, 0x15, 0x13, 0x11, 0x11],
+        'O' => [0x0E, 0x11, 0x11, 0x11, 0x11, 0x11, 0x0E],
+        'P' => [0x1E, 0x11, 0x11, 0x1E, 0x10, 0x10, 0x10],
+        'Q' => [0x0E, 0x11, 0x11, 0x11, 0x15, 0x12, 0x0D],
+        'R' => [0x1E, 0x11, 0x11, 0x1E, 0x14, 0x12, 0x11],
+        'S' => [0x0F, 0x10, 0x10, 0x0E, 0x01, 0x01, 0x1E],
+        'T' => [0x1F, 0x04, 0x04, 0x04, 0x04, 0x04, 0x04],
+        'U' => [0x11, 0x11, 0x11, 0x11, 0x11, 0x11, 0x0E],
+        'V' => [0x11, 0x11, 0x11, 0x11, 0x11, 0x0A, 0x04],
+        'W' => [0x11, 0x11, 0x11, 0x15, 0x15, 0x15, 0x0A],
+        'X' => [0x11, 0x11, 0x0A, 0x04, 0x0A, 0x11, 0x11],
+        'Y' => [0x11, 0x11, 0x0A, 0x04, 0x04, 0x04, 0x04],
+        'Z' => [0x1F, 0x01, 0x02, 0x04, 0x08, 0x10, 0x1F],
+        '0' => [0x0E, 0x11, 0x13, 0x15, 0x19, 0x11, 0x0E],
+        '1' => [0x04, 0x0C, 0x04, 0x04, 0x04, 0x04, 0x0E],
+        '2' => [0x0E, 0x11, 0x01, 0x02, 0x04, 0x08, 0x1F],
+        '3' => [0x1F, 0x02, 0x04, 0x02, 0x01, 0x11, 0x0E],
+        '4' => [0x02, 0x06, 0x0A, 0x12, 0x1F, 0x02, 0x02],
+        '5' => [0x1F, 0x10, 0x1E, 0x01, 0x01, 0x11, 0x0E],
+        '6' => [0x06, 0x08, 0x10, 0x1E, 0x11, 0x11, 0x0E],
+        '7' => [0x1F, 0x01, 0x02, 0x04, 0x08, 0x08, 0x08],
+        '8' => [0x0E, 0x11, 0x11, 0x0E, 0x11, 0x11, 0x0E],
+        '9' => [0x0E, 0x11, 0x11, 0x0F, 0x01, 0x02, 0x0C],
+        ' ' => [0; 7],
+        ':' => [0x00, 0x0C, 0x0C, 0x00, 0x0C, 0x0C, 0x00],
+        '<' => [0x02, 0x04, 0x08, 0x10, 0x08, 0x04, 0x02],
+        '>' => [0x08, 0x04, 0x02, 0x01, 0x02, 0x04, 0x08],
+        '-' => [0x00, 0x00, 0x00, 0x1F, 0x00, 0x00, 0x00],
+        '.' => [0x00, 0x00, 0x00, 0x00, 0x00, 0x0C, 0x0C],
+        '/' => [0x00, 0x01, 0x02, 0x04, 0x08, 0x10, 0x00],
+        '+' => [0x00, 0x04, 0x04, 0x1F, 0x04, 0x04, 0x00],
+        _ => [0x0E, 0x11, 0x01, 0x02, 0x04, 0x00, 0x04], // '?'
+    }
+}
+
+/// Width in pixels `text` takes, without the trailing space
+pub fn text_width(text: &str) -> usize {
+    (text.chars().count() * CHAR_WIDTH).saturating_sub(1)
+}
+
+fn set_pixel(frame: &mut [u8], x: usize, y: usize, color: [u8; 3]) {
+    if x < WIDTH && y < HEIGHT {
+        let offset = (y * WIDTH + x) * 3;
+        frame[offset..offset + 3].copy_from_slice(&color);
+    }
+}
+
+/// Draw `text` with its top-left corner at (x, y); pixels off the frame are clipped
+pub fn draw_text(frame: &mut [u8], x: usize, y: usize, text: &str, color: [u8; 3]) {
+    for (i, c) in text.chars().enumerate() {
+        let left = x + i * CHAR_WIDTH;
+        for (row, bits) in glyph(c).into_iter().enumerate() {
+            for column in 0..5 {
+                if bits & (0x10 >> column) != 0 {
+                    set_pixel(frame, left + column, y + row, color);
+                }
+            }
+        }
+    }
+}
+
+/// Fill a `width` x `height` box at (x, y)
+pub fn fill_rect(
+    frame: &mut [u8],
+    x: usize,
+    y: usize,
+    width: usize,
+    height: usize,
+    color: [u8; 3],
+) {
+    for row in y..y + height {
+        for column in x..x + width {
+            set_pixel(frame, column, row, color);
+        }
+    }
+}
+
+const BANNER_BACKGROUND: [u8; 3] = [0x10, 0x18, 0x28];
+const BANNER_TEXT: [u8; 3] = [0xFF, 0xE0, 0x60];
+const BANNER_LINE_HEIGHT: usize = CHAR_HEIGHT + 3;
+
+/// Draw `lines` centered in a strip along the bottom of the frame, for short notices
+/// over the running game
+pub fn draw_banner(frame: &mut [u8], lines: &[&str]) {
+    let height = lines.len() * BANNER_LINE_HEIGHT + 5;
+    let top = HEIGHT.saturating_sub(height);
+    fill_rect(frame, 0, top, WIDTH, height, BANNER_BACKGROUND);
+    for (i, line) in lines.iter().enumerate() {
+        let left = WIDTH.saturating_sub(text_width(line)) / 2;
+        draw_text(
+            frame,
+            left,
+            top + 4 + i * BANNER_LINE_HEIGHT,
+            line,
+            BANNER_TEXT,
+        );
+    }
+}
+
+/// Darken the whole frame to a quarter of its brightness, so overlays stand out
+pub fn dim(frame: &mut [u8]) {
+    for channel in frame.iter_mut() {
+        *channel /= 4;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lit(frame: &[u8], x: usize, y: usize) -> bool {
+        frame[(y * WIDTH + x) * 3] != 0
+    }
+
+    #[test]
+    fn test_draw_text() {
+        let mut frame = vec![0; WIDTH * HEIGHT * 3];
+        draw_text(&mut frame, 10, 20, "Hi", [255, 255, 255]);
+        // H: both uprights and the bar; I: the centre column
+        assert!(lit(&frame, 10, 20) && lit(&frame, 14, 20) && !lit(&frame, 12, 20));
+        assert!(lit(&frame, 12, 23));
+        assert!(lit(&frame, 18, 22) && !lit(&frame, 16, 22));
+        assert_eq!(text_width("Hi"), 11);
+
+        // Clipped at the right edge instead of panicking
+        draw_text(&mut frame, WIDTH - 3, HEIGHT - 3, "W", [255, 0, 0]);
+    }
+
+    #[test]
+    fn test_fill_and_dim() {
+        let mut frame = vec![200; WIDTH * HEIGHT * 3];
+        dim(&mut frame);
+        assert_eq!(frame[0], 50);
+        fill_rect(&mut frame, 150, 140, 20, 20, [1, 2, 3]);
+        assert_eq!(frame[(143 * WIDTH + 159) * 3..][..3], [1, 2, 3]);
+        assert_eq!(frame[(139 * WIDTH + 159) * 3], 50);
+    }
+
+    #[test]
+    fn test_banner() {
+        let mut frame = vec![0xFF; WIDTH * HEIGHT * 3];
+        draw_banner(&mut frame, &["TETRIS", "ROM ONLY"]);
+        // Two lines and their margins, across the full width
+        assert_eq!(frame[(HEIGHT - 26) * WIDTH * 3], 0xFF);
+        assert_eq!(frame[(HEIGHT - 25) * WIDTH * 3..][..3], BANNER_BACKGROUND);
+        assert_eq!(frame[((HEIGHT - 21) * WIDTH + 62) * 3..][..3], BANNER_TEXT);
+        assert_ne!(glyph('+'), glyph('?'));
+    }
+}
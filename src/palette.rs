@@ -0,0 +1,144 @@
+// DMG color schemes
+// The DMG LCD only has four shades; which RGB colors they are shown as is a host
+// preference. Schemes are written as four RRGGBB hex colors, lightest first
+// (e.g. `9BBC0F,8BAC0F,306230,0F380F`), or by preset name.
+
+use std::fmt;
+use std::str::FromStr;
+
+pub type Rgb = (u8, u8, u8);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ColorScheme {
+    /// RGB color of each shade, lightest first
+    pub shades: [Rgb; 4],
+}
+
+impl ColorScheme {
+    /// Green-tinted LCD of the original DMG
+    pub const DMG_GREEN: ColorScheme = ColorScheme {
+        shades: [
+            (0x9B, 0xBC, 0x0F),
+            (0x8B, 0xAC, 0x0F),
+            (0x30, 0x62, 0x30),
+            (0x0F, 0x38, 0x0F),
+        ],
+    };
+
+    pub const GRAYSCALE: ColorScheme = ColorScheme {
+        shades: [
+            (0xFF, 0xFF, 0xFF),
+            (0xAA, 0xAA, 0xAA),
+            (0x55, 0x55, 0x55),
+            (0x00, 0x00, 0x00),
+        ],
+    };
+
+    /// Game Boy Pocket LCD
+    pub const POCKET: ColorScheme = ColorScheme {
+        shades: [
+            (0xC4, 0xCF, 0xA1),
+            (0x8B, 0x95, 0x6D),
+            (0x4D, 0x53, 0x3C),
+            (0x1F, 0x1F, 0x1F),
+        ],
+    };
+
+    pub const PRESETS: [(&'static str, ColorScheme); 3] = [
+        ("green", Self::DMG_GREEN),
+        ("gray", Self::GRAYSCALE),
+        ("pocket", Self::POCKET),
+    ];
+
+    pub fn preset(name: &str) -> Option<ColorScheme> {
+        Self::PRESETS
+            .iter()
+            .find(|(preset, _)| *preset == name)
+            .map(|&(_, scheme)| scheme)
+    }
+
+    /// The preset after this scheme, wrapping around; custom schemes go to the first preset
+    pub fn next_preset(&self) -> (&'static str, ColorScheme) {
+        let next = Self::PRESETS
+            .iter()
+            .position(|(_, scheme)| scheme == self)
+            .map_or(0, |i| (i + 1) % Self::PRESETS.len());
+        Self::PRESETS[next]
+    }
+}
+
+impl Default for ColorScheme {
+    fn default() -> Self {
+        Self::DMG_GREEN
+    }
+}
+
+fn parse_rgb(value: &str) -> Result<Rgb, String> {
+    let hex = value.trim().trim_start_matches('#');
+    let rgb = (hex.len() == 6)
+        .then(|| u32::from_str_radix(hex, 16).ok())
+        .flatten()
+        .ok_or_else(|| format!("invalid color '{}' (expected RRGGBB)", value))?;
+    Ok(((rgb >> 16) as u8, (rgb >> 8) as u8, rgb as u8))
+}
+
+/// Parses a preset name or four comma-separated RRGGBB colors, lightest first
+impl FromStr for ColorScheme {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Some(scheme) = Self::preset(s.trim()) {
+            return Ok(scheme);
+        }
+        let colors = s.split(',').map(parse_rgb).collect::<Result<Vec<_>, _>>()?;
+        let shades = colors
+            .try_into()
+            .map_err(|colors: Vec<Rgb>| format!("expected 4 colors, got {}", colors.len()))?;
+        Ok(ColorScheme { shades })
+    }
+}
+
+impl fmt::Display for ColorScheme {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, (r, g, b)) in self.shades.iter().enumerate() {
+            if i > 0 {
+                write!(f, ",")?;
+            }
+            write!(f, "{:02X}{:02X}{:02X}", r, g, b)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_and_display() {
+        let scheme: ColorScheme = "#FFFFFF,aaaaaa,555555,000000".parse().unwrap();
+        assert_eq!(scheme, ColorScheme::GRAYSCALE);
+        assert_eq!(scheme.to_string(), "FFFFFF,AAAAAA,555555,000000");
+        assert_eq!("pocket".parse(), Ok(ColorScheme::POCKET));
+        assert_eq!(
+            ColorScheme::DMG_GREEN.to_string().parse(),
+            Ok(ColorScheme::DMG_GREEN)
+        );
+
+        assert!("FFFFFF,AAAAAA,555555".parse::<ColorScheme>().is_err());
+        assert!("FFFFFF,AAAAAA,555555,00000G"
+            .parse::<ColorScheme>()
+            .is_err());
+    }
+
+    #[test]
+    fn test_next_preset_cycles() {
+        assert_eq!(ColorScheme::DMG_GREEN.next_preset().0, "gray");
+        assert_eq!(ColorScheme::POCKET.next_preset().0, "green");
+
+        let custom = ColorScheme {
+            shades: [(1, 2, 3); 4],
+        };
+        assert_eq!(custom.next_preset().0, "green");
+    }
+}
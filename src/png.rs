@@ -0,0 +1,128 @@
+// PNG encoding
+// Just enough of PNG to save screenshots without an image library: 8-bit RGB, no
+// interlacing, and the zlib stream made of stored (uncompressed) deflate blocks. Files
+// are bigger than a real encoder's (~70 KB for a Game Boy frame) but any viewer or
+// image library reads them.
+
+/// First bytes of every PNG file
+pub const SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1A, b'\n'];
+
+// Largest payload of a stored deflate block
+const STORED_BLOCK: usize = 0xFFFF;
+
+/// Encode `rgb` (3 bytes per pixel, rows top to bottom) as a PNG file
+pub fn encode_rgb(width: u32, height: u32, rgb: &[u8]) -> Vec<u8> {
+    let row = width as usize * 3;
+    assert_eq!(
+        rgb.len(),
+        row * height as usize,
+        "RGB data doesn't match the size"
+    );
+
+    let mut png = SIGNATURE.to_vec();
+    let mut header = Vec::with_capacity(13);
+    header.extend_from_slice(&width.to_be_bytes());
+    header.extend_from_slice(&height.to_be_bytes());
+    // 8 bits per channel, truecolor, deflate, adaptive filtering, no interlace
+    header.extend_from_slice(&[8, 2, 0, 0, 0]);
+    write_chunk(&mut png, b"IHDR", &header);
+
+    // Every row starts with its filter type, 0 (none)
+    let mut scanlines = Vec::with_capacity((row + 1) * height as usize);
+    for line in rgb.chunks(row.max(1)) {
+        scanlines.push(0);
+        scanlines.extend_from_slice(line);
+    }
+    write_chunk(&mut png, b"IDAT", &zlib_stored(&scanlines));
+    write_chunk(&mut png, b"IEND", &[]);
+    png
+}
+
+// Length, type, data and the CRC of type and data
+fn write_chunk(png: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    png.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    let start = png.len();
+    png.extend_from_slice(kind);
+    png.extend_from_slice(data);
+    let crc = crc32(&png[start..]);
+    png.extend_from_slice(&crc.to_be_bytes());
+}
+
+// A zlib stream holding `data` in stored deflate blocks
+fn zlib_stored(data: &[u8]) -> Vec<u8> {
+    // Deflate with a 32 KB window, no preset dictionary, fastest compression
+    let mut out = vec![0x78, 0x01];
+    let mut blocks = data.chunks(STORED_BLOCK).peekable();
+    if blocks.peek().is_none() {
+        out.extend_from_slice(&[1, 0, 0, 0xFF, 0xFF]);
+    }
+    while let Some(block) = blocks.next() {
+        out.push(blocks.peek().is_none() as u8);
+        let len = block.len() as u16;
+        out.extend_from_slice(&len.to_le_bytes());
+        out.extend_from_slice(&(!len).to_le_bytes());
+        out.extend_from_slice(block);
+    }
+    out.extend_from_slice(&adler32(data).to_be_bytes());
+    out
+}
+
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in data {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = (crc >> 1) ^ (0xEDB8_8320 & (crc & 1).wrapping_neg());
+        }
+    }
+    !crc
+}
+
+fn adler32(data: &[u8]) -> u32 {
+    let (mut a, mut b) = (1u32, 0u32);
+    for &byte in data {
+        a = (a + byte as u32) % 65521;
+        b = (b + a) % 65521;
+    }
+    (b << 16) | a
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_checksums() {
+        assert_eq!(crc32(b"IEND"), 0xAE42_6082);
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+        assert_eq!(adler32(b"Wikipedia"), 0x11E6_0398);
+    }
+
+    #[test]
+    fn test_encode_rgb() {
+        let png = encode_rgb(2, 1, &[255, 0, 0, 0, 0, 255]);
+        assert_eq!(png[..8], SIGNATURE);
+        assert_eq!(
+            png[8..33],
+            [
+                0, 0, 0, 13, b'I', b'H', b'D', b'R', 0, 0, 0, 2, 0, 0, 0, 1, 8, 2, 0, 0, 0, 0x7B,
+                0x40, 0xE8, 0xDD
+            ]
+        );
+        // One final stored block with the filter byte and both pixels
+        assert_eq!(
+            png[33..],
+            [
+                0, 0, 0, 18, b'I', b'D', b'A', b'T', 0x78, 0x01, 1, 7, 0, 0xF8, 0xFF, 0, 255, 0, 0,
+                0, 0, 255, 0x07, 0x00, 0x01, 0xFF, 0x55, 0x36, 0xBA, 0xC7, 0, 0, 0, 0, b'I', b'E',
+                b'N', b'D', 0xAE, 0x42, 0x60, 0x82
+            ]
+        );
+
+        // Large images are split into several blocks, the last one marked final
+        let png = encode_rgb(160, 144, &[0x55; 160 * 144 * 3]);
+        let idat = 33 + 8;
+        assert_eq!(png[idat + 2], 0);
+        assert_eq!(png.len(), idat + 2 + 2 * 5 + 144 * 481 + 4 + 4 + 12);
+    }
+}
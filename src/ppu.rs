@@ -1,35 +1,382 @@
+use std::ops::Range;
+
+use crate::gb_log;
+use crate::logging::LogTarget;
+use crate::palette::ColorScheme;
+use crate::savestate::{StateReader, StateWriter};
+
+#[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Ppu {
-    pub vram: [u8; 0x2000],
+    #[cfg_attr(feature = "serde", serde(with = "crate::savestate::serde_array"))]
+    pub(crate) vram: [u8; 0x2000],
+    #[cfg_attr(feature = "serde", serde(with = "crate::savestate::serde_array"))]
     pub oam: [u8; 0xA0],
 
-    // LCD Control registers
-    pub lcdc: u8,
-    pub stat: u8,
-    pub scy: u8,
-    pub scx: u8,
-    pub ly: u8,
-    pub lyc: u8,
-    pub bgp: u8,
-    pub obp0: u8,
-    pub obp1: u8,
-    pub wy: u8,
-    pub wx: u8,
+    // LCD Control registers, written through `write` so changes can be logged
+    lcdc: Lcdc,
+    stat: Stat,
+    scy: u8,
+    scx: u8,
+    ly: u8,
+    lyc: u8,
+    bgp: u8,
+    obp0: u8,
+    obp1: u8,
+    wy: u8,
+    wx: u8,
 
     // RGB framebuffer for SDL2
+    #[cfg_attr(feature = "serde", serde(with = "crate::savestate::serde_array"))]
     pub framebuffer: [u8; 160 * 144 * 3],
     // Per-pixel background color index (0..3) used to implement OBJ priority
+    #[cfg_attr(feature = "serde", serde(with = "crate::savestate::serde_array"))]
     pub bg_color_index: [u8; 160 * 144],
+    // Per-pixel (layer, palette, color index) of the last rendered frame, only kept
+    // when enabled with `set_pixel_info_enabled` (rendering tests, layer debug view)
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub pixel_info: Option<Vec<PixelInfo>>,
+    // Lines whose pixels changed since the frontend last took them (take_dirty_rows)
+    #[cfg_attr(feature = "serde", serde(with = "crate::savestate::serde_array"))]
+    dirty_rows: [bool; 144],
+    // RGB colors the four shades are drawn with
+    #[cfg_attr(feature = "serde", serde(skip))]
+    color_scheme: ColorScheme,
+    // Set through OPRI by the model's boot ROM
+    object_priority: ObjectPriority,
+    // Chosen by the user, in place of OPRI (see `force_object_priority`)
+    #[cfg_attr(feature = "serde", serde(skip))]
+    forced_object_priority: Option<ObjectPriority>,
 
     // Internal state
     pub mode_cycles: u32,
+    // Length of mode 3 on the current line
+    drawing_cycles: u32,
     pub vblank_interrupt: bool,
     pub stat_interrupt: bool,
-
-    // Window internal line counter (resets at start of frame)
+    // The STAT interrupt line: the OR of every enabled source. The interrupt is requested
+    // only when it rises, so a source turning on while another holds it high is blocked.
+    stat_line: bool,
+    // LY as seen by the LYC comparator: None for the first dots of a line, while the
+    // hardware clears the LYC=LY flag, and 153 for a few dots after LY reads 0 on line 153
+    ly_compare: Option<u8>,
+
+    // Window internal line counter (resets at start of frame). Only advances on lines
+    // where the window produced pixels, so it keeps its value while the window is off.
     window_line_counter: u8,
+    // Set once LY == WY has been seen this frame; the window can only appear after that
+    window_y_triggered: bool,
+    // Pixel FIFO of the line being drawn; pixels are output as mode 3 dots pass
+    fifo: PixelFifo,
+    // Run the FIFO as mode 3 goes rather than all at its end (AccuracySettings::pixel_fifo)
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub(crate) pixel_fifo: bool,
 
     // track previous LCD enabled state to avoid spam
     prev_lcd_enabled: bool,
+
+    // Register writes that changed a value, only kept when enabled with
+    // `set_register_log_enabled` (event viewer)
+    #[cfg_attr(feature = "serde", serde(skip))]
+    register_changes: Option<Vec<RegisterChange>>,
+}
+
+/// LCD control register (FF40)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Lcdc(pub u8);
+
+impl Lcdc {
+    pub fn lcd_enabled(&self) -> bool {
+        self.0 & 0x80 != 0
+    }
+
+    /// Base address of the window tile map
+    pub fn window_tilemap(&self) -> u16 {
+        if self.0 & 0x40 != 0 {
+            0x9C00
+        } else {
+            0x9800
+        }
+    }
+
+    pub fn window_enabled(&self) -> bool {
+        self.0 & 0x20 != 0
+    }
+
+    /// BG/window tiles use 0x8000 unsigned addressing (otherwise 0x8800 signed)
+    pub fn unsigned_tile_data(&self) -> bool {
+        self.0 & 0x10 != 0
+    }
+
+    /// Base address of the BG tile map
+    pub fn bg_tilemap(&self) -> u16 {
+        if self.0 & 0x08 != 0 {
+            0x9C00
+        } else {
+            0x9800
+        }
+    }
+
+    /// Sprite height in pixels (8 or 16)
+    pub fn obj_size(&self) -> usize {
+        if self.0 & 0x04 != 0 {
+            16
+        } else {
+            8
+        }
+    }
+
+    pub fn obj_enabled(&self) -> bool {
+        self.0 & 0x02 != 0
+    }
+
+    /// On DMG this also gates the window
+    pub fn bg_enabled(&self) -> bool {
+        self.0 & 0x01 != 0
+    }
+}
+
+/// LCD status register (FF41)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Stat(pub u8);
+
+impl Stat {
+    /// Current PPU mode (0 HBlank, 1 VBlank, 2 OAM scan, 3 drawing)
+    pub fn mode(&self) -> u8 {
+        self.0 & 0x03
+    }
+
+    pub fn set_mode(&mut self, mode: u8) {
+        self.0 = (self.0 & !0x03) | (mode & 0x03);
+    }
+
+    /// LYC=LY flag
+    pub fn coincidence(&self) -> bool {
+        self.0 & 0x04 != 0
+    }
+
+    pub fn set_coincidence(&mut self, on: bool) {
+        if on {
+            self.0 |= 0x04;
+        } else {
+            self.0 &= !0x04;
+        }
+    }
+
+    /// STAT interrupt source enabled for entering `mode` (bits 3-5, modes 0-2)
+    pub fn mode_interrupt_enabled(&self, mode: u8) -> bool {
+        mode < 3 && self.0 & (0x08 << mode) != 0
+    }
+
+    pub fn lyc_interrupt_enabled(&self) -> bool {
+        self.0 & 0x40 != 0
+    }
+}
+
+/// CPU write that changed a PPU register
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RegisterChange {
+    pub address: u16,
+    pub old: u8,
+    pub new: u8,
+    /// Scanline and mode the PPU was in when the write happened
+    pub ly: u8,
+    pub mode: u8,
+}
+
+/// Which sprite wins where opaque sprite pixels overlap (OPRI, FF6C)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum ObjectPriority {
+    /// DMG, and CGB in DMG-compatibility mode: smaller X wins, then lower OAM index
+    #[default]
+    XCoordinate,
+    /// CGB mode: lower OAM index wins
+    OamIndex,
+}
+
+impl std::str::FromStr for ObjectPriority {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "dmg" => Ok(ObjectPriority::XCoordinate),
+            "cgb" => Ok(ObjectPriority::OamIndex),
+            other => Err(format!(
+                "unknown sprite priority '{}' (expected dmg or cgb)",
+                other
+            )),
+        }
+    }
+}
+
+impl std::fmt::Display for ObjectPriority {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            ObjectPriority::XCoordinate => "dmg",
+            ObjectPriority::OamIndex => "cgb",
+        })
+    }
+}
+
+/// Layer a pixel was taken from
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PixelLayer {
+    #[default]
+    Background,
+    Window,
+    Sprite,
+}
+
+/// Palette register a pixel's color index was looked up in
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PixelPalette {
+    #[default]
+    Bgp,
+    Obp0,
+    Obp1,
+}
+
+/// Where a framebuffer pixel came from
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct PixelInfo {
+    pub layer: PixelLayer,
+    pub palette: PixelPalette,
+    /// Color index (0..3) before palette lookup
+    pub color_index: u8,
+}
+
+// Sprite pixel waiting in the pixel FIFO to be mixed with the background
+#[derive(Debug, Clone, Copy, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+struct ObjPixel {
+    // Color index, 0 for transparent
+    color: u8,
+    attr: u8,
+    oam: u8,
+}
+
+// Set in background FIFO entries that came from the window tilemap
+const WINDOW_PIXEL: u8 = 0x04;
+
+// Mode 3 state (Pan Docs, "Pixel FIFO"). The fetcher pushes tile rows into the background
+// FIFO and one pixel is shifted out per dot; sprite fetches and the window start stall it.
+// Sprite pixels are fetched when the output reaches their X, into a line buffer standing
+// in for the sprite FIFO.
+#[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+struct PixelFifo {
+    // Mode 3 dots run so far
+    dot: u32,
+    // Next screen X to output; 160 once the line is drawn
+    x: u8,
+    // Fine scroll pixels still to shift out and drop
+    discard: u8,
+    // Dots left of the current sprite fetch or window restart
+    stall: u32,
+    // Tile column of the next fetch, counted from SCX or from the window's left edge
+    fetch_x: u8,
+    // The window started on this line
+    window: bool,
+    // Ring buffer of background color indices, tagged with WINDOW_PIXEL
+    bg: [u8; 16],
+    bg_head: u8,
+    bg_len: u8,
+    // OAM indices of the line's sprites sorted by X, and the next one to fetch
+    sprites: [u8; 10],
+    sprite_count: u8,
+    next_sprite: u8,
+    // Background tile the last sprite fetch waited for
+    waited_tile: Option<i16>,
+    #[cfg_attr(feature = "serde", serde(with = "crate::savestate::serde_array"))]
+    obj_line: [ObjPixel; 160],
+}
+
+impl PixelFifo {
+    fn new() -> Self {
+        Self {
+            dot: 0,
+            x: 0,
+            discard: 0,
+            stall: 0,
+            fetch_x: 0,
+            window: false,
+            bg: [0; 16],
+            bg_head: 0,
+            bg_len: 0,
+            sprites: [0; 10],
+            sprite_count: 0,
+            next_sprite: 0,
+            waited_tile: None,
+            obj_line: [ObjPixel::default(); 160],
+        }
+    }
+
+    fn push_bg(&mut self, pixel: u8) {
+        self.bg[(self.bg_head + self.bg_len) as usize % 16] = pixel;
+        self.bg_len += 1;
+    }
+
+    fn pop_bg(&mut self) -> u8 {
+        let pixel = self.bg[self.bg_head as usize];
+        self.bg_head = (self.bg_head + 1) % 16;
+        self.bg_len -= 1;
+        pixel
+    }
+
+    fn save_state(&self, w: &mut StateWriter) {
+        w.u32(self.dot);
+        w.u8(self.x);
+        w.u8(self.discard);
+        w.u32(self.stall);
+        w.u8(self.fetch_x);
+        w.bool(self.window);
+        w.bytes(&self.bg);
+        w.u8(self.bg_head);
+        w.u8(self.bg_len);
+        w.bytes(&self.sprites);
+        w.u8(self.sprite_count);
+        w.u8(self.next_sprite);
+        w.bool(self.waited_tile.is_some());
+        w.u16(self.waited_tile.unwrap_or(0) as u16);
+        for pixel in &self.obj_line {
+            w.u8(pixel.color);
+            w.u8(pixel.attr);
+            w.u8(pixel.oam);
+        }
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> Result<(), String> {
+        self.dot = r.u32()?;
+        self.x = r.u8()?;
+        self.discard = r.u8()?;
+        self.stall = r.u32()?;
+        self.fetch_x = r.u8()?;
+        self.window = r.bool()?;
+        r.fill(&mut self.bg)?;
+        self.bg_head = r.u8()?;
+        self.bg_len = r.u8()?;
+        r.fill(&mut self.sprites)?;
+        self.sprite_count = r.u8()?;
+        self.next_sprite = r.u8()?;
+        let waited = r.bool()?;
+        let tile = r.u16()? as i16;
+        self.waited_tile = waited.then_some(tile);
+        for pixel in self.obj_line.iter_mut() {
+            *pixel = ObjPixel {
+                color: r.u8()?,
+                attr: r.u8()?,
+                oam: r.u8()?,
+            };
+        }
+        let sprites_valid = self.sprite_count <= 10 && self.next_sprite <= self.sprite_count;
+        if self.x > 160 || self.bg_head >= 16 || self.bg_len > 16 || !sprites_valid {
+            return Err("invalid pixel FIFO state".to_string());
+        }
+        Ok(())
+    }
 }
 
 // LCD Modes
@@ -38,24 +385,27 @@ const MODE_VBLANK: u8 = 1;
 const MODE_OAM_SCAN: u8 = 2;
 const MODE_DRAWING: u8 = 3;
 
-// LCDC flags
-const LCDC_LCD_ENABLE: u8 = 0b10000000;
-const LCDC_WINDOW_ENABLE: u8 = 0b00100000;
-const LCDC_WINDOW_TILEMAP: u8 = 0b01000000;
-const LCDC_BG_TILEMAP: u8 = 0b00001000;
-const LCDC_BG_WINDOW_TILES: u8 = 0b00010000;
-const LCDC_BG_ENABLE: u8 = 0b00000001;
-
-// STAT flags
-const STAT_MODE_MASK: u8 = 0b00000011;
-
 // Timing (in CPU cycles)
 const OAM_SCAN_CYCLES: u32 = 80;
+// Shortest mode 3; fine scroll, the window and sprites extend it (see mode3_length)
 const DRAWING_CYCLES: u32 = 172;
-const HBLANK_CYCLES: u32 = 204;
 const SCANLINE_CYCLES: u32 = 456;
-// LCDC OBJ size bit
-const LCDC_OBJ_SIZE: u8 = 0b00000100;
+// Sprite fetch cost on top of the wait for the background fetcher
+const SPRITE_FETCH_CYCLES: u32 = 6;
+// Background fetcher restart when the window starts on a line
+const WINDOW_START_CYCLES: u32 = 6;
+// Mode 3 dot at which the fetcher reads the tile number of the first tile ...
+const FIRST_TILE_FETCH_DOT: u32 = 6;
+// ... and the one at which the FIFO shifts out its first pixel
+const FIRST_PIXEL_DOT: u32 = 12;
+// The fetcher pushes the next tile row once the FIFO is down to this many pixels
+const FETCH_THRESHOLD: u8 = 6;
+// Dots at the start of a line before LYC is compared with the new LY
+const LY_COMPARE_DELAY: u32 = 4;
+// Line 153: LY reads 0 from this dot on, but LYC is still compared with 153 ...
+const LINE_153_LY_RESET: u32 = 4;
+// ... until this dot, so LYC=0 matches here rather than at the start of line 0
+const LINE_153_COMPARE_ZERO: u32 = 8;
 
 impl Default for Ppu {
     fn default() -> Self {
@@ -68,8 +418,8 @@ impl Ppu {
         Self {
             vram: [0; 0x2000],
             oam: [0; 0xA0],
-            lcdc: 0x91,
-            stat: 0x02,
+            lcdc: Lcdc(0x91),
+            stat: Stat(0x02),
             scy: 0,
             scx: 0,
             ly: 0,
@@ -81,19 +431,156 @@ impl Ppu {
             wx: 0,
             framebuffer: [0; 160 * 144 * 3],
             bg_color_index: [0; 160 * 144],
+            pixel_info: None,
+            dirty_rows: [true; 144],
+            color_scheme: ColorScheme::default(),
+            object_priority: ObjectPriority::default(),
+            forced_object_priority: None,
             mode_cycles: 0,
+            drawing_cycles: DRAWING_CYCLES,
             vblank_interrupt: false,
             stat_interrupt: false,
+            stat_line: false,
+            ly_compare: Some(0),
             window_line_counter: 0,
+            window_y_triggered: false,
+            fifo: PixelFifo::new(),
+            pixel_fifo: true,
             // track previous LCD enabled state to avoid spam
             prev_lcd_enabled: true,
+            register_changes: None,
+        }
+    }
+
+    pub fn lcdc(&self) -> Lcdc {
+        self.lcdc
+    }
+
+    pub fn stat(&self) -> Stat {
+        self.stat
+    }
+
+    pub fn ly(&self) -> u8 {
+        self.ly
+    }
+
+    pub fn lyc(&self) -> u8 {
+        self.lyc
+    }
+
+    /// Priority sprites are drawn with: the forced one if set, otherwise OPRI's
+    pub fn object_priority(&self) -> ObjectPriority {
+        self.forced_object_priority.unwrap_or(self.object_priority)
+    }
+
+    /// Set by the CGB boot ROM through OPRI; DMG hardware always uses X priority
+    pub fn set_object_priority(&mut self, priority: ObjectPriority) {
+        self.object_priority = priority;
+    }
+
+    /// Draw sprites with `priority` whatever the model and OPRI say, or go back to
+    /// theirs with None. Lets games be compared under both overlap orders.
+    pub fn force_object_priority(&mut self, priority: Option<ObjectPriority>) {
+        self.forced_object_priority = priority;
+    }
+
+    pub fn forced_object_priority(&self) -> Option<ObjectPriority> {
+        self.forced_object_priority
+    }
+
+    /// Position within the current line (0-455 T-cycles)
+    pub fn dot(&self) -> u32 {
+        match self.stat.mode() {
+            MODE_DRAWING => OAM_SCAN_CYCLES + self.mode_cycles,
+            MODE_HBLANK => OAM_SCAN_CYCLES + self.drawing_cycles + self.mode_cycles,
+            _ => self.mode_cycles,
+        }
+    }
+
+    /// Background scroll as (SCX, SCY)
+    pub fn scroll(&self) -> (u8, u8) {
+        (self.scx, self.scy)
+    }
+
+    /// Window position as (WX, WY)
+    pub fn window_position(&self) -> (u8, u8) {
+        (self.wx, self.wy)
+    }
+
+    /// DMG palettes as (BGP, OBP0, OBP1)
+    pub fn palettes(&self) -> (u8, u8, u8) {
+        (self.bgp, self.obp0, self.obp1)
+    }
+
+    /// Set (BGP, OBP0, OBP1) without logging register changes (savestate restore)
+    pub fn set_palettes(&mut self, (bgp, obp0, obp1): (u8, u8, u8)) {
+        self.bgp = bgp;
+        self.obp0 = obp0;
+        self.obp1 = obp1;
+    }
+
+    /// Start or stop logging register changes (see `take_register_changes`)
+    pub fn set_register_log_enabled(&mut self, enabled: bool) {
+        self.register_changes = enabled.then(Vec::new);
+    }
+
+    /// Register changes since the last call, oldest first
+    pub fn take_register_changes(&mut self) -> Vec<RegisterChange> {
+        self.register_changes
+            .as_mut()
+            .map(std::mem::take)
+            .unwrap_or_default()
+    }
+
+    /// Start or stop recording per-pixel metadata alongside the framebuffer
+    pub fn set_pixel_info_enabled(&mut self, enabled: bool) {
+        self.pixel_info = enabled.then(|| vec![PixelInfo::default(); 160 * 144]);
+    }
+
+    /// Metadata of the pixel at (x, y), if recording is enabled
+    pub fn pixel_info_at(&self, x: usize, y: usize) -> Option<PixelInfo> {
+        self.pixel_info.as_ref().map(|info| info[y * 160 + x])
+    }
+
+    /// RGB image of the last frame tinted by layer (BG blue, window green, OBP0 sprites
+    /// red, OBP1 sprites magenta) and shaded by color index; None unless recording is enabled
+    pub fn layer_view(&self) -> Option<Vec<u8>> {
+        const SHADES: [u16; 4] = [255, 170, 100, 45];
+        let info = self.pixel_info.as_ref()?;
+        let mut rgb = Vec::with_capacity(info.len() * 3);
+        for pixel in info {
+            let tint: [u16; 3] = match (pixel.layer, pixel.palette) {
+                (PixelLayer::Background, _) => [0x40, 0x70, 0xFF],
+                (PixelLayer::Window, _) => [0x40, 0xFF, 0x60],
+                (PixelLayer::Sprite, PixelPalette::Obp1) => [0xFF, 0x40, 0xFF],
+                (PixelLayer::Sprite, _) => [0xFF, 0x50, 0x40],
+            };
+            let shade = SHADES[pixel.color_index as usize & 3];
+            rgb.extend(tint.iter().map(|&c| (c * shade / 255) as u8));
+        }
+        Some(rgb)
+    }
+
+    fn record_pixel(
+        &mut self,
+        ly: usize,
+        x: usize,
+        layer: PixelLayer,
+        palette: PixelPalette,
+        color_index: u8,
+    ) {
+        if let Some(info) = self.pixel_info.as_mut() {
+            info[ly * 160 + x] = PixelInfo {
+                layer,
+                palette,
+                color_index,
+            };
         }
     }
 
     /// Step PPU by given CPU cycles, returns true if VBlank interrupt requested
     pub fn step(&mut self, cycles: u32) -> bool {
-        let lcd_enabled = (self.lcdc & LCDC_LCD_ENABLE) != 0;
-        if !lcd_enabled {
+        if !self.lcdc.lcd_enabled() {
             // When LCD is off, PPU doesn't run, but we need to track state
             // Reset to safe state
             if self.prev_lcd_enabled {
@@ -110,34 +597,47 @@ impl Ppu {
         self.mode_cycles += cycles;
 
         // Determine current mode early for tracing
-        let current_mode = self.stat & STAT_MODE_MASK;
+        let current_mode = self.stat.mode();
 
         let mut vblank = false;
 
         match current_mode {
-            MODE_OAM_SCAN => {
-                if self.mode_cycles >= OAM_SCAN_CYCLES {
-                    self.mode_cycles -= OAM_SCAN_CYCLES;
-                    self.set_mode(MODE_DRAWING);
+            MODE_OAM_SCAN if self.mode_cycles >= OAM_SCAN_CYCLES => {
+                self.mode_cycles -= OAM_SCAN_CYCLES;
+                self.start_line();
+                self.drawing_cycles = self.mode3_length();
+                self.set_mode(MODE_DRAWING);
+                if self.pixel_fifo {
+                    self.draw_dots();
                 }
             }
             MODE_DRAWING => {
-                if self.mode_cycles >= DRAWING_CYCLES {
-                    self.mode_cycles -= DRAWING_CYCLES;
+                // Without the FIFO running along, finish_line draws the whole line
+                if self.pixel_fifo {
+                    self.draw_dots();
+                }
+                if self.mode_cycles >= self.drawing_cycles {
+                    self.mode_cycles -= self.drawing_cycles;
                     self.set_mode(MODE_HBLANK);
-                    self.render_scanline();
+                    self.finish_line();
                 }
             }
             MODE_HBLANK => {
-                if self.mode_cycles >= HBLANK_CYCLES {
-                    self.mode_cycles -= HBLANK_CYCLES;
+                // HBlank takes whatever is left of the line after mode 3
+                let hblank_cycles = SCANLINE_CYCLES - OAM_SCAN_CYCLES - self.drawing_cycles;
+                if self.mode_cycles >= hblank_cycles {
+                    self.mode_cycles -= hblank_cycles;
                     self.set_ly(self.ly + 1);
                     if self.ly == 144 {
-                        self.set_mode(MODE_VBLANK);
+                        self.stat.set_mode(MODE_VBLANK);
+                        // Entering VBlank also raises the mode 2 source, for this dot only
+                        let oam_source = self.stat.mode_interrupt_enabled(MODE_OAM_SCAN);
+                        self.set_stat_line(self.stat_sources() || oam_source);
                         vblank = true;
                         self.vblank_interrupt = true;
                         // Reset window line counter at end of frame
                         self.window_line_counter = 0;
+                        self.window_y_triggered = false;
                     } else if self.ly < 144 {
                         // Normal scanline 0-143: return to OAM scan for next line
                         self.set_mode(MODE_OAM_SCAN);
@@ -145,390 +645,485 @@ impl Ppu {
                 }
             }
             MODE_VBLANK => {
+                // Line 153 is the last of the frame, but LY reads 0 for most of it
+                if self.ly == 153 && self.mode_cycles >= LINE_153_LY_RESET {
+                    self.ly = 0;
+                    self.ly_compare = Some(153);
+                    self.update_coincidence();
+                }
+                if self.ly == 0
+                    && self.ly_compare == Some(153)
+                    && self.mode_cycles >= LINE_153_COMPARE_ZERO
+                {
+                    self.ly_compare = Some(0);
+                    self.update_coincidence();
+                }
+
                 if self.mode_cycles >= SCANLINE_CYCLES {
                     self.mode_cycles -= SCANLINE_CYCLES;
-
-                    // Advance LY using the helper which performs LYC==LY checks
-                    let next_ly = self.ly.wrapping_add(1);
-                    self.set_ly(next_ly);
-
-                    // After LY passes the last VBlank scanline, wrap to 0 and resume OAM scan
-                    if self.ly > 153 {
-                        self.set_ly(0);
+                    if self.ly == 0 {
+                        // End of line 153: LY and the LYC=LY flag already are what
+                        // line 0 needs
                         self.set_mode(MODE_OAM_SCAN);
+                    } else {
+                        self.set_ly(self.ly + 1);
                     }
                 }
             }
             _ => {}
         }
 
+        if self.ly_compare.is_none() && self.dot() >= LY_COMPARE_DELAY {
+            self.ly_compare = Some(self.ly);
+            self.update_coincidence();
+        }
+
         vblank
     }
 
+    // Start a new line: LYC is compared with it after LY_COMPARE_DELAY
     fn set_ly(&mut self, value: u8) {
         self.ly = value;
-        if self.ly == self.lyc {
-            self.stat |= 0x04; // Set LYC=LY coincidence flag
-            if (self.stat & 0x40) != 0 {
-                // LYC interrupt enabled?
-                self.stat_interrupt = true;
-            }
-        } else {
-            self.stat &= !0x04; // Clear LYC=LY coincidence flag
-        }
+        self.ly_compare = None;
+        self.update_coincidence();
+    }
+
+    // Recompute the LYC=LY flag
+    fn update_coincidence(&mut self) {
+        self.stat.set_coincidence(self.ly_compare == Some(self.lyc));
+        self.set_stat_line(self.stat_sources());
     }
 
     fn set_mode(&mut self, mode: u8) {
-        let old_mode = self.stat & STAT_MODE_MASK;
-        self.stat = (self.stat & !STAT_MODE_MASK) | (mode & STAT_MODE_MASK);
-
-        // Generate STAT interrupt if enabled for this mode
-        // STAT register bits: bit 6=LYC, bit 5=Mode2, bit 4=Mode1, bit 3=Mode0
-        let should_interrupt = match mode {
-            MODE_HBLANK => (self.stat & 0x08) != 0, // Bit 3: Mode 0 HBlank interrupt
-            MODE_VBLANK => (self.stat & 0x10) != 0, // Bit 4: Mode 1 VBlank interrupt
-            MODE_OAM_SCAN => (self.stat & 0x20) != 0, // Bit 5: Mode 2 OAM interrupt
-            _ => false,
-        };
+        self.stat.set_mode(mode);
+        self.set_stat_line(self.stat_sources());
+    }
+
+    // Enabled STAT sources whose condition currently holds
+    fn stat_sources(&self) -> bool {
+        let lyc = self.stat.lyc_interrupt_enabled() && self.stat.coincidence();
+        lyc || self.stat.mode_interrupt_enabled(self.stat.mode())
+    }
 
-        if should_interrupt && old_mode != mode {
+    fn set_stat_line(&mut self, line: bool) {
+        if line && !self.stat_line {
             self.stat_interrupt = true;
         }
+        self.stat_line = line;
     }
 
-    fn render_scanline(&mut self) {
-        let ly = self.ly as usize;
-        if ly >= 144 {
-            return;
+    // Mode 3 length in dots for the current line (Pan Docs, "Mode 3 length"):
+    // SCX fine scroll pixels are fetched and discarded, starting the window restarts the
+    // background fetcher, and each sprite stalls the fetcher. Sprites cost 6 dots, plus a
+    // wait for the background fetch of the tile under their leftmost pixel if no earlier
+    // sprite already waited for that tile.
+    fn mode3_length(&self) -> u32 {
+        let mut length = DRAWING_CYCLES + (self.scx % 8) as u32;
+
+        if self.lcdc.window_enabled()
+            && self.lcdc.bg_enabled()
+            && (self.window_y_triggered || self.ly == self.wy)
+            && self.wx <= 166
+        {
+            length += WINDOW_START_CYCLES;
         }
 
-        let palette = self.get_palette(self.bgp);
-
-        if (self.lcdc & LCDC_BG_ENABLE) != 0 {
-            self.render_background_line(ly, &palette);
-        } else {
-            // BG disabled - fill with white
-            for x in 0..160 {
-                let idx = (ly * 160 + x) * 3;
-                self.framebuffer[idx] = 0x9B;
-                self.framebuffer[idx + 1] = 0xBC;
-                self.framebuffer[idx + 2] = 0x0F;
-                self.bg_color_index[ly * 160 + x] = 0;
+        if self.lcdc.obj_enabled() {
+            let (sprites, count) = self.line_sprites();
+            let mut waited_tile = None;
+            for &i in &sprites[..count] {
+                length += self.sprite_penalty(self.oam[i as usize * 4 + 1], &mut waited_tile);
             }
         }
 
-        // Render window on top of background (but under sprites)
-        // On DMG, window requires both Window Enable (bit 5) AND BG Enable (bit 0)
-        if (self.lcdc & LCDC_WINDOW_ENABLE) != 0 && (self.lcdc & LCDC_BG_ENABLE) != 0 {
-            self.render_window_line(ly);
-        }
-
-        // Render sprites for this scanline (after background/window) so they overlay correctly
-        self.render_sprites_line(ly);
+        length
     }
 
-    fn render_background_line(&mut self, ly: usize, palette: &[(u8, u8, u8); 4]) {
-        let y = (ly as u8).wrapping_add(self.scy);
-        let tile_y = ((y / 8) % 32) as u16; // Wrap at 32 tiles
-        let tile_y_offset = (y % 8) as u16;
-
-        let tilemap_base = if (self.lcdc & LCDC_BG_TILEMAP) != 0 {
-            0x9C00u16
-        } else {
-            0x9800u16
-        };
-
-        let signed_addressing = (self.lcdc & LCDC_BG_WINDOW_TILES) == 0;
-
-        for screen_x in 0..160 {
-            let x = (screen_x as u8).wrapping_add(self.scx);
-            let tile_x = ((x / 8) % 32) as u16; // Wrap at 32 tiles
-            let tile_x_offset = 7 - (x % 8);
-
-            // Calculate tilemap address with bounds checking
-            let tilemap_offset = tile_y * 32 + tile_x;
-            if tilemap_offset >= 1024 {
-                // Out of bounds, skip this pixel
-                continue;
-            }
+    // Dots a sprite at OAM X `oam_x` stalls the fetcher for, given the background tile the
+    // previous sprite fetch waited for
+    fn sprite_penalty(&self, oam_x: u8, waited_tile: &mut Option<i16>) -> u32 {
+        let bg_x = oam_x as i16 - 8 + self.scx as i16;
+        let tile = bg_x.div_euclid(8);
+        let mut penalty = SPRITE_FETCH_CYCLES;
+        if *waited_tile != Some(tile) {
+            *waited_tile = Some(tile);
+            // Pixels of the tile right of the sprite's leftmost pixel, minus 2
+            penalty += 5u32.saturating_sub(bg_x.rem_euclid(8) as u32);
+        }
+        penalty
+    }
 
-            let tilemap_addr = tilemap_base + tilemap_offset;
-            let vram_index = (tilemap_addr - 0x8000) as usize;
+    // OAM indices of the sprites on the current line in the order they are fetched: left
+    // to right, OAM order for equal X. X >= 168 is never reached by the fetcher.
+    fn line_sprites(&self) -> ([u8; 10], usize) {
+        let mut sprites = [0u8; 10];
+        let mut count = 0;
+        for i in self
+            .sprites_on_line(self.ly as usize)
+            .filter(|&i| self.oam[i * 4 + 1] < 168)
+        {
+            sprites[count] = i as u8;
+            count += 1;
+        }
+        sprites[..count].sort_by_key(|&i| self.oam[i as usize * 4 + 1]);
+        (sprites, count)
+    }
 
-            if vram_index >= 0x2000 {
-                // Out of VRAM bounds, skip
-                continue;
-            }
+    /// Rows that changed since the last call, as one span covering all of them
+    /// (a single rectangle for a partial texture upload)
+    pub fn take_dirty_rows(&mut self) -> Option<Range<usize>> {
+        let first = self.dirty_rows.iter().position(|&dirty| dirty)?;
+        let last = self.dirty_rows.iter().rposition(|&dirty| dirty)?;
+        self.dirty_rows = [false; 144];
+        Some(first..last + 1)
+    }
 
-            let tile_num = self.vram[vram_index];
+    /// Force the next take_dirty_rows to cover the whole screen, e.g. after the
+    /// framebuffer was replaced or something else was shown in its place
+    pub fn mark_all_rows_dirty(&mut self) {
+        self.dirty_rows = [true; 144];
+    }
 
-            let tile_addr = if signed_addressing {
-                let offset = (tile_num as i8 as i16 + 128) as u16;
-                0x8800u16 + offset * 16
-            } else {
-                0x8000u16 + (tile_num as u16) * 16
-            };
+    pub fn color_scheme(&self) -> ColorScheme {
+        self.color_scheme
+    }
 
-            // Bounds check tile data access
-            let tile_data_offset = (tile_addr + tile_y_offset * 2 - 0x8000) as usize;
-            if tile_data_offset >= 0x1FFF {
-                // Out of bounds, use color 0
-                let fb_idx = (ly * 160 + screen_x) * 3;
-                let color = palette[0];
-                self.framebuffer[fb_idx] = color.0;
-                self.framebuffer[fb_idx + 1] = color.1;
-                self.framebuffer[fb_idx + 2] = color.2;
-                self.bg_color_index[ly * 160 + screen_x] = 0;
-                continue;
+    /// Draw the four shades with `scheme` from now on. The current framebuffer is
+    /// recolored too, so the change is visible without waiting for the next frame.
+    pub fn set_color_scheme(&mut self, scheme: ColorScheme) {
+        let old = self.color_scheme.shades;
+        for pixel in self.framebuffer.chunks_exact_mut(3) {
+            if let Some(shade) = old.iter().position(|&(r, g, b)| pixel == [r, g, b]) {
+                let (r, g, b) = scheme.shades[shade];
+                pixel.copy_from_slice(&[r, g, b]);
             }
+        }
+        self.color_scheme = scheme;
+        self.mark_all_rows_dirty();
+    }
 
-            let byte1 = self.vram[tile_data_offset];
-            let byte2 = self.vram[tile_data_offset + 1];
+    // OAM scan is over: pick the line's sprites and reset the pixel FIFO for mode 3
+    fn start_line(&mut self) {
+        // WY is compared on every line, whether or not the window is enabled
+        if self.ly == self.wy {
+            self.window_y_triggered = true;
+        }
+        let (sprites, count) = self.line_sprites();
+        self.fifo = PixelFifo {
+            discard: self.scx % 8,
+            sprites,
+            sprite_count: count as u8,
+            ..PixelFifo::new()
+        };
+    }
 
-            let color_low = (byte1 >> tile_x_offset) & 1;
-            let color_high = (byte2 >> tile_x_offset) & 1;
-            let color_id = (color_high << 1) | color_low;
+    // Run the pixel FIFO up to the current mode 3 dot
+    fn draw_dots(&mut self) {
+        let until = self.mode_cycles.min(self.drawing_cycles);
+        while self.fifo.dot < until {
+            self.fifo_dot();
+        }
+    }
 
-            let fb_idx = (ly * 160 + screen_x) * 3;
-            let color = palette[color_id as usize];
-            self.framebuffer[fb_idx] = color.0;
-            self.framebuffer[fb_idx + 1] = color.1;
-            self.framebuffer[fb_idx + 2] = color.2;
-            // Save bg color_id for sprite priority decisions
-            self.bg_color_index[ly * 160 + screen_x] = color_id;
+    // End of mode 3: output the pixels the FIFO has not reached yet (registers written
+    // mid-line can make it run longer than mode3_length predicted)
+    fn finish_line(&mut self) {
+        while self.fifo.x < 160 {
+            self.fifo_dot();
+        }
+        if self.fifo.window {
+            self.window_line_counter = self.window_line_counter.wrapping_add(1);
         }
     }
 
-    fn render_window_line(&mut self, ly: usize) {
-        // Window coordinates: WX-7 is the leftmost position, WY is the topmost position
-        // Window is only visible when LY >= WY
-        if (ly as u8) < self.wy {
+    /// DMG OAM corruption bug: the CPU touched FE00-FEFF (or put an address there on
+    /// the 16-bit increment unit, `write` = true, like a write) while the PPU was reading
+    /// OAM in mode 2. The 8-byte row being read gets mixed with the one before it.
+    pub fn corrupt_oam(&mut self, write: bool) {
+        if !self.lcdc.lcd_enabled() || self.stat.mode() != MODE_OAM_SCAN {
             return;
         }
-
-        let palette = self.get_palette(self.bgp);
-
-        // Use window internal line counter (not LY - WY)
-        let window_y = self.window_line_counter;
-        let tile_y = ((window_y / 8) % 32) as u16;
-        let tile_y_offset = (window_y % 8) as u16;
-
-        let tilemap_base = if (self.lcdc & LCDC_WINDOW_TILEMAP) != 0 {
-            0x9C00u16
+        // One row of two objects per M-cycle; the first row has none before it
+        let row = (self.mode_cycles / 4) as usize * 8;
+        if row == 0 || row >= self.oam.len() {
+            return;
+        }
+        let word = |oam: &[u8; 0xA0], at: usize| u16::from_le_bytes([oam[at], oam[at + 1]]);
+        let a = word(&self.oam, row);
+        let b = word(&self.oam, row - 8);
+        let c = word(&self.oam, row - 4);
+        let first = if write {
+            ((a ^ c) & (b ^ c)) ^ c
         } else {
-            0x9800u16
+            b | (a & c)
         };
+        self.oam[row..row + 2].copy_from_slice(&first.to_le_bytes());
+        self.oam.copy_within(row - 6..row, row + 2);
+    }
 
-        let signed_addressing = (self.lcdc & LCDC_BG_WINDOW_TILES) == 0;
+    // Draw a whole line at once
+    #[cfg(test)]
+    fn render_scanline(&mut self) {
+        self.start_line();
+        self.finish_line();
+    }
 
-        // Track if we actually rendered any window pixels this line
-        let mut rendered_window = false;
+    // One mode 3 dot: registers are read as the fetcher and the FIFO use them, so
+    // mid-line writes to LCDC, SCX, SCY and the palettes land on the pixels after them
+    fn fifo_dot(&mut self) {
+        let dot = self.fifo.dot;
+        self.fifo.dot += 1;
+        if self.fifo.x >= 160 || dot < FIRST_TILE_FETCH_DOT {
+            return;
+        }
+        if self.fifo.stall > 0 {
+            self.fifo.stall -= 1;
+            return;
+        }
+        let drawing = dot >= FIRST_PIXEL_DOT && self.fifo.discard == 0;
+        if drawing && (self.start_sprite_fetch() || self.start_window()) {
+            return;
+        }
+        if self.fifo.bg_len <= FETCH_THRESHOLD {
+            self.fetch_tile();
+        }
+        if dot < FIRST_PIXEL_DOT {
+            return;
+        }
+        let pixel = self.fifo.pop_bg();
+        if self.fifo.discard > 0 {
+            self.fifo.discard -= 1;
+        } else {
+            self.output_pixel(pixel);
+        }
+    }
 
-        // Window starts at screen position WX-7 (can be negative)
-        // WX=0 means window X starts at -7, WX=7 means window X starts at 0
-        let window_start_x_signed = (self.wx as i16) - 7;
+    // Fetch the next sprite if the output has reached its X; its pixels go into the
+    // line buffer and the fetcher stalls for the rest of the sprite penalty
+    fn start_sprite_fetch(&mut self) -> bool {
+        if self.fifo.next_sprite == self.fifo.sprite_count || !self.lcdc.obj_enabled() {
+            return false;
+        }
+        let index = self.fifo.sprites[self.fifo.next_sprite as usize] as usize;
+        let oam_x = self.oam[index * 4 + 1];
+        if oam_x.saturating_sub(8) > self.fifo.x {
+            return false;
+        }
+        self.fifo.next_sprite += 1;
+        let mut waited_tile = self.fifo.waited_tile;
+        self.fifo.stall = self.sprite_penalty(oam_x, &mut waited_tile) - 1;
+        self.fifo.waited_tile = waited_tile;
+        self.fetch_sprite(index);
+        true
+    }
+
+    // Restart the fetcher on the window's tiles if the output has reached WX
+    fn start_window(&mut self) -> bool {
+        let window_on = self.lcdc.window_enabled() && self.lcdc.bg_enabled();
+        if self.fifo.window
+            || !window_on
+            || !self.window_y_triggered
+            || self.wx > 166
+            || self.fifo.x != self.wx.saturating_sub(7)
+        {
+            return false;
+        }
+        self.fifo.window = true;
+        self.fifo.fetch_x = 0;
+        self.fifo.bg_len = 0;
+        self.fetch_tile();
+        // WX below 7 starts the window left of the screen
+        for _ in self.wx..7 {
+            self.fifo.pop_bg();
+        }
+        self.fifo.stall = WINDOW_START_CYCLES - 1;
+        true
+    }
 
-        // Determine the range of screen X coordinates to render
-        let screen_x_start = if window_start_x_signed < 0 {
-            0
+    // Push the next background or window tile row into the FIFO, with LCDC and the
+    // scroll registers as they are now
+    fn fetch_tile(&mut self) {
+        let (tilemap, column, row) = if self.fifo.window {
+            let tilemap = self.lcdc.window_tilemap();
+            (tilemap, self.fifo.fetch_x, self.window_line_counter)
         } else {
-            window_start_x_signed as u8
+            let column = (self.scx / 8).wrapping_add(self.fifo.fetch_x);
+            let row = self.ly.wrapping_add(self.scy);
+            (self.lcdc.bg_tilemap(), column, row)
         };
-
-        // Determine the starting position within the window tilemap
-        // If window_start_x_signed < 0, we skip the first few window pixels
-        let window_pixel_x_start = if window_start_x_signed < 0 {
-            (-window_start_x_signed) as u8
+        self.fifo.fetch_x = self.fifo.fetch_x.wrapping_add(1);
+
+        // Wrap at 32 tiles
+        let map_row = (row as usize / 8) * 32;
+        let map_index = (tilemap - 0x8000) as usize + map_row + column as usize % 32;
+        let tile_num = self.vram[map_index];
+        let tile_offset = if self.lcdc.unsigned_tile_data() {
+            tile_num as usize * 16
         } else {
-            0
+            // 0x8800 addressing: tile numbers are signed, relative to 0x9000
+            (0x1000 + tile_num as i8 as isize * 16) as usize
         };
+        let data = tile_offset + (row as usize % 8) * 2;
+        let (low, high) = (self.vram[data], self.vram[data + 1]);
 
-        // Render window pixels
-        for screen_x in screen_x_start..160 {
-            // Calculate position within window tilemap
-            let window_pixel_x = window_pixel_x_start + (screen_x - screen_x_start);
-            let tile_x = ((window_pixel_x / 8) % 32) as u16;
-            let tile_x_offset = 7 - (window_pixel_x % 8);
-
-            let tilemap_offset = tile_y * 32 + tile_x;
-            if tilemap_offset >= 1024 {
-                continue;
-            }
-
-            let tilemap_addr = tilemap_base + tilemap_offset;
-            let vram_index = (tilemap_addr - 0x8000) as usize;
-
-            if vram_index >= 0x2000 {
-                continue;
-            }
-
-            let tile_num = self.vram[vram_index];
-
-            let tile_addr = if signed_addressing {
-                let offset = (tile_num as i8 as i16 + 128) as u16;
-                0x8800u16 + offset * 16
-            } else {
-                0x8000u16 + (tile_num as u16) * 16
-            };
-
-            let tile_data_offset = (tile_addr + tile_y_offset * 2 - 0x8000) as usize;
-            if tile_data_offset >= 0x1FFF {
-                let fb_idx = (ly * 160 + screen_x as usize) * 3;
-                let color = palette[0];
-                self.framebuffer[fb_idx] = color.0;
-                self.framebuffer[fb_idx + 1] = color.1;
-                self.framebuffer[fb_idx + 2] = color.2;
-                self.bg_color_index[ly * 160 + screen_x as usize] = 0;
-                rendered_window = true;
-                continue;
-            }
-
-            let byte1 = self.vram[tile_data_offset];
-            let byte2 = self.vram[tile_data_offset + 1];
-
-            let color_low = (byte1 >> tile_x_offset) & 1;
-            let color_high = (byte2 >> tile_x_offset) & 1;
-            let color_id = (color_high << 1) | color_low;
-
-            let fb_idx = (ly * 160 + screen_x as usize) * 3;
-            let color = palette[color_id as usize];
-            self.framebuffer[fb_idx] = color.0;
-            self.framebuffer[fb_idx + 1] = color.1;
-            self.framebuffer[fb_idx + 2] = color.2;
-            // Window pixels also count as background for sprite priority
-            self.bg_color_index[ly * 160 + screen_x as usize] = color_id;
-            rendered_window = true;
-        }
-
-        // Increment window line counter only if we actually rendered window pixels
-        if rendered_window {
-            self.window_line_counter = self.window_line_counter.wrapping_add(1);
+        let window = if self.fifo.window { WINDOW_PIXEL } else { 0 };
+        for bit in (0..8).rev() {
+            let color = (((high >> bit) & 1) << 1) | ((low >> bit) & 1);
+            self.fifo.push_bg(color | window);
         }
     }
 
-    fn render_sprites_line(&mut self, ly: usize) {
+    // Fetch the current line's row of sprite `index` into the sprite line buffer.
+    // On DMG the sprite with the smaller X wins where opaque pixels overlap, then the
+    // lower OAM index; in CGB mode only the OAM index counts.
+    fn fetch_sprite(&mut self, index: usize) {
         // Each OAM entry: Y, X, tile, attributes
-        let obj_size = if (self.lcdc & LCDC_OBJ_SIZE) != 0 {
-            16
+        let obj_size = self.lcdc.obj_size();
+        let base = index * 4;
+        let sprite_y = (self.oam[base] as i16) - 16;
+        let sprite_x = (self.oam[base + 1] as i16) - 8;
+        let mut tile = self.oam[base + 2];
+        let attr = self.oam[base + 3];
+
+        // Sprites that no longer cover the line (OAM or OBJ size changed since the scan)
+        // fetch nothing, but still cost their dots
+        let y = self.ly as i16 - sprite_y;
+        if !(0..obj_size as i16).contains(&y) {
+            return;
+        }
+        let y = y as usize;
+        let y_eff = if (attr & 0x40) != 0 {
+            // Y flip
+            (obj_size - 1) - y
         } else {
-            8
+            y
         };
 
-        // Collect up to 10 sprites on this line in OAM order
-        let mut sprites_on_line: Vec<usize> = Vec::new();
-        for i in 0..40 {
-            let base = i * 4;
-            let sprite_y = (self.oam[base] as i16) - 16;
-
-            // Only need sprite_y to determine if sprite is on this scanline
-            if (ly as i16) >= sprite_y && (ly as i16) < (sprite_y + obj_size as i16) {
-                sprites_on_line.push(i);
-                if sprites_on_line.len() >= 10 {
-                    break;
-                }
-            }
+        // For 8x16 mode, tile number LSB is ignored (tile & 0xFE)
+        if obj_size == 16 {
+            tile &= 0xFE;
         }
 
-        // Draw sprites in OAM order (lower index has priority)
-        for &i in sprites_on_line.iter() {
-            let base = i * 4;
-            let sprite_y = (self.oam[base] as i16) - 16;
-            let sprite_x = (self.oam[base + 1] as i16) - 8;
-            let mut tile = self.oam[base + 2];
-            let attr = self.oam[base + 3];
+        // Determine which tile within the sprite (for 8x16 may need second tile)
+        let tile_index = tile as usize + y_eff / 8;
+        let data = tile_index * 16 + (y_eff % 8) * 2;
+        let (byte1, byte2) = (self.vram[data], self.vram[data + 1]);
 
-            let y_in_sprite = (ly as i16 - sprite_y) as u8;
-            let y = y_in_sprite as usize;
-            let y_eff = if (attr & 0x40) != 0 {
-                // Y flip
-                (obj_size - 1) - y
+        let priority = self.object_priority();
+        for px in 0..8 {
+            let bit_index = if (attr & 0x20) != 0 {
+                // X flip
+                px
             } else {
-                y
+                7 - px
             };
+            let color_low = (byte1 >> bit_index) & 1;
+            let color_high = (byte2 >> bit_index) & 1;
+            let color_id = (color_high << 1) | color_low;
 
-            // For 8x16 mode, tile number LSB is ignored (tile & 0xFE)
-            if obj_size == 16 {
-                tile &= 0xFE;
-            }
-
-            // Determine which tile within the sprite (for 8x16 may need second tile)
-            let tile_index = (tile as u16) + (y_eff as u16 / 8);
-            let tile_line = (y_eff % 8) as u16;
-            let tile_addr = 0x8000u16 + tile_index * 16u16;
-            let tile_offset = (tile_addr + tile_line * 2 - 0x8000) as usize;
-
-            if tile_offset + 1 >= self.vram.len() {
+            let x = sprite_x + px as i16;
+            if color_id == 0 || !(0..160).contains(&x) {
                 continue;
             }
-            let byte1 = self.vram[tile_offset];
-            let byte2 = self.vram[tile_offset + 1];
-
-            for px in 0..8 {
-                let bit_index = if (attr & 0x20) != 0 {
-                    // X flip
-                    px
-                } else {
-                    7 - px
-                };
-                let color_low = (byte1 >> bit_index) & 1;
-                let color_high = (byte2 >> bit_index) & 1;
-                let color_id = (color_high << 1) | color_low;
-
-                if color_id == 0 {
-                    continue;
-                } // transparent
 
-                let x = sprite_x + px as i16;
-                if !(0..160).contains(&x) {
-                    continue;
-                }
-                let x_usize = x as usize;
+            // Sprites are fetched in X order, then OAM order: on DMG whatever is already
+            // there came from a sprite with a smaller or equal X and keeps the pixel
+            let owner = &mut self.fifo.obj_line[x as usize];
+            let owner_wins = owner.color != 0
+                && match priority {
+                    ObjectPriority::XCoordinate => true,
+                    ObjectPriority::OamIndex => (owner.oam as usize) < index,
+                };
+            if !owner_wins {
+                *owner = ObjPixel {
+                    color: color_id,
+                    attr,
+                    oam: index as u8,
+                };
+            }
+        }
+    }
 
-                // OBJ priority: if bit 7 set and bg color != 0 => bg has priority
-                if (attr & 0x80) != 0 {
-                    let bg_color = self.bg_color_index[ly * 160 + x_usize];
-                    if bg_color != 0 {
-                        continue;
-                    }
-                }
+    // Shift a background pixel out to the screen, mixed with the sprite pixel at its X.
+    // The palettes are applied here, so palette writes take effect from the next pixel.
+    fn output_pixel(&mut self, pixel: u8) {
+        let ly = self.ly as usize;
+        let x = self.fifo.x as usize;
+        self.fifo.x += 1;
 
-                // Choose palette
-                let palette = if (attr & 0x10) != 0 {
-                    self.get_palette(self.obp1)
-                } else {
-                    self.get_palette(self.obp0)
-                };
-                let color = palette[color_id as usize];
+        let layer = if pixel & WINDOW_PIXEL != 0 {
+            PixelLayer::Window
+        } else {
+            PixelLayer::Background
+        };
+        let (bg_color, bg_rgb) = if self.lcdc.bg_enabled() {
+            let color = pixel & 0x03;
+            (color, self.shade(self.bgp, color))
+        } else {
+            // BG disabled - white
+            (0, self.color_scheme.shades[0])
+        };
+        // Save bg color_id for sprite priority decisions
+        self.bg_color_index[ly * 160 + x] = bg_color;
+
+        // BG-to-OBJ priority (bit 7): BG colors 1-3 hide the sprite. The winning sprite was
+        // picked when it was fetched, so a hidden sprite still masks the ones below it.
+        let obj = self.fifo.obj_line[x];
+        let obj_shown = obj.color != 0 && (obj.attr & 0x80 == 0 || bg_color == 0);
+        let (rgb, layer, palette, color) = if obj_shown {
+            let (palette, palette_id) = if (obj.attr & 0x10) != 0 {
+                (self.obp1, PixelPalette::Obp1)
+            } else {
+                (self.obp0, PixelPalette::Obp0)
+            };
+            let rgb = self.shade(palette, obj.color);
+            (rgb, PixelLayer::Sprite, palette_id, obj.color)
+        } else {
+            (bg_rgb, layer, PixelPalette::Bgp, bg_color)
+        };
 
-                let fb_idx = (ly * 160 + x_usize) * 3;
-                self.framebuffer[fb_idx] = color.0;
-                self.framebuffer[fb_idx + 1] = color.1;
-                self.framebuffer[fb_idx + 2] = color.2;
-            }
+        let fb_idx = (ly * 160 + x) * 3;
+        let (r, g, b) = rgb;
+        if self.framebuffer[fb_idx..fb_idx + 3] != [r, g, b] {
+            self.framebuffer[fb_idx..fb_idx + 3].copy_from_slice(&[r, g, b]);
+            self.dirty_rows[ly] = true;
         }
+        self.record_pixel(ly, x, layer, palette, color);
     }
 
-    fn get_palette(&self, palette_byte: u8) -> [(u8, u8, u8); 4] {
-        const COLORS: [(u8, u8, u8); 4] = [
-            (0x9B, 0xBC, 0x0F), // Lightest
-            (0x8B, 0xAC, 0x0F), // Light
-            (0x30, 0x62, 0x30), // Dark
-            (0x0F, 0x38, 0x0F), // Darkest
-        ];
+    // OAM indices of the (at most 10) sprites selected for line `ly` by OAM scan, in OAM order
+    // (an iterator, so that rendering a line does not allocate)
+    fn sprites_on_line(&self, ly: usize) -> impl Iterator<Item = usize> + '_ {
+        let obj_size = self.lcdc.obj_size() as i16;
+        (0..40)
+            .filter(move |&i| {
+                // Only need sprite_y to determine if sprite is on this scanline
+                let sprite_y = (self.oam[i * 4] as i16) - 16;
+                (ly as i16) >= sprite_y && (ly as i16) < sprite_y + obj_size
+            })
+            .take(10)
+    }
 
+    #[cfg(test)]
+    fn get_palette(&self, palette_byte: u8) -> [(u8, u8, u8); 4] {
         let mut result = [(0, 0, 0); 4];
         for (i, colour) in result.iter_mut().enumerate() {
             let color_id = (palette_byte >> (i * 2)) & 0x03;
-            *colour = COLORS[color_id as usize];
+            *colour = self.color_scheme.shades[color_id as usize];
         }
         result
     }
 
+    // RGB color of `color_id` through palette register `palette_byte`
+    fn shade(&self, palette_byte: u8, color_id: u8) -> (u8, u8, u8) {
+        self.color_scheme.shades[((palette_byte >> (color_id * 2)) & 0x03) as usize]
+    }
+
     pub fn read(&self, address: u16) -> u8 {
         
         match address {
-            0xFF40 => self.lcdc,
-            0xFF41 => self.stat,
+            0xFF40 => self.lcdc.0,
+            0xFF41 => self.stat.0,
             0xFF42 => self.scy,
             0xFF43 => self.scx,
             0xFF44 => self.ly,
@@ -543,25 +1138,49 @@ impl Ppu {
     }
 
     pub fn write(&mut self, address: u16, value: u8) {
+        let old = self.read(address);
         match address {
             0xFF40 => {
-                let lcd_was_off = (self.lcdc & LCDC_LCD_ENABLE) == 0;
+                let lcd_was_off = !self.lcdc.lcd_enabled();
 
-                self.lcdc = value;
-                let lcd_is_on = (self.lcdc & LCDC_LCD_ENABLE) != 0;
+                self.lcdc = Lcdc(value);
+                let lcd_is_on = self.lcdc.lcd_enabled();
 
+                if !lcd_is_on {
+                    self.stat_line = false;
+                }
+                if lcd_was_off == lcd_is_on {
+                    let state = if lcd_is_on { "on" } else { "off" };
+                    gb_log!(LogTarget::Ppu, "LCD {} at LY {}", state, self.ly);
+                }
                 // When LCD is turned on, reset PPU timing
                 if lcd_was_off && lcd_is_on {
                     self.ly = 0;
+                    self.ly_compare = Some(0);
+                    self.update_coincidence();
                     self.mode_cycles = 0;
+                    self.window_line_counter = 0;
+                    self.window_y_triggered = false;
                     self.set_mode(MODE_OAM_SCAN);
                 }
             }
-            0xFF41 => self.stat = (self.stat & 0x07) | (value & 0xF8),
+            0xFF41 => {
+                self.stat = Stat((self.stat.0 & 0x07) | (value & 0xF8));
+                // Enabling a source whose condition already holds raises the line
+                if self.lcdc.lcd_enabled() {
+                    self.set_stat_line(self.stat_sources());
+                }
+            }
             0xFF42 => self.scy = value,
             0xFF43 => self.scx = value,
             0xFF44 => {} // LY is read-only
-            0xFF45 => self.lyc = value,
+            0xFF45 => {
+                self.lyc = value;
+                // The comparator runs continuously while the LCD is on
+                if self.lcdc.lcd_enabled() {
+                    self.update_coincidence();
+                }
+            }
             0xFF47 => self.bgp = value,
             0xFF48 => self.obp0 = value,
             0xFF49 => self.obp1 = value,
@@ -569,6 +1188,19 @@ impl Ppu {
             0xFF4B => self.wx = value,
             _ => {}
         }
+
+        let new = self.read(address);
+        if let Some(changes) = self.register_changes.as_mut() {
+            if new != old {
+                changes.push(RegisterChange {
+                    address,
+                    old,
+                    new,
+                    ly: self.ly,
+                    mode: self.stat.mode(),
+                });
+            }
+        }
     }
 
     /// Check if a frame is ready
@@ -576,8 +1208,651 @@ impl Ppu {
         self.vblank_interrupt
     }
 
+    /// Video RAM (8000-9FFF)
+    pub fn vram(&self) -> &[u8] {
+        &self.vram
+    }
+
+    pub fn vram_mut(&mut self) -> &mut [u8] {
+        &mut self.vram
+    }
+
     /// Get the framebuffer data
     pub fn get_framebuffer(&self) -> &[u8] {
         &self.framebuffer
     }
 }
+
+impl Ppu {
+    // Logs, pixel info and the color scheme are host settings and stay as they are
+    pub(crate) fn save_state(&self, w: &mut StateWriter) {
+        w.bytes(&self.vram);
+        w.bytes(&self.oam);
+        for value in [
+            self.lcdc.0,
+            self.stat.0,
+            self.scy,
+            self.scx,
+            self.ly,
+            self.lyc,
+            self.bgp,
+            self.obp0,
+            self.obp1,
+            self.wy,
+            self.wx,
+        ] {
+            w.u8(value);
+        }
+        w.bytes(&self.framebuffer);
+        w.bytes(&self.bg_color_index);
+        w.u32(self.mode_cycles);
+        w.u32(self.drawing_cycles);
+        w.bool(self.vblank_interrupt);
+        w.bool(self.stat_interrupt);
+        w.bool(self.ly_compare.is_some());
+        w.u8(self.ly_compare.unwrap_or(0));
+        w.u8(self.window_line_counter);
+        w.bool(self.window_y_triggered);
+        w.bool(self.prev_lcd_enabled);
+        w.bool(self.object_priority == ObjectPriority::OamIndex);
+        w.bool(self.stat_line);
+        self.fifo.save_state(w);
+    }
+
+    pub(crate) fn load_state(&mut self, r: &mut StateReader) -> Result<(), String> {
+        r.fill(&mut self.vram)?;
+        r.fill(&mut self.oam)?;
+        self.lcdc = Lcdc(r.u8()?);
+        self.stat = Stat(r.u8()?);
+        for register in [
+            &mut self.scy,
+            &mut self.scx,
+            &mut self.ly,
+            &mut self.lyc,
+            &mut self.bgp,
+            &mut self.obp0,
+            &mut self.obp1,
+            &mut self.wy,
+            &mut self.wx,
+        ] {
+            *register = r.u8()?;
+        }
+        r.fill(&mut self.framebuffer)?;
+        r.fill(&mut self.bg_color_index)?;
+        self.mode_cycles = r.u32()?;
+        self.drawing_cycles = r.u32()?;
+        self.vblank_interrupt = r.bool()?;
+        self.stat_interrupt = r.bool()?;
+        let has_compare = r.bool()?;
+        let compare = r.u8()?;
+        self.ly_compare = has_compare.then_some(compare);
+        self.window_line_counter = r.u8()?;
+        self.window_y_triggered = r.bool()?;
+        self.prev_lcd_enabled = r.bool()?;
+        self.object_priority = if r.bool()? {
+            ObjectPriority::OamIndex
+        } else {
+            ObjectPriority::XCoordinate
+        };
+        self.stat_line = r.bool()?;
+        self.fifo.load_state(r)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Line 0: BG tile 0 has color 1 on its left half and color 0 on its right half;
+    // tile 1 is solid color 3 for sprites
+    fn ppu_with_tiles() -> Ppu {
+        let mut ppu = Ppu::new();
+        ppu.lcdc = Lcdc(0x93); // LCD, 0x8000 tile data, OBJ and BG on
+        ppu.bgp = 0xE4;
+        ppu.obp0 = 0xE4;
+        ppu.obp1 = 0x1B;
+        ppu.vram[0] = 0xF0;
+        ppu.vram[16] = 0xFF;
+        ppu.vram[17] = 0xFF;
+        ppu
+    }
+
+    fn set_sprite(ppu: &mut Ppu, index: usize, screen_x: u8, attr: u8) {
+        ppu.oam[index * 4..index * 4 + 4].copy_from_slice(&[16, screen_x + 8, 1, attr]);
+    }
+
+    // Window map at 0x9C00 uses tile 2, whose rows have colors 0,1,2,3,1,2,3,1;
+    // the BG map uses the blank tile 3
+    fn ppu_with_window() -> Ppu {
+        let mut ppu = Ppu::new();
+        ppu.lcdc = Lcdc(0xF1); // LCD, window map 0x9C00, window, 0x8000 tile data, BG
+        ppu.bgp = 0xE4;
+        ppu.wx = 7;
+        for (row, color) in [0u8, 1, 2, 3, 1, 2, 3, 1].iter().enumerate() {
+            ppu.vram[32 + row * 2] = if color & 1 != 0 { 0xFF } else { 0 };
+            ppu.vram[32 + row * 2 + 1] = if color & 2 != 0 { 0xFF } else { 0 };
+        }
+        ppu.vram[0x1800..0x1C00].fill(3);
+        ppu.vram[0x1C00..0x2000].fill(2);
+        ppu
+    }
+
+    // Color of the leftmost pixel of each of the first `lines` lines, rendered one by one
+    // with `before_line` run ahead of each
+    fn render_lines(ppu: &mut Ppu, lines: u8, mut before_line: impl FnMut(&mut Ppu)) -> Vec<u8> {
+        let palette = ppu.get_palette(ppu.bgp);
+        (0..lines)
+            .map(|ly| {
+                ppu.ly = ly;
+                before_line(ppu);
+                ppu.render_scanline();
+                let px = &ppu.framebuffer[ly as usize * 160 * 3..][..3];
+                palette
+                    .iter()
+                    .position(|c| *c == (px[0], px[1], px[2]))
+                    .unwrap() as u8
+            })
+            .collect()
+    }
+
+    fn pixel(ppu: &Ppu, x: usize) -> (u8, u8, u8) {
+        let p = &ppu.framebuffer[x * 3..x * 3 + 3];
+        (p[0], p[1], p[2])
+    }
+
+    #[test]
+    fn test_bg_over_obj_resolved_per_pixel() {
+        let mut ppu = ppu_with_tiles();
+        set_sprite(&mut ppu, 0, 0, 0x80);
+        ppu.render_scanline();
+
+        let bg = ppu.get_palette(ppu.bgp);
+        let obj = ppu.get_palette(ppu.obp0);
+        // BG color 1 hides the sprite, BG color 0 does not
+        for x in 0..4 {
+            assert_eq!(pixel(&ppu, x), bg[1], "x={}", x);
+        }
+        for x in 4..8 {
+            assert_eq!(pixel(&ppu, x), obj[3], "x={}", x);
+        }
+    }
+
+    #[test]
+    fn test_hidden_sprite_still_masks_lower_priority_sprites() {
+        let mut ppu = ppu_with_tiles();
+        // OAM 0 is further right, so OAM 1 (smaller X) wins where they overlap
+        set_sprite(&mut ppu, 0, 2, 0x10);
+        set_sprite(&mut ppu, 1, 0, 0x80);
+        ppu.render_scanline();
+
+        let bg = ppu.get_palette(ppu.bgp);
+        let obj0 = ppu.get_palette(ppu.obp0);
+        let obj1 = ppu.get_palette(ppu.obp1);
+        assert_eq!(pixel(&ppu, 2), bg[1]);
+        assert_eq!(pixel(&ppu, 3), bg[1]);
+        assert_eq!(pixel(&ppu, 4), obj0[3]);
+        assert_eq!(pixel(&ppu, 8), obj1[3]);
+    }
+
+    #[test]
+    fn test_object_priority_modes() {
+        let mut ppu = ppu_with_tiles();
+        set_sprite(&mut ppu, 0, 2, 0x10);
+        set_sprite(&mut ppu, 1, 0, 0x00);
+        let obj0 = ppu.get_palette(ppu.obp0);
+        let obj1 = ppu.get_palette(ppu.obp1);
+
+        // DMG: OAM 1 has the smaller X and wins the overlap
+        ppu.render_scanline();
+        assert_eq!(pixel(&ppu, 2), obj0[3]);
+        assert_eq!(pixel(&ppu, 8), obj1[3]);
+
+        // CGB mode: OAM 0 comes first in OAM and wins
+        ppu.set_object_priority(ObjectPriority::OamIndex);
+        ppu.render_scanline();
+        assert_eq!(pixel(&ppu, 1), obj0[3]);
+        assert_eq!(pixel(&ppu, 2), obj1[3]);
+
+        // A forced priority wins over OPRI until released
+        ppu.force_object_priority(Some(ObjectPriority::XCoordinate));
+        ppu.render_scanline();
+        assert_eq!(pixel(&ppu, 2), obj0[3]);
+        ppu.force_object_priority(None);
+        assert_eq!(ppu.object_priority(), ObjectPriority::OamIndex);
+        assert_eq!("CGB".parse(), Ok(ObjectPriority::OamIndex));
+        assert_eq!(ObjectPriority::XCoordinate.to_string(), "dmg");
+    }
+
+    #[test]
+    fn test_lcdc_and_stat_fields() {
+        let lcdc = Lcdc(0x93);
+        assert!(lcdc.lcd_enabled() && lcdc.unsigned_tile_data() && lcdc.obj_enabled());
+        assert!(!lcdc.window_enabled());
+        assert_eq!(lcdc.bg_tilemap(), 0x9800);
+        assert_eq!(Lcdc(0x0C).bg_tilemap(), 0x9C00);
+        assert_eq!(Lcdc(0x04).obj_size(), 16);
+
+        let mut stat = Stat(0x28); // Mode 0 and mode 2 interrupt sources
+        stat.set_mode(MODE_OAM_SCAN);
+        assert_eq!(stat.mode(), MODE_OAM_SCAN);
+        assert!(stat.mode_interrupt_enabled(MODE_HBLANK));
+        assert!(!stat.mode_interrupt_enabled(MODE_VBLANK));
+        assert!(stat.mode_interrupt_enabled(MODE_OAM_SCAN));
+        assert!(!stat.mode_interrupt_enabled(MODE_DRAWING));
+    }
+
+    #[test]
+    fn test_register_change_log() {
+        let mut ppu = Ppu::new();
+        ppu.write(0xFF43, 0x10); // Not logged while disabled
+
+        ppu.set_register_log_enabled(true);
+        ppu.write(0xFF43, 0x20);
+        ppu.write(0xFF43, 0x20); // Same value: no change
+        ppu.write(0xFF44, 0x55); // LY is read-only
+        assert_eq!(
+            ppu.take_register_changes(),
+            vec![RegisterChange {
+                address: 0xFF43,
+                old: 0x10,
+                new: 0x20,
+                ly: 0,
+                mode: MODE_OAM_SCAN,
+            }]
+        );
+        assert!(ppu.take_register_changes().is_empty());
+        assert_eq!(ppu.scroll(), (0x20, 0));
+    }
+
+    #[test]
+    fn test_pixel_info_records_source_of_each_pixel() {
+        let mut ppu = ppu_with_tiles();
+        assert_eq!(ppu.pixel_info_at(0, 0), None);
+
+        ppu.set_pixel_info_enabled(true);
+        set_sprite(&mut ppu, 0, 4, 0x10);
+        ppu.render_scanline();
+
+        let info = |x| ppu.pixel_info_at(x, 0).unwrap();
+        assert_eq!(
+            info(0),
+            PixelInfo {
+                layer: PixelLayer::Background,
+                palette: PixelPalette::Bgp,
+                color_index: 1,
+            }
+        );
+        assert_eq!(
+            info(5),
+            PixelInfo {
+                layer: PixelLayer::Sprite,
+                palette: PixelPalette::Obp1,
+                color_index: 3,
+            }
+        );
+        assert_eq!(info(12).color_index, 0);
+        assert_eq!(ppu.layer_view().map(|rgb| rgb.len()), Some(160 * 144 * 3));
+    }
+
+    #[test]
+    fn test_mode3_length_penalties() {
+        let mut ppu = ppu_with_tiles();
+        assert_eq!(ppu.mode3_length(), 172);
+
+        ppu.scx = 3;
+        assert_eq!(ppu.mode3_length(), 175);
+        ppu.scx = 0;
+
+        // Aligned with a tile: 5 dots waiting for the fetcher + 6 for the fetch
+        set_sprite(&mut ppu, 0, 0, 0);
+        assert_eq!(ppu.mode3_length(), 183);
+        // A second sprite over the same tile only pays for its own fetch
+        set_sprite(&mut ppu, 1, 4, 0);
+        assert_eq!(ppu.mode3_length(), 189);
+        // Late in a tile the fetcher wait is gone
+        set_sprite(&mut ppu, 2, 14, 0);
+        assert_eq!(ppu.mode3_length(), 195);
+        // Off the right edge: never fetched
+        ppu.oam[3 * 4..3 * 4 + 4].copy_from_slice(&[16, 168, 1, 0]);
+        assert_eq!(ppu.mode3_length(), 195);
+
+        ppu.lcdc = Lcdc(ppu.lcdc.0 & !0x02);
+        assert_eq!(ppu.mode3_length(), 172);
+    }
+
+    #[test]
+    fn test_fifo_outputs_last_pixel_on_last_mode3_dot() {
+        for (scx, wx, sprite_xs) in [
+            (0, 255, &[][..]),
+            (5, 255, &[0, 3, 3, 40, 159][..]),
+            (2, 7, &[12][..]),
+            (7, 90, &[85, 86, 150][..]),
+            (0, 3, &[0][..]),
+        ] {
+            let mut ppu = ppu_with_window();
+            ppu.lcdc = Lcdc(0xF3);
+            ppu.scx = scx;
+            ppu.wx = wx;
+            for (i, &x) in sprite_xs.iter().enumerate() {
+                set_sprite(&mut ppu, i, x, 0);
+            }
+            ppu.step(OAM_SCAN_CYCLES);
+            let length = ppu.drawing_cycles;
+            ppu.step(length - 1);
+            assert_eq!(ppu.fifo.x, 159, "scx={} wx={}", scx, wx);
+            ppu.step(1);
+            assert_eq!(ppu.fifo.x, 160);
+            assert_eq!(ppu.stat().mode(), MODE_HBLANK);
+        }
+    }
+
+    #[test]
+    fn test_palette_write_during_mode3_recolors_rest_of_line() {
+        let mut ppu = ppu_with_tiles();
+        ppu.step(OAM_SCAN_CYCLES);
+        // Pixel x is shifted out on mode 3 dot FIRST_PIXEL_DOT + x
+        ppu.step(FIRST_PIXEL_DOT + 80);
+        ppu.write(0xFF47, 0x1B);
+        ppu.step(DRAWING_CYCLES);
+        assert_eq!(ppu.stat().mode(), MODE_HBLANK);
+
+        // BG tile 0 repeats color 1 x4, color 0 x4
+        let before = ppu.get_palette(0xE4);
+        let after = ppu.get_palette(0x1B);
+        assert_eq!(pixel(&ppu, 75), before[1]);
+        assert_eq!(pixel(&ppu, 79), before[0]);
+        assert_eq!(pixel(&ppu, 80), after[1]);
+        assert_eq!(pixel(&ppu, 159), after[0]);
+    }
+
+    #[test]
+    fn test_scx_write_during_mode3_moves_later_tiles() {
+        // Tile 1 is solid color 3; the map is tile 0 except for column 11
+        let mut ppu = ppu_with_tiles();
+        ppu.vram[0x1800 + 11] = 1;
+        ppu.step(OAM_SCAN_CYCLES);
+        // Scroll one tile right just before the 11th fetch: it reads column 11, which
+        // shows up a tile earlier than it would have
+        ppu.step(FIRST_TILE_FETCH_DOT + 8 * 10);
+        ppu.write(0xFF43, 8);
+        ppu.step(DRAWING_CYCLES);
+
+        let bg = ppu.get_palette(ppu.bgp);
+        assert_eq!(pixel(&ppu, 72), bg[1]);
+        assert_eq!(pixel(&ppu, 79), bg[0]);
+        assert!((80..88).all(|x| pixel(&ppu, x) == bg[3]));
+        assert_eq!(pixel(&ppu, 88), bg[1]);
+    }
+
+    #[test]
+    fn test_hblank_shrinks_as_mode3_grows() {
+        let mut ppu = ppu_with_tiles();
+        ppu.set_mode(MODE_OAM_SCAN);
+        set_sprite(&mut ppu, 0, 0, 0);
+
+        ppu.step(OAM_SCAN_CYCLES);
+        assert_eq!(ppu.stat().mode(), MODE_DRAWING);
+        ppu.step(182);
+        assert_eq!(ppu.stat().mode(), MODE_DRAWING);
+        ppu.step(1);
+        assert_eq!(ppu.stat().mode(), MODE_HBLANK);
+        ppu.step(SCANLINE_CYCLES - OAM_SCAN_CYCLES - 183 - 1);
+        assert_eq!((ppu.stat().mode(), ppu.ly()), (MODE_HBLANK, 0));
+        ppu.step(1);
+        assert_eq!((ppu.stat().mode(), ppu.ly()), (MODE_OAM_SCAN, 1));
+    }
+
+    #[test]
+    fn test_lyc_compare_timing_around_line_153() {
+        let mut ppu = Ppu::new();
+        ppu.write(0xFF41, 0x40); // LYC=LY interrupt
+        ppu.write(0xFF45, 153);
+        while ppu.ly() != 153 {
+            ppu.step(4);
+        }
+        // The flag is cleared for the first dots of every line
+        assert!(!ppu.stat().coincidence());
+        ppu.stat_interrupt = false;
+        ppu.step(4);
+        // LY already reads 0, but is still compared as 153
+        assert_eq!(ppu.ly(), 0);
+        assert!(ppu.stat().coincidence() && ppu.stat_interrupt);
+
+        ppu.write(0xFF45, 0);
+        assert!(!ppu.stat().coincidence());
+        ppu.stat_interrupt = false;
+        ppu.step(4);
+        assert!(ppu.stat().coincidence() && ppu.stat_interrupt);
+
+        // Line 0 starts with the flag already set: no second interrupt
+        ppu.stat_interrupt = false;
+        ppu.step(SCANLINE_CYCLES - 8);
+        assert_eq!((ppu.stat().mode(), ppu.ly()), (MODE_OAM_SCAN, 0));
+        assert!(ppu.stat().coincidence() && !ppu.stat_interrupt);
+
+        ppu.write(0xFF45, 1);
+        while ppu.ly() != 1 {
+            ppu.step(4);
+        }
+        assert!(!ppu.stat().coincidence());
+        ppu.step(4);
+        assert!(ppu.stat().coincidence() && ppu.stat_interrupt);
+    }
+
+    #[test]
+    fn test_stat_sources_share_one_interrupt_line() {
+        let step_until = |ppu: &mut Ppu, ly: u8, mode: u8| {
+            while (ppu.ly(), ppu.stat().mode()) != (ly, mode) {
+                ppu.step(4);
+            }
+        };
+
+        // Mode 2 alone interrupts at every line start, and on entering VBlank
+        let mut ppu = Ppu::new();
+        ppu.write(0xFF41, 0x20);
+        step_until(&mut ppu, 1, MODE_HBLANK);
+        ppu.stat_interrupt = false;
+        step_until(&mut ppu, 2, MODE_OAM_SCAN);
+        assert!(ppu.stat_interrupt);
+        step_until(&mut ppu, 143, MODE_HBLANK);
+        ppu.stat_interrupt = false;
+        step_until(&mut ppu, 144, MODE_VBLANK);
+        assert!(ppu.stat_interrupt);
+
+        // With HBlank enabled too, the line is still high when mode 2 starts
+        let mut ppu = Ppu::new();
+        ppu.write(0xFF41, 0x28);
+        step_until(&mut ppu, 1, MODE_HBLANK);
+        assert!(ppu.stat_interrupt);
+        ppu.stat_interrupt = false;
+        step_until(&mut ppu, 2, MODE_OAM_SCAN);
+        assert!(!ppu.stat_interrupt);
+        step_until(&mut ppu, 2, MODE_DRAWING);
+        step_until(&mut ppu, 2, MODE_HBLANK);
+        assert!(ppu.stat_interrupt);
+
+        // Enabling a source whose condition holds raises the line at once
+        ppu.write(0xFF41, 0x00);
+        ppu.stat_interrupt = false;
+        ppu.write(0xFF41, 0x08);
+        assert!(ppu.stat_interrupt);
+    }
+
+    #[test]
+    fn test_window_line_counter_survives_window_toggle() {
+        let mut ppu = ppu_with_window();
+        let colors = render_lines(&mut ppu, 12, |ppu| {
+            let window_on = !(4..8).contains(&ppu.ly);
+            ppu.lcdc = Lcdc(if window_on { 0xF1 } else { 0xD1 });
+        });
+        // Lines 8-11 continue with window rows 4-7, not rows 0-3 (LY - WY)
+        assert_eq!(colors, [0, 1, 2, 3, 0, 0, 0, 0, 1, 2, 3, 1]);
+    }
+
+    #[test]
+    fn test_window_waits_for_wy_match() {
+        let mut ppu = ppu_with_window();
+        ppu.wy = 5;
+        // WY moved above LY before it was reached: the window stays hidden this frame
+        let colors = render_lines(&mut ppu, 8, |ppu| {
+            if ppu.ly == 3 {
+                ppu.wy = 2;
+            }
+        });
+        assert_eq!(colors, [0; 8]);
+
+        // Turning the LCD off and on mid-frame starts a new frame with a fresh counter
+        ppu.wy = 0;
+        assert_eq!(render_lines(&mut ppu, 4, |_| {}), [0, 1, 2, 3]);
+        ppu.write(0xFF40, 0x71);
+        ppu.write(0xFF40, 0xF1);
+        assert_eq!(render_lines(&mut ppu, 4, |_| {}), [0, 1, 2, 3]);
+    }
+
+    #[test]
+    fn test_dirty_rows_track_changed_lines() {
+        let mut ppu = ppu_with_tiles();
+        assert_eq!(ppu.take_dirty_rows(), Some(0..144)); // Nothing presented yet
+        assert_eq!(ppu.take_dirty_rows(), None);
+
+        ppu.ly = 10;
+        ppu.render_scanline();
+        ppu.ly = 20;
+        ppu.render_scanline();
+        assert_eq!(ppu.take_dirty_rows(), Some(10..21));
+
+        // Rendering the same pixels again changes nothing
+        ppu.render_scanline();
+        assert_eq!(ppu.take_dirty_rows(), None);
+
+        ppu.mark_all_rows_dirty();
+        assert_eq!(ppu.take_dirty_rows(), Some(0..144));
+    }
+
+    #[test]
+    fn test_color_scheme_recolors_framebuffer() {
+        let mut ppu = ppu_with_tiles();
+        ppu.ly = 0;
+        ppu.render_scanline();
+        let shade_of = |ppu: &Ppu, scheme: ColorScheme| {
+            let pixel = &ppu.framebuffer[0..3];
+            scheme
+                .shades
+                .iter()
+                .position(|&(r, g, b)| pixel == [r, g, b])
+        };
+        let shade = shade_of(&ppu, ColorScheme::DMG_GREEN);
+        assert!(shade.is_some());
+        ppu.take_dirty_rows();
+
+        // The frame already on screen switches colors immediately
+        ppu.set_color_scheme(ColorScheme::GRAYSCALE);
+        assert_eq!(shade_of(&ppu, ColorScheme::GRAYSCALE), shade);
+        assert_eq!(ppu.take_dirty_rows(), Some(0..144));
+
+        // And new lines are drawn with the new colors
+        ppu.render_scanline();
+        assert_eq!(ppu.take_dirty_rows(), None);
+    }
+    #[test]
+    fn test_lcdc_tile_data_switch_mid_line_golden_frame() {
+        // Tile 0 is black at 0x8000 and white at 0x9000; the map is all tile 0
+        let mut ppu = Ppu::new();
+        ppu.lcdc = Lcdc(0x91);
+        ppu.bgp = 0xE4;
+        ppu.scx = 3;
+        ppu.vram[0..16].fill(0xFF);
+
+        // On line N, switch to 0x8800 tile data as tile N % 21 is fetched, back in HBlank
+        while !ppu.vblank_interrupt {
+            ppu.step(1);
+            let switch_dot = FIRST_TILE_FETCH_DOT + 8 * (ppu.ly as u32 % 21);
+            if ppu.stat().mode() == MODE_DRAWING && ppu.mode_cycles == switch_dot {
+                ppu.write(0xFF40, 0x81);
+            } else if ppu.stat().mode() == MODE_HBLANK && ppu.lcdc.0 != 0x91 {
+                ppu.write(0xFF40, 0x91);
+            }
+        }
+
+        let palette = ppu.get_palette(ppu.bgp);
+        let frame: Vec<String> = ppu
+            .framebuffer
+            .chunks(160 * 3)
+            .map(|line| {
+                line.chunks(3)
+                    .map(|p| {
+                        let color = palette.iter().position(|c| *c == (p[0], p[1], p[2]));
+                        match color {
+                            Some(0) => '.',
+                            Some(3) => '#',
+                            _ => '?',
+                        }
+                    })
+                    .collect()
+            })
+            .collect();
+        // Tiles fetched before the switch are black; SCX 3 shifts them 3 pixels left
+        let golden: Vec<String> = (0..144usize)
+            .map(|ly| {
+                let black = (8 * (ly % 21)).saturating_sub(3).min(160);
+                "#".repeat(black) + &".".repeat(160 - black)
+            })
+            .collect();
+        assert_eq!(frame, golden);
+    }
+
+    #[test]
+    fn test_scanline_renderer_draws_lines_at_end_of_mode_3() {
+        // As above, but without the FIFO running along the switch applies to whole lines
+        let mut ppu = Ppu::new();
+        ppu.pixel_fifo = false;
+        ppu.bgp = 0xE4;
+        ppu.vram[0..16].fill(0xFF);
+        while !ppu.vblank_interrupt {
+            ppu.step(1);
+            // Halfway through the line, which the FIFO would draw half black
+            if ppu.stat().mode() == MODE_DRAWING && ppu.mode_cycles == FIRST_TILE_FETCH_DOT + 80 {
+                ppu.write(0xFF40, 0x81);
+            } else if ppu.stat().mode() == MODE_HBLANK && ppu.lcdc.0 != 0x91 {
+                ppu.write(0xFF40, 0x91);
+            }
+        }
+        let white = ppu.get_palette(ppu.bgp)[0];
+        assert!(ppu
+            .framebuffer
+            .chunks(3)
+            .all(|p| (p[0], p[1], p[2]) == white));
+    }
+
+    #[test]
+    fn test_oam_corruption() {
+        let mut ppu = Ppu::new();
+        for (i, byte) in ppu.oam.iter_mut().enumerate() {
+            *byte = i as u8;
+        }
+        let original = ppu.oam;
+        let word = |oam: &[u8; 0xA0], at: usize| u16::from_le_bytes([oam[at], oam[at + 1]]);
+
+        // Only while the PPU reads OAM, from the second row on
+        ppu.stat.set_mode(MODE_HBLANK);
+        ppu.corrupt_oam(true);
+        ppu.stat.set_mode(MODE_OAM_SCAN);
+        ppu.mode_cycles = 2;
+        ppu.corrupt_oam(true);
+        assert_eq!(ppu.oam, original);
+
+        // Row 2 (bytes 16-23) is read on the third M-cycle
+        ppu.mode_cycles = 9;
+        ppu.corrupt_oam(true);
+        let (a, b, c) = (0x1110, 0x0908, 0x0D0C);
+        assert_eq!(word(&ppu.oam, 16), ((a ^ c) & (b ^ c)) ^ c);
+        assert_eq!(ppu.oam[18..24], original[10..16]);
+        assert_eq!(ppu.oam[..16], original[..16]);
+        assert_eq!(ppu.oam[24..], original[24..]);
+
+        ppu.oam = original;
+        ppu.corrupt_oam(false);
+        assert_eq!(word(&ppu.oam, 16), b | (a & c));
+        assert_eq!(ppu.oam[18..24], original[10..16]);
+    }
+}
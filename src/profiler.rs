@@ -0,0 +1,282 @@
+// Host-side frame time profiling
+// The frontend measures how long each part of a host frame took and records it here.
+// The rolling history lets "stutter" reports tell apart an emulator that can't keep up
+// (emulation/render over budget) from display pacing problems (present/sleep overruns).
+
+use std::collections::VecDeque;
+use std::fmt::Write;
+use std::time::Duration;
+
+// Histogram bucket width and count; the last bucket collects everything slower
+const BUCKET_WIDTH_MS: u64 = 2;
+const BUCKET_COUNT: usize = 20;
+
+// A frame counts as a stutter when it takes this much longer than the target
+const STUTTER_FACTOR: f64 = 1.5;
+
+/// Host time spent in each stage of one frame
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct FrameTimings {
+    pub emulation: Duration,
+    pub render: Duration,
+    pub present: Duration,
+    pub sleep: Duration,
+}
+
+impl FrameTimings {
+    pub fn total(&self) -> Duration {
+        self.emulation + self.render + self.present + self.sleep
+    }
+
+    /// Time the frame spent doing work, i.e. excluding the pacing sleep and present
+    pub fn busy(&self) -> Duration {
+        self.emulation + self.render
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FrameStage {
+    Emulation,
+    Render,
+    Present,
+    Sleep,
+    Total,
+}
+
+impl FrameStage {
+    pub const ALL: [FrameStage; 5] = [
+        FrameStage::Emulation,
+        FrameStage::Render,
+        FrameStage::Present,
+        FrameStage::Sleep,
+        FrameStage::Total,
+    ];
+
+    fn of(&self, timings: &FrameTimings) -> Duration {
+        match self {
+            FrameStage::Emulation => timings.emulation,
+            FrameStage::Render => timings.render,
+            FrameStage::Present => timings.present,
+            FrameStage::Sleep => timings.sleep,
+            FrameStage::Total => timings.total(),
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            FrameStage::Emulation => "emulation",
+            FrameStage::Render => "render",
+            FrameStage::Present => "present",
+            FrameStage::Sleep => "sleep",
+            FrameStage::Total => "total",
+        }
+    }
+}
+
+/// Rolling window of the most recent frame timings
+pub struct FrameProfiler {
+    history: VecDeque<FrameTimings>,
+    capacity: usize,
+    target: Duration,
+}
+
+impl FrameProfiler {
+    pub fn new(capacity: usize, target: Duration) -> Self {
+        Self {
+            history: VecDeque::with_capacity(capacity),
+            capacity,
+            target,
+        }
+    }
+
+    pub fn record(&mut self, timings: FrameTimings) {
+        if self.history.len() == self.capacity {
+            self.history.pop_front();
+        }
+        self.history.push_back(timings);
+    }
+
+    pub fn len(&self) -> usize {
+        self.history.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.history.is_empty()
+    }
+
+    /// Frame count per total-time bucket (BUCKET_WIDTH_MS wide, last bucket is open-ended)
+    pub fn histogram(&self) -> [u32; BUCKET_COUNT] {
+        let mut buckets = [0u32; BUCKET_COUNT];
+        for timings in &self.history {
+            let bucket = (timings.total().as_millis() as u64 / BUCKET_WIDTH_MS) as usize;
+            buckets[bucket.min(BUCKET_COUNT - 1)] += 1;
+        }
+        buckets
+    }
+
+    /// The `p`-th percentile (0.0..=1.0) of a stage over the window
+    pub fn percentile(&self, stage: FrameStage, p: f64) -> Duration {
+        let mut samples: Vec<Duration> = self.history.iter().map(|t| stage.of(t)).collect();
+        if samples.is_empty() {
+            return Duration::ZERO;
+        }
+        samples.sort();
+        let index = ((samples.len() - 1) as f64 * p.clamp(0.0, 1.0)).round() as usize;
+        samples[index]
+    }
+
+    pub fn average(&self, stage: FrameStage) -> Duration {
+        if self.history.is_empty() {
+            return Duration::ZERO;
+        }
+        let sum: Duration = self.history.iter().map(|t| stage.of(t)).sum();
+        sum / self.history.len() as u32
+    }
+
+    fn stutter_threshold(&self) -> Duration {
+        self.target.mul_f64(STUTTER_FACTOR)
+    }
+
+    /// Frames that overran the target by more than STUTTER_FACTOR
+    pub fn stutter_count(&self) -> usize {
+        let threshold = self.stutter_threshold();
+        self.history
+            .iter()
+            .filter(|t| t.total() > threshold)
+            .count()
+    }
+
+    /// Stutters caused by the emulator itself (emulation + render alone blew the budget)
+    pub fn emulation_stutter_count(&self) -> usize {
+        let threshold = self.stutter_threshold();
+        self.history
+            .iter()
+            .filter(|t| t.total() > threshold && t.busy() > self.target)
+            .count()
+    }
+
+    /// One-line summary suitable for a window title or OSD
+    pub fn summary(&self) -> String {
+        format!(
+            "p99 {:.1} ms, {} stutters",
+            self.percentile(FrameStage::Total, 0.99).as_secs_f64() * 1000.0,
+            self.stutter_count()
+        )
+    }
+
+    /// Multi-line report to attach to stutter bug reports
+    pub fn report(&self) -> String {
+        let mut out = String::new();
+        let ms = |d: Duration| d.as_secs_f64() * 1000.0;
+
+        let _ = writeln!(
+            out,
+            "Frame timings over the last {} frames (target {:.2} ms)",
+            self.history.len(),
+            ms(self.target)
+        );
+        let _ = writeln!(
+            out,
+            "{:<10} {:>8} {:>8} {:>8} {:>8}",
+            "stage", "avg", "p50", "p99", "max"
+        );
+        for stage in FrameStage::ALL {
+            let _ = writeln!(
+                out,
+                "{:<10} {:>8.2} {:>8.2} {:>8.2} {:>8.2}",
+                stage.name(),
+                ms(self.average(stage)),
+                ms(self.percentile(stage, 0.5)),
+                ms(self.percentile(stage, 0.99)),
+                ms(self.percentile(stage, 1.0))
+            );
+        }
+
+        let stutters = self.stutter_count();
+        let emulation_stutters = self.emulation_stutter_count();
+        let _ = writeln!(
+            out,
+            "stutters: {} ({} emulator too slow, {} display pacing)",
+            stutters,
+            emulation_stutters,
+            stutters - emulation_stutters
+        );
+
+        let _ = writeln!(out, "histogram (total frame time):");
+        for (i, count) in self.histogram().iter().enumerate() {
+            if *count == 0 {
+                continue;
+            }
+            let low = i as u64 * BUCKET_WIDTH_MS;
+            let label = if i == BUCKET_COUNT - 1 {
+                format!("{:>3}+ ms", low)
+            } else {
+                format!("{:>3}-{:<3} ms", low, low + BUCKET_WIDTH_MS)
+            };
+            let _ = writeln!(out, "  {:<10} {:>6}", label, count);
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frame(emulation_ms: u64, present_ms: u64, sleep_ms: u64) -> FrameTimings {
+        FrameTimings {
+            emulation: Duration::from_millis(emulation_ms),
+            render: Duration::ZERO,
+            present: Duration::from_millis(present_ms),
+            sleep: Duration::from_millis(sleep_ms),
+        }
+    }
+
+    #[test]
+    fn test_rolling_window_drops_oldest() {
+        let mut profiler = FrameProfiler::new(2, Duration::from_millis(16));
+        profiler.record(frame(30, 0, 0));
+        profiler.record(frame(5, 1, 10));
+        profiler.record(frame(5, 1, 10));
+        assert_eq!(profiler.len(), 2);
+        assert_eq!(profiler.stutter_count(), 0);
+    }
+
+    #[test]
+    fn test_histogram_buckets() {
+        let mut profiler = FrameProfiler::new(10, Duration::from_millis(16));
+        profiler.record(frame(5, 1, 10)); // 16 ms
+        profiler.record(frame(5, 1, 11)); // 17 ms
+        profiler.record(frame(100, 0, 0)); // open-ended bucket
+        let histogram = profiler.histogram();
+        assert_eq!(histogram[8], 2);
+        assert_eq!(histogram[BUCKET_COUNT - 1], 1);
+    }
+
+    #[test]
+    fn test_stutter_classification() {
+        let mut profiler = FrameProfiler::new(10, Duration::from_millis(16));
+        profiler.record(frame(5, 1, 10));
+        profiler.record(frame(30, 1, 0)); // emulator too slow
+        profiler.record(frame(5, 30, 0)); // present blocked
+        assert_eq!(profiler.stutter_count(), 2);
+        assert_eq!(profiler.emulation_stutter_count(), 1);
+        assert!(profiler.report().contains("1 emulator too slow, 1 display pacing"));
+    }
+
+    #[test]
+    fn test_percentiles() {
+        let mut profiler = FrameProfiler::new(100, Duration::from_millis(16));
+        for ms in 1..=100 {
+            profiler.record(frame(ms, 0, 0));
+        }
+        assert_eq!(
+            profiler.percentile(FrameStage::Emulation, 0.5),
+            Duration::from_millis(51)
+        );
+        assert_eq!(
+            profiler.percentile(FrameStage::Emulation, 1.0),
+            Duration::from_millis(100)
+        );
+    }
+}
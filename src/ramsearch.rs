@@ -0,0 +1,224 @@
+// RAM search (cheat finder)
+// Starts with every byte of WRAM, cartridge RAM and HRAM as a candidate, then narrows the
+// set with comparisons against a value or against the previous search, frame after frame.
+
+use std::fmt;
+use std::ops::RangeInclusive;
+use std::str::FromStr;
+
+use crate::i18n::Catalog;
+use crate::memory::Memory;
+
+/// Memory searched: cartridge RAM, WRAM and HRAM
+pub const SEARCH_REGIONS: [RangeInclusive<u16>; 3] =
+    [0xA000..=0xBFFF, 0xC000..=0xDFFF, 0xFF80..=0xFFFE];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SearchCondition {
+    Equal(u8),
+    NotEqual(u8),
+    Greater(u8),
+    Less(u8),
+    /// Value went up since the last search
+    Increased,
+    /// Value went down since the last search
+    Decreased,
+    Changed,
+    Unchanged,
+}
+
+impl SearchCondition {
+    fn matches(&self, previous: u8, value: u8) -> bool {
+        match *self {
+            SearchCondition::Equal(n) => value == n,
+            SearchCondition::NotEqual(n) => value != n,
+            SearchCondition::Greater(n) => value > n,
+            SearchCondition::Less(n) => value < n,
+            SearchCondition::Increased => value > previous,
+            SearchCondition::Decreased => value < previous,
+            SearchCondition::Changed => value != previous,
+            SearchCondition::Unchanged => value == previous,
+        }
+    }
+}
+
+fn parse_value(value: Option<&str>) -> Result<u8, String> {
+    let value = value.ok_or("missing value")?;
+    let parsed = match value.strip_prefix("0x").or_else(|| value.strip_prefix('$')) {
+        Some(hex) => u8::from_str_radix(hex, 16),
+        None => value.parse(),
+    };
+    parsed.map_err(|_| format!("invalid value '{}'", value))
+}
+
+/// Parses debugger syntax: `eq N`, `ne N`, `gt N`, `lt N`, `inc`, `dec`, `changed`, `unchanged`.
+/// N is decimal, or hex with a 0x/$ prefix.
+impl FromStr for SearchCondition {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut words = s.split_whitespace();
+        let op = words.next().ok_or("empty search condition")?;
+        let condition = match op {
+            "eq" | "=" => SearchCondition::Equal(parse_value(words.next())?),
+            "ne" | "!=" => SearchCondition::NotEqual(parse_value(words.next())?),
+            "gt" | ">" => SearchCondition::Greater(parse_value(words.next())?),
+            "lt" | "<" => SearchCondition::Less(parse_value(words.next())?),
+            "inc" => SearchCondition::Increased,
+            "dec" => SearchCondition::Decreased,
+            "changed" => SearchCondition::Changed,
+            "unchanged" => SearchCondition::Unchanged,
+            other => return Err(format!("unknown search condition '{}'", other)),
+        };
+        match words.next() {
+            Some(extra) => Err(format!("unexpected '{}'", extra)),
+            None => Ok(condition),
+        }
+    }
+}
+
+/// Address still matching every search so far
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Candidate {
+    pub address: u16,
+    /// Value at the last search
+    pub value: u8,
+}
+
+pub struct RamSearch {
+    candidates: Vec<Candidate>,
+    searches: u32,
+}
+
+impl RamSearch {
+    /// Start a search with every searchable byte as a candidate ("unknown initial value")
+    pub fn new(mem: &Memory) -> Self {
+        let candidates = SEARCH_REGIONS
+            .iter()
+            .flat_map(|region| region.clone())
+            .map(|address| Candidate {
+                address,
+                value: mem.read_8(address),
+            })
+            .collect();
+        Self {
+            candidates,
+            searches: 0,
+        }
+    }
+
+    /// Keep only candidates matching `condition`, then remember the current values
+    /// for the next relative search. Returns the number of candidates left.
+    pub fn filter(&mut self, mem: &Memory, condition: SearchCondition) -> usize {
+        self.candidates.retain_mut(|candidate| {
+            let value = mem.read_8(candidate.address);
+            let keep = condition.matches(candidate.value, value);
+            candidate.value = value;
+            keep
+        });
+        self.searches += 1;
+        self.candidates.len()
+    }
+
+    pub fn candidates(&self) -> &[Candidate] {
+        &self.candidates
+    }
+
+    pub fn len(&self) -> usize {
+        self.candidates.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.candidates.is_empty()
+    }
+
+    /// Searches applied since the search was started
+    pub fn searches(&self) -> u32 {
+        self.searches
+    }
+}
+
+/// Candidate list for the debugger, truncated to `limit` entries
+pub struct CandidateList<'a> {
+    pub search: &'a RamSearch,
+    pub limit: usize,
+}
+
+impl CandidateList<'_> {
+    /// The count, then one candidate per line, in `catalog`'s language
+    pub fn describe(&self, catalog: &Catalog) -> String {
+        let (len, searches) = (self.search.len(), self.search.searches());
+        let mut text = catalog.format("debug-candidates", &[&len, &searches]) + "\n";
+        for candidate in self.search.candidates().iter().take(self.limit) {
+            text += &format!(
+                "  {:04X}: {:02X} ({})\n",
+                candidate.address, candidate.value, candidate.value
+            );
+        }
+        if len > self.limit {
+            text += &catalog.format("debug-more-candidates", &[&(len - self.limit)]);
+            text.push('\n');
+        }
+        text
+    }
+}
+
+impl fmt::Display for CandidateList<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.describe(&Catalog::default()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_narrowing_finds_variable() {
+        let mut mem = Memory::new(vec![0; 0x8000]);
+        let lives = 0xC123;
+        mem.write_8(lives, 3);
+        mem.write_8(0xC200, 3);
+
+        let mut search = RamSearch::new(&mem);
+        assert_eq!(search.len(), 0x2000 + 0x2000 + 0x7F);
+
+        assert_eq!(search.filter(&mem, SearchCondition::Equal(3)), 2);
+
+        // Lose a life; the other 3 stays put
+        mem.write_8(lives, 2);
+        search.filter(&mem, SearchCondition::Decreased);
+        assert_eq!(
+            search.candidates(),
+            &[Candidate {
+                address: lives,
+                value: 2
+            }]
+        );
+    }
+
+    #[test]
+    fn test_unknown_initial_value_search() {
+        let mut mem = Memory::new(vec![0; 0x8000]);
+        let mut search = RamSearch::new(&mem);
+        mem.write_8(0xFF90, 0x10);
+        mem.write_8(0xD000, 0x80);
+        search.filter(&mem, SearchCondition::Changed);
+        mem.write_8(0xD000, 0x81);
+        search.filter(&mem, SearchCondition::Increased);
+
+        let addresses: Vec<u16> = search.candidates().iter().map(|c| c.address).collect();
+        assert_eq!(addresses, vec![0xD000]);
+    }
+
+    #[test]
+    fn test_parse_conditions() {
+        assert_eq!("eq 0x1F".parse(), Ok(SearchCondition::Equal(0x1F)));
+        assert_eq!("lt $10".parse(), Ok(SearchCondition::Less(0x10)));
+        assert_eq!("gt 200".parse(), Ok(SearchCondition::Greater(200)));
+        assert_eq!("inc".parse(), Ok(SearchCondition::Increased));
+        assert!("eq".parse::<SearchCondition>().is_err());
+        assert!("eq 300".parse::<SearchCondition>().is_err());
+        assert!("inc 1".parse::<SearchCondition>().is_err());
+    }
+}
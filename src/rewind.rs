@@ -0,0 +1,170 @@
+// Rewind history
+// Every `interval` frames the runner pushes a full save state into the RewindBuffer. Only
+// the newest state is kept whole: each older one is stored as its XOR with the state after
+// it (see delta.rs), where everything that didn't change is zero and takes next to no
+// room. That keeps minutes of history in a few megabytes. Stepping back restores the
+// newest state and undoes one delta to reach the next one; past the byte budget the
+// oldest deltas are dropped. `gbemu_rust rewind-bench ROM` measures the cost per second.
+
+use std::collections::VecDeque;
+
+use crate::cpu::Cpu;
+use crate::delta;
+use crate::memory::Memory;
+use crate::savestate;
+
+/// Frames between snapshots when none is given
+pub const DEFAULT_INTERVAL: u32 = 4;
+/// Bytes of compressed history kept when no budget is given
+pub const DEFAULT_BUDGET: usize = 32 << 20;
+
+/// Bounded history of machine states, newest last
+#[derive(Debug, Clone)]
+pub struct RewindBuffer {
+    interval: u32,
+    budget: usize,
+    // Frames run since the last snapshot
+    frames: u32,
+    newest: Option<Vec<u8>>,
+    // Delta from each state to the one before it, oldest first
+    deltas: VecDeque<Vec<u8>>,
+    delta_bytes: usize,
+}
+
+impl RewindBuffer {
+    /// Snapshot every `interval` frames, keeping up to `budget` bytes of history
+    pub fn new(interval: u32, budget: usize) -> Self {
+        Self {
+            interval: interval.max(1),
+            budget,
+            frames: 0,
+            newest: None,
+            deltas: VecDeque::new(),
+            delta_bytes: 0,
+        }
+    }
+
+    pub fn interval(&self) -> u32 {
+        self.interval
+    }
+
+    /// Number of states that can be stepped back to
+    pub fn len(&self) -> usize {
+        self.deltas.len() + self.newest.is_some() as usize
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.newest.is_none()
+    }
+
+    /// Frames between the oldest and the newest state
+    pub fn history_frames(&self) -> u64 {
+        self.deltas.len() as u64 * self.interval as u64
+    }
+
+    /// Bytes held by the history, the newest state included
+    pub fn memory_used(&self) -> usize {
+        self.delta_bytes + self.newest.as_ref().map_or(0, Vec::len)
+    }
+
+    pub fn clear(&mut self) {
+        self.frames = 0;
+        self.newest = None;
+        self.deltas.clear();
+        self.delta_bytes = 0;
+    }
+
+    /// Count a frame run, taking a snapshot when `interval` frames have passed
+    pub fn record_frame(&mut self, cpu: &Cpu, mem: &Memory) {
+        self.frames += 1;
+        if self.frames >= self.interval {
+            self.frames = 0;
+            self.push(savestate::save_state(cpu, mem));
+        }
+    }
+
+    /// Add `state` as the newest one
+    pub fn push(&mut self, state: Vec<u8>) {
+        if let Some(previous) = self.newest.take() {
+            let delta = delta::encode(&state, &previous);
+            self.delta_bytes += delta.len();
+            self.deltas.push_back(delta);
+        }
+        self.newest = Some(state);
+        while self.delta_bytes > self.budget {
+            match self.deltas.pop_front() {
+                Some(oldest) => self.delta_bytes -= oldest.len(),
+                None => break,
+            }
+        }
+    }
+
+    /// Restore the newest state and drop it, so the next call goes further back.
+    /// Returns false, leaving the machine alone, once the history is used up.
+    pub fn step_back(&mut self, cpu: &mut Cpu, mem: &mut Memory) -> Result<bool, String> {
+        let Some(state) = self.newest.take() else {
+            return Ok(false);
+        };
+        let result = savestate::load_state(cpu, mem, &state);
+        if let Some(delta) = self.deltas.pop_back() {
+            self.delta_bytes -= delta.len();
+            match delta::decode(&state, &delta) {
+                Ok(previous) => self.newest = Some(previous),
+                Err(err) => {
+                    self.clear();
+                    return Err(err);
+                }
+            }
+        }
+        self.frames = 0;
+        result.map(|()| true)
+    }
+}
+
+impl Default for RewindBuffer {
+    fn default() -> Self {
+        Self::new(DEFAULT_INTERVAL, DEFAULT_BUDGET)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::Model;
+
+    #[test]
+    fn test_step_back_through_history() {
+        let mut mem = Memory::new(vec![0; 0x8000]);
+        mem.skip_boot(Model::Dmg);
+        let mut cpu = Cpu::new();
+        cpu.skip_boot(Model::Dmg, 0);
+
+        let mut rewind = RewindBuffer::new(2, DEFAULT_BUDGET);
+        for value in 0..6u8 {
+            mem.write_8(0xC000, value);
+            rewind.record_frame(&cpu, &mem);
+        }
+        // Snapshots were taken after the frames that wrote 1, 3 and 5
+        assert_eq!(rewind.len(), 3);
+        assert_eq!(rewind.history_frames(), 4);
+        assert!(rewind.memory_used() < 2 * savestate::save_state(&cpu, &mem).len());
+
+        for expected in [5, 3, 1] {
+            mem.write_8(0xC000, 0xFF);
+            assert_eq!(rewind.step_back(&mut cpu, &mut mem), Ok(true));
+            assert_eq!(mem.read_8(0xC000), expected);
+        }
+        assert_eq!(rewind.step_back(&mut cpu, &mut mem), Ok(false));
+        assert!(rewind.is_empty());
+
+        // Past the budget the oldest states go
+        let mut rewind = RewindBuffer::new(1, 0);
+        for value in 0..3u8 {
+            mem.write_8(0xC000, value);
+            rewind.record_frame(&cpu, &mem);
+        }
+        assert_eq!(rewind.len(), 1);
+        assert_eq!(rewind.step_back(&mut cpu, &mut mem), Ok(true));
+        assert_eq!(mem.read_8(0xC000), 2);
+    }
+}
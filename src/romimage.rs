@@ -0,0 +1,256 @@
+// Shared ROM images
+// A RomImage is the cartridge contents, reference-counted so that every Memory running
+// a game (keyframes, parallel compatibility checks, linked instances) shares one copy, and
+// opening a file that is already open returns the same image.
+//
+// `open` reads the file. For checking large ROM sets, `map` memory-maps it read-only on
+// Unix instead: opening costs no read and pages load as the game touches them. A mapping
+// sees later changes to the file, and reading a page cut off by truncation raises SIGBUS,
+// so `map` is unsafe and only used where the files are known to stay put (verify-dir).
+// Elsewhere, and for files too small to map as they are (under 32 KiB), it reads too.
+
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::ops::Deref;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, Weak};
+
+/// Smallest image: the two 16 KiB banks mapped at 0x0000-0x7FFF
+pub const MIN_ROM_SIZE: usize = 0x8000;
+
+enum RomData {
+    Owned(Box<[u8]>),
+    #[cfg(unix)]
+    Mapped(mmap::Mapping),
+}
+
+impl RomData {
+    fn bytes(&self) -> &[u8] {
+        match self {
+            RomData::Owned(bytes) => bytes,
+            #[cfg(unix)]
+            RomData::Mapped(mapping) => mapping.bytes(),
+        }
+    }
+}
+
+// Open images by canonical path and whether they were mapped, so instances of one game
+// share their bytes, and a mapping is never handed out by `open`
+type OpenImages = HashMap<(PathBuf, bool), Weak<RomData>>;
+static OPEN_IMAGES: Mutex<Option<OpenImages>> = Mutex::new(None);
+
+/// A ROM image, at least `MIN_ROM_SIZE` bytes. Clones share the bytes.
+#[derive(Clone)]
+pub struct RomImage(Arc<RomData>);
+
+impl RomImage {
+    /// Image of a ROM file, read into memory and shared with any image of the same file
+    /// read earlier that is still open
+    pub fn open(path: &Path) -> io::Result<Self> {
+        Self::shared(path, false, |path| Ok(Self::from(fs::read(path)?)))
+    }
+
+    /// Image of a ROM file, memory-mapped where the platform allows and shared with any
+    /// mapping of the same file that is still open
+    ///
+    /// # Safety
+    ///
+    /// The file must not be written or truncated while the image or any clone of it is
+    /// alive: the bytes would change under the emulator, and reading past a truncation
+    /// kills the process with SIGBUS.
+    pub unsafe fn map(path: &Path) -> io::Result<Self> {
+        Self::shared(path, true, Self::load_mapped)
+    }
+
+    fn shared(
+        path: &Path,
+        mapped: bool,
+        load: impl FnOnce(&Path) -> io::Result<Self>,
+    ) -> io::Result<Self> {
+        let key = (fs::canonicalize(path)?, mapped);
+        let mut open_images = OPEN_IMAGES.lock().unwrap_or_else(|err| err.into_inner());
+        let open_images = open_images.get_or_insert_with(HashMap::new);
+        if let Some(data) = open_images.get(&key).and_then(Weak::upgrade) {
+            return Ok(RomImage(data));
+        }
+        open_images.retain(|_, data| data.strong_count() > 0);
+
+        let image = load(&key.0)?;
+        open_images.insert(key, Arc::downgrade(&image.0));
+        Ok(image)
+    }
+
+    #[cfg(unix)]
+    fn load_mapped(path: &Path) -> io::Result<Self> {
+        let file = fs::File::open(path)?;
+        let len = file.metadata()?.len();
+        if len < MIN_ROM_SIZE as u64 {
+            return Ok(Self::from(fs::read(path)?));
+        }
+        let len = usize::try_from(len)
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "ROM file too large"))?;
+        Ok(RomImage(Arc::new(RomData::Mapped(mmap::Mapping::new(
+            &file, len,
+        )?))))
+    }
+
+    #[cfg(not(unix))]
+    fn load_mapped(path: &Path) -> io::Result<Self> {
+        Ok(Self::from(fs::read(path)?))
+    }
+
+    /// Whether the bytes are mapped from the file rather than held in memory
+    pub fn is_mapped(&self) -> bool {
+        !matches!(*self.0, RomData::Owned(_))
+    }
+
+    /// Whether `self` and `other` share their bytes
+    pub fn ptr_eq(&self, other: &RomImage) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+/// Take `rom` as the image, padded with zeros to `MIN_ROM_SIZE`
+impl From<Vec<u8>> for RomImage {
+    fn from(mut rom: Vec<u8>) -> Self {
+        if rom.len() < MIN_ROM_SIZE {
+            rom.resize(MIN_ROM_SIZE, 0);
+        }
+        RomImage(Arc::new(RomData::Owned(rom.into_boxed_slice())))
+    }
+}
+
+impl Default for RomImage {
+    fn default() -> Self {
+        Self::from(Vec::new())
+    }
+}
+
+impl Deref for RomImage {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        self.0.bytes()
+    }
+}
+
+impl AsRef<[u8]> for RomImage {
+    fn as_ref(&self) -> &[u8] {
+        self
+    }
+}
+
+// Read-only private file mappings through the C library std already links against
+#[cfg(unix)]
+mod mmap {
+    use std::ffi::{c_int, c_void};
+    use std::fs::File;
+    use std::io;
+    use std::os::unix::io::AsRawFd;
+
+    const PROT_READ: c_int = 1;
+    const MAP_PRIVATE: c_int = 2;
+
+    extern "C" {
+        fn mmap(
+            addr: *mut c_void,
+            len: usize,
+            prot: c_int,
+            flags: c_int,
+            fd: c_int,
+            offset: isize,
+        ) -> *mut c_void;
+        fn munmap(addr: *mut c_void, len: usize) -> c_int;
+    }
+
+    pub struct Mapping {
+        ptr: *mut c_void,
+        len: usize,
+    }
+
+    // SAFETY: the mapping is read-only and owned by this value until it is dropped
+    unsafe impl Send for Mapping {}
+    unsafe impl Sync for Mapping {}
+
+    impl Mapping {
+        /// Map the first `len` bytes of `file`; `len` must not be 0
+        pub fn new(file: &File, len: usize) -> io::Result<Self> {
+            // SAFETY: a fresh read-only mapping at an address the kernel picks, of a file
+            // kept open for the duration of the call. The mapping outlives the descriptor.
+            let ptr = unsafe {
+                mmap(
+                    std::ptr::null_mut(),
+                    len,
+                    PROT_READ,
+                    MAP_PRIVATE,
+                    file.as_raw_fd(),
+                    0,
+                )
+            };
+            if ptr as isize == -1 {
+                return Err(io::Error::last_os_error());
+            }
+            Ok(Self { ptr, len })
+        }
+
+        pub fn bytes(&self) -> &[u8] {
+            // SAFETY: `ptr` is a live mapping of `len` readable bytes until `drop`
+            unsafe { std::slice::from_raw_parts(self.ptr as *const u8, self.len) }
+        }
+    }
+
+    impl Drop for Mapping {
+        fn drop(&mut self) {
+            // SAFETY: unmaps exactly the region `new` mapped, once
+            unsafe {
+                munmap(self.ptr, self.len);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_small_roms_are_padded() {
+        let image = RomImage::from(vec![0xC3; 0x100]);
+        assert_eq!(image.len(), MIN_ROM_SIZE);
+        assert_eq!((image[0xFF], image[0x100]), (0xC3, 0x00));
+        assert!(!image.is_mapped());
+    }
+
+    #[test]
+    fn test_open_shares_one_image_per_file() {
+        let dir = std::env::temp_dir().join(format!("gbemu_romimage_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("game.gb");
+        let mut rom = vec![0; 0x10000];
+        rom[0x4000] = 0x42;
+        fs::write(&path, &rom).unwrap();
+        fs::write(dir.join("tiny.gb"), [0x18, 0xFE]).unwrap();
+
+        let image = RomImage::open(&path).unwrap();
+        assert_eq!(&image[..], &rom[..]);
+        assert!(!image.is_mapped());
+        assert!(image.ptr_eq(&RomImage::open(&dir.join(".").join("game.gb")).unwrap()));
+
+        // SAFETY: the test files stay as they are until the images are dropped
+        let mapped = unsafe { RomImage::map(&path) }.unwrap();
+        assert_eq!(&mapped[..], &rom[..]);
+        assert_eq!(mapped.is_mapped(), cfg!(unix));
+        assert!(!mapped.ptr_eq(&image));
+        assert!(mapped.ptr_eq(&unsafe { RomImage::map(&path) }.unwrap()));
+
+        let tiny = unsafe { RomImage::map(&dir.join("tiny.gb")) }.unwrap();
+        assert_eq!(
+            (tiny.len(), tiny[1], tiny.is_mapped()),
+            (MIN_ROM_SIZE, 0xFE, false)
+        );
+        assert!(RomImage::open(&dir.join("missing.gb")).is_err());
+        drop((image, mapped, tiny));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,348 @@
+// MBC3 real-time clock
+// The clock counts emulated time, so fast-forward and pause affect it like everything
+// else, and can be set, shifted or stopped by hand to test time-based game events.
+// Battery saves store it in the common 48-byte .sav footer (five registers, five latched
+// registers, host timestamp); loading a footer catches the clock up on the host time
+// that passed while the emulator was closed.
+//
+// The MBC3 mapper (see mbc.rs) owns the Rtc and forwards register selects, latch writes and
+// emulated time to it.
+
+use std::fmt;
+use std::str::FromStr;
+
+use crate::clock::EmuDuration;
+use crate::savestate::{StateReader, StateWriter};
+
+/// Size of the RTC footer appended to battery saves
+pub const FOOTER_SIZE: usize = 48;
+
+const SECONDS_PER_DAY: u64 = 24 * 60 * 60;
+// The day counter is 9 bits; passing day 511 sets the carry flag and wraps to 0
+const COUNTER_PERIOD: u64 = 512 * SECONDS_PER_DAY;
+
+/// Name of clock register `select` (0x08-0x0C)
+pub fn register_name(select: u8) -> &'static str {
+    match select {
+        0x08 => "seconds",
+        0x09 => "minutes",
+        0x0A => "hours",
+        0x0B => "day low",
+        0x0C => "day high/flags",
+        _ => "unmapped",
+    }
+}
+
+/// Time as shown by the clock registers
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct RtcTime {
+    /// 0-511
+    pub days: u16,
+    pub hours: u8,
+    pub minutes: u8,
+    pub seconds: u8,
+}
+
+impl RtcTime {
+    fn from_seconds(total: u64) -> Self {
+        Self {
+            days: (total / SECONDS_PER_DAY) as u16,
+            hours: (total / 3600 % 24) as u8,
+            minutes: (total / 60 % 60) as u8,
+            seconds: (total % 60) as u8,
+        }
+    }
+
+    fn to_seconds(self) -> u64 {
+        self.days as u64 * SECONDS_PER_DAY
+            + self.hours as u64 * 3600
+            + self.minutes as u64 * 60
+            + self.seconds as u64
+    }
+}
+
+/// Parses `DAYS:HH:MM:SS`, the format used by Display
+impl FromStr for RtcTime {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("invalid clock time '{}' (expected DAYS:HH:MM:SS)", s);
+        let fields: Vec<u16> = s
+            .split(':')
+            .map(|field| field.parse().map_err(|_| invalid()))
+            .collect::<Result<_, _>>()?;
+        let [days, hours, minutes, seconds] = fields[..] else {
+            return Err(invalid());
+        };
+        if days > 511 || hours > 23 || minutes > 59 || seconds > 59 {
+            return Err(invalid());
+        }
+        Ok(Self {
+            days,
+            hours: hours as u8,
+            minutes: minutes as u8,
+            seconds: seconds as u8,
+        })
+    }
+}
+
+impl fmt::Display for RtcTime {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}:{:02}:{:02}:{:02}",
+            self.days, self.hours, self.minutes, self.seconds
+        )
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Rtc {
+    // Seconds since day 0, below COUNTER_PERIOD
+    counter: u64,
+    // Emulated time not yet counted as a whole second
+    subsecond: EmuDuration,
+    halted: bool,
+    carry: bool,
+    // Registers 0x08-0x0C as of the last latch
+    latched: [u8; 5],
+    // 0x00 was written to the latch register; 0x01 next latches the time
+    latch_armed: bool,
+}
+
+impl Rtc {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Count emulated time; does nothing while halted
+    pub fn advance(&mut self, elapsed: EmuDuration) {
+        if self.halted {
+            return;
+        }
+        self.subsecond += elapsed;
+        let seconds = self.subsecond.as_secs();
+        if seconds > 0 {
+            self.subsecond = EmuDuration::from_cycles(self.subsecond.subsec_cycles());
+            self.add_seconds(seconds);
+        }
+    }
+
+    fn add_seconds(&mut self, seconds: u64) {
+        let total = self.counter + seconds;
+        if total >= COUNTER_PERIOD {
+            self.carry = true;
+        }
+        self.counter = total % COUNTER_PERIOD;
+    }
+
+    pub fn time(&self) -> RtcTime {
+        RtcTime::from_seconds(self.counter)
+    }
+
+    /// Set the clock, starting a fresh second
+    pub fn set(&mut self, time: RtcTime) {
+        self.counter = time.to_seconds() % COUNTER_PERIOD;
+        self.subsecond = EmuDuration::ZERO;
+    }
+
+    /// Move the clock forward (or back, for negative `seconds`). Moving forward past day
+    /// 511 sets the carry flag like real time would; moving back stops at day 0.
+    pub fn adjust(&mut self, seconds: i64) {
+        if seconds >= 0 {
+            self.add_seconds(seconds as u64);
+        } else {
+            self.counter = self.counter.saturating_sub(seconds.unsigned_abs());
+        }
+    }
+
+    pub fn halted(&self) -> bool {
+        self.halted
+    }
+
+    /// Stop or restart the clock (bit 6 of the day-high register)
+    pub fn set_halted(&mut self, halted: bool) {
+        self.halted = halted;
+    }
+
+    /// Day counter overflowed; stays set until the game clears it
+    pub fn carry(&self) -> bool {
+        self.carry
+    }
+
+    fn registers(&self) -> [u8; 5] {
+        let time = self.time();
+        [
+            time.seconds,
+            time.minutes,
+            time.hours,
+            time.days as u8,
+            (time.days >> 8) as u8 | (self.halted as u8) << 6 | (self.carry as u8) << 7,
+        ]
+    }
+
+    /// Write to the latch register (0x6000-0x7FFF): 0x00 then 0x01 copies the time into
+    /// the registers the game reads
+    pub fn write_latch(&mut self, value: u8) {
+        if self.latch_armed && value == 0x01 {
+            self.latched = self.registers();
+        }
+        self.latch_armed = value == 0x00;
+    }
+
+    /// Read the latched register selected with 0x08-0x0C
+    pub fn read_register(&self, select: u8) -> u8 {
+        match select {
+            0x08..=0x0C => self.latched[(select - 0x08) as usize],
+            _ => 0xFF,
+        }
+    }
+
+    /// Write the live register selected with 0x08-0x0C. Out-of-range values wrap.
+    pub fn write_register(&mut self, select: u8, value: u8) {
+        let mut time = self.time();
+        match select {
+            0x08 => {
+                time.seconds = (value & 0x3F) % 60;
+                self.subsecond = EmuDuration::ZERO;
+            }
+            0x09 => time.minutes = (value & 0x3F) % 60,
+            0x0A => time.hours = (value & 0x1F) % 24,
+            0x0B => time.days = time.days & 0x100 | value as u16,
+            0x0C => {
+                time.days = time.days & 0xFF | ((value as u16 & 0x01) << 8);
+                self.halted = value & 0x40 != 0;
+                self.carry = value & 0x80 != 0;
+            }
+            _ => return,
+        }
+        self.counter = time.to_seconds();
+    }
+
+    /// Battery save footer, stamped with the host time in seconds since the Unix epoch
+    pub fn footer(&self, unix_time: u64) -> [u8; FOOTER_SIZE] {
+        let mut footer = [0; FOOTER_SIZE];
+        let registers = self.registers().into_iter().chain(self.latched);
+        for (chunk, value) in footer.chunks_exact_mut(4).zip(registers) {
+            chunk.copy_from_slice(&(value as u32).to_le_bytes());
+        }
+        footer[40..].copy_from_slice(&unix_time.to_le_bytes());
+        footer
+    }
+
+    /// Restore a footer written by `footer`, then run the clock for the host time that
+    /// passed since it was saved (unless the clock was halted)
+    pub fn load_footer(&mut self, footer: &[u8], unix_time: u64) -> Result<(), String> {
+        if footer.len() != FOOTER_SIZE {
+            return Err(format!(
+                "RTC footer is {} bytes, expected {}",
+                footer.len(),
+                FOOTER_SIZE
+            ));
+        }
+        let value = |i: usize| footer[i * 4];
+        for (select, i) in (0x08..=0x0C).zip(0..) {
+            self.write_register(select, value(i));
+        }
+        self.latched = std::array::from_fn(|i| value(5 + i));
+        let saved_at = u64::from_le_bytes(footer[40..].try_into().unwrap());
+        if !self.halted {
+            self.add_seconds(unix_time.saturating_sub(saved_at));
+        }
+        Ok(())
+    }
+}
+
+impl Rtc {
+    pub(crate) fn save_state(&self, w: &mut StateWriter) {
+        w.u64(self.counter);
+        w.u64(self.subsecond.cycles());
+        w.bool(self.halted);
+        w.bool(self.carry);
+        w.bytes(&self.latched);
+        w.bool(self.latch_armed);
+    }
+
+    pub(crate) fn load_state(&mut self, r: &mut StateReader) -> Result<(), String> {
+        self.counter = r.u64()?;
+        self.subsecond = EmuDuration::from_cycles(r.u64()?);
+        self.halted = r.bool()?;
+        self.carry = r.bool()?;
+        r.fill(&mut self.latched)?;
+        self.latch_armed = r.bool()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_advance_halt_and_carry() {
+        let mut rtc = Rtc::new();
+        for _ in 0..90 {
+            rtc.advance(EmuDuration::from_millis(500));
+        }
+        assert_eq!(rtc.time().to_string(), "0:00:00:45");
+
+        rtc.set_halted(true);
+        rtc.advance(EmuDuration::from_secs(3600));
+        assert_eq!(rtc.time().seconds, 45);
+        rtc.set_halted(false);
+
+        rtc.set("511:23:59:59".parse().unwrap());
+        rtc.advance(EmuDuration::from_secs(2));
+        assert_eq!(rtc.time().to_string(), "0:00:00:01");
+        assert!(rtc.carry());
+
+        rtc.adjust(-10);
+        assert_eq!(rtc.time(), RtcTime::default());
+        rtc.adjust(3 * SECONDS_PER_DAY as i64 + 61);
+        assert_eq!(rtc.time().to_string(), "3:00:01:01");
+        assert!("3:24:00:00".parse::<RtcTime>().is_err());
+        assert!("1:00:00".parse::<RtcTime>().is_err());
+    }
+
+    #[test]
+    fn test_registers_are_latched() {
+        let mut rtc = Rtc::new();
+        rtc.set("300:12:34:56".parse().unwrap());
+        rtc.write_latch(0x00);
+        rtc.write_latch(0x01);
+        rtc.advance(EmuDuration::from_secs(10));
+
+        // Reads see the latched time until the next latch
+        let latched: Vec<u8> = (0x08..=0x0C).map(|r| rtc.read_register(r)).collect();
+        assert_eq!(latched, [56, 34, 12, 300u16 as u8, 0x01]);
+        rtc.write_latch(0x01);
+        assert_eq!(rtc.read_register(0x08), 56);
+
+        rtc.write_register(0x0C, 0x40);
+        assert!(rtc.halted());
+        assert_eq!(rtc.time().days, 44);
+        rtc.write_latch(0x00);
+        rtc.write_latch(0x01);
+        assert_eq!(rtc.read_register(0x08), 6);
+        assert_eq!(rtc.read_register(0x0C), 0x40);
+    }
+
+    #[test]
+    fn test_footer_catches_up_on_host_time() {
+        let mut rtc = Rtc::new();
+        rtc.set("1:23:00:00".parse().unwrap());
+        let footer = rtc.footer(1_000_000);
+
+        let mut loaded = Rtc::new();
+        loaded.load_footer(&footer, 1_000_000 + 7200).unwrap();
+        assert_eq!(loaded.time().to_string(), "2:01:00:00");
+
+        rtc.set_halted(true);
+        let mut loaded = Rtc::new();
+        loaded.load_footer(&rtc.footer(0), 7200).unwrap();
+        assert_eq!(loaded.time().to_string(), "1:23:00:00");
+        assert!(loaded.halted());
+        assert!(loaded.load_footer(&footer[..40], 0).is_err());
+    }
+}
@@ -0,0 +1,782 @@
+// Host-independent main loop
+// `Runner` emulates one host frame at a time and hands the results to the frontend
+// through small traits: Video shows frames, Input handles host events and Clock paces
+// frames. SDL, other window libraries, a TUI or a headless run all drive the same loop
+// and only differ in the trait implementations they pass in.
+
+use std::io::{self, Write};
+use std::time::{Duration, Instant};
+
+use crate::battery::BatterySave;
+#[cfg(feature = "control-server")]
+use crate::control::ControlServer;
+use crate::cpu::{Cpu, Reg16};
+use crate::freeze::RamFreezer;
+use crate::i18n::Catalog;
+use crate::memory::Memory;
+use crate::metrics::{FrameRecord, MetricsWriter};
+use crate::ppu::Ppu;
+use crate::profiler::{FrameProfiler, FrameTimings};
+use crate::rewind::RewindBuffer;
+use crate::trace::Tracer;
+
+/// T-cycles per video frame (154 lines of 456 dots)
+pub const CYCLES_PER_FRAME: u32 = 70224;
+
+/// Slowest and fastest emulation speeds `set_speed` accepts
+pub const MIN_SPEED: f64 = 0.01;
+pub const MAX_SPEED: f64 = 100.0;
+
+// Smoothing factor of the present() duration estimate (exponential moving average)
+const PRESENT_TIME_ALPHA: f64 = 0.2;
+
+// Frames of timing history kept for stutter reports (~10 seconds)
+const PROFILER_FRAMES: usize = 600;
+
+/// What happened during the frame handed to `Video::draw`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FrameInfo {
+    /// Frames emulated before this one (see `Runner::frame_number`)
+    pub frame_number: u64,
+    /// T-cycles emulated; 0 while paused, when the framebuffer is left as it was, and while
+    /// rewinding, when it comes from the restored state
+    pub cpu_cycles: u32,
+    /// The previous frame missed its deadline, so emulation is running behind real time
+    pub dropped: bool,
+    /// LCDC bit 7 at the end of the frame. While the LCD is off the framebuffer does not
+    /// change, so frontends can skip presenting it again.
+    pub lcd_on: bool,
+}
+
+pub trait Video {
+    /// Prepare the current frame for display. `Ppu::take_dirty_rows` tells which lines
+    /// changed since the last call, and `Ppu::layer_view` is set while the layer debug
+    /// view is on.
+    fn draw(&mut self, ppu: &mut Ppu, info: &FrameInfo);
+
+    /// Prepare the frame of the `Runner::link_partner` machine, after `draw`
+    fn draw_partner(&mut self, _ppu: &mut Ppu, _info: &FrameInfo) {}
+
+    /// Show the drawn frame. Called as close to the frame deadline as possible.
+    fn present(&mut self) {}
+
+    /// One-line status (FPS, emulated time, frame timings), updated once a second
+    fn set_status(&mut self, _status: &str) {}
+}
+
+/// Sample sink for a frontend's audio output. The core has no APU yet, so the runner
+/// does not produce samples; frontends can implement this ahead of it.
+pub trait Audio {
+    /// Interleaved stereo samples produced since the last call
+    fn push_samples(&mut self, samples: &[i16]);
+
+    /// Duration to pace the next frame to. Outputs driven by the sound card's clock
+    /// stretch or shrink `frame_duration` slightly to stay in sync with it.
+    fn pace(&self, frame_duration: Duration) -> Duration {
+        frame_duration
+    }
+}
+
+pub trait Input {
+    /// Handle pending host events (buttons, hotkeys) before the next frame.
+    /// Returns false when the host wants to quit.
+    fn poll(&mut self, cpu: &mut Cpu, mem: &mut Memory) -> bool;
+
+    /// Hand the `Runner::link_partner` machine its share of the events taken by `poll`
+    fn poll_partner(&mut self, _cpu: &mut Cpu, _mem: &mut Memory) {}
+
+    /// True while the host holds emulation, e.g. with a menu open
+    fn paused(&self) -> bool {
+        false
+    }
+
+    /// True while the host wants frames as fast as they can be emulated (turbo), with
+    /// only every `Runner::frame_skip`th one drawn
+    fn fast_forward(&self) -> bool {
+        false
+    }
+
+    /// True while the host wants to go back in time, one `Runner::rewind` snapshot per frame
+    fn rewinding(&self) -> bool {
+        false
+    }
+}
+
+/// Host time source used to pace frames
+pub trait Clock {
+    fn now(&self) -> Instant;
+    fn sleep(&mut self, duration: Duration);
+}
+
+/// Wall clock: frames are paced to real time
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+
+    fn sleep(&mut self, duration: Duration) {
+        std::thread::sleep(duration);
+    }
+}
+
+/// Wall clock that never sleeps, to run as fast as possible (headless)
+pub struct UnthrottledClock;
+
+impl Clock for UnthrottledClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+
+    fn sleep(&mut self, _duration: Duration) {}
+}
+
+/// Video output that discards frames (headless)
+pub struct NullVideo;
+
+impl Video for NullVideo {
+    fn draw(&mut self, ppu: &mut Ppu, _info: &FrameInfo) {
+        ppu.take_dirty_rows();
+    }
+}
+
+pub struct Runner {
+    pub cpu: Cpu,
+    pub mem: Memory,
+    pub freezer: RamFreezer,
+    /// Per-frame metrics export
+    pub metrics: Option<MetricsWriter<Box<dyn Write>>>,
+    pub battery_save: Option<BatterySave>,
+    /// Paces frames when set, so audio and video stay in sync
+    pub audio: Option<Box<dyn Audio>>,
+    #[cfg(feature = "control-server")]
+    pub control: Option<ControlServer>,
+    /// Serial output is forwarded here as it arrives (test ROM results)
+    pub serial_out: Option<Box<dyn Write>>,
+    /// Logs each instruction before it runs
+    pub trace: Option<Tracer>,
+    pub profiler: FrameProfiler,
+    /// Messages for warnings and the status line
+    pub catalog: Catalog,
+    /// While fast-forwarding, draw and present one frame in this many (1 draws them all)
+    pub frame_skip: u32,
+    /// History to rewind through; snapshots are taken as frames run
+    pub rewind: Option<RewindBuffer>,
+    /// Second machine on the other end of the link cable, run a frame after each of this
+    /// one's and paused with it. Rewinding is off while it is set, as it would only move
+    /// this side of the link.
+    pub link_partner: Option<Box<Runner>>,
+    frame_duration: Duration,
+    frame_number: u64,
+    estimated_present_time: Duration,
+}
+
+impl Runner {
+    pub fn new(cpu: Cpu, mem: Memory) -> Self {
+        let frame_duration = Duration::from_secs_f64(1.0 / 60.0);
+        Self {
+            cpu,
+            mem,
+            freezer: RamFreezer::default(),
+            metrics: None,
+            battery_save: None,
+            audio: None,
+            #[cfg(feature = "control-server")]
+            control: None,
+            serial_out: None,
+            trace: None,
+            profiler: FrameProfiler::new(PROFILER_FRAMES, frame_duration),
+            catalog: Catalog::default(),
+            frame_skip: 1,
+            rewind: None,
+            link_partner: None,
+            frame_duration,
+            frame_number: 0,
+            estimated_present_time: Duration::ZERO,
+        }
+    }
+
+    /// Pace frames to `speed` times the Game Boy's frame rate (2.0 runs twice as fast).
+    /// Starts the frame timing history over, as its budget changes with the speed.
+    /// Speeds outside MIN_SPEED..=MAX_SPEED are clamped.
+    pub fn set_speed(&mut self, speed: f64) {
+        let speed = if speed.is_nan() {
+            1.0
+        } else {
+            speed.clamp(MIN_SPEED, MAX_SPEED)
+        };
+        self.frame_duration = Duration::from_secs_f64(1.0 / 60.0 / speed);
+        self.profiler = FrameProfiler::new(PROFILER_FRAMES, self.frame_duration);
+    }
+
+    /// Frames emulated so far (frames spent paused are not counted)
+    pub fn frame_number(&self) -> u64 {
+        self.frame_number
+    }
+
+    /// Run until `input` asks to quit, then flush metrics and the battery save
+    pub fn run(
+        &mut self,
+        video: &mut impl Video,
+        input: &mut impl Input,
+        clock: &mut impl Clock,
+    ) -> io::Result<()> {
+        let mut last_frame = clock.now();
+        let mut fps_counter = 0u32;
+        let mut fps_timer = clock.now();
+        let mut late = false;
+
+        while input.poll(&mut self.cpu, &mut self.mem) {
+            if let Some(partner) = self.link_partner.as_mut() {
+                input.poll_partner(&mut partner.cpu, &mut partner.mem);
+            }
+            let emulation_start = clock.now();
+            let paused = self.poll_control() || input.paused();
+            let rewinding = !paused && self.link_partner.is_none() && input.rewinding();
+            if rewinding {
+                self.rewind_step();
+            }
+            let info = if paused || rewinding {
+                self.held_frame(late)
+            } else {
+                FrameInfo {
+                    dropped: late,
+                    ..self.run_frame()?
+                }
+            };
+            let partner_info = match self.link_partner.as_mut() {
+                Some(partner) if paused => Some(partner.held_frame(late)),
+                Some(partner) => Some(FrameInfo {
+                    dropped: late,
+                    ..partner.run_frame()?
+                }),
+                None => None,
+            };
+            let emulation_time = clock.now() - emulation_start;
+            let fast_forward = !paused && !rewinding && input.fast_forward();
+            let skipped = fast_forward && info.frame_number % self.frame_skip.max(1) as u64 != 0;
+
+            self.update_battery_save(clock.now());
+            if let Some(partner) = self.link_partner.as_mut() {
+                partner.update_battery_save(clock.now());
+            }
+
+            // Skipped frames leave their dirty rows for the next frame drawn
+            let render_start = clock.now();
+            if !skipped {
+                video.draw(&mut self.mem.ppu, &info);
+                if let (Some(partner), Some(info)) = (self.link_partner.as_mut(), partner_info) {
+                    video.draw_partner(&mut partner.mem.ppu, &info);
+                }
+            }
+            let render_time = clock.now() - render_start;
+
+            fps_counter += 1;
+            if clock.now() - fps_timer >= Duration::from_secs(1) {
+                video.set_status(&self.status(fps_counter));
+                fps_counter = 0;
+                fps_timer = clock.now();
+            }
+
+            // Sleep until the frame deadline, leaving room for the expected present() time
+            let frame_time = clock.now() - last_frame;
+            let frame_duration = match &self.audio {
+                Some(audio) => audio.pace(self.frame_duration),
+                None => self.frame_duration,
+            };
+            late = frame_time > frame_duration;
+            let target_sleep = frame_duration
+                .saturating_sub(frame_time)
+                .saturating_sub(self.estimated_present_time);
+            let sleep_start = clock.now();
+            if target_sleep > Duration::from_micros(100) && !fast_forward {
+                clock.sleep(target_sleep);
+            }
+            let sleep_time = clock.now() - sleep_start;
+
+            let present_start = clock.now();
+            if !skipped {
+                video.present();
+            }
+            let present_time = clock.now() - present_start;
+            let estimate_micros = PRESENT_TIME_ALPHA * present_time.as_micros() as f64
+                + (1.0 - PRESENT_TIME_ALPHA) * self.estimated_present_time.as_micros() as f64;
+            self.estimated_present_time = Duration::from_micros(estimate_micros as u64);
+
+            self.profiler.record(FrameTimings {
+                emulation: emulation_time,
+                render: render_time,
+                present: present_time,
+                sleep: sleep_time,
+            });
+            last_frame = clock.now();
+        }
+        self.finish()
+    }
+
+    // Restore the newest rewind snapshot, if any are left
+    fn rewind_step(&mut self) {
+        let Some(rewind) = self.rewind.as_mut() else {
+            return;
+        };
+        if let Err(err) = rewind.step_back(&mut self.cpu, &mut self.mem) {
+            eprintln!("{}", self.catalog.format("warning", &[&err]));
+        }
+    }
+
+    // The frame shown while emulation is held (paused or rewinding): nothing ran
+    fn held_frame(&self, dropped: bool) -> FrameInfo {
+        FrameInfo {
+            frame_number: self.frame_number,
+            cpu_cycles: 0,
+            dropped,
+            lcd_on: self.mem.ppu.lcdc().lcd_enabled(),
+        }
+    }
+
+    // Write cartridge RAM out if it is due; errors are printed as warnings
+    fn update_battery_save(&mut self, now: Instant) {
+        if let Some(save) = self.battery_save.as_mut() {
+            if let Err(err) = save.update(&mut self.mem, now) {
+                let err = self
+                    .catalog
+                    .format("cannot-write", &[&save.path().display(), &err]);
+                eprintln!("{}", self.catalog.format("warning", &[&err]));
+            }
+        }
+    }
+
+    /// Emulate one frame, applying RAM freezes and exporting metrics. `run` fills in
+    /// `dropped`, which only it can know.
+    pub fn run_frame(&mut self) -> io::Result<FrameInfo> {
+        let mut cycles = 0u32;
+        let mut pc_at_vblank = None;
+        let mut vblank_cycle = None;
+        while cycles < CYCLES_PER_FRAME {
+            // The VBlank request is latched into IF within the step, so watch the mode
+            let pc = self.cpu.registers.read_r16(Reg16::PC);
+            let was_vblank = self.in_vblank();
+            if let Some(tracer) = self.trace.as_mut() {
+                tracer.trace(&self.cpu, &self.mem);
+            }
+            cycles += self.cpu.step(&mut self.mem);
+            self.freezer.after_instruction(&mut self.mem);
+
+            if !was_vblank && self.in_vblank() && vblank_cycle.is_none() {
+                pc_at_vblank = Some(pc);
+                vblank_cycle = Some(cycles);
+            }
+
+            self.forward_serial_output()?;
+        }
+        self.freezer.after_frame(&mut self.mem);
+        for event in self.mem.take_strict_events() {
+            let message = format!("strict: {}", event);
+            eprintln!("{}", self.catalog.format("warning", &[&message]));
+        }
+
+        if let Some(writer) = self.metrics.as_mut() {
+            let record = FrameRecord {
+                frame: self.frame_number,
+                cycles,
+                pc_at_vblank,
+                vblank_cycle,
+                rom_bank: self.mem.rom_bank(),
+                buttons: self.mem.joypad.buttons(),
+                watches: writer
+                    .watch_addresses()
+                    .iter()
+                    .map(|&addr| self.mem.read_8(addr))
+                    .collect(),
+                interrupt_latency: self
+                    .mem
+                    .interrupt_latency_mut()
+                    .map(|latency| latency.take_frame()),
+            };
+            writer.write_record(&record)?;
+        }
+        if let Some(rewind) = self.rewind.as_mut() {
+            rewind.record_frame(&self.cpu, &self.mem);
+        }
+        let info = FrameInfo {
+            frame_number: self.frame_number,
+            cpu_cycles: cycles,
+            dropped: false,
+            lcd_on: self.mem.ppu.lcdc().lcd_enabled(),
+        };
+        self.frame_number += 1;
+        Ok(info)
+    }
+
+    /// Flush metrics and any unsaved cartridge RAM (on exit); save errors are printed
+    pub fn finish(&mut self) -> io::Result<()> {
+        if let Some(writer) = self.metrics.as_mut() {
+            writer.flush()?;
+        }
+        if let Some(tracer) = self.trace.as_mut() {
+            tracer.flush()?;
+        }
+        if let Some(save) = self.battery_save.as_mut() {
+            if let Err(err) = save.flush(&mut self.mem) {
+                let err = self
+                    .catalog
+                    .format("cannot-write", &[&save.path().display(), &err]);
+                eprintln!("{}", self.catalog.format("error", &[&err]));
+            }
+        }
+        match self.link_partner.as_mut() {
+            Some(partner) => partner.finish(),
+            None => Ok(()),
+        }
+    }
+
+    // Apply control server requests; true while a client has paused emulation
+    #[cfg(feature = "control-server")]
+    fn poll_control(&mut self) -> bool {
+        match self.control.as_mut() {
+            Some(server) => {
+                server.poll(&mut self.cpu, &mut self.mem);
+                server.paused()
+            }
+            None => false,
+        }
+    }
+
+    #[cfg(not(feature = "control-server"))]
+    fn poll_control(&mut self) -> bool {
+        false
+    }
+
+    fn in_vblank(&self) -> bool {
+        let ppu = &self.mem.ppu;
+        ppu.lcdc().lcd_enabled() && ppu.stat().mode() == 1
+    }
+
+    fn forward_serial_output(&mut self) -> io::Result<()> {
+        let Some(out) = self.serial_out.as_mut() else {
+            return Ok(());
+        };
+        let mut result = Ok(());
+        let mut written = false;
+        self.mem.serial.drain_output(&mut |byte| {
+            if result.is_ok() {
+                result = out.write_all(&[byte]);
+            }
+            written = true;
+        });
+        result?;
+        if written {
+            out.flush()?;
+        }
+        Ok(())
+    }
+
+    fn status(&self, fps: u32) -> String {
+        let emulated_secs = self.mem.clock.emulated_time().as_secs();
+        self.catalog.format(
+            "window-title-status",
+            &[
+                &fps,
+                &format!("{:02}:{:02}", emulated_secs / 60, emulated_secs % 60),
+                &self.profiler.summary(),
+            ],
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::Model;
+    use crate::savestate;
+    use crate::trace::TraceFilter;
+    use std::cell::{Cell, RefCell};
+    use std::rc::Rc;
+
+    // Time only moves when the runner sleeps
+    struct FakeClock {
+        now: Cell<Instant>,
+        slept: Duration,
+    }
+
+    impl Clock for FakeClock {
+        fn now(&self) -> Instant {
+            self.now.get()
+        }
+
+        fn sleep(&mut self, duration: Duration) {
+            self.now.set(self.now.get() + duration);
+            self.slept += duration;
+        }
+    }
+
+    // Quits after a fixed number of frames
+    struct Frames(u32);
+
+    impl Input for Frames {
+        fn poll(&mut self, _cpu: &mut Cpu, _mem: &mut Memory) -> bool {
+            self.0 = self.0.saturating_sub(1);
+            self.0 > 0
+        }
+    }
+
+    #[derive(Default)]
+    struct CountingVideo {
+        drawn: Vec<FrameInfo>,
+        partner_drawn: Vec<FrameInfo>,
+        presented: u32,
+    }
+
+    impl Video for CountingVideo {
+        fn draw(&mut self, ppu: &mut Ppu, info: &FrameInfo) {
+            ppu.take_dirty_rows();
+            self.drawn.push(*info);
+        }
+
+        fn draw_partner(&mut self, ppu: &mut Ppu, info: &FrameInfo) {
+            ppu.take_dirty_rows();
+            self.partner_drawn.push(*info);
+        }
+
+        fn present(&mut self) {
+            self.presented += 1;
+        }
+    }
+
+    fn runner() -> Runner {
+        // LD A,0x42 ; LD (0xC000),A ; JR -2
+        runner_with(&[0x3E, 0x42, 0xEA, 0x00, 0xC0, 0x18, 0xFE])
+    }
+
+    fn runner_with(program: &[u8]) -> Runner {
+        let mut rom = vec![0; 0x8000];
+        rom[0x0100..0x0100 + program.len()].copy_from_slice(program);
+        let mut mem = Memory::new(rom);
+        mem.skip_boot(Model::Dmg);
+        let mut cpu = Cpu::new();
+        cpu.skip_boot(Model::Dmg, 0);
+        Runner::new(cpu, mem)
+    }
+
+    #[test]
+    fn test_run_paces_and_presents_each_frame() {
+        let mut runner = runner();
+        let mut video = CountingVideo::default();
+        let mut clock = FakeClock {
+            now: Cell::new(Instant::now()),
+            slept: Duration::ZERO,
+        };
+        runner.run(&mut video, &mut Frames(4), &mut clock).unwrap();
+
+        assert_eq!(runner.frame_number(), 3);
+        assert_eq!((video.drawn.len(), video.presented), (3, 3));
+        assert_eq!(
+            video.drawn[2],
+            FrameInfo {
+                frame_number: 2,
+                cpu_cycles: video.drawn[2].cpu_cycles,
+                dropped: false,
+                lcd_on: true,
+            }
+        );
+        // Whole instructions, so a frame can run a few cycles over
+        assert!((CYCLES_PER_FRAME..CYCLES_PER_FRAME + 24).contains(&video.drawn[2].cpu_cycles));
+        assert_eq!(runner.mem.read_8(0xC000), 0x42);
+        assert_eq!(runner.profiler.len(), 3);
+        // Nothing else takes fake time, so every frame sleeps its whole budget
+        assert_eq!(clock.slept, runner.frame_duration * 3);
+    }
+
+    // Asks for frames twice as long as video alone would
+    struct SlowAudio;
+
+    impl Audio for SlowAudio {
+        fn push_samples(&mut self, _samples: &[i16]) {}
+
+        fn pace(&self, frame_duration: Duration) -> Duration {
+            frame_duration * 2
+        }
+    }
+
+    #[test]
+    fn test_audio_paces_frames() {
+        let mut runner = runner();
+        runner.audio = Some(Box::new(SlowAudio));
+        let mut clock = FakeClock {
+            now: Cell::new(Instant::now()),
+            slept: Duration::ZERO,
+        };
+        runner
+            .run(&mut NullVideo, &mut Frames(3), &mut clock)
+            .unwrap();
+        assert_eq!(clock.slept, runner.frame_duration * 4);
+    }
+
+    // Every look at the time costs 10 ms, so no frame meets its deadline
+    struct SlowClock(Cell<Instant>);
+
+    impl Clock for SlowClock {
+        fn now(&self) -> Instant {
+            self.0.set(self.0.get() + Duration::from_millis(10));
+            self.0.get()
+        }
+
+        fn sleep(&mut self, _duration: Duration) {}
+    }
+
+    // Fast-forwards for a fixed number of frames
+    struct FastForward(Frames);
+
+    impl Input for FastForward {
+        fn poll(&mut self, cpu: &mut Cpu, mem: &mut Memory) -> bool {
+            self.0.poll(cpu, mem)
+        }
+
+        fn fast_forward(&self) -> bool {
+            true
+        }
+    }
+
+    #[test]
+    fn test_fast_forward_and_speed() {
+        let mut runner = runner();
+        runner.frame_skip = 3;
+        let mut video = CountingVideo::default();
+        let mut clock = FakeClock {
+            now: Cell::new(Instant::now()),
+            slept: Duration::ZERO,
+        };
+        runner
+            .run(&mut video, &mut FastForward(Frames(8)), &mut clock)
+            .unwrap();
+        // Frames 0, 3 and 6 of 7 are shown, and none waits for its deadline
+        let shown: Vec<_> = video.drawn.iter().map(|info| info.frame_number).collect();
+        assert_eq!((shown, video.presented), (vec![0, 3, 6], 3));
+        assert_eq!(clock.slept, Duration::ZERO);
+
+        let mut runner = runner_with(&[0x18, 0xFE]);
+        runner.set_speed(2.0);
+        runner
+            .run(&mut NullVideo, &mut Frames(3), &mut clock)
+            .unwrap();
+        assert_eq!(runner.frame_duration, Duration::from_secs_f64(1.0 / 120.0));
+        assert_eq!(clock.slept, runner.frame_duration * 2);
+
+        runner.set_speed(1e-300);
+        assert_eq!(runner.frame_duration, Duration::from_secs_f64(1.0 / 0.6));
+        runner.set_speed(f64::INFINITY);
+        assert_eq!(runner.frame_duration, Duration::from_secs_f64(1.0 / 6000.0));
+    }
+
+    // Runs `run` frames, then holds rewind until it quits
+    struct RewindAfter {
+        frames: Frames,
+        run: u32,
+    }
+
+    impl Input for RewindAfter {
+        fn poll(&mut self, cpu: &mut Cpu, mem: &mut Memory) -> bool {
+            self.run = self.run.saturating_sub(1);
+            self.frames.poll(cpu, mem)
+        }
+
+        fn rewinding(&self) -> bool {
+            self.run == 0
+        }
+    }
+
+    #[test]
+    fn test_rewind_restores_earlier_frames() {
+        // INC A ; LD (0xC000),A ; JR -6
+        let program = [0x3C, 0xEA, 0x00, 0xC0, 0x18, 0xFA];
+        let mut runner = runner_with(&program);
+        runner.rewind = Some(RewindBuffer::new(1, usize::MAX));
+        let mut video = CountingVideo::default();
+        let mut input = RewindAfter {
+            frames: Frames(7),
+            run: 5,
+        };
+        let mut clock = FakeClock {
+            now: Cell::new(Instant::now()),
+            slept: Duration::ZERO,
+        };
+        runner.run(&mut video, &mut input, &mut clock).unwrap();
+
+        // Four frames ran, then the two rewound ones restored the states after frames 4 and 3
+        assert_eq!(runner.frame_number(), 4);
+        let cycles: Vec<_> = video.drawn.iter().map(|info| info.cpu_cycles).collect();
+        assert_eq!(cycles[4..], [0, 0]);
+        assert_eq!(runner.rewind.as_ref().unwrap().len(), 2);
+
+        let mut expected = runner_with(&program);
+        for _ in 0..3 {
+            expected.run_frame().unwrap();
+        }
+        assert_eq!(
+            savestate::save_state(&runner.cpu, &runner.mem),
+            savestate::save_state(&expected.cpu, &expected.mem)
+        );
+    }
+
+    #[test]
+    fn test_link_partner_runs_and_draws_with_each_frame() {
+        let mut runner = runner();
+        runner.rewind = Some(RewindBuffer::new(1, usize::MAX));
+        // LD A,0x24 ; LD (0xC000),A ; JR -2
+        let partner = runner_with(&[0x3E, 0x24, 0xEA, 0x00, 0xC0, 0x18, 0xFE]);
+        runner.link_partner = Some(Box::new(partner));
+        let mut video = CountingVideo::default();
+        // Rewinding would only move one side of the link, so it is ignored
+        let mut input = RewindAfter {
+            frames: Frames(5),
+            run: 2,
+        };
+        let mut clock = FakeClock {
+            now: Cell::new(Instant::now()),
+            slept: Duration::ZERO,
+        };
+        runner.run(&mut video, &mut input, &mut clock).unwrap();
+
+        let partner = runner.link_partner.as_ref().unwrap();
+        assert_eq!((runner.frame_number(), partner.frame_number()), (4, 4));
+        assert_eq!(video.partner_drawn.len(), 4);
+        assert!(video.partner_drawn.iter().all(|info| info.cpu_cycles > 0));
+        assert_eq!(runner.mem.read_8(0xC000), 0x42);
+        assert_eq!(partner.mem.read_8(0xC000), 0x24);
+    }
+
+    #[test]
+    fn test_frame_info_reports_late_frames_and_lcd_off() {
+        // LD A,0 ; LDH (LCDC),A ; JR -2
+        let mut runner = runner_with(&[0x3E, 0x00, 0xE0, 0x40, 0x18, 0xFE]);
+        let mut video = CountingVideo::default();
+        let mut clock = SlowClock(Cell::new(Instant::now()));
+        runner.run(&mut video, &mut Frames(3), &mut clock).unwrap();
+
+        let dropped: Vec<_> = video.drawn.iter().map(|info| info.dropped).collect();
+        assert_eq!(dropped, [false, true]);
+        assert!(video.drawn.iter().all(|info| !info.lcd_on));
+    }
+
+    #[test]
+    fn test_trace_logs_each_instruction() {
+        // LDH A,(LY) ; JR -2
+        let mut runner = runner_with(&[0xF0, 0x44, 0x18, 0xFE]);
+        runner.mem.set_ly_override(Some(0x90));
+        let lines = Rc::new(RefCell::new(Vec::new()));
+        let sink = lines.clone();
+        runner.trace = Some(
+            Tracer::with_callback(move |line| sink.borrow_mut().push(line.to_string()))
+                .with_filter(TraceFilter::new().skip_loops(1)),
+        );
+        runner.run_frame().unwrap();
+
+        let lines = lines.borrow();
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0].ends_with("PC:0100 PCMEM:F0,44,18,FE"));
+        assert!(lines[1].starts_with("A:90 "));
+        let suppressed = runner.trace.as_ref().unwrap().filter().suppressed();
+        assert!(suppressed > 1000);
+    }
+}
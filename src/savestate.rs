@@ -0,0 +1,537 @@
+// Savestates
+// Full states capture the whole machine in a versioned binary format: MAGIC, the format
+// version, the cartridge's header checksums (a state only loads into the game it came
+// from), then every component's fields in a fixed order, written by the component itself
+// through StateWriter. Host settings (accuracy, colors, logs, input) are not included.
+//
+// Tools that only need part of the machine snapshot just that part: VRAM and palettes
+// for a frame debugger, CPU and WRAM for a lightweight rewind buffer. Each snapshot
+// starts with its component's tag, so it can't be restored into the wrong component.
+//
+// With the `serde` feature the components also implement Serialize and Deserialize, for
+// callers that store or inspect state in their own format. The same fields are left out
+// as here, and the cartridge ROM too: a deserialized Memory gets it back through
+// `rom.buffer`.
+
+use std::fmt;
+use std::str::FromStr;
+
+use crate::cpu::{Cpu, Reg16};
+use crate::memory::Memory;
+
+/// First bytes of a full state
+pub const MAGIC: &[u8; 4] = b"GBST";
+/// Format of full states written by `save_state`; older versions are rejected
+pub const STATE_VERSION: u16 = 4;
+
+const WRAM: std::ops::Range<usize> = 0xC000..0xE000;
+const HRAM: std::ops::Range<usize> = 0xFF80..0xFFFF;
+const CPU_REGISTERS: [Reg16; 6] = [
+    Reg16::AF,
+    Reg16::BC,
+    Reg16::DE,
+    Reg16::HL,
+    Reg16::SP,
+    Reg16::PC,
+];
+
+/// Part of the machine that can be saved and restored on its own
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Component {
+    /// Registers, IME and the EI/HALT state
+    Cpu,
+    /// Work RAM (C000-DFFF)
+    Wram,
+    /// High RAM (FF80-FFFE)
+    Hram,
+    /// Video RAM (8000-9FFF)
+    Vram,
+    /// Sprite attribute table (FE00-FE9F)
+    Oam,
+    /// DMG palettes (BGP, OBP0, OBP1)
+    Palettes,
+}
+
+impl Component {
+    pub const ALL: [Component; 6] = [
+        Component::Cpu,
+        Component::Wram,
+        Component::Hram,
+        Component::Vram,
+        Component::Oam,
+        Component::Palettes,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Component::Cpu => "cpu",
+            Component::Wram => "wram",
+            Component::Hram => "hram",
+            Component::Vram => "vram",
+            Component::Oam => "oam",
+            Component::Palettes => "palettes",
+        }
+    }
+
+    fn tag(&self) -> u8 {
+        Self::ALL.iter().position(|c| c == self).unwrap() as u8
+    }
+
+    // Snapshot size without the tag byte
+    fn payload_len(&self) -> usize {
+        match self {
+            Component::Cpu => CPU_REGISTERS.len() * 2 + 4,
+            Component::Wram => WRAM.len(),
+            Component::Hram => HRAM.len(),
+            Component::Vram => 0x2000,
+            Component::Oam => 0xA0,
+            Component::Palettes => 3,
+        }
+    }
+}
+
+impl FromStr for Component {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::ALL
+            .into_iter()
+            .find(|component| component.name() == s)
+            .ok_or_else(|| format!("unknown component '{}'", s))
+    }
+}
+
+impl fmt::Display for Component {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// Snapshot one component
+pub fn serialize_component(cpu: &Cpu, mem: &Memory, component: Component) -> Vec<u8> {
+    let mut data = Vec::with_capacity(1 + component.payload_len());
+    data.push(component.tag());
+    match component {
+        Component::Cpu => {
+            for register in CPU_REGISTERS {
+                data.extend_from_slice(&cpu.registers.read_r16(register).to_le_bytes());
+            }
+            data.extend_from_slice(&[
+                cpu.registers.read_ime(),
+                cpu.ei_pending as u8,
+                cpu.halted as u8,
+                cpu.halt_bug as u8,
+            ]);
+        }
+        Component::Wram => data.extend_from_slice(mem.work_ram()),
+        Component::Hram => data.extend_from_slice(mem.high_ram()),
+        Component::Vram => data.extend_from_slice(mem.ppu.vram()),
+        Component::Oam => data.extend_from_slice(&mem.ppu.oam),
+        Component::Palettes => {
+            let (bgp, obp0, obp1) = mem.ppu.palettes();
+            data.extend_from_slice(&[bgp, obp0, obp1]);
+        }
+    }
+    data
+}
+
+/// Restore a snapshot taken with `serialize_component`; the rest of the machine is untouched
+pub fn restore_component(
+    cpu: &mut Cpu,
+    mem: &mut Memory,
+    component: Component,
+    data: &[u8],
+) -> Result<(), String> {
+    let payload = match data.split_first() {
+        Some((&tag, payload)) if tag == component.tag() => payload,
+        Some(_) => return Err(format!("snapshot is not a {} snapshot", component)),
+        None => return Err("empty snapshot".to_string()),
+    };
+    if payload.len() != component.payload_len() {
+        return Err(format!(
+            "{} snapshot is {} bytes, expected {}",
+            component,
+            payload.len(),
+            component.payload_len()
+        ));
+    }
+
+    match component {
+        Component::Cpu => {
+            let (words, flags) = payload.split_at(CPU_REGISTERS.len() * 2);
+            for (register, bytes) in CPU_REGISTERS.into_iter().zip(words.chunks_exact(2)) {
+                cpu.registers
+                    .write_r16(register, u16::from_le_bytes([bytes[0], bytes[1]]));
+            }
+            cpu.registers.write_ime(flags[0]);
+            cpu.ei_pending = flags[1] != 0;
+            cpu.halted = flags[2] != 0;
+            cpu.halt_bug = flags[3] != 0;
+        }
+        Component::Wram => mem.work_ram_mut().copy_from_slice(payload),
+        Component::Hram => mem.high_ram_mut().copy_from_slice(payload),
+        Component::Vram => mem.ppu.vram_mut().copy_from_slice(payload),
+        Component::Oam => mem.ppu.oam.copy_from_slice(payload),
+        Component::Palettes => mem.ppu.set_palettes((payload[0], payload[1], payload[2])),
+    }
+    Ok(())
+}
+
+/// Little-endian field writer for full states
+#[derive(Debug, Default)]
+pub struct StateWriter {
+    data: Vec<u8>,
+}
+
+impl StateWriter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn u8(&mut self, value: u8) {
+        self.data.push(value);
+    }
+
+    pub fn bool(&mut self, value: bool) {
+        self.data.push(value as u8);
+    }
+
+    pub fn u16(&mut self, value: u16) {
+        self.data.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn u32(&mut self, value: u32) {
+        self.data.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn u64(&mut self, value: u64) {
+        self.data.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn bytes(&mut self, bytes: &[u8]) {
+        self.data.extend_from_slice(bytes);
+    }
+
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.data
+    }
+}
+
+/// Reads fields in the order a StateWriter wrote them
+#[derive(Debug)]
+pub struct StateReader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> StateReader<'a> {
+    pub fn new(data: &'a [u8]) -> Self {
+        Self { data }
+    }
+
+    pub fn bytes(&mut self, len: usize) -> Result<&'a [u8], String> {
+        if self.data.len() < len {
+            return Err("state is truncated".to_string());
+        }
+        let (bytes, rest) = self.data.split_at(len);
+        self.data = rest;
+        Ok(bytes)
+    }
+
+    /// Fill `out` from the next `out.len()` bytes
+    pub fn fill(&mut self, out: &mut [u8]) -> Result<(), String> {
+        out.copy_from_slice(self.bytes(out.len())?);
+        Ok(())
+    }
+
+    pub fn u8(&mut self) -> Result<u8, String> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    pub fn bool(&mut self) -> Result<bool, String> {
+        Ok(self.u8()? != 0)
+    }
+
+    pub fn u16(&mut self) -> Result<u16, String> {
+        Ok(u16::from_le_bytes(self.bytes(2)?.try_into().unwrap()))
+    }
+
+    pub fn u32(&mut self) -> Result<u32, String> {
+        Ok(u32::from_le_bytes(self.bytes(4)?.try_into().unwrap()))
+    }
+
+    pub fn u64(&mut self) -> Result<u64, String> {
+        Ok(u64::from_le_bytes(self.bytes(8)?.try_into().unwrap()))
+    }
+
+    /// Bytes not read yet
+    pub fn remaining(&self) -> usize {
+        self.data.len()
+    }
+}
+
+// Header checksum (0x014D) and global checksum (0x014E-0x014F) of the loaded cartridge
+fn cartridge_id(mem: &Memory) -> [u8; 3] {
+    [0x014D, 0x014E, 0x014F].map(|address| mem.rom.buffer.get(address).copied().unwrap_or(0))
+}
+
+/// Snapshot the whole machine
+pub fn save_state(cpu: &Cpu, mem: &Memory) -> Vec<u8> {
+    let mut writer = StateWriter::new();
+    writer.bytes(MAGIC);
+    writer.u16(STATE_VERSION);
+    writer.bytes(&cartridge_id(mem));
+    cpu.save_state(&mut writer);
+    mem.save_state(&mut writer);
+    writer.into_bytes()
+}
+
+/// Restore a state taken with `save_state`. On error the machine is left untouched.
+pub fn load_state(cpu: &mut Cpu, mem: &mut Memory, data: &[u8]) -> Result<(), String> {
+    let mut reader = StateReader::new(data);
+    if reader.bytes(MAGIC.len()).ok() != Some(&MAGIC[..]) {
+        return Err("not a save state".to_string());
+    }
+    let version = reader.u16()?;
+    if version != STATE_VERSION {
+        return Err(format!(
+            "save state version {} is not supported (expected {})",
+            version, STATE_VERSION
+        ));
+    }
+    if reader.bytes(3)? != cartridge_id(mem) {
+        return Err("save state is from a different cartridge".to_string());
+    }
+
+    let mut new_cpu = cpu.clone();
+    let mut new_mem = mem.clone();
+    new_cpu.load_state(&mut reader)?;
+    new_mem.load_state(&mut reader)?;
+    if reader.remaining() != 0 {
+        return Err(format!(
+            "save state has {} unexpected trailing bytes",
+            reader.remaining()
+        ));
+    }
+    new_mem.ppu.mark_all_rows_dirty();
+    *cpu = new_cpu;
+    *mem = new_mem;
+    Ok(())
+}
+
+/// Serde for arrays longer than the 32 elements serde supports, as sequences
+#[cfg(feature = "serde")]
+pub(crate) mod serde_array {
+    use serde::de::Error;
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    pub fn serialize<S: Serializer, T: Serialize, const N: usize>(
+        array: &[T; N],
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        array[..].serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>, T: Deserialize<'de>, const N: usize>(
+        deserializer: D,
+    ) -> Result<[T; N], D::Error> {
+        let items = Vec::<T>::deserialize(deserializer)?;
+        items.try_into().map_err(|items: Vec<T>| {
+            D::Error::invalid_length(items.len(), &format!("{} elements", N).as_str())
+        })
+    }
+
+    /// The same for boxed arrays
+    pub mod boxed {
+        use super::*;
+
+        pub fn serialize<S: Serializer, T: Serialize, const N: usize>(
+            array: &[T; N],
+            serializer: S,
+        ) -> Result<S::Ok, S::Error> {
+            super::serialize(array, serializer)
+        }
+
+        pub fn deserialize<'de, D: Deserializer<'de>, T: Deserialize<'de>, const N: usize>(
+            deserializer: D,
+        ) -> Result<Box<[T; N]>, D::Error> {
+            super::deserialize(deserializer).map(Box::new)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gameboy::GameBoy;
+    use crate::model::Model;
+    use crate::testrom::assemble;
+
+    fn machine() -> (Cpu, Memory) {
+        let mut mem = Memory::new(vec![0; 0x8000]);
+        mem.skip_boot(Model::Dmg);
+        let mut cpu = Cpu::new();
+        cpu.skip_boot(Model::Dmg, 0);
+        (cpu, mem)
+    }
+
+    #[test]
+    fn test_round_trip_restores_only_that_component() {
+        let (mut cpu, mut mem) = machine();
+        mem.write_8(0xC123, 0x42);
+        mem.write_8(0x8010, 0x99);
+        cpu.registers.write_r16(Reg16::BC, 0x1234);
+        cpu.halted = true;
+
+        let snapshots: Vec<_> = Component::ALL
+            .into_iter()
+            .map(|component| serialize_component(&cpu, &mem, component))
+            .collect();
+
+        mem.write_8(0xC123, 0x00);
+        mem.write_8(0x8010, 0x00);
+        mem.write_8(0xFF47, 0x1B);
+        cpu.registers.write_r16(Reg16::BC, 0);
+        cpu.halted = false;
+
+        restore_component(&mut cpu, &mut mem, Component::Wram, &snapshots[1]).unwrap();
+        assert_eq!(mem.read_8(0xC123), 0x42);
+        assert_eq!(mem.read_8(0x8010), 0x00);
+        assert_eq!(cpu.registers.read_r16(Reg16::BC), 0);
+
+        restore_component(&mut cpu, &mut mem, Component::Cpu, &snapshots[0]).unwrap();
+        assert_eq!(cpu.registers.read_r16(Reg16::BC), 0x1234);
+        assert!(cpu.halted);
+
+        restore_component(&mut cpu, &mut mem, Component::Vram, &snapshots[3]).unwrap();
+        restore_component(&mut cpu, &mut mem, Component::Palettes, &snapshots[5]).unwrap();
+        assert_eq!(mem.read_8(0x8010), 0x99);
+        assert_eq!(mem.read_8(0xFF47), 0xFC);
+    }
+
+    #[test]
+    fn test_restore_rejects_mismatched_snapshot() {
+        let (mut cpu, mut mem) = machine();
+        let vram = serialize_component(&cpu, &mem, Component::Vram);
+        assert!(restore_component(&mut cpu, &mut mem, Component::Oam, &vram).is_err());
+        assert!(restore_component(&mut cpu, &mut mem, Component::Vram, &vram[..100]).is_err());
+        assert!(restore_component(&mut cpu, &mut mem, Component::Vram, &[]).is_err());
+        assert_eq!("palettes".parse(), Ok(Component::Palettes));
+    }
+
+    // Counts up in C000 and scrolls the screen with the count, forever
+    const COUNTER_ROM: &str = "
+        org 0x0100
+        loop:
+        ld a, (0xC000)
+        inc a
+        ld (0xC000), a
+        ldh (0x43), a
+        ldh a, (0x04)
+        ld (0xC001), a
+        jr loop
+        ";
+
+    #[test]
+    fn test_full_state_resumes_identically() {
+        let mut gb = GameBoy::new(assemble(COUNTER_ROM), Model::Dmg).unwrap();
+        gb.run_frame();
+        let state = gb.save_state();
+
+        gb.run_frame();
+        gb.run_frame();
+        let expected_ram = gb.memory().work_ram()[..2].to_vec();
+        let expected_frame = gb.framebuffer().to_vec();
+        let expected_pc = gb.cpu().registers.read_r16(Reg16::PC);
+
+        gb.load_state(&state).unwrap();
+        gb.run_frame();
+        gb.run_frame();
+        assert_eq!(gb.memory().work_ram()[..2], expected_ram[..]);
+        assert_eq!(gb.framebuffer(), &expected_frame[..]);
+        assert_eq!(gb.cpu().registers.read_r16(Reg16::PC), expected_pc);
+        assert_eq!(gb.save_state().len(), state.len());
+    }
+
+    // Fills tiles 0x8000-0x8FFF with color 3, then flips LCDC between the two tile data
+    // areas forever, so lines mix black and white at whichever fetch saw each value
+    const LCDC_FLIP_ROM: &str = "
+        org 0x0100
+        xor a
+        ldh (0x40), a
+        ld hl, 0x8000
+        ld a, 0xFF
+        fill:
+        ld (hl+), a
+        bit 4, h
+        jr z, fill
+        ld a, 0x81
+        loop:
+        xor 0x10
+        ldh (0x40), a
+        jr loop
+        ";
+
+    // Step until the PPU completes a frame (LY enters 144) and return the picture
+    fn next_frame(gb: &mut GameBoy) -> Vec<u8> {
+        while gb.memory().read_8(0xFF44) == 144 {
+            gb.step();
+        }
+        while gb.memory().read_8(0xFF44) != 144 {
+            gb.step();
+        }
+        gb.framebuffer().to_vec()
+    }
+
+    #[test]
+    fn test_state_saved_mid_frame_resumes_pixel_exact() {
+        let mut seed = 0x2545_F491u32;
+        for _ in 0..16 {
+            // Save at a pseudo-random dot of the second frame
+            seed ^= seed << 13;
+            seed ^= seed >> 17;
+            seed ^= seed << 5;
+            let mut gb = GameBoy::new(assemble(LCDC_FLIP_ROM), Model::Dmg).unwrap();
+            next_frame(&mut gb);
+            for _ in 0..seed % 8000 {
+                gb.step();
+            }
+            let state = gb.save_state();
+            let expected = next_frame(&mut gb);
+
+            let mut restored = GameBoy::new(assemble(LCDC_FLIP_ROM), Model::Dmg).unwrap();
+            restored.load_state(&state).unwrap();
+            assert!(next_frame(&mut restored) == expected, "seed {:08X}", seed);
+        }
+    }
+
+    #[test]
+    fn test_load_state_rejects_bad_states() {
+        let mut gb = GameBoy::new(assemble(COUNTER_ROM), Model::Dmg).unwrap();
+        gb.run_frame();
+        let state = gb.save_state();
+        let counter = gb.memory().work_ram()[0];
+
+        let mut other_rom = assemble(COUNTER_ROM);
+        other_rom[0x014D] ^= 0xFF;
+        let other = GameBoy::new(other_rom, Model::Dmg).unwrap();
+        assert!(gb.load_state(&other.save_state()).is_err());
+
+        let mut old_version = state.clone();
+        old_version[4..6].copy_from_slice(&0u16.to_le_bytes());
+        assert!(gb.load_state(&old_version).is_err());
+        assert!(gb.load_state(&state[..state.len() - 1]).is_err());
+        assert!(gb.load_state(b"not a state").is_err());
+        assert_eq!(gb.memory().work_ram()[0], counter);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_serde_array_checks_the_length() {
+        use serde::de::value::{Error, SeqDeserializer};
+        let items = |items: Vec<u8>| SeqDeserializer::<_, Error>::new(items.into_iter());
+        let array: [u8; 3] = serde_array::deserialize(items(vec![1, 2, 3])).unwrap();
+        assert_eq!(array, [1, 2, 3]);
+        let error = serde_array::deserialize::<_, u8, 3>(items(vec![1])).unwrap_err();
+        assert_eq!(error.to_string(), "invalid length 1, expected 3 elements");
+    }
+}
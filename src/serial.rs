@@ -4,12 +4,79 @@
 // 0xFF02 - SC (Serial Transfer Control)
 //   Bit 7: Transfer Start Flag (1=Start, 0=None)
 //   Bit 0: Shift Clock (1=Internal, 0=External)
+//
+// Transfers only progress when `tick` is called (AccuracySettings::serial_timing). With the
+// internal clock one bit is shifted every 512 T-cycles; with no link partner the incoming
+// bits are all 1s, so SB reads 0xFF and the interrupt fires 8 * 512 cycles after the start.
+// Transfers on the external clock wait for a partner and never complete.
+//
+// With the loopback device plugged in, the cable's output is wired back to its input and
+// the device also clocks external-clock transfers, so every transfer completes after the
+// usual 8 * 512 cycles with the sent byte back in SB. Homebrew link code can then be
+// exercised in one emulator instance, on either end of the protocol.
+//
+// A link cable to another machine (see link.rs, and netlink.rs over TCP) takes over
+// internal-clock transfers, which end with SB swapped for the partner's byte, and
+// completes external-clock transfers when the partner clocks one.
 
+use std::fmt;
+use std::str::FromStr;
+use std::sync::Arc;
+
+use crate::link::SerialLink;
+use crate::savestate::{StateReader, StateWriter};
+
+// T-cycles per bit with the internal 8192 Hz shift clock
+const INTERNAL_CLOCK_BIT_CYCLES: u32 = 512;
+
+/// What is plugged into the link port
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SerialDevice {
+    /// No cable: 1s are shifted in, external-clock transfers never complete
+    #[default]
+    Disconnected,
+    /// Sent bits come straight back, on either clock
+    Loopback,
+}
+
+impl FromStr for SerialDevice {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "none" => Ok(SerialDevice::Disconnected),
+            "loopback" => Ok(SerialDevice::Loopback),
+            other => Err(format!(
+                "unknown serial device '{}' (expected none or loopback)",
+                other
+            )),
+        }
+    }
+}
+
+impl fmt::Display for SerialDevice {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            SerialDevice::Disconnected => "none",
+            SerialDevice::Loopback => "loopback",
+        })
+    }
+}
+
+#[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Serial {
     sb: u8,                      // Serial transfer data
     sc: u8,                      // Serial transfer control
     pub interrupt_pending: bool, // Serial interrupt flag
-    pub output_buffer: Vec<u8>,  // Buffer for captured output
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub output_buffer: Vec<u8>, // Buffer for captured output
+    bits_remaining: u8,          // Bits left in the internal-clock transfer in progress
+    bit_cycles: u32,             // T-cycles spent on the current bit
+    #[cfg_attr(feature = "serde", serde(skip))]
+    device: SerialDevice, // Link port partner
+    #[cfg_attr(feature = "serde", serde(skip))]
+    link: Option<Arc<dyn SerialLink>>, // Cable to another machine
 }
 
 impl Default for Serial {
@@ -25,6 +92,10 @@ impl Serial {
             sc: 0,
             interrupt_pending: false,
             output_buffer: Vec::new(),
+            bits_remaining: 0,
+            bit_cycles: 0,
+            device: SerialDevice::Disconnected,
+            link: None,
         }
     }
 
@@ -55,15 +126,87 @@ impl Serial {
                         self.output_buffer.push(self.sb);
                     }
 
-                    // DON'T complete the transfer - let bit 7 stay set
-                    // DON'T set interrupt_pending
-                    // Tetris will eventually give up and continue
+                    // Without `tick` the transfer never completes: bit 7 stays set and
+                    // no interrupt is raised. Tetris will eventually give up and continue
+                    if (value & 0x01) != 0 || self.device == SerialDevice::Loopback {
+                        self.bits_remaining = 8;
+                        self.bit_cycles = 0;
+                    }
+                } else {
+                    self.bits_remaining = 0;
+                }
+                if self.sc & 0x81 != 0x80 {
+                    if let Some(link) = &self.link {
+                        link.cancel();
+                    }
                 }
             }
             _ => {}
         }
     }
 
+    /// Advance an internal-clock transfer by `t_cycles`, or see whether the link partner
+    /// clocked an external-clock one
+    pub fn tick(&mut self, t_cycles: u32) {
+        if self.bits_remaining == 0 {
+            if self.sc & 0x81 == 0x80 {
+                if let Some(byte) = self.link.as_ref().and_then(|link| link.poll(self.sb)) {
+                    self.sb = byte;
+                    self.finish_transfer();
+                }
+            }
+            return;
+        }
+        // The cable swaps whole bytes, so SB keeps the outgoing byte until the end
+        let link = self.link.clone().filter(|_| self.sc & 0x01 != 0);
+        self.bit_cycles += t_cycles;
+        while self.bit_cycles >= INTERNAL_CLOCK_BIT_CYCLES && self.bits_remaining > 0 {
+            self.bit_cycles -= INTERNAL_CLOCK_BIT_CYCLES;
+            if link.is_none() {
+                let incoming = match self.device {
+                    // No link partner: a 1 is shifted in
+                    SerialDevice::Disconnected => 1,
+                    SerialDevice::Loopback => self.sb >> 7,
+                };
+                self.sb = (self.sb << 1) | incoming;
+            }
+            self.bits_remaining -= 1;
+            if self.bits_remaining == 0 {
+                if let Some(link) = &link {
+                    self.sb = link.exchange(self.sb).unwrap_or(0xFF);
+                }
+                self.finish_transfer();
+            }
+        }
+    }
+
+    fn finish_transfer(&mut self) {
+        self.sc &= 0x7F;
+        self.interrupt_pending = true;
+    }
+
+    pub fn device(&self) -> SerialDevice {
+        self.device
+    }
+
+    /// Plug `device` into the link port; takes effect from the next transfer
+    pub fn set_device(&mut self, device: SerialDevice) {
+        self.device = device;
+    }
+
+    pub fn link(&self) -> Option<&Arc<dyn SerialLink>> {
+        self.link.as_ref()
+    }
+
+    /// Connect a cable to another machine, or unplug it with None. Internal-clock
+    /// transfers go through it instead of `device`.
+    pub fn set_link(&mut self, link: Option<Arc<dyn SerialLink>>) {
+        if let Some(old) = &self.link {
+            old.cancel();
+        }
+        self.link = link;
+    }
+
     // Clear the interrupt flag (called after interrupt is serviced)
     pub fn clear_interrupt(&mut self) {
         self.interrupt_pending = false;
@@ -78,6 +221,14 @@ impl Serial {
         }
     }
 
+    /// Pass the captured output to `sink` a byte at a time, oldest first, and remove it.
+    /// The buffer keeps its capacity, so draining every frame does not allocate.
+    pub fn drain_output(&mut self, sink: &mut impl FnMut(u8)) {
+        for byte in self.output_buffer.drain(..) {
+            sink(byte);
+        }
+    }
+
     // Get all output as a string
     pub fn get_output_string(&self) -> String {
         String::from_utf8_lossy(&self.output_buffer).to_string()
@@ -89,6 +240,26 @@ impl Serial {
     }
 }
 
+impl Serial {
+    // The captured output is a host-side log, not machine state
+    pub(crate) fn save_state(&self, w: &mut StateWriter) {
+        w.u8(self.sb);
+        w.u8(self.sc);
+        w.bool(self.interrupt_pending);
+        w.u8(self.bits_remaining);
+        w.u32(self.bit_cycles);
+    }
+
+    pub(crate) fn load_state(&mut self, r: &mut StateReader) -> Result<(), String> {
+        self.sb = r.u8()?;
+        self.sc = r.u8()?;
+        self.interrupt_pending = r.bool()?;
+        self.bits_remaining = r.u8()?;
+        self.bit_cycles = r.u32()?;
+        Ok(())
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -104,7 +275,7 @@ mod tests {
         // Start transfer by setting bit 7 of SC
         serial.write(0xFF02, 0x81);
 
-        // Note: Transfer is NOT completed automatically to avoid Tetris link cable issues
+        // Note: Transfer is NOT completed until `tick` advances it
         // SB remains unchanged, SC keeps bit 7 set, no interrupt is generated
         assert_eq!(serial.read(0xFF01), 0x42); // SB unchanged
         assert_eq!(serial.read(0xFF02) & 0x80, 0x80); // Transfer flag still set
@@ -140,4 +311,67 @@ mod tests {
 
         assert_eq!(serial.get_output_string(), "Hello");
     }
+
+    #[test]
+    fn test_drain_output() {
+        let mut serial = Serial::new();
+        for &byte in b"Hi" {
+            serial.write(0xFF01, byte);
+            serial.write(0xFF02, 0x81);
+        }
+
+        let mut drained = Vec::new();
+        serial.drain_output(&mut |byte| drained.push(byte));
+        assert_eq!(drained, b"Hi");
+        // Drained bytes are gone; the buffer keeps its storage for the next ones
+        serial.drain_output(&mut |_| panic!("nothing left to drain"));
+        assert!(serial.output_buffer.capacity() >= 2);
+    }
+
+    #[test]
+    fn test_timed_transfer_completes_after_eight_bits() {
+        let mut serial = Serial::new();
+        serial.write(0xFF01, 0x00);
+        serial.write(0xFF02, 0x81);
+
+        serial.tick(INTERNAL_CLOCK_BIT_CYCLES * 3);
+        assert_eq!(serial.read(0xFF01), 0x07); // Three 1 bits shifted in so far
+        serial.tick(INTERNAL_CLOCK_BIT_CYCLES * 5 - 1);
+        assert!(!serial.interrupt_pending);
+        assert_eq!(serial.read(0xFF02) & 0x80, 0x80);
+
+        serial.tick(1);
+        assert!(serial.interrupt_pending);
+        assert_eq!(serial.read(0xFF01), 0xFF);
+        assert_eq!(serial.read(0xFF02) & 0x80, 0x00);
+    }
+
+    #[test]
+    fn test_external_clock_transfer_waits_for_partner() {
+        let mut serial = Serial::new();
+        serial.write(0xFF02, 0x80);
+        serial.tick(INTERNAL_CLOCK_BIT_CYCLES * 16);
+        assert!(!serial.interrupt_pending);
+        assert_eq!(serial.read(0xFF02) & 0x80, 0x80);
+    }
+
+    #[test]
+    fn test_loopback_returns_sent_byte_on_either_clock() {
+        let mut serial = Serial::new();
+        serial.set_device("loopback".parse().unwrap());
+
+        for control in [0x81, 0x80] {
+            serial.interrupt_pending = false;
+            serial.write(0xFF01, 0xA5);
+            serial.write(0xFF02, control);
+            serial.tick(INTERNAL_CLOCK_BIT_CYCLES * 4);
+            assert_eq!(serial.read(0xFF01), 0x5A); // Halfway: the nibbles have swapped
+            serial.tick(INTERNAL_CLOCK_BIT_CYCLES * 4);
+            assert!(serial.interrupt_pending);
+            assert_eq!(serial.read(0xFF01), 0xA5);
+            assert_eq!(serial.read(0xFF02) & 0x80, 0x00);
+        }
+        assert_eq!(SerialDevice::Loopback.to_string(), "loopback");
+        assert!("modem".parse::<SerialDevice>().is_err());
+    }
 }
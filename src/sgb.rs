@@ -0,0 +1,325 @@
+// Super Game Boy command packets
+// SGB commands are sent bit by bit through P1 (FF00) writes: a reset pulse (P14=P15=0),
+// 128 data bits (P14=0 => 0, P15=0 => 1, each followed by P14=P15=1), then a stop bit.
+// MLT_REQ is acted on. SOUND and SOU_TRN, which play jingles on the SNES side, are
+// decoded into events for the frontend; other commands are decoded so their packets are
+// skipped.
+
+pub const PACKET_SIZE: usize = 16;
+
+const CMD_SOUND: u8 = 0x08;
+const CMD_SOU_TRN: u8 = 0x09;
+const CMD_MLT_REQ: u8 = 0x11;
+
+/// Effect number that stops the effect playing on its port
+pub const SOUND_STOP: u8 = 0x80;
+
+// Built-in SNES sound effects, numbered from 1
+const EFFECTS_A: [&str; 0x30] = [
+    "Nintendo",
+    "Game Over",
+    "Drop",
+    "OK A",
+    "OK B",
+    "Select A",
+    "Select B",
+    "Select C",
+    "Mistake (buzzer)",
+    "Catch item",
+    "Gate squeaks",
+    "Explosion (small)",
+    "Explosion (medium)",
+    "Explosion (large)",
+    "Attacked A",
+    "Attacked B",
+    "Hit (punch) A",
+    "Hit (punch) B",
+    "Breath in air",
+    "Rocket projectile A",
+    "Rocket projectile B",
+    "Escaping bubble",
+    "Jump",
+    "Fast jump",
+    "Jet takeoff",
+    "Jet landing",
+    "Cup breaking",
+    "Glass breaking",
+    "Level up",
+    "Insert air",
+    "Sword swing",
+    "Water falling",
+    "Fire",
+    "Wall collapsing",
+    "Cancel",
+    "Walking",
+    "Blocking strike",
+    "Picture floats on and off",
+    "Fade in",
+    "Fade out",
+    "Window opening",
+    "Window closing",
+    "Big laser",
+    "Stone gate",
+    "Teleportation",
+    "Lightning",
+    "Earthquake",
+    "Small laser",
+];
+const EFFECTS_B: [&str; 0x19] = [
+    "Applause (small group)",
+    "Applause (medium group)",
+    "Applause (large group)",
+    "Wind",
+    "Rain",
+    "Storm",
+    "Storm with wind and thunder",
+    "Lightning",
+    "Earthquake",
+    "Avalanche",
+    "Wave",
+    "River",
+    "Waterfall",
+    "Small character running",
+    "Horse running",
+    "Warning sound",
+    "Approaching car",
+    "Jet flying",
+    "UFO flying",
+    "Electromagnetic waves",
+    "Score up",
+    "Fire",
+    "Camera shutter",
+    "Write",
+    "Show up title",
+];
+
+/// Name of built-in sound effect `effect` on port A, if it has one
+pub fn effect_a_name(effect: u8) -> Option<&'static str> {
+    EFFECTS_A.get((effect as usize).wrapping_sub(1)).copied()
+}
+
+/// Name of built-in sound effect `effect` on port B, if it has one
+pub fn effect_b_name(effect: u8) -> Option<&'static str> {
+    EFFECTS_B.get((effect as usize).wrapping_sub(1)).copied()
+}
+
+/// A SOUND command: up to two effects, one per port, and a music score.
+/// Effect 0 leaves the port alone and `SOUND_STOP` silences it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SgbSound {
+    pub effect_a: u8,
+    pub effect_b: u8,
+    /// Pitch of each port's effect, 0 (low) to 3 (high)
+    pub pitch_a: u8,
+    pub pitch_b: u8,
+    /// Volume of each port's effect: 0 high, 1 medium, 2 low, 3 mute
+    pub volume_a: u8,
+    pub volume_b: u8,
+    /// Music score from data sent with SOU_TRN; 0 for none
+    pub music: u8,
+}
+
+impl SgbSound {
+    fn decode(packet: &[u8; PACKET_SIZE]) -> SgbSound {
+        let attributes = packet[3];
+        SgbSound {
+            effect_a: packet[1],
+            effect_b: packet[2],
+            pitch_a: attributes & 0x03,
+            volume_a: (attributes >> 2) & 0x03,
+            pitch_b: (attributes >> 4) & 0x03,
+            volume_b: (attributes >> 6) & 0x03,
+            music: packet[4],
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SgbCommand {
+    /// Multiplayer request: number of joypads multiplexed through P1 (1, 2 or 4)
+    MltReq { players: u8 },
+    /// Play or stop SNES sound effects
+    Sound(SgbSound),
+    /// Upload SNES sound program or music data; it arrives through VRAM, which is not
+    /// captured
+    SouTrn,
+    /// Any command not emulated yet, with its command code
+    Unsupported(u8),
+}
+
+impl SgbCommand {
+    /// Decode the first packet of a command
+    pub fn decode(packet: &[u8; PACKET_SIZE]) -> SgbCommand {
+        match packet[0] >> 3 {
+            CMD_SOUND => SgbCommand::Sound(SgbSound::decode(packet)),
+            CMD_SOU_TRN => SgbCommand::SouTrn,
+            CMD_MLT_REQ => SgbCommand::MltReq {
+                players: match packet[1] & 0x03 {
+                    1 => 2,
+                    3 => 4,
+                    _ => 1,
+                },
+            },
+            code => SgbCommand::Unsupported(code),
+        }
+    }
+}
+
+/// Reassembles command packets from P1 writes
+#[derive(Debug, Clone, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SgbPacketReceiver {
+    packet: [u8; PACKET_SIZE],
+    // Bits received so far in the current packet, None when not receiving
+    bit: Option<usize>,
+    // Waiting for P14=P15=1 between pulses
+    awaiting_release: bool,
+    // Packets still to come for a multi-packet command
+    continuation_packets: u8,
+}
+
+impl SgbPacketReceiver {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Feed a P1 write (bits 4-5); returns a command once its first packet is complete
+    pub fn write(&mut self, value: u8) -> Option<SgbCommand> {
+        let lines = value & 0x30;
+        if lines == 0x30 {
+            self.awaiting_release = false;
+            return None;
+        }
+        if self.awaiting_release {
+            return None;
+        }
+        self.awaiting_release = true;
+
+        if lines == 0x00 {
+            // Reset pulse: start of a packet
+            self.packet = [0; PACKET_SIZE];
+            self.bit = Some(0);
+            return None;
+        }
+
+        let bit = self.bit?;
+        let one = lines == 0x10;
+        if bit == PACKET_SIZE * 8 {
+            // Stop bit
+            self.bit = None;
+            return if one { None } else { self.finish_packet() };
+        }
+        if one {
+            self.packet[bit / 8] |= 1 << (bit % 8);
+        }
+        self.bit = Some(bit + 1);
+        None
+    }
+
+    fn finish_packet(&mut self) -> Option<SgbCommand> {
+        if self.continuation_packets > 0 {
+            self.continuation_packets -= 1;
+            return None;
+        }
+        let length = (self.packet[0] & 0x07).max(1);
+        self.continuation_packets = length - 1;
+        Some(SgbCommand::decode(&self.packet))
+    }
+}
+
+// P1 writes a game performs to send one packet
+#[cfg(test)]
+pub(crate) fn packet_writes(packet: &[u8; PACKET_SIZE]) -> Vec<u8> {
+    let mut writes = vec![0x00, 0x30];
+    for i in 0..PACKET_SIZE * 8 {
+        let one = packet[i / 8] & (1 << (i % 8)) != 0;
+        writes.push(if one { 0x10 } else { 0x20 });
+        writes.push(0x30);
+    }
+    writes.extend([0x20, 0x30]);
+    writes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn send(receiver: &mut SgbPacketReceiver, packet: &[u8; PACKET_SIZE]) -> Vec<SgbCommand> {
+        packet_writes(packet)
+            .into_iter()
+            .filter_map(|value| receiver.write(value))
+            .collect()
+    }
+
+    #[test]
+    fn test_mlt_req_packet() {
+        let mut receiver = SgbPacketReceiver::new();
+        let mut packet = [0; PACKET_SIZE];
+        packet[0] = (CMD_MLT_REQ << 3) | 1;
+        packet[1] = 0x03;
+        assert_eq!(
+            send(&mut receiver, &packet),
+            vec![SgbCommand::MltReq { players: 4 }]
+        );
+
+        packet[1] = 0x00;
+        assert_eq!(
+            send(&mut receiver, &packet),
+            vec![SgbCommand::MltReq { players: 1 }]
+        );
+    }
+
+    #[test]
+    fn test_sound_packet() {
+        let mut receiver = SgbPacketReceiver::new();
+        let mut packet = [0; PACKET_SIZE];
+        packet[0] = (CMD_SOUND << 3) | 1;
+        packet[1] = 0x02;
+        packet[2] = SOUND_STOP;
+        packet[3] = 0b10_01_11_00;
+        let sound = SgbSound {
+            effect_a: 0x02,
+            effect_b: SOUND_STOP,
+            pitch_a: 0,
+            volume_a: 3,
+            pitch_b: 1,
+            volume_b: 2,
+            music: 0,
+        };
+        assert_eq!(send(&mut receiver, &packet), vec![SgbCommand::Sound(sound)]);
+        assert_eq!(effect_a_name(0x02), Some("Game Over"));
+        assert_eq!(effect_a_name(0x30), Some("Small laser"));
+        assert_eq!(effect_b_name(0x19), Some("Show up title"));
+        assert_eq!(effect_a_name(0), None);
+        assert_eq!(effect_b_name(SOUND_STOP), None);
+
+        packet[0] = (CMD_SOU_TRN << 3) | 1;
+        assert_eq!(send(&mut receiver, &packet), vec![SgbCommand::SouTrn]);
+    }
+
+    #[test]
+    fn test_multi_packet_command_is_skipped() {
+        let mut receiver = SgbPacketReceiver::new();
+        // PAL_TRN-like command spanning 2 packets
+        let mut first = [0; PACKET_SIZE];
+        first[0] = (0x0B << 3) | 2;
+        assert_eq!(
+            send(&mut receiver, &first),
+            vec![SgbCommand::Unsupported(0x0B)]
+        );
+        // The continuation packet is not decoded as a new command
+        let mut continuation = [0; PACKET_SIZE];
+        continuation[0] = (CMD_MLT_REQ << 3) | 1;
+        assert!(send(&mut receiver, &continuation).is_empty());
+    }
+
+    #[test]
+    fn test_ordinary_joypad_polling_is_ignored() {
+        let mut receiver = SgbPacketReceiver::new();
+        for _ in 0..100 {
+            for value in [0x20, 0x10, 0x30] {
+                assert_eq!(receiver.write(value), None);
+            }
+        }
+    }
+}
@@ -0,0 +1,259 @@
+// Play statistics
+// Time played, frames emulated, savestate use and when each game was last played, kept
+// per ROM in the data directory. Games are identified by their header and global
+// checksums (0x014D-0x014F), so renaming or moving a ROM file keeps its history. The file
+// has one game per line: id, seconds played, frames, sessions, states saved, states
+// loaded, last played (Unix time), then the title for display.
+//
+// Time played is emulated time: pauses and menus do not count, and fast-forwarded play
+// counts at the speed the game saw it.
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::cartridge::CartridgeHeader;
+use crate::clock::{EmuDuration, CPU_FREQUENCY};
+
+/// Totals for one game
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct GameStats {
+    pub title: String,
+    pub play_time: EmuDuration,
+    pub frames: u64,
+    pub sessions: u64,
+    pub states_saved: u64,
+    pub states_loaded: u64,
+    /// Seconds since the Unix epoch at the end of the last session
+    pub last_played: u64,
+}
+
+/// One run of a game, added to its totals with `PlayStats::record`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Session {
+    pub play_time: EmuDuration,
+    pub frames: u64,
+    pub states_saved: u64,
+    pub states_loaded: u64,
+    /// Seconds since the Unix epoch when the session ended
+    pub ended: u64,
+}
+
+/// Statistics of every game played, keyed by `game_id`
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PlayStats {
+    games: BTreeMap<String, GameStats>,
+}
+
+/// Identifier of the game in `rom`: its header and global checksums as hex
+pub fn game_id(rom: &[u8]) -> String {
+    [0x014D, 0x014E, 0x014F]
+        .iter()
+        .map(|&address| format!("{:02X}", rom.get(address).copied().unwrap_or(0)))
+        .collect()
+}
+
+impl PlayStats {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// `$GBEMU_STATS`, or `gbemu_rust/stats` in the XDG data directory
+    pub fn default_path() -> Option<PathBuf> {
+        if let Some(path) = std::env::var_os("GBEMU_STATS") {
+            return Some(PathBuf::from(path));
+        }
+        let data_dir = std::env::var_os("XDG_DATA_HOME")
+            .map(PathBuf::from)
+            .or_else(|| {
+                std::env::var_os("HOME").map(|home| Path::new(&home).join(".local").join("share"))
+            })?;
+        Some(data_dir.join("gbemu_rust").join("stats"))
+    }
+
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let mut stats = PlayStats::new();
+        for (index, line) in text.lines().enumerate() {
+            if line.trim().is_empty() {
+                continue;
+            }
+            let fields: Vec<&str> = line.splitn(8, ' ').collect();
+            let [id, seconds, frames, sessions, saved, loaded, last_played, title] = fields[..]
+            else {
+                return Err(format!("line {}: expected 8 fields", index + 1));
+            };
+            let number = |value: &str| {
+                value
+                    .parse::<u64>()
+                    .map_err(|_| format!("line {}: invalid number '{}'", index + 1, value))
+            };
+            // Counted in cycles, so the seconds have to fit 64 bits once multiplied
+            let play_time = number(seconds)?
+                .checked_mul(CPU_FREQUENCY)
+                .map(EmuDuration::from_cycles)
+                .ok_or_else(|| format!("line {}: play time out of range", index + 1))?;
+            let game = GameStats {
+                title: title.to_string(),
+                play_time,
+                frames: number(frames)?,
+                sessions: number(sessions)?,
+                states_saved: number(saved)?,
+                states_loaded: number(loaded)?,
+                last_played: number(last_played)?,
+            };
+            stats.games.insert(id.to_string(), game);
+        }
+        Ok(stats)
+    }
+
+    /// Read the stats file; a missing file has no games
+    pub fn load(path: &Path) -> Result<Self, String> {
+        match fs::read_to_string(path) {
+            Ok(text) => Self::parse(&text).map_err(|err| format!("{}: {}", path.display(), err)),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(PlayStats::new()),
+            Err(err) => Err(format!("{}: {}", path.display(), err)),
+        }
+    }
+
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        fs::write(path, self.to_string())
+    }
+
+    pub fn get(&self, rom: &[u8]) -> Option<&GameStats> {
+        self.games.get(&game_id(rom))
+    }
+
+    /// Add `session` to the totals of the game in `rom`
+    pub fn record(&mut self, rom: &[u8], session: &Session) {
+        let game = self.games.entry(game_id(rom)).or_default();
+        game.title = CartridgeHeader::parse(rom).title;
+        game.play_time += session.play_time;
+        game.frames += session.frames;
+        game.sessions += 1;
+        game.states_saved += session.states_saved;
+        game.states_loaded += session.states_loaded;
+        game.last_played = session.ended;
+    }
+
+    /// Games, most recently played first
+    pub fn games(&self) -> Vec<&GameStats> {
+        let mut games: Vec<_> = self.games.values().collect();
+        games.sort_by_key(|game| std::cmp::Reverse(game.last_played));
+        games
+    }
+}
+
+impl fmt::Display for PlayStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (id, game) in &self.games {
+            writeln!(
+                f,
+                "{} {} {} {} {} {} {} {}",
+                id,
+                game.play_time.as_secs(),
+                game.frames,
+                game.sessions,
+                game.states_saved,
+                game.states_loaded,
+                game.last_played,
+                game.title
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// `H:MM:SS`
+pub fn format_play_time(time: EmuDuration) -> String {
+    let seconds = time.as_secs();
+    format!(
+        "{}:{:02}:{:02}",
+        seconds / 3600,
+        seconds / 60 % 60,
+        seconds % 60
+    )
+}
+
+/// `YYYY-MM-DD HH:MM` in UTC for seconds since the Unix epoch
+pub fn format_timestamp(unix_seconds: u64) -> String {
+    // Days to civil date, from Howard Hinnant's date algorithms
+    let days = (unix_seconds / 86400) as i64 + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    };
+    let year = year_of_era + era * 400 + (month <= 2) as i64;
+    let minutes = unix_seconds % 86400 / 60;
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}",
+        year,
+        month,
+        day,
+        minutes / 60,
+        minutes % 60
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rom(title: &str, checksum: u8) -> Vec<u8> {
+        let mut rom = vec![0; 0x8000];
+        rom[0x0134..0x0134 + title.len()].copy_from_slice(title.as_bytes());
+        rom[0x014D] = checksum;
+        rom
+    }
+
+    #[test]
+    fn test_record_and_round_trip() {
+        let tetris = rom("TETRIS", 0x0A);
+        let mut stats = PlayStats::new();
+        let session = Session {
+            play_time: EmuDuration::from_secs(90),
+            frames: 5400,
+            states_saved: 2,
+            states_loaded: 1,
+            ended: 1_700_000_000,
+        };
+        stats.record(&tetris, &session);
+        stats.record(&rom("POKEMON RED", 0x20), &Session::default());
+        stats.record(&tetris, &session);
+
+        let game = stats.get(&tetris).unwrap();
+        assert_eq!(game.title, "TETRIS");
+        assert_eq!(game.play_time, EmuDuration::from_secs(180));
+        assert_eq!((game.frames, game.sessions), (10800, 2));
+        assert_eq!((game.states_saved, game.states_loaded), (4, 2));
+        assert_eq!(stats.games()[0].title, "TETRIS");
+        assert_eq!(PlayStats::parse(&stats.to_string()), Ok(stats));
+
+        assert!(PlayStats::parse("0A0000 1 2 3").is_err());
+        assert!(PlayStats::parse("0A0000 x 0 0 0 0 0 TETRIS").is_err());
+        assert!(PlayStats::parse("0A0000 18446744073709551615 0 0 0 0 0 TETRIS").is_err());
+    }
+
+    #[test]
+    fn test_formatting() {
+        assert_eq!(
+            format_play_time(EmuDuration::from_secs(3 * 3600 + 62)),
+            "3:01:02"
+        );
+        assert_eq!(format_timestamp(0), "1970-01-01 00:00");
+        assert_eq!(format_timestamp(1_700_000_000), "2023-11-14 22:13");
+        assert_eq!(format_timestamp(951_782_400), "2000-02-29 00:00");
+    }
+}
@@ -0,0 +1,173 @@
+// Strict mode for homebrew development
+// Flags guest behavior that this emulator tolerates but that is almost always a bug on
+// hardware: writes to ROM on a cartridge with no mapper to receive them, reads of work RAM
+// the game never wrote (random at power-on), OAM access while the PPU owns it (modes 2 and
+// 3), and pops that take SP past the top of the stack. Off by default; see
+// `Memory::set_strict_enabled`.
+
+use std::collections::VecDeque;
+use std::fmt;
+
+/// Violations kept until they are taken; older ones are dropped
+pub const VIOLATION_LOG_CAPACITY: usize = 256;
+
+const WRAM_START: u16 = 0xC000;
+const WRAM_SIZE: usize = 0x2000;
+
+/// Something a game did that is likely a bug
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Violation {
+    /// Write to 0x0000-0x7FFF on a cartridge without a mapper
+    RomWrite { address: u16, value: u8 },
+    /// Read of a work RAM byte (or its echo) that has not been written since power-on
+    UninitializedRead { address: u16 },
+    /// CPU access to OAM with the LCD on and the PPU in mode 2 or 3
+    OamAccess { address: u16, mode: u8, write: bool },
+    /// Pop with SP at 0xFFFE or above, which wraps SP past 0xFFFF
+    StackUnderflow { sp: u16 },
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Violation::RomWrite { address, value } => write!(
+                f,
+                "write of {:02X} to ROM at {:04X} with no mapper",
+                value, address
+            ),
+            Violation::UninitializedRead { address } => {
+                write!(f, "read of uninitialized WRAM at {:04X}", address)
+            }
+            Violation::OamAccess {
+                address,
+                mode,
+                write,
+            } => write!(
+                f,
+                "OAM {} at {:04X} during mode {}",
+                if write { "write" } else { "read" },
+                address,
+                mode
+            ),
+            Violation::StackUnderflow { sp } => write!(f, "stack underflow (pop at SP {:04X})", sp),
+        }
+    }
+}
+
+/// A violation, stamped with when and where it happened
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StrictEvent {
+    /// T-cycle of the access (see `EmulatedClock::cycles`)
+    pub cycle: u64,
+    /// Address of the instruction that made the access
+    pub pc: u16,
+    pub violation: Violation,
+}
+
+impl fmt::Display for StrictEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "cycle {} PC {:04X}: {}",
+            self.cycle, self.pc, self.violation
+        )
+    }
+}
+
+/// Strict mode state kept by `Memory` while it is enabled
+#[derive(Debug, Clone)]
+pub struct StrictChecker {
+    // One bit per work RAM byte, set once the game has written it
+    wram_written: Box<[u64; WRAM_SIZE / 64]>,
+    // Instruction being executed, for stamping events
+    pc: u16,
+    events: VecDeque<StrictEvent>,
+}
+
+impl Default for StrictChecker {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl StrictChecker {
+    pub fn new() -> Self {
+        Self {
+            wram_written: Box::new([0; WRAM_SIZE / 64]),
+            pc: 0,
+            events: VecDeque::with_capacity(VIOLATION_LOG_CAPACITY),
+        }
+    }
+
+    /// Stamp later events with `pc`
+    pub fn set_pc(&mut self, pc: u16) {
+        self.pc = pc;
+    }
+
+    /// Record `violation` at `cycle`
+    pub fn report(&mut self, cycle: u64, violation: Violation) {
+        if self.events.len() == VIOLATION_LOG_CAPACITY {
+            self.events.pop_front();
+        }
+        self.events.push_back(StrictEvent {
+            cycle,
+            pc: self.pc,
+            violation,
+        });
+    }
+
+    /// Note a write; `address` is a work RAM address (0xC000-0xDFFF), echo already folded
+    pub fn mark_written(&mut self, address: u16) {
+        let index = (address - WRAM_START) as usize;
+        self.wram_written[index / 64] |= 1 << (index % 64);
+    }
+
+    /// Whether the work RAM byte at `address` has been written
+    pub fn is_written(&self, address: u16) -> bool {
+        let index = (address - WRAM_START) as usize;
+        self.wram_written[index / 64] & (1 << (index % 64)) != 0
+    }
+
+    /// Events since the last call, oldest first
+    pub fn take_events(&mut self) -> Vec<StrictEvent> {
+        self.events.drain(..).collect()
+    }
+}
+
+/// Work RAM address behind `address`, folding echo RAM, if it is work RAM at all
+pub fn wram_address(address: u16) -> Option<u16> {
+    match address {
+        0xC000..=0xDFFF => Some(address),
+        0xE000..=0xFDFF => Some(address - 0x2000),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_written_bits_track_each_byte() {
+        let mut strict = StrictChecker::new();
+        strict.mark_written(0xC001);
+        strict.mark_written(0xDFFF);
+        assert!(!strict.is_written(0xC000));
+        assert!(strict.is_written(0xC001));
+        assert!(strict.is_written(0xDFFF));
+        assert_eq!(wram_address(0xFDFF), Some(0xDDFF));
+        assert_eq!(wram_address(0xFE00), None);
+    }
+
+    #[test]
+    fn test_log_drops_oldest_events() {
+        let mut strict = StrictChecker::new();
+        for sp in 0..VIOLATION_LOG_CAPACITY as u16 + 3 {
+            strict.report(sp as u64, Violation::StackUnderflow { sp });
+        }
+        let events = strict.take_events();
+        assert_eq!(events.len(), VIOLATION_LOG_CAPACITY);
+        assert_eq!(events[0].cycle, 3);
+        assert!(strict.take_events().is_empty());
+    }
+}
@@ -0,0 +1,385 @@
+// Test ROM assembler
+// A minimal SM83 assembler for building micro-ROMs in unit tests, so core behavior is
+// covered without external (often copyrighted) test ROMs. Supports the instruction set
+// with Game Boy syntax (`ld a, $05`, `ldh ($07), a`, `jr nz, loop`), labels,
+// and the `org`, `bank` and `db` directives:
+//
+//     org $0100
+//         ld a, $05
+//     loop:
+//         inc bc
+//         jr loop
+//
+// Numbers are decimal, `$FF` or `0xFF`. `bank N` places the following `org $4000..$7FFF`
+// code in switchable ROM bank N (the cartridge type byte is left to the test).
+
+use std::collections::HashMap;
+
+const REGISTERS: [&str; 8] = ["b", "c", "d", "e", "h", "l", "(hl)", "a"];
+const PAIRS: [&str; 4] = ["bc", "de", "hl", "sp"];
+const STACK_PAIRS: [&str; 4] = ["bc", "de", "hl", "af"];
+const CONDITIONS: [&str; 4] = ["nz", "z", "nc", "c"];
+const ALU: [&str; 8] = ["add", "adc", "sub", "sbc", "and", "xor", "or", "cp"];
+const CB_SHIFTS: [&str; 8] = ["rlc", "rrc", "rl", "rr", "sla", "sra", "swap", "srl"];
+
+fn index_of(table: &[&str], operand: &str) -> Option<u8> {
+    table
+        .iter()
+        .position(|&name| name == operand)
+        .map(|i| i as u8)
+}
+
+// Bytes of one instruction; values not known until labels are resolved are taken
+// from `value` in the second pass
+enum Emit {
+    Bytes(Vec<u8>),
+    // Opcode bytes followed by an 8-bit immediate
+    Imm8(Vec<u8>, String),
+    // Opcode bytes followed by a little-endian 16-bit immediate
+    Imm16(Vec<u8>, String),
+    // Opcode followed by a PC-relative offset to the target
+    Relative(u8, String),
+}
+
+impl Emit {
+    fn len(&self) -> usize {
+        match self {
+            Emit::Bytes(bytes) => bytes.len(),
+            Emit::Imm8(op, _) => op.len() + 1,
+            Emit::Imm16(op, _) => op.len() + 2,
+            Emit::Relative(_, _) => 2,
+        }
+    }
+}
+
+fn memory_operand(operand: &str) -> Option<&str> {
+    operand
+        .strip_prefix('(')
+        .and_then(|inner| inner.strip_suffix(')'))
+        .map(str::trim)
+}
+
+fn parse_number(value: &str) -> Option<i64> {
+    let value = value.trim();
+    if let Some(hex) = value.strip_prefix('$').or_else(|| value.strip_prefix("0x")) {
+        i64::from_str_radix(hex, 16).ok()
+    } else if let Some(negative) = value.strip_prefix('-') {
+        parse_number(negative).map(|n| -n)
+    } else {
+        value.parse().ok()
+    }
+}
+
+fn encode(mnemonic: &str, operands: &[&str]) -> Result<Emit, String> {
+    let imm = |value: &str| value.to_string();
+    let reg = |operand: &str| index_of(&REGISTERS, operand);
+    let bad = || Err(format!("unsupported operands for {}", mnemonic));
+
+    let fixed = match mnemonic {
+        "nop" => Some(0x00),
+        "halt" => Some(0x76),
+        "di" => Some(0xF3),
+        "ei" => Some(0xFB),
+        "reti" => Some(0xD9),
+        "scf" => Some(0x37),
+        "ccf" => Some(0x3F),
+        "cpl" => Some(0x2F),
+        "daa" => Some(0x27),
+        "rlca" => Some(0x07),
+        "rrca" => Some(0x0F),
+        "rla" => Some(0x17),
+        "rra" => Some(0x1F),
+        _ => None,
+    };
+    if let Some(opcode) = fixed {
+        return match operands {
+            [] => Ok(Emit::Bytes(vec![opcode])),
+            _ => bad(),
+        };
+    }
+
+    if let Some(op) = index_of(&ALU, mnemonic) {
+        // `add a, b` and `add b` are the same instruction
+        let source = match operands {
+            ["hl", pair] if mnemonic == "add" => {
+                let rr = index_of(&PAIRS, pair).ok_or("bad register pair")?;
+                return Ok(Emit::Bytes(vec![0x09 | rr << 4]));
+            }
+            ["a", source] | [source] => *source,
+            _ => return bad(),
+        };
+        return Ok(match reg(source) {
+            Some(r) => Emit::Bytes(vec![0x80 | op << 3 | r]),
+            None => Emit::Imm8(vec![0xC6 | op << 3], imm(source)),
+        });
+    }
+
+    if let Some(op) = index_of(&CB_SHIFTS, mnemonic) {
+        return match operands {
+            [r] => Ok(Emit::Bytes(vec![
+                0xCB,
+                op << 3 | reg(r).ok_or("bad register")?,
+            ])),
+            _ => bad(),
+        };
+    }
+
+    let emit = match (mnemonic, operands) {
+        ("stop", []) => Emit::Bytes(vec![0x10, 0x00]),
+        ("db", values) => Emit::Bytes(
+            values
+                .iter()
+                .map(|v| parse_number(v).map(|n| n as u8))
+                .collect::<Option<_>>()
+                .ok_or("db takes numbers only")?,
+        ),
+
+        ("ld", ["a", "(c)"]) => Emit::Bytes(vec![0xF2]),
+        ("ld", ["(c)", "a"]) => Emit::Bytes(vec![0xE2]),
+        ("ld", ["a", "(bc)"]) => Emit::Bytes(vec![0x0A]),
+        ("ld", ["a", "(de)"]) => Emit::Bytes(vec![0x1A]),
+        ("ld", ["(bc)", "a"]) => Emit::Bytes(vec![0x02]),
+        ("ld", ["(de)", "a"]) => Emit::Bytes(vec![0x12]),
+        ("ld", ["a", "(hl+)"]) => Emit::Bytes(vec![0x2A]),
+        ("ld", ["a", "(hl-)"]) => Emit::Bytes(vec![0x3A]),
+        ("ld", ["(hl+)", "a"]) => Emit::Bytes(vec![0x22]),
+        ("ld", ["(hl-)", "a"]) => Emit::Bytes(vec![0x32]),
+        ("ld", ["sp", "hl"]) => Emit::Bytes(vec![0xF9]),
+        ("ld", [dst, src]) => match (reg(dst), reg(src), index_of(&PAIRS, dst)) {
+            (Some(6), Some(6), _) => return bad(),
+            (Some(d), Some(s), _) => Emit::Bytes(vec![0x40 | d << 3 | s]),
+            (Some(d), None, _) if memory_operand(src).is_none() => {
+                Emit::Imm8(vec![0x06 | d << 3], imm(src))
+            }
+            (None, None, Some(rr)) => Emit::Imm16(vec![0x01 | rr << 4], imm(src)),
+            _ => match (memory_operand(dst), memory_operand(src)) {
+                (Some(address), None) if *src == "a" => Emit::Imm16(vec![0xEA], imm(address)),
+                (Some(address), None) if *src == "sp" => Emit::Imm16(vec![0x08], imm(address)),
+                (None, Some(address)) if *dst == "a" => Emit::Imm16(vec![0xFA], imm(address)),
+                _ => return bad(),
+            },
+        },
+        ("ldh", ["a", src]) => match memory_operand(src) {
+            Some("c") => Emit::Bytes(vec![0xF2]),
+            Some(address) => Emit::Imm8(vec![0xF0], imm(address)),
+            None => return bad(),
+        },
+        ("ldh", [dst, "a"]) => match memory_operand(dst) {
+            Some("c") => Emit::Bytes(vec![0xE2]),
+            Some(address) => Emit::Imm8(vec![0xE0], imm(address)),
+            None => return bad(),
+        },
+
+        ("inc" | "dec", [operand]) => {
+            let dec = (mnemonic == "dec") as u8;
+            match (reg(operand), index_of(&PAIRS, operand)) {
+                (Some(r), _) => Emit::Bytes(vec![0x04 | r << 3 | dec]),
+                (None, Some(rr)) => Emit::Bytes(vec![0x03 | rr << 4 | dec << 3]),
+                _ => return bad(),
+            }
+        }
+        ("push" | "pop", [pair]) => {
+            let qq = index_of(&STACK_PAIRS, pair).ok_or("bad register pair")?;
+            let base = if mnemonic == "push" { 0xC5 } else { 0xC1 };
+            Emit::Bytes(vec![base | qq << 4])
+        }
+
+        ("jp", ["hl"]) => Emit::Bytes(vec![0xE9]),
+        ("jp", [target]) => Emit::Imm16(vec![0xC3], imm(target)),
+        ("call", [target]) => Emit::Imm16(vec![0xCD], imm(target)),
+        ("jr", [target]) => Emit::Relative(0x18, imm(target)),
+        ("ret", []) => Emit::Bytes(vec![0xC9]),
+        ("jp" | "call" | "jr" | "ret", [condition, rest @ ..]) => {
+            let cc = index_of(&CONDITIONS, condition).ok_or("bad condition")? << 3;
+            match (mnemonic, rest) {
+                ("jp", [target]) => Emit::Imm16(vec![0xC2 | cc], imm(target)),
+                ("call", [target]) => Emit::Imm16(vec![0xC4 | cc], imm(target)),
+                ("jr", [target]) => Emit::Relative(0x20 | cc, imm(target)),
+                ("ret", []) => Emit::Bytes(vec![0xC0 | cc]),
+                _ => return bad(),
+            }
+        }
+        ("rst", [vector]) => {
+            let vector = parse_number(vector).filter(|v| v & !0x38 == 0);
+            Emit::Bytes(vec![0xC7 | vector.ok_or("bad rst vector")? as u8])
+        }
+
+        ("bit" | "res" | "set", [bit, r]) => {
+            let base = match mnemonic {
+                "bit" => 0x40,
+                "res" => 0x80,
+                _ => 0xC0,
+            };
+            let bit = parse_number(bit).filter(|b| (0..8).contains(b));
+            let r = reg(r).ok_or("bad register")?;
+            Emit::Bytes(vec![0xCB, base | (bit.ok_or("bad bit")? as u8) << 3 | r])
+        }
+        _ => return Err(format!("unknown instruction '{}'", mnemonic)),
+    };
+    Ok(emit)
+}
+
+/// Assemble `source` into a ROM image of at least 32 KiB.
+/// Panics with the offending line on errors, since it is only used by tests.
+pub fn assemble(source: &str) -> Vec<u8> {
+    // (ROM offset, address, line number, encoded instruction)
+    let mut instructions = Vec::new();
+    let mut labels = HashMap::new();
+    let mut bank = 1usize;
+    let mut address = 0usize;
+
+    let rom_offset = |bank: usize, address: usize| match address {
+        0x4000..=0x7FFF => bank * 0x4000 + address - 0x4000,
+        _ => address,
+    };
+
+    for (number, raw) in source.lines().enumerate() {
+        let fail = |message: &str| -> ! { panic!("line {}: {}: {}", number + 1, message, raw) };
+        let mut line = raw
+            .split(';')
+            .next()
+            .unwrap_or_default()
+            .trim()
+            .to_lowercase();
+        if let Some((label, rest)) = line.split_once(':') {
+            if labels.insert(label.trim().to_string(), address).is_some() {
+                fail("duplicate label");
+            }
+            line = rest.trim().to_string();
+        }
+        if line.is_empty() {
+            continue;
+        }
+
+        let (mnemonic, operands) = line.split_once(char::is_whitespace).unwrap_or((&line, ""));
+        let operands: Vec<&str> = operands
+            .split(',')
+            .map(str::trim)
+            .filter(|op| !op.is_empty())
+            .collect();
+        let number_operand = || match operands[..] {
+            [value] => parse_number(value)
+                .map(|n| n as usize)
+                .unwrap_or_else(|| fail("expected a number")),
+            _ => fail("expected one operand"),
+        };
+        match mnemonic {
+            "org" => address = number_operand(),
+            "bank" => bank = number_operand(),
+            _ => {
+                let emit = encode(mnemonic, &operands).unwrap_or_else(|err| fail(&err));
+                let len = emit.len();
+                instructions.push((rom_offset(bank, address), address, number, emit));
+                address += len;
+            }
+        }
+    }
+
+    let end = instructions
+        .iter()
+        .map(|(offset, _, _, emit)| offset + emit.len())
+        .max()
+        .unwrap_or(0);
+    let mut rom = vec![0; end.max(0x8000).next_power_of_two()];
+
+    for (offset, address, number, emit) in instructions {
+        let value = |expr: &str| -> i64 {
+            parse_number(expr)
+                .or_else(|| labels.get(expr).map(|&a| a as i64))
+                .unwrap_or_else(|| panic!("line {}: unknown label '{}'", number + 1, expr))
+        };
+        let bytes = match emit {
+            Emit::Bytes(bytes) => bytes,
+            Emit::Imm8(mut op, expr) => {
+                op.push(value(&expr) as u8);
+                op
+            }
+            Emit::Imm16(mut op, expr) => {
+                op.extend_from_slice(&(value(&expr) as u16).to_le_bytes());
+                op
+            }
+            Emit::Relative(op, expr) => {
+                let delta = value(&expr) - (address as i64 + 2);
+                if !(-128..=127).contains(&delta) {
+                    panic!("line {}: jump to '{}' out of range", number + 1, expr);
+                }
+                vec![op, delta as u8]
+            }
+        };
+        rom[offset..offset + bytes.len()].copy_from_slice(&bytes);
+    }
+    rom
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encodings() {
+        let rom = assemble(
+            "
+            org $0100
+                ld a, $05
+                ldh ($07), a
+                ld b, (hl)
+                ld hl, $C000
+                ld ($FF80), a
+                add a, c
+                cp $90
+                inc bc
+                dec (hl)
+                push af
+                bit 7, h
+                swap a
+                rst $38
+            ",
+        );
+        assert_eq!(
+            &rom[0x0100..0x0116],
+            &[
+                0x3E, 0x05, 0xE0, 0x07, 0x46, 0x21, 0x00, 0xC0, 0xEA, 0x80, 0xFF, 0x81, 0xFE, 0x90,
+                0x03, 0x35, 0xF5, 0xCB, 0x7C, 0xCB, 0x37, 0xFF,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_labels_and_jumps() {
+        let rom = assemble(
+            "
+            org $0100
+            loop:
+                ldh a, ($44)
+                cp $90
+                jr nz, loop
+                call done
+                jp z, loop
+            done: ret
+            ",
+        );
+        assert_eq!(
+            &rom[0x0100..0x010D],
+            &[0xF0, 0x44, 0xFE, 0x90, 0x20, 0xFA, 0xCD, 0x0C, 0x01, 0xCA, 0x00, 0x01, 0xC9]
+        );
+    }
+
+    #[test]
+    fn test_banks() {
+        let rom = assemble(
+            "
+            bank 3
+            org $4000
+                db $33
+            ",
+        );
+        assert_eq!(rom.len(), 0x10000);
+        assert_eq!(rom[3 * 0x4000], 0x33);
+    }
+
+    #[test]
+    #[should_panic(expected = "line 2")]
+    fn test_errors_name_the_line() {
+        assemble("nop\nld (hl), (hl)");
+    }
+}
@@ -10,6 +10,12 @@
 // TIMA increments on a falling edge of a specific bit in the counter, selected by TAC.
 // Note: The tick() method receives M-cycles and converts to T-cycles (1 M-cycle = 4 T-cycles).
 
+use crate::gb_log;
+use crate::logging::{self, LogTarget};
+use crate::savestate::{StateReader, StateWriter};
+
+#[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Timer {
     internal_counter: u16,       // Internal 16-bit counter (increments every T-cycle)
     tima: u8,                    // Timer counter
@@ -29,7 +35,7 @@ impl Default for Timer {
 impl Timer {
     pub fn new() -> Timer {
         Timer {
-            internal_counter: 0xABCC, // Post-boot ROM initial value
+            internal_counter: 0, // Power-on value, see Model::post_boot_div for the post-boot phase
             tima: 0,
             tma: 0,
             tac: 0,
@@ -66,7 +72,7 @@ impl Timer {
     pub fn tick(&mut self, m_cycles: u16) {
         // Convert M-cycles to T-cycles (1 M-cycle = 4 T-cycles)
         let t_cycles = m_cycles * 4;
-        let debug = std::env::var("TIMER_DEBUG").is_ok(); // Set TIMER_DEBUG=1 to enable
+        let debug = logging::enabled(LogTarget::Timer); // --log timer or GBEMU_LOG=timer
 
         for i in 0..t_cycles {
             // Handle overflow delay countdown
@@ -74,7 +80,13 @@ impl Timer {
                 self.overflow_cycles -= 1;
 
                 if debug {
-                    println!("    T+{}: overflow_cycles={}, TIMA=0x{:02X}", i, self.overflow_cycles, self.tima);
+                    gb_log!(
+                        LogTarget::Timer,
+                        "T+{}: overflow_cycles={}, TIMA=0x{:02X}",
+                        i,
+                        self.overflow_cycles,
+                        self.tima
+                    );
                 }
 
                 if self.overflow_cycles == 0 {
@@ -82,7 +94,12 @@ impl Timer {
                     self.tima = self.tma;
                     self.interrupt_pending = true;
                     if debug {
-                        println!("    T+{}: Loaded TMA=0x{:02X} into TIMA, interrupt set", i, self.tma);
+                        gb_log!(
+                            LogTarget::Timer,
+                            "T+{}: Loaded TMA=0x{:02X} into TIMA, interrupt set",
+                            i,
+                            self.tma
+                        );
                     }
                 }
 
@@ -105,8 +122,14 @@ impl Timer {
                 let (new_tima, overflow) = self.tima.overflowing_add(1);
 
                 if debug {
-                    println!("    T+{}: Falling edge detected, TIMA 0x{:02X} -> 0x{:02X}, overflow={}",
-                             i, self.tima, new_tima, overflow);
+                    gb_log!(
+                        LogTarget::Timer,
+                        "T+{}: Falling edge detected, TIMA 0x{:02X} -> 0x{:02X}, overflow={}",
+                        i,
+                        self.tima,
+                        new_tima,
+                        overflow
+                    );
                 }
 
                 if overflow {
@@ -115,7 +138,11 @@ impl Timer {
                     self.tima_overflow_value = new_tima; // This is 0x00
                     self.tima = new_tima; // TIMA becomes 0 immediately
                     if debug {
-                        println!("    T+{}: Overflow! Starting 4 T-cycle delay", i);
+                        gb_log!(
+                            LogTarget::Timer,
+                            "T+{}: Overflow! Starting 4 T-cycle delay",
+                            i
+                        );
                     }
                 } else {
                     self.tima = new_tima;
@@ -202,12 +229,66 @@ impl Timer {
         }
     }
 
+    // Set the internal 16-bit divider directly (used to reproduce the post-boot DIV phase)
+    pub fn set_internal_counter(&mut self, value: u16) {
+        self.internal_counter = value;
+    }
+
+    /// Internal 16-bit divider (DIV is its upper byte)
+    pub fn internal_counter(&self) -> u16 {
+        self.internal_counter
+    }
+
+    /// Divider bit whose falling edge increments TIMA, as selected by TAC bits 0-1
+    pub fn selected_bit(&self) -> u16 {
+        self.get_timer_bit()
+    }
+
+    /// T-cycles until the next TIMA increment, None while the timer is stopped
+    pub fn cycles_until_increment(&self) -> Option<u32> {
+        if self.tac & 0x04 == 0 {
+            return None;
+        }
+        // The selected bit falls when the counter crosses a multiple of twice its value
+        let period = 2u32 << self.get_timer_bit();
+        let phase = self.internal_counter as u32 & (period - 1);
+        Some(period - phase)
+    }
+
+    /// T-cycles until TMA is loaded after an overflow, None when no reload is pending
+    pub fn reload_pending(&self) -> Option<u8> {
+        (self.overflow_cycles > 0).then_some(self.overflow_cycles)
+    }
+
     // Clear the interrupt flag (called after interrupt is serviced)
     pub fn clear_interrupt(&mut self) {
         self.interrupt_pending = false;
     }
 }
 
+impl Timer {
+    pub(crate) fn save_state(&self, w: &mut StateWriter) {
+        w.u16(self.internal_counter);
+        w.u8(self.tima);
+        w.u8(self.tma);
+        w.u8(self.tac);
+        w.bool(self.interrupt_pending);
+        w.u8(self.overflow_cycles);
+        w.u8(self.tima_overflow_value);
+    }
+
+    pub(crate) fn load_state(&mut self, r: &mut StateReader) -> Result<(), String> {
+        self.internal_counter = r.u16()?;
+        self.tima = r.u8()?;
+        self.tma = r.u8()?;
+        self.tac = r.u8()?;
+        self.interrupt_pending = r.bool()?;
+        self.overflow_cycles = r.u8()?;
+        self.tima_overflow_value = r.u8()?;
+        Ok(())
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
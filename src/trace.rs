@@ -0,0 +1,320 @@
+// Execution trace support
+// Tracer logs the CPU state before every instruction in the line format of Gameboy Doctor
+// (the same fields BGB can log), so a run can be diffed against a reference emulator.
+// TraceFilter decides which instructions make it into a trace, so users who only care
+// about one routine can restrict output to PC ranges/ROM banks and drop wait loops.
+
+use std::collections::VecDeque;
+use std::io::{self, Write};
+use std::ops::RangeInclusive;
+
+use crate::cpu::{Cpu, Reg16, Reg8};
+use crate::memory::Memory;
+
+/// A PC range, optionally restricted to one ROM bank for addresses in 0x4000-0x7FFF
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PcRange {
+    pub range: RangeInclusive<u16>,
+    pub bank: Option<u16>,
+}
+
+impl PcRange {
+    fn matches(&self, pc: u16, bank: Option<u16>) -> bool {
+        if !self.range.contains(&pc) {
+            return false;
+        }
+        match self.bank {
+            Some(wanted) => bank == Some(wanted),
+            None => true,
+        }
+    }
+}
+
+/// Parse "START-END" or "START-END@BANK" with hex addresses (optional 0x prefix)
+impl std::str::FromStr for PcRange {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (range, bank) = match s.split_once('@') {
+            Some((range, bank)) => (range, Some(bank)),
+            None => (s, None),
+        };
+        let (start, end) = range
+            .split_once('-')
+            .ok_or_else(|| format!("expected START-END, got '{}'", s))?;
+        let hex = |v: &str| {
+            let v = v.trim();
+            let v = v
+                .strip_prefix("0x")
+                .or_else(|| v.strip_prefix("0X"))
+                .unwrap_or(v);
+            u16::from_str_radix(v, 16).map_err(|e| format!("invalid address '{}': {}", v, e))
+        };
+        let start = hex(start)?;
+        let end = hex(end)?;
+        if start > end {
+            return Err(format!(
+                "range start 0x{:04X} is after end 0x{:04X}",
+                start, end
+            ));
+        }
+        let bank = match bank {
+            Some(b) => Some(
+                b.trim()
+                    .parse::<u16>()
+                    .map_err(|e| format!("invalid bank '{}': {}", b, e))?,
+            ),
+            None => None,
+        };
+        Ok(PcRange {
+            range: start..=end,
+            bank,
+        })
+    }
+}
+
+/// Which instructions to keep in a trace
+#[derive(Debug, Clone, Default)]
+pub struct TraceFilter {
+    // Empty means every PC is traced
+    ranges: Vec<PcRange>,
+    // Drop an instruction if its PC was traced within the last `loop_window` entries
+    loop_window: usize,
+    recent_pcs: VecDeque<u16>,
+    suppressed: u64,
+}
+
+impl TraceFilter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Only trace PCs inside `range` (may be called several times)
+    pub fn with_range(mut self, range: PcRange) -> Self {
+        self.ranges.push(range);
+        self
+    }
+
+    /// Skip instructions whose PC repeats within the last `window` traced instructions.
+    /// A window of 1 drops identical consecutive PCs (e.g. `JR -2`); a few entries
+    /// are enough to collapse typical `LD A,(FF44) / CP n / JR NZ` wait loops.
+    pub fn skip_loops(mut self, window: usize) -> Self {
+        self.loop_window = window;
+        self
+    }
+
+    /// Decide whether the instruction at `pc` should be traced. `bank` is the ROM
+    /// bank mapped at `pc` (None outside ROM).
+    pub fn should_trace(&mut self, pc: u16, bank: Option<u16>) -> bool {
+        if !self.ranges.is_empty() && !self.ranges.iter().any(|r| r.matches(pc, bank)) {
+            return false;
+        }
+
+        if self.loop_window > 0 {
+            if self.recent_pcs.contains(&pc) {
+                self.suppressed += 1;
+                return false;
+            }
+            if self.recent_pcs.len() == self.loop_window {
+                self.recent_pcs.pop_front();
+            }
+            self.recent_pcs.push_back(pc);
+        }
+        true
+    }
+
+    /// Number of instructions dropped by loop skipping so far
+    pub fn suppressed(&self) -> u64 {
+        self.suppressed
+    }
+}
+
+/// The Gameboy Doctor line for the instruction about to run, e.g.
+/// `A:01 F:B0 B:00 C:13 D:00 E:D8 H:01 L:4D SP:FFFE PC:0100 PCMEM:00,C3,13,02`
+pub fn doctor_line(cpu: &Cpu, mem: &Memory) -> String {
+    let r8 = |register| cpu.registers.read_r8(register);
+    let pc = cpu.registers.read_r16(Reg16::PC);
+    let pcmem: Vec<String> = (0..4)
+        .map(|offset| format!("{:02X}", mem.read_8(pc.wrapping_add(offset))))
+        .collect();
+    format!(
+        "A:{:02X} F:{:02X} B:{:02X} C:{:02X} D:{:02X} E:{:02X} H:{:02X} L:{:02X} SP:{:04X} PC:{:04X} PCMEM:{}",
+        r8(Reg8::A),
+        r8(Reg8::F),
+        r8(Reg8::B),
+        r8(Reg8::C),
+        r8(Reg8::D),
+        r8(Reg8::E),
+        r8(Reg8::H),
+        r8(Reg8::L),
+        cpu.registers.read_r16(Reg16::SP),
+        pc,
+        pcmem.join(",")
+    )
+}
+
+enum TraceSink {
+    Writer(Box<dyn Write>),
+    Callback(Box<dyn FnMut(&str)>),
+}
+
+/// Writes a `doctor_line` for each instruction its filter keeps
+pub struct Tracer {
+    filter: TraceFilter,
+    sink: TraceSink,
+    // First write error; nothing more is written after one
+    error: Option<io::Error>,
+}
+
+impl Tracer {
+    /// Trace to `out`, one line per instruction
+    pub fn to_writer(out: Box<dyn Write>) -> Self {
+        Self::new(TraceSink::Writer(out))
+    }
+
+    /// Pass each line, without its newline, to `callback`
+    pub fn with_callback(callback: impl FnMut(&str) + 'static) -> Self {
+        Self::new(TraceSink::Callback(Box::new(callback)))
+    }
+
+    fn new(sink: TraceSink) -> Self {
+        Self {
+            filter: TraceFilter::new(),
+            sink,
+            error: None,
+        }
+    }
+
+    pub fn with_filter(mut self, filter: TraceFilter) -> Self {
+        self.filter = filter;
+        self
+    }
+
+    /// Log the instruction the CPU is about to run. A halted CPU runs none, so nothing
+    /// is logged for it.
+    pub fn trace(&mut self, cpu: &Cpu, mem: &Memory) {
+        if cpu.halted || self.error.is_some() {
+            return;
+        }
+        let pc = cpu.registers.read_r16(Reg16::PC);
+        let bank = match pc {
+            0x0000..=0x3FFF => Some(0),
+            0x4000..=0x7FFF => Some(mem.rom_bank()),
+            _ => None,
+        };
+        if !self.filter.should_trace(pc, bank) {
+            return;
+        }
+        let line = doctor_line(cpu, mem);
+        match &mut self.sink {
+            TraceSink::Writer(out) => {
+                if let Err(err) = writeln!(out, "{}", line) {
+                    self.error = Some(err);
+                }
+            }
+            TraceSink::Callback(callback) => callback(&line),
+        }
+    }
+
+    /// Flush the writer, or return the error that stopped the trace
+    pub fn flush(&mut self) -> io::Result<()> {
+        if let Some(err) = self.error.take() {
+            return Err(err);
+        }
+        match &mut self.sink {
+            TraceSink::Writer(out) => out.flush(),
+            TraceSink::Callback(_) => Ok(()),
+        }
+    }
+
+    pub fn filter(&self) -> &TraceFilter {
+        &self.filter
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::Model;
+    use crate::testrom::assemble;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    #[test]
+    fn test_doctor_lines() {
+        let mut mem = Memory::new(assemble(
+            "
+            org $0100
+                nop
+                jp $0150
+            org $0150
+                ld b, $12
+                inc b
+            ",
+        ));
+        mem.skip_boot(Model::Dmg);
+        let mut cpu = Cpu::new();
+        cpu.skip_boot(Model::Dmg, 0x4D);
+
+        let lines = Rc::new(RefCell::new(Vec::new()));
+        let sink = lines.clone();
+        let mut tracer =
+            Tracer::with_callback(move |line| sink.borrow_mut().push(line.to_string()))
+                .with_filter(TraceFilter::new().with_range("0150-7FFF".parse().unwrap()));
+        for _ in 0..4 {
+            tracer.trace(&cpu, &mem);
+            cpu.step(&mut mem);
+        }
+        assert_eq!(
+            *lines.borrow(),
+            [
+                "A:01 F:B0 B:00 C:13 D:00 E:D8 H:01 L:4D SP:FFFE PC:0150 PCMEM:06,12,04,00",
+                "A:01 F:B0 B:12 C:13 D:00 E:D8 H:01 L:4D SP:FFFE PC:0152 PCMEM:04,00,00,00",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_empty_filter_traces_everything() {
+        let mut filter = TraceFilter::new();
+        assert!(filter.should_trace(0x0100, Some(0)));
+        assert!(filter.should_trace(0xC000, None));
+    }
+
+    #[test]
+    fn test_pc_range_and_bank() {
+        let mut filter = TraceFilter::new()
+            .with_range("4000-4FFF@3".parse().unwrap())
+            .with_range("0x0150-0x0160".parse().unwrap());
+
+        assert!(filter.should_trace(0x4100, Some(3)));
+        assert!(!filter.should_trace(0x4100, Some(2)));
+        assert!(!filter.should_trace(0x5000, Some(3)));
+        assert!(filter.should_trace(0x0155, Some(0)));
+        assert!(!filter.should_trace(0x0161, Some(0)));
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert!("4000".parse::<PcRange>().is_err());
+        assert!("5000-4000".parse::<PcRange>().is_err());
+        assert!("4000-4FFF@x".parse::<PcRange>().is_err());
+    }
+
+    #[test]
+    fn test_skip_wait_loop() {
+        let mut filter = TraceFilter::new().skip_loops(3);
+        // LD A,(FF44) / CP n / JR NZ,-6 spinning three times
+        let traced: Vec<bool> = [0x0200, 0x0202, 0x0204]
+            .iter()
+            .cycle()
+            .take(9)
+            .map(|&pc| filter.should_trace(pc, Some(0)))
+            .collect();
+        assert_eq!(traced.iter().filter(|&&t| t).count(), 3);
+        assert_eq!(filter.suppressed(), 6);
+
+        // Leaving the loop resumes tracing
+        assert!(filter.should_trace(0x0206, Some(0)));
+    }
+}
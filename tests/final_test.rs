@@ -0,0 +1,236 @@
+// Blargg and Mooneye test ROMs run headlessly, judged by their serial output or
+// by the Mooneye register signature.
+// ROMs are not part of the repository: each test is skipped when its ROM is missing.
+// GB_TEST_ROMS overrides the ROM directory (default roms/test_roms) and
+// GB_TEST_MAX_CYCLES the per-ROM cycle cap.
+// GB_TEST_SUITE names a directory whose .gb files are all run in parallel
+// (Blargg serial and Mooneye LD B,B conventions); GB_TEST_JOBS caps the threads.
+
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+use std::thread;
+
+use gbemu_rust::compat;
+use gbemu_rust::cpu::{Cpu, Reg16, Reg8};
+use gbemu_rust::memory::Memory;
+use gbemu_rust::model::Model;
+
+// 60 emulated seconds; cpu_instrs needs a little under that
+const DEFAULT_MAX_CYCLES: u64 = 60 * 4_194_304;
+
+const FRAME_CYCLES: u64 = 70224;
+
+fn rom_path(name: &str) -> PathBuf {
+    let dir = env::var("GB_TEST_ROMS").unwrap_or_else(|_| "roms/test_roms".to_string());
+    PathBuf::from(dir).join(name)
+}
+
+fn max_cycles() -> u64 {
+    env::var("GB_TEST_MAX_CYCLES")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(DEFAULT_MAX_CYCLES)
+}
+
+/// Run a ROM until it reports Passed/Failed over serial or the cycle cap is hit.
+/// Returns None (test skipped) when the ROM is not available.
+fn run_serial_test(name: &str) -> Option<String> {
+    let path = rom_path(name);
+    let Ok(rom) = fs::read(&path) else {
+        eprintln!("skipping {}: {} not found", name, path.display());
+        return None;
+    };
+
+    let mut mem = Memory::from_rom(rom).expect("test ROM uses a supported mapper");
+    mem.skip_boot(Model::Dmg);
+    let mut cpu = Cpu::new();
+    cpu.skip_boot(Model::Dmg, mem.read_8(0x014D));
+    cpu.registers.ime = 1;
+
+    let cap = max_cycles();
+    let mut cycles = 0u64;
+    let mut next_check = FRAME_CYCLES;
+    while cycles < cap {
+        cycles += cpu.step(&mut mem) as u64;
+
+        // Checking the output once per frame keeps the loop cheap
+        if cycles >= next_check {
+            next_check += FRAME_CYCLES;
+            let output = mem.serial.get_output_string();
+            if output.contains("Passed") || output.contains("Failed") {
+                return Some(output);
+            }
+        }
+    }
+    Some(mem.serial.get_output_string())
+}
+
+fn assert_passed(name: &'static str) {
+    // Memory is large and is moved around by value, which can overflow
+    // the default 2 MB test thread stack in debug builds
+    let output = thread::Builder::new()
+        .stack_size(32 * 1024 * 1024)
+        .spawn(move || run_serial_test(name))
+        .expect("failed to spawn emulator thread")
+        .join()
+        .expect("emulator thread panicked");
+    if let Some(output) = output {
+        assert!(
+            output.contains("Passed"),
+            "{} did not pass within {} cycles, serial output:\n{}",
+            name,
+            max_cycles(),
+            output
+        );
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum SuiteOutcome {
+    Passed,
+    Failed(String),
+}
+
+// Mooneye tests end with LD B,B and signal a pass with the Fibonacci numbers in registers
+const MOONEYE_PASS: [u8; 6] = [3, 5, 8, 13, 21, 34];
+const MOONEYE_FAIL: [u8; 6] = [0x42; 6];
+
+fn run_suite_rom(path: &PathBuf) -> SuiteOutcome {
+    let rom = match fs::read(path) {
+        Ok(rom) => rom,
+        Err(err) => return SuiteOutcome::Failed(format!("failed to read ROM: {}", err)),
+    };
+    let mut mem = match Memory::from_rom(rom) {
+        Ok(mem) => mem,
+        Err(err) => return SuiteOutcome::Failed(err.to_string()),
+    };
+    mem.skip_boot(Model::Dmg);
+    let mut cpu = Cpu::new();
+    cpu.skip_boot(Model::Dmg, mem.read_8(0x014D));
+
+    let cap = max_cycles();
+    let mut cycles = 0u64;
+    let mut next_check = FRAME_CYCLES;
+    while cycles < cap {
+        if mem.read_8(cpu.registers.read_r16(Reg16::PC)) == 0x40 {
+            let registers = [Reg8::B, Reg8::C, Reg8::D, Reg8::E, Reg8::H, Reg8::L]
+                .map(|r| cpu.registers.read_r8(r));
+            if registers == MOONEYE_PASS {
+                return SuiteOutcome::Passed;
+            }
+            if registers == MOONEYE_FAIL {
+                return SuiteOutcome::Failed("mooneye failure signature".to_string());
+            }
+        }
+        cycles += cpu.step(&mut mem) as u64;
+
+        if cycles >= next_check {
+            next_check += FRAME_CYCLES;
+            let output = mem.serial.get_output_string();
+            if output.contains("Passed") {
+                return SuiteOutcome::Passed;
+            }
+            if output.contains("Failed") {
+                return SuiteOutcome::Failed(output);
+            }
+        }
+    }
+    SuiteOutcome::Failed(format!("no result within {} cycles", cap))
+}
+
+#[test]
+fn suite() {
+    let Ok(dir) = env::var("GB_TEST_SUITE") else {
+        eprintln!("skipping suite: GB_TEST_SUITE not set");
+        return;
+    };
+    let mut roms: Vec<PathBuf> = fs::read_dir(&dir)
+        .expect("GB_TEST_SUITE is not a readable directory")
+        .filter_map(|entry| entry.ok().map(|e| e.path()))
+        .filter(|path| path.extension().is_some_and(|ext| ext == "gb"))
+        .collect();
+    roms.sort();
+
+    let jobs = env::var("GB_TEST_JOBS")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or_else(compat::default_jobs);
+    let outcomes = compat::run_parallel(&roms, jobs, run_suite_rom);
+
+    let failures: Vec<String> = roms
+        .iter()
+        .zip(&outcomes)
+        .filter_map(|(rom, outcome)| match outcome {
+            SuiteOutcome::Passed => None,
+            SuiteOutcome::Failed(reason) => Some(format!("{}: {}", rom.display(), reason)),
+        })
+        .collect();
+    assert!(
+        failures.is_empty(),
+        "{} of {} ROMs failed:\n{}",
+        failures.len(),
+        roms.len(),
+        failures.join("\n")
+    );
+}
+
+fn assert_mooneye_passed(name: &'static str) {
+    let path = rom_path(name);
+    if !path.exists() {
+        eprintln!("skipping {}: {} not found", name, path.display());
+        return;
+    }
+    let outcome = thread::Builder::new()
+        .stack_size(32 * 1024 * 1024)
+        .spawn(move || run_suite_rom(&path))
+        .expect("failed to spawn emulator thread")
+        .join()
+        .expect("emulator thread panicked");
+    assert_eq!(outcome, SuiteOutcome::Passed, "{}", name);
+}
+
+#[test]
+fn cpu_instrs() {
+    assert_passed("cpu_instrs.gb");
+}
+
+#[test]
+fn instr_timing() {
+    assert_passed("instr_timing.gb");
+}
+
+#[test]
+fn mem_timing() {
+    assert_passed("mem_timing.gb");
+}
+
+#[test]
+fn intr_2_mode0_timing() {
+    assert_mooneye_passed("intr_2_mode0_timing.gb");
+}
+
+#[test]
+fn intr_2_mode0_timing_sprites() {
+    assert_mooneye_passed("intr_2_mode0_timing_sprites.gb");
+}
+
+#[test]
+fn lcdon_timing() {
+    assert_mooneye_passed("lcdon_timing-GS.gb");
+}
+
+#[test]
+fn stat_lyc_onoff() {
+    assert_mooneye_passed("stat_lyc_onoff.gb");
+}
+
+#[test]
+fn stat_irq_blocking() {
+    assert_mooneye_passed("stat_irq_blocking.gb");
+}
+
+#[test]
+fn vblank_stat_intr() {
+    assert_mooneye_passed("vblank_stat_intr-GS.gb");
+}